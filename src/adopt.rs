@@ -0,0 +1,118 @@
+//! Mihomo started outside proxy-rs, by hand or by another tool, found by its
+//! process and config so proxy-rs can take it over instead of reporting it as
+//! not running.
+
+use crate::backend::CoreKind;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::ffi::OsStr;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// What is known about an adopted core, saved next to its pid file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct External {
+    pub pid: u32,
+    /// Config the core was started with
+    pub config: PathBuf,
+    /// External controller, from `-ext-ctl` or the config
+    pub controller: Option<String>,
+    pub secret: Option<String>,
+    pub mixed_port: Option<u16>,
+}
+
+impl External {
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path)?;
+        toml::from_str(&content).with_context(|| format!("Invalid {}", path.display()))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        fs::write(path, toml::to_string_pretty(self)?)?;
+        Ok(())
+    }
+}
+
+/// Running Mihomo processes other than the ones in `known`, the pids of the
+/// instances proxy-rs started.
+pub fn find(known: &[u32]) -> Vec<External> {
+    let system = sysinfo::System::new_all();
+    let mut found: Vec<External> = system
+        .processes()
+        .iter()
+        .filter(|(pid, process)| is_mihomo(process.name()) && !known.contains(&pid.as_u32()))
+        // Threads are listed as processes on Linux
+        .filter(|(_, process)| process.thread_kind().is_none())
+        .map(|(pid, process)| probe(pid.as_u32(), process))
+        .collect();
+    found.sort_by_key(|external| external.pid);
+    found
+}
+
+/// `mihomo`, release names like `mihomo-linux-amd64`, the `verge-mihomo`
+/// sidecar of Clash Verge and the older `clash-meta`.
+fn is_mihomo(name: &OsStr) -> bool {
+    let name = name.to_string_lossy().to_lowercase();
+    name.contains("mihomo") || name.starts_with("clash-meta") || name.starts_with("clash.meta")
+}
+
+/// Reads the config of the process the way Mihomo finds it: `-f`, or
+/// `config.yaml` in `-d`, `CLASH_HOME_DIR` or `~/.config/mihomo`, relative
+/// paths resolved against its working directory.
+fn probe(pid: u32, process: &sysinfo::Process) -> External {
+    let args: Vec<String> = process
+        .cmd()
+        .iter()
+        .map(|arg| arg.to_string_lossy().into_owned())
+        .collect();
+    let env = |name: &str| {
+        process.environ().iter().find_map(|var| {
+            let var = var.to_string_lossy();
+            var.strip_prefix(name)?.strip_prefix('=').map(String::from)
+        })
+    };
+    let cwd = process.cwd().map(Path::to_path_buf).unwrap_or_default();
+    let home = env("HOME")
+        .map(PathBuf::from)
+        .or_else(dirs::home_dir)
+        .unwrap_or_default();
+    let dir = flag(&args, "d")
+        .or_else(|| env("CLASH_HOME_DIR"))
+        .map(|dir| cwd.join(dir))
+        .unwrap_or_else(|| home.join(".config").join("mihomo"));
+    let config = flag(&args, "f")
+        .or_else(|| env("CLASH_CONFIG_FILE"))
+        .map(|file| cwd.join(file))
+        .unwrap_or_else(|| dir.join("config.yaml"));
+
+    let backend = CoreKind::Mihomo.backend();
+    External {
+        pid,
+        controller: flag(&args, "ext-ctl").or_else(|| backend.external_controller(&config)),
+        secret: flag(&args, "secret").or_else(|| backend.secret(&config)),
+        mixed_port: backend.mixed_port(&config),
+        config,
+    }
+}
+
+/// Value of the Go-style flag `name` in `args`: `-name value`, `-name=value`
+/// or the same with `--`.
+fn flag(args: &[String], name: &str) -> Option<String> {
+    let mut args = args.iter().skip(1);
+    while let Some(arg) = args.next() {
+        let Some(rest) = arg
+            .strip_prefix("--")
+            .or_else(|| arg.strip_prefix('-'))
+            .and_then(|rest| rest.strip_prefix(name))
+        else {
+            continue;
+        };
+        if rest.is_empty() {
+            return args.next().cloned();
+        }
+        if let Some(value) = rest.strip_prefix('=') {
+            return Some(value.to_string());
+        }
+    }
+    None
+}
@@ -0,0 +1,749 @@
+use crate::config::{
+    is_clash_config, is_sensitive_key, parse_external_controller, parse_external_controller_unix,
+    parse_mixed_port, parse_secret, redact_config, remove_key, update_delay_test,
+    update_external_controller, update_external_controller_unix, update_mixed_port, update_secret,
+    update_tun, REDACTED,
+};
+use crate::controller::ControllerAddress;
+use crate::sing_box::is_sing_box_config;
+use anyhow::{anyhow, Context, Result};
+use log::*;
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+use std::ffi::OsString;
+use std::fs;
+use std::path::Path;
+
+/// The proxy cores proxy-rs can run, selected with `--core` or `core` in proxy-rs.toml.
+#[derive(Deserialize, clap::ValueEnum, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum CoreKind {
+    #[default]
+    Mihomo,
+    SingBox,
+}
+
+impl CoreKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CoreKind::Mihomo => "mihomo",
+            CoreKind::SingBox => "sing-box",
+        }
+    }
+
+    pub fn backend(&self) -> &'static dyn CoreBackend {
+        match self {
+            CoreKind::Mihomo => &Mihomo,
+            CoreKind::SingBox => &SingBox,
+        }
+    }
+}
+
+/// Which releases `update` and `auto-update-core` follow.
+#[derive(clap::ValueEnum, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Channel {
+    #[default]
+    Stable,
+    /// Mihomo's Prerelease-Alpha builds, or sing-box's newest prerelease
+    Alpha,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveType {
+    Gz,
+    Zip,
+    TarGz,
+}
+
+impl ArchiveType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ArchiveType::Gz => "gz",
+            ArchiveType::Zip => "zip",
+            ArchiveType::TarGz => "tar.gz",
+        }
+    }
+
+    /// The type of the archive named `name`, by its extension.
+    pub fn of(name: &str) -> Option<Self> {
+        if name.ends_with(".tar.gz") {
+            Some(ArchiveType::TarGz)
+        } else if name.ends_with(".zip") {
+            Some(ArchiveType::Zip)
+        } else if name.ends_with(".gz") {
+            Some(ArchiveType::Gz)
+        } else {
+            None
+        }
+    }
+}
+
+impl std::fmt::Display for ArchiveType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// What differs between proxy cores: how they are released, run and configured.
+/// Everything else goes through the Clash-compatible external controller, which
+/// every core implements.
+pub trait CoreBackend: Send + Sync {
+    fn kind(&self) -> CoreKind;
+    /// Name used in logs, e.g. `Mihomo`
+    fn name(&self) -> &'static str;
+    /// File name of the binary in the data dir, without `.exe`
+    fn binary_name(&self) -> &'static str;
+    /// File name of the config in the instance's config dir
+    fn config_file(&self) -> &'static str;
+    /// Subscription format the core reads, for messages
+    fn config_format(&self) -> &'static str;
+    /// subconverter `target` that produces [`Self::config_format`]
+    fn subconverter_target(&self) -> &'static str;
+    /// User-Agent sent with subscription downloads, providers pick the format from it
+    fn user_agent(&self) -> &'static str;
+    /// Whether `content` is a config the core can run
+    fn is_config(&self, content: &str) -> bool;
+
+    /// `owner/name` of the GitHub repository the core is released in
+    fn repository(&self) -> &'static str;
+    /// GitHub URL that tells the latest release in `channel`, parsed by
+    /// [`Self::parse_latest_version`]
+    fn latest_version_url(&self, channel: Channel) -> &'static str;
+    fn parse_latest_version(&self, channel: Channel, body: &str) -> Result<String>;
+    /// Channel a version was released in
+    fn channel_of(&self, version: &str) -> Channel;
+    /// Tag of the GitHub release `version` is downloaded from
+    fn release_tag(&self, version: &str) -> String {
+        version.to_string()
+    }
+    /// Release asset for this platform and how it is packed. `os` and `arch`
+    /// replace the detected ones, e.g. `windows` or `amd64-compatible`.
+    fn release_asset(
+        &self,
+        version: &str,
+        os: Option<&str>,
+        arch: Option<&str>,
+    ) -> Result<(String, ArchiveType)>;
+    /// Release asset with the SHA256 of the others, `None` if the core publishes none
+    fn checksums_asset(&self) -> Option<&'static str>;
+    /// GitHub API URL listing the most recent releases
+    fn releases_url(&self) -> String {
+        format!(
+            "https://api.github.com/repos/{}/releases?per_page=30",
+            self.repository()
+        )
+    }
+
+    /// Arguments that run the core on `config_dir`, serving `ui` unless it is `None`
+    fn run_args(
+        &self,
+        config_dir: &Path,
+        controller: &ControllerAddress,
+        ui: Option<&Path>,
+    ) -> Vec<OsString>;
+    /// Arguments that print the version and exit
+    fn version_args(&self) -> Vec<OsString>;
+    /// Arguments that check the config in `config_dir` without running it
+    fn test_args(&self, config_dir: &Path) -> Vec<OsString>;
+
+    fn mixed_port(&self, config_path: &Path) -> Option<u16>;
+    fn set_mixed_port(&self, config_path: &Path, port: u16) -> Result<()>;
+    fn external_controller(&self, config_path: &Path) -> Option<String>;
+    /// Where the config's controller listens, its Unix socket if it has one
+    fn controller_address(&self, config_path: &Path) -> Option<ControllerAddress> {
+        self.external_controller(config_path)?
+            .parse()
+            .ok()
+            .map(ControllerAddress::Tcp)
+    }
+    /// Cores that don't take the controller on the command line also get the WebUI dir here.
+    fn set_external_controller(
+        &self,
+        config_path: &Path,
+        address: &ControllerAddress,
+        ui: Option<&Path>,
+    ) -> Result<()>;
+    fn secret(&self, config_path: &Path) -> Option<String>;
+    fn set_secret(&self, config_path: &Path, secret: &str) -> Result<()>;
+    /// Adds a TUN inbound, or turns an existing one off
+    fn set_tun(&self, config_path: &Path, enable: bool) -> Result<()>;
+    /// Sets the delay test URL and interval in seconds of the groups that pick
+    /// nodes by delay, returning how many changed
+    fn set_delay_test(
+        &self,
+        config_path: &Path,
+        url: Option<&str>,
+        interval: Option<u64>,
+    ) -> Result<usize>;
+    /// The config as text with credentials and server addresses masked
+    fn redact_config(&self, config_path: &Path) -> Result<String>;
+}
+
+/// OSes as spelled in release asset names.
+pub const OSES: [&str; 3] = ["linux", "windows", "darwin"];
+
+/// This OS as spelled in release asset names.
+pub fn current_os() -> Result<&'static str> {
+    if cfg!(target_os = "windows") {
+        Ok("windows")
+    } else if cfg!(target_os = "linux") {
+        Ok("linux")
+    } else if cfg!(target_os = "macos") {
+        Ok("darwin")
+    } else {
+        Err(anyhow!("Unsupported OS"))
+    }
+}
+
+/// (os, arch) as spelled in release asset names. `os` and `arch` replace the
+/// detected ones, e.g. `armv6` on a CPU detected as `armv7`.
+fn platform(os: Option<&str>, arch: Option<&str>) -> Result<(String, String)> {
+    let os = match os {
+        Some(os) => os.to_string(),
+        None => current_os()?.to_string(),
+    };
+    if let Some(arch) = arch {
+        return Ok((os, arch.to_string()));
+    }
+
+    let arch = if cfg!(target_arch = "x86_64") {
+        "amd64"
+    } else if cfg!(target_arch = "aarch64") {
+        "arm64"
+    } else if cfg!(target_arch = "arm") {
+        "armv7"
+    } else if cfg!(target_arch = "riscv64") {
+        "riscv64"
+    } else if cfg!(target_arch = "x86") {
+        "386"
+    } else {
+        return Err(anyhow!(
+            "Unsupported architecture, pick a release asset with --core-arch"
+        ));
+    };
+    Ok((os, arch.to_string()))
+}
+
+/// How the release assets for `os` are packed: zip on Windows, `other` elsewhere.
+fn archive_type(os: &str, other: ArchiveType) -> ArchiveType {
+    if os == "windows" {
+        ArchiveType::Zip
+    } else {
+        other
+    }
+}
+
+/// Whether the CPU lacks AVX2, which Mihomo's default amd64 build (x86-64-v3) needs.
+fn needs_compatible_build() -> bool {
+    #[cfg(target_arch = "x86_64")]
+    {
+        !std::arch::is_x86_feature_detected!("avx2")
+    }
+    #[cfg(not(target_arch = "x86_64"))]
+    {
+        false
+    }
+}
+
+/// [MetaCubeX/mihomo](https://github.com/MetaCubeX/mihomo), a Clash.Meta core
+/// configured with YAML.
+pub struct Mihomo;
+
+impl CoreBackend for Mihomo {
+    fn kind(&self) -> CoreKind {
+        CoreKind::Mihomo
+    }
+
+    fn name(&self) -> &'static str {
+        "Mihomo"
+    }
+
+    fn binary_name(&self) -> &'static str {
+        "mihomo"
+    }
+
+    fn config_file(&self) -> &'static str {
+        "config.yaml"
+    }
+
+    fn config_format(&self) -> &'static str {
+        "Clash"
+    }
+
+    fn subconverter_target(&self) -> &'static str {
+        "clash"
+    }
+
+    fn user_agent(&self) -> &'static str {
+        "mihomo.proxy.sh/v1.0 (clash.meta)"
+    }
+
+    fn is_config(&self, content: &str) -> bool {
+        is_clash_config(content)
+    }
+
+    fn repository(&self) -> &'static str {
+        "MetaCubeX/mihomo"
+    }
+
+    fn latest_version_url(&self, channel: Channel) -> &'static str {
+        match channel {
+            Channel::Stable => {
+                "https://github.com/MetaCubeX/mihomo/releases/latest/download/version.txt"
+            }
+            Channel::Alpha => {
+                "https://github.com/MetaCubeX/mihomo/releases/download/Prerelease-Alpha/version.txt"
+            }
+        }
+    }
+
+    fn parse_latest_version(&self, _channel: Channel, body: &str) -> Result<String> {
+        Ok(body.trim().to_string())
+    }
+
+    /// Alpha builds are versioned `alpha-<commit>`
+    fn channel_of(&self, version: &str) -> Channel {
+        if version.starts_with("alpha-") {
+            Channel::Alpha
+        } else {
+            Channel::Stable
+        }
+    }
+
+    /// Every alpha build replaces the assets of the `Prerelease-Alpha` release
+    fn release_tag(&self, version: &str) -> String {
+        match self.channel_of(version) {
+            Channel::Stable => version.to_string(),
+            Channel::Alpha => "Prerelease-Alpha".to_string(),
+        }
+    }
+
+    fn release_asset(
+        &self,
+        version: &str,
+        os: Option<&str>,
+        arch: Option<&str>,
+    ) -> Result<(String, ArchiveType)> {
+        // Only this machine's CPU can be checked
+        let detected = os.is_none() && arch.is_none();
+        let (os, mut arch) = platform(os, arch)?;
+        if detected && arch == "amd64" && needs_compatible_build() {
+            info!("The CPU lacks AVX2, using Mihomo's amd64-compatible build");
+            arch = "amd64-compatible".to_string();
+        }
+        let archive_type = archive_type(&os, ArchiveType::Gz);
+        Ok((
+            format!("mihomo-{os}-{arch}-{version}.{archive_type}"),
+            archive_type,
+        ))
+    }
+
+    fn checksums_asset(&self) -> Option<&'static str> {
+        Some("checksums.txt")
+    }
+
+    fn run_args(
+        &self,
+        config_dir: &Path,
+        controller: &ControllerAddress,
+        ui: Option<&Path>,
+    ) -> Vec<OsString> {
+        let mut args: Vec<OsString> = vec!["-d".into(), config_dir.into()];
+        // The Unix socket is only in the config, so no TCP controller is opened
+        if let ControllerAddress::Tcp(address) = controller {
+            args.push("-ext-ctl".into());
+            args.push(address.to_string().into());
+        }
+        if let Some(ui) = ui {
+            args.push("-ext-ui".into());
+            args.push(ui.into());
+        }
+        args
+    }
+
+    fn version_args(&self) -> Vec<OsString> {
+        vec!["-v".into()]
+    }
+
+    fn test_args(&self, config_dir: &Path) -> Vec<OsString> {
+        vec!["-t".into(), "-d".into(), config_dir.into()]
+    }
+
+    fn mixed_port(&self, config_path: &Path) -> Option<u16> {
+        parse_mixed_port(config_path)
+    }
+
+    fn set_mixed_port(&self, config_path: &Path, port: u16) -> Result<()> {
+        update_mixed_port(config_path, port)
+    }
+
+    fn external_controller(&self, config_path: &Path) -> Option<String> {
+        parse_external_controller(config_path)
+    }
+
+    fn controller_address(&self, config_path: &Path) -> Option<ControllerAddress> {
+        // Relative paths are resolved against the home directory, where the config is
+        if let Some(path) = parse_external_controller_unix(config_path) {
+            let home = config_path.parent().unwrap_or(Path::new(""));
+            return Some(ControllerAddress::Unix(home.join(path)));
+        }
+        parse_external_controller(config_path)?
+            .parse()
+            .ok()
+            .map(ControllerAddress::Tcp)
+    }
+
+    fn set_external_controller(
+        &self,
+        config_path: &Path,
+        address: &ControllerAddress,
+        _ui: Option<&Path>,
+    ) -> Result<()> {
+        match address {
+            ControllerAddress::Tcp(address) => {
+                update_external_controller(config_path, &address.to_string())?;
+                update_external_controller_unix(config_path, None)
+            }
+            ControllerAddress::Unix(path) => {
+                remove_key(config_path, "external-controller")?;
+                update_external_controller_unix(config_path, Some(path))
+            }
+        }
+    }
+
+    fn secret(&self, config_path: &Path) -> Option<String> {
+        parse_secret(config_path)
+    }
+
+    fn set_secret(&self, config_path: &Path, secret: &str) -> Result<()> {
+        update_secret(config_path, secret)
+    }
+
+    fn set_tun(&self, config_path: &Path, enable: bool) -> Result<()> {
+        update_tun(config_path, enable)
+    }
+
+    fn set_delay_test(
+        &self,
+        config_path: &Path,
+        url: Option<&str>,
+        interval: Option<u64>,
+    ) -> Result<usize> {
+        update_delay_test(config_path, url, interval)
+    }
+
+    fn redact_config(&self, config_path: &Path) -> Result<String> {
+        redact_config(config_path)
+    }
+}
+
+/// [SagerNet/sing-box](https://github.com/SagerNet/sing-box), configured with
+/// JSON. The controller and WebUI come from its `clash_api`.
+pub struct SingBox;
+
+impl SingBox {
+    fn read(config_path: &Path) -> Option<Value> {
+        let content = fs::read_to_string(config_path).ok()?;
+        serde_json::from_str(&content).ok()
+    }
+
+    fn edit(config_path: &Path, f: impl FnOnce(&mut Map<String, Value>)) -> Result<()> {
+        let content = fs::read_to_string(config_path)?;
+        let mut json: Value = serde_json::from_str(&content)
+            .with_context(|| format!("Invalid {}", config_path.display()))?;
+        let map = json
+            .as_object_mut()
+            .ok_or_else(|| anyhow!("Invalid JSON"))?;
+        f(map);
+        fs::write(config_path, serde_json::to_string_pretty(&json)?)?;
+        Ok(())
+    }
+
+    fn redact(value: &mut Value) {
+        match value {
+            Value::Object(map) => {
+                for (key, value) in map.iter_mut() {
+                    if is_sensitive_key(key) {
+                        *value = REDACTED.into();
+                    } else {
+                        Self::redact(value);
+                    }
+                }
+            }
+            Value::Array(items) => items.iter_mut().for_each(Self::redact),
+            _ => {}
+        }
+    }
+
+    /// `experimental.clash_api`, created if missing.
+    fn clash_api(map: &mut Map<String, Value>) -> &mut Map<String, Value> {
+        if !map.get("experimental").is_some_and(Value::is_object) {
+            map.insert("experimental".into(), json!({}));
+        }
+        let experimental = map["experimental"].as_object_mut().unwrap();
+        if !experimental.get("clash_api").is_some_and(Value::is_object) {
+            experimental.insert("clash_api".into(), json!({}));
+        }
+        experimental["clash_api"].as_object_mut().unwrap()
+    }
+
+    /// The `inbounds` array, created if missing.
+    fn inbounds(map: &mut Map<String, Value>) -> &mut Vec<Value> {
+        if !map.get("inbounds").is_some_and(Value::is_array) {
+            map.insert("inbounds".into(), json!([]));
+        }
+        map["inbounds"].as_array_mut().unwrap()
+    }
+
+    fn clash_api_field(config_path: &Path, field: &str) -> Option<String> {
+        Self::read(config_path)?
+            .pointer(&format!("/experimental/clash_api/{field}"))?
+            .as_str()
+            .filter(|s| !s.is_empty())
+            .map(String::from)
+    }
+}
+
+impl CoreBackend for SingBox {
+    fn kind(&self) -> CoreKind {
+        CoreKind::SingBox
+    }
+
+    fn name(&self) -> &'static str {
+        "sing-box"
+    }
+
+    fn binary_name(&self) -> &'static str {
+        "sing-box"
+    }
+
+    fn config_file(&self) -> &'static str {
+        "config.json"
+    }
+
+    fn config_format(&self) -> &'static str {
+        "sing-box"
+    }
+
+    fn subconverter_target(&self) -> &'static str {
+        "singbox"
+    }
+
+    fn user_agent(&self) -> &'static str {
+        "sing-box proxy-rs"
+    }
+
+    fn is_config(&self, content: &str) -> bool {
+        is_sing_box_config(content)
+    }
+
+    fn repository(&self) -> &'static str {
+        "SagerNet/sing-box"
+    }
+
+    /// The latest release skips prereleases, the release list starts with the newest of any
+    fn latest_version_url(&self, channel: Channel) -> &'static str {
+        match channel {
+            Channel::Stable => "https://api.github.com/repos/SagerNet/sing-box/releases/latest",
+            Channel::Alpha => "https://api.github.com/repos/SagerNet/sing-box/releases?per_page=1",
+        }
+    }
+
+    fn parse_latest_version(&self, channel: Channel, body: &str) -> Result<String> {
+        let json: Value = serde_json::from_str(body)?;
+        let release = match channel {
+            Channel::Stable => &json,
+            Channel::Alpha => &json[0],
+        };
+        release["tag_name"]
+            .as_str()
+            .map(String::from)
+            .ok_or_else(|| anyhow!("No tag_name in the latest sing-box release"))
+    }
+
+    /// Prereleases are tagged like `v1.12.0-beta.1`
+    fn channel_of(&self, version: &str) -> Channel {
+        if version.contains('-') {
+            Channel::Alpha
+        } else {
+            Channel::Stable
+        }
+    }
+
+    fn release_asset(
+        &self,
+        version: &str,
+        os: Option<&str>,
+        arch: Option<&str>,
+    ) -> Result<(String, ArchiveType)> {
+        let (os, arch) = platform(os, arch)?;
+        let archive_type = archive_type(&os, ArchiveType::TarGz);
+        let version = version.trim_start_matches('v');
+        Ok((
+            format!("sing-box-{version}-{os}-{arch}.{archive_type}"),
+            archive_type,
+        ))
+    }
+
+    fn checksums_asset(&self) -> Option<&'static str> {
+        None
+    }
+
+    fn run_args(
+        &self,
+        config_dir: &Path,
+        _controller: &ControllerAddress,
+        _ui: Option<&Path>,
+    ) -> Vec<OsString> {
+        vec![
+            "run".into(),
+            "-D".into(),
+            config_dir.into(),
+            "-c".into(),
+            config_dir.join(self.config_file()).into(),
+        ]
+    }
+
+    fn version_args(&self) -> Vec<OsString> {
+        vec!["version".into()]
+    }
+
+    fn test_args(&self, config_dir: &Path) -> Vec<OsString> {
+        vec![
+            "check".into(),
+            "-D".into(),
+            config_dir.into(),
+            "-c".into(),
+            config_dir.join(self.config_file()).into(),
+        ]
+    }
+
+    fn mixed_port(&self, config_path: &Path) -> Option<u16> {
+        Self::read(config_path)?
+            .get("inbounds")?
+            .as_array()?
+            .iter()
+            .find(|inbound| inbound["type"] == "mixed")?
+            .get("listen_port")?
+            .as_u64()
+            .map(|port| port as u16)
+    }
+
+    fn set_mixed_port(&self, config_path: &Path, port: u16) -> Result<()> {
+        Self::edit(config_path, |map| {
+            let inbounds = Self::inbounds(map);
+            match inbounds
+                .iter_mut()
+                .find(|inbound| inbound["type"] == "mixed")
+            {
+                Some(inbound) => inbound["listen_port"] = port.into(),
+                None => inbounds.push(json!({
+                    "type": "mixed",
+                    "tag": "mixed-in",
+                    "listen": "127.0.0.1",
+                    "listen_port": port,
+                })),
+            }
+        })
+    }
+
+    fn external_controller(&self, config_path: &Path) -> Option<String> {
+        Self::clash_api_field(config_path, "external_controller")
+    }
+
+    fn set_external_controller(
+        &self,
+        config_path: &Path,
+        address: &ControllerAddress,
+        ui: Option<&Path>,
+    ) -> Result<()> {
+        let ControllerAddress::Tcp(address) = address else {
+            return Err(anyhow!(
+                "sing-box's controller can't listen on a Unix socket"
+            ));
+        };
+        Self::edit(config_path, |map| {
+            let clash_api = Self::clash_api(map);
+            clash_api.insert("external_controller".into(), address.to_string().into());
+            match ui {
+                Some(ui) => clash_api.insert("external_ui".into(), ui.to_string_lossy().into()),
+                None => clash_api.remove("external_ui"),
+            };
+        })
+    }
+
+    fn secret(&self, config_path: &Path) -> Option<String> {
+        Self::clash_api_field(config_path, "secret")
+    }
+
+    fn set_secret(&self, config_path: &Path, secret: &str) -> Result<()> {
+        Self::edit(config_path, |map| {
+            Self::clash_api(map).insert("secret".into(), secret.into());
+        })
+    }
+
+    fn set_tun(&self, config_path: &Path, enable: bool) -> Result<()> {
+        Self::edit(config_path, |map| {
+            let inbounds = Self::inbounds(map);
+            if !enable {
+                inbounds.retain(|inbound| inbound["type"] != "tun");
+                return;
+            }
+            if !inbounds.iter().any(|inbound| inbound["type"] == "tun") {
+                inbounds.push(json!({
+                    "type": "tun",
+                    "tag": "tun-in",
+                    "address": ["172.19.0.1/30", "fdfe:dcba:9876::1/126"],
+                    "auto_route": true,
+                    "strict_route": false,
+                    "stack": "mixed",
+                }));
+            }
+            if let Some(route) = map
+                .entry("route")
+                .or_insert_with(|| json!({}))
+                .as_object_mut()
+            {
+                route.insert("auto_detect_interface".into(), true.into());
+            }
+        })
+    }
+
+    fn set_delay_test(
+        &self,
+        config_path: &Path,
+        url: Option<&str>,
+        interval: Option<u64>,
+    ) -> Result<usize> {
+        let mut changed = 0;
+        Self::edit(config_path, |map| {
+            let Some(outbounds) = map.get_mut("outbounds").and_then(Value::as_array_mut) else {
+                return;
+            };
+            for outbound in outbounds.iter_mut().filter(|o| o["type"] == "urltest") {
+                let Some(outbound) = outbound.as_object_mut() else {
+                    continue;
+                };
+                let before = outbound.clone();
+                if let Some(url) = url {
+                    outbound.insert("url".into(), url.into());
+                }
+                if let Some(interval) = interval {
+                    outbound.insert("interval".into(), format!("{interval}s").into());
+                }
+                if *outbound != before {
+                    changed += 1;
+                }
+            }
+        })?;
+        Ok(changed)
+    }
+
+    fn redact_config(&self, config_path: &Path) -> Result<String> {
+        let content = fs::read_to_string(config_path)?;
+        let mut json: Value = serde_json::from_str(&content)
+            .with_context(|| format!("Invalid {}", config_path.display()))?;
+        Self::redact(&mut json);
+        Ok(serde_json::to_string_pretty(&json)? + "\n")
+    }
+}
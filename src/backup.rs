@@ -0,0 +1,88 @@
+use crate::utils::make_private;
+use anyhow::{Context, Result};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use log::*;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use tar::{Archive, Builder};
+
+/// Writes the `files` relative to `dir` that exist into a tar.gz at `archive`,
+/// readable only by the user as the files may hold secrets. Returns the files
+/// that were packed.
+pub fn pack(dir: &Path, files: &[PathBuf], archive: &Path) -> Result<Vec<PathBuf>> {
+    let file =
+        File::create(archive).with_context(|| format!("Failed to create {}", archive.display()))?;
+    make_private(archive)?;
+    let mut builder = Builder::new(GzEncoder::new(file, Compression::default()));
+    let mut packed = Vec::new();
+    for relative in files {
+        let path = dir.join(relative);
+        if !path.is_file() {
+            continue;
+        }
+        builder.append_path_with_name(&path, relative)?;
+        debug!("Packed {}", relative.display());
+        packed.push(relative.clone());
+    }
+    builder.into_inner()?.finish()?;
+    Ok(packed)
+}
+
+/// Extracts the files of a tar.gz made by [`pack`] into `dir`, overwriting
+/// existing ones. Entries `accept` rejects are skipped with a warning.
+/// Returns the files that were restored.
+pub fn unpack(archive: &Path, dir: &Path, accept: impl Fn(&Path) -> bool) -> Result<Vec<PathBuf>> {
+    let file =
+        File::open(archive).with_context(|| format!("Failed to open {}", archive.display()))?;
+    let mut archive = Archive::new(GzDecoder::new(file));
+    let mut restored = Vec::new();
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let relative = entry.path()?.into_owned();
+        if !entry.header().entry_type().is_file() || !accept(&relative) {
+            warn!(
+                "Skipping {}, it doesn't belong in the archive",
+                relative.display()
+            );
+            continue;
+        }
+        let path = dir.join(&relative);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        entry.unpack(&path)?;
+        debug!("Restored {}", relative.display());
+        restored.push(relative);
+    }
+    Ok(restored)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn archives_and_restores_privately() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = std::env::temp_dir().join(format!("proxy-rs-backup-{}", std::process::id()));
+        let mode = |path: &Path| fs::metadata(path).unwrap().permissions().mode() & 0o777;
+        fs::create_dir_all(dir.join("data")).unwrap();
+        fs::write(dir.join("data/proxy-rs.toml"), "secret = 1\n").unwrap();
+        fs::set_permissions(
+            dir.join("data/proxy-rs.toml"),
+            fs::Permissions::from_mode(0o644),
+        )
+        .unwrap();
+        let archive = dir.join("backup.tar.gz");
+        let files = [PathBuf::from("proxy-rs.toml")];
+        assert_eq!(pack(&dir.join("data"), &files, &archive).unwrap(), files);
+        assert_eq!(mode(&archive), 0o600);
+
+        let restored = unpack(&archive, &dir.join("restored"), |_| true).unwrap();
+        assert_eq!(restored, files);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,277 @@
+use crate::controller::{Controller, DEFAULT_DELAY_TEST_URL, DEFAULT_DELAY_TIMEOUT_MS};
+use crate::latency;
+use crate::output::Table;
+use anyhow::{anyhow, Result};
+use colored::Colorize;
+use futures_util::stream::{self, StreamExt};
+use log::*;
+use serde::Serialize;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::Path;
+
+/// Tested after the delay test URL, a common destination that is slow through a poor node
+const SLOW_TARGET: &str = "https://github.com";
+pub const DEFAULT_BENCH_ROUNDS: u32 = 5;
+const CONCURRENCY: usize = 16;
+
+/// How [`run_bench`] picks the nodes and targets.
+#[derive(Debug)]
+pub struct BenchOptions {
+    /// Only test the members of this proxy group
+    pub group: Option<String>,
+    /// The first target, the usual delay test URL
+    pub delay_test_url: String,
+    /// Tested after the delay test URL and GitHub
+    pub urls: Vec<String>,
+    /// Delay tests per node and target
+    pub rounds: u32,
+    pub timeout_ms: u64,
+    pub sort: BenchSort,
+}
+
+impl Default for BenchOptions {
+    fn default() -> Self {
+        Self {
+            group: None,
+            delay_test_url: DEFAULT_DELAY_TEST_URL.to_string(),
+            urls: Vec::new(),
+            rounds: DEFAULT_BENCH_ROUNDS,
+            timeout_ms: DEFAULT_DELAY_TIMEOUT_MS,
+            sort: BenchSort::Latency,
+        }
+    }
+}
+
+/// Order of the benchmark report.
+#[derive(clap::ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BenchSort {
+    /// Lowest mean delay first
+    #[default]
+    Latency,
+    /// Steadiest first
+    Jitter,
+    /// Fewest failed tests first
+    Loss,
+    Name,
+}
+
+/// Delay statistics of one node over every target and round.
+#[derive(Serialize, Debug)]
+pub struct BenchResult {
+    pub name: String,
+    /// Mean delay in milliseconds of the tests that succeeded
+    pub latency: Option<f64>,
+    /// Mean difference in milliseconds between consecutive delays to a target
+    pub jitter: Option<f64>,
+    /// Share of the tests that failed or timed out, 0 to 1
+    pub failure_rate: f64,
+    pub targets: Vec<TargetStats>,
+}
+
+/// Delay statistics of one node to one target.
+#[derive(Serialize, Debug)]
+pub struct TargetStats {
+    pub url: String,
+    pub latency: Option<f64>,
+    pub jitter: Option<f64>,
+    pub failure_rate: f64,
+}
+
+impl TargetStats {
+    fn new(url: &str, samples: &[Option<u64>]) -> Self {
+        let delays: Vec<f64> = samples.iter().flatten().map(|&d| d as f64).collect();
+        Self {
+            url: url.to_string(),
+            latency: mean(&delays),
+            jitter: mean(
+                &delays
+                    .windows(2)
+                    .map(|pair| (pair[1] - pair[0]).abs())
+                    .collect::<Vec<_>>(),
+            ),
+            failure_rate: failure_rate(samples.len() - delays.len(), samples.len()),
+        }
+    }
+}
+
+fn mean(values: &[f64]) -> Option<f64> {
+    (!values.is_empty()).then(|| values.iter().sum::<f64>() / values.len() as f64)
+}
+
+fn failure_rate(failed: usize, total: usize) -> f64 {
+    if total == 0 {
+        0.0
+    } else {
+        failed as f64 / total as f64
+    }
+}
+
+/// Tests every node, or only the members of `group`, `rounds` times against
+/// each target. Nodes are tested in parallel, the rounds of a node one after another.
+pub async fn run_bench(
+    controller: &Controller,
+    options: &BenchOptions,
+) -> Result<Vec<BenchResult>> {
+    if options.rounds == 0 {
+        return Err(anyhow!("--rounds must be at least 1"));
+    }
+    let mut targets = vec![options.delay_test_url.as_str(), SLOW_TARGET];
+    for url in &options.urls {
+        if !targets.contains(&url.as_str()) {
+            targets.push(url);
+        }
+    }
+    let names = latency::node_names(controller, options.group.as_deref()).await?;
+    if names.is_empty() {
+        return Err(anyhow!("No node to test"));
+    }
+    info!(
+        "Testing {} nodes against {} targets, {} rounds each...",
+        names.len(),
+        targets.len(),
+        options.rounds
+    );
+
+    let targets = &targets;
+    let mut results: Vec<BenchResult> = stream::iter(names)
+        .map(|name| async move {
+            let mut stats = Vec::new();
+            let (mut failed, mut total) = (0, 0);
+            for url in targets {
+                let mut samples = Vec::new();
+                for _ in 0..options.rounds {
+                    let delay = controller
+                        .proxy_delay(&name, url, options.timeout_ms)
+                        .await
+                        .unwrap_or_else(|e| {
+                            debug!("Delay test for {name} with {url} failed: {e}");
+                            None
+                        });
+                    samples.push(delay);
+                }
+                failed += samples.iter().filter(|d| d.is_none()).count();
+                total += samples.len();
+                stats.push(TargetStats::new(url, &samples));
+            }
+            let per_target = |value: fn(&TargetStats) -> Option<f64>| {
+                mean(&stats.iter().filter_map(value).collect::<Vec<_>>())
+            };
+            BenchResult {
+                latency: per_target(|t| t.latency),
+                jitter: per_target(|t| t.jitter),
+                failure_rate: failure_rate(failed, total),
+                name,
+                targets: stats,
+            }
+        })
+        .buffer_unordered(CONCURRENCY)
+        .collect()
+        .await;
+
+    sort_results(&mut results, options.sort);
+    Ok(results)
+}
+
+fn sort_results(results: &mut [BenchResult], sort: BenchSort) {
+    // Nodes that never answered go last
+    let key = |value: Option<f64>| value.unwrap_or(f64::MAX);
+    match sort {
+        BenchSort::Latency => results.sort_by(|a, b| key(a.latency).total_cmp(&key(b.latency))),
+        BenchSort::Jitter => results.sort_by(|a, b| {
+            key(a.jitter)
+                .total_cmp(&key(b.jitter))
+                .then(key(a.latency).total_cmp(&key(b.latency)))
+        }),
+        BenchSort::Loss => results.sort_by(|a, b| {
+            a.failure_rate
+                .total_cmp(&b.failure_rate)
+                .then(key(a.latency).total_cmp(&key(b.latency)))
+        }),
+        BenchSort::Name => results.sort_by(|a, b| a.name.cmp(&b.name)),
+    }
+}
+
+fn format_ms(value: Option<f64>) -> String {
+    value.map_or_else(|| "-".to_string(), |ms| format!("{ms:.0} ms"))
+}
+
+/// Prints the results as a table with the mean delay to each target, colored
+/// by failure rate.
+pub fn print_bench_table(results: &[BenchResult]) {
+    let Some(first) = results.first() else {
+        return;
+    };
+    let hosts = first.targets.iter().map(|t| {
+        reqwest::Url::parse(&t.url)
+            .ok()
+            .and_then(|url| url.host_str().map(String::from))
+            .unwrap_or_else(|| t.url.clone())
+    });
+    let header: Vec<String> = ["Node", "Latency", "Jitter", "Loss"]
+        .into_iter()
+        .map(String::from)
+        .chain(hosts)
+        .collect();
+    let mut table = Table::new(header).align_right(1..4 + first.targets.len());
+    for result in results {
+        let loss = format!("{:.0}%", result.failure_rate * 100.0);
+        let loss = if result.failure_rate == 0.0 {
+            loss.green()
+        } else if result.failure_rate < 0.2 {
+            loss.yellow()
+        } else {
+            loss.red()
+        };
+        let targets = result.targets.iter().map(|t| format_ms(t.latency).into());
+        table.row(
+            [
+                result.name.as_str().into(),
+                format_ms(result.latency).into(),
+                format_ms(result.jitter).into(),
+                loss,
+            ]
+            .into_iter()
+            .chain(targets),
+        );
+    }
+    table.print();
+}
+
+/// Writes the results to `path` as a JSON array.
+pub fn write_bench_json(results: &[BenchResult], path: &Path) -> Result<()> {
+    fs::write(path, serde_json::to_string_pretty(results)?)?;
+    info!("Results written to {}", path.display());
+    Ok(())
+}
+
+/// Writes the results to `path` as CSV, one row per node and target.
+pub fn write_bench_csv(results: &[BenchResult], path: &Path) -> Result<()> {
+    let field = |value: Option<f64>| value.map_or_else(String::new, |v| format!("{v:.1}"));
+    let mut csv = String::from("node,target,latency_ms,jitter_ms,failure_rate\n");
+    for result in results {
+        for target in &result.targets {
+            let _ = writeln!(
+                csv,
+                "{},{},{},{},{:.3}",
+                csv_field(&result.name),
+                csv_field(&target.url),
+                field(target.latency),
+                field(target.jitter),
+                target.failure_rate
+            );
+        }
+    }
+    fs::write(path, csv)?;
+    info!("Results written to {}", path.display());
+    Ok(())
+}
+
+/// Quotes `value` if it holds a comma, quote or line break.
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
@@ -0,0 +1,152 @@
+//! Offline bundles: the core, WebUI and geodata downloaded on a connected
+//! machine and packed into a tar.gz by `bundle create`, for `bundle install`
+//! on one that can't reach GitHub or a mirror.
+
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+/// Describes the rest of the bundle, at its root
+pub const MANIFEST_FILE: &str = "bundle.toml";
+pub const CORE_DIR: &str = "core";
+pub const UI_DIR: &str = "ui";
+pub const GEODATA_DIR: &str = "geodata";
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BundleManifest {
+    pub created_at: String,
+    /// OS the core was downloaded for, as spelled in release asset names
+    pub os: String,
+    /// `mihomo` or `sing-box`
+    pub core: String,
+    pub core_version: String,
+    /// Release asset of the core, in [`CORE_DIR`]
+    pub core_asset: BundledFile,
+    /// Release zip of the WebUI, in [`UI_DIR`]
+    pub ui: Option<BundledFile>,
+    /// Geodata files, in [`GEODATA_DIR`]
+    #[serde(default)]
+    pub geodata: Vec<BundledFile>,
+}
+
+/// A downloaded file and where it came from, recorded in the manifest on install.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BundledFile {
+    pub name: String,
+    pub url: String,
+}
+
+impl BundleManifest {
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("{MANIFEST_FILE} is missing, this is not a bundle"))?;
+        let manifest: Self =
+            toml::from_str(&content).with_context(|| format!("Invalid {MANIFEST_FILE}"))?;
+        // The names are joined to the bundle and data dirs
+        let files = std::iter::once(&manifest.core_asset)
+            .chain(&manifest.ui)
+            .chain(&manifest.geodata);
+        for file in files {
+            if !is_plain_name(&file.name) {
+                return Err(anyhow!(
+                    "Invalid file name {} in {MANIFEST_FILE}",
+                    file.name
+                ));
+            }
+        }
+        Ok(manifest)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        fs::write(path, toml::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    /// Every file of the bundle, relative to its root.
+    pub fn files(&self) -> Vec<PathBuf> {
+        let mut files = vec![
+            PathBuf::from(MANIFEST_FILE),
+            Path::new(CORE_DIR).join(&self.core_asset.name),
+        ];
+        files.extend(self.ui.iter().map(|ui| Path::new(UI_DIR).join(&ui.name)));
+        files.extend(
+            self.geodata
+                .iter()
+                .map(|file| Path::new(GEODATA_DIR).join(&file.name)),
+        );
+        files
+    }
+}
+
+/// Whether `relative` can be part of a bundle: the manifest, or a plain file
+/// in one of its folders.
+pub fn is_bundle_file(relative: &Path) -> bool {
+    let components: Vec<_> = relative.components().collect();
+    match components.as_slice() {
+        [Component::Normal(name)] => *name == MANIFEST_FILE,
+        [Component::Normal(dir), Component::Normal(_)] => {
+            [CORE_DIR, UI_DIR, GEODATA_DIR].iter().any(|d| dir == d)
+        }
+        _ => false,
+    }
+}
+
+/// Whether `name` is a file name without separators, `.` or `..`.
+fn is_plain_name(name: &str) -> bool {
+    let mut components = Path::new(name).components();
+    !name.contains(['/', '\\'])
+        && matches!(
+            (components.next(), components.next()),
+            (Some(Component::Normal(_)), None)
+        )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accepts_only_plain_names() {
+        assert!(is_plain_name("mihomo-linux-amd64-v1.19.0.gz"));
+        assert!(is_plain_name("geosite.dat"));
+        for name in [
+            "",
+            ".",
+            "..",
+            "../x",
+            "a/b",
+            "/etc/passwd",
+            "..\\x",
+            "C:\\x",
+        ] {
+            assert!(!is_plain_name(name), "{name}");
+        }
+    }
+
+    #[test]
+    fn rejects_manifests_escaping_the_bundle() {
+        let dir = std::env::temp_dir().join(format!("proxy-rs-bundle-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(MANIFEST_FILE);
+        let file = |name: &str| BundledFile {
+            name: name.to_string(),
+            url: String::new(),
+        };
+        let mut manifest = BundleManifest {
+            created_at: String::new(),
+            os: "linux".to_string(),
+            core: "mihomo".to_string(),
+            core_version: "v1.19.0".to_string(),
+            core_asset: file("mihomo.gz"),
+            ui: None,
+            geodata: vec![file("geoip.dat")],
+        };
+        manifest.save(&path).unwrap();
+        assert!(BundleManifest::load(&path).is_ok());
+        manifest.geodata.push(file("../../mihomo"));
+        manifest.save(&path).unwrap();
+        assert!(BundleManifest::load(&path).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,209 @@
+//! Downloads shared by every data dir and instance on the machine: core
+//! release assets, WebUI archives and geodata are stored once by SHA256, so
+//! other profiles link or copy them instead of downloading them again.
+
+use crate::downloader::sha256_file;
+use anyhow::{Context, Result};
+use jiff::Timestamp;
+use log::*;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+const INDEX_FILE: &str = "index.toml";
+/// Held while the index is read, changed and written back
+const LOCK_FILE: &str = "index.lock";
+const BLOBS_DIR: &str = "blobs";
+/// Downloads of URLs that always serve the newest file, such as the `latest`
+/// geodata release or a branch archive, are reused for this long
+const UNVERSIONED_MAX_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+/// Entries no profile used for this long are removed when another is stored
+const UNUSED_MAX_AGE: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+pub struct SharedCache {
+    dir: PathBuf,
+}
+
+/// Cached downloads keyed by URL.
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct Index {
+    #[serde(default)]
+    entries: BTreeMap<String, Entry>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "kebab-case")]
+struct Entry {
+    sha256: String,
+    fetched_at: Timestamp,
+    used_at: Timestamp,
+}
+
+impl SharedCache {
+    pub fn new(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Puts the cached download of `url` at `path`, hard-linked if `link`,
+    /// otherwise copied. `false` if there is none, or it is stale or damaged.
+    pub fn restore(&self, url: &str, path: &Path, link: bool) -> Result<bool> {
+        let _lock = self.lock()?;
+        let mut index = self.load();
+        let Some(entry) = index.entries.get_mut(url) else {
+            return Ok(false);
+        };
+        if !is_versioned(url) && age(entry.fetched_at) > UNVERSIONED_MAX_AGE {
+            return Ok(false);
+        }
+        let blob = self.blob_path(&entry.sha256);
+        // A linked copy may have been rewritten in place since
+        if !blob.exists() || sha256_file(&blob)? != entry.sha256 {
+            index.entries.remove(url);
+            let _ = fs::remove_file(&blob);
+            self.save(&index)?;
+            return Ok(false);
+        }
+        if path.exists() {
+            fs::remove_file(path)?;
+        }
+        if !link || fs::hard_link(&blob, path).is_err() {
+            fs::copy(&blob, path)?;
+        }
+        entry.used_at = Timestamp::now();
+        self.save(&index)?;
+        info!(
+            "Using {} from the shared cache in {}",
+            file_name(url),
+            self.dir.display()
+        );
+        Ok(true)
+    }
+
+    /// Adds the file at `path`, just downloaded from `url`, and removes the
+    /// entries no profile used for a month.
+    pub fn store(&self, url: &str, path: &Path) -> Result<()> {
+        let _lock = self.lock()?;
+        let sha256 = sha256_file(path)?;
+        let blob = self.blob_path(&sha256);
+        if !blob.exists() {
+            fs::create_dir_all(self.dir.join(BLOBS_DIR))?;
+            let partial = blob.with_extension("part");
+            fs::copy(path, &partial)?;
+            fs::rename(&partial, &blob)?;
+        }
+        let mut index = self.load();
+        let now = Timestamp::now();
+        index.entries.insert(
+            url.to_string(),
+            Entry {
+                sha256,
+                fetched_at: now,
+                used_at: now,
+            },
+        );
+        index
+            .entries
+            .retain(|_, entry| age(entry.used_at) <= UNUSED_MAX_AGE);
+        self.save(&index)?;
+        self.remove_orphans(&index)
+    }
+
+    /// Number of files and bytes in the cache.
+    pub fn usage(&self) -> Result<(usize, u64)> {
+        let Ok(entries) = fs::read_dir(self.dir.join(BLOBS_DIR)) else {
+            return Ok((0, 0));
+        };
+        let mut usage = (0, 0);
+        for entry in entries {
+            usage.0 += 1;
+            usage.1 += entry?.metadata()?.len();
+        }
+        Ok(usage)
+    }
+
+    /// Removes every cached download.
+    pub fn clear(&self) -> Result<()> {
+        if self.dir.exists() {
+            fs::remove_dir_all(&self.dir)
+                .with_context(|| format!("Failed to remove {}", self.dir.display()))?;
+        }
+        Ok(())
+    }
+
+    /// Waits for other profiles to finish with the index, so none of them
+    /// drops the entry another just added or prunes a blob it is linking.
+    fn lock(&self) -> Result<fs::File> {
+        fs::create_dir_all(&self.dir)?;
+        let file = fs::OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(false)
+            .open(self.dir.join(LOCK_FILE))?;
+        file.lock()?;
+        Ok(file)
+    }
+
+    fn blob_path(&self, sha256: &str) -> PathBuf {
+        self.dir.join(BLOBS_DIR).join(sha256)
+    }
+
+    fn load(&self) -> Index {
+        fs::read_to_string(self.dir.join(INDEX_FILE))
+            .ok()
+            .and_then(|content| toml::from_str(&content).ok())
+            .unwrap_or_default()
+    }
+
+    /// Written through a temporary file, in case another proxy-rs version
+    /// reads it without the lock.
+    fn save(&self, index: &Index) -> Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        let path = self.dir.join(INDEX_FILE);
+        let partial = path.with_extension("part");
+        fs::write(&partial, toml::to_string_pretty(index)?)?;
+        fs::rename(&partial, &path)?;
+        Ok(())
+    }
+
+    /// Blobs no entry refers to anymore.
+    fn remove_orphans(&self, index: &Index) -> Result<()> {
+        for entry in fs::read_dir(self.dir.join(BLOBS_DIR))? {
+            let path = entry?.path();
+            // Blobs another profile is still writing
+            if path.extension().is_some() {
+                continue;
+            }
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
+            if !index.entries.values().any(|entry| entry.sha256 == name) {
+                debug!("Removing {} from the shared cache", path.display());
+                let _ = fs::remove_file(&path);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Whether `url` is a release asset of a tag other than `latest`, which
+/// never changes once published.
+fn is_versioned(url: &str) -> bool {
+    url.split_once("/releases/download/")
+        .and_then(|(_, rest)| rest.split('/').next())
+        .is_some_and(|tag| tag != "latest")
+}
+
+fn age(time: Timestamp) -> Duration {
+    Timestamp::now()
+        .duration_since(time)
+        .try_into()
+        .unwrap_or_default()
+}
+
+fn file_name(url: &str) -> &str {
+    url.rsplit('/').next().unwrap_or(url)
+}
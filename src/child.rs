@@ -0,0 +1,232 @@
+//! Ties a core run in the foreground or by the watchdog to proxy-rs, so it
+//! doesn't keep running when proxy-rs is killed or crashes.
+
+#[cfg(unix)]
+use anyhow::anyhow;
+use anyhow::Result;
+#[cfg(unix)]
+use log::debug;
+#[cfg(target_os = "linux")]
+use log::warn;
+#[cfg(unix)]
+use std::path::Path;
+use std::process::{Child, Command};
+
+/// Puts the child in its own process group, so Ctrl+C reaches only proxy-rs
+/// which then stops it gracefully, and on Linux has it sent SIGTERM when
+/// proxy-rs dies. On Windows the child gets its own console process group.
+/// Changing the user clears the death signal, so [`run_as`] goes first.
+pub fn tie_to_parent(command: &mut Command) {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+        command.process_group(0);
+        #[cfg(target_os = "linux")]
+        {
+            warn_file_capabilities(Path::new(command.get_program()));
+            let parent = nix::unistd::getpid();
+            // SAFETY: prctl, getppid and _exit are async-signal-safe
+            unsafe {
+                command.pre_exec(move || {
+                    // Sent when the spawning thread exits, tokio's workers live
+                    // as long as the runtime
+                    nix::sys::prctl::set_pdeathsig(nix::sys::signal::Signal::SIGTERM)?;
+                    // proxy-rs died before the signal was set up
+                    if nix::unistd::getppid() != parent {
+                        nix::libc::_exit(1);
+                    }
+                    Ok(())
+                });
+            }
+        }
+    }
+    #[cfg(windows)]
+    {
+        use std::os::windows::process::CommandExt;
+        const CREATE_NEW_PROCESS_GROUP: u32 = 0x0000_0200;
+        command.creation_flags(CREATE_NEW_PROCESS_GROUP);
+    }
+}
+
+/// Executing a binary with file capabilities, as granted for TUN, clears the
+/// death signal, so such a core outlives a killed proxy-rs. Warns once.
+#[cfg(target_os = "linux")]
+fn warn_file_capabilities(program: &Path) {
+    use std::os::unix::ffi::OsStrExt;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    static WARNED: AtomicBool = AtomicBool::new(false);
+    let Ok(path) = std::ffi::CString::new(program.as_os_str().as_bytes()) else {
+        return;
+    };
+    // SAFETY: both strings are NUL-terminated, a null buffer only asks for the size
+    let size = unsafe {
+        nix::libc::getxattr(
+            path.as_ptr(),
+            c"security.capability".as_ptr(),
+            std::ptr::null_mut(),
+            0,
+        )
+    };
+    if size > 0 && !WARNED.swap(true, Ordering::Relaxed) {
+        warn!(
+            "{} has file capabilities, so it keeps running if proxy-rs is killed, \
+             `proxy stop` it then",
+            program.display()
+        );
+    }
+}
+
+/// Runs the child as `user` instead of root, switching in a `pre_exec` hook,
+/// so it must be called after [`crate::resources::ResourceLimits::apply`] and
+/// before [`tie_to_parent`]. `owned` is handed to the user, as
+/// the core writes there, and it and `reachable` must be reachable by it.
+/// Directories under `private`, which proxy-rs keeps at 0700, get the search
+/// bit (0711) so the user can pass through without listing them.
+#[cfg(unix)]
+pub fn run_as(
+    command: &mut Command,
+    user: &str,
+    private: &Path,
+    owned: &Path,
+    reachable: &[&Path],
+) -> Result<()> {
+    use std::os::unix::fs::{MetadataExt, PermissionsExt};
+    use std::os::unix::process::CommandExt;
+    let user =
+        nix::unistd::User::from_name(user)?.ok_or_else(|| anyhow!("No user named {user}"))?;
+    let (uid, gid) = (user.uid.as_raw(), user.gid.as_raw());
+    for path in std::iter::once(owned).chain(reachable.iter().copied()) {
+        // The supplementary groups are dropped, only the primary one counts
+        for dir in path.ancestors().skip(1).filter(|dir| dir.is_dir()) {
+            let metadata = std::fs::metadata(dir)?;
+            let mode = metadata.mode();
+            let searchable = if metadata.uid() == uid {
+                mode & 0o100 != 0
+            } else if metadata.gid() == gid {
+                mode & 0o010 != 0
+            } else {
+                mode & 0o001 != 0
+            };
+            if searchable {
+                continue;
+            }
+            if dir.starts_with(private) {
+                std::fs::set_permissions(dir, std::fs::Permissions::from_mode(mode | 0o011))?;
+                debug!("Let {} search {}", user.name, dir.display());
+                continue;
+            }
+            return Err(anyhow!(
+                "{} can't reach {} through {}, move it where the user can, e.g. with --data-dir /var/lib/proxy-rs",
+                user.name,
+                path.display(),
+                dir.display()
+            ));
+        }
+    }
+    chown_all(owned, uid, gid)?;
+    // Not Command::uid, which switches before any pre_exec runs and so before
+    // the negative niceness and raised open-file limit the core may be given
+    // SAFETY: setgroups, setgid and setuid are async-signal-safe
+    unsafe {
+        command.pre_exec(move || {
+            use nix::libc;
+            if libc::setgroups(0, std::ptr::null()) != 0
+                || libc::setgid(gid) != 0
+                || libc::setuid(uid) != 0
+            {
+                return Err(std::io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+    command.env("HOME", &user.dir).env("USER", &user.name);
+    Ok(())
+}
+
+/// Hands `path` and everything in it to `uid`, skipping what the user owns
+/// already, so only files proxy-rs wrote since the last start are changed.
+#[cfg(unix)]
+fn chown_all(path: &Path, uid: u32, gid: u32) -> Result<()> {
+    use std::os::unix::fs::MetadataExt;
+    let metadata = std::fs::symlink_metadata(path)?;
+    if metadata.uid() != uid || metadata.gid() != gid {
+        std::os::unix::fs::lchown(path, Some(uid), Some(gid))?;
+    }
+    if metadata.is_dir() {
+        for entry in std::fs::read_dir(path)? {
+            chown_all(&entry?.path(), uid, gid)?;
+        }
+    }
+    Ok(())
+}
+
+/// On Windows, adds `child` to a job object that kills it once the last handle
+/// to the job, held by proxy-rs until it exits, is closed. The process group
+/// and death signal of [`tie_to_parent`] cover the other platforms.
+pub fn adopt(child: &Child) -> Result<()> {
+    #[cfg(windows)]
+    windows::assign_to_job(child)?;
+    #[cfg(not(windows))]
+    let _ = child;
+    Ok(())
+}
+
+#[cfg(windows)]
+mod windows {
+    use anyhow::{anyhow, Result};
+    use std::os::windows::io::AsRawHandle;
+    use std::process::Child;
+    use std::sync::OnceLock;
+    use winapi::um::jobapi2::{
+        AssignProcessToJobObject, CreateJobObjectW, SetInformationJobObject,
+    };
+    use winapi::um::winnt::{
+        JobObjectExtendedLimitInformation, HANDLE, JOBOBJECT_EXTENDED_LIMIT_INFORMATION,
+        JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE,
+    };
+
+    /// The job handle, never closed so the job lives exactly as long as proxy-rs.
+    struct Job(HANDLE);
+    // SAFETY: a job handle can be used from any thread
+    unsafe impl Send for Job {}
+    unsafe impl Sync for Job {}
+
+    static JOB: OnceLock<Option<Job>> = OnceLock::new();
+
+    fn create_job() -> Option<Job> {
+        // SAFETY: the limit information is fully initialized before it is passed on
+        unsafe {
+            let job = CreateJobObjectW(std::ptr::null_mut(), std::ptr::null());
+            if job.is_null() {
+                return None;
+            }
+            let mut info: JOBOBJECT_EXTENDED_LIMIT_INFORMATION = std::mem::zeroed();
+            info.BasicLimitInformation.LimitFlags = JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE;
+            let set = SetInformationJobObject(
+                job,
+                JobObjectExtendedLimitInformation,
+                &mut info as *mut _ as *mut _,
+                std::mem::size_of::<JOBOBJECT_EXTENDED_LIMIT_INFORMATION>() as u32,
+            );
+            (set != 0).then_some(Job(job))
+        }
+    }
+
+    pub fn assign_to_job(child: &Child) -> Result<()> {
+        let Some(job) = JOB.get_or_init(create_job) else {
+            return Err(anyhow!(
+                "Failed to create a job object: {}",
+                std::io::Error::last_os_error()
+            ));
+        };
+        // SAFETY: both handles are valid while `child` and `JOB` are alive
+        let assigned = unsafe { AssignProcessToJobObject(job.0, child.as_raw_handle() as HANDLE) };
+        if assigned == 0 {
+            return Err(anyhow!(
+                "Failed to add the core to a job object: {}",
+                std::io::Error::last_os_error()
+            ));
+        }
+        Ok(())
+    }
+}
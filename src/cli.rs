@@ -1,16 +1,225 @@
-use clap::{Parser, Subcommand};
+use clap::{ArgAction, Parser, Subcommand, ValueEnum};
+use log::LevelFilter;
+use proxy::backend::{Channel, CoreKind, OSES};
+use proxy::bench::{BenchSort, DEFAULT_BENCH_ROUNDS};
+use proxy::controller::{Mode, ProviderKind, DEFAULT_DELAY_TIMEOUT_MS};
+use proxy::import::ImportClient;
+use proxy::logs::CoreLogLevel;
+use proxy::output::ColorChoice;
+use proxy::overrides::{Bypass, DnsMode, GroupType};
+use proxy::settings::WebUi;
+use proxy::shell::Shell;
+use proxy::speedtest::{DEFAULT_SPEEDTEST_SECS, DEFAULT_SPEEDTEST_URL};
+use proxy::tunnel::TunnelBackend;
+use std::ffi::OsString;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::time::Duration;
 
 #[derive(Parser, Debug)]
-#[command(author, version, about, long_about = None, arg_required_else_help = true)]
+#[command(
+    author,
+    version,
+    about,
+    long_about = None,
+    arg_required_else_help = true,
+    after_help = "Other commands run proxy-rs-<command> from PATH, e.g. `proxy hello` runs proxy-rs-hello"
+)]
 pub struct Cli {
+    #[arg(
+        long,
+        global = true,
+        value_name = "DIR",
+        help = "Directory for the core, config and state [env: PROXY_RS_DATA_DIR] [default: data-dir from proxy-rs.toml, or platform data dir]"
+    )]
+    pub data_dir: Option<PathBuf>,
+    #[arg(
+        long,
+        global = true,
+        conflicts_with = "data_dir",
+        help = "Use ./proxy-data in the current directory instead of the global data dir, for a project-scoped proxy, even with PROXY_RS_DATA_DIR set"
+    )]
+    pub local: bool,
+    #[arg(
+        long,
+        global = true,
+        value_name = "NAME",
+        value_parser = parse_instance_name,
+        help = "Operate on a named instance with its own config, ports and scripts"
+    )]
+    pub instance: Option<String>,
+    #[arg(
+        long,
+        global = true,
+        value_enum,
+        num_args = 0..=1,
+        help = "Proxy core to use, sing-box reads sing-box JSON subscriptions [default: core from proxy-rs.toml, or mihomo]. `clean --core` without a value removes the core binary"
+    )]
+    pub core: Option<Option<CoreKind>>,
+    #[arg(
+        long,
+        global = true,
+        value_name = "ARCH",
+        help = "Architecture of the core release to download, e.g. amd64-compatible, amd64-go120 or armv6 [default: core-arch from proxy-rs.toml, or detected]"
+    )]
+    pub core_arch: Option<String>,
+    #[arg(
+        long,
+        global = true,
+        help = "Download through the running Mihomo's mixed-port [default: via-proxy from proxy-rs.toml, or only when running]"
+    )]
+    pub via_proxy: bool,
+    #[arg(
+        long,
+        global = true,
+        value_name = "URL",
+        help = "Proxy for downloads, e.g. http://proxy.corp:3128 or socks5://127.0.0.1:1080, NO_PROXY hosts are still reached directly [default: download-proxy from proxy-rs.toml, or HTTPS_PROXY/HTTP_PROXY/ALL_PROXY]"
+    )]
+    pub download_proxy: Option<String>,
+    #[arg(
+        long,
+        global = true,
+        value_name = "URL",
+        help = "URL of the delay tests of url-test groups, test, bench and the checks after start [default: delay-test-url from proxy-rs.toml, or Google generate_204]"
+    )]
+    pub delay_test_url: Option<String>,
+    #[arg(
+        long,
+        global = true,
+        value_name = "SECS",
+        help = "Seconds between the delay tests of url-test and fallback groups [default: delay-test-interval from proxy-rs.toml, or the subscription's]"
+    )]
+    pub delay_test_interval: Option<u64>,
+    #[arg(
+        long,
+        global = true,
+        help = "Select the nodes picked in each group again after the core restarts or reloads [default: sticky-selection from proxy-rs.toml]"
+    )]
+    pub sticky_selection: bool,
+    #[arg(
+        long,
+        global = true,
+        value_name = "LEVEL",
+        help = "Log level: off, error, warn, info, debug or trace [default: RUST_LOG, or info]"
+    )]
+    pub log_level: Option<LevelFilter>,
+    #[arg(
+        short,
+        long,
+        global = true,
+        action = ArgAction::Count,
+        conflicts_with = "log_level",
+        help = "Log more, -v for debug and -vv for trace"
+    )]
+    pub verbose: u8,
+    #[arg(
+        short,
+        long,
+        global = true,
+        conflicts_with_all = ["log_level", "verbose"],
+        help = "Only log errors, for scripting"
+    )]
+    pub quiet: bool,
+    #[arg(
+        long,
+        global = true,
+        value_name = "FILE",
+        help = "Append logs to this file instead of stderr"
+    )]
+    pub log_file: Option<PathBuf>,
+    #[arg(
+        short,
+        long,
+        global = true,
+        env = "PROXY_RS_NONINTERACTIVE",
+        value_parser = clap::builder::FalseyValueParser::new(),
+        help = "Take the default answer of every prompt, for scripts and cron. Commands that delete data also need --force"
+    )]
+    pub yes: bool,
+    #[arg(
+        long,
+        global = true,
+        help = "Probe the GitHub mirrors again instead of using the fastest one of the last hour"
+    )]
+    pub reselect_mirror: bool,
+    #[arg(
+        long,
+        global = true,
+        value_enum,
+        default_value_t = LogFormat::Text,
+        help = "Log format, json prints one object per line"
+    )]
+    pub log_format: LogFormat,
+    #[arg(
+        long,
+        global = true,
+        value_enum,
+        default_value_t = ColorChoice::Auto,
+        help = "Color tables and logs: auto only on a terminal without NO_COLOR, never or always"
+    )]
+    pub color: ColorChoice,
     #[command(subcommand)]
     pub command: Option<Commands>,
 }
 
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+impl Cli {
+    /// The level from `--log-level`, `-v` or `-q`, `None` to use `RUST_LOG`.
+    pub fn log_level(&self) -> Option<LevelFilter> {
+        if self.quiet {
+            return Some(LevelFilter::Error);
+        }
+        match self.verbose {
+            0 => self.log_level,
+            1 => Some(LevelFilter::Debug),
+            _ => Some(LevelFilter::Trace),
+        }
+    }
+
+    /// Whether this is `start --container`.
+    pub fn container(&self) -> bool {
+        matches!(
+            self.command,
+            Some(Commands::Start {
+                container: true,
+                ..
+            })
+        )
+    }
+}
+
 #[derive(Subcommand, Debug)]
+// Parsed once per run, boxing `Start` would only complicate matching it
+#[allow(clippy::large_enum_variant)]
 pub enum Commands {
     #[command(about = "Show status of Mihomo")]
-    Status,
+    Status {
+        #[arg(long, conflicts_with = "instance", help = "Show all instances")]
+        all: bool,
+        #[arg(
+            long,
+            value_name = "SECS",
+            num_args = 0..=1,
+            default_missing_value = "2",
+            conflicts_with = "all",
+            help = "Repaint memory, traffic and connections every SECS seconds [default: 2]"
+        )]
+        watch: Option<u64>,
+    },
+    #[command(
+        about = "Set up proxy-rs.toml step by step: data dir, subscription, WebUI, ports and autostart"
+    )]
+    Init,
+    #[command(about = "Create named instances for --instance")]
+    Instance {
+        #[command(subcommand)]
+        command: InstanceCommands,
+    },
     #[command(about = "Start Mihomo", alias="run")]
     Start {
         #[arg(
@@ -18,12 +227,1140 @@ pub enum Commands {
             help = "URL to download subscription config file."
         )]
         url: Option<String>,
+        #[arg(
+            long = "url",
+            value_name = "URL",
+            help = "Another subscription URL to merge into the config, can be repeated"
+        )]
+        urls: Vec<String>,
+        #[arg(
+            long,
+            conflicts_with_all = ["url", "urls"],
+            help = "Download the subscription last passed to start again"
+        )]
+        update_sub: bool,
+        #[arg(
+            long,
+            help = "Skip SHA256 verification of the downloaded Mihomo binary"
+        )]
+        no_verify: bool,
+        #[arg(
+            long,
+            help = "Enable TUN mode for system-wide transparent proxying (requires root/administrator), kept for later starts"
+        )]
+        tun: bool,
+        #[arg(long, conflicts_with = "tun", help = "Turn TUN mode off again")]
+        no_tun: bool,
+        #[arg(
+            long,
+            help = "Stay in the foreground and restart Mihomo whenever it exits"
+        )]
+        watch: bool,
+        #[arg(
+            long,
+            help = "Stay in the foreground and reload the config whenever config.yaml or override.yaml changes"
+        )]
+        watch_config: bool,
+        #[arg(
+            long,
+            conflicts_with = "watch",
+            help = "Run Mihomo as a child and log its output here until Ctrl+C or SIGTERM, for systemd and containers"
+        )]
+        foreground: bool,
+        #[arg(
+            long,
+            env = "PROXY_RS_CONTAINER",
+            value_parser = clap::builder::FalseyValueParser::new(),
+            conflicts_with_all = ["watch", "tun"],
+            help = "Run as a container's entrypoint: in the foreground, logging to stdout, without prompts, open to the container network, configured by SUBSCRIPTION_URL, MIXED_PORT and CONTROLLER_PORT"
+        )]
+        container: bool,
+        #[arg(
+            long,
+            value_name = "PORT",
+            help = "Serve /healthz on this port with --foreground, --watch or --container, for monitors and orchestrators [default: health-port from proxy-rs.toml]"
+        )]
+        health_port: Option<u16>,
+        #[arg(
+            long,
+            value_name = "MIB",
+            help = "Memory the core may use before the OS kills it, through a cgroup v2 on Linux and a job object on Windows [default: memory-limit-mb from proxy-rs.toml]"
+        )]
+        memory_limit_mb: Option<u64>,
+        #[arg(
+            long,
+            value_name = "N",
+            allow_hyphen_values = true,
+            value_parser = clap::value_parser!(i32).range(-20..=19),
+            help = "Niceness of the core, up to 19 to yield the CPU to other work, negative values need root [default: nice from proxy-rs.toml]"
+        )]
+        nice: Option<i32>,
+        #[arg(
+            long,
+            value_name = "N",
+            help = "Open files the core may have, on Unix [default: open-files-limit from proxy-rs.toml]"
+        )]
+        open_files_limit: Option<u64>,
+        #[arg(
+            long,
+            value_name = "URL",
+            help = "URL requested through the proxy to verify it works after start [default: --delay-test-url]"
+        )]
+        check_url: Option<String>,
+        #[arg(long, help = "Skip the connectivity check after start")]
+        no_check: bool,
+        #[arg(
+            long,
+            help = "Switch the main selector group to the node with the lowest delay once started"
+        )]
+        auto_select: bool,
+        #[arg(
+            long,
+            help = "Add Auto (url-test) and Fallback groups over all nodes if the config lacks them"
+        )]
+        auto_groups: bool,
+        #[arg(
+            long,
+            value_name = "TAG",
+            help = "Use a specific Mihomo release, e.g. v1.18.10"
+        )]
+        core_version: Option<String>,
+        #[arg(
+            long,
+            value_name = "PATH",
+            conflicts_with = "core_version",
+            help = "Run this core binary instead of downloading one [default: core-path from proxy-rs.toml]"
+        )]
+        core_path: Option<PathBuf>,
+        #[arg(
+            long,
+            value_enum,
+            help = "WebUI to serve, saved to proxy-rs.toml [default: ui from proxy-rs.toml]"
+        )]
+        ui: Option<WebUi>,
+        #[arg(
+            long,
+            help = "Serve the controller and WebUI over HTTPS with a self-signed certificate, plain HTTP then only listens on 127.0.0.1"
+        )]
+        controller_tls: bool,
+        #[arg(
+            long,
+            conflicts_with_all = ["controller_port", "controller_tls", "container"],
+            help = "Serve the controller only on a Unix socket in the config dir, with no TCP listener or WebUI. Falls back to TCP on Windows"
+        )]
+        controller_unix: bool,
+        #[arg(
+            long,
+            help = "Print how long each startup phase took as JSON to stdout once started"
+        )]
+        trace_startup: bool,
+        #[arg(
+            long,
+            value_name = "PORT",
+            help = "Preferred mixed-port [default: 7890]"
+        )]
+        mixed_port: Option<u16>,
+        #[arg(
+            long,
+            value_name = "PORT",
+            help = "Preferred external controller port [default: 9090]"
+        )]
+        controller_port: Option<u16>,
+        #[arg(
+            long,
+            conflicts_with_all = ["mixed_port", "controller_port"],
+            help = "Pick random high ports for the mixed-port and the controller, for machines shared with other users"
+        )]
+        random_ports: bool,
+        #[arg(
+            long,
+            value_name = "PORT",
+            num_args = 0..=1,
+            default_missing_value = "7891",
+            help = "Also listen on a SOCKS5-only port, the next free one if it is taken [default: 7891]"
+        )]
+        socks_port: Option<u16>,
+        #[arg(
+            long,
+            value_name = "PORT",
+            num_args = 0..=1,
+            default_missing_value = "7892",
+            help = "Also listen on an HTTP-only port, the next free one if it is taken [default: 7892]"
+        )]
+        http_port: Option<u16>,
+        #[arg(
+            long,
+            value_name = "ADDR",
+            help = "Address the external controller and WebUI listen on, e.g. 0.0.0.0 for the LAN [default: 127.0.0.1]"
+        )]
+        listen: Option<IpAddr>,
+        #[arg(
+            long,
+            value_enum,
+            value_name = "MODE",
+            help = "Replace the subscription's DNS settings with a preset, kept for later starts"
+        )]
+        dns: Option<DnsMode>,
+        #[arg(
+            long = "nameserver",
+            value_name = "ADDR",
+            help = "Upstream DNS server for the preset, can be repeated [default: doh.pub and AliDNS]"
+        )]
+        nameservers: Vec<String>,
+        #[arg(
+            long,
+            value_name = "on|off",
+            value_parser = clap::builder::BoolishValueParser::new(),
+            help = "Turn IPv6 on or off in the config and its DNS, kept for later starts"
+        )]
+        ipv6: Option<bool>,
+        #[arg(
+            long,
+            value_name = "NAME",
+            help = "Send outbound traffic through this network interface, kept for later starts, `auto` to detect it again"
+        )]
+        interface: Option<String>,
+        #[arg(
+            long,
+            value_name = "REGEX",
+            help = "Only keep nodes whose name matches this regex, e.g. \"HK|SG\", kept for later starts, \"\" to keep all"
+        )]
+        include: Option<String>,
+        #[arg(
+            long,
+            value_name = "REGEX",
+            help = "Drop nodes whose name matches this regex, e.g. \"官网|流量|到期\", kept for later starts, \"\" to keep all"
+        )]
+        exclude: Option<String>,
+        #[arg(
+            long,
+            value_enum,
+            value_name = "PRESET",
+            help = "Send LAN and this region's sites DIRECT ahead of the subscription's rules, can be repeated, kept for later starts, `none` to remove"
+        )]
+        bypass: Vec<Bypass>,
+        #[arg(
+            long,
+            value_name = "URL",
+            help = "subconverter /sub endpoint used to convert non-Clash subscriptions [default: subconverter from proxy-rs.toml]"
+        )]
+        subconverter: Option<String>,
+    },
+    #[command(about = "Download the latest (or a specific) Mihomo release")]
+    Update {
+        #[arg(long, value_name = "TAG", help = "Release to install, e.g. v1.18.10")]
+        version: Option<String>,
+        #[arg(
+            long,
+            value_enum,
+            conflicts_with = "version",
+            help = "Release channel, alpha for Mihomo's Prerelease-Alpha builds [default: channel of the installed core]"
+        )]
+        channel: Option<Channel>,
+        #[arg(
+            long,
+            help = "Skip SHA256 verification of the downloaded Mihomo binary"
+        )]
+        no_verify: bool,
+    },
+    #[command(
+        about = "Collect versions, doctor results, the redacted config and logs for a bug report"
+    )]
+    Report {
+        #[arg(
+            long,
+            value_name = "FILE",
+            help = "Write a tar.gz instead of printing pastable Markdown"
+        )]
+        archive: Option<PathBuf>,
+    },
+    #[command(
+        about = "Report whether newer core, WebUI or geodata releases exist, without installing them"
+    )]
+    CheckUpdate,
+    #[command(about = "Show the versions of proxy-rs, the core, the WebUI and the geodata")]
+    Version,
+    #[command(about = "Update proxy-rs itself to its latest release")]
+    SelfUpdate {
+        #[arg(long, help = "Skip SHA256 verification of the downloaded binary")]
+        no_verify: bool,
+        #[arg(long, help = "Reinstall even if this version is the latest")]
+        force: bool,
+    },
+    #[command(about = "List or install core releases")]
+    Core {
+        #[command(subcommand)]
+        command: CoreCommands,
+    },
+    #[command(about = "Stop Mihomo and remove downloaded artifacts")]
+    Clean {
+        #[arg(
+            long,
+            help = "Remove the core, the WebUI, geodata, logs and the shared download cache"
+        )]
+        all: bool,
+        #[arg(
+            long,
+            help = "Remove geodata, Mihomo's cache, logs and the download cache shared by all data dirs"
+        )]
+        cache: bool,
+        #[arg(long, help = "Remove the WebUI")]
+        ui: bool,
+        #[arg(long, help = "Don't ask for confirmation, also needed with --yes")]
+        force: bool,
+    },
+    #[command(about = "Stop Mihomo and remove the whole data directory")]
+    Uninstall {
+        #[arg(long, help = "Don't ask for confirmation, also needed with --yes")]
+        force: bool,
+    },
+    #[command(about = "Manage a Mihomo started outside proxy-rs as this instance")]
+    Adopt {
+        #[arg(long, help = "Process to adopt, needed when more than one is found")]
+        pid: Option<u32>,
+    },
+    #[command(about = "Restore the Mihomo binary replaced by the last update")]
+    Rollback,
+    #[command(about = "Check config.yaml and reload it in the running Mihomo")]
+    Reload,
+    #[command(about = "Stop Mihomo, killing it if it doesn't exit in time")]
+    Stop {
+        #[arg(
+            value_name = "INSTANCE",
+            value_parser = parse_instance_name,
+            conflicts_with_all = ["instance", "all"],
+            help = "Named instance to stop, same as --instance"
+        )]
+        name: Option<String>,
+        #[arg(long, conflicts_with = "instance", help = "Stop all instances")]
+        all: bool,
+        #[arg(
+            long,
+            value_name = "SECS",
+            help = "Seconds to wait for Mihomo to exit before killing it [default: stop-timeout from proxy-rs.toml, or 5]"
+        )]
+        timeout: Option<u64>,
+    },
+    #[command(about = "Stop Mihomo gracefully and start it again on the same ports")]
+    Restart {
+        #[arg(
+            long,
+            value_name = "SECS",
+            help = "Seconds to wait for Mihomo to exit before killing it [default: stop-timeout from proxy-rs.toml, or 5]"
+        )]
+        timeout: Option<u64>,
+    },
+    #[command(about = "Test the latency of every node through the controller")]
+    Test {
+        #[arg(long, help = "Only test the nodes of this proxy group")]
+        group: Option<String>,
+        #[arg(long, help = "URL used for the delay test [default: --delay-test-url]")]
+        url: Option<String>,
+        #[arg(long, value_name = "FILE", help = "Write the results to a JSON file")]
+        json: Option<PathBuf>,
     },
-    #[command(about = "Stop Mihomo by killing the process")]
-    Stop,
-    #[command(about = "Tunnel localhost:<port> through a free service")]
+    #[command(about = "Measure the download speed of the nodes, one at a time")]
+    Speedtest {
+        #[arg(long, help = "Only test the nodes of this proxy group")]
+        group: Option<String>,
+        #[arg(long, default_value = DEFAULT_SPEEDTEST_URL, help = "Payload downloaded through each node")]
+        url: String,
+        #[arg(
+            long,
+            value_name = "SECS",
+            default_value_t = DEFAULT_SPEEDTEST_SECS,
+            help = "Longest time spent downloading through one node"
+        )]
+        duration: u64,
+        #[arg(
+            long,
+            value_name = "N",
+            help = "Only test the N nodes with the lowest delay"
+        )]
+        top: Option<usize>,
+    },
+    #[command(
+        about = "Benchmark every node's latency, jitter and failure rate against several targets"
+    )]
+    Bench {
+        #[arg(long, help = "Only test the nodes of this proxy group")]
+        group: Option<String>,
+        #[arg(
+            long,
+            value_name = "URL",
+            help = "Also test against this URL, can be repeated [default targets: --delay-test-url, GitHub]"
+        )]
+        url: Vec<String>,
+        #[arg(
+            long,
+            value_name = "N",
+            default_value_t = DEFAULT_BENCH_ROUNDS,
+            help = "Delay tests per node and target"
+        )]
+        rounds: u32,
+        #[arg(long, value_name = "MS", default_value_t = DEFAULT_DELAY_TIMEOUT_MS, help = "Timeout of each test")]
+        timeout: u64,
+        #[arg(long, value_enum, default_value_t = BenchSort::Latency, help = "Order of the report")]
+        sort: BenchSort,
+        #[arg(long, value_name = "FILE", help = "Write the results to a CSV file")]
+        csv: Option<PathBuf>,
+        #[arg(long, value_name = "FILE", help = "Write the results to a JSON file")]
+        json: Option<PathBuf>,
+    },
+    #[command(about = "Show live upload/download throughput")]
+    Traffic {
+        #[arg(long, help = "Print one JSON object per second instead of a live line")]
+        json: bool,
+    },
+    #[command(
+        about = "Block all traffic for a while whenever the average throughput goes over a limit, until Ctrl+C"
+    )]
+    Limit {
+        #[arg(
+            long,
+            value_name = "RATE",
+            value_parser = parse_rate,
+            help = "Upload limit, e.g. 5mbps or 1MB/s"
+        )]
+        up: Option<u64>,
+        #[arg(
+            long,
+            value_name = "RATE",
+            value_parser = parse_rate,
+            help = "Download limit, e.g. 20mbps or 2MB/s"
+        )]
+        down: Option<u64>,
+        #[arg(
+            long,
+            value_name = "DURATION",
+            default_value = "10s",
+            value_parser = parse_duration,
+            help = "Period the throughput is averaged over, e.g. 10s or 1m"
+        )]
+        window: Duration,
+        #[arg(
+            long,
+            value_name = "DURATION",
+            default_value = "30s",
+            value_parser = parse_duration,
+            help = "How long traffic is blocked once a limit is exceeded"
+        )]
+        cooldown: Duration,
+    },
+    #[command(about = "Show the core's log, or stream it from the controller with --remote")]
+    Logs {
+        #[arg(
+            long,
+            help = "Stream the log from the controller instead of reading mihomo.log"
+        )]
+        remote: bool,
+        #[arg(
+            long,
+            value_enum,
+            default_value_t = CoreLogLevel::Info,
+            help = "Only show lines at this level and above, `debug` adds the rule each connection matched"
+        )]
+        level: CoreLogLevel,
+        #[arg(
+            short = 'n',
+            long,
+            default_value_t = 50,
+            conflicts_with = "remote",
+            help = "Number of lines to show from the end of mihomo.log"
+        )]
+        lines: usize,
+        #[arg(long, requires = "remote", help = "Print one JSON object per line")]
+        json: bool,
+    },
+    #[command(about = "List active connections")]
+    Connections {
+        #[arg(long, help = "Refresh the list every second")]
+        watch: bool,
+        #[command(subcommand)]
+        command: Option<ConnectionsCommands>,
+    },
+    #[command(about = "Interactive terminal dashboard")]
+    Dashboard,
+    #[command(about = "Check the installation and print how to fix each problem")]
+    Doctor,
+    #[command(about = "Manage geosite/geoip databases")]
+    Geo {
+        #[command(subcommand)]
+        command: GeoCommands,
+    },
+    #[command(about = "Show the country and ASN GEOIP and IP-ASN rules see for an IP or a domain")]
+    Geoip {
+        #[arg(
+            value_name = "IP|DOMAIN",
+            help = "Domains are resolved with the system resolver"
+        )]
+        target: String,
+    },
+    #[command(about = "Manage the subscription last passed to start")]
+    Sub {
+        #[command(subcommand)]
+        command: SubCommands,
+    },
+    #[command(about = "Manage the WebUI served by the controller")]
+    Ui {
+        #[command(subcommand)]
+        command: UiCommands,
+    },
+    #[command(
+        about = "Import the profile of Clash Verge or Clash for Windows, with its subscription and enhancements"
+    )]
+    Import {
+        #[arg(value_enum)]
+        client: ImportClient,
+        #[arg(
+            value_name = "DIR",
+            help = "Directory of the client's profiles [default: where the client keeps them]"
+        )]
+        path: Option<PathBuf>,
+        #[arg(
+            long,
+            value_name = "NAME",
+            help = "Profile to import [default: the one the client uses]"
+        )]
+        profile: Option<String>,
+        #[arg(
+            long,
+            conflicts_with = "profile",
+            help = "List the profiles instead of importing one"
+        )]
+        list: bool,
+    },
+    #[command(about = "Save proxy-rs.toml and the configs of all instances to a tar.gz")]
+    Backup {
+        #[arg(value_name = "FILE", help = "Archive to write, e.g. proxy-rs.tar.gz")]
+        file: PathBuf,
+    },
+    #[command(about = "Restore the configs from a tar.gz made by backup")]
+    Restore {
+        #[arg(value_name = "FILE", help = "Archive written by backup")]
+        file: PathBuf,
+        #[arg(long, help = "Don't ask for confirmation, also needed with --yes")]
+        force: bool,
+    },
+    #[command(about = "Carry the core, WebUI and geodata to machines without GitHub access")]
+    Bundle {
+        #[command(subcommand)]
+        command: BundleCommands,
+    },
+    #[command(
+        about = "Run the jobs in [schedule] of proxy-rs.toml, e.g. subscription refreshes, until Ctrl+C"
+    )]
+    Schedule {
+        #[arg(
+            long,
+            help = "Print the scheduled jobs and when they run next, then exit"
+        )]
+        list: bool,
+    },
+    #[command(about = "Sync the configs of all instances with the git remote in sync-remote")]
+    Sync {
+        #[command(subcommand)]
+        command: SyncCommands,
+    },
+    #[command(about = "Inspect the config")]
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommands,
+    },
+    #[command(about = "Manage proxy-providers and rule-providers")]
+    Providers {
+        #[command(subcommand)]
+        command: ProvidersCommands,
+    },
+    #[command(about = "Manage GitHub mirrors used for downloads")]
+    Mirrors {
+        #[command(subcommand)]
+        command: MirrorsCommands,
+    },
+    #[command(about = "Pick the node of a proxy group with a fuzzy search")]
+    Select {
+        #[arg(value_name = "GROUP", help = "Proxy group to switch [default: ask]")]
+        group: Option<String>,
+    },
+    #[command(about = "Show or switch the proxy mode of the running Mihomo")]
+    Mode {
+        #[arg(
+            value_enum,
+            help = "Mode to switch to, the current one is shown if omitted"
+        )]
+        mode: Option<Mode>,
+    },
+    #[command(about = "Send traffic directly until `resume`, keeping the mode and selected nodes")]
+    Pause,
+    #[command(about = "Restore the mode and selected nodes saved by `pause`")]
+    Resume,
+    #[command(
+        about = "Manage custom rules, kept across subscription refreshes",
+        visible_alias = "rules"
+    )]
+    Rule {
+        #[command(subcommand)]
+        command: RuleCommands,
+    },
+    #[command(
+        about = "Chain two nodes in a relay group, e.g. a domestic transit node in front of an overseas exit"
+    )]
+    Chain {
+        #[arg(
+            value_name = "ENTRY",
+            required_unless_present = "remove",
+            help = "Node traffic goes through first"
+        )]
+        entry: Option<String>,
+        #[arg(
+            value_name = "EXIT",
+            required_unless_present = "remove",
+            help = "Node traffic leaves through"
+        )]
+        exit: Option<String>,
+        #[arg(
+            long,
+            conflicts_with = "remove",
+            help = "Offer the relay group first in the main selector and switch to it"
+        )]
+        select: bool,
+        #[arg(long, conflicts_with_all = ["entry", "exit"], help = "Remove the relay group")]
+        remove: bool,
+    },
+    #[command(about = "Add or remove proxy groups, kept across subscription refreshes")]
+    Group {
+        #[command(subcommand)]
+        command: GroupCommands,
+    },
+    #[command(about = "Share the proxy with other devices on the LAN")]
+    Lan {
+        #[command(subcommand)]
+        command: LanCommands,
+    },
+    #[command(
+        about = "Show which rule and proxy chain the running core picks for a domain, IP or URL"
+    )]
+    Which {
+        #[arg(
+            value_name = "TARGET",
+            help = "Domain or IP with an optional port (443 by default), or a URL"
+        )]
+        target: String,
+        #[arg(
+            long,
+            help = "Open a connection through the core instead of evaluating the rules, for rules on processes or sources. The core connects to the destination"
+        )]
+        dial: bool,
+    },
+    #[command(
+        about = "Show node delay and traffic trends from the samples of the sample-stats job"
+    )]
+    Stats {
+        #[arg(
+            long,
+            value_name = "NODE",
+            help = "Show the delays of one node over time"
+        )]
+        node: Option<String>,
+        #[arg(
+            long,
+            value_name = "DURATION",
+            default_value = "7d",
+            value_parser = parse_duration,
+            help = "Period to show, e.g. 12h, 7d or 4w"
+        )]
+        since: Duration,
+        #[arg(long, help = "Take a sample now instead of showing the stats")]
+        sample: bool,
+    },
+    #[command(about = "Block ads with the anti-AD rule list, kept across subscription refreshes")]
+    Adblock {
+        #[command(subcommand)]
+        command: AdblockCommands,
+    },
+    #[command(about = "Show QR codes of the LAN proxy and Web UI for phones and tablets")]
+    Share,
+    #[command(about = "Set the OS-level proxy to the running Mihomo")]
+    Sysproxy {
+        #[command(subcommand)]
+        command: SysproxyCommands,
+    },
+    #[command(
+        about = "Manage the hosts the env scripts and system proxy send directly instead of through Mihomo"
+    )]
+    NoProxy {
+        #[command(subcommand)]
+        command: NoProxyCommands,
+    },
+    #[command(about = "Print the proxy environment variables, e.g. eval \"$(proxy env)\"")]
+    Env {
+        #[arg(long, value_enum, help = "Shell syntax to print [default: detected]")]
+        shell: Option<Shell>,
+    },
+    #[command(
+        about = "Run a command with the proxy environment variables set, e.g. proxy exec -- curl ipinfo.io"
+    )]
+    Exec {
+        #[arg(
+            value_name = "COMMAND",
+            required = true,
+            trailing_var_arg = true,
+            allow_hyphen_values = true,
+            help = "Command and its arguments"
+        )]
+        command: Vec<String>,
+    },
+    #[command(
+        about = "Tunnel the WebUI, or localhost:<port>, through a free service",
+        args_conflicts_with_subcommands = true
+    )]
     Tunnel {
-        #[arg(value_name = "PORT", help = "Port to tunnel through a free service")]
-        port: u16,
+        #[arg(
+            value_name = "PORT",
+            help = "Port to tunnel through a free service [default: the running controller, which serves the WebUI]"
+        )]
+        port: Option<u16>,
+        #[arg(
+            long,
+            value_enum,
+            default_value = "ssh",
+            help = "Tunnel service or program to use, a single ssh service is tried before the others"
+        )]
+        service: TunnelBackend,
+        #[arg(
+            long,
+            conflicts_with = "service",
+            help = "Serve the WebUI inside your tailnet only, same as --service tailscale"
+        )]
+        tailscale: bool,
+        #[arg(
+            long,
+            conflicts_with_all = ["port", "tailscale"],
+            help = "Tunnel the proxy itself with a generated password instead of the WebUI, through pinggy by default"
+        )]
+        proxy: bool,
+        #[arg(
+            long,
+            help = "Only try --service instead of falling back to the other ssh services"
+        )]
+        no_fallback: bool,
+        #[arg(
+            long,
+            value_name = "N",
+            default_value_t = 0,
+            conflicts_with = "detach",
+            help = "Reconnect up to N times when the tunnel drops"
+        )]
+        retry: u32,
+        #[arg(long, help = "Print the public URL as a JSON line on stdout")]
+        json: bool,
+        #[arg(long, help = "Keep the tunnel running in the background")]
+        detach: bool,
+        #[command(subcommand)]
+        command: Option<TunnelCommands>,
     },
+    /// Any other command runs `proxy-rs-<command>` from PATH
+    #[command(external_subcommand)]
+    External(Vec<OsString>),
+}
+
+#[derive(Subcommand, Debug)]
+pub enum InstanceCommands {
+    #[command(about = "Create an instance with its own config, ports and scripts")]
+    Create {
+        #[arg(value_name = "NAME", value_parser = parse_instance_name)]
+        name: String,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum TunnelCommands {
+    #[command(about = "Show the tunnel running in the background")]
+    Status {
+        #[arg(long, help = "Print the public URL as a JSON line on stdout")]
+        json: bool,
+    },
+    #[command(about = "Stop the tunnel running in the background")]
+    Stop,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ConnectionsCommands {
+    #[command(about = "Close a connection by id (or unique id prefix)")]
+    Kill {
+        #[arg(value_name = "ID", required_unless_present = "all")]
+        id: Option<String>,
+        #[arg(long, conflicts_with = "id", help = "Close all connections")]
+        all: bool,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum CoreCommands {
+    #[command(about = "List the recent releases with their dates")]
+    List,
+    #[command(about = "Install a release, picked from the list without TAG")]
+    Install {
+        #[arg(value_name = "TAG", help = "Release to install, e.g. v1.18.10")]
+        tag: Option<String>,
+        #[arg(
+            long,
+            help = "Skip SHA256 verification of the downloaded Mihomo binary"
+        )]
+        no_verify: bool,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum GeoCommands {
+    #[command(about = "Re-download the geodata files used by the config")]
+    Update {
+        #[arg(long, help = "Download even if the files were updated recently")]
+        force: bool,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum BundleCommands {
+    #[command(about = "Download the core, WebUI and geodata into a tar.gz for another machine")]
+    Create {
+        #[arg(
+            value_name = "FILE",
+            help = "Archive to write, e.g. proxy-rs-bundle.tar.gz"
+        )]
+        file: PathBuf,
+        #[arg(
+            long,
+            value_parser = OSES,
+            help = "OS of the target machine, pair with --core-arch for its architecture [default: this OS]"
+        )]
+        os: Option<String>,
+        #[arg(
+            long,
+            value_name = "TAG",
+            help = "Bundle a specific core release, e.g. v1.18.10 [default: latest]"
+        )]
+        core_version: Option<String>,
+        #[arg(long, help = "Skip SHA256 verification of the downloaded core")]
+        no_verify: bool,
+    },
+    #[command(about = "Install the core, WebUI and geodata from a bundle, without downloading")]
+    Install {
+        #[arg(value_name = "FILE", help = "Archive written by bundle create")]
+        file: PathBuf,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum SubCommands {
+    #[command(about = "Download the subscription again and reload the running instance")]
+    Refresh,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum UiCommands {
+    #[command(about = "Download the latest WebUI and replace the old one")]
+    Update,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum SyncCommands {
+    #[command(about = "Commit the local configs, merge the remote ones and push")]
+    Push,
+    #[command(about = "Merge the remote configs into the local ones")]
+    Pull,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ConfigCommands {
+    #[command(about = "Print the effective config, e.g. for a bug report")]
+    Export {
+        #[arg(long, help = "Mask secrets, server addresses, uuids and passwords")]
+        redact: bool,
+    },
+    #[command(about = "Open the config in $EDITOR, check it and offer to reload the core")]
+    Edit,
+    #[command(
+        about = "Look for unknown keys, wrong types and broken references, then run the core's own check"
+    )]
+    Check,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ProvidersCommands {
+    #[command(about = "Download the providers again and report each result")]
+    Update {
+        #[arg(
+            value_enum,
+            help = "Only update providers of this kind [default: both]"
+        )]
+        kind: Option<ProviderKind>,
+        #[arg(help = "Only update the provider with this name")]
+        name: Option<String>,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum MirrorsCommands {
+    #[command(about = "Measure the latency of each GitHub mirror")]
+    Test,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum SysproxyCommands {
+    #[command(about = "Point the system proxy at the Mihomo mixed-port")]
+    On,
+    #[command(about = "Restore the previous system proxy settings")]
+    Off,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum NoProxyCommands {
+    #[command(about = "Bypass the proxy for hosts, domains or CIDRs")]
+    Add {
+        #[arg(
+            value_name = "HOST",
+            required = true,
+            value_parser = parse_no_proxy_host,
+            help = "e.g. intranet, *.corp.example or 100.64.0.0/10"
+        )]
+        hosts: Vec<String>,
+    },
+    #[command(about = "Send hosts through the proxy again")]
+    Remove {
+        #[arg(value_name = "HOST", required = true)]
+        hosts: Vec<String>,
+    },
+    #[command(about = "List the bypassed hosts")]
+    List,
+    #[command(
+        about = "Go back to the default list: localhost, loopback, private networks and *.local"
+    )]
+    Reset,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum RuleCommands {
+    #[command(about = "Add a rule in front of the subscription's rules")]
+    Add {
+        #[arg(value_name = "RULE", help = "e.g. DOMAIN-SUFFIX,example.com,DIRECT")]
+        rule: String,
+    },
+    #[command(about = "Remove a rule")]
+    Remove {
+        #[arg(value_name = "RULE")]
+        rule: String,
+    },
+    #[command(about = "List the custom rules")]
+    List {
+        #[arg(long, help = "List every rule in config.yaml")]
+        all: bool,
+    },
+    #[command(
+        about = "Show the rule and group domains and IPs match, evaluated locally even when the core is stopped"
+    )]
+    Try {
+        #[arg(
+            value_name = "TARGET",
+            help = "Domain or IP with an optional port (443 by default), or a URL, asked for interactively if none is given"
+        )]
+        targets: Vec<String>,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum LanCommands {
+    #[command(about = "Allow LAN connections to the mixed-port")]
+    On,
+    #[command(about = "Only accept connections from this machine")]
+    Off,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum GroupCommands {
+    #[command(
+        about = "Add a group of the nodes whose name matches a regex, or replace the group of that name"
+    )]
+    Add {
+        #[arg(value_name = "NAME", help = "Name of the group")]
+        name: String,
+        #[arg(long = "type", value_enum, default_value_t = GroupType::Select)]
+        kind: GroupType,
+        #[arg(
+            long,
+            value_name = "REGEX",
+            help = "Nodes whose name matches, e.g. \"HK|SG\" or \"(?i)japan\""
+        )]
+        include: String,
+        #[arg(
+            long,
+            value_name = "REGEX",
+            help = "Leave out the nodes whose name matches"
+        )]
+        exclude: Option<String>,
+        #[arg(
+            long,
+            help = "Offer the group first in the main selector and switch to it"
+        )]
+        select: bool,
+    },
+    #[command(about = "Remove a group, rules that used it go through the main selector")]
+    Remove {
+        #[arg(value_name = "NAME")]
+        name: String,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum AdblockCommands {
+    #[command(about = "Download the anti-AD list and reject the domains on it")]
+    On,
+    #[command(about = "Remove the anti-AD list and its rule")]
+    Off,
+}
+
+fn parse_instance_name(name: &str) -> Result<String, String> {
+    if !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        Ok(name.to_string())
+    } else {
+        Err("instance names may only contain letters, digits, '-' and '_'".to_string())
+    }
+}
+
+/// A host name, a domain suffix like `*.corp.example` or `.corp.example`, an IP
+/// or a CIDR.
+fn parse_no_proxy_host(host: &str) -> Result<String, String> {
+    if let Some((address, prefix)) = host.split_once('/') {
+        let max = match address.parse::<IpAddr>() {
+            Ok(IpAddr::V4(_)) => 32,
+            Ok(IpAddr::V6(_)) => 128,
+            Err(_) => return Err(format!("`{address}` is not an IP address")),
+        };
+        return match prefix.parse::<u8>() {
+            Ok(prefix) if prefix <= max => Ok(host.to_string()),
+            _ => Err(format!("invalid prefix length in `{host}`")),
+        };
+    }
+    if host.parse::<IpAddr>().is_ok() {
+        return Ok(host.to_string());
+    }
+    let name = host
+        .strip_prefix("*.")
+        .or_else(|| host.strip_prefix('.'))
+        .unwrap_or(host);
+    if !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.')
+    {
+        Ok(host.to_string())
+    } else {
+        Err("expected a host name, *.domain, an IP or a CIDR like 10.0.0.0/8".to_string())
+    }
+}
+
+/// Parses durations like `30m`, `12h`, `7d` and `4w`.
+fn parse_duration(value: &str) -> Result<Duration, String> {
+    let unit = match value.chars().last() {
+        Some('s') => 1,
+        Some('m') => 60,
+        Some('h') => 60 * 60,
+        Some('d') => 24 * 60 * 60,
+        Some('w') => 7 * 24 * 60 * 60,
+        _ => return Err("expected a number followed by s, m, h, d or w, e.g. 7d".to_string()),
+    };
+    let count: u64 = value[..value.len() - 1]
+        .parse()
+        .map_err(|_| format!("invalid number in `{value}`"))?;
+    count
+        .checked_mul(unit)
+        .map(Duration::from_secs)
+        .ok_or_else(|| format!("`{value}` is too long"))
+}
+
+/// Bytes per second from a rate in bits like `5mbps`, or in bytes like `2MB/s`.
+fn parse_rate(value: &str) -> Result<u64, String> {
+    let lower = value.trim().to_lowercase();
+    let split = lower
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(lower.len());
+    let (number, unit) = lower.split_at(split);
+    let number: f64 = number
+        .parse()
+        .map_err(|_| format!("invalid number in `{value}`"))?;
+    let (prefix, bits) = if let Some(prefix) = unit.trim().strip_suffix("bps") {
+        (prefix, true)
+    } else if let Some(prefix) = unit.trim().strip_suffix("b/s") {
+        (prefix, false)
+    } else {
+        return Err(format!(
+            "expected a rate like 5mbps or 2MB/s, got `{value}`"
+        ));
+    };
+    let scale = match (prefix, bits) {
+        ("", _) => 1.0,
+        ("k", true) => 1e3,
+        ("m", true) => 1e6,
+        ("g", true) => 1e9,
+        ("k", false) => 1024.0,
+        ("m", false) => 1024.0 * 1024.0,
+        ("g", false) => 1024.0 * 1024.0 * 1024.0,
+        _ => return Err(format!("unknown unit in `{value}`")),
+    };
+    let bytes = number * scale / if bits { 8.0 } else { 1.0 };
+    Ok(bytes as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_durations() {
+        assert_eq!(parse_duration("30s"), Ok(Duration::from_secs(30)));
+        assert_eq!(parse_duration("30m"), Ok(Duration::from_secs(30 * 60)));
+        assert_eq!(parse_duration("12h"), Ok(Duration::from_secs(12 * 60 * 60)));
+        assert_eq!(
+            parse_duration("7d"),
+            Ok(Duration::from_secs(7 * 24 * 60 * 60))
+        );
+        assert_eq!(
+            parse_duration("4w"),
+            Ok(Duration::from_secs(4 * 7 * 24 * 60 * 60))
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_durations() {
+        assert!(parse_duration("").is_err());
+        assert!(parse_duration("7").is_err());
+        assert!(parse_duration("d").is_err());
+        assert!(parse_duration("-1d").is_err());
+        assert!(parse_duration("1.5h").is_err());
+        assert!(parse_duration("7日").is_err());
+        assert!(parse_duration("99999999999999999w").is_err());
+    }
+
+    #[test]
+    fn parses_rates() {
+        assert_eq!(parse_rate("5mbps"), Ok(625_000));
+        assert_eq!(parse_rate("8kbps"), Ok(1_000));
+        assert_eq!(parse_rate("2MB/s"), Ok(2 * 1024 * 1024));
+        assert_eq!(parse_rate("1.5 kb/s"), Ok(1536));
+        assert_eq!(parse_rate("800bps"), Ok(100));
+        assert_eq!(parse_rate("1Gbps"), Ok(125_000_000));
+    }
+
+    #[test]
+    fn rejects_invalid_rates() {
+        assert!(parse_rate("").is_err());
+        assert!(parse_rate("5").is_err());
+        assert!(parse_rate("mbps").is_err());
+        assert!(parse_rate("5tbps").is_err());
+        assert!(parse_rate("5 mbit").is_err());
+    }
 }
@@ -1,84 +1,607 @@
-use crate::utils::ask_for_confirmation;
-use anyhow::Result;
+use crate::backend::{CoreBackend, CoreKind};
+use crate::controller::DEFAULT_DELAY_TEST_URL;
+use crate::errors::Failure;
+use crate::sing_box;
+use crate::subscription::{
+    subscription_name, DownloadFailed, Downloaded, SubscriptionInfo, Validators,
+};
+use crate::utils::{ask_for_confirmation, open_in_editor};
+use anyhow::{anyhow, Context, Result};
 use log::*;
-use reqwest::blocking::Client;
+use reqwest::header::{ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED};
+use reqwest::{Client, StatusCode};
 use serde_yaml::Value;
-use std::fs::{self, File};
-use std::io::{self, Read, Write};
-use std::path::Path;
+use std::fs;
+use std::io::{self, Read};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::path::{Path, PathBuf};
 
-const MIHOMO_USER_AGENT: &str = "mihomo.proxy.sh/v1.0 (clash.meta)";
-
-pub fn handle_subscription_config(
+/// Downloads the subscription, merging several into one config, or makes
+/// sure there is a valid config for `core`. Subscriptions in other formats are
+/// converted with the `subconverter` endpoint.
+/// Returns the quota if the provider reported one.
+pub async fn handle_subscription_config(
     client: &Client,
-    subscription_url: Option<&str>,
+    core: &dyn CoreBackend,
+    subscription_urls: &[String],
+    subconverter: Option<&str>,
     config_path: &Path,
-) -> Result<()> {
-    if let Some(url) = subscription_url {
-        download_subscription(client, url, config_path)?;
-    } else if !is_config_valid(config_path) {
+) -> Result<Option<Downloaded>> {
+    match subscription_urls {
+        [] => {}
+        [url] => return download_subscription(client, core, url, subconverter, config_path).await,
+        urls => {
+            return merge_subscriptions(client, core, urls, subconverter, config_path)
+                .await
+                .map(Some)
+        }
+    }
+    if !is_config_valid(core, config_path) {
+        // Nobody is there to paste a config with --yes
         if ask_for_confirmation(
             "No valid config file found. Do you want to input config content manually?",
+            false,
         ) {
-            if !read_config_from_stdin(config_path) {
-                warn!("No valid content input, keeping existing config file unchanged");
+            let content = match edit_config_content(core, config_path, config_template(core.kind()))
+            {
+                Ok(content) => content,
+                Err(e) => {
+                    warn!("{e}, reading the config from stdin instead");
+                    read_config_from_stdin()
+                }
+            };
+            match content {
+                Some(content) => {
+                    fs::write(config_path, content)?;
+                    info!("Config saved to {}", config_path.display());
+                }
+                None => warn!("No valid content input, keeping existing config file unchanged"),
             }
         } else {
-            warn!( "Skipping config input. You may need to put your subscription file at proxy-data/config/config.yaml and restart Mihomo.");
+            warn!(
+                "Skipping config input. You may need to put your subscription file at {} and restart {}.",
+                config_path.display(),
+                core.name()
+            );
         }
     } else {
         info!("Valid config file already exists");
     }
-    Ok(())
+    Ok(None)
+}
+
+/// A subscription response, `content` is `None` if it was not modified.
+struct Fetched {
+    content: Option<String>,
+    info: Option<SubscriptionInfo>,
+    validators: Validators,
+}
+
+/// Downloads one subscription, converting it if needed. With `cached`
+/// validators the provider may answer that it did not change.
+async fn fetch_subscription(
+    client: &Client,
+    core: &dyn CoreBackend,
+    url: &str,
+    subconverter: Option<&str>,
+    cached: Option<&Validators>,
+) -> Result<Fetched> {
+    let mut request = client.get(url).header("User-Agent", core.user_agent());
+    if let Some(cached) = cached {
+        if let Some(etag) = &cached.etag {
+            request = request.header(IF_NONE_MATCH, etag);
+        }
+        if let Some(last_modified) = &cached.last_modified {
+            request = request.header(IF_MODIFIED_SINCE, last_modified);
+        }
+    }
+    let response = request.send().await?;
+    let status = response.status();
+    match status {
+        StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN | StatusCode::NOT_FOUND => {
+            return Err(Failure::SubscriptionRefused { status }.into())
+        }
+        StatusCode::TOO_MANY_REQUESTS => {
+            return Err(Failure::SubscriptionRateLimited { status }.into())
+        }
+        _ => {}
+    }
+    let response = response.error_for_status()?;
+
+    let header = |name| {
+        response
+            .headers()
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .map(String::from)
+    };
+    let info = header("subscription-userinfo")
+        .as_deref()
+        .and_then(SubscriptionInfo::parse);
+    let validators = Validators {
+        url: url.to_string(),
+        etag: header(ETAG.as_str()),
+        last_modified: header(LAST_MODIFIED.as_str()),
+    };
+    if response.status() == StatusCode::NOT_MODIFIED {
+        return Ok(Fetched {
+            content: None,
+            info,
+            validators,
+        });
+    }
+    let mut content = response.text().await?;
+    if core.kind() == CoreKind::Mihomo && sing_box::is_sing_box_config(&content) {
+        info!("The subscription is a sing-box config, converting it for Mihomo");
+        content = sing_box::to_mihomo_config(&content)
+            .context("Failed to convert the sing-box subscription")?;
+    }
+    if !core.is_config(&content) {
+        match subconverter {
+            Some(endpoint) => {
+                content = convert_subscription(client, core, endpoint, url).await?;
+                if !core.is_config(&content) {
+                    return Err(anyhow!(
+                        "{endpoint} did not return a {} config",
+                        core.config_format()
+                    ));
+                }
+            }
+            None => {
+                return Err(anyhow!(
+                    "The subscription is not a {} config, set `subconverter` in proxy-rs.toml \
+                     or use --subconverter to convert it",
+                    core.config_format()
+                ))
+            }
+        }
+    }
+    Ok(Fetched {
+        content: Some(content),
+        info,
+        validators,
+    })
 }
 
-fn download_subscription(client: &Client, url: &str, config_path: &Path) -> Result<()> {
+async fn download_subscription(
+    client: &Client,
+    core: &dyn CoreBackend,
+    url: &str,
+    subconverter: Option<&str>,
+    config_path: &Path,
+) -> Result<Option<Downloaded>> {
     info!("Downloading subscription from URL...");
     if !url.starts_with("http://") && !url.starts_with("https://") {
         warn!("URL does not start with http:// or https:// prefix. Skipping download.");
-        return Ok(());
+        return Ok(None);
     }
 
-    let response = client
-        .get(url)
-        .header("User-Agent", MIHOMO_USER_AGENT)
-        .send()?
-        .error_for_status()?;
+    // Only ask for changes if the subscription as downloaded is still there,
+    // config.yaml is derived from it again with the current overrides
+    let validators_path = Validators::path(config_path);
+    let pristine = pristine_path(config_path);
+    let cached = Validators::load(&validators_path)
+        .filter(|cached| cached.url == url && is_config_valid(core, &pristine));
+    let fetched = fetch_subscription(client, core, url, subconverter, cached.as_ref()).await?;
+    let Some(content) = fetched.content else {
+        info!(
+            "Subscription is unchanged, rebuilding {} from {}",
+            config_path.display(),
+            pristine.display()
+        );
+        rebuild_from_pristine(config_path)?;
+        return Ok(Some(Downloaded {
+            info: fetched.info,
+            unchanged: true,
+        }));
+    };
+    replace_config(core, config_path, &content)?;
+    fs::write(&pristine, &content)?;
+    fetched.validators.save(&validators_path)?;
+    info!("Downloaded to {}", config_path.display());
+    Ok(Some(Downloaded {
+        info: fetched.info,
+        unchanged: false,
+    }))
+}
+
+/// Downloads every subscription and merges them into one config, see
+/// [`merge_configs`]. Returns the quota of the first one that reports it.
+async fn merge_subscriptions(
+    client: &Client,
+    core: &dyn CoreBackend,
+    urls: &[String],
+    subconverter: Option<&str>,
+    config_path: &Path,
+) -> Result<Downloaded> {
+    if core.kind() != CoreKind::Mihomo {
+        return Err(anyhow!(
+            "Merging subscriptions is only supported with the Mihomo core"
+        ));
+    }
+    let mut configs = Vec::new();
+    let mut quota = None;
+    for url in urls {
+        if !url.starts_with("http://") && !url.starts_with("https://") {
+            return Err(anyhow!("{url} is not an http:// or https:// URL"));
+        }
+        info!(
+            "Downloading subscription from {}...",
+            subscription_name(url)
+        );
+        let fetched = fetch_subscription(client, core, url, subconverter, None)
+            .await
+            .with_context(|| DownloadFailed { url: url.clone() })?;
+        if let Some(info) = fetched.info {
+            info.log();
+            quota.get_or_insert(info);
+        }
+        let content = fetched.content.unwrap_or_default();
+        configs.push((
+            subscription_name(url),
+            serde_yaml::from_str::<Value>(&content)?,
+        ));
+    }
 
-    let content = response.text()?;
+    let merged = serde_yaml::to_string(&merge_configs(configs)?)?;
+    replace_config(core, config_path, &merged)?;
+    fs::write(pristine_path(config_path), &merged)?;
+    // The validators of a single subscription don't describe the merged config
+    Validators::default().save(&Validators::path(config_path))?;
+    info!(
+        "Merged {} subscriptions into {}",
+        urls.len(),
+        config_path.display()
+    );
+    Ok(Downloaded {
+        info: quota,
+        unchanged: false,
+    })
+}
+
+/// Writes a downloaded subscription to `config_path` once it parses and has
+/// nodes, so an error page or a truncated download keeps the working config.
+/// The previous config is kept at [`backup_path`].
+pub fn replace_config(core: &dyn CoreBackend, config_path: &Path, content: &str) -> Result<()> {
+    check_config_content(core, content)
+        .and_then(|()| check_has_nodes(core, content))
+        .map_err(|e| {
+            anyhow!(
+                "The subscription is not usable: {e:#}, keeping {}",
+                config_path.display()
+            )
+        })?;
+
+    let backup = backup_path(config_path);
+    if is_config_valid(core, config_path) {
+        fs::copy(config_path, &backup)?;
+    } else if backup.exists() {
+        // Older than the broken config, it's not what the user last had
+        fs::remove_file(&backup)?;
+    }
     fs::write(config_path, content)?;
-    info!("Downloaded to {}", config_path.display());
     Ok(())
 }
 
-fn is_config_valid(config_path: &Path) -> bool {
+/// Fails if the config has no node nor a provider to get them from.
+fn check_has_nodes(core: &dyn CoreBackend, content: &str) -> Result<()> {
+    let has_nodes = match core.kind() {
+        CoreKind::Mihomo => {
+            let yaml: Value = serde_yaml::from_str(content)?;
+            let not_empty = |key| {
+                yaml.get(key).is_some_and(|value| match value {
+                    Value::Sequence(list) => !list.is_empty(),
+                    Value::Mapping(map) => !map.is_empty(),
+                    _ => false,
+                })
+            };
+            not_empty("proxies") || not_empty("proxy-providers")
+        }
+        CoreKind::SingBox => {
+            let json: serde_json::Value = serde_json::from_str(content)?;
+            json.get("outbounds")
+                .and_then(|outbounds| outbounds.as_array())
+                .is_some_and(|outbounds| !outbounds.is_empty())
+        }
+    };
+    if !has_nodes {
+        return Err(anyhow!("it has no nodes"));
+    }
+    Ok(())
+}
+
+/// Puts the config kept by the last download back. Returns whether there was one.
+pub fn restore_backup(config_path: &Path) -> Result<bool> {
+    let backup = backup_path(config_path);
+    if !backup.exists() {
+        return Ok(false);
+    }
+    fs::rename(&backup, config_path)?;
+    // The validators and the pristine copy describe the rejected download,
+    // fetch it in full next time
+    Validators::default().save(&Validators::path(config_path))?;
+    let pristine = pristine_path(config_path);
+    if pristine.exists() {
+        fs::remove_file(pristine)?;
+    }
+    Ok(true)
+}
+
+/// Merges `configs` into the first one, which keeps its settings, groups and
+/// rules. The nodes of the others are added, renamed where their names are
+/// taken, and each subscription gets a `select` group named after it that the
+/// first config's main selector offers.
+fn merge_configs(configs: Vec<(String, Value)>) -> Result<Value> {
+    let mut configs = configs.into_iter();
+    let (first_name, mut merged) = configs
+        .next()
+        .ok_or_else(|| anyhow!("No config to merge"))?;
+    let map = merged
+        .as_mapping_mut()
+        .ok_or_else(|| anyhow!("Invalid YAML"))?;
+    let mut proxies = match map.remove("proxies") {
+        Some(Value::Sequence(proxies)) => proxies,
+        _ => Vec::new(),
+    };
+    let mut names: Vec<String> = proxies.iter().filter_map(proxy_name).collect();
+    let mut subscriptions = vec![(first_name, names.clone())];
+
+    for (name, config) in configs {
+        let mut nodes = Vec::new();
+        if let Some(Value::Sequence(others)) = config.get("proxies") {
+            for proxy in others {
+                let (Some(node), Value::Mapping(mut proxy)) = (proxy_name(proxy), proxy.clone())
+                else {
+                    continue;
+                };
+                let unique = unique_name(&node, &names);
+                proxy.insert("name".into(), unique.as_str().into());
+                names.push(unique.clone());
+                nodes.push(unique);
+                proxies.push(Value::Mapping(proxy));
+            }
+        }
+        subscriptions.push((name, nodes));
+    }
+    map.insert("proxies".into(), Value::Sequence(proxies));
+
+    let groups = map
+        .entry("proxy-groups".into())
+        .or_insert_with(|| Value::Sequence(Vec::new()));
+    let Value::Sequence(groups) = groups else {
+        return Err(anyhow!("proxy-groups is not a list"));
+    };
+    // Group names share one namespace with node and existing group names
+    names.extend(groups.iter().filter_map(proxy_name));
+    let mut group_names = Vec::new();
+    for (name, _) in &subscriptions {
+        let unique = unique_name(name, &names);
+        names.push(unique.clone());
+        group_names.push(unique);
+    }
+    // Offer every subscription in the main selector, the first `select` group
+    if let Some(Value::Sequence(main)) = groups
+        .iter_mut()
+        .find(|group| group.get("type").and_then(Value::as_str) == Some("select"))
+        .and_then(|group| group.get_mut("proxies"))
+    {
+        for (index, name) in group_names.iter().enumerate() {
+            main.insert(index, name.as_str().into());
+        }
+    }
+    for (name, (_, nodes)) in group_names.iter().zip(subscriptions) {
+        let mut group = serde_yaml::Mapping::new();
+        group.insert("name".into(), name.as_str().into());
+        group.insert("type".into(), "select".into());
+        group.insert(
+            "proxies".into(),
+            Value::Sequence(nodes.into_iter().map(Value::from).collect()),
+        );
+        groups.push(Value::Mapping(group));
+    }
+    Ok(merged)
+}
+
+fn proxy_name(proxy: &Value) -> Option<String> {
+    proxy.get("name")?.as_str().map(String::from)
+}
+
+/// `name`, or `name 2`, `name 3`... if it is in `taken`.
+fn unique_name(name: &str, taken: &[String]) -> String {
+    if !taken.iter().any(|t| t == name) {
+        return name.to_string();
+    }
+    (2..)
+        .map(|n| format!("{name} {n}"))
+        .find(|candidate| !taken.contains(candidate))
+        .expect("some suffix is free")
+}
+
+/// Asks a [subconverter](https://github.com/tindy2013/subconverter) backend to
+/// turn a subscription in another format (Surge, Quantumult X, share links...)
+/// into a config for `core`. `endpoint` is its `/sub` URL.
+async fn convert_subscription(
+    client: &Client,
+    core: &dyn CoreBackend,
+    endpoint: &str,
+    url: &str,
+) -> Result<String> {
+    info!("Converting the subscription with {endpoint}...");
+    let content = client
+        .get(endpoint)
+        .query(&[("target", core.subconverter_target()), ("url", url)])
+        .header("User-Agent", core.user_agent())
+        .send()
+        .await?
+        .error_for_status()
+        .context("Failed to convert the subscription")?
+        .text()
+        .await?;
+    Ok(content)
+}
+
+fn is_config_valid(core: &dyn CoreBackend, config_path: &Path) -> bool {
     if !config_path.exists() || !config_path.is_file() {
         return false;
     }
-    if let Ok(content) = fs::read_to_string(config_path) {
-        if let Ok(yaml) = serde_yaml::from_str::<Value>(&content) {
-            if let Some(map) = yaml.as_mapping() {
-                return map.contains_key("proxies")
-                    || map.contains_key("proxy-groups")
-                    || map.contains_key("rules");
-            }
+    fs::read_to_string(config_path).is_ok_and(|content| core.is_config(&content))
+}
+
+/// Whether `content` is a YAML mapping with any of the top-level Clash sections.
+pub fn is_clash_config(content: &str) -> bool {
+    if let Ok(yaml) = serde_yaml::from_str::<Value>(content) {
+        if let Some(map) = yaml.as_mapping() {
+            return map.contains_key("proxies")
+                || map.contains_key("proxy-groups")
+                || map.contains_key("rules");
         }
     }
     false
 }
 
-fn read_config_from_stdin(config_path: &Path) -> bool {
+fn read_config_from_stdin() -> Option<String> {
     info!("Please input your config content below (press Ctrl+D on a new line to finish):");
     let mut buffer = String::new();
     if io::stdin().read_to_string(&mut buffer).is_ok() && !buffer.trim().is_empty() {
-        if let Ok(mut file) = File::create(config_path) {
-            if file.write_all(buffer.as_bytes()).is_ok() {
-                info!("Config saved to {}", config_path.display());
-                return true;
-            }
+        return Some(buffer);
+    }
+    None
+}
+
+const CLASH_TEMPLATE: &str = "\
+# Paste your Clash/Mihomo config here, save and close the editor.
+# proxy-rs sets the ports, controller and WebUI when it starts the core.
+proxies:
+  - name: example
+    type: ss
+    server: example.com
+    port: 8388
+    cipher: aes-128-gcm
+    password: password
+proxy-groups:
+  - name: PROXY
+    type: select
+    proxies:
+      - example
+rules:
+  - MATCH,PROXY
+";
+
+const SING_BOX_TEMPLATE: &str = r#"{
+  "outbounds": [
+    {
+      "type": "direct",
+      "tag": "direct"
+    }
+  ]
+}
+"#;
+
+/// Where the previous config is kept while a new one is checked, `config.yaml.bak`.
+pub fn backup_path(config_path: &Path) -> PathBuf {
+    let mut name = config_path.as_os_str().to_owned();
+    name.push(".bak");
+    PathBuf::from(name)
+}
+
+/// The config before the last `config edit`, `config.yaml.edit.bak`. Apart from
+/// [`backup_path`] so an edit doesn't replace the config a download kept.
+pub fn edit_backup_path(config_path: &Path) -> PathBuf {
+    let mut name = config_path.as_os_str().to_owned();
+    name.push(".edit.bak");
+    PathBuf::from(name)
+}
+
+/// The subscription as downloaded, before any override, `config.sub.yaml`.
+/// The config is rebuilt from it when the provider reports no change.
+pub fn pristine_path(config_path: &Path) -> PathBuf {
+    let stem = config_path
+        .file_stem()
+        .unwrap_or_default()
+        .to_string_lossy();
+    match config_path.extension() {
+        Some(extension) => {
+            config_path.with_file_name(format!("{stem}.sub.{}", extension.to_string_lossy()))
         }
+        None => config_path.with_file_name(format!("{stem}.sub")),
     }
-    false
+}
+
+/// Puts the subscription as downloaded back in place of the config, for the
+/// overrides to be applied again. Returns whether there was a pristine copy.
+pub fn rebuild_from_pristine(config_path: &Path) -> Result<bool> {
+    let pristine = pristine_path(config_path);
+    if !pristine.exists() {
+        return Ok(false);
+    }
+    fs::copy(&pristine, config_path)?;
+    Ok(true)
+}
+
+/// Starting point for a new config of `kind`.
+pub fn config_template(kind: CoreKind) -> &'static str {
+    match kind {
+        CoreKind::Mihomo => CLASH_TEMPLATE,
+        CoreKind::SingBox => SING_BOX_TEMPLATE,
+    }
+}
+
+/// Fails with the syntax error, including its line, or with the reason `content`
+/// is not a config for `core`.
+pub fn check_config_content(core: &dyn CoreBackend, content: &str) -> Result<()> {
+    match core.kind() {
+        CoreKind::Mihomo => {
+            serde_yaml::from_str::<Value>(content).context("Invalid YAML")?;
+        }
+        CoreKind::SingBox => {
+            serde_json::from_str::<serde_json::Value>(content).context("Invalid JSON")?;
+        }
+    }
+    if !core.is_config(content) {
+        return Err(anyhow!(
+            "Not a {} config, it needs {}",
+            core.name(),
+            match core.kind() {
+                CoreKind::Mihomo => "proxies, proxy-groups or rules",
+                CoreKind::SingBox => "outbounds",
+            }
+        ));
+    }
+    Ok(())
+}
+
+/// Opens `initial` in the editor as a file next to `config_path` until it holds
+/// a valid config. Returns the new content, `None` if it was left unchanged or
+/// the user gave up.
+pub fn edit_config_content(
+    core: &dyn CoreBackend,
+    config_path: &Path,
+    initial: &str,
+) -> Result<Option<String>> {
+    let extension = config_path
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or("yaml");
+    let edit_path = config_path.with_extension(format!("edit.{extension}"));
+    fs::write(&edit_path, initial)?;
+    let result = (|| loop {
+        open_in_editor(&edit_path)?;
+        let content = fs::read_to_string(&edit_path)?;
+        if content == initial {
+            info!("No changes made");
+            return Ok(None);
+        }
+        match check_config_content(core, &content) {
+            Ok(()) => return Ok(Some(content)),
+            Err(e) => warn!("{e:#}"),
+        }
+        // The editor reopens with the mistake, unless nobody is there to fix it
+        if !ask_for_confirmation("Edit it again?", false) {
+            return Ok(None);
+        }
+    })();
+    let _ = fs::remove_file(&edit_path);
+    result
 }
 
 pub fn parse_mixed_port(config_path: &Path) -> Option<u16> {
@@ -107,6 +630,37 @@ pub fn update_mixed_port(config_path: &Path, new_port: u16) -> Result<()> {
     fs::write(config_path, serde_yaml::to_string(&yaml)?)?;
     Ok(())
 }
+/// `port` or `socks-port` of the config, the HTTP-only and SOCKS5-only listeners.
+pub fn parse_listener_port(config_path: &Path, key: &str) -> Option<u16> {
+    let content = fs::read_to_string(config_path).ok()?;
+    let yaml = serde_yaml::from_str::<Value>(&content).ok()?;
+    yaml.get(key)?.as_u64().and_then(|p| u16::try_from(p).ok())
+}
+
+pub fn update_listener_port(config_path: &Path, key: &str, port: u16) -> Result<()> {
+    let content = fs::read_to_string(config_path)?;
+    let mut yaml = serde_yaml::from_str::<Value>(&content)?;
+    let map = yaml
+        .as_mapping_mut()
+        .ok_or_else(|| anyhow::anyhow!("Invalid YAML"))?;
+    map.insert(key.into(), port.into());
+    fs::write(config_path, serde_yaml::to_string(&yaml)?)?;
+    Ok(())
+}
+
+/// Removes the top-level `key` from the config, if it is there.
+pub fn remove_key(config_path: &Path, key: &str) -> Result<()> {
+    let content = fs::read_to_string(config_path)?;
+    let mut yaml = serde_yaml::from_str::<Value>(&content)?;
+    let map = yaml
+        .as_mapping_mut()
+        .ok_or_else(|| anyhow::anyhow!("Invalid YAML"))?;
+    if map.remove(key).is_some() {
+        fs::write(config_path, serde_yaml::to_string(&yaml)?)?;
+    }
+    Ok(())
+}
+
 pub fn update_external_controller(config_path: &Path, external_controller: &str) -> Result<()> {
     let content = fs::read_to_string(config_path)?;
     let mut yaml = serde_yaml::from_str::<Value>(&content)?;
@@ -117,3 +671,544 @@ pub fn update_external_controller(config_path: &Path, external_controller: &str)
     fs::write(config_path, serde_yaml::to_string(&yaml)?)?;
     Ok(())
 }
+
+/// Serves the controller on the Unix socket at `path`, or stops with `None`.
+pub fn update_external_controller_unix(config_path: &Path, path: Option<&Path>) -> Result<()> {
+    let content = fs::read_to_string(config_path)?;
+    let mut yaml = serde_yaml::from_str::<Value>(&content)?;
+    let map = yaml
+        .as_mapping_mut()
+        .ok_or_else(|| anyhow::anyhow!("Invalid YAML"))?;
+    match path {
+        Some(path) => map.insert(
+            "external-controller-unix".into(),
+            path.to_string_lossy().into(),
+        ),
+        None => map.remove("external-controller-unix"),
+    };
+    fs::write(config_path, serde_yaml::to_string(&yaml)?)?;
+    Ok(())
+}
+
+/// Serves the controller over HTTPS on `address` with `certificate` and `private_key`.
+/// With `None` the HTTPS controller is removed again, unless the config uses
+/// a certificate of its own.
+pub fn update_controller_tls(
+    config_path: &Path,
+    address: Option<&str>,
+    certificate: &Path,
+    private_key: &Path,
+) -> Result<()> {
+    let content = fs::read_to_string(config_path)?;
+    let mut yaml = serde_yaml::from_str::<Value>(&content)?;
+    let map = yaml
+        .as_mapping_mut()
+        .ok_or_else(|| anyhow::anyhow!("Invalid YAML"))?;
+    let certificate = certificate.to_string_lossy();
+    match address {
+        Some(address) => {
+            map.insert("external-controller-tls".into(), address.into());
+            let tls = map
+                .entry("tls".into())
+                .or_insert_with(|| Value::Mapping(serde_yaml::Mapping::new()));
+            if !tls.is_mapping() {
+                *tls = Value::Mapping(serde_yaml::Mapping::new());
+            }
+            let tls = tls.as_mapping_mut().expect("tls is a mapping");
+            tls.insert("certificate".into(), certificate.as_ref().into());
+            tls.insert(
+                "private-key".into(),
+                private_key.to_string_lossy().as_ref().into(),
+            );
+        }
+        None => {
+            let ours = map
+                .get("tls")
+                .and_then(|tls| tls.get("certificate"))
+                .and_then(Value::as_str)
+                == Some(certificate.as_ref());
+            if !ours {
+                return Ok(());
+            }
+            map.remove("external-controller-tls");
+            if let Some(tls) = map.get_mut("tls").and_then(Value::as_mapping_mut) {
+                tls.remove("certificate");
+                tls.remove("private-key");
+                if tls.is_empty() {
+                    map.remove("tls");
+                }
+            }
+        }
+    }
+    fs::write(config_path, serde_yaml::to_string(&yaml)?)?;
+    Ok(())
+}
+
+/// Writes a `tun:` section that routes all traffic through Mihomo, or turns
+/// the existing one off.
+pub fn update_tun(config_path: &Path, enable: bool) -> Result<()> {
+    let content = fs::read_to_string(config_path)?;
+    let mut yaml = serde_yaml::from_str::<Value>(&content)?;
+    let map = yaml
+        .as_mapping_mut()
+        .ok_or_else(|| anyhow::anyhow!("Invalid YAML"))?;
+    if !enable {
+        if let Some(tun) = map.get_mut("tun").and_then(Value::as_mapping_mut) {
+            tun.insert("enable".into(), false.into());
+            fs::write(config_path, serde_yaml::to_string(&yaml)?)?;
+        }
+        return Ok(());
+    }
+    let tun = serde_yaml::from_str::<Value>(
+        r#"
+enable: true
+stack: mixed
+auto-route: true
+auto-redirect: false
+auto-detect-interface: true
+strict-route: false
+dns-hijack:
+  - any:53
+  - tcp://any:53
+"#,
+    )?;
+    map.insert("tun".into(), tun);
+    fs::write(config_path, serde_yaml::to_string(&yaml)?)?;
+    Ok(())
+}
+
+pub fn parse_external_controller(config_path: &Path) -> Option<String> {
+    let content = fs::read_to_string(config_path).ok()?;
+    let yaml = serde_yaml::from_str::<Value>(&content).ok()?;
+    yaml.get("external-controller")?
+        .as_str()
+        .map(|s| s.to_string())
+}
+
+/// `external-controller-unix`, the controller's Unix socket.
+pub fn parse_external_controller_unix(config_path: &Path) -> Option<String> {
+    let content = fs::read_to_string(config_path).ok()?;
+    let yaml = serde_yaml::from_str::<Value>(&content).ok()?;
+    yaml.get("external-controller-unix")?
+        .as_str()
+        .map(|s| s.to_string())
+}
+
+/// `external-controller-tls`, the HTTPS controller address.
+pub fn parse_controller_tls(config_path: &Path) -> Option<String> {
+    let content = fs::read_to_string(config_path).ok()?;
+    let yaml = serde_yaml::from_str::<Value>(&content).ok()?;
+    yaml.get("external-controller-tls")?
+        .as_str()
+        .map(|s| s.to_string())
+}
+
+pub fn parse_allow_lan(config_path: &Path) -> bool {
+    fs::read_to_string(config_path)
+        .ok()
+        .and_then(|content| serde_yaml::from_str::<Value>(&content).ok())
+        .and_then(|yaml| yaml.get("allow-lan")?.as_bool())
+        .unwrap_or(false)
+}
+
+pub fn parse_secret(config_path: &Path) -> Option<String> {
+    let content = fs::read_to_string(config_path).ok()?;
+    let yaml = serde_yaml::from_str::<Value>(&content).ok()?;
+    yaml.get("secret")?
+        .as_str()
+        .filter(|s| !s.is_empty())
+        .map(|s| s.to_string())
+}
+
+pub fn update_secret(config_path: &Path, secret: &str) -> Result<()> {
+    let content = fs::read_to_string(config_path)?;
+    let mut yaml = serde_yaml::from_str::<Value>(&content)?;
+    let map = yaml
+        .as_mapping_mut()
+        .ok_or_else(|| anyhow::anyhow!("Invalid YAML"))?;
+    map.insert("secret".into(), secret.into());
+    fs::write(config_path, serde_yaml::to_string(&yaml)?)?;
+    Ok(())
+}
+
+/// Sets the delay test URL and the seconds between tests of every url-test,
+/// fallback and load-balance group. Returns the number of groups changed.
+pub fn update_delay_test(
+    config_path: &Path,
+    url: Option<&str>,
+    interval: Option<u64>,
+) -> Result<usize> {
+    let content = fs::read_to_string(config_path)?;
+    let mut yaml = serde_yaml::from_str::<Value>(&content)?;
+    let Some(groups) = yaml
+        .get_mut("proxy-groups")
+        .and_then(Value::as_sequence_mut)
+    else {
+        return Ok(0);
+    };
+    let mut changed = 0;
+    for group in groups.iter_mut().filter_map(Value::as_mapping_mut) {
+        let kind = group.get("type").and_then(Value::as_str);
+        if !matches!(kind, Some("url-test" | "fallback" | "load-balance")) {
+            continue;
+        }
+        let before = group.clone();
+        if let Some(url) = url {
+            group.insert("url".into(), url.into());
+        }
+        if let Some(interval) = interval {
+            group.insert("interval".into(), interval.into());
+        }
+        if *group != before {
+            changed += 1;
+        }
+    }
+    if changed > 0 {
+        fs::write(config_path, serde_yaml::to_string(&yaml)?)?;
+    }
+    Ok(changed)
+}
+
+pub fn update_mode(config_path: &Path, mode: &str) -> Result<()> {
+    let content = fs::read_to_string(config_path)?;
+    let mut yaml = serde_yaml::from_str::<Value>(&content)?;
+    let map = yaml
+        .as_mapping_mut()
+        .ok_or_else(|| anyhow::anyhow!("Invalid YAML"))?;
+    map.insert("mode".into(), mode.into());
+    fs::write(config_path, serde_yaml::to_string(&yaml)?)?;
+    Ok(())
+}
+
+/// Sets `allow-lan`, binding on all interfaces when it is enabled.
+pub fn update_allow_lan(config_path: &Path, allow_lan: bool) -> Result<()> {
+    let content = fs::read_to_string(config_path)?;
+    let mut yaml = serde_yaml::from_str::<Value>(&content)?;
+    let map = yaml
+        .as_mapping_mut()
+        .ok_or_else(|| anyhow::anyhow!("Invalid YAML"))?;
+    map.insert("allow-lan".into(), allow_lan.into());
+    if allow_lan {
+        map.insert("bind-address".into(), "*".into());
+    }
+    fs::write(config_path, serde_yaml::to_string(&yaml)?)?;
+    Ok(())
+}
+
+/// Name of the listener `tunnel --proxy` adds for its forwarded connections.
+pub const TUNNEL_LISTENER: &str = "proxy-rs-tunnel";
+
+/// Adds a mixed listener named [`TUNNEL_LISTENER`] on 127.0.0.1:`port` that
+/// only accepts `username` with `password`, replacing an earlier one.
+pub fn add_tunnel_listener(
+    config_path: &Path,
+    port: u16,
+    username: &str,
+    password: &str,
+) -> Result<()> {
+    let content = fs::read_to_string(config_path)?;
+    let mut yaml = serde_yaml::from_str::<Value>(&content)?;
+    let map = yaml
+        .as_mapping_mut()
+        .ok_or_else(|| anyhow::anyhow!("Invalid YAML"))?;
+    let listeners = map
+        .entry("listeners".into())
+        .or_insert_with(|| Value::Sequence(Vec::new()))
+        .as_sequence_mut()
+        .ok_or_else(|| anyhow!("listeners is not a list"))?;
+    listeners.retain(|l| l.get("name").and_then(Value::as_str) != Some(TUNNEL_LISTENER));
+    let mut user = serde_yaml::Mapping::new();
+    user.insert("username".into(), username.into());
+    user.insert("password".into(), password.into());
+    let mut listener = serde_yaml::Mapping::new();
+    listener.insert("name".into(), TUNNEL_LISTENER.into());
+    listener.insert("type".into(), "mixed".into());
+    listener.insert("port".into(), port.into());
+    listener.insert("listen".into(), "127.0.0.1".into());
+    listener.insert("users".into(), Value::Sequence(vec![user.into()]));
+    listeners.push(listener.into());
+    fs::write(config_path, serde_yaml::to_string(&yaml)?)?;
+    Ok(())
+}
+
+/// Removes the [`TUNNEL_LISTENER`], returns whether the config had it.
+pub fn remove_tunnel_listener(config_path: &Path) -> Result<bool> {
+    let content = fs::read_to_string(config_path)?;
+    let mut yaml = serde_yaml::from_str::<Value>(&content)?;
+    let Some(listeners) = yaml.get_mut("listeners").and_then(Value::as_sequence_mut) else {
+        return Ok(false);
+    };
+    let count = listeners.len();
+    listeners.retain(|l| l.get("name").and_then(Value::as_str) != Some(TUNNEL_LISTENER));
+    if listeners.len() == count {
+        return Ok(false);
+    }
+    fs::write(config_path, serde_yaml::to_string(&yaml)?)?;
+    Ok(true)
+}
+
+/// Whether `skip-auth-prefixes` lets connections from 127.0.0.1 or ::1 skip
+/// authentication, which is where tunneled connections come from.
+pub fn skips_loopback_auth(config_path: &Path) -> bool {
+    let Some(yaml) = fs::read_to_string(config_path)
+        .ok()
+        .and_then(|content| serde_yaml::from_str::<Value>(&content).ok())
+    else {
+        return false;
+    };
+    let Some(prefixes) = yaml.get("skip-auth-prefixes").and_then(Value::as_sequence) else {
+        return false;
+    };
+    prefixes.iter().filter_map(Value::as_str).any(|prefix| {
+        prefix_contains(prefix, Ipv4Addr::LOCALHOST.into())
+            || prefix_contains(prefix, Ipv6Addr::LOCALHOST.into())
+    })
+}
+
+/// Whether the CIDR `prefix`, e.g. `127.0.0.1/8`, contains `ip`.
+pub fn prefix_contains(prefix: &str, ip: IpAddr) -> bool {
+    let (address, len) = prefix.trim().split_once('/').unwrap_or((prefix.trim(), ""));
+    let Ok(address) = address.parse::<IpAddr>() else {
+        return false;
+    };
+    match (address, ip) {
+        (IpAddr::V4(address), IpAddr::V4(ip)) => {
+            let len = len.parse().unwrap_or(32).min(32);
+            let mask = u32::MAX.checked_shl(32 - len).unwrap_or(0);
+            u32::from(address) & mask == u32::from(ip) & mask
+        }
+        (IpAddr::V6(address), IpAddr::V6(ip)) => {
+            let len = len.parse().unwrap_or(128).min(128);
+            let mask = u128::MAX.checked_shl(128 - len).unwrap_or(0);
+            u128::from(address) & mask == u128::from(ip) & mask
+        }
+        _ => false,
+    }
+}
+
+/// Adds an `Auto` url-test and a `Fallback` group over every node unless the
+/// config has groups with those names. A config without any groups also gets
+/// a `Proxy` selector, and one without rules sends everything through it.
+/// Returns the names of the added groups.
+pub fn add_auto_groups(config_path: &Path) -> Result<Vec<String>> {
+    let content = fs::read_to_string(config_path)?;
+    let mut yaml = serde_yaml::from_str::<Value>(&content)?;
+    let map = yaml
+        .as_mapping_mut()
+        .ok_or_else(|| anyhow::anyhow!("Invalid YAML"))?;
+    let nodes: Vec<Value> = map
+        .get("proxies")
+        .and_then(Value::as_sequence)
+        .into_iter()
+        .flatten()
+        .filter_map(|proxy| proxy.get("name").cloned())
+        .collect();
+    if nodes.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let groups = map
+        .entry("proxy-groups".into())
+        .or_insert_with(|| Value::Sequence(Vec::new()));
+    let groups = groups
+        .as_sequence_mut()
+        .ok_or_else(|| anyhow!("proxy-groups is not a list"))?;
+    let had_groups = !groups.is_empty();
+    let exists = |groups: &[Value], name: &str| {
+        groups
+            .iter()
+            .any(|group| group.get("name").and_then(Value::as_str) == Some(name))
+    };
+    let mut added = Vec::new();
+    for (name, kind) in [("Auto", "url-test"), ("Fallback", "fallback")] {
+        if exists(groups, name) {
+            continue;
+        }
+        let mut group = serde_yaml::Mapping::new();
+        group.insert("name".into(), name.into());
+        group.insert("type".into(), kind.into());
+        group.insert("proxies".into(), Value::Sequence(nodes.clone()));
+        group.insert("url".into(), DEFAULT_DELAY_TEST_URL.into());
+        group.insert("interval".into(), 300.into());
+        if kind == "url-test" {
+            // Don't switch nodes for a few milliseconds of difference
+            group.insert("tolerance".into(), 50.into());
+        }
+        groups.push(Value::Mapping(group));
+        added.push(name.to_string());
+    }
+    if !had_groups {
+        let mut members: Vec<Value> = added.iter().map(|name| name.as_str().into()).collect();
+        members.extend(nodes);
+        let mut group = serde_yaml::Mapping::new();
+        group.insert("name".into(), "Proxy".into());
+        group.insert("type".into(), "select".into());
+        group.insert("proxies".into(), Value::Sequence(members));
+        // Listed first, the UI and `--auto-select` treat it as the main group
+        groups.insert(0, Value::Mapping(group));
+        added.insert(0, "Proxy".to_string());
+
+        let rules = map
+            .entry("rules".into())
+            .or_insert_with(|| Value::Sequence(Vec::new()));
+        if rules.as_sequence().is_some_and(Vec::is_empty) {
+            *rules = Value::Sequence(vec!["MATCH,Proxy".into()]);
+        }
+    }
+    fs::write(config_path, serde_yaml::to_string(&yaml)?)?;
+    Ok(added)
+}
+
+/// Replaces masked values in [`redact_config`] and sing-box's equivalent.
+pub const REDACTED: &str = "<redacted>";
+
+/// Config keys holding credentials or server addresses, in Mihomo's or
+/// sing-box's spelling, in any case.
+pub fn is_sensitive_key(key: &str) -> bool {
+    const KEYS: &[&str] = &[
+        "secret",
+        "server",
+        "servername",
+        "server-name",
+        "host",
+        "sni",
+        "uuid",
+        "username",
+        "password",
+        "obfs-password",
+        "auth",
+        "auth-str",
+        "authentication",
+        "token",
+        "psk",
+        "pre-shared-key",
+        "private-key",
+        "public-key",
+        "short-id",
+    ];
+    // Configs converted from other clients spell keys like `Password` or `SNI`
+    KEYS.contains(&key.replace('_', "-").to_ascii_lowercase().as_str())
+}
+
+fn redact_value(value: &mut Value) {
+    match value {
+        Value::Mapping(map) => {
+            for (key, value) in map.iter_mut() {
+                if key.as_str().is_some_and(is_sensitive_key) {
+                    *value = REDACTED.into();
+                } else {
+                    redact_value(value);
+                }
+            }
+        }
+        Value::Sequence(items) => items.iter_mut().for_each(redact_value),
+        _ => {}
+    }
+}
+
+/// The config with credentials and server addresses masked, including the
+/// subscription URLs of `proxy-providers`.
+pub fn redact_config(config_path: &Path) -> Result<String> {
+    let content = fs::read_to_string(config_path)?;
+    let mut yaml = serde_yaml::from_str::<Value>(&content)
+        .with_context(|| format!("Invalid {}", config_path.display()))?;
+    redact_value(&mut yaml);
+    if let Some(providers) = yaml
+        .get_mut("proxy-providers")
+        .and_then(Value::as_mapping_mut)
+    {
+        for provider in providers.values_mut() {
+            if let Some(url) = provider.get_mut("url") {
+                *url = REDACTED.into();
+            }
+        }
+    }
+    Ok(serde_yaml::to_string(&yaml)?)
+}
+
+pub fn parse_geodata_mode(config_path: &Path) -> Option<bool> {
+    let content = fs::read_to_string(config_path).ok()?;
+    let yaml = serde_yaml::from_str::<Value>(&content).ok()?;
+    yaml.get("geodata-mode")?.as_bool()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn yaml(content: &str) -> Value {
+        serde_yaml::from_str(content).unwrap()
+    }
+
+    fn names(value: &Value) -> Vec<&str> {
+        value
+            .as_sequence()
+            .unwrap()
+            .iter()
+            .map(|item| item.as_str().or_else(|| item["name"].as_str()).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn merges_nodes_into_the_first_config() {
+        let first = yaml(
+            "
+mixed-port: 7890
+proxies: [{name: HK, type: ss}, {name: JP, type: ss}]
+proxy-groups: [{name: Proxy, type: select, proxies: [HK, JP]}]
+rules: ['MATCH,Proxy']
+",
+        );
+        let second = yaml(
+            "
+mixed-port: 1234
+proxies: [{name: HK, type: trojan}, {name: SG, type: trojan}]
+proxy-groups: [{name: Other, type: select, proxies: [HK, SG]}]
+rules: ['MATCH,DIRECT']
+",
+        );
+        let merged = merge_configs(vec![
+            ("a.example".to_string(), first),
+            ("b.example".to_string(), second),
+        ])
+        .unwrap();
+
+        assert_eq!(merged["mixed-port"], Value::from(7890));
+        assert_eq!(names(&merged["rules"]), ["MATCH,Proxy"]);
+        // Taken names get a number
+        assert_eq!(names(&merged["proxies"]), ["HK", "JP", "HK 2", "SG"]);
+        let groups = &merged["proxy-groups"];
+        assert_eq!(names(groups), ["Proxy", "a.example", "b.example"]);
+        assert_eq!(
+            names(&groups[0]["proxies"]),
+            ["a.example", "b.example", "HK", "JP"]
+        );
+        assert_eq!(names(&groups[1]["proxies"]), ["HK", "JP"]);
+        assert_eq!(names(&groups[2]["proxies"]), ["HK 2", "SG"]);
+    }
+
+    #[test]
+    fn group_names_avoid_node_names() {
+        let first = yaml("proxies: [{name: b.example, type: ss}]");
+        let second = yaml("proxies: [{name: SG, type: ss}]");
+        let merged = merge_configs(vec![
+            ("a.example".to_string(), first),
+            ("b.example".to_string(), second),
+        ])
+        .unwrap();
+        assert_eq!(names(&merged["proxy-groups"]), ["a.example", "b.example 2"]);
+    }
+
+    #[test]
+    fn merging_nothing_fails() {
+        assert!(merge_configs(Vec::new()).is_err());
+        assert!(merge_configs(vec![("a".to_string(), yaml("[1, 2]"))]).is_err());
+    }
+
+    #[test]
+    fn numbers_taken_names() {
+        let taken = ["HK".to_string(), "HK 2".to_string()];
+        assert_eq!(unique_name("JP", &taken), "JP");
+        assert_eq!(unique_name("HK", &taken), "HK 3");
+    }
+}
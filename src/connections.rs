@@ -0,0 +1,224 @@
+use crate::controller::{Connection, Controller};
+use crate::output::{format_bytes, Table};
+use anyhow::{anyhow, Context, Result};
+use colored::Colorize;
+use log::*;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+const ID_WIDTH: usize = 8;
+/// How long `which` waits for the core to connect to the destination
+const WHICH_TIMEOUT: Duration = Duration::from_secs(10);
+
+fn destination(connection: &Connection) -> String {
+    let metadata = &connection.metadata;
+    let host = if metadata.host.is_empty() {
+        &metadata.destination_ip
+    } else {
+        &metadata.host
+    };
+    format!("{host}:{}", metadata.destination_port)
+}
+
+fn rule(connection: &Connection) -> String {
+    if connection.rule_payload.is_empty() {
+        connection.rule.clone()
+    } else {
+        format!("{}({})", connection.rule, connection.rule_payload)
+    }
+}
+
+/// Mihomo lists the chain from the outbound node back to the first group.
+fn chain(connection: &Connection) -> String {
+    connection
+        .chains
+        .iter()
+        .rev()
+        .cloned()
+        .collect::<Vec<_>>()
+        .join(" > ")
+}
+
+pub async fn print_connections(controller: &Controller) -> Result<()> {
+    let mut snapshot = controller.connections().await?;
+    snapshot
+        .connections
+        .sort_by_key(|c| std::cmp::Reverse(c.upload + c.download));
+
+    let mut table =
+        Table::new(["ID", "DESTINATION", "RULE", "CHAIN", "UP", "DOWN"]).align_right([4, 5]);
+    for c in &snapshot.connections {
+        table.row([
+            c.id.chars().take(ID_WIDTH).collect::<String>().into(),
+            destination(c).into(),
+            rule(c).into(),
+            chain(c).into(),
+            format_bytes(c.upload).into(),
+            format_bytes(c.download).into(),
+        ]);
+    }
+    table.print();
+    println!(
+        "\n{} connections, total ↑ {} ↓ {}",
+        table.len(),
+        format_bytes(snapshot.upload_total),
+        format_bytes(snapshot.download_total)
+    );
+    Ok(())
+}
+
+/// Redraws the connection table every second until Ctrl+C is pressed.
+pub async fn watch_connections(controller: &Controller) -> Result<()> {
+    loop {
+        print!("\x1b[2J\x1b[H");
+        print_connections(controller).await?;
+        tokio::select! {
+            _ = tokio::time::sleep(Duration::from_secs(1)) => {}
+            _ = tokio::signal::ctrl_c() => break,
+        }
+    }
+    Ok(())
+}
+
+/// Closes the connection whose id starts with `id`.
+pub async fn kill_connection(controller: &Controller, id: &str) -> Result<()> {
+    let snapshot = controller.connections().await?;
+    let matches: Vec<&Connection> = snapshot
+        .connections
+        .iter()
+        .filter(|c| c.id.starts_with(id))
+        .collect();
+    let connection = match matches.as_slice() {
+        [connection] => connection,
+        [] => return Err(anyhow!("Connection not found: {id}")),
+        _ => return Err(anyhow!("Connection id {id} is ambiguous")),
+    };
+    controller.close_connection(&connection.id).await?;
+    info!("Closed connection to {}", destination(connection));
+    Ok(())
+}
+
+/// Where the core sends traffic to a destination, found by `which`.
+#[derive(Debug, Clone)]
+pub struct Route {
+    /// The rule that matched, e.g. `GeoSite(cn)` or `Match`
+    pub rule: String,
+    /// 1-based position of the rule in the running config, if it was found
+    pub rule_index: Option<usize>,
+    /// Groups and the node the connection went through, `DIRECT` or `REJECT`
+    pub chain: String,
+    /// The IP the destination resolved to, if it was resolved
+    pub ip: Option<String>,
+}
+
+/// The groups the running core has selected from `target` on down to a node,
+/// e.g. `Proxy > Auto > HK 01`, without opening any connection.
+pub async fn selected_chain(controller: &Controller, target: &str) -> Result<String> {
+    let proxies = controller.proxies().await?;
+    let mut chain = vec![target.to_string()];
+    while let Some(now) = proxies
+        .get(chain.last().unwrap())
+        .and_then(|proxy| proxy.now.as_ref())
+    {
+        // Groups may select each other
+        if chain.contains(now) {
+            break;
+        }
+        chain.push(now.clone());
+    }
+    Ok(chain.join(" > "))
+}
+
+/// Opens a connection to `host`:`port` through the core's HTTP proxy on
+/// `proxy_port` and reads back which rule and chain the core picked for it.
+/// The connection is closed right after, without sending any data through it.
+pub async fn which(
+    controller: &Controller,
+    proxy_port: u16,
+    host: &str,
+    port: u16,
+) -> Result<Route> {
+    let mut stream = TcpStream::connect(("127.0.0.1", proxy_port))
+        .await
+        .with_context(|| format!("Failed to connect to the proxy on port {proxy_port}"))?;
+    let source_port = stream.local_addr()?.port().to_string();
+    let target = host_port(host, port);
+    stream
+        .write_all(format!("CONNECT {target} HTTP/1.1\r\nHost: {target}\r\n\r\n").as_bytes())
+        .await?;
+    let mut response = [0; 512];
+    let read = tokio::time::timeout(WHICH_TIMEOUT, stream.read(&mut response))
+        .await
+        .map_err(|_| anyhow!("The proxy did not answer within {WHICH_TIMEOUT:?}"))??;
+    let status = String::from_utf8_lossy(&response[..read]);
+    let status = status.lines().next().unwrap_or_default().to_string();
+    debug!("CONNECT {target}: {status}");
+    if status.contains(" 407") {
+        return Err(anyhow!(
+            "The proxy asks for a password, add 127.0.0.1/8 to skip-auth-prefixes"
+        ));
+    }
+
+    // Mihomo answers CONNECT before it dials, the connection shows up once it did
+    let deadline = tokio::time::Instant::now() + WHICH_TIMEOUT;
+    let connection = loop {
+        let snapshot = controller.connections().await?;
+        if let Some(connection) = snapshot
+            .connections
+            .into_iter()
+            .find(|c| c.metadata.source_port == source_port)
+        {
+            break connection;
+        }
+        if tokio::time::Instant::now() >= deadline {
+            return Err(anyhow!(
+                "The core could not connect to {target}, see `proxy logs` for why"
+            ));
+        }
+        tokio::time::sleep(Duration::from_millis(200)).await;
+    };
+    drop(stream);
+    let _ = controller.close_connection(&connection.id).await;
+
+    let rule_index = controller.rules().await.ok().and_then(|rules| {
+        rules
+            .iter()
+            .position(|r| r.kind == connection.rule && r.payload == connection.rule_payload)
+            .map(|index| index + 1)
+    });
+    Ok(Route {
+        rule: rule(&connection),
+        rule_index,
+        chain: chain(&connection),
+        ip: Some(connection.metadata.destination_ip.clone()).filter(|ip| !ip.is_empty()),
+    })
+}
+
+/// `host:port`, with IPv6 addresses in brackets.
+fn host_port(host: &str, port: u16) -> String {
+    if host.contains(':') {
+        format!("[{host}]:{port}")
+    } else {
+        format!("{host}:{port}")
+    }
+}
+
+/// Prints a [`Route`] found by [`which`].
+pub fn print_route(host: &str, port: u16, route: &Route) {
+    println!("{}", host_port(host, port).bold());
+    if let Some(ip) = &route.ip {
+        println!("  IP:    {ip}");
+    }
+    match route.rule_index {
+        Some(index) => println!("  Rule:  #{index} {}", route.rule),
+        None => println!("  Rule:  {}", route.rule),
+    }
+    println!("  Chain: {}", route.chain);
+}
+
+pub async fn kill_all_connections(controller: &Controller) -> Result<()> {
+    controller.close_all_connections().await?;
+    info!("Closed all connections");
+    Ok(())
+}
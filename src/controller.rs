@@ -0,0 +1,565 @@
+use anyhow::{anyhow, Result};
+use futures_util::{Stream, StreamExt};
+use reqwest::{Client, Method, RequestBuilder, Url};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio_tungstenite::tungstenite::Message;
+
+pub const DEFAULT_DELAY_TEST_URL: &str = "https://www.gstatic.com/generate_204";
+pub const DEFAULT_DELAY_TIMEOUT_MS: u64 = 5000;
+
+/// Proxy types that group other proxies instead of being a node themselves.
+const GROUP_TYPES: &[&str] = &["Selector", "URLTest", "Fallback", "LoadBalance", "Relay"];
+/// Built-in outbounds that are neither real nodes nor groups.
+const BUILTIN_TYPES: &[&str] = &[
+    "Direct",
+    "Reject",
+    "RejectDrop",
+    "Compatible",
+    "Pass",
+    "Dns",
+];
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Proxy {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub kind: String,
+    #[serde(default)]
+    pub all: Vec<String>,
+    pub now: Option<String>,
+    /// Whether the core's last health check of the node succeeded
+    pub alive: Option<bool>,
+    /// The core's recent health checks of the node, empty if it was never checked
+    #[serde(default)]
+    pub history: Vec<DelayHistory>,
+}
+
+/// One health check in [`Proxy::history`].
+#[derive(Deserialize, Debug, Clone)]
+pub struct DelayHistory {
+    /// Delay in milliseconds, 0 if the check failed
+    pub delay: u64,
+}
+
+impl Proxy {
+    pub fn is_group(&self) -> bool {
+        GROUP_TYPES.contains(&self.kind.as_str())
+    }
+
+    pub fn is_node(&self) -> bool {
+        !self.is_group() && !BUILTIN_TYPES.contains(&self.kind.as_str())
+    }
+}
+
+/// Subset of the running configuration returned by `GET /configs`.
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "kebab-case")]
+pub struct RunningConfig {
+    #[serde(default)]
+    pub port: u16,
+    #[serde(default)]
+    pub socks_port: u16,
+    #[serde(default)]
+    pub mixed_port: u16,
+    #[serde(default)]
+    pub mode: String,
+    #[serde(default)]
+    pub allow_lan: bool,
+}
+
+/// How Mihomo routes traffic: by rules, everything through the `GLOBAL`
+/// group, or everything directly.
+#[derive(Serialize, Deserialize, clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Mode {
+    Rule,
+    Global,
+    Direct,
+}
+
+impl Mode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Mode::Rule => "rule",
+            Mode::Global => "global",
+            Mode::Direct => "direct",
+        }
+    }
+}
+
+impl std::fmt::Display for Mode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// The two kinds of providers: `proxy-providers` and `rule-providers`.
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProviderKind {
+    Proxy,
+    Rule,
+}
+
+impl ProviderKind {
+    pub const ALL: [ProviderKind; 2] = [ProviderKind::Proxy, ProviderKind::Rule];
+
+    /// Path segment under `/providers`.
+    fn segment(&self) -> &'static str {
+        match self {
+            ProviderKind::Proxy => "proxies",
+            ProviderKind::Rule => "rules",
+        }
+    }
+}
+
+/// A proxy or rule provider, as listed by `GET /providers/...`.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Provider {
+    pub name: String,
+    /// `HTTP`, `File`, `Inline`, or `Compatible` for the groups' built-in providers
+    #[serde(default)]
+    pub vehicle_type: String,
+    pub updated_at: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ProvidersResponse {
+    providers: HashMap<String, Provider>,
+}
+
+#[derive(Deserialize)]
+struct VersionResponse {
+    version: String,
+}
+
+#[derive(Deserialize)]
+struct ProxiesResponse {
+    proxies: HashMap<String, Proxy>,
+}
+
+/// A rule of the running config, as listed by `GET /rules`.
+#[derive(Deserialize, Debug, Clone)]
+pub struct Rule {
+    #[serde(rename = "type")]
+    pub kind: String,
+    #[serde(default)]
+    pub payload: String,
+    #[serde(default)]
+    pub proxy: String,
+}
+
+#[derive(Deserialize)]
+struct RulesResponse {
+    rules: Vec<Rule>,
+}
+
+#[derive(Deserialize)]
+struct DelayResponse {
+    delay: u64,
+}
+
+/// Throughput in bytes per second, reported once per second.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy)]
+pub struct Traffic {
+    pub up: u64,
+    pub down: u64,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct ConnectionMetadata {
+    #[serde(default)]
+    pub network: String,
+    #[serde(default)]
+    pub host: String,
+    #[serde(default, rename = "destinationIP")]
+    pub destination_ip: String,
+    #[serde(default)]
+    pub destination_port: String,
+    #[serde(default)]
+    pub source_port: String,
+    #[serde(default)]
+    pub process: String,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Connection {
+    pub id: String,
+    #[serde(default)]
+    pub metadata: ConnectionMetadata,
+    #[serde(default)]
+    pub upload: u64,
+    #[serde(default)]
+    pub download: u64,
+    #[serde(default)]
+    pub chains: Vec<String>,
+    #[serde(default)]
+    pub rule: String,
+    #[serde(default)]
+    pub rule_payload: String,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Connections {
+    #[serde(default)]
+    pub upload_total: u64,
+    #[serde(default)]
+    pub download_total: u64,
+    #[serde(default)]
+    pub connections: Vec<Connection>,
+}
+
+/// A line of the core's log, as streamed by `/logs`.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct LogEntry {
+    /// `debug`, `info`, `warning` or `error`
+    #[serde(rename = "type")]
+    pub level: String,
+    pub payload: String,
+}
+
+/// Where the external controller listens.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ControllerAddress {
+    Tcp(SocketAddr),
+    /// `external-controller-unix`, only Mihomo on Unix has it
+    Unix(PathBuf),
+}
+
+impl ControllerAddress {
+    /// The TCP port, `None` on a Unix socket.
+    pub fn port(&self) -> Option<u16> {
+        match self {
+            ControllerAddress::Tcp(address) => Some(address.port()),
+            ControllerAddress::Unix(_) => None,
+        }
+    }
+}
+
+impl std::fmt::Display for ControllerAddress {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ControllerAddress::Tcp(address) => write!(f, "{address}"),
+            ControllerAddress::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+/// Client for the Mihomo external controller (RESTful API).
+#[derive(Clone)]
+pub struct Controller {
+    client: Client,
+    base_url: Url,
+    secret: Option<String>,
+    /// Socket every request goes through instead of `base_url`'s host
+    unix_socket: Option<PathBuf>,
+}
+
+impl Controller {
+    /// `address` is `host:port` or a full URL, `secret` the controller's `secret`.
+    pub fn new(address: &str, secret: Option<String>) -> Result<Self> {
+        let base_url = if address.starts_with("http://") || address.starts_with("https://") {
+            Url::parse(address)?
+        } else {
+            Url::parse(&format!("http://{address}"))?
+        };
+        // HTTP_PROXY is meant for the internet, not for a core on this machine
+        let client = if is_local_host(base_url.host_str().unwrap_or_default()) {
+            Client::builder().no_proxy().build()?
+        } else {
+            Client::new()
+        };
+        Ok(Self {
+            client,
+            base_url,
+            secret,
+            unix_socket: None,
+        })
+    }
+
+    /// Client for the controller on the Unix socket at `path`.
+    #[cfg(unix)]
+    pub fn unix(path: &Path, secret: Option<String>) -> Result<Self> {
+        Ok(Self {
+            client: Client::builder().unix_socket(path).build()?,
+            base_url: Url::parse("http://localhost")?,
+            secret,
+            unix_socket: Some(path.to_path_buf()),
+        })
+    }
+
+    /// Client for the controller on the Unix socket at `path`, which Windows
+    /// builds can't reach.
+    #[cfg(not(unix))]
+    pub fn unix(path: &Path, _secret: Option<String>) -> Result<Self> {
+        Err(anyhow!(
+            "The controller listens on the Unix socket {}, which is not supported on this OS",
+            path.display()
+        ))
+    }
+
+    /// Client for the controller at `address`, loopback for one listening on
+    /// all interfaces.
+    pub fn connect(address: &ControllerAddress, secret: Option<String>) -> Result<Self> {
+        match address {
+            ControllerAddress::Tcp(address) => {
+                let ip = match address.ip() {
+                    IpAddr::V4(ip) if ip.is_unspecified() => Ipv4Addr::LOCALHOST.into(),
+                    IpAddr::V6(ip) if ip.is_unspecified() => Ipv6Addr::LOCALHOST.into(),
+                    ip => ip,
+                };
+                Self::new(&SocketAddr::new(ip, address.port()).to_string(), secret)
+            }
+            ControllerAddress::Unix(path) => Self::unix(path, secret),
+        }
+    }
+
+    /// Port the controller listens on, `None` on a Unix socket.
+    pub fn port(&self) -> Option<u16> {
+        if self.unix_socket.is_some() {
+            return None;
+        }
+        self.base_url.port_or_known_default()
+    }
+
+    /// The Unix socket the controller listens on, if it does.
+    pub fn unix_socket(&self) -> Option<&Path> {
+        self.unix_socket.as_deref()
+    }
+
+    fn url(&self, segments: &[&str]) -> Result<Url> {
+        let mut url = self.base_url.clone();
+        url.path_segments_mut()
+            .map_err(|_| anyhow!("Invalid controller address: {}", self.base_url))?
+            .pop_if_empty()
+            .extend(segments);
+        Ok(url)
+    }
+
+    fn request(&self, method: Method, segments: &[&str]) -> Result<RequestBuilder> {
+        let mut builder = self.client.request(method, self.url(segments)?);
+        if let Some(secret) = &self.secret {
+            builder = builder.bearer_auth(secret);
+        }
+        Ok(builder)
+    }
+
+    /// Version of the running core, e.g. `v1.18.10`.
+    pub async fn version(&self) -> Result<String> {
+        let response: VersionResponse = self
+            .request(Method::GET, &["version"])?
+            .timeout(Duration::from_secs(3))
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        Ok(response.version)
+    }
+
+    /// The running configuration.
+    pub async fn configs(&self) -> Result<RunningConfig> {
+        let configs: RunningConfig = self
+            .request(Method::GET, &["configs"])?
+            .timeout(Duration::from_secs(3))
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        Ok(configs)
+    }
+
+    /// Switches the running mode without touching the config file.
+    pub async fn set_mode(&self, mode: Mode) -> Result<()> {
+        self.request(Method::PATCH, &["configs"])?
+            .json(&serde_json::json!({ "mode": mode }))
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+
+    /// Reloads the config file at `path`, which must be inside Mihomo's home directory.
+    pub async fn reload_config(&self, path: &str) -> Result<()> {
+        self.request(Method::PUT, &["configs"])?
+            .query(&[("force", "true")])
+            .json(&serde_json::json!({ "path": path }))
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+
+    /// The rules of the running config, in matching order.
+    pub async fn rules(&self) -> Result<Vec<Rule>> {
+        let response: RulesResponse = self
+            .request(Method::GET, &["rules"])?
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        Ok(response.rules)
+    }
+
+    /// The providers of `kind`, keyed by name.
+    pub async fn providers(&self, kind: ProviderKind) -> Result<HashMap<String, Provider>> {
+        let response: ProvidersResponse = self
+            .request(Method::GET, &["providers", kind.segment()])?
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        Ok(response.providers)
+    }
+
+    /// Makes Mihomo download a provider again, or re-read it for `File` providers.
+    pub async fn update_provider(&self, kind: ProviderKind, name: &str) -> Result<()> {
+        self.request(Method::PUT, &["providers", kind.segment(), name])?
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+
+    /// All proxies and groups, keyed by name.
+    pub async fn proxies(&self) -> Result<HashMap<String, Proxy>> {
+        let response: ProxiesResponse = self
+            .request(Method::GET, &["proxies"])?
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        Ok(response.proxies)
+    }
+
+    /// Tests the delay of a single proxy, `None` on timeout or error.
+    pub async fn proxy_delay(&self, name: &str, url: &str, timeout_ms: u64) -> Result<Option<u64>> {
+        let response = self
+            .request(Method::GET, &["proxies", name, "delay"])?
+            .query(&[("url", url), ("timeout", &timeout_ms.to_string())])
+            .timeout(Duration::from_millis(timeout_ms + 1000))
+            .send()
+            .await?;
+        if !response.status().is_success() {
+            return Ok(None);
+        }
+        Ok(Some(response.json::<DelayResponse>().await?.delay))
+    }
+
+    /// Switches the selected node of a `Selector` group.
+    pub async fn select_proxy(&self, group: &str, name: &str) -> Result<()> {
+        self.request(Method::PUT, &["proxies", group])?
+            .json(&serde_json::json!({ "name": name }))
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+
+    /// Active connections and the total traffic since Mihomo started.
+    pub async fn connections(&self) -> Result<Connections> {
+        let connections: Connections = self
+            .request(Method::GET, &["connections"])?
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        Ok(connections)
+    }
+
+    /// Closes the connection with the given id.
+    pub async fn close_connection(&self, id: &str) -> Result<()> {
+        self.request(Method::DELETE, &["connections", id])?
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+
+    /// Closes all active connections.
+    pub async fn close_all_connections(&self) -> Result<()> {
+        self.request(Method::DELETE, &["connections"])?
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+
+    /// Opens a WebSocket to a streaming endpoint and yields each JSON message.
+    async fn websocket<T>(
+        &self,
+        segments: &[&str],
+        query: &[(&str, &str)],
+    ) -> Result<impl Stream<Item = Result<T>>>
+    where
+        T: serde::de::DeserializeOwned + Send + 'static,
+    {
+        let mut url = self.url(segments)?;
+        let scheme = if url.scheme() == "https" { "wss" } else { "ws" };
+        url.set_scheme(scheme)
+            .map_err(|_| anyhow!("Invalid controller address: {}", self.base_url))?;
+        if let Some(secret) = &self.secret {
+            url.query_pairs_mut().append_pair("token", secret);
+        }
+        if !query.is_empty() {
+            url.query_pairs_mut().extend_pairs(query);
+        }
+
+        #[cfg(unix)]
+        if let Some(path) = &self.unix_socket {
+            let stream = tokio::net::UnixStream::connect(path).await?;
+            let (socket, _) = tokio_tungstenite::client_async(url.as_str(), stream).await?;
+            return Ok(json_messages(socket).boxed());
+        }
+        let (socket, _) = tokio_tungstenite::connect_async(url.as_str()).await?;
+        Ok(json_messages(socket).boxed())
+    }
+
+    /// Streams the current throughput once per second.
+    pub async fn traffic(&self) -> Result<impl Stream<Item = Result<Traffic>>> {
+        self.websocket(&["traffic"], &[]).await
+    }
+
+    /// Streams the core's log lines at `level` and above, including the rule
+    /// matched by each connection at `debug`.
+    pub async fn logs(&self, level: &str) -> Result<impl Stream<Item = Result<LogEntry>>> {
+        self.websocket(&["logs"], &[("level", level)]).await
+    }
+}
+
+/// The JSON text messages of `socket`, other messages skipped.
+fn json_messages<S, T>(socket: S) -> impl Stream<Item = Result<T>>
+where
+    S: Stream<Item = Result<Message, tokio_tungstenite::tungstenite::Error>>,
+    T: serde::de::DeserializeOwned,
+{
+    socket.filter_map(|message| async move {
+        match message {
+            Ok(Message::Text(text)) => Some(serde_json::from_str(&text).map_err(Into::into)),
+            Ok(_) => None,
+            Err(e) => Some(Err(e.into())),
+        }
+    })
+}
+
+/// Whether `host` from a URL is this machine, e.g. `127.0.0.1`, `[::1]` or `localhost`.
+fn is_local_host(host: &str) -> bool {
+    host.eq_ignore_ascii_case("localhost")
+        || host
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .parse::<IpAddr>()
+            .is_ok_and(|ip| ip.is_loopback() || ip.is_unspecified())
+}
@@ -0,0 +1,441 @@
+use crate::controller::{Controller, Proxy, Traffic, DEFAULT_DELAY_TIMEOUT_MS};
+use crate::latency::{self, LatencyResult};
+use crate::output::format_bytes;
+use anyhow::Result;
+use futures_util::StreamExt;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::layout::{Constraint, Direction, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Borders, List, ListItem, ListState, Paragraph, Sparkline};
+use ratatui::{DefaultTerminal, Frame};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+
+const TICK: Duration = Duration::from_millis(200);
+const REFRESH_INTERVAL: Duration = Duration::from_secs(2);
+const TRAFFIC_HISTORY: usize = 120;
+const LOG_LINES: usize = 200;
+
+#[derive(PartialEq)]
+enum Focus {
+    Groups,
+    Nodes,
+}
+
+enum Update {
+    Traffic(Traffic),
+    Latency(Vec<LatencyResult>),
+    Message(String),
+}
+
+struct Dashboard {
+    controller: Controller,
+    pid: u32,
+    log_path: PathBuf,
+    /// URL of the latency tests started with `t`
+    delay_test_url: String,
+    groups: Vec<Proxy>,
+    group_state: ListState,
+    node_state: ListState,
+    focus: Focus,
+    delays: HashMap<String, Option<u64>>,
+    traffic: Traffic,
+    up_history: Vec<u64>,
+    down_history: Vec<u64>,
+    logs: Vec<String>,
+    message: String,
+    last_refresh: Option<Instant>,
+}
+
+impl Dashboard {
+    fn current_group(&self) -> Option<&Proxy> {
+        self.group_state.selected().and_then(|i| self.groups.get(i))
+    }
+
+    async fn refresh(&mut self) {
+        self.last_refresh = Some(Instant::now());
+        match self.controller.proxies().await {
+            Ok(proxies) => {
+                let mut groups: Vec<Proxy> =
+                    proxies.into_values().filter(|p| p.is_group()).collect();
+                groups.sort_by(|a, b| a.name.cmp(&b.name));
+                self.groups = groups;
+                if self.group_state.selected().is_none() && !self.groups.is_empty() {
+                    self.group_state.select(Some(0));
+                }
+            }
+            Err(e) => self.message = format!("Failed to fetch proxies: {e}"),
+        }
+        self.logs = tail_lines(&self.log_path, LOG_LINES);
+    }
+
+    fn move_selection(&mut self, down: bool) {
+        let (state, len) = match self.focus {
+            Focus::Groups => (&mut self.group_state, self.groups.len()),
+            Focus::Nodes => {
+                let len = self.current_group().map(|g| g.all.len()).unwrap_or(0);
+                (&mut self.node_state, len)
+            }
+        };
+        if len == 0 {
+            return;
+        }
+        let i = state.selected().unwrap_or(0);
+        let next = if down {
+            (i + 1) % len
+        } else {
+            (i + len - 1) % len
+        };
+        state.select(Some(next));
+        if self.focus == Focus::Groups {
+            self.node_state.select(Some(0));
+        }
+    }
+
+    async fn select_node(&mut self) {
+        let Some(group) = self.current_group() else {
+            return;
+        };
+        if group.kind != "Selector" {
+            self.message = format!(
+                "{} is a {} group, it can't be selected",
+                group.name, group.kind
+            );
+            return;
+        }
+        let Some(node) = self.node_state.selected().and_then(|i| group.all.get(i)) else {
+            return;
+        };
+        let (group, node) = (group.name.clone(), node.clone());
+        self.message = match self.controller.select_proxy(&group, &node).await {
+            Ok(()) => format!("{group} -> {node}"),
+            Err(e) => format!("Failed to select {node}: {e}"),
+        };
+        self.refresh().await;
+    }
+
+    fn test_latency(&mut self, tx: &mpsc::UnboundedSender<Update>) {
+        let Some(group) = self.current_group() else {
+            return;
+        };
+        let group = group.name.clone();
+        self.message = format!("Testing latency of {group}...");
+        let controller = self.controller.clone();
+        let url = self.delay_test_url.clone();
+        let tx = tx.clone();
+        tokio::spawn(async move {
+            let update = match latency::test_latency(
+                &controller,
+                Some(&group),
+                &url,
+                DEFAULT_DELAY_TIMEOUT_MS,
+            )
+            .await
+            {
+                Ok(results) => Update::Latency(results),
+                Err(e) => Update::Message(format!("Latency test failed: {e}")),
+            };
+            let _ = tx.send(update);
+        });
+    }
+
+    fn apply(&mut self, update: Update) {
+        match update {
+            Update::Traffic(traffic) => {
+                self.traffic = traffic;
+                for (history, value) in [
+                    (&mut self.up_history, traffic.up),
+                    (&mut self.down_history, traffic.down),
+                ] {
+                    history.push(value);
+                    if history.len() > TRAFFIC_HISTORY {
+                        history.remove(0);
+                    }
+                }
+            }
+            Update::Latency(results) => {
+                self.message = format!("Tested {} nodes", results.len());
+                for result in results {
+                    self.delays.insert(result.name, result.delay);
+                }
+            }
+            Update::Message(message) => self.message = message,
+        }
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let rows = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([
+                Constraint::Length(3),
+                Constraint::Length(6),
+                Constraint::Min(8),
+                Constraint::Length(10),
+                Constraint::Length(1),
+            ])
+            .split(frame.area());
+
+        self.draw_status(frame, rows[0]);
+        self.draw_traffic(frame, rows[1]);
+        self.draw_proxies(frame, rows[2]);
+        self.draw_logs(frame, rows[3]);
+
+        let help =
+            "q: quit  tab: switch pane  ↑/↓: move  enter: select node  t: test latency  r: refresh";
+        frame.render_widget(
+            Paragraph::new(Line::from(vec![
+                Span::styled(help, Style::default().fg(Color::DarkGray)),
+                Span::raw("  "),
+                Span::styled(self.message.as_str(), Style::default().fg(Color::Yellow)),
+            ])),
+            rows[4],
+        );
+    }
+
+    fn draw_status(&self, frame: &mut Frame, area: Rect) {
+        let line = Line::from(vec![
+            Span::styled("Mihomo ", Style::default().add_modifier(Modifier::BOLD)),
+            Span::styled("running", Style::default().fg(Color::Green)),
+            Span::raw(format!("  pid {}  ", self.pid)),
+            Span::raw(format!(
+                "↑ {}/s  ↓ {}/s",
+                format_bytes(self.traffic.up),
+                format_bytes(self.traffic.down)
+            )),
+        ]);
+        frame.render_widget(
+            Paragraph::new(line).block(Block::default().borders(Borders::ALL).title("Status")),
+            area,
+        );
+    }
+
+    fn draw_traffic(&self, frame: &mut Frame, area: Rect) {
+        let columns = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints([Constraint::Percentage(50), Constraint::Percentage(50)])
+            .split(area);
+        for (i, (title, history, color)) in [
+            ("Upload", &self.up_history, Color::Green),
+            ("Download", &self.down_history, Color::Cyan),
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            let sparkline = Sparkline::default()
+                .block(Block::default().borders(Borders::ALL).title(title))
+                .data(history)
+                .style(Style::default().fg(color));
+            frame.render_widget(sparkline, columns[i]);
+        }
+    }
+
+    fn draw_proxies(&mut self, frame: &mut Frame, area: Rect) {
+        let columns = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints([Constraint::Percentage(35), Constraint::Percentage(65)])
+            .split(area);
+        let highlight = Style::default().add_modifier(Modifier::REVERSED);
+        let border = |focused: bool| {
+            if focused {
+                Style::default().fg(Color::Cyan)
+            } else {
+                Style::default()
+            }
+        };
+
+        let groups: Vec<ListItem> = self
+            .groups
+            .iter()
+            .map(|g| ListItem::new(format!("{} [{}]", g.name, g.now.as_deref().unwrap_or("-"))))
+            .collect();
+        let groups = List::new(groups)
+            .block(
+                Block::default()
+                    .borders(Borders::ALL)
+                    .title("Groups")
+                    .border_style(border(self.focus == Focus::Groups)),
+            )
+            .highlight_style(highlight);
+        frame.render_stateful_widget(groups, columns[0], &mut self.group_state);
+
+        let (title, nodes) = match self.current_group() {
+            Some(group) => (
+                group.name.clone(),
+                group
+                    .all
+                    .iter()
+                    .map(|name| {
+                        let marker = if group.now.as_deref() == Some(name) {
+                            "● "
+                        } else {
+                            "  "
+                        };
+                        let delay = match self.delays.get(name) {
+                            Some(Some(d)) => Span::styled(
+                                format!("  {d} ms"),
+                                Style::default().fg(delay_color(*d)),
+                            ),
+                            Some(None) => {
+                                Span::styled("  timeout", Style::default().fg(Color::Red))
+                            }
+                            None => Span::raw(""),
+                        };
+                        ListItem::new(Line::from(vec![
+                            Span::raw(marker),
+                            Span::raw(name.clone()),
+                            delay,
+                        ]))
+                    })
+                    .collect(),
+            ),
+            None => ("Nodes".to_string(), Vec::new()),
+        };
+        let nodes = List::new(nodes)
+            .block(
+                Block::default()
+                    .borders(Borders::ALL)
+                    .title(title)
+                    .border_style(border(self.focus == Focus::Nodes)),
+            )
+            .highlight_style(highlight);
+        frame.render_stateful_widget(nodes, columns[1], &mut self.node_state);
+    }
+
+    fn draw_logs(&self, frame: &mut Frame, area: Rect) {
+        let visible = area.height.saturating_sub(2) as usize;
+        let lines: Vec<Line> = self
+            .logs
+            .iter()
+            .skip(self.logs.len().saturating_sub(visible))
+            .map(|l| Line::from(l.as_str()))
+            .collect();
+        frame.render_widget(
+            Paragraph::new(lines).block(Block::default().borders(Borders::ALL).title("Logs")),
+            area,
+        );
+    }
+}
+
+fn delay_color(delay: u64) -> Color {
+    match delay {
+        0..200 => Color::Green,
+        200..500 => Color::Yellow,
+        _ => Color::Red,
+    }
+}
+
+fn tail_lines(path: &Path, n: usize) -> Vec<String> {
+    let content = fs::read_to_string(path).unwrap_or_default();
+    let lines: Vec<&str> = content.lines().collect();
+    lines[lines.len().saturating_sub(n)..]
+        .iter()
+        .map(|l| l.to_string())
+        .collect()
+}
+
+pub async fn run_dashboard(
+    controller: Controller,
+    pid: u32,
+    log_path: PathBuf,
+    delay_test_url: String,
+) -> Result<()> {
+    let mut terminal = ratatui::init();
+    let result = run(&mut terminal, controller, pid, log_path, delay_test_url).await;
+    ratatui::restore();
+    result
+}
+
+async fn run(
+    terminal: &mut DefaultTerminal,
+    controller: Controller,
+    pid: u32,
+    log_path: PathBuf,
+    delay_test_url: String,
+) -> Result<()> {
+    let (tx, mut rx) = mpsc::unbounded_channel();
+
+    let traffic_controller = controller.clone();
+    let traffic_tx = tx.clone();
+    let traffic_task = tokio::spawn(async move {
+        match traffic_controller.traffic().await {
+            Ok(stream) => {
+                futures_util::pin_mut!(stream);
+                while let Some(Ok(traffic)) = stream.next().await {
+                    if traffic_tx.send(Update::Traffic(traffic)).is_err() {
+                        break;
+                    }
+                }
+            }
+            Err(e) => {
+                let _ = traffic_tx.send(Update::Message(format!("Traffic unavailable: {e}")));
+            }
+        }
+    });
+
+    let mut dashboard = Dashboard {
+        controller,
+        pid,
+        log_path,
+        delay_test_url,
+        groups: Vec::new(),
+        group_state: ListState::default(),
+        node_state: ListState::default(),
+        focus: Focus::Groups,
+        delays: HashMap::new(),
+        traffic: Traffic { up: 0, down: 0 },
+        up_history: Vec::new(),
+        down_history: Vec::new(),
+        logs: Vec::new(),
+        message: String::new(),
+        last_refresh: None,
+    };
+    dashboard.refresh().await;
+
+    loop {
+        while let Ok(update) = rx.try_recv() {
+            dashboard.apply(update);
+        }
+        if dashboard
+            .last_refresh
+            .is_none_or(|t| t.elapsed() >= REFRESH_INTERVAL)
+        {
+            dashboard.refresh().await;
+        }
+        terminal.draw(|frame| dashboard.draw(frame))?;
+
+        if !event::poll(TICK)? {
+            continue;
+        }
+        let Event::Key(key) = event::read()? else {
+            continue;
+        };
+        if key.kind != KeyEventKind::Press {
+            continue;
+        }
+        match key.code {
+            KeyCode::Char('q') | KeyCode::Esc => break,
+            KeyCode::Tab | KeyCode::Left | KeyCode::Right => {
+                dashboard.focus = match dashboard.focus {
+                    Focus::Groups => Focus::Nodes,
+                    Focus::Nodes => Focus::Groups,
+                };
+                if dashboard.node_state.selected().is_none() {
+                    dashboard.node_state.select(Some(0));
+                }
+            }
+            KeyCode::Up | KeyCode::Char('k') => dashboard.move_selection(false),
+            KeyCode::Down | KeyCode::Char('j') => dashboard.move_selection(true),
+            KeyCode::Enter => dashboard.select_node().await,
+            KeyCode::Char('t') => dashboard.test_latency(&tx),
+            KeyCode::Char('r') => dashboard.refresh().await,
+            _ => {}
+        }
+    }
+
+    traffic_task.abort();
+    Ok(())
+}
@@ -0,0 +1,353 @@
+use crate::backend::CoreBackend;
+use crate::controller::Controller;
+use crate::manifest::Integrity;
+use crate::proxy_selector::{measure_github_proxies, proxy_display_name};
+use crate::utils::{has_tun_privileges, tun_privileges_fix};
+use anyhow::{anyhow, Result};
+use reqwest::Client;
+use std::ffi::OsString;
+use std::fs;
+use std::net::{Ipv4Addr, SocketAddr, TcpListener, TcpStream};
+use std::path::Path;
+use std::time::Duration;
+use tokio::process::Command;
+
+/// Checking a Mihomo config may download missing geodata before it gives up.
+const CORE_TIMEOUT: Duration = Duration::from_secs(30);
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(1);
+
+pub enum Outcome {
+    Pass(String),
+    Skip(String),
+    /// A problem that only matters for some features, or that `start` works around
+    Warn {
+        problem: String,
+        fix: String,
+    },
+    Fail {
+        problem: String,
+        fix: String,
+    },
+}
+
+/// Result of one `doctor` check.
+pub struct Check {
+    pub name: &'static str,
+    pub outcome: Outcome,
+}
+
+impl Check {
+    fn pass(name: &'static str, detail: impl Into<String>) -> Self {
+        Self {
+            name,
+            outcome: Outcome::Pass(detail.into()),
+        }
+    }
+
+    fn skip(name: &'static str, reason: impl Into<String>) -> Self {
+        Self {
+            name,
+            outcome: Outcome::Skip(reason.into()),
+        }
+    }
+
+    fn warn(name: &'static str, problem: impl Into<String>, fix: impl Into<String>) -> Self {
+        Self {
+            name,
+            outcome: Outcome::Warn {
+                problem: problem.into(),
+                fix: fix.into(),
+            },
+        }
+    }
+
+    fn fail(name: &'static str, problem: impl Into<String>, fix: impl Into<String>) -> Self {
+        Self {
+            name,
+            outcome: Outcome::Fail {
+                problem: problem.into(),
+                fix: fix.into(),
+            },
+        }
+    }
+
+    pub fn passed(&self) -> bool {
+        matches!(self.outcome, Outcome::Pass(_))
+    }
+}
+
+/// Runs the core with `args`, returning its combined output if it succeeded.
+pub async fn run_core(core_path: &Path, args: Vec<OsString>) -> Result<String> {
+    let output = Command::new(core_path)
+        .args(args)
+        .kill_on_drop(true)
+        .output();
+    let output = tokio::time::timeout(CORE_TIMEOUT, output)
+        .await
+        .map_err(|_| anyhow!("timed out after {CORE_TIMEOUT:?}"))??;
+    let text = format!(
+        "{}{}",
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    );
+    if !output.status.success() {
+        // The cores log the reason on the last line
+        let reason = text.lines().rev().find(|line| !line.trim().is_empty());
+        return Err(anyhow!(
+            "{}",
+            reason.map_or_else(|| output.status.to_string(), str::to_string)
+        ));
+    }
+    Ok(text)
+}
+
+/// The core binary exists, is executable, runs on this machine and is the one
+/// proxy-rs installed.
+pub async fn core(core: &dyn CoreBackend, core_path: &Path, integrity: Integrity) -> Check {
+    const NAME: &str = "Core";
+    if !core_path.exists() {
+        return Check::fail(
+            NAME,
+            format!(
+                "{} is not installed at {}",
+                core.name(),
+                core_path.display()
+            ),
+            "Run `proxy update`, or `proxy start` which downloads it too.",
+        );
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let executable = fs::metadata(core_path)
+            .map(|m| m.permissions().mode() & 0o111 != 0)
+            .unwrap_or(false);
+        if !executable {
+            return Check::fail(
+                NAME,
+                format!("{} is not executable", core_path.display()),
+                format!("Run: chmod +x {}", core_path.display()),
+            );
+        }
+    }
+    match run_core(core_path, core.version_args()).await {
+        Ok(_) if integrity == Integrity::Changed => Check::warn(
+            NAME,
+            format!("{} differs from the binary proxy-rs installed", core_path.display()),
+            "If it was not replaced on purpose, install it again with `proxy update`.",
+        ),
+        Ok(output) => Check::pass(NAME, output.lines().next().unwrap_or_default().trim()),
+        Err(e) => Check::fail(
+            NAME,
+            format!("Failed to run {}: {e}", core_path.display()),
+            "The binary may be damaged or built for another CPU, download it again with `proxy update`, e.g. with --core-arch amd64-compatible on CPUs without AVX2.",
+        ),
+    }
+}
+
+/// The config is one the core can run and, when the core works, passes its check.
+pub async fn config(
+    core: &dyn CoreBackend,
+    core_path: &Path,
+    config_dir: &Path,
+    test_with_core: bool,
+) -> Check {
+    const NAME: &str = "Config";
+    let config_file = core.config_file();
+    let config_path = config_dir.join(config_file);
+    let Ok(content) = fs::read_to_string(&config_path) else {
+        return Check::fail(
+            NAME,
+            format!("{} does not exist", config_path.display()),
+            "Run `proxy start <URL>` with your subscription URL.",
+        );
+    };
+    if !core.is_config(&content) {
+        return Check::fail(
+            NAME,
+            format!("{config_file} is not a {} config", core.config_format()),
+            "Download the subscription again with `proxy start <URL>`, or convert it with --subconverter.",
+        );
+    }
+    if !test_with_core {
+        return Check::pass(
+            NAME,
+            format!(
+                "{config_file} parses, checking it with {} skipped without a working core",
+                core.name()
+            ),
+        );
+    }
+    match run_core(core_path, core.test_args(config_dir)).await {
+        Ok(_) => Check::pass(
+            NAME,
+            format!("{config_file} passes {}'s check", core.name()),
+        ),
+        Err(e) => Check::fail(
+            NAME,
+            format!("{} rejected {config_file}: {e}", core.name()),
+            format!(
+                "Fix the error in {}, or download the subscription again with `proxy start <URL>`.",
+                config_path.display()
+            ),
+        ),
+    }
+}
+
+/// The running core listens on `address`, read from its config.
+pub fn listening(name: &'static str, address: Option<SocketAddr>) -> Check {
+    let Some(address) = address else {
+        return Check::fail(
+            name,
+            format!("The config has no {name}"),
+            "Restart with `proxy start`, which sets it.",
+        );
+    };
+    match TcpStream::connect_timeout(&address, CONNECT_TIMEOUT) {
+        Ok(_) => Check::pass(name, format!("Listening on {address}")),
+        Err(e) => Check::fail(
+            name,
+            format!("The core is running but {address} does not accept connections: {e}"),
+            "Look for errors in mihomo.err and restart with `proxy start`.",
+        ),
+    }
+}
+
+/// The port `start` will try first is not taken by another program.
+pub fn free(name: &'static str, port: u16, flag: &str) -> Check {
+    match TcpListener::bind((Ipv4Addr::LOCALHOST, port)) {
+        Ok(_) => Check::pass(name, format!("{port} is free")),
+        Err(_) => Check::warn(
+            name,
+            format!("{port} is in use by another program, `start` will pick the next free port"),
+            format!("Stop that program, or choose a port with {flag} or in proxy-rs.toml."),
+        ),
+    }
+}
+
+/// The controller answers, `None` if the core is not running.
+pub async fn controller(controller: Option<Result<Controller>>) -> Check {
+    const NAME: &str = "Controller";
+    let Some(controller) = controller else {
+        return Check::skip(NAME, "Not running");
+    };
+    match controller {
+        Ok(controller) => match controller.version().await {
+            Ok(version) => Check::pass(NAME, format!("reachable, {version}")),
+            Err(e) => Check::fail(
+                NAME,
+                format!("The external controller did not answer: {e}"),
+                "Check the controller address and secret in the config, or restart with `proxy start`.",
+            ),
+        },
+        Err(e) => Check::fail(
+            NAME,
+            e.to_string(),
+            "Restart with `proxy start`.",
+        ),
+    }
+}
+
+/// Every geodata file is in the config dir, as it was downloaded.
+pub fn geodata(files: &[(&str, Integrity)]) -> Check {
+    const NAME: &str = "Geodata";
+    let with = |wanted: Integrity| -> Vec<&str> {
+        files
+            .iter()
+            .filter(|(_, integrity)| *integrity == wanted)
+            .map(|(name, _)| *name)
+            .collect()
+    };
+    let missing = with(Integrity::Missing);
+    if !missing.is_empty() {
+        return Check::warn(
+            NAME,
+            format!("Missing {}", missing.join(", ")),
+            "Run `proxy geo update`, otherwise `proxy start` downloads them.",
+        );
+    }
+    let changed = with(Integrity::Changed);
+    if !changed.is_empty() {
+        return Check::warn(
+            NAME,
+            format!("{} changed since the download", changed.join(", ")),
+            "Run `proxy geo update`, otherwise `proxy start` downloads them again.",
+        );
+    }
+    let present: Vec<&str> = files.iter().map(|(name, _)| *name).collect();
+    Check::pass(NAME, present.join(", "))
+}
+
+/// At least one GitHub mirror can be downloaded from.
+pub async fn mirrors(client: &Client, mirrors: &[String]) -> Check {
+    const NAME: &str = "GitHub mirrors";
+    let fix = "Check the network, add a mirror to github-mirrors in proxy-rs.toml, set a proxy with --download-proxy or HTTPS_PROXY, or download through a running proxy with --via-proxy.";
+    let results = measure_github_proxies(client, mirrors).await;
+    let fastest = results
+        .iter()
+        .filter_map(|(mirror, elapsed)| elapsed.map(|t| (mirror, t)))
+        .min_by_key(|(_, t)| *t);
+    let reachable = results.iter().filter(|(_, t)| t.is_some()).count();
+    match fastest {
+        Some((mirror, elapsed)) => Check::pass(
+            NAME,
+            format!(
+                "{reachable} of {} reachable, fastest: {} ({} ms)",
+                results.len(),
+                proxy_display_name(mirror),
+                elapsed.as_millis()
+            ),
+        ),
+        None => Check::fail(NAME, "No GitHub mirror is reachable", fix),
+    }
+}
+
+/// The TUN device exists and the core may create it, needed for `start --tun`.
+pub fn tun(core_path: &Path) -> Check {
+    const NAME: &str = "TUN";
+    if cfg!(target_os = "linux") && !Path::new("/dev/net/tun").exists() {
+        return Check::warn(
+            NAME,
+            "/dev/net/tun does not exist, --tun will not work",
+            "Load the module with `sudo modprobe tun`, in a container pass --device /dev/net/tun.",
+        );
+    }
+    if !has_tun_privileges(core_path) {
+        return Check::warn(
+            NAME,
+            "The core lacks the privileges to create the TUN device, --tun will not work",
+            tun_privileges_fix(core_path),
+        );
+    }
+    Check::pass(NAME, "The core can create the TUN device")
+}
+
+/// Prints one line per check and the fix below each problem. Returns the
+/// number of failed checks.
+pub fn report(checks: &[Check]) -> usize {
+    println!();
+    print!("{}", to_text(checks));
+    checks
+        .iter()
+        .filter(|check| matches!(check.outcome, Outcome::Fail { .. }))
+        .count()
+}
+
+/// One line per check and the fix below each problem, as [`report`] prints them.
+pub fn to_text(checks: &[Check]) -> String {
+    let width = checks.iter().map(|c| c.name.len()).max().unwrap_or(0);
+    let mut text = String::new();
+    for check in checks {
+        let (label, detail, fix) = match &check.outcome {
+            Outcome::Pass(detail) => ("ok", detail, None),
+            Outcome::Skip(reason) => ("skip", reason, None),
+            Outcome::Warn { problem, fix } => ("warn", problem, Some(fix)),
+            Outcome::Fail { problem, fix } => ("FAIL", problem, Some(fix)),
+        };
+        text.push_str(&format!("{label:<4}  {:<width$}  {detail}\n", check.name));
+        if let Some(fix) = fix {
+            text.push_str(&format!("{:<4}  {:<width$}  fix: {fix}\n", "", ""));
+        }
+    }
+    text
+}
@@ -1,84 +1,317 @@
-use anyhow::{anyhow, Result};
+use crate::errors::{with_tls_failure, Failure};
+use anyhow::{anyhow, Context, Result};
 use flate2::read::GzDecoder;
-use indicatif::{ProgressBar, ProgressStyle};
+use futures_util::StreamExt;
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
 use log::*;
-use reqwest::blocking::Client;
+use reqwest::{Client, ClientBuilder, NoProxy, Proxy};
+use sha2::{Digest, Sha256};
 use std::fs::{self, File};
 use std::io::{self, Read, Write};
-use std::path::Path;
+use std::path::{Component, Path, PathBuf};
+use std::sync::LazyLock;
+use std::time::Duration;
+use tar::Archive;
+use tokio::io::AsyncWriteExt;
 use zip::read::ZipFile;
 use zip::ZipArchive;
 
-pub fn download_file_with_progress(client: &Client, url: &str, path: &Path) -> Result<()> {
+/// Archives that expand to more than this are refused, a core or a WebUI
+/// is a few dozen MiB and anything far larger is likely a zip bomb
+const MAX_EXTRACTED_SIZE: u64 = 1024 * 1024 * 1024;
+
+/// Cleared from the modes in an archive, so the group and others can't write
+/// to the extracted files
+const EXTRACTED_MODE_MASK: u32 = 0o022;
+
+/// Holds the bars of the downloads running at the same time, one line each
+static PROGRESS: LazyLock<MultiProgress> = LazyLock::new(MultiProgress::new);
+
+/// Log target that hides the progress bars while a line is written, so log
+/// lines end up above the bars instead of through them.
+pub struct ProgressLogWriter;
+
+impl Write for ProgressLogWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        PROGRESS.suspend(|| io::stderr().write_all(buf))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        io::stderr().flush()
+    }
+}
+
+/// Client builder for proxy-rs's own downloads. They go through `proxy` when
+/// it is set and otherwise through `HTTPS_PROXY`, `HTTP_PROXY` or `ALL_PROXY`.
+/// Hosts listed in `NO_PROXY` are reached directly either way.
+pub fn client_builder(proxy: Option<&str>) -> Result<ClientBuilder> {
+    let builder = Client::builder();
+    let Some(url) = proxy else {
+        return Ok(builder);
+    };
+    let proxy = Proxy::all(url)
+        .with_context(|| format!("Invalid download proxy {url}"))?
+        .no_proxy(NoProxy::from_env());
+    Ok(builder.proxy(proxy))
+}
+
+/// Downloads `url` to `path`, showing a progress bar named after the file
+/// below those of the other downloads in progress. The file is written to
+/// `<name>.tmp` and renamed into place once complete, so an interrupted
+/// download never leaves a truncated file at `path`.
+pub async fn download_file_with_progress(client: &Client, url: &str, path: &Path) -> Result<()> {
     info!("Downloading from: {url}");
 
-    let mut response = client.get(url).send()?.error_for_status()?;
+    let response = client
+        .get(url)
+        .send()
+        .await
+        .map_err(|e| with_tls_failure(e, url))?
+        .error_for_status()?;
     let total_size = response.content_length().unwrap_or(0);
 
-    let pb = ProgressBar::new(total_size);
+    let pb = PROGRESS.add(ProgressBar::new(total_size));
     pb.set_style(ProgressStyle::default_bar()
-        .template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {bytes}/{total_bytes} ({eta})")?
+        .template("{spinner:.green} {prefix:<16!} [{elapsed_precise}] [{bar:40.cyan/blue}] {bytes}/{total_bytes} ({eta})")?
         .progress_chars("#>-"));
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    pb.set_prefix(name.into_owned());
 
-    let mut file = File::create(path)?;
-    let mut downloaded = 0;
-
-    let mut buffer = [0; 8192];
-    while let Ok(n) = response.read(&mut buffer) {
-        if n == 0 {
-            break;
+    let temp_path = temp_download_path(path);
+    let result = async {
+        let mut file = tokio::fs::File::create(&temp_path).await?;
+        let mut stream = response.bytes_stream();
+        while let Some(chunk) = stream.next().await {
+            let chunk = chunk?;
+            file.write_all(&chunk).await?;
+            pb.inc(chunk.len() as u64);
         }
-        file.write_all(&buffer[..n])?;
-        downloaded += n as u64;
-        pb.set_position(downloaded);
+        file.flush().await?;
+        file.sync_all().await?;
+        drop(file);
+        tokio::fs::rename(&temp_path, path).await?;
+        anyhow::Ok(())
+    }
+    .await;
+    pb.finish_and_clear();
+    PROGRESS.remove(&pb);
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
     }
+    result?;
 
-    pb.finish_with_message("Downloaded");
     info!("Downloaded to {}", path.display());
     Ok(())
 }
 
+/// `path` with `.tmp` appended to its file name.
+fn temp_download_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".tmp");
+    path.with_file_name(name)
+}
+
+/// Like [`download_file_with_progress`], but tries up to `attempts` times,
+/// waiting 1s, 2s, 4s... (at most 30s) between attempts.
+pub async fn download_with_retries(
+    client: &Client,
+    url: &str,
+    path: &Path,
+    attempts: u32,
+) -> Result<()> {
+    let mut delay = Duration::from_secs(1);
+    let mut attempt = 1;
+    loop {
+        match download_file_with_progress(client, url, path).await {
+            Ok(()) => return Ok(()),
+            Err(e) if attempt >= attempts => return Err(e),
+            Err(e) => {
+                warn!("Download failed (attempt {attempt}/{attempts}): {e}, retrying in {delay:?}");
+                tokio::time::sleep(delay).await;
+                delay = (delay * 2).min(Duration::from_secs(30));
+                attempt += 1;
+            }
+        }
+    }
+}
+
+/// Writes `reader` to a new file at `path`, taking the bytes from `budget` and
+/// failing once more than [`MAX_EXTRACTED_SIZE`] have been extracted in total.
+/// The partly written file is removed on failure.
+fn extract_to_file(reader: &mut impl Read, path: &Path, budget: &mut u64) -> Result<()> {
+    let result = File::create(path)
+        .and_then(|mut file| io::copy(&mut reader.take(*budget + 1), &mut file))
+        .map_err(anyhow::Error::from)
+        .and_then(|copied| {
+            *budget = budget.checked_sub(copied).ok_or_else(too_large)?;
+            Ok(())
+        });
+    if result.is_err() {
+        let _ = fs::remove_file(path);
+    }
+    result
+}
+
+fn too_large() -> anyhow::Error {
+    anyhow!(
+        "The archive expands to more than {} MiB, refusing to extract it",
+        MAX_EXTRACTED_SIZE / 1024 / 1024
+    )
+}
+
+/// Extracts the files and directories of a zip archive into `dest_dir`,
+/// keeping their executable bits. Symlinks and entries that would land
+/// outside `dest_dir` are skipped.
 pub fn unzip_file(zip_path: &Path, dest_dir: &Path) -> Result<()> {
     info!("Unzipping...");
     let file = File::open(zip_path)?;
-    let mut archive = ZipArchive::new(file)?;
+    let mut archive = ZipArchive::new(file).context(corrupt(zip_path))?;
+    // Refuse honest zip bombs before writing anything, lying sizes are caught while extracting
+    let mut declared = 0u64;
+    for i in 0..archive.len() {
+        declared = declared.saturating_add(archive.by_index_raw(i)?.size());
+    }
+    if declared > MAX_EXTRACTED_SIZE {
+        return Err(too_large());
+    }
+    let mut budget = MAX_EXTRACTED_SIZE;
 
     for i in 0..archive.len() {
         let mut file = archive.by_index(i)?;
-        let outpath = match file.enclosed_name() {
-            Some(path) => dest_dir.join(path),
-            None => continue,
+        let Some(relative) = file.enclosed_name().and_then(|path| strip_path(&path, 0)) else {
+            warn!("Skipping {}, it points outside the archive", file.name());
+            continue;
         };
+        if file.is_symlink() {
+            warn!("Skipping the symlink {}", file.name());
+            continue;
+        }
+        let outpath = dest_dir.join(relative);
 
-        if (*file.name()).ends_with('/') {
+        if file.is_dir() {
             fs::create_dir_all(&outpath)?;
-        } else {
-            if let Some(p) = outpath.parent() {
-                if !p.exists() {
-                    fs::create_dir_all(p)?;
-                }
-            }
-            let mut outfile = File::create(&outpath)?;
-            io::copy(&mut file, &mut outfile)?;
+            continue;
+        }
+        if let Some(p) = outpath.parent() {
+            fs::create_dir_all(p)?;
+        }
+        extract_to_file(&mut file, &outpath, &mut budget)?;
+        #[cfg(unix)]
+        if let Some(mode) = file.unix_mode() {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = mode & 0o777 & !EXTRACTED_MODE_MASK;
+            fs::set_permissions(&outpath, fs::Permissions::from_mode(mode))?;
         }
     }
     info!("Unzipped to {}", dest_dir.display());
     Ok(())
 }
 
+/// Decompresses a single gzip-compressed file.
 pub fn decompress_gz(gz_path: &Path, dest_path: &Path) -> Result<()> {
     info!("Decompressing gz...");
     let mut gz_file = GzDecoder::new(File::open(gz_path)?);
-    let mut dest_file = File::create(dest_path)?;
-    io::copy(&mut gz_file, &mut dest_file)?;
+    let mut budget = MAX_EXTRACTED_SIZE;
+    extract_to_file(&mut gz_file, dest_path, &mut budget)
+        .map_err(|e| corrupt_if_invalid(e, gz_path))?;
     info!("Decompressed to {}", dest_path.display());
     Ok(())
 }
 
+/// Extracts a gzip-compressed tarball into `dest_dir`, see [`untar`].
+pub fn decompress_tar_gz(
+    tar_gz_path: &Path,
+    dest_dir: &Path,
+    strip_components: usize,
+) -> Result<()> {
+    info!("Decompressing tar.gz...");
+    untar(
+        GzDecoder::new(File::open(tar_gz_path)?),
+        dest_dir,
+        strip_components,
+    )
+    .map_err(|e| corrupt_if_invalid(e, tar_gz_path))
+}
+
+fn corrupt(path: &Path) -> Failure {
+    Failure::CorruptArchive {
+        path: path.to_path_buf(),
+    }
+}
+
+/// Marks `error` as a [`Failure::CorruptArchive`] if the data could not be
+/// decompressed, rather than written.
+fn corrupt_if_invalid(error: anyhow::Error, path: &Path) -> anyhow::Error {
+    let invalid = error.chain().any(|e| {
+        e.downcast_ref::<io::Error>().is_some_and(|e| {
+            matches!(
+                e.kind(),
+                io::ErrorKind::InvalidData
+                    | io::ErrorKind::InvalidInput
+                    | io::ErrorKind::UnexpectedEof
+            )
+        })
+    });
+    if invalid {
+        error.context(corrupt(path))
+    } else {
+        error
+    }
+}
+
+/// Extracts a tar stream into `dest_dir`, dropping the first `strip_components`
+/// path components of every entry like `tar --strip-components`, e.g. 1 for
+/// archives that wrap everything in a `name-version/` directory. Only files
+/// and directories are extracted, links and special files are skipped.
+pub fn untar<R: Read>(reader: R, dest_dir: &Path, strip_components: usize) -> Result<()> {
+    let mut archive = Archive::new(reader);
+    archive.set_mask(EXTRACTED_MODE_MASK);
+    let mut budget = MAX_EXTRACTED_SIZE;
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let path = entry.path()?.into_owned();
+        let Some(relative) = strip_path(&path, strip_components) else {
+            continue;
+        };
+        let kind = entry.header().entry_type();
+        if !kind.is_file() && !kind.is_dir() {
+            warn!("Skipping {}, it is not a file or directory", path.display());
+            continue;
+        }
+        // Entries are written as big as their header says
+        budget = budget.checked_sub(entry.size()).ok_or_else(too_large)?;
+        let outpath = dest_dir.join(relative);
+        if let Some(p) = outpath.parent() {
+            fs::create_dir_all(p)?;
+        }
+        entry.unpack(&outpath)?;
+    }
+    info!("Extracted to {}", dest_dir.display());
+    Ok(())
+}
+
+/// `path` without its first `n` components, `None` if nothing is left or
+/// it would escape the destination directory.
+fn strip_path(path: &Path, n: usize) -> Option<PathBuf> {
+    let mut components = path.components().filter(|c| *c != Component::CurDir);
+    for _ in 0..n {
+        components.next()?;
+    }
+    let mut stripped = PathBuf::new();
+    for component in components {
+        match component {
+            Component::Normal(part) => stripped.push(part),
+            _ => return None,
+        }
+    }
+    (!stripped.as_os_str().is_empty()).then_some(stripped)
+}
+
+/// Extracts a zip archive that contains exactly one file to `dest_path`.
 pub fn decompress_zip(zip_path: &Path, dest_path: &Path) -> Result<()> {
     info!("Decompressing zip...");
-    let mut zip_archive = ZipArchive::new(File::open(zip_path)?)?;
-    let mut dest_file = File::create(dest_path)?;
+    let mut zip_archive = ZipArchive::new(File::open(zip_path)?).context(corrupt(zip_path))?;
 
     let mut zip_file: ZipFile = match zip_archive.len() {
         1 => zip_archive
@@ -88,7 +321,183 @@ pub fn decompress_zip(zip_path: &Path, dest_path: &Path) -> Result<()> {
         n => Err(anyhow!("Zip file contains multiple files ({})", n)),
     }?;
 
-    io::copy(&mut zip_file, &mut dest_file)?;
+    let mut budget = MAX_EXTRACTED_SIZE;
+    extract_to_file(&mut zip_file, dest_path, &mut budget)?;
     info!("Decompressed to {}", dest_path.display());
     Ok(())
 }
+
+/// Lowercase hex SHA256 digest of a file.
+pub fn sha256_file(path: &Path) -> Result<String> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    io::copy(&mut file, &mut hasher)?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Fails if the SHA256 digest of `path` is not `expected`.
+pub fn verify_sha256(path: &Path, expected: &str) -> Result<()> {
+    info!("Verifying checksum...");
+    let actual = sha256_file(path)?;
+    if !actual.eq_ignore_ascii_case(expected.trim()) {
+        return Err(anyhow!(
+            "Checksum mismatch for {}: expected {}, got {}",
+            path.display(),
+            expected.trim(),
+            actual
+        ));
+    }
+    info!("Checksum OK: {actual}");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use tar::{Builder, EntryType, Header};
+    use zip::write::SimpleFileOptions;
+    use zip::ZipWriter;
+
+    /// An empty directory named `name` under the temp dir, unique per test.
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("proxy-rs-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// A tar entry named `name` as it is, `..` and leading `/` included.
+    fn raw_header(name: &str, kind: EntryType, size: u64) -> Header {
+        let mut header = Header::new_gnu();
+        header.as_gnu_mut().unwrap().name[..name.len()].copy_from_slice(name.as_bytes());
+        header.set_entry_type(kind);
+        header.set_size(size);
+        header.set_mode(0o644);
+        header.set_cksum();
+        header
+    }
+
+    fn tar(entries: &[(&str, EntryType, &[u8])]) -> Vec<u8> {
+        let mut builder = Builder::new(Vec::new());
+        for (name, kind, data) in entries {
+            let header = raw_header(name, *kind, data.len() as u64);
+            builder.append(&header, *data).unwrap();
+        }
+        builder.into_inner().unwrap()
+    }
+
+    #[test]
+    fn strips_leading_components() {
+        assert_eq!(
+            strip_path(Path::new("a/b/c"), 1),
+            Some(PathBuf::from("b/c"))
+        );
+        assert_eq!(strip_path(Path::new("./a/b"), 1), Some(PathBuf::from("b")));
+        assert_eq!(strip_path(Path::new("a/b"), 0), Some(PathBuf::from("a/b")));
+        assert_eq!(strip_path(Path::new("a"), 1), None);
+        assert_eq!(strip_path(Path::new("a/"), 1), None);
+    }
+
+    #[test]
+    fn strip_path_rejects_escapes() {
+        assert_eq!(strip_path(Path::new("../evil"), 0), None);
+        assert_eq!(strip_path(Path::new("a/../../evil"), 1), None);
+        assert_eq!(strip_path(Path::new("/etc/passwd"), 0), None);
+        // The root is stripped like any component, what is left stays inside
+        assert_eq!(
+            strip_path(Path::new("/etc/passwd"), 1),
+            Some(PathBuf::from("etc/passwd"))
+        );
+    }
+
+    #[test]
+    fn untar_skips_escaping_entries() {
+        let dir = temp_dir("untar-escape");
+        let dest = dir.join("dest");
+        let archive = tar(&[
+            ("ok.txt", EntryType::Regular, b"ok"),
+            ("../evil.txt", EntryType::Regular, b"evil"),
+            ("/tmp/proxy-rs-absolute.txt", EntryType::Regular, b"evil"),
+            ("sub/../../evil2.txt", EntryType::Regular, b"evil"),
+        ]);
+        untar(Cursor::new(archive), &dest, 0).unwrap();
+        assert_eq!(fs::read(dest.join("ok.txt")).unwrap(), b"ok");
+        assert!(!dir.join("evil.txt").exists());
+        assert!(!dir.join("evil2.txt").exists());
+        assert!(!Path::new("/tmp/proxy-rs-absolute.txt").exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn untar_skips_links() {
+        let dir = temp_dir("untar-links");
+        let dest = dir.join("dest");
+        let mut builder = Builder::new(Vec::new());
+        let mut link = raw_header("escape", EntryType::Symlink, 0);
+        link.set_link_name("..").unwrap();
+        link.set_cksum();
+        builder.append(&link, io::empty()).unwrap();
+        let file = raw_header("escape/evil.txt", EntryType::Regular, 4);
+        builder.append(&file, &b"evil"[..]).unwrap();
+        let archive = builder.into_inner().unwrap();
+
+        untar(Cursor::new(archive), &dest, 0).unwrap();
+        assert!(!dest.join("escape").is_symlink());
+        assert!(!dir.join("evil.txt").exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn untar_refuses_oversized_entries() {
+        let dir = temp_dir("untar-size");
+        // Only the header, the size check comes before the data is read
+        let mut archive = raw_header("big", EntryType::Regular, MAX_EXTRACTED_SIZE + 1)
+            .as_bytes()
+            .to_vec();
+        archive.extend([0; 1024]);
+        let error = untar(Cursor::new(archive), &dir, 0).unwrap_err();
+        assert!(error.to_string().contains("refusing to extract"), "{error}");
+        assert!(!dir.join("big").exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn untar_strips_components() {
+        let dir = temp_dir("untar-strip");
+        let archive = tar(&[
+            ("pkg-1.0", EntryType::Directory, b""),
+            ("pkg-1.0/bin/core", EntryType::Regular, b"core"),
+        ]);
+        untar(Cursor::new(archive), &dir, 1).unwrap();
+        assert_eq!(fs::read(dir.join("bin/core")).unwrap(), b"core");
+        assert!(!dir.join("pkg-1.0").exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn unzip_skips_escaping_entries_and_symlinks() {
+        let dir = temp_dir("unzip");
+        let zip_path = dir.join("archive.zip");
+        let mut zip = ZipWriter::new(File::create(&zip_path).unwrap());
+        let options = SimpleFileOptions::default();
+        for (name, data) in [
+            ("ui/index.html", &b"ok"[..]),
+            ("../evil.txt", b"evil"),
+            ("/tmp/proxy-rs-absolute.txt", b"evil"),
+        ] {
+            zip.start_file(name, options).unwrap();
+            zip.write_all(data).unwrap();
+        }
+        zip.add_symlink("ui/link", "../..", options).unwrap();
+        zip.finish().unwrap();
+
+        let dest = dir.join("dest");
+        unzip_file(&zip_path, &dest).unwrap();
+        assert_eq!(fs::read(dest.join("ui/index.html")).unwrap(), b"ok");
+        assert!(!dir.join("evil.txt").exists());
+        assert!(!Path::new("/tmp/proxy-rs-absolute.txt").exists());
+        assert!(!dest.join("ui/link").exists() && !dest.join("ui/link").is_symlink());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,136 @@
+//! Common failures with a suggested fix, see [`hint`].
+
+use crate::utils::tun_privileges_fix;
+use reqwest::StatusCode;
+use std::error::Error as _;
+use std::io;
+use std::path::PathBuf;
+
+/// A failure with a known cause. They are wrapped into the anyhow chain where
+/// they are detected, [`hint`] finds them again to tell how to fix them.
+#[derive(Debug, thiserror::Error)]
+pub enum Failure {
+    #[error("Port {port} is already in use")]
+    PortInUse { port: u16 },
+    #[error("The subscription provider refused the download ({status})")]
+    SubscriptionRefused { status: StatusCode },
+    #[error("The subscription provider is limiting downloads ({status})")]
+    SubscriptionRateLimited { status: StatusCode },
+    #[error("The TLS connection to {host} failed")]
+    Tls { host: String },
+    #[error("The core lacks the privileges to create the TUN device")]
+    TunPermission { core_path: PathBuf },
+    #[error("{} is not a valid archive", path.display())]
+    CorruptArchive { path: PathBuf },
+}
+
+impl Failure {
+    /// What the user can do about it.
+    pub fn hint(&self) -> String {
+        match self {
+            Failure::PortInUse { port } => format!(
+                "Stop the program listening on {port}, or choose another port with --mixed-port or mixed-port in proxy-rs.toml. `proxy doctor` shows which ports are taken."
+            ),
+            Failure::SubscriptionRefused { .. } => {
+                "Check that the subscription has not expired and its URL is complete, providers refuse stale or mistyped tokens. Copy it from the provider again and pass it to `proxy start`.".to_string()
+            }
+            Failure::SubscriptionRateLimited { .. } => {
+                "Wait a few minutes before refreshing again, and space out refresh-subscription in [schedule] if it runs often.".to_string()
+            }
+            Failure::Tls { host } => format!(
+                "{host} may be blocked or intercepted. Pass --reselect-mirror to pick another GitHub mirror, add one to github-mirrors in proxy-rs.toml, and check that the system clock is right."
+            ),
+            Failure::TunPermission { core_path } => tun_privileges_fix(core_path),
+            Failure::CorruptArchive { path } => format!(
+                "The download was probably cut short or altered by a mirror. Delete {} and run the command again, with --reselect-mirror to use another mirror.",
+                path.display()
+            ),
+        }
+    }
+}
+
+/// The fix for the first known failure in the chain of `error`, also
+/// recognizing raw errors that are common enough to explain.
+pub fn hint(error: &anyhow::Error) -> Option<String> {
+    // A failure added as context is only found by anyhow's own downcast
+    let failure = error
+        .downcast_ref::<Failure>()
+        .or_else(|| error.chain().find_map(|e| e.downcast_ref::<Failure>()));
+    if let Some(failure) = failure {
+        return Some(failure.hint());
+    }
+    error.chain().find_map(|e| {
+        if let Some(e) = e.downcast_ref::<reqwest::Error>() {
+            if e.is_timeout() || e.is_connect() {
+                return Some("The server could not be reached. Check the network, or set download-proxy in proxy-rs.toml if a proxy is needed.".to_string());
+            }
+            if e.is_decode() {
+                return Some("The server answered with something unexpected, e.g. a login page of the network or an error page of a mirror. Try again, with --reselect-mirror for downloads from GitHub.".to_string());
+            }
+        }
+        let e = e.downcast_ref::<io::Error>()?;
+        match e.kind() {
+            io::ErrorKind::AddrInUse => Some(
+                "Another program is using the port, stop it or choose another port.".to_string(),
+            ),
+            io::ErrorKind::PermissionDenied => Some(
+                "Check the owner and permissions of the file, it may have been created by sudo."
+                    .to_string(),
+            ),
+            _ => None,
+        }
+    })
+}
+
+/// Whether `error` comes from the TLS handshake or certificate validation.
+pub fn is_tls_error(error: &reqwest::Error) -> bool {
+    let mut source = error.source();
+    while let Some(e) = source {
+        let message = e.to_string().to_lowercase();
+        if ["certificate", "tls", "ssl", "handshake"]
+            .iter()
+            .any(|word| message.contains(word))
+        {
+            return true;
+        }
+        source = e.source();
+    }
+    false
+}
+
+/// `error` with a [`Failure::Tls`] for the host of `url` in front if it is a
+/// TLS failure.
+pub fn with_tls_failure(error: reqwest::Error, url: &str) -> anyhow::Error {
+    if !is_tls_error(&error) {
+        return error.into();
+    }
+    let host = reqwest::Url::parse(url)
+        .ok()
+        .and_then(|url| url.host_str().map(String::from))
+        .unwrap_or_else(|| url.to_string());
+    anyhow::Error::new(error).context(Failure::Tls { host })
+}
+
+/// Finds the failures the core reports in its log when it exits during startup.
+pub fn from_core_log(log: &str, core_path: PathBuf) -> Option<Failure> {
+    for line in log.lines() {
+        let lower = line.to_lowercase();
+        if lower.contains("address already in use") {
+            // e.g. `listen tcp 127.0.0.1:7890: bind: address already in use`
+            let port = lower
+                .split(": bind")
+                .next()
+                .and_then(|listen| listen.rsplit(':').next())
+                .and_then(|port| port.trim().parse().ok());
+            if let Some(port) = port {
+                return Some(Failure::PortInUse { port });
+            }
+        }
+        if lower.contains("tun")
+            && (lower.contains("operation not permitted") || lower.contains("permission denied"))
+        {
+            return Some(Failure::TunPermission { core_path });
+        }
+    }
+    None
+}
@@ -0,0 +1,637 @@
+//! Reads the geoip databases Mihomo matches `GEOIP` and `IP-ASN` rules with:
+//! MaxMind DB files like country.mmdb and ASN.mmdb, and V2Ray's geoip.dat in
+//! geodata mode. Also reads geosite.dat for `GEOSITE` rules.
+
+use anyhow::{anyhow, Context, Result};
+use regex::Regex;
+use serde_json::{Map, Value};
+use std::cell::OnceCell;
+use std::fs;
+use std::net::IpAddr;
+use std::path::Path;
+
+const METADATA_MARKER: &[u8] = b"\xAB\xCD\xEFMaxMind.com";
+/// Between the search tree and the data section
+const DATA_SECTION_SEPARATOR: usize = 16;
+/// Maps, arrays and pointers nested deeper than this are a corrupt file, e.g.
+/// a pointer cycle that would recurse forever
+const MAX_DEPTH: usize = 64;
+
+/// A MaxMind DB file, see <https://maxmind.github.io/MaxMind-DB/>.
+pub struct Mmdb {
+    buffer: Vec<u8>,
+    node_count: usize,
+    record_size: usize,
+    ip_version: u64,
+    data_start: usize,
+}
+
+impl Mmdb {
+    pub fn open(path: &Path) -> Result<Self> {
+        let buffer =
+            fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+        let marker = buffer
+            .windows(METADATA_MARKER.len())
+            .rposition(|window| window == METADATA_MARKER)
+            .ok_or_else(|| anyhow!("{} is not a MaxMind DB file", path.display()))?;
+        let metadata_start = marker + METADATA_MARKER.len();
+        let metadata = Decoder {
+            buffer: &buffer[metadata_start..],
+        }
+        .decode(0)?
+        .0;
+        let field = |name: &str| {
+            metadata
+                .get(name)
+                .and_then(Value::as_u64)
+                .ok_or_else(|| anyhow!("{} has no {name} in its metadata", path.display()))
+        };
+        let node_count = field("node_count")? as usize;
+        let record_size = field("record_size")? as usize;
+        if ![24, 28, 32].contains(&record_size) {
+            return Err(anyhow!("Unsupported record size {record_size}"));
+        }
+        let ip_version = field("ip_version")?;
+        let search_tree_size = (record_size * 2 / 8)
+            .checked_mul(node_count)
+            .ok_or_else(|| anyhow!("Invalid node count {node_count}"))?;
+        Ok(Self {
+            node_count,
+            record_size,
+            ip_version,
+            data_start: search_tree_size + DATA_SECTION_SEPARATOR,
+            buffer,
+        })
+    }
+
+    /// The record for `ip`, `None` if the database has none.
+    pub fn lookup(&self, ip: IpAddr) -> Result<Option<Value>> {
+        let (bits, mut node) = match ip {
+            IpAddr::V4(ip) if self.ip_version == 6 => {
+                // IPv4 addresses live under ::/96 of an IPv6 tree
+                let mut node = 0;
+                for _ in 0..96 {
+                    if node >= self.node_count {
+                        break;
+                    }
+                    node = self.record(node, false)?;
+                }
+                (u128::from(u32::from(ip)) << 96, node)
+            }
+            IpAddr::V4(ip) => (u128::from(u32::from(ip)) << 96, 0),
+            IpAddr::V6(_) if self.ip_version == 4 => return Ok(None),
+            IpAddr::V6(ip) => (u128::from(ip), 0),
+        };
+        let bit_count = if matches!(ip, IpAddr::V4(_)) { 32 } else { 128 };
+        for i in 0..bit_count {
+            if node >= self.node_count {
+                break;
+            }
+            let right = bits & (1 << (127 - i)) != 0;
+            node = self.record(node, right)?;
+        }
+        if node == self.node_count {
+            return Ok(None);
+        }
+        if node < self.node_count {
+            return Err(anyhow!("Invalid search tree"));
+        }
+        let offset = (node - self.node_count)
+            .checked_sub(DATA_SECTION_SEPARATOR)
+            .ok_or_else(|| anyhow!("Invalid search tree"))?;
+        let decoder = Decoder {
+            buffer: self
+                .buffer
+                .get(self.data_start..)
+                .ok_or_else(|| anyhow!("Truncated database"))?,
+        };
+        Ok(Some(decoder.decode(offset)?.0))
+    }
+
+    fn record(&self, node: usize, right: bool) -> Result<usize> {
+        let size = self.record_size * 2 / 8;
+        let bytes = self
+            .buffer
+            .get(node * size..(node + 1) * size)
+            .ok_or_else(|| anyhow!("Truncated search tree"))?;
+        let be = |bytes: &[u8]| bytes.iter().fold(0, |n, &b| n << 8 | b as usize);
+        Ok(match (self.record_size, right) {
+            (24, false) => be(&bytes[0..3]),
+            (24, true) => be(&bytes[3..6]),
+            (28, false) => ((bytes[3] as usize & 0xF0) << 20) | be(&bytes[0..3]),
+            (28, true) => ((bytes[3] as usize & 0x0F) << 24) | be(&bytes[4..7]),
+            (_, false) => be(&bytes[0..4]),
+            (_, true) => be(&bytes[4..8]),
+        })
+    }
+}
+
+/// Decodes the data section, pointers being relative to the start of `buffer`.
+struct Decoder<'a> {
+    buffer: &'a [u8],
+}
+
+impl Decoder<'_> {
+    /// The value at `offset` and the offset after it.
+    fn decode(&self, offset: usize) -> Result<(Value, usize)> {
+        self.decode_nested(offset, 0)
+    }
+
+    fn decode_nested(&self, offset: usize, depth: usize) -> Result<(Value, usize)> {
+        if depth > MAX_DEPTH {
+            return Err(anyhow!("Data section nested too deeply"));
+        }
+        let byte = |at: usize| {
+            self.buffer
+                .get(at)
+                .copied()
+                .ok_or_else(|| anyhow!("Truncated data section"))
+        };
+        let bytes = |at: usize, len: usize| {
+            self.buffer
+                .get(at..at.saturating_add(len))
+                .ok_or_else(|| anyhow!("Truncated data section"))
+        };
+        let be = |bytes: &[u8]| bytes.iter().fold(0u128, |n, &b| n << 8 | b as u128);
+
+        let control = byte(offset)?;
+        let mut at = offset + 1;
+        let mut kind = control >> 5;
+        if kind == 1 {
+            let small = (control & 0x07) as usize;
+            let (pointer, len) = match (control >> 3) & 0x03 {
+                0 => (small << 8 | be(bytes(at, 1)?) as usize, 1),
+                1 => ((small << 16 | be(bytes(at, 2)?) as usize) + 2048, 2),
+                2 => ((small << 24 | be(bytes(at, 3)?) as usize) + 526_336, 3),
+                _ => (be(bytes(at, 4)?) as usize, 4),
+            };
+            return Ok((self.decode_nested(pointer, depth + 1)?.0, at + len));
+        }
+        if kind == 0 {
+            kind = 7 + byte(at)?;
+            at += 1;
+        }
+        let mut size = (control & 0x1F) as usize;
+        match size {
+            29 => {
+                size = 29 + byte(at)? as usize;
+                at += 1;
+            }
+            30 => {
+                size = 285 + be(bytes(at, 2)?) as usize;
+                at += 2;
+            }
+            31 => {
+                size = 65_821 + be(bytes(at, 3)?) as usize;
+                at += 3;
+            }
+            _ => {}
+        }
+
+        Ok(match kind {
+            2 => (
+                Value::String(String::from_utf8_lossy(bytes(at, size)?).into_owned()),
+                at + size,
+            ),
+            3 => {
+                let value = f64::from_be_bytes(bytes(at, 8)?.try_into()?);
+                (Value::from(value), at + 8)
+            }
+            4 => (Value::from(bytes(at, size)?.to_vec()), at + size),
+            // uint16, uint32, int32, uint64 and uint128
+            5 | 6 | 8 | 9 | 10 => (Value::from(be(bytes(at, size)?) as u64), at + size),
+            7 => {
+                let mut map = Map::new();
+                for _ in 0..size {
+                    let (key, next) = self.decode_nested(at, depth + 1)?;
+                    let (value, next) = self.decode_nested(next, depth + 1)?;
+                    map.insert(key.as_str().unwrap_or_default().to_string(), value);
+                    at = next;
+                }
+                (Value::Object(map), at)
+            }
+            11 => {
+                // Each value takes at least a byte
+                let mut array = Vec::with_capacity(size.min(self.buffer.len()));
+                for _ in 0..size {
+                    let (value, next) = self.decode_nested(at, depth + 1)?;
+                    array.push(value);
+                    at = next;
+                }
+                (Value::Array(array), at)
+            }
+            14 => (Value::Bool(size != 0), at),
+            15 => {
+                let value = f32::from_be_bytes(bytes(at, 4)?.try_into()?);
+                (Value::from(value), at + 4)
+            }
+            _ => return Err(anyhow!("Unsupported data type {kind}")),
+        })
+    }
+}
+
+/// Country codes in a country record: a code or a list of them in MetaCubeX's
+/// own databases, `country.iso_code` in MaxMind's.
+pub fn country_codes(record: &Value) -> Vec<String> {
+    match record {
+        Value::String(code) => vec![code.to_uppercase()],
+        Value::Array(codes) => codes
+            .iter()
+            .filter_map(Value::as_str)
+            .map(str::to_uppercase)
+            .collect(),
+        _ => record
+            .pointer("/country/iso_code")
+            .or_else(|| record.pointer("/registered_country/iso_code"))
+            .and_then(Value::as_str)
+            .map(|code| vec![code.to_uppercase()])
+            .unwrap_or_default(),
+    }
+}
+
+/// Whether Mihomo's `GEOIP,LAN` matches `ip`: private, loopback, link-local,
+/// multicast and unspecified addresses.
+pub fn is_lan(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            ip.is_private()
+                || ip.is_loopback()
+                || ip.is_link_local()
+                || ip.is_multicast()
+                || ip.is_unspecified()
+        }
+        IpAddr::V6(ip) => {
+            ip.is_unique_local()
+                || ip.is_loopback()
+                || ip.is_unicast_link_local()
+                || ip.is_multicast()
+                || ip.is_unspecified()
+        }
+    }
+}
+
+/// (number, organization) in an ASN record.
+pub fn asn(record: &Value) -> Option<(u64, String)> {
+    let number = record.get("autonomous_system_number")?.as_u64()?;
+    let organization = record
+        .get("autonomous_system_organization")
+        .and_then(Value::as_str)
+        .unwrap_or_default();
+    Some((number, organization.to_string()))
+}
+
+/// Country codes whose ranges in V2Ray's geoip.dat contain `ip`.
+pub fn dat_country_codes(path: &Path, ip: IpAddr) -> Result<Vec<String>> {
+    let buffer = fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let mut codes = Vec::new();
+    // GeoIPList { repeated GeoIP entry = 1 }
+    for (field, entry) in fields(&buffer)? {
+        let (1, Field::Bytes(entry)) = (field, entry) else {
+            continue;
+        };
+        // GeoIP { string country_code = 1; repeated CIDR cidr = 2; bool reverse_match = 3 }
+        let mut code = String::new();
+        let mut contained = false;
+        let mut reverse = false;
+        for (field, value) in fields(entry)? {
+            match (field, value) {
+                (1, Field::Bytes(bytes)) => code = String::from_utf8_lossy(bytes).to_uppercase(),
+                (2, Field::Bytes(cidr)) => contained |= cidr_contains(cidr, ip)?,
+                (3, Field::Varint(value)) => reverse = value != 0,
+                _ => {}
+            }
+        }
+        if contained != reverse {
+            codes.push(code);
+        }
+    }
+    Ok(codes)
+}
+
+/// V2Ray's geosite.dat, the domain lists of `GEOSITE` rules.
+pub struct GeoSite {
+    sites: Vec<Site>,
+}
+
+struct Site {
+    code: String,
+    domains: Vec<SiteDomain>,
+}
+
+struct SiteDomain {
+    pattern: DomainPattern,
+    attributes: Vec<String>,
+}
+
+enum DomainPattern {
+    Keyword(String),
+    /// Compiled the first time a rule uses its list, `None` if Rust can't
+    Regex(String, OnceCell<Option<Regex>>),
+    /// The domain and its subdomains
+    Suffix(String),
+    Full(String),
+}
+
+impl GeoSite {
+    pub fn open(path: &Path) -> Result<Self> {
+        let buffer =
+            fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+        let mut sites = Vec::new();
+        // GeoSiteList { repeated GeoSite entry = 1 }
+        for (field, entry) in fields(&buffer)? {
+            let (1, Field::Bytes(entry)) = (field, entry) else {
+                continue;
+            };
+            // GeoSite { string country_code = 1; repeated Domain domain = 2 }
+            let mut site = Site {
+                code: String::new(),
+                domains: Vec::new(),
+            };
+            for (field, value) in fields(entry)? {
+                match (field, value) {
+                    (1, Field::Bytes(bytes)) => {
+                        site.code = String::from_utf8_lossy(bytes).to_lowercase()
+                    }
+                    (2, Field::Bytes(domain)) => site.domains.push(site_domain(domain)?),
+                    _ => {}
+                }
+            }
+            sites.push(site);
+        }
+        Ok(Self { sites })
+    }
+
+    /// Whether the list `code` contains `domain`. `code` may select the domains
+    /// with an attribute, as in `google@cn`.
+    pub fn contains(&self, code: &str, domain: &str) -> bool {
+        let code = code.to_lowercase();
+        let (code, attribute) = match code.split_once('@') {
+            Some((code, attribute)) => (code, Some(attribute)),
+            None => (code.as_str(), None),
+        };
+        let domain = domain.to_lowercase();
+        self.sites
+            .iter()
+            .filter(|site| site.code == code)
+            .flat_map(|site| &site.domains)
+            .filter(|d| attribute.is_none_or(|a| d.attributes.iter().any(|attr| attr == a)))
+            .any(|d| match &d.pattern {
+                DomainPattern::Keyword(keyword) => domain.contains(keyword.as_str()),
+                DomainPattern::Regex(pattern, regex) => regex
+                    .get_or_init(|| Regex::new(pattern).ok())
+                    .as_ref()
+                    .is_some_and(|regex| regex.is_match(&domain)),
+                DomainPattern::Suffix(suffix) => {
+                    domain == *suffix || domain.ends_with(&format!(".{suffix}"))
+                }
+                DomainPattern::Full(full) => domain == *full,
+            })
+    }
+}
+
+/// Domain { Type type = 1; string value = 2; repeated Attribute attribute = 3 }
+fn site_domain(domain: &[u8]) -> Result<SiteDomain> {
+    let mut kind = 0;
+    let mut value = String::new();
+    let mut attributes = Vec::new();
+    for (field, field_value) in fields(domain)? {
+        match (field, field_value) {
+            (1, Field::Varint(v)) => kind = v,
+            (2, Field::Bytes(bytes)) => value = String::from_utf8_lossy(bytes).to_lowercase(),
+            // Attribute { string key = 1; ... }
+            (3, Field::Bytes(attribute)) => {
+                for (field, key) in fields(attribute)? {
+                    if let (1, Field::Bytes(key)) = (field, key) {
+                        attributes.push(String::from_utf8_lossy(key).to_lowercase());
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    let pattern = match kind {
+        0 => DomainPattern::Keyword(value),
+        1 => DomainPattern::Regex(value, OnceCell::new()),
+        2 => DomainPattern::Suffix(value),
+        _ => DomainPattern::Full(value),
+    };
+    Ok(SiteDomain {
+        pattern,
+        attributes,
+    })
+}
+
+/// CIDR { bytes ip = 1; uint32 prefix = 2 }
+fn cidr_contains(cidr: &[u8], ip: IpAddr) -> Result<bool> {
+    let mut network: &[u8] = &[];
+    let mut prefix = 0;
+    for (field, value) in fields(cidr)? {
+        match (field, value) {
+            (1, Field::Bytes(bytes)) => network = bytes,
+            (2, Field::Varint(value)) => prefix = value as u32,
+            _ => {}
+        }
+    }
+    let (address, width) = match (ip, network.len()) {
+        (IpAddr::V4(ip), 4) => (u128::from(u32::from(ip)), 32),
+        (IpAddr::V6(ip), 16) => (u128::from(ip), 128),
+        _ => return Ok(false),
+    };
+    let network = network.iter().fold(0u128, |n, &b| n << 8 | b as u128);
+    let shift = width - prefix.min(width);
+    Ok(shift == 128 || address >> shift == network >> shift)
+}
+
+enum Field<'a> {
+    Varint(u64),
+    Bytes(&'a [u8]),
+    Fixed,
+}
+
+/// The (field number, value) pairs of a protobuf message.
+fn fields(mut buffer: &[u8]) -> Result<Vec<(u64, Field<'_>)>> {
+    let mut fields = Vec::new();
+    while !buffer.is_empty() {
+        let key = varint(&mut buffer)?;
+        let field = match key & 0x07 {
+            0 => Field::Varint(varint(&mut buffer)?),
+            2 => {
+                let len = varint(&mut buffer)? as usize;
+                let bytes = buffer
+                    .get(..len)
+                    .ok_or_else(|| anyhow!("Truncated geodata file"))?;
+                buffer = &buffer[len..];
+                Field::Bytes(bytes)
+            }
+            wire @ (1 | 5) => {
+                let len = if wire == 1 { 8 } else { 4 };
+                buffer = buffer
+                    .get(len..)
+                    .ok_or_else(|| anyhow!("Truncated geodata file"))?;
+                Field::Fixed
+            }
+            wire => return Err(anyhow!("Unsupported protobuf wire type {wire}")),
+        };
+        fields.push((key >> 3, field));
+    }
+    Ok(fields)
+}
+
+fn varint(buffer: &mut &[u8]) -> Result<u64> {
+    let mut value = 0;
+    for shift in (0..64).step_by(7) {
+        let (&byte, rest) = buffer
+            .split_first()
+            .ok_or_else(|| anyhow!("Truncated geodata file"))?;
+        *buffer = rest;
+        value |= u64::from(byte & 0x7F) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(anyhow!("Invalid varint in geodata file"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An IPv4 database with one node: 0.0.0.0/1 maps to `data`, the rest is empty.
+    fn mmdb(data: &[u8]) -> Vec<u8> {
+        // Record 1 + 16 points at offset 0 of the data section, 1 is "no data"
+        let mut buffer = vec![0x00, 0x00, 0x11, 0x00, 0x00, 0x01];
+        buffer.extend([0; DATA_SECTION_SEPARATOR]);
+        buffer.extend(data);
+        buffer.extend(METADATA_MARKER);
+        // {node_count: 1, record_size: 24, ip_version: 4}
+        buffer.push(0xE3);
+        for (key, value) in [("node_count", 1), ("record_size", 24), ("ip_version", 4)] {
+            buffer.push(0x40 | key.len() as u8);
+            buffer.extend(key.as_bytes());
+            buffer.extend([0xA1, value]);
+        }
+        buffer
+    }
+
+    /// Opens `buffer` written to a file named `name`, unique per test.
+    fn open(name: &str, buffer: &[u8]) -> Result<Mmdb> {
+        let dir = std::env::temp_dir().join(format!("proxy-rs-geoip-{}", std::process::id()));
+        fs::create_dir_all(&dir)?;
+        let path = dir.join(name);
+        fs::write(&path, buffer)?;
+        let db = Mmdb::open(&path);
+        let _ = fs::remove_file(&path);
+        // Fails while other tests still have their file in it
+        let _ = fs::remove_dir(&dir);
+        db
+    }
+
+    #[test]
+    fn looks_up_ipv4() {
+        // "US"
+        let db = open("ipv4.mmdb", &mmdb(&[0x42, b'U', b'S'])).unwrap();
+        let record = db.lookup("1.2.3.4".parse().unwrap()).unwrap().unwrap();
+        assert_eq!(country_codes(&record), ["US"]);
+        assert_eq!(db.lookup("200.0.0.1".parse().unwrap()).unwrap(), None);
+        assert_eq!(db.lookup("::1".parse().unwrap()).unwrap(), None);
+    }
+
+    #[test]
+    fn decodes_nested_records() {
+        // {"country": {"iso_code": "DE"}}
+        let data = [
+            &[0xE1, 0x47][..],
+            b"country",
+            &[0xE1, 0x48],
+            b"iso_code",
+            &[0x42],
+            b"DE",
+        ]
+        .concat();
+        let db = open("nested.mmdb", &mmdb(&data)).unwrap();
+        let record = db.lookup("10.0.0.1".parse().unwrap()).unwrap().unwrap();
+        assert_eq!(country_codes(&record), ["DE"]);
+    }
+
+    #[test]
+    fn rejects_pointer_cycles() {
+        // A pointer to itself
+        let decoder = Decoder {
+            buffer: &[0x20, 0x00],
+        };
+        assert!(decoder.decode(0).is_err());
+        let db = open("cycle.mmdb", &mmdb(&[0x20, 0x00])).unwrap();
+        assert!(db.lookup("1.1.1.1".parse().unwrap()).is_err());
+    }
+
+    #[test]
+    fn rejects_truncated_data() {
+        // A string of 5 bytes with 2 left
+        let decoder = Decoder {
+            buffer: &[0x45, b'a', b'b'],
+        };
+        assert!(decoder.decode(0).is_err());
+        // An array claiming 16M elements
+        let decoder = Decoder {
+            buffer: &[0x1F, 0x04, 0xFF, 0xFF, 0xFF],
+        };
+        assert!(decoder.decode(0).is_err());
+    }
+
+    #[test]
+    fn rejects_files_without_metadata() {
+        assert!(open("invalid.mmdb", b"not a database").is_err());
+    }
+
+    #[test]
+    fn decodes_protobuf_fields() {
+        // field 1 varint 150, field 2 bytes "hi"
+        let buffer = [0x08, 0x96, 0x01, 0x12, 0x02, b'h', b'i'];
+        let fields = fields(&buffer).unwrap();
+        assert!(matches!(fields[0], (1, Field::Varint(150))));
+        assert!(matches!(fields[1], (2, Field::Bytes(b"hi"))));
+        assert!(super::fields(&[0x12, 0x05, b'h']).is_err());
+    }
+
+    #[test]
+    fn matches_cidrs() {
+        // CIDR { ip = 10.0.0.0, prefix = 8 }
+        let cidr = [0x0A, 0x04, 10, 0, 0, 0, 0x10, 8];
+        assert!(cidr_contains(&cidr, "10.1.2.3".parse().unwrap()).unwrap());
+        assert!(!cidr_contains(&cidr, "11.0.0.1".parse().unwrap()).unwrap());
+        assert!(!cidr_contains(&cidr, "::1".parse().unwrap()).unwrap());
+    }
+
+    #[test]
+    fn matches_geosite_domains() {
+        let domain = |pattern, attributes: &[&str]| SiteDomain {
+            pattern,
+            attributes: attributes.iter().map(|a| a.to_string()).collect(),
+        };
+        let geosite = GeoSite {
+            sites: vec![Site {
+                code: "test".to_string(),
+                domains: vec![
+                    domain(DomainPattern::Keyword("tracker".into()), &[]),
+                    domain(
+                        DomainPattern::Regex(r"^ads\d+\.".into(), OnceCell::new()),
+                        &[],
+                    ),
+                    domain(DomainPattern::Regex("(?=x)".into(), OnceCell::new()), &[]),
+                    domain(DomainPattern::Suffix("example.com".into()), &["cn"]),
+                    domain(DomainPattern::Full("exact.org".into()), &[]),
+                ],
+            }],
+        };
+        assert!(geosite.contains("TEST", "a.tracker.net"));
+        assert!(geosite.contains("test", "ads12.net"));
+        // The regex is compiled once and reused
+        assert!(geosite.contains("test", "ads3.org"));
+        assert!(geosite.contains("test", "www.Example.com"));
+        assert!(geosite.contains("test@cn", "example.com"));
+        assert!(!geosite.contains("test@cn", "exact.org"));
+        assert!(!geosite.contains("test", "notexample.com"));
+        assert!(!geosite.contains("test", "sub.exact.org"));
+        assert!(!geosite.contains("other", "exact.org"));
+    }
+}
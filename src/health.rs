@@ -0,0 +1,154 @@
+//! `/healthz` for orchestration tools and uptime monitors, served next to a
+//! core run in the foreground or by the watchdog.
+
+use anyhow::{Context, Result};
+use jiff::Timestamp;
+use log::*;
+use serde::Serialize;
+use std::fs;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+
+/// How often a request is sent through the proxy
+const PROBE_INTERVAL: Duration = Duration::from_secs(30);
+const PROBE_TIMEOUT: Duration = Duration::from_secs(10);
+/// Without a successful request for this long the proxy counts as down
+const STALE_AFTER: Duration = Duration::from_secs(3 * 30);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Where the health of an instance is read from.
+pub struct HealthSource {
+    /// Pid file of the core, rewritten by the watchdog on restarts
+    pub pid_path: PathBuf,
+    /// Written with the time of each successful subscription download
+    pub refreshed_path: PathBuf,
+    pub mixed_port: u16,
+    /// Requested through the mixed-port to check the proxy works
+    pub probe_url: String,
+}
+
+#[derive(Serialize)]
+struct Health {
+    healthy: bool,
+    pid: Option<u32>,
+    last_subscription_refresh: Option<String>,
+    last_proxied_request: Option<String>,
+    last_proxy_error: Option<String>,
+}
+
+#[derive(Default)]
+struct Probe {
+    last_success: Option<Timestamp>,
+    last_error: Option<String>,
+}
+
+/// Listens on `address` for [`serve`], before the core is started so a port
+/// in use is reported up front.
+pub async fn bind(address: SocketAddr) -> Result<TcpListener> {
+    let listener = TcpListener::bind(address)
+        .await
+        .with_context(|| format!("Failed to serve the health endpoint on {address}"))?;
+    info!("Health endpoint: http://{address}/healthz");
+    Ok(listener)
+}
+
+/// Serves `GET /healthz` on `listener` and probes the proxy in the background.
+/// Answers 200 while the core runs and its last request through the proxy
+/// succeeded recently, 503 otherwise, with the details as JSON.
+pub async fn serve(listener: TcpListener, source: HealthSource) -> Result<()> {
+    let probe = Mutex::new(Probe::default());
+    let client = reqwest::Client::builder()
+        .proxy(reqwest::Proxy::all(format!(
+            "http://127.0.0.1:{}",
+            source.mixed_port
+        ))?)
+        .timeout(PROBE_TIMEOUT)
+        .build()?;
+
+    let probing = async {
+        loop {
+            let result = client.get(&source.probe_url).send().await;
+            match result.and_then(|response| response.error_for_status()) {
+                Ok(_) => {
+                    let mut probe = probe.lock().unwrap();
+                    probe.last_success = Some(Timestamp::now());
+                    probe.last_error = None;
+                }
+                Err(e) => {
+                    debug!("Health probe failed: {e}");
+                    probe.lock().unwrap().last_error = Some(e.to_string());
+                }
+            }
+            tokio::time::sleep(PROBE_INTERVAL).await;
+        }
+    };
+    let serving = async {
+        loop {
+            let stream = match listener.accept().await {
+                Ok((stream, _)) => stream,
+                Err(e) => {
+                    // E.g. out of file descriptors for a moment
+                    debug!("Failed to accept a health request: {e}");
+                    tokio::time::sleep(Duration::from_secs(1)).await;
+                    continue;
+                }
+            };
+            let health = health(&source, &probe.lock().unwrap());
+            if let Err(e) = respond(stream, &health).await {
+                debug!("Health request failed: {e}");
+            }
+        }
+    };
+    tokio::select! {
+        () = probing => {}
+        () = serving => {}
+    }
+    Ok(())
+}
+
+fn health(source: &HealthSource, probe: &Probe) -> Health {
+    let pid = fs::read_to_string(&source.pid_path)
+        .ok()
+        .and_then(|pid| pid.trim().parse::<u32>().ok())
+        .filter(|pid| {
+            let pid = sysinfo::Pid::from_u32(*pid);
+            let mut system = sysinfo::System::new();
+            system.refresh_processes(sysinfo::ProcessesToUpdate::Some(&[pid]), true);
+            system.process(pid).is_some()
+        });
+    let recent = probe
+        .last_success
+        .is_some_and(|time| Timestamp::now().duration_since(time).unsigned_abs() < STALE_AFTER);
+    Health {
+        healthy: pid.is_some() && recent,
+        pid,
+        last_subscription_refresh: fs::read_to_string(&source.refreshed_path)
+            .ok()
+            .map(|time| time.trim().to_string()),
+        last_proxied_request: probe.last_success.map(|time| time.to_string()),
+        last_proxy_error: probe.last_error.clone(),
+    }
+}
+
+async fn respond(stream: TcpStream, health: &Health) -> Result<()> {
+    let mut stream = BufReader::new(stream);
+    let mut request_line = String::new();
+    tokio::time::timeout(REQUEST_TIMEOUT, stream.read_line(&mut request_line)).await??;
+    let path = request_line.split_whitespace().nth(1).unwrap_or_default();
+    let (status, body) = match path.split('?').next() {
+        Some("/healthz") if health.healthy => ("200 OK", serde_json::to_string(health)?),
+        Some("/healthz") => ("503 Service Unavailable", serde_json::to_string(health)?),
+        _ => ("404 Not Found", r#"{"error":"not found"}"#.to_string()),
+    };
+    let response = format!(
+        "HTTP/1.1 {status}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    );
+    stream.get_mut().write_all(response.as_bytes()).await?;
+    stream.get_mut().shutdown().await?;
+    Ok(())
+}
@@ -0,0 +1,197 @@
+use anyhow::{anyhow, Result};
+use log::*;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::process::Command;
+
+const HOOK_TIMEOUT: Duration = Duration::from_secs(30);
+/// Directory in the data dir with the scripts run at each [`Stage`]
+pub const SCRIPTS_DIR: &str = "hooks";
+
+/// Something that happened to an instance, hooks can subscribe to it.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Event {
+    /// `start` finished and Mihomo is running
+    Started,
+    /// Mihomo exited unexpectedly while supervised with `--watch`
+    Crashed,
+    /// The subscription was downloaded again
+    SubscriptionRefreshed,
+    /// The subscription's remaining traffic dropped below `quota-low-percent` or it expires soon
+    QuotaLow,
+    /// The watchdog noticed a wake from sleep or a network switch
+    NetworkChanged,
+}
+
+impl Event {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Event::Started => "started",
+            Event::Crashed => "crashed",
+            Event::SubscriptionRefreshed => "subscription-refreshed",
+            Event::QuotaLow => "quota-low",
+            Event::NetworkChanged => "network-changed",
+        }
+    }
+}
+
+/// A point in an instance's lifecycle that runs `hooks/<stage>` in the data dir,
+/// e.g. `hooks/pre-start`, if the script exists.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stage {
+    /// Before the core is spawned, a failing script aborts `start`
+    PreStart,
+    /// After the core started
+    PostStart,
+    /// Before a running core is stopped
+    PreStop,
+    /// After the core was stopped
+    PostStop,
+    /// After the running core loaded config.yaml again
+    OnReload,
+}
+
+impl Stage {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Stage::PreStart => "pre-start",
+            Stage::PostStart => "post-start",
+            Stage::PreStop => "pre-stop",
+            Stage::PostStop => "post-stop",
+            Stage::OnReload => "on-reload",
+        }
+    }
+}
+
+/// The script for `stage` in `dir`: the bare name on Unix, which must be
+/// executable, and `.cmd`, `.bat`, `.ps1` or `.exe` on Windows.
+fn find_script(dir: &Path, stage: Stage) -> Option<PathBuf> {
+    let extensions: &[&str] = if cfg!(windows) {
+        &["cmd", "bat", "ps1", "exe"]
+    } else {
+        &[""]
+    };
+    extensions
+        .iter()
+        .map(|extension| dir.join(stage.as_str()).with_extension(extension))
+        .find(|path| path.is_file())
+}
+
+/// Runs the script for `stage` in `dir` if there is one, with `PROXY_RS_EVENT`
+/// set to the stage and `env` added to its environment. It is killed after
+/// [`HOOK_TIMEOUT`], and a non-zero exit is an error.
+pub async fn run_script(dir: &Path, stage: Stage, env: &[(&str, String)]) -> Result<()> {
+    let Some(script) = find_script(dir, stage) else {
+        return Ok(());
+    };
+    debug!("Running {}", script.display());
+    let mut command = match script.extension().and_then(|e| e.to_str()) {
+        Some("ps1") => {
+            let mut command = Command::new("powershell");
+            command.args(["-NoProfile", "-ExecutionPolicy", "Bypass", "-File"]);
+            command.arg(&script);
+            command
+        }
+        Some("cmd" | "bat") => {
+            let mut command = Command::new("cmd");
+            command.arg("/C").arg(&script);
+            command
+        }
+        _ => Command::new(&script),
+    };
+    let status = command
+        .env("PROXY_RS_EVENT", stage.as_str())
+        .envs(env.iter().map(|(key, value)| (key, value)))
+        .kill_on_drop(true)
+        .status();
+    let status = tokio::time::timeout(HOOK_TIMEOUT, status)
+        .await
+        .map_err(|_| anyhow!("{} timed out after {HOOK_TIMEOUT:?}", script.display()))?
+        .map_err(|e| anyhow!("Failed to run {}: {e}", script.display()))?;
+    if !status.success() {
+        return Err(anyhow!("{} exited with {status}", script.display()));
+    }
+    Ok(())
+}
+
+/// A `[[hooks]]` entry of `proxy-rs.toml`: a shell command to run and/or a URL
+/// to POST a JSON message to when one of `events` happens.
+///
+/// The command gets `PROXY_RS_EVENT`, `PROXY_RS_INSTANCE` and `PROXY_RS_MESSAGE`
+/// in its environment. The JSON body has `event`, `instance`, `message` and the
+/// message again as `text` and `content`, which Slack/Telegram and Discord show.
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct Hook {
+    /// Events to fire on, every event if empty
+    pub events: Vec<Event>,
+    pub command: Option<String>,
+    pub url: Option<String>,
+}
+
+/// Runs every hook subscribed to `event`. Failures are logged, never returned,
+/// so a broken hook can't stop Mihomo from starting or being restarted.
+pub async fn fire(hooks: &[Hook], event: Event, instance: &str, message: &str) {
+    for hook in hooks
+        .iter()
+        .filter(|h| h.events.is_empty() || h.events.contains(&event))
+    {
+        if let Some(command) = &hook.command {
+            if let Err(e) = run_command(command, event, instance, message).await {
+                warn!("Hook command for {} failed: {e}", event.as_str());
+            }
+        }
+        if let Some(url) = &hook.url {
+            if let Err(e) = post_webhook(url, event, instance, message).await {
+                warn!("Webhook for {} failed: {e}", event.as_str());
+            }
+        }
+    }
+}
+
+async fn run_command(command: &str, event: Event, instance: &str, message: &str) -> Result<()> {
+    let mut shell = if cfg!(windows) {
+        let mut shell = Command::new("cmd");
+        shell.arg("/C");
+        shell
+    } else {
+        let mut shell = Command::new("sh");
+        shell.arg("-c");
+        shell
+    };
+    let status = shell
+        .arg(command)
+        .env("PROXY_RS_EVENT", event.as_str())
+        .env("PROXY_RS_INSTANCE", instance)
+        .env("PROXY_RS_MESSAGE", message)
+        .kill_on_drop(true)
+        .status();
+    let status = tokio::time::timeout(HOOK_TIMEOUT, status)
+        .await
+        .map_err(|_| anyhow!("timed out after {HOOK_TIMEOUT:?}"))??;
+    if !status.success() {
+        return Err(anyhow!("`{command}` exited with {status}"));
+    }
+    Ok(())
+}
+
+async fn post_webhook(url: &str, event: Event, instance: &str, message: &str) -> Result<()> {
+    let text = format!("[proxy-rs {instance}] {message}");
+    Client::new()
+        .post(url)
+        .timeout(HOOK_TIMEOUT)
+        .json(&serde_json::json!({
+            "event": event,
+            "instance": instance,
+            "message": message,
+            "text": text,
+            "content": text,
+        }))
+        .send()
+        .await?
+        .error_for_status()?;
+    Ok(())
+}
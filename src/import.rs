@@ -0,0 +1,461 @@
+//! Profiles of Clash Verge and Clash for Windows, read so users moving to
+//! proxy-rs keep their subscriptions and the enhancements they layered on top.
+
+use anyhow::{anyhow, Context, Result};
+use regex::Regex;
+use serde::Deserialize;
+use serde_yaml::{Mapping, Value};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Data dirs of Clash Verge Rev and, before it, Clash Verge.
+const VERGE_DIRS: &[&str] = &["io.github.clash-verge-rev.clash-verge-rev", "clash-verge"];
+const VERGE_PROFILES_FILE: &str = "profiles.yaml";
+/// Enhancements Clash Verge Rev applies to every profile
+const VERGE_GLOBAL_ENHANCEMENTS: &[&str] = &["Merge", "Script"];
+const CFW_PROFILES_FILE: &str = "list.yml";
+const CFW_SETTINGS_FILE: &str = "cfw-settings.yaml";
+
+/// GUI clients `import` reads the profiles of.
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImportClient {
+    /// Clash Verge Rev, or the original Clash Verge
+    ClashVerge,
+    /// Clash for Windows
+    Cfw,
+}
+
+impl ImportClient {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ImportClient::ClashVerge => "Clash Verge",
+            ImportClient::Cfw => "Clash for Windows",
+        }
+    }
+
+    /// Where the client keeps its profiles on this machine.
+    pub fn find_dir(&self) -> Result<PathBuf> {
+        let home = dirs::home_dir().unwrap_or_default();
+        let candidates: Vec<PathBuf> = match self {
+            ImportClient::ClashVerge => VERGE_DIRS
+                .iter()
+                .flat_map(|name| {
+                    [
+                        dirs::data_dir(),
+                        dirs::config_dir(),
+                        Some(home.join(".config")),
+                    ]
+                    .into_iter()
+                    .flatten()
+                    .map(move |dir| dir.join(name))
+                })
+                .collect(),
+            // On Windows too
+            ImportClient::Cfw => vec![home.join(".config").join("clash")],
+        };
+        candidates
+            .into_iter()
+            .find(|dir| self.profiles_file(dir).exists())
+            .ok_or_else(|| {
+                anyhow!(
+                    "No {} profiles found, pass the directory they are in",
+                    self.name()
+                )
+            })
+    }
+
+    fn profiles_file(&self, dir: &Path) -> PathBuf {
+        match self {
+            ImportClient::ClashVerge => dir.join(VERGE_PROFILES_FILE),
+            ImportClient::Cfw => dir.join("profiles").join(CFW_PROFILES_FILE),
+        }
+    }
+}
+
+/// A profile of either client and the enhancements it is used with.
+#[derive(Debug)]
+pub struct Profile {
+    pub name: String,
+    /// Subscription the client refreshes it from, `None` for a local profile
+    pub url: Option<String>,
+    pub file: PathBuf,
+    /// The profile the client is using
+    pub current: bool,
+    /// Merge enhancements in the order the client applies them, see [`apply_merge`]
+    pub merges: Vec<(PathBuf, Mapping)>,
+    /// JavaScript enhancements, which proxy-rs can't run
+    pub scripts: Vec<Script>,
+}
+
+/// A JavaScript enhancement.
+#[derive(Debug)]
+pub struct Script {
+    /// File name to keep it as
+    pub name: String,
+    /// File it was read from
+    pub source: PathBuf,
+    pub code: String,
+}
+
+/// The profiles of `client` in `dir`.
+pub fn profiles(client: ImportClient, dir: &Path) -> Result<Vec<Profile>> {
+    match client {
+        ImportClient::ClashVerge => verge_profiles(dir),
+        ImportClient::Cfw => cfw_profiles(dir),
+    }
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+struct VergeProfiles {
+    current: Option<String>,
+    /// Enhancements of the original Clash Verge, applied to the current profile
+    chain: Vec<String>,
+    items: Vec<VergeItem>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+struct VergeItem {
+    uid: String,
+    #[serde(rename = "type")]
+    kind: String,
+    name: Option<String>,
+    file: Option<String>,
+    url: Option<String>,
+    option: VergeOption,
+}
+
+/// Enhancements of a Clash Verge Rev profile, by uid.
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+struct VergeOption {
+    rules: Option<String>,
+    proxies: Option<String>,
+    groups: Option<String>,
+    merge: Option<String>,
+    script: Option<String>,
+}
+
+fn verge_profiles(dir: &Path) -> Result<Vec<Profile>> {
+    let path = dir.join(VERGE_PROFILES_FILE);
+    let content =
+        fs::read_to_string(&path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let list: VergeProfiles =
+        serde_yaml::from_str(&content).with_context(|| format!("Invalid {}", path.display()))?;
+    let item = |uid: &str| list.items.iter().find(|item| item.uid == uid);
+    let file = |item: &VergeItem| {
+        item.file
+            .as_ref()
+            .map(|file| dir.join("profiles").join(file))
+    };
+
+    let mut profiles = Vec::new();
+    for profile in &list.items {
+        if !matches!(profile.kind.as_str(), "remote" | "local") {
+            continue;
+        }
+        let Some(profile_file) = file(profile) else {
+            continue;
+        };
+        let current = list.current.as_deref() == Some(profile.uid.as_str());
+        // Verge Rev: global merge and script, then the profile's own rules,
+        // proxies, groups, merge and script
+        let option = &profile.option;
+        let mut uids: Vec<&str> = VERGE_GLOBAL_ENHANCEMENTS.to_vec();
+        if current {
+            uids.extend(list.chain.iter().map(String::as_str));
+        }
+        uids.extend(
+            [
+                &option.rules,
+                &option.proxies,
+                &option.groups,
+                &option.merge,
+                &option.script,
+            ]
+            .into_iter()
+            .flatten()
+            .map(String::as_str),
+        );
+
+        let mut merges = Vec::new();
+        let mut scripts = Vec::new();
+        for enhancement in uids.into_iter().filter_map(item) {
+            let Some(path) = file(enhancement).filter(|path| path.exists()) else {
+                continue;
+            };
+            let prefix = match enhancement.kind.as_str() {
+                "script" => {
+                    // Only the file name, the rest comes from profiles.yaml
+                    let name = enhancement
+                        .file
+                        .as_deref()
+                        .and_then(|file| Path::new(file).file_name())
+                        .map(|name| name.to_string_lossy().into_owned())
+                        .unwrap_or_else(|| format!("script-{}.js", scripts.len() + 1));
+                    scripts.push(Script {
+                        name,
+                        code: fs::read_to_string(&path)?,
+                        source: path,
+                    });
+                    continue;
+                }
+                "merge" => None,
+                "rules" => Some("rules"),
+                "proxies" => Some("proxies"),
+                "groups" => Some("proxy-groups"),
+                _ => continue,
+            };
+            let mapping = read_mapping(&path)?;
+            let merge = match prefix {
+                Some(section) => seq_enhancement(mapping, section),
+                None => mapping,
+            };
+            merges.push((path, merge));
+        }
+
+        profiles.push(Profile {
+            name: profile.name.clone().unwrap_or_else(|| profile.uid.clone()),
+            url: profile.url.clone().filter(|_| profile.kind == "remote"),
+            file: profile_file,
+            current,
+            merges,
+            scripts,
+        });
+    }
+    Ok(profiles)
+}
+
+/// A Verge Rev rules, proxies or groups enhancement, `prepend:`, `append:`
+/// and `delete:` lists, as a merge of `prepend-<section>` and so on.
+fn seq_enhancement(mapping: Mapping, section: &str) -> Mapping {
+    mapping
+        .into_iter()
+        .filter_map(|(key, value)| {
+            let key = key.as_str()?;
+            Some((Value::from(format!("{key}-{section}")), value))
+        })
+        .collect()
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+struct CfwProfiles {
+    files: Vec<CfwFile>,
+    /// Position of the current profile in `files`
+    index: Option<usize>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+struct CfwFile {
+    /// File name in the profiles dir, named after the time it was added
+    time: String,
+    name: Option<String>,
+    url: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+struct CfwSettings {
+    parsers: Vec<CfwParser>,
+}
+
+/// A parser of `cfw-settings.yaml`, run on the profiles downloaded from `url`
+/// or from URLs matching `reg`.
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+struct CfwParser {
+    url: Option<String>,
+    reg: Option<String>,
+    yaml: Option<Mapping>,
+    /// JavaScript, inline or in a file
+    code: Option<String>,
+    file: Option<PathBuf>,
+}
+
+fn cfw_profiles(dir: &Path) -> Result<Vec<Profile>> {
+    let path = dir.join("profiles").join(CFW_PROFILES_FILE);
+    let content =
+        fs::read_to_string(&path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let list: CfwProfiles =
+        serde_yaml::from_str(&content).with_context(|| format!("Invalid {}", path.display()))?;
+    let settings_path = dir.join(CFW_SETTINGS_FILE);
+    let settings: CfwSettings = match fs::read_to_string(&settings_path) {
+        Ok(content) => serde_yaml::from_str(&content)
+            .with_context(|| format!("Invalid {}", settings_path.display()))?,
+        Err(_) => CfwSettings::default(),
+    };
+
+    let mut profiles = Vec::new();
+    for (index, file) in list.files.iter().enumerate() {
+        let url = file.url.clone().filter(|url| !url.is_empty());
+        let mut merges = Vec::new();
+        let mut scripts = Vec::new();
+        for (number, parser) in settings.parsers.iter().enumerate() {
+            let Some(url) = &url else {
+                break;
+            };
+            let matches = parser.url.as_ref() == Some(url)
+                || parser
+                    .reg
+                    .as_deref()
+                    .and_then(|reg| Regex::new(reg).ok())
+                    .is_some_and(|reg| reg.is_match(url));
+            if !matches {
+                continue;
+            }
+            if let Some(yaml) = &parser.yaml {
+                merges.push((settings_path.clone(), yaml.clone()));
+            }
+            if let Some(file) = &parser.file {
+                let source = dir.join(file);
+                scripts.push(Script {
+                    name: file.file_name().map_or_else(
+                        || format!("parser-{}.js", number + 1),
+                        |name| name.to_string_lossy().into_owned(),
+                    ),
+                    code: fs::read_to_string(&source)
+                        .with_context(|| format!("Failed to read {}", source.display()))?,
+                    source,
+                });
+            } else if let Some(code) = &parser.code {
+                scripts.push(Script {
+                    name: format!("parser-{}.js", number + 1),
+                    source: settings_path.clone(),
+                    code: code.clone(),
+                });
+            }
+        }
+        profiles.push(Profile {
+            name: file.name.clone().unwrap_or_else(|| file.time.clone()),
+            url,
+            file: dir.join("profiles").join(&file.time),
+            current: list.index == Some(index),
+            merges,
+            scripts,
+        });
+    }
+    Ok(profiles)
+}
+
+fn read_mapping(path: &Path) -> Result<Mapping> {
+    let content =
+        fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let value: Value =
+        serde_yaml::from_str(&content).with_context(|| format!("Invalid {}", path.display()))?;
+    match value {
+        Value::Mapping(mapping) => Ok(mapping),
+        // An enhancement left empty
+        Value::Null => Ok(Mapping::new()),
+        _ => Err(anyhow!("{} is not a YAML mapping", path.display())),
+    }
+}
+
+/// Applies a merge enhancement to `config` the way the clients do:
+/// `prepend-`/`append-`/`delete-` lists of rules, proxies and proxy-groups,
+/// `mix-object` and the `mix-` providers merged in, and any other key set as
+/// is. `prepend-rules` go to `prepend_rules` instead, to be kept as overrides.
+/// Returns the keys it can't apply.
+pub fn apply_merge(
+    config: &mut Mapping,
+    merge: &Mapping,
+    prepend_rules: &mut Vec<String>,
+) -> Vec<String> {
+    let mut unsupported = Vec::new();
+    for (key, value) in merge {
+        let Some(key) = key.as_str() else {
+            continue;
+        };
+        let list = || value.as_sequence().cloned().unwrap_or_default();
+        match key {
+            "prepend-rules" => prepend_rules.extend(
+                list()
+                    .iter()
+                    .filter_map(|rule| rule.as_str().map(String::from)),
+            ),
+            "append-rules" => {
+                let rules = sequence(config, "rules");
+                // After MATCH a rule would never be reached
+                let end = match rules.last().and_then(Value::as_str) {
+                    Some(rule) if rule.starts_with("MATCH,") => rules.len() - 1,
+                    _ => rules.len(),
+                };
+                rules.splice(end..end, list());
+            }
+            "delete-rules" => {
+                let deleted = list();
+                sequence(config, "rules").retain(|rule| !deleted.contains(rule));
+                prepend_rules.retain(|rule| !deleted.contains(&Value::from(rule.as_str())));
+            }
+            "mix-object" => {
+                if let Some(mix) = value.as_mapping() {
+                    unsupported.extend(apply_merge(config, mix, prepend_rules));
+                }
+            }
+            "mix-rule-providers" | "mix-proxy-providers" => {
+                let section = key.trim_start_matches("mix-");
+                if !config.get(section).is_some_and(Value::is_mapping) {
+                    config.insert(section.into(), Value::Mapping(Mapping::new()));
+                }
+                if let (Some(providers), Some(mix)) = (
+                    config.get_mut(section).and_then(Value::as_mapping_mut),
+                    value.as_mapping(),
+                ) {
+                    providers.extend(mix.clone());
+                }
+            }
+            _ => {
+                let Some((action, section)) = key.split_once('-').filter(|(action, section)| {
+                    ["prepend", "append", "delete"].contains(action)
+                        && ["proxies", "proxy-groups"].contains(section)
+                }) else {
+                    if key.starts_with("mix-") || key == "commands" {
+                        unsupported.push(key.to_string());
+                    } else {
+                        config.insert(key.into(), value.clone());
+                    }
+                    continue;
+                };
+                let entries = sequence(config, section);
+                match action {
+                    "prepend" => {
+                        entries.splice(0..0, list());
+                    }
+                    "append" => entries.extend(list()),
+                    _ => {
+                        // By name, or the whole entry
+                        let names: Vec<Value> = list()
+                            .into_iter()
+                            .map(|entry| entry.get("name").cloned().unwrap_or(entry))
+                            .collect();
+                        entries.retain(|entry| {
+                            entry.get("name").is_none_or(|name| !names.contains(name))
+                        });
+                        // Groups can't refer to what is gone
+                        for group in sequence(config, "proxy-groups") {
+                            if let Some(members) =
+                                group.get_mut("proxies").and_then(Value::as_sequence_mut)
+                            {
+                                members.retain(|member| !names.contains(member));
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+    unsupported
+}
+
+/// The list at `key`, created if it is missing.
+fn sequence<'a>(config: &'a mut Mapping, key: &str) -> &'a mut Vec<Value> {
+    if !config.get(key).is_some_and(Value::is_sequence) {
+        config.insert(key.into(), Value::Sequence(Vec::new()));
+    }
+    config
+        .get_mut(key)
+        .and_then(Value::as_sequence_mut)
+        .expect("just inserted")
+}
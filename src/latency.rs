@@ -0,0 +1,94 @@
+use crate::controller::Controller;
+use crate::output::Table;
+use anyhow::{anyhow, Result};
+use colored::Colorize;
+use futures_util::stream::{self, StreamExt};
+use log::*;
+use serde::Serialize;
+use std::fs;
+use std::path::Path;
+
+const CONCURRENCY: usize = 16;
+
+/// Delay test result of one node.
+#[derive(Serialize, Debug)]
+pub struct LatencyResult {
+    pub name: String,
+    /// Delay in milliseconds, `None` on timeout
+    pub delay: Option<u64>,
+}
+
+/// Tests the delay of every node, or only the members of `group`.
+pub async fn test_latency(
+    controller: &Controller,
+    group: Option<&str>,
+    url: &str,
+    timeout_ms: u64,
+) -> Result<Vec<LatencyResult>> {
+    let names = node_names(controller, group).await?;
+    info!("Testing {} nodes with {url}...", names.len());
+
+    let mut results: Vec<LatencyResult> = stream::iter(names)
+        .map(|name| async move {
+            let delay = controller
+                .proxy_delay(&name, url, timeout_ms)
+                .await
+                .unwrap_or_else(|e| {
+                    debug!("Delay test for {name} failed: {e}");
+                    None
+                });
+            LatencyResult { name, delay }
+        })
+        .buffer_unordered(CONCURRENCY)
+        .collect()
+        .await;
+
+    results.sort_by_key(|r| r.delay.unwrap_or(u64::MAX));
+    Ok(results)
+}
+
+/// Every node sorted by name, or only the members of `group`.
+pub(crate) async fn node_names(
+    controller: &Controller,
+    group: Option<&str>,
+) -> Result<Vec<String>> {
+    let proxies = controller.proxies().await?;
+    let mut names: Vec<String> = match group {
+        Some(group) => {
+            let group = proxies
+                .get(group)
+                .filter(|p| p.is_group())
+                .ok_or_else(|| anyhow!("Proxy group not found: {group}"))?;
+            group.all.clone()
+        }
+        None => proxies
+            .values()
+            .filter(|p| p.is_node())
+            .map(|p| p.name.clone())
+            .collect(),
+    };
+    names.sort();
+    Ok(names)
+}
+
+/// Prints the results as a table, colored by delay.
+pub fn print_latency_table(results: &[LatencyResult]) {
+    let mut table = Table::new(["Node", "Delay"]).align_right([1]);
+    for result in results {
+        let delay = match result.delay {
+            Some(d) if d < 200 => format!("{d} ms").green(),
+            Some(d) if d < 500 => format!("{d} ms").yellow(),
+            Some(d) => format!("{d} ms").red(),
+            None => "timeout".red(),
+        };
+        table.row([result.name.as_str().into(), delay]);
+    }
+    table.print();
+}
+
+/// Writes the results to `path` as a JSON array.
+pub fn write_latency_json(results: &[LatencyResult], path: &Path) -> Result<()> {
+    fs::write(path, serde_json::to_string_pretty(results)?)?;
+    info!("Results written to {}", path.display());
+    Ok(())
+}
@@ -0,0 +1,80 @@
+//! Download, configure and run the [Mihomo](https://github.com/MetaCubeX/mihomo) proxy core.
+//!
+//! [`MihomoManager`] owns a data directory and drives the whole lifecycle: it
+//! downloads the core, the WebUI and geodata, starts and stops Mihomo and gives
+//! access to the running instance through its external [`Controller`].
+//!
+//! ```no_run
+//! use proxy::{default_data_dir, MihomoManager, StartOptions};
+//!
+//! # async fn run() -> anyhow::Result<()> {
+//! let manager = MihomoManager::new(default_data_dir(), None)?;
+//! let started = manager.start(&StartOptions::default()).await?;
+//! println!("HTTP/SOCKS proxy on 127.0.0.1:{}", started.mixed_port);
+//!
+//! let proxies = manager.controller()?.proxies().await?;
+//! println!("{} proxies", proxies.len());
+//!
+//! manager.stop().await?;
+//! # Ok(())
+//! # }
+//! ```
+
+pub mod backend;
+pub mod bench;
+pub mod controller;
+pub mod downloader;
+pub mod errors;
+pub mod hooks;
+pub mod import;
+pub mod latency;
+pub mod limit;
+pub mod logs;
+pub mod mihomo;
+pub mod network;
+pub mod output;
+pub mod overrides;
+pub mod schedule;
+pub mod settings;
+pub mod shell;
+pub mod speedtest;
+pub mod subscription;
+pub mod tunnel;
+pub mod wizard;
+
+mod adopt;
+mod backup;
+mod bundle;
+mod cache;
+mod child;
+mod config;
+mod connections;
+mod dashboard;
+mod doctor;
+mod geoip;
+mod health;
+mod lint;
+mod manifest;
+mod picker;
+mod provider_health;
+mod providers;
+mod proxy_selector;
+mod resources;
+mod rule_match;
+mod self_update;
+mod share;
+mod sing_box;
+mod stats;
+mod sync;
+mod sysproxy;
+mod timing;
+mod tls;
+mod traffic;
+mod utils;
+
+pub use controller::Controller;
+pub use mihomo::{
+    BundleOptions, CleanTargets, MihomoManager, RunningStatus, StartInfo, StartOptions, Status,
+    TunnelOptions, UpdateCheck,
+};
+pub use utils::{default_data_dir, local_data_dir, set_assume_yes, Installation};
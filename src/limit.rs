@@ -0,0 +1,188 @@
+//! Soft bandwidth limit for `limit`. Mihomo has no rate limiting of its own,
+//! so all traffic is blocked for a while whenever the average throughput goes
+//! over the limit.
+
+use crate::controller::{Controller, Mode, Traffic};
+use anyhow::{anyhow, Result};
+use clap::ValueEnum;
+use futures_util::{pin_mut, StreamExt};
+use log::*;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+/// Group that routes everything in `global` mode, pointed at REJECT to block.
+const GLOBAL_GROUP: &str = "GLOBAL";
+const REJECT: &str = "REJECT";
+
+/// Limits in bytes per second, see [`enforce`].
+#[derive(Debug, Clone)]
+pub struct Limits {
+    pub up: Option<u64>,
+    pub down: Option<u64>,
+    /// Period the throughput is averaged over
+    pub window: Duration,
+    /// How long traffic is blocked once a limit is exceeded
+    pub cooldown: Duration,
+}
+
+/// What blocking replaced, restored afterwards. Saved while traffic is blocked,
+/// so it can be restored if `limit` is killed before the cooldown ends.
+#[derive(Serialize, Deserialize)]
+struct Blocked {
+    /// Process of the `limit` that blocked
+    pid: u32,
+    mode: Mode,
+    global: Option<String>,
+}
+
+/// Watches `/traffic` until Ctrl+C or SIGTERM. When the average upload or
+/// download over the window exceeds its limit, switches to global mode with
+/// GLOBAL on REJECT and closes the connections, restoring both after the
+/// cooldown. `blocked_path` holds what to restore while traffic is blocked.
+pub async fn enforce(controller: &Controller, limits: &Limits, blocked_path: &Path) -> Result<()> {
+    if limits.up.is_none() && limits.down.is_none() {
+        return Err(anyhow!("Pass --up, --down or both"));
+    }
+    if let Some(pid) = blocking_pid(blocked_path) {
+        return Err(anyhow!("A limit is already enforced (pid: {pid})"));
+    }
+    if restore(controller, blocked_path).await? {
+        info!("Unblocked the traffic a stopped limit left blocked");
+    }
+    #[cfg(unix)]
+    let mut sigterm = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())?;
+    let stream = controller.traffic().await?;
+    pin_mut!(stream);
+    info!(
+        "Limiting to ↑ {} ↓ {} averaged over {}s, press Ctrl+C to stop",
+        limits.up.map_or_else(|| "-".to_string(), format_rate),
+        limits.down.map_or_else(|| "-".to_string(), format_rate),
+        limits.window.as_secs()
+    );
+
+    // Traffic is reported once per second
+    let samples = limits.window.as_secs().max(1) as usize;
+    let mut window: VecDeque<Traffic> = VecDeque::with_capacity(samples);
+    loop {
+        #[cfg(unix)]
+        let terminated = sigterm.recv();
+        #[cfg(not(unix))]
+        let terminated = std::future::pending::<Option<()>>();
+        let traffic = tokio::select! {
+            traffic = stream.next() => match traffic {
+                Some(traffic) => traffic?,
+                None => return Err(anyhow!("The controller closed the traffic stream")),
+            },
+            _ = tokio::signal::ctrl_c() => return Ok(()),
+            _ = terminated => return Ok(()),
+        };
+        if window.len() == samples {
+            window.pop_front();
+        }
+        window.push_back(traffic);
+        if window.len() < samples {
+            continue;
+        }
+        let average =
+            |rate: fn(&Traffic) -> u64| window.iter().map(rate).sum::<u64>() / samples as u64;
+        let (up, down) = (average(|t| t.up), average(|t| t.down));
+        let exceeded = match (limits.up, limits.down) {
+            (Some(limit), _) if up > limit => format!("upload {}", format_rate(up)),
+            (_, Some(limit)) if down > limit => format!("download {}", format_rate(down)),
+            _ => continue,
+        };
+        warn!(
+            "Average {exceeded} is over the limit, blocking traffic for {}s",
+            limits.cooldown.as_secs()
+        );
+        let blocked = block(controller).await?;
+        fs::write(blocked_path, toml::to_string_pretty(&blocked)?)?;
+        #[cfg(unix)]
+        let terminated = sigterm.recv();
+        #[cfg(not(unix))]
+        let terminated = std::future::pending::<Option<()>>();
+        let interrupted = tokio::select! {
+            _ = tokio::time::sleep(limits.cooldown) => false,
+            _ = tokio::signal::ctrl_c() => true,
+            _ = terminated => true,
+        };
+        unblock(controller, &blocked).await?;
+        fs::remove_file(blocked_path)?;
+        if interrupted {
+            return Ok(());
+        }
+        info!("Traffic unblocked");
+        window.clear();
+    }
+}
+
+async fn block(controller: &Controller) -> Result<Blocked> {
+    let mode = controller.configs().await?.mode;
+    let mode = Mode::from_str(&mode, true).map_err(|_| anyhow!("Unknown mode {mode}"))?;
+    let global = controller
+        .proxies()
+        .await?
+        .remove(GLOBAL_GROUP)
+        .and_then(|group| group.now);
+    controller.select_proxy(GLOBAL_GROUP, REJECT).await?;
+    let blocked = Blocked {
+        pid: std::process::id(),
+        mode,
+        global,
+    };
+    if let Err(e) = controller.set_mode(Mode::Global).await {
+        unblock(controller, &blocked).await?;
+        return Err(e);
+    }
+    if let Err(e) = controller.close_all_connections().await {
+        warn!("Failed to close connections: {e}");
+    }
+    Ok(blocked)
+}
+
+async fn unblock(controller: &Controller, blocked: &Blocked) -> Result<()> {
+    controller.set_mode(blocked.mode).await?;
+    if let Some(node) = &blocked.global {
+        controller.select_proxy(GLOBAL_GROUP, node).await?;
+    }
+    Ok(())
+}
+
+/// The `limit` process blocking traffic now, if it still runs.
+pub fn blocking_pid(blocked_path: &Path) -> Option<u32> {
+    let blocked: Blocked = toml::from_str(&fs::read_to_string(blocked_path).ok()?).ok()?;
+    let pid = sysinfo::Pid::from_u32(blocked.pid);
+    let mut system = sysinfo::System::new();
+    system.refresh_processes(sysinfo::ProcessesToUpdate::Some(&[pid]), true);
+    system.process(pid).map(|_| blocked.pid)
+}
+
+/// Unblocks traffic a `limit` that no longer runs left blocked. Returns
+/// whether there was any.
+pub async fn restore(controller: &Controller, blocked_path: &Path) -> Result<bool> {
+    let Ok(content) = fs::read_to_string(blocked_path) else {
+        return Ok(false);
+    };
+    if blocking_pid(blocked_path).is_some() {
+        return Ok(false);
+    }
+    let blocked: Blocked =
+        toml::from_str(&content).map_err(|e| anyhow!("Invalid {}: {e}", blocked_path.display()))?;
+    unblock(controller, &blocked).await?;
+    fs::remove_file(blocked_path)?;
+    Ok(true)
+}
+
+/// Bytes per second in the bits per second bandwidth is sold in.
+pub fn format_rate(bytes_per_second: u64) -> String {
+    let bits = bytes_per_second as f64 * 8.0;
+    match bits {
+        b if b >= 1e9 => format!("{:.1} Gbps", b / 1e9),
+        b if b >= 1e6 => format!("{:.1} Mbps", b / 1e6),
+        b if b >= 1e3 => format!("{:.1} kbps", b / 1e3),
+        b => format!("{b:.0} bps"),
+    }
+}
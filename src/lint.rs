@@ -0,0 +1,559 @@
+use colored::Colorize;
+use serde_yaml::{Mapping, Value};
+use std::collections::HashSet;
+
+/// Policies every Mihomo config has, usable by groups and rules
+const BUILTIN_POLICIES: &[&str] = &[
+    "DIRECT",
+    "REJECT",
+    "REJECT-DROP",
+    "PASS",
+    "COMPATIBLE",
+    "GLOBAL",
+];
+
+const GROUP_TYPES: &[&str] = &["select", "url-test", "fallback", "load-balance", "relay"];
+
+/// Group keys that add members without listing them in `proxies` or `use`
+const INCLUDE_KEYS: &[&str] = &[
+    "include-all",
+    "include-all-proxies",
+    "include-all-providers",
+];
+
+/// Trailing rule fields that are options rather than the policy
+const RULE_OPTIONS: &[&str] = &["no-resolve", "src"];
+
+/// What a top-level value must be.
+#[derive(Clone, Copy)]
+enum Expected {
+    Bool,
+    Int,
+    Str,
+    Seq,
+    Map,
+    OneOf(&'static [&'static str]),
+}
+
+/// Top-level keys Mihomo reads and the type of their value
+const KNOWN_KEYS: &[(&str, Expected)] = &[
+    ("port", Expected::Int),
+    ("socks-port", Expected::Int),
+    ("redir-port", Expected::Int),
+    ("tproxy-port", Expected::Int),
+    ("mixed-port", Expected::Int),
+    ("authentication", Expected::Seq),
+    ("skip-auth-prefixes", Expected::Seq),
+    ("lan-allowed-ips", Expected::Seq),
+    ("lan-disallowed-ips", Expected::Seq),
+    ("allow-lan", Expected::Bool),
+    ("bind-address", Expected::Str),
+    ("mode", Expected::OneOf(&["rule", "global", "direct"])),
+    (
+        "log-level",
+        Expected::OneOf(&["silent", "error", "warning", "info", "debug"]),
+    ),
+    ("ipv6", Expected::Bool),
+    ("external-controller", Expected::Str),
+    ("external-controller-tls", Expected::Str),
+    ("external-controller-unix", Expected::Str),
+    ("external-controller-pipe", Expected::Str),
+    ("external-controller-cors", Expected::Map),
+    ("external-doh-server", Expected::Str),
+    ("external-ui", Expected::Str),
+    ("external-ui-name", Expected::Str),
+    ("external-ui-url", Expected::Str),
+    ("secret", Expected::Str),
+    ("interface-name", Expected::Str),
+    ("routing-mark", Expected::Int),
+    ("unified-delay", Expected::Bool),
+    ("tcp-concurrent", Expected::Bool),
+    (
+        "find-process-mode",
+        Expected::OneOf(&["always", "strict", "off"]),
+    ),
+    ("global-client-fingerprint", Expected::Str),
+    ("global-ua", Expected::Str),
+    ("etag-support", Expected::Bool),
+    ("keep-alive-idle", Expected::Int),
+    ("keep-alive-interval", Expected::Int),
+    ("disable-keep-alive", Expected::Bool),
+    ("inbound-tfo", Expected::Bool),
+    ("inbound-mptcp", Expected::Bool),
+    ("geodata-mode", Expected::Bool),
+    (
+        "geodata-loader",
+        Expected::OneOf(&["memconservative", "standard"]),
+    ),
+    ("geosite-matcher", Expected::Str),
+    ("geo-auto-update", Expected::Bool),
+    ("geo-update-interval", Expected::Int),
+    ("geox-url", Expected::Map),
+    ("profile", Expected::Map),
+    ("hosts", Expected::Map),
+    ("dns", Expected::Map),
+    ("tun", Expected::Map),
+    ("sniffer", Expected::Map),
+    ("tls", Expected::Map),
+    ("ntp", Expected::Map),
+    ("experimental", Expected::Map),
+    ("iptables", Expected::Map),
+    ("ebpf", Expected::Map),
+    ("clash-for-android", Expected::Map),
+    ("listeners", Expected::Seq),
+    ("tunnels", Expected::Seq),
+    ("proxies", Expected::Seq),
+    ("proxy-groups", Expected::Seq),
+    ("proxy-providers", Expected::Map),
+    ("rule-providers", Expected::Map),
+    ("sub-rules", Expected::Map),
+    ("rules", Expected::Seq),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    /// Mihomo runs, but likely not the way it was meant
+    Warning,
+    /// Mihomo refuses the config, or a reference leads nowhere
+    Error,
+}
+
+/// A mistake found in config.yaml, on a 1-based `line` when it could be located.
+#[derive(Debug)]
+pub struct Issue {
+    pub severity: Severity,
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+/// Checks a Mihomo config for unknown keys, values of the wrong type and
+/// references to nodes, groups, providers and sub-rules that don't exist.
+/// The issues are in the order they appear in `content`.
+pub fn lint_mihomo_config(content: &str) -> Vec<Issue> {
+    let mut lint = Lint {
+        lines: content.lines().collect(),
+        issues: Vec::new(),
+    };
+    let yaml: Value = match serde_yaml::from_str(content) {
+        Ok(yaml) => yaml,
+        Err(e) => {
+            lint.error(e.location().map(|l| l.line()), format!("Invalid YAML: {e}"));
+            return lint.issues;
+        }
+    };
+    let Some(map) = yaml.as_mapping() else {
+        lint.error(None, "The config is not a mapping of keys to values");
+        return lint.issues;
+    };
+    lint.top_level(map);
+
+    let nodes = lint.named("proxies", map, "Node");
+    let groups = lint.named("proxy-groups", map, "Group");
+    let providers = keys(map, "proxy-providers");
+    let rule_providers = keys(map, "rule-providers");
+    let sub_rules = keys(map, "sub-rules");
+    let refs = References {
+        nodes: &nodes,
+        groups: &groups,
+        providers: &providers,
+        rule_providers: &rule_providers,
+        sub_rules: &sub_rules,
+    };
+    for name in nodes.intersection(&groups) {
+        let line = lint.entry_line(name, lint.top_line("proxy-groups"));
+        lint.error(line, format!("{name} is both a node and a group"));
+    }
+    lint.groups(map, &refs);
+    lint.rules(map.get("rules"), "rules", &refs);
+    if let Some(Value::Mapping(subs)) = map.get("sub-rules") {
+        for (name, rules) in subs {
+            let name = name.as_str().unwrap_or_default();
+            lint.rules(Some(rules), name, &refs);
+        }
+    }
+
+    lint.issues
+        .sort_by_key(|issue| issue.line.unwrap_or(usize::MAX));
+    lint.issues
+}
+
+/// Prints each issue as `config.yaml:LINE: level: message`.
+pub fn print_issues(file_name: &str, issues: &[Issue]) {
+    for issue in issues {
+        let location = match issue.line {
+            Some(line) => format!("{file_name}:{line}"),
+            None => file_name.to_string(),
+        };
+        let level = match issue.severity {
+            Severity::Error => "error".red().bold(),
+            Severity::Warning => "warning".yellow().bold(),
+        };
+        println!("{}: {level}: {}", location.bold(), issue.message);
+    }
+}
+
+/// Names a rule or group may refer to.
+struct References<'a> {
+    nodes: &'a HashSet<String>,
+    groups: &'a HashSet<String>,
+    providers: &'a HashSet<String>,
+    rule_providers: &'a HashSet<String>,
+    sub_rules: &'a HashSet<String>,
+}
+
+impl References<'_> {
+    fn is_policy(&self, name: &str) -> bool {
+        BUILTIN_POLICIES.contains(&name) || self.nodes.contains(name) || self.groups.contains(name)
+    }
+}
+
+struct Lint<'a> {
+    lines: Vec<&'a str>,
+    issues: Vec<Issue>,
+}
+
+impl Lint<'_> {
+    fn error(&mut self, line: Option<usize>, message: impl Into<String>) {
+        self.issues.push(Issue {
+            severity: Severity::Error,
+            line,
+            message: message.into(),
+        });
+    }
+
+    fn warning(&mut self, line: Option<usize>, message: impl Into<String>) {
+        self.issues.push(Issue {
+            severity: Severity::Warning,
+            line,
+            message: message.into(),
+        });
+    }
+
+    /// Line of the top-level `key:`.
+    fn top_line(&self, key: &str) -> Option<usize> {
+        self.lines
+            .iter()
+            .position(|line| {
+                line.strip_prefix(key)
+                    .or_else(|| line.strip_prefix(&format!("\"{key}\"")))
+                    .is_some_and(|rest| rest.trim_start().starts_with(':'))
+            })
+            .map(|i| i + 1)
+    }
+
+    /// First line from `from` on that mentions `text`, falling back to `from`.
+    fn line_of(&self, text: &str, from: Option<usize>) -> Option<usize> {
+        let start = from.unwrap_or(1);
+        self.lines
+            .iter()
+            .enumerate()
+            .skip(start - 1)
+            .find(|(_, line)| line.contains(text))
+            .map(|(i, _)| i + 1)
+            .or(from)
+    }
+
+    /// Line of the `name:` of the list entry called `name`, from `from` on.
+    /// Entries are searched in order, so duplicates get their own line.
+    fn entry_line(&self, name: &str, from: Option<usize>) -> Option<usize> {
+        let start = from.unwrap_or(1);
+        self.lines
+            .iter()
+            .enumerate()
+            .skip(start - 1)
+            .find(|(_, line)| line.contains("name") && line.contains(name))
+            .map(|(i, _)| i + 1)
+    }
+
+    fn top_level(&mut self, map: &Mapping) {
+        for (key, value) in map {
+            let Some(key) = key.as_str() else {
+                self.error(None, format!("{key:?} is not a valid key"));
+                continue;
+            };
+            let line = self.top_line(key);
+            let Some((_, expected)) = KNOWN_KEYS.iter().find(|(known, _)| *known == key) else {
+                let hint = suggestion(key)
+                    .map(|known| format!(", did you mean {known}?"))
+                    .unwrap_or_default();
+                self.warning(line, format!("Unknown key {key}, Mihomo ignores it{hint}"));
+                continue;
+            };
+            if let Some(problem) = type_problem(value, *expected) {
+                self.error(line, format!("{key} {problem}"));
+            }
+        }
+    }
+
+    /// Names of the entries of the `key` list, reporting entries without a
+    /// name or type and duplicate names.
+    fn named(&mut self, key: &str, map: &Mapping, what: &str) -> HashSet<String> {
+        let mut names = HashSet::new();
+        let section = self.top_line(key);
+        let Some(Value::Sequence(entries)) = map.get(key) else {
+            return names;
+        };
+        let mut cursor = section;
+        for (i, entry) in entries.iter().enumerate() {
+            let Some(name) = entry.get("name").and_then(Value::as_str) else {
+                self.error(section, format!("{what} #{} in {key} has no name", i + 1));
+                continue;
+            };
+            let line = self.entry_line(name, cursor).or(section);
+            cursor = line.map(|line| line + 1);
+            if entry.get("type").and_then(Value::as_str).is_none() {
+                self.error(line, format!("{what} {name} has no type"));
+            }
+            if !names.insert(name.to_string()) {
+                self.error(line, format!("{what} {name} is defined more than once"));
+            }
+        }
+        names
+    }
+
+    fn groups(&mut self, map: &Mapping, refs: &References) {
+        let section = self.top_line("proxy-groups");
+        let Some(Value::Sequence(groups)) = map.get("proxy-groups") else {
+            return;
+        };
+        let mut cursor = section;
+        for group in groups {
+            let Some(name) = group.get("name").and_then(Value::as_str) else {
+                continue;
+            };
+            let line = self.entry_line(name, cursor).or(section);
+            cursor = line.map(|line| line + 1);
+            if let Some(kind) = group.get("type").and_then(Value::as_str) {
+                if !GROUP_TYPES.contains(&kind) {
+                    self.error(
+                        line,
+                        format!(
+                            "Group {name} has the unknown type {kind}, expected one of {}",
+                            GROUP_TYPES.join(", ")
+                        ),
+                    );
+                }
+            }
+
+            let members = strings(group.get("proxies"));
+            for member in &members {
+                if *member == name {
+                    self.error(line, format!("Group {name} lists itself"));
+                } else if !refs.is_policy(member) {
+                    let member_line = self.line_of(member, line);
+                    self.error(
+                        member_line,
+                        format!("Group {name} lists {member}, which is not a node or group"),
+                    );
+                }
+            }
+            let used = strings(group.get("use"));
+            for provider in &used {
+                if !refs.providers.contains(*provider) {
+                    let provider_line = self.line_of(provider, line);
+                    self.error(
+                        provider_line,
+                        format!("Group {name} uses {provider}, which is not in proxy-providers"),
+                    );
+                }
+            }
+            let includes = INCLUDE_KEYS
+                .iter()
+                .any(|key| group.get(key).and_then(Value::as_bool) == Some(true));
+            if members.is_empty() && used.is_empty() && !includes {
+                self.error(
+                    line,
+                    format!("Group {name} has no members, add proxies or use"),
+                );
+            }
+        }
+    }
+
+    /// Checks the policies, rule sets and sub-rules the rules of `list` refer
+    /// to. `list` is `rules` or the name of a sub-rule.
+    fn rules(&mut self, rules: Option<&Value>, list: &str, refs: &References) {
+        let Some(Value::Sequence(rules)) = rules else {
+            return;
+        };
+        let section = if list == "rules" {
+            self.top_line("rules")
+        } else {
+            self.line_of(list, self.top_line("sub-rules"))
+        };
+        let mut after_match_line = None;
+        let mut seen_match = false;
+        for rule in rules {
+            let Some(rule) = rule.as_str() else {
+                self.error(section, format!("{rule:?} in {list} is not a string"));
+                continue;
+            };
+            let line = self.line_of(rule, section);
+            if seen_match && after_match_line.is_none() {
+                after_match_line = line.or(section);
+            }
+            let Some((kind, payload, target)) = split_rule(rule) else {
+                self.error(line, format!("Invalid rule {rule}"));
+                continue;
+            };
+            match kind.as_str() {
+                "MATCH" => seen_match = true,
+                "RULE-SET" if !refs.rule_providers.contains(payload) => self.error(
+                    line,
+                    format!("{rule} uses {payload}, which is not in rule-providers"),
+                ),
+                "SUB-RULE" => {
+                    if !refs.sub_rules.contains(target) {
+                        self.error(
+                            line,
+                            format!("{rule} refers to {target}, which is not in sub-rules"),
+                        );
+                    }
+                    continue;
+                }
+                _ => {}
+            }
+            if !refs.is_policy(target) {
+                self.error(
+                    line,
+                    format!("{rule} sends traffic to {target}, which is not a node or group"),
+                );
+            }
+        }
+        if after_match_line.is_some() {
+            self.warning(
+                after_match_line,
+                format!("The rules from here on come after MATCH in {list} and never apply"),
+            );
+        }
+    }
+}
+
+/// Splits a rule into its type, its payload and the policy or sub-rule it leads to.
+fn split_rule(rule: &str) -> Option<(String, &str, &str)> {
+    let (kind, rest) = rule.split_once(',')?;
+    let kind = kind.trim().to_ascii_uppercase();
+    // AND,((DOMAIN,a.com),(NETWORK,UDP)),Proxy and SUB-RULE,(NETWORK,TCP),name
+    if matches!(kind.as_str(), "AND" | "OR" | "NOT" | "SUB-RULE") {
+        let close = rest.rfind(')')?;
+        let target = rest[close + 1..]
+            .trim_start_matches(',')
+            .split(',')
+            .next()?;
+        return Some((kind, rest[..=close].trim(), target.trim()));
+    }
+    let mut fields: Vec<&str> = rest.split(',').map(str::trim).collect();
+    while fields.len() > 1 && RULE_OPTIONS.contains(&fields[fields.len() - 1]) {
+        fields.pop();
+    }
+    let target = fields.pop().filter(|target| !target.is_empty())?;
+    let payload = fields.first().copied().unwrap_or_default();
+    if kind != "MATCH" && fields.is_empty() {
+        return None;
+    }
+    Some((kind, payload, target))
+}
+
+fn type_problem(value: &Value, expected: Expected) -> Option<String> {
+    let ok = match expected {
+        Expected::Bool => value.is_bool(),
+        Expected::Int => value.as_u64().is_some(),
+        Expected::Str => value.is_string(),
+        Expected::Seq => value.is_sequence(),
+        Expected::Map => value.is_mapping(),
+        Expected::OneOf(choices) => {
+            return match value.as_str() {
+                Some(s) if choices.iter().any(|c| c.eq_ignore_ascii_case(s)) => None,
+                Some(s) => Some(format!("is {s}, expected one of {}", choices.join(", "))),
+                None => Some(format!("must be one of {}", choices.join(", "))),
+            };
+        }
+    };
+    let name = match expected {
+        Expected::Bool => "true or false",
+        Expected::Int => "a number",
+        Expected::Str => "a string",
+        Expected::Seq => "a list",
+        Expected::Map => "a mapping",
+        Expected::OneOf(_) => unreachable!(),
+    };
+    (!ok).then(|| format!("must be {name}"))
+}
+
+/// The known key closest to a misspelled `key`.
+fn suggestion(key: &str) -> Option<&'static str> {
+    let normalized = key.to_ascii_lowercase().replace('_', "-");
+    KNOWN_KEYS
+        .iter()
+        .map(|(known, _)| (*known, strsim::levenshtein(&normalized, known)))
+        .filter(|(_, distance)| *distance <= 2)
+        .min_by_key(|(_, distance)| *distance)
+        .map(|(known, _)| known)
+}
+
+fn keys(map: &Mapping, key: &str) -> HashSet<String> {
+    map.get(key)
+        .and_then(Value::as_mapping)
+        .into_iter()
+        .flatten()
+        .filter_map(|(name, _)| name.as_str().map(String::from))
+        .collect()
+}
+
+fn strings(value: Option<&Value>) -> Vec<&str> {
+    value
+        .and_then(Value::as_sequence)
+        .into_iter()
+        .flatten()
+        .filter_map(Value::as_str)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_plain_rules() {
+        assert_eq!(
+            split_rule("domain-suffix, example.com, DIRECT"),
+            Some(("DOMAIN-SUFFIX".to_string(), "example.com", "DIRECT"))
+        );
+        assert_eq!(
+            split_rule("MATCH,Proxy"),
+            Some(("MATCH".to_string(), "", "Proxy"))
+        );
+    }
+
+    #[test]
+    fn drops_rule_options() {
+        assert_eq!(
+            split_rule("IP-CIDR,10.0.0.0/8,DIRECT,no-resolve"),
+            Some(("IP-CIDR".to_string(), "10.0.0.0/8", "DIRECT"))
+        );
+        assert_eq!(
+            split_rule("IP-CIDR,10.0.0.0/8,DIRECT,src,no-resolve"),
+            Some(("IP-CIDR".to_string(), "10.0.0.0/8", "DIRECT"))
+        );
+    }
+
+    #[test]
+    fn splits_logic_rules() {
+        assert_eq!(
+            split_rule("AND,((DOMAIN,a.com),(NETWORK,UDP)),Proxy"),
+            Some(("AND".to_string(), "((DOMAIN,a.com),(NETWORK,UDP))", "Proxy"))
+        );
+        assert_eq!(
+            split_rule("SUB-RULE,(NETWORK,TCP),tcp-rules"),
+            Some(("SUB-RULE".to_string(), "(NETWORK,TCP)", "tcp-rules"))
+        );
+    }
+
+    #[test]
+    fn rejects_incomplete_rules() {
+        assert_eq!(split_rule("DIRECT"), None);
+        assert_eq!(split_rule("DOMAIN,a.com,"), None);
+        // A payload is only optional for MATCH
+        assert_eq!(split_rule("DOMAIN,DIRECT"), None);
+        assert_eq!(split_rule("AND,(DOMAIN,a.com"), None);
+    }
+}
@@ -0,0 +1,170 @@
+use crate::controller::Controller;
+use anyhow::Result;
+use colored::{ColoredString, Colorize};
+use futures_util::{pin_mut, StreamExt};
+use log::*;
+use std::fs::{self, OpenOptions};
+use std::path::{Path, PathBuf};
+
+/// Mihomo's log levels, the least severe first.
+#[derive(clap::ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CoreLogLevel {
+    /// Also the rule each connection matched
+    Debug,
+    #[default]
+    Info,
+    Warning,
+    Error,
+}
+
+impl CoreLogLevel {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CoreLogLevel::Debug => "debug",
+            CoreLogLevel::Info => "info",
+            CoreLogLevel::Warning => "warning",
+            CoreLogLevel::Error => "error",
+        }
+    }
+
+    fn to_level(self) -> Level {
+        match self {
+            CoreLogLevel::Debug => Level::Debug,
+            CoreLogLevel::Info => Level::Info,
+            CoreLogLevel::Warning => Level::Warn,
+            CoreLogLevel::Error => Level::Error,
+        }
+    }
+}
+
+fn parse_level(level: &str) -> Option<Level> {
+    match level {
+        "debug" => Some(Level::Debug),
+        "info" => Some(Level::Info),
+        "warning" => Some(Level::Warn),
+        "error" | "fatal" | "panic" => Some(Level::Error),
+        _ => None,
+    }
+}
+
+fn colorize(level: Level, text: &str) -> ColoredString {
+    match level {
+        Level::Error => text.red(),
+        Level::Warn => text.yellow(),
+        Level::Info => text.normal(),
+        Level::Debug | Level::Trace => text.dimmed(),
+    }
+}
+
+/// Level and message of a Mihomo log line, `time="…" level=info msg="…"`.
+pub fn parse_core_line(line: &str) -> Option<(Level, String)> {
+    let rest = &line[line.find("level=")? + "level=".len()..];
+    let (level, rest) = rest.split_once(' ')?;
+    let level = parse_level(level)?;
+    let message = rest.strip_prefix("msg=")?;
+    let message = message
+        .strip_prefix('"')
+        .and_then(|m| m.strip_suffix('"'))
+        .map_or_else(|| message.to_string(), |m| m.replace("\\\"", "\""));
+    Some((level, message))
+}
+
+/// Prints the last `count` lines of the core's log file at `level` and above,
+/// colored by level. Lines in another format are always printed.
+pub fn print_log_tail(path: &Path, count: usize, level: CoreLogLevel) -> Result<()> {
+    let content = fs::read_to_string(path)?;
+    let lines: Vec<(Option<Level>, &str)> = content
+        .lines()
+        .map(|line| (parse_core_line(line).map(|(level, _)| level), line))
+        .filter(|(line_level, _)| line_level.is_none_or(|l| l <= level.to_level()))
+        .collect();
+    for (line_level, line) in &lines[lines.len().saturating_sub(count)..] {
+        println!("{}", colorize(line_level.unwrap_or(Level::Info), line));
+    }
+    Ok(())
+}
+
+/// Streams the core's log from the controller until it closes or Ctrl+C is
+/// pressed, one JSON object per line with `json`.
+pub async fn stream_remote_logs(
+    controller: &Controller,
+    level: CoreLogLevel,
+    json: bool,
+) -> Result<()> {
+    let stream = controller.logs(level.as_str()).await?;
+    pin_mut!(stream);
+    if !json {
+        info!("Streaming {} logs, press Ctrl+C to exit", level.as_str());
+    }
+    loop {
+        let entry = tokio::select! {
+            entry = stream.next() => match entry {
+                Some(entry) => entry?,
+                None => break,
+            },
+            _ = tokio::signal::ctrl_c() => break,
+        };
+        if json {
+            println!("{}", serde_json::to_string(&entry)?);
+            continue;
+        }
+        let line_level = parse_level(&entry.level).unwrap_or(Level::Info);
+        println!(
+            "{} {}",
+            colorize(line_level, &format!("{:<7}", entry.level.to_uppercase())).bold(),
+            colorize(line_level, &entry.payload)
+        );
+    }
+    Ok(())
+}
+
+/// `mihomo.log.1` for `n == 1`.
+fn rotated_path(path: &Path, n: usize) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{n}"));
+    PathBuf::from(name)
+}
+
+/// Shifts `path.1` to `path.2` and so on, dropping the oldest beyond `keep`.
+fn shift(path: &Path, keep: usize) -> Result<()> {
+    let _ = fs::remove_file(rotated_path(path, keep.max(1)));
+    for n in (1..keep).rev() {
+        let from = rotated_path(path, n);
+        if from.exists() {
+            fs::rename(&from, rotated_path(path, n + 1))?;
+        }
+    }
+    Ok(())
+}
+
+/// Archives a log nobody is writing to as `path.1`, keeping `keep` archives.
+/// With `keep == 0` the log is just removed.
+pub fn rotate(path: &Path, keep: usize) -> Result<()> {
+    if !path.exists() {
+        return Ok(());
+    }
+    if keep == 0 {
+        fs::remove_file(path)?;
+        return Ok(());
+    }
+    shift(path, keep)?;
+    fs::rename(path, rotated_path(path, 1))?;
+    Ok(())
+}
+
+/// Rotates a log that is still being written to once it exceeds `max_size`
+/// bytes: it is copied to `path.1` and truncated in place, so the writer must
+/// have opened it in append mode. Returns whether it was rotated.
+pub fn rotate_if_larger(path: &Path, max_size: u64, keep: usize) -> Result<bool> {
+    let size = fs::metadata(path).map(|m| m.len()).unwrap_or(0);
+    if size <= max_size {
+        return Ok(false);
+    }
+    if keep > 0 {
+        shift(path, keep)?;
+        fs::copy(path, rotated_path(path, 1))?;
+    }
+    OpenOptions::new().write(true).open(path)?.set_len(0)?;
+    debug!("Rotated {} ({size} bytes)", path.display());
+    Ok(true)
+}
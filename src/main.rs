@@ -1,41 +1,755 @@
-pub mod cli;
-pub mod config;
-pub mod downloader;
-pub mod mihomo;
-pub mod proxy_selector;
-pub mod tunnel;
-pub mod utils;
-
-use std::env;
-
-use crate::cli::{Cli, Commands};
-use crate::mihomo::MihomoManager;
-use crate::tunnel::try_tunnel_service;
+mod cli;
+
+use std::ffi::OsString;
+use std::fs::OpenOptions;
+use std::io::{IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::cli::{
+    AdblockCommands, BundleCommands, Cli, Commands, ConfigCommands, ConnectionsCommands,
+    CoreCommands, GeoCommands, GroupCommands, InstanceCommands, LanCommands, LogFormat,
+    MirrorsCommands, NoProxyCommands, ProvidersCommands, RuleCommands, SubCommands, SyncCommands,
+    SysproxyCommands, TunnelCommands, UiCommands,
+};
 use anyhow::Ok;
-use clap::Parser;
+use clap::{CommandFactory, Parser};
+use colored::Colorize;
 use log::*;
+use proxy::bench::{self, BenchOptions};
+use proxy::downloader::ProgressLogWriter;
+use proxy::errors;
+use proxy::latency;
+use proxy::limit::Limits;
+use proxy::output::{ColorChoice, Table};
+use proxy::overrides::CustomGroup;
+use proxy::settings::{resolve_data_dir, ViaProxy};
+use proxy::shell::Shell;
+use proxy::speedtest::{self, SpeedtestOptions};
+use proxy::tunnel::TunnelBackend;
+use proxy::wizard;
+use proxy::{
+    local_data_dir, BundleOptions, CleanTargets, Installation, MihomoManager, StartOptions,
+    TunnelOptions,
+};
 
-fn main() {
-    if env::var("RUST_LOG").is_err() {
-        env::set_var("RUST_LOG", "info")
-    }
-    env_logger::init();
+#[tokio::main]
+async fn main() {
     let cli = Cli::parse();
-    let manager = MihomoManager::new().unwrap_or_else(|e| {
+    proxy::output::set_color(cli.color);
+    if let Err(e) = init_logging(&cli) {
+        eprintln!("Failed to initialize logging: {e}");
+        std::process::exit(1);
+    }
+    // Prompts would hang a container without a terminal
+    let assume_yes = cli.yes || cli.container();
+    // Not a clap env default, --local overrides the variable, e.g. in a plugin
+    let data_dir = cli.data_dir.or_else(|| {
+        (!cli.local)
+            .then(|| std::env::var_os("PROXY_RS_DATA_DIR"))
+            .flatten()
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+    });
+    let explicit = data_dir.is_some();
+    let data_dir = resolve_data_dir(data_dir, cli.local).unwrap_or_else(|e| {
         error!("Failed to initialize: {e}");
         std::process::exit(1);
     });
+    if !explicit && local_data_dir().is_dir() && Installation::of(&data_dir) != Installation::Local
+    {
+        warn!(
+            "Using {}, pass --local to use the project-local ./proxy-data instead",
+            data_dir.display()
+        );
+    }
+    // `proxy stop work` is `proxy --instance work stop`
+    let instance = match &cli.command {
+        Some(Commands::Stop {
+            name: Some(name), ..
+        }) => Some(name.clone()),
+        _ => cli.instance.clone(),
+    };
+    // Only `instance create` makes new instances, a typo'd name must not
+    if let Some(name) = &instance {
+        let names = MihomoManager::instances(&data_dir).unwrap_or_default();
+        if !names.contains(name) {
+            error!("No instance named {name}, `proxy status --all` lists them and `proxy instance create {name}` adds it");
+            std::process::exit(1);
+        }
+    }
+    let mut manager =
+        MihomoManager::new(data_dir.clone(), instance.as_deref()).unwrap_or_else(|e| {
+            error!("Failed to initialize: {e}");
+            std::process::exit(1);
+        });
+    match cli.core {
+        Some(Some(core)) => manager.set_core(core),
+        // Only `clean --core` goes without a value, it removes the core binary
+        Some(None) if !matches!(cli.command, Some(Commands::Clean { .. })) => {
+            error!("--core needs a value: mihomo or sing-box");
+            std::process::exit(1);
+        }
+        _ => {}
+    }
+    if let Some(arch) = &cli.core_arch {
+        manager.set_core_arch(arch);
+    }
+    if cli.via_proxy {
+        manager.set_via_proxy(ViaProxy::Always);
+    }
+    if let Some(url) = &cli.delay_test_url {
+        manager.set_delay_test_url(url);
+    }
+    if let Some(secs) = cli.delay_test_interval {
+        manager.set_delay_test_interval(secs);
+    }
+    if cli.sticky_selection {
+        manager.set_sticky_selection();
+    }
+    if cli.reselect_mirror {
+        manager.set_reselect_mirror();
+    }
+    if let Some(url) = &cli.download_proxy {
+        if let Err(e) = manager.set_download_proxy(url) {
+            error!("Failed to initialize: {e}");
+            std::process::exit(1);
+        }
+    }
+    proxy::set_assume_yes(assume_yes);
 
     let result = match cli.command {
-        Some(Commands::Status) => manager.status(),
-        Some(Commands::Start { url }) => manager.start(url.as_deref()),
-        Some(Commands::Stop) => manager.stop(),
-        Some(Commands::Tunnel { port }) => try_tunnel_service(port),
+        Some(Commands::Status {
+            watch: Some(secs), ..
+        }) => manager.watch_status(Duration::from_secs(secs)).await,
+        Some(Commands::Status { all: false, .. }) => {
+            manager.status().await.and_then(|_| match instance {
+                Some(_) => Ok(()),
+                None => log_instances(&data_dir),
+            })
+        }
+        Some(Commands::Status { all: true, .. }) => status_all(&data_dir).await,
+        Some(Commands::Instance { command }) => match command {
+            InstanceCommands::Create { name } => MihomoManager::create_instance(&data_dir, &name)
+                .map(|()| {
+                    info!("Created instance {name}, start it with `proxy --instance {name} start`")
+                }),
+        },
+        Some(Commands::Init) => wizard::run_wizard(cli.download_proxy.as_deref())
+            .await
+            .map(|_| info!("Run `proxy start` to download the core and start")),
+        Some(Commands::Start {
+            url,
+            urls,
+            update_sub,
+            no_verify,
+            tun,
+            no_tun,
+            watch,
+            watch_config,
+            foreground,
+            container,
+            health_port,
+            memory_limit_mb,
+            nice,
+            open_files_limit,
+            check_url,
+            no_check,
+            auto_select,
+            auto_groups,
+            core_version,
+            core_path,
+            ui,
+            controller_tls,
+            controller_unix,
+            trace_startup,
+            mixed_port,
+            controller_port,
+            random_ports,
+            socks_port,
+            http_port,
+            listen,
+            subconverter,
+            dns,
+            nameservers,
+            ipv6,
+            interface,
+            include,
+            exclude,
+            bypass,
+        }) => {
+            if let Some(path) = core_path {
+                manager.set_core_path(path);
+            }
+            let mut urls: Vec<String> = url.into_iter().chain(urls).collect();
+            let (mut mixed_port, mut controller_port) = (mixed_port, controller_port);
+            if container {
+                let env = container_env().unwrap_or_else(|e| {
+                    error!("An error occurred: {e}");
+                    std::process::exit(1);
+                });
+                if urls.is_empty() && !update_sub {
+                    urls = env.urls;
+                }
+                mixed_port = mixed_port.or(env.mixed_port);
+                controller_port = controller_port.or(env.controller_port);
+            }
+            let options = StartOptions {
+                urls,
+                update_sub,
+                no_verify,
+                tun: (tun || no_tun).then_some(tun),
+                watch,
+                watch_config,
+                foreground: foreground || container,
+                container,
+                health_port,
+                memory_limit_mb,
+                nice,
+                open_files_limit,
+                check_url: (!no_check)
+                    .then(|| check_url.unwrap_or_else(|| manager.delay_test_url().to_string())),
+                auto_select,
+                auto_groups,
+                core_version,
+                mixed_port,
+                controller_port,
+                socks_port,
+                http_port,
+                listen,
+                controller_tls,
+                random_ports,
+                controller_unix,
+                trace_startup,
+                subconverter,
+                dns,
+                nameservers,
+                ipv6,
+                interface,
+                include,
+                exclude,
+                bypass,
+            };
+            match ui.map(|ui| manager.set_ui(ui)) {
+                Some(Err(e)) => Err(e),
+                _ => manager.start(&options).await.map(|_| ()),
+            }
+        }
+        Some(Commands::Update {
+            version,
+            channel,
+            no_verify,
+        }) => manager
+            .update_core(no_verify, version.as_deref(), channel)
+            .await
+            .map(|_| ()),
+        Some(Commands::CheckUpdate) => manager.check_update().await.map(|_| ()),
+        Some(Commands::Version) => manager.version().await,
+        Some(Commands::Report { archive }) => manager.bug_report(archive.as_deref()).await,
+        Some(Commands::SelfUpdate { no_verify, force }) => {
+            manager.self_update(no_verify, force).await.map(|_| ())
+        }
+        Some(Commands::Core { command }) => match command {
+            CoreCommands::List => manager.list_core_releases().await,
+            CoreCommands::Install { tag, no_verify } => manager
+                .install_core(no_verify, tag.as_deref())
+                .await
+                .map(|_| ()),
+        },
+        Some(Commands::Clean {
+            all,
+            cache,
+            ui,
+            force,
+        }) => {
+            let core = cli.core == Some(None);
+            // Without any selection, clean everything that can be downloaded again
+            let all = all || !(cache || core || ui);
+            manager
+                .clean(
+                    &CleanTargets {
+                        core: all || core,
+                        ui: all || ui,
+                        cache: all || cache,
+                    },
+                    force,
+                )
+                .await
+        }
+        Some(Commands::Uninstall { force }) => manager.uninstall(force).await,
+        Some(Commands::Adopt { pid }) => manager.adopt_external_core(pid),
+        Some(Commands::Reload) => manager.reload().await,
+        Some(Commands::Rollback) => manager.rollback().await.map(|_| ()),
+        Some(Commands::Stop {
+            all: false,
+            timeout,
+            ..
+        }) => {
+            if let Some(secs) = timeout {
+                manager.set_stop_timeout(secs);
+            }
+            manager.stop().await
+        }
+        Some(Commands::Stop {
+            all: true, timeout, ..
+        }) => stop_all(&data_dir, timeout).await,
+        Some(Commands::Restart { timeout }) => {
+            if let Some(secs) = timeout {
+                manager.set_stop_timeout(secs);
+            }
+            manager.restart().await
+        }
+        Some(Commands::Test { group, url, json }) => manager
+            .test_latency(
+                group.as_deref(),
+                url.as_deref().unwrap_or(manager.delay_test_url()),
+            )
+            .await
+            .and_then(|results| {
+                latency::print_latency_table(&results);
+                match json {
+                    Some(path) => latency::write_latency_json(&results, &path),
+                    None => Ok(()),
+                }
+            }),
+        Some(Commands::Speedtest {
+            group,
+            url,
+            duration,
+            top,
+        }) => {
+            let options = SpeedtestOptions {
+                group,
+                url,
+                delay_test_url: manager.delay_test_url().to_string(),
+                duration: Duration::from_secs(duration),
+                top,
+            };
+            manager
+                .speedtest(&options)
+                .await
+                .map(|results| speedtest::print_speed_table(&results))
+        }
+        Some(Commands::Bench {
+            group,
+            url,
+            rounds,
+            timeout,
+            sort,
+            csv,
+            json,
+        }) => {
+            let options = BenchOptions {
+                group,
+                delay_test_url: manager.delay_test_url().to_string(),
+                urls: url,
+                rounds,
+                timeout_ms: timeout,
+                sort,
+            };
+            manager.bench(&options).await.and_then(|results| {
+                bench::print_bench_table(&results);
+                if let Some(path) = csv {
+                    bench::write_bench_csv(&results, &path)?;
+                }
+                match json {
+                    Some(path) => bench::write_bench_json(&results, &path),
+                    None => Ok(()),
+                }
+            })
+        }
+        Some(Commands::Traffic { json }) => manager.watch_traffic(json).await,
+        Some(Commands::Limit {
+            up,
+            down,
+            window,
+            cooldown,
+        }) => {
+            let limits = Limits {
+                up,
+                down,
+                window,
+                cooldown,
+            };
+            manager.limit(&limits).await
+        }
+        Some(Commands::Logs {
+            remote,
+            level,
+            lines,
+            json,
+        }) => manager.logs(remote, level, lines, json).await,
+        Some(Commands::Connections { watch, command }) => match command {
+            Some(ConnectionsCommands::Kill { id, all }) => {
+                manager.kill_connections(id.as_deref(), all).await
+            }
+            None => manager.connections(watch).await,
+        },
+        Some(Commands::Dashboard) => manager.dashboard().await,
+        Some(Commands::Doctor) => manager.doctor().await,
+        Some(Commands::Geo {
+            command: GeoCommands::Update { force },
+        }) => manager.update_geodata(force).await,
+        Some(Commands::Geoip { target }) => manager.geoip(&target).await,
+        Some(Commands::Sub {
+            command: SubCommands::Refresh,
+        }) => manager.refresh_subscription().await,
+        Some(Commands::Ui {
+            command: UiCommands::Update,
+        }) => manager.update_ui().await,
+        Some(Commands::Import {
+            client,
+            path,
+            profile,
+            list,
+        }) => {
+            if list {
+                manager.import_profiles(client, path.as_deref())
+            } else {
+                manager
+                    .import_profile(client, path.as_deref(), profile.as_deref())
+                    .await
+            }
+        }
+        Some(Commands::Backup { file }) => manager.backup(&file),
+        Some(Commands::Restore { file, force }) => manager.restore(&file, force),
+        Some(Commands::Bundle { command }) => match command {
+            BundleCommands::Create {
+                file,
+                os,
+                core_version,
+                no_verify,
+            } => {
+                let options = BundleOptions {
+                    os,
+                    core_version,
+                    no_verify,
+                };
+                manager.bundle_create(&file, &options).await
+            }
+            BundleCommands::Install { file } => manager.bundle_install(&file).await,
+        },
+        Some(Commands::Schedule { list }) => manager.schedule(list).await,
+        Some(Commands::Sync { command }) => match command {
+            SyncCommands::Push => manager.sync_push(),
+            SyncCommands::Pull => manager.sync_pull(),
+        },
+        Some(Commands::Config {
+            command: ConfigCommands::Export { redact },
+        }) => manager
+            .export_config(redact)
+            .map(|config| print!("{config}")),
+        Some(Commands::Config {
+            command: ConfigCommands::Edit,
+        }) => manager.edit_config().await,
+        Some(Commands::Config {
+            command: ConfigCommands::Check,
+        }) => manager.check_config().await,
+        Some(Commands::Providers {
+            command: ProvidersCommands::Update { kind, name },
+        }) => manager.update_providers(kind, name.as_deref()).await,
+        Some(Commands::Mirrors {
+            command: MirrorsCommands::Test,
+        }) => manager.test_mirrors().await,
+        Some(Commands::Select { group }) => manager.select(group.as_deref()).await.map(|_| ()),
+        Some(Commands::Mode { mode }) => manager.mode(mode).await.map(|_| ()),
+        Some(Commands::Pause) => manager.pause().await,
+        Some(Commands::Resume) => manager.resume().await,
+        Some(Commands::Chain {
+            entry: Some(entry),
+            exit: Some(exit),
+            select,
+            remove: false,
+        }) => manager.chain(&entry, &exit, select).await,
+        Some(Commands::Chain { .. }) => manager.unchain().await,
+        Some(Commands::Rule { command }) => match command {
+            RuleCommands::Add { rule } => manager.add_rule(&rule).await,
+            RuleCommands::Remove { rule } => manager.remove_rule(&rule).await,
+            RuleCommands::List { all } => manager
+                .rules(all)
+                .map(|rules| rules.iter().for_each(|rule| println!("{rule}"))),
+            RuleCommands::Try { targets } => manager.try_rules(&targets),
+        },
+        Some(Commands::Which { target, dial }) => manager.which(&target, dial).await,
+        Some(Commands::Stats {
+            node,
+            since,
+            sample,
+        }) => {
+            if sample {
+                manager.sample_stats().await
+            } else {
+                manager.stats(node.as_deref(), since)
+            }
+        }
+        Some(Commands::Adblock { command }) => match command {
+            AdblockCommands::On => manager.adblock(true).await,
+            AdblockCommands::Off => manager.adblock(false).await,
+        },
+        Some(Commands::Group { command }) => match command {
+            GroupCommands::Add {
+                name,
+                kind,
+                include,
+                exclude,
+                select,
+            } => {
+                let group = CustomGroup {
+                    name,
+                    kind,
+                    include,
+                    exclude,
+                    select,
+                };
+                manager.add_group(group).await
+            }
+            GroupCommands::Remove { name } => manager.remove_group(&name).await,
+        },
+        Some(Commands::Lan { command }) => match command {
+            LanCommands::On => manager.lan(true).await,
+            LanCommands::Off => manager.lan(false).await,
+        },
+        Some(Commands::Sysproxy { command }) => match command {
+            SysproxyCommands::On => manager.sysproxy_on(),
+            SysproxyCommands::Off => manager.sysproxy_off(),
+        },
+        Some(Commands::Share) => manager.share(),
+        Some(Commands::NoProxy { command }) => match command {
+            NoProxyCommands::Add { hosts } => manager.add_no_proxy(&hosts),
+            NoProxyCommands::Remove { hosts } => manager.remove_no_proxy(&hosts),
+            NoProxyCommands::List => {
+                manager
+                    .no_proxy()
+                    .iter()
+                    .for_each(|host| println!("{host}"));
+                Ok(())
+            }
+            NoProxyCommands::Reset => manager.reset_no_proxy(),
+        },
+        Some(Commands::Env { shell }) => manager
+            .env(shell.unwrap_or_else(Shell::detect))
+            .map(|exports| print!("{exports}")),
+        Some(Commands::Exec { command }) => manager.exec(&command).map(|code| {
+            std::process::exit(code);
+        }),
+        Some(Commands::Tunnel {
+            port,
+            service,
+            tailscale,
+            proxy,
+            json,
+            no_fallback,
+            retry,
+            detach,
+            command,
+        }) => match (command, port) {
+            (Some(TunnelCommands::Status { json }), _) => manager.tunnel_status(json).map(|_| ()),
+            (Some(TunnelCommands::Stop), _) => manager.tunnel_stop().await,
+            (None, port) => {
+                let options = TunnelOptions {
+                    service: if tailscale {
+                        TunnelBackend::Tailscale
+                    } else {
+                        service
+                    },
+                    json,
+                    detach,
+                    no_fallback,
+                    retries: retry,
+                    proxy,
+                };
+                manager.tunnel(port, &options).await
+            }
+        },
+        Some(Commands::External(args)) => run_plugin(&manager, &args),
         None => Ok(()),
     };
 
     if let Err(e) = result {
         error!("An error occurred: {e}");
+        if let Some(hint) = errors::hint(&e) {
+            warn!("Hint: {hint}");
+        }
         std::process::exit(1);
     }
 }
+
+/// Sets up env_logger from the logging flags, falling back to `RUST_LOG` or info.
+fn init_logging(cli: &Cli) -> anyhow::Result<()> {
+    let mut builder = match cli.log_level() {
+        Some(level) => {
+            // Dependencies are noisy at debug level, RUST_LOG still enables them
+            let mut builder = env_logger::Builder::new();
+            builder
+                .filter_level(level.min(LevelFilter::Info))
+                .filter_module("proxy", level);
+            builder
+        }
+        None => env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")),
+    };
+
+    if let Some(path) = &cli.log_file {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        builder.target(env_logger::Target::Pipe(Box::new(file)));
+        builder.write_style(env_logger::WriteStyle::Never);
+    } else if cli.container() {
+        // Container runtimes collect stdout, and show no progress bars
+        builder.target(env_logger::Target::Stdout);
+    } else {
+        // Parallel downloads draw their progress bars on stderr. env_logger
+        // doesn't color a pipe by itself, so decide the way it does for stderr
+        builder.target(env_logger::Target::Pipe(Box::new(ProgressLogWriter)));
+        if let Some(style) = std::env::var_os("RUST_LOG_STYLE") {
+            builder.parse_write_style(&style.to_string_lossy());
+        } else if std::env::var_os("NO_COLOR").is_none() && std::io::stderr().is_terminal() {
+            builder.write_style(env_logger::WriteStyle::Always);
+        }
+    }
+    if cli.log_file.is_none() {
+        match cli.color {
+            ColorChoice::Auto => {}
+            ColorChoice::Never => {
+                builder.write_style(env_logger::WriteStyle::Never);
+            }
+            ColorChoice::Always => {
+                builder.write_style(env_logger::WriteStyle::Always);
+            }
+        }
+    }
+
+    if cli.log_format == LogFormat::Json {
+        builder.format(|buf, record| {
+            let line = serde_json::json!({
+                "timestamp": buf.timestamp().to_string(),
+                "level": record.level().as_str(),
+                "target": record.target(),
+                "message": record.args().to_string(),
+            });
+            writeln!(buf, "{line}")
+        });
+    }
+
+    builder.try_init()?;
+    Ok(())
+}
+
+/// Settings of `start --container` from the environment, flags take precedence.
+struct ContainerEnv {
+    /// `SUBSCRIPTION_URL`, several separated by whitespace
+    urls: Vec<String>,
+    /// `MIXED_PORT`
+    mixed_port: Option<u16>,
+    /// `CONTROLLER_PORT`
+    controller_port: Option<u16>,
+}
+
+fn container_env() -> anyhow::Result<ContainerEnv> {
+    let port = |name: &str| -> anyhow::Result<Option<u16>> {
+        match std::env::var(name) {
+            std::result::Result::Ok(value) if !value.trim().is_empty() => {
+                let port = value
+                    .trim()
+                    .parse()
+                    .map_err(|_| anyhow::anyhow!("{name}={value} is not a port"))?;
+                Ok(Some(port))
+            }
+            _ => Ok(None),
+        }
+    };
+    Ok(ContainerEnv {
+        urls: std::env::var("SUBSCRIPTION_URL")
+            .unwrap_or_default()
+            .split_whitespace()
+            .map(String::from)
+            .collect(),
+        mixed_port: port("MIXED_PORT")?,
+        controller_port: port("CONTROLLER_PORT")?,
+    })
+}
+
+/// Stops the default instance and every named instance.
+async fn stop_all(data_dir: &Path, timeout: Option<u64>) -> anyhow::Result<()> {
+    for mut manager in all_managers(data_dir)? {
+        if let Some(secs) = timeout {
+            manager.set_stop_timeout(secs);
+        }
+        manager.stop().await?;
+    }
+    Ok(())
+}
+
+/// Logs the status of the default instance and every named instance.
+async fn status_all(data_dir: &Path) -> anyhow::Result<()> {
+    for (i, manager) in all_managers(data_dir)?.into_iter().enumerate() {
+        if i > 0 {
+            println!();
+        }
+        manager.status().await?;
+    }
+    Ok(())
+}
+
+/// Lists the named instances below the status of the default one.
+fn log_instances(data_dir: &Path) -> anyhow::Result<()> {
+    let names = MihomoManager::instances(data_dir)?;
+    if names.is_empty() {
+        return Ok(());
+    }
+    let mut table =
+        Table::new(["Instance", "Status", "Mixed port", "Controller port"]).align_right([2, 3]);
+    for name in names {
+        let manager = MihomoManager::new(data_dir.to_path_buf(), Some(&name))?;
+        let state = match manager.is_running()? {
+            Some(pid) => format!("running (pid: {pid})").green(),
+            None => "not running".red(),
+        };
+        let (mixed_port, controller_port) = manager.configured_ports();
+        let port = |port: Option<u16>| port.map_or_else(|| "-".to_string(), |p| p.to_string());
+        table.row([
+            name.into(),
+            state,
+            port(mixed_port).into(),
+            port(controller_port).into(),
+        ]);
+    }
+    println!();
+    table.print();
+    info!("`proxy status --instance NAME` shows the details of one");
+    Ok(())
+}
+
+/// Runs the plugin for an unknown subcommand, exiting with its exit code.
+/// Without one, suggests the built-in command closest to the name.
+fn run_plugin(manager: &MihomoManager, args: &[OsString]) -> anyhow::Result<()> {
+    let (name, args) = args.split_first().expect("clap passes the subcommand name");
+    let name = name.to_string_lossy();
+    let code = manager.run_plugin(&name, args).map_err(|e| {
+        let closest = Cli::command()
+            .get_subcommands()
+            .map(|command| command.get_name().to_string())
+            // Ties go to the command of the closest length, `stauts` is `status`
+            .map(|command| {
+                let distance = strsim::damerau_levenshtein(&name, &command);
+                (distance, command.len().abs_diff(name.len()), command)
+            })
+            .filter(|(distance, ..)| *distance <= 2)
+            .min();
+        match closest {
+            Some((.., command)) => anyhow::anyhow!("{e}, did you mean `{command}`?"),
+            None => e,
+        }
+    })?;
+    if code != 0 {
+        std::process::exit(code);
+    }
+    Ok(())
+}
+
+/// Managers for the default instance and every named instance.
+fn all_managers(data_dir: &Path) -> anyhow::Result<Vec<MihomoManager>> {
+    let mut managers = vec![MihomoManager::new(data_dir.to_path_buf(), None)?];
+    for name in MihomoManager::instances(data_dir)? {
+        managers.push(MihomoManager::new(data_dir.to_path_buf(), Some(&name))?);
+    }
+    Ok(managers)
+}
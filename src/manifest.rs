@@ -0,0 +1,170 @@
+use crate::downloader::sha256_file;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+pub const MANIFEST_FILE: &str = "manifest.json";
+/// Same for the sing-box core, installed next to Mihomo.
+pub const SING_BOX_MANIFEST_FILE: &str = "manifest.sing-box.json";
+
+/// What proxy-rs has installed in the data dir.
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct Manifest {
+    /// Release tag of the installed core binary, e.g. `v1.18.10`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub core_version: Option<String>,
+    /// Release tag of the binary kept as `<core>.bak` for `rollback`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub previous_core_version: Option<String>,
+    /// The installed core binary
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub core: Option<Artifact>,
+    /// The binary kept as `<core>.bak`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub previous_core: Option<Artifact>,
+    /// The downloaded WebUIs, by folder name
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub ui: BTreeMap<String, Artifact>,
+    /// The downloaded geodata files, by path relative to the data dir
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub geodata: BTreeMap<String, Artifact>,
+}
+
+/// A download proxy-rs installed.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub struct Artifact {
+    /// GitHub URL, without the mirror it went through
+    pub url: String,
+    /// SHA256 of the installed file, for a folder of the archive it was extracted from
+    pub sha256: String,
+    pub installed_at: jiff::Timestamp,
+    /// Size and modification time of the installed file when it was hashed,
+    /// while they match the file isn't hashed again
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub modified: Option<jiff::Timestamp>,
+}
+
+impl Artifact {
+    pub fn new(url: &str, sha256: String) -> Self {
+        Self {
+            url: url.to_string(),
+            sha256,
+            installed_at: jiff::Timestamp::now(),
+            size: None,
+            modified: None,
+        }
+    }
+
+    /// An installed file, hashed now.
+    pub fn for_file(url: &str, path: &Path) -> Result<Self> {
+        let (size, modified) = stat(path);
+        Ok(Self {
+            size,
+            modified,
+            ..Self::new(url, sha256_file(path)?)
+        })
+    }
+
+    /// Whether `path` still has the size and modification time it was hashed with.
+    fn unchanged(&self, path: &Path) -> bool {
+        let (size, modified) = stat(path);
+        self.size.is_some()
+            && self.modified.is_some()
+            && (size, modified) == (self.size, self.modified)
+    }
+}
+
+fn stat(path: &Path) -> (Option<u64>, Option<jiff::Timestamp>) {
+    let Ok(metadata) = fs::metadata(path) else {
+        return (None, None);
+    };
+    let modified = metadata
+        .modified()
+        .ok()
+        .and_then(|time| jiff::Timestamp::try_from(time).ok());
+    (Some(metadata.len()), modified)
+}
+
+/// How an installed file compares to its record in the manifest.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Integrity {
+    Missing,
+    Intact,
+    /// Differs from the download, e.g. replaced by hand or cut short
+    Changed,
+    /// Installed before the manifest recorded it, or put there by the user
+    Unrecorded,
+}
+
+/// Checks `path` against `artifact`. Folders only need to exist.
+pub fn integrity(artifact: Option<&Artifact>, path: &Path) -> Integrity {
+    if !path.exists() {
+        return Integrity::Missing;
+    }
+    let Some(artifact) = artifact else {
+        return Integrity::Unrecorded;
+    };
+    if !path.is_file() || artifact.unchanged(path) {
+        return Integrity::Intact;
+    }
+    match sha256_file(path) {
+        Ok(actual) if actual.eq_ignore_ascii_case(&artifact.sha256) => Integrity::Intact,
+        _ => Integrity::Changed,
+    }
+}
+
+impl Manifest {
+    pub fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            // Written as TOML before it moved to JSON
+            let legacy = path.with_extension("toml");
+            if legacy.exists() {
+                let content = fs::read_to_string(&legacy)?;
+                return toml::from_str(&content)
+                    .with_context(|| format!("Invalid {}", legacy.display()));
+            }
+            return Ok(Self::default());
+        }
+        let content = fs::read_to_string(path)?;
+        serde_json::from_str(&content).with_context(|| format!("Invalid {}", path.display()))
+    }
+
+    /// Saves the manifest, or removes it once nothing is recorded.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let legacy = path.with_extension("toml");
+        if legacy.exists() {
+            fs::remove_file(legacy)?;
+        }
+        if self.is_empty() {
+            if path.exists() {
+                fs::remove_file(path)?;
+            }
+            return Ok(());
+        }
+        fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    fn is_empty(&self) -> bool {
+        self.core_version.is_none()
+            && self.previous_core_version.is_none()
+            && self.core.is_none()
+            && self.previous_core.is_none()
+            && self.ui.is_empty()
+            && self.geodata.is_empty()
+    }
+
+    /// Forgets the core binary and its `.bak`, after `clean --core`.
+    pub fn forget_core(&mut self) {
+        self.core_version = None;
+        self.previous_core_version = None;
+        self.core = None;
+        self.previous_core = None;
+    }
+}
@@ -1,338 +1,6696 @@
-use crate::config::{handle_subscription_config, update_external_controller, update_mixed_port};
-use crate::downloader::{decompress_gz, decompress_zip, download_file_with_progress, unzip_file};
-use crate::proxy_selector::select_fastest_github_proxy;
-use crate::utils::find_unused_port;
-use anyhow::{anyhow, Context, Ok, Result};
+use crate::adopt::{self, External};
+use crate::backend::{self, ArchiveType, Channel, CoreBackend, CoreKind};
+use crate::backup;
+use crate::bench::{self, BenchOptions, BenchResult};
+use crate::bundle::{self, BundleManifest, BundledFile};
+use crate::cache::SharedCache;
+use crate::child;
+use crate::config::{
+    add_auto_groups, add_tunnel_listener, check_config_content, config_template, edit_backup_path,
+    edit_config_content, handle_subscription_config, parse_allow_lan, parse_controller_tls,
+    parse_geodata_mode, parse_listener_port, pristine_path, rebuild_from_pristine, remove_key,
+    remove_tunnel_listener, replace_config, restore_backup, skips_loopback_auth, update_allow_lan,
+    update_controller_tls, update_listener_port, update_mode,
+};
+use crate::connections;
+use crate::controller::{
+    Controller, ControllerAddress, Mode, ProviderKind, DEFAULT_DELAY_TEST_URL,
+    DEFAULT_DELAY_TIMEOUT_MS,
+};
+use crate::dashboard;
+use crate::doctor;
+use crate::downloader::{
+    client_builder, decompress_gz, decompress_tar_gz, download_with_retries, sha256_file,
+    unzip_file, verify_sha256,
+};
+use crate::errors;
+use crate::geoip::{self, Mmdb};
+use crate::health::{self, HealthSource};
+use crate::hooks::{self, Event, Stage, SCRIPTS_DIR};
+use crate::import::{self, ImportClient, Profile};
+use crate::latency::{self, LatencyResult};
+use crate::limit::{self, Limits};
+use crate::lint::{self, Issue, Severity};
+use crate::logs::{self, CoreLogLevel};
+use crate::manifest::{self, Artifact, Integrity, Manifest, MANIFEST_FILE, SING_BOX_MANIFEST_FILE};
+use crate::network::{NetworkAction, NetworkChange, NetworkMonitor};
+use crate::output::{format_bytes, format_duration, Fields};
+use crate::overrides::{
+    check_relay, config_rules, parse_rule, remove_config_group, remove_config_rule,
+    remove_relay_group, Bypass, CustomGroup, DnsMode, Overrides, Relay, ADBLOCK_FILE, ADBLOCK_URL,
+    OVERRIDES_FILE, RELAY_GROUP,
+};
+use crate::picker;
+use crate::provider_health::{ProviderHealth, PROVIDER_HEALTH_FILE};
+use crate::providers;
+use crate::proxy_selector::{
+    fallback_github_proxy, github_mirrors, measure_github_proxies, proxy_display_name,
+    select_fastest_github_proxy, MirrorCache, MIRROR_CACHE_FILE,
+};
+use crate::resources::ResourceLimits;
+use crate::rule_match::RuleMatcher;
+use crate::schedule::{Cron, Job};
+use crate::self_update;
+use crate::settings::{
+    remove_setting, save_setting, Settings, ViaProxy, WebUi, DEFAULT_NO_PROXY, SETTINGS_FILE,
+};
+use crate::share;
+use crate::shell::{self, Shell};
+use crate::speedtest::{self, SpeedResult, SpeedtestOptions};
+use crate::stats::{self, STATS_FILE};
+use crate::subscription::{
+    subscription_name, DownloadFailed, Downloaded, SubscriptionInfo, Validators, SUBSCRIPTION_FILE,
+};
+use crate::sync::{self, SYNC_DIR};
+use crate::sysproxy::{self, SYSPROXY_BACKUP_FILE};
+use crate::timing::StartupTimer;
+use crate::tls;
+use crate::traffic;
+use crate::tunnel::{self, BackgroundTunnel, Exposed, TunnelBackend};
+#[cfg(target_os = "linux")]
+use crate::utils::grant_tun_capabilities;
+use crate::utils::{
+    ask_for_confirmation, assume_yes, default_cache_dir, find_random_port, find_unused_port,
+    find_unused_port_except, generate_secret, has_tun_privileges, is_elevated, lan_ip, lock_file,
+    make_private, warn_missing_tun_privileges, warn_port_taken, Installation,
+};
+use anyhow::{anyhow, Context, Result};
+use clap::ValueEnum;
+use colored::Colorize;
+use futures_util::future::join_all;
 use log::*;
-use reqwest::blocking::Client;
-use std::fs::{self, File};
-use std::path::PathBuf;
+use notify::{RecursiveMode, Watcher};
+use rand::Rng;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use std::ffi::{OsStr, OsString};
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, IsTerminal, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::path::{Path, PathBuf};
 use std::process::{Child, Command, Stdio};
 use std::str::FromStr;
-const PROXY_DATA_DIR: &str = "proxy-data";
-const MIHOMO_PID_FILE: &str = "proxy-data/mihomo.pid";
+use std::time::{Duration, Instant};
+const INSTANCES_DIR: &str = "instances";
+const MIHOMO_PID_FILE: &str = "mihomo.pid";
+/// Core the running process was started with, next to its pid file
+const CORE_FILE: &str = "core";
+/// Config and controller of a core started outside proxy-rs and adopted
+const ADOPTED_FILE: &str = "adopted.toml";
+const WATCHDOG_PID_FILE: &str = "watchdog.pid";
+/// Executables named `proxy-rs-<command>` on PATH add commands to proxy-rs
+const PLUGIN_PREFIX: &str = "proxy-rs-";
+/// Left by `restart` so the watchdog starts the core again without counting a crash
+const RESTART_REQUEST_FILE: &str = "restart-requested";
+const LOCK_FILE: &str = "proxy-rs.lock";
+/// Mode and selections saved by `pause` for `resume`
+const PAUSED_FILE: &str = "paused.toml";
+/// Mode and GLOBAL node `limit` restores after blocking traffic
+const LIMIT_BLOCKED_FILE: &str = "limit-blocked.toml";
+/// Selections saved before a stop or reload with `sticky-selection`
+const SELECTIONS_FILE: &str = "selections.toml";
+const CORE_LOCK_FILE: &str = "core.lock";
+/// Where bundles are assembled and extracted
+const BUNDLE_STAGING_DIR: &str = "bundle.tmp";
+const SECRET_FILE: &str = "secret";
+/// Subscription URL last passed to `start`, for `start --update-sub` and `sub refresh`
+const SUBSCRIPTION_URL_FILE: &str = "subscription-url";
+/// Time of the last successful subscription download, for `/healthz`
+const SUBSCRIPTION_REFRESHED_FILE: &str = "subscription-refreshed";
+/// JavaScript enhancements of imported profiles, kept next to config.yaml
+const IMPORTED_SCRIPTS_DIR: &str = "imported-scripts";
+/// Controller socket of `start --controller-unix`, in the private config dir
+const CONTROLLER_SOCKET_FILE: &str = "controller.sock";
+/// Longest socket path every Unix accepts, `sun_path` is 104 bytes on macOS
+const MAX_SOCKET_PATH: usize = 103;
+pub const DEFAULT_MIXED_PORT: u16 = 7890;
+pub const DEFAULT_CONTROLLER_PORT: u16 = 9090;
 
+/// Longest sleep between checks for due `[schedule]` jobs
+const SCHEDULE_MAX_SLEEP: Duration = Duration::from_secs(60);
+const GEODATA_MAX_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+
+const DEFAULT_DOWNLOAD_RETRIES: u32 = 3;
+
+const DEFAULT_QUOTA_LOW_PERCENT: u8 = 10;
+
+const LOG_FILES: [&str; 2] = ["mihomo.log", "mihomo.err"];
+const DEFAULT_LOG_MAX_SIZE_MB: u64 = 10;
+const DEFAULT_LOG_KEEP: usize = 3;
+const DEFAULT_STOP_TIMEOUT_SECS: u64 = 5;
+
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(15);
+
+const WATCHDOG_MIN_BACKOFF: Duration = Duration::from_secs(1);
+const WATCHDOG_MAX_BACKOFF: Duration = Duration::from_secs(60);
+/// Uptime after which a crash is no longer considered part of a crash loop
+const WATCHDOG_STABLE_UPTIME: Duration = Duration::from_secs(300);
+/// Time for DHCP and DNS to settle after a network change before the watchdog acts on it
+const NETWORK_SETTLE: Duration = Duration::from_secs(3);
+/// Lines of mihomo.log and mihomo.err included in a `report`
+const REPORT_LOG_LINES: usize = 50;
+/// Editors save in several steps, `--watch-config` waits for them to settle
+const CONFIG_WATCH_DEBOUNCE: Duration = Duration::from_millis(500);
+/// User of the listener `tunnel --proxy` opens, the password is generated
+const TUNNEL_USERNAME: &str = "proxy-rs";
+
+/// How [`MihomoManager::start`] prepares and runs Mihomo.
+#[derive(Debug, Default)]
+pub struct StartOptions {
+    /// Subscription URLs to download the config from, merged if there are several,
+    /// otherwise the existing config is used
+    pub urls: Vec<String>,
+    /// Skip SHA256 verification of a downloaded Mihomo binary
+    pub no_verify: bool,
+    /// Turn TUN mode, which needs root/administrator, on or off, saved to the
+    /// override file
+    pub tun: Option<bool>,
+    /// Stay in the foreground and restart Mihomo whenever it exits
+    pub watch: bool,
+    /// Stay in the foreground and reload the config whenever it or the override file changes
+    pub watch_config: bool,
+    /// Run the core as a child, logging its output, until Ctrl+C or SIGTERM stops it
+    pub foreground: bool,
+    /// Serve `/healthz` on this port with `foreground` or `watch`, overrides the
+    /// `health-port` setting
+    pub health_port: Option<u16>,
+    /// Memory in MiB the core may use, overrides the `memory-limit-mb` setting
+    pub memory_limit_mb: Option<u64>,
+    /// Niceness of the core, overrides the `nice` setting
+    pub nice: Option<i32>,
+    /// Open files the core may have, overrides the `open-files-limit` setting
+    pub open_files_limit: Option<u64>,
+    /// Run as a container's main process: with `foreground`, the mixed-port and
+    /// the controller listen on all interfaces so they can be published
+    pub container: bool,
+    /// URL requested through the proxy after startup, `None` to skip the check
+    pub check_url: Option<String>,
+    /// Release tag to install instead of the latest one
+    pub core_version: Option<String>,
+    /// Preferred mixed-port, overrides the `mixed-port` setting
+    pub mixed_port: Option<u16>,
+    /// Preferred external controller port, overrides the `controller-port` setting
+    pub controller_port: Option<u16>,
+    /// Preferred SOCKS5-only port, set as `socks-port` in the config
+    pub socks_port: Option<u16>,
+    /// Preferred HTTP-only port, set as `port` in the config
+    pub http_port: Option<u16>,
+    /// subconverter endpoint for non-Clash subscriptions, overrides the `subconverter` setting
+    pub subconverter: Option<String>,
+    /// Replace the subscription's DNS section with this preset, saved to the override file
+    pub dns: Option<DnsMode>,
+    /// Upstream DNS servers for the preset, saved to the override file
+    pub nameservers: Vec<String>,
+    /// Download the subscription last passed to `start` again
+    pub update_sub: bool,
+    /// Turn IPv6 on or off in the config and its DNS, saved to the override file
+    pub ipv6: Option<bool>,
+    /// Network interface for outbound traffic, saved to the override file.
+    /// `auto` goes back to Mihomo's own detection.
+    pub interface: Option<String>,
+    /// Only keep nodes whose name matches this regex, saved in the override file
+    pub include: Option<String>,
+    /// Drop nodes whose name matches this regex, saved in the override file
+    pub exclude: Option<String>,
+    /// Send these regions DIRECT ahead of the subscription's rules, saved in the
+    /// override file. [`Bypass::None`] removes them.
+    pub bypass: Vec<Bypass>,
+    /// Add url-test and fallback groups over all nodes to configs that lack them
+    pub auto_groups: bool,
+    /// Switch the main selector group to the node with the lowest delay once started
+    pub auto_select: bool,
+    /// Address the external controller and WebUI listen on, 127.0.0.1 by default.
+    /// Use 0.0.0.0 to reach them from the LAN, they are protected by the secret.
+    pub listen: Option<IpAddr>,
+    /// Serve the controller and WebUI over HTTPS on `listen` with a self-signed
+    /// certificate, the plain HTTP controller then only listens on 127.0.0.1
+    pub controller_tls: bool,
+    /// Pick random high ports for the mixed-port and the controller instead of
+    /// searching from 7890 and 9090, for machines shared with other users
+    pub random_ports: bool,
+    /// Serve the controller only on a Unix socket in the config dir, without
+    /// any TCP listener or WebUI. Ignored on Windows, where it stays on TCP.
+    pub controller_unix: bool,
+    /// Print the time of each startup phase as JSON to stdout once started
+    pub trace_startup: bool,
+}
+
+/// Where a started Mihomo can be reached.
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct StartInfo {
+    /// Pid of the Mihomo process
+    pub pid: u32,
+    /// HTTP and SOCKS5 proxy port on 127.0.0.1
+    pub mixed_port: u16,
+    /// SOCKS5-only port on 127.0.0.1, if the config has one
+    pub socks_port: Option<u16>,
+    /// HTTP-only port on 127.0.0.1, if the config has one
+    pub http_port: Option<u16>,
+    /// External controller port on the `listen` address, the WebUI is served at `/ui`.
+    /// `None` when the controller only listens on a Unix socket.
+    pub controller_port: Option<u16>,
+}
+
+/// The node selected in each `Selector` group, sorted by group.
+async fn selected_nodes(controller: &Controller) -> Result<Vec<GroupSelection>> {
+    let mut selected: Vec<GroupSelection> = controller
+        .proxies()
+        .await?
+        .into_values()
+        .filter(|p| p.kind == "Selector")
+        .filter_map(|p| {
+            p.now.map(|node| GroupSelection {
+                group: p.name,
+                node,
+            })
+        })
+        .collect();
+    selected.sort_by(|a, b| a.group.cmp(&b.group));
+    Ok(selected)
+}
+
+/// What `pause` changed, put back by `resume`.
+#[derive(Serialize, Deserialize, Debug)]
+struct Paused {
+    mode: String,
+    #[serde(default)]
+    selected: Vec<GroupSelection>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct GroupSelection {
+    group: String,
+    node: String,
+}
+
+/// The nodes selected before a stop or reload, put back with `sticky-selection`.
+#[derive(Serialize, Deserialize, Debug)]
+struct Selections {
+    selected: Vec<GroupSelection>,
+}
+
+/// State of an instance, as reported by [`MihomoManager::status`].
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct Status {
+    /// Data dir of the installation the status is about
+    pub data_dir: PathBuf,
+    /// Pid of the running Mihomo, `None` if it is not running
+    pub pid: Option<u32>,
+    /// Release tag of the installed core, `None` if it was not installed by proxy-rs
+    pub core_version: Option<String>,
+    /// Binary `start` runs, the downloaded one unless `core-path` is set
+    pub core_path: PathBuf,
+    /// Quota reported by the provider when the subscription was last downloaded
+    pub subscription: Option<SubscriptionInfo>,
+    /// Details of the running Mihomo, `None` if it is not running
+    pub running: Option<RunningStatus>,
+}
+
+/// Details of a running Mihomo, part of [`Status`]. Fields the controller
+/// didn't answer for are `None`.
+#[derive(Debug, Clone, Default)]
+#[non_exhaustive]
+pub struct RunningStatus {
+    /// Version reported by the controller, e.g. `v1.18.10`
+    pub version: Option<String>,
+    /// Executable of the process, as reported by the OS
+    pub core_path: Option<PathBuf>,
+    pub mixed_port: Option<u16>,
+    /// External controller address from config.yaml
+    pub controller: Option<String>,
+    /// WebUI address, logged in with the controller secret
+    pub webui: Option<String>,
+    pub uptime: Duration,
+    /// Resident memory in bytes
+    pub memory: u64,
+    /// CPU usage in percent of one core
+    pub cpu_usage: f32,
+    /// `rule`, `global` or `direct`
+    pub mode: Option<String>,
+    /// (group, node) of every `Selector` group
+    pub selected: Vec<(String, String)>,
+}
+
+/// How [`MihomoManager::tunnel`] exposes a port.
+#[derive(Debug, Default)]
+pub struct TunnelOptions {
+    pub service: TunnelBackend,
+    /// Print the public URL as a JSON line on stdout
+    pub json: bool,
+    /// Run in the background, see [`MihomoManager::tunnel_status`]
+    pub detach: bool,
+    /// Only try `service` instead of moving on to the other services of its kind
+    pub no_fallback: bool,
+    /// Times a foreground tunnel is reconnected after it drops
+    pub retries: u32,
+    /// Tunnel the proxy itself through a listener that requires a password
+    pub proxy: bool,
+}
+
+/// Which downloaded artifacts [`MihomoManager::clean`] removes.
+#[derive(Debug, Default)]
+pub struct CleanTargets {
+    /// The Mihomo binary and its backup
+    pub core: bool,
+    /// The WebUI
+    pub ui: bool,
+    /// Geodata, Mihomo's cache.db and logs
+    pub cache: bool,
+}
+
+/// What [`MihomoManager::bundle_create`] downloads the core for.
+#[derive(Debug, Default)]
+pub struct BundleOptions {
+    /// OS as spelled in release asset names, this one by default. The
+    /// architecture is `core-arch`, detected without it.
+    pub os: Option<String>,
+    /// Core version, the latest stable release by default
+    pub core_version: Option<String>,
+    pub no_verify: bool,
+}
+
+/// A GitHub release of the core, from the releases API.
+#[derive(Debug, Deserialize)]
+struct CoreRelease {
+    tag_name: String,
+    published_at: Option<String>,
+    prerelease: bool,
+}
+
+impl CoreRelease {
+    /// `2024-11-20` from `2024-11-20T08:12:34Z`, `-` for drafts
+    fn date(&self) -> &str {
+        self.published_at
+            .as_deref()
+            .and_then(|published| published.get(..10))
+            .unwrap_or("-")
+    }
+}
+
+/// An installed component compared with its latest release by
+/// [`MihomoManager::check_update`].
+#[derive(Debug, Clone)]
+pub struct UpdateCheck {
+    /// e.g. `Mihomo`, `metacubexd` or `geosite.dat`
+    pub component: String,
+    /// Installed version, or the date it was downloaded
+    pub installed: String,
+    /// Latest version, or the date it was published
+    pub latest: String,
+    pub outdated: bool,
+    /// Command that installs the latest version
+    pub update_command: &'static str,
+}
+
+/// GitHub's `date` of a commit, from the commits API.
+#[derive(Debug, Deserialize)]
+struct GitHubCommit {
+    commit: GitHubCommitDetails,
+}
+
+#[derive(Debug, Deserialize)]
+struct GitHubCommitDetails {
+    committer: GitHubSignature,
+}
+
+#[derive(Debug, Deserialize)]
+struct GitHubSignature {
+    date: jiff::Timestamp,
+}
+
+/// The assets of a release with their upload times, from the releases API.
+#[derive(Debug, Deserialize)]
+struct GitHubRelease {
+    assets: Vec<GitHubAsset>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GitHubAsset {
+    name: String,
+    updated_at: jiff::Timestamp,
+}
+
+/// When `path` was last written, `None` if it does not exist.
+fn modified_at(path: &Path) -> Option<jiff::Timestamp> {
+    let modified = fs::metadata(path).and_then(|m| m.modified()).ok()?;
+    jiff::Timestamp::try_from(modified).ok()
+}
+
+fn format_date(time: jiff::Timestamp) -> String {
+    time.to_zoned(jiff::tz::TimeZone::system())
+        .strftime("%Y-%m-%d")
+        .to_string()
+}
+
+/// What `reload` reports the changes of, read from the controller.
+#[derive(PartialEq, Eq)]
+struct ConfigSummary {
+    mixed_port: u16,
+    port: u16,
+    socks_port: u16,
+    mode: String,
+    proxies: usize,
+    groups: usize,
+    rules: usize,
+}
+
+impl ConfigSummary {
+    async fn fetch(controller: &Controller) -> Result<Self> {
+        let config = controller.configs().await?;
+        let proxies = controller.proxies().await?;
+        Ok(Self {
+            mixed_port: config.mixed_port,
+            port: config.port,
+            socks_port: config.socks_port,
+            mode: config.mode,
+            proxies: proxies.values().filter(|p| p.is_node()).count(),
+            groups: proxies
+                .values()
+                .filter(|p| p.is_group() && p.name != "GLOBAL")
+                .count(),
+            rules: controller.rules().await?.len(),
+        })
+    }
+
+    /// Logs `name: before -> after` for every field that changed.
+    fn log_changes(&self, after: &Self) {
+        if self == after {
+            info!("Nothing changed in ports, mode, proxies or rules");
+            return;
+        }
+        let fields = [
+            (
+                "mixed-port",
+                self.mixed_port.to_string(),
+                after.mixed_port.to_string(),
+            ),
+            ("port", self.port.to_string(), after.port.to_string()),
+            (
+                "socks-port",
+                self.socks_port.to_string(),
+                after.socks_port.to_string(),
+            ),
+            ("mode", self.mode.clone(), after.mode.clone()),
+            (
+                "proxies",
+                self.proxies.to_string(),
+                after.proxies.to_string(),
+            ),
+            (
+                "proxy groups",
+                self.groups.to_string(),
+                after.groups.to_string(),
+            ),
+            ("rules", self.rules.to_string(), after.rules.to_string()),
+        ];
+        for (name, before, after) in fields {
+            if before != after {
+                info!("  {name}: {before} -> {after}");
+            }
+        }
+    }
+}
+
+/// Where the core launched by `start` listens, as written to its config.
+struct Listeners {
+    controller_address: ControllerAddress,
+    /// The controller over HTTPS, with --controller-tls
+    tls_address: Option<SocketAddr>,
+    mixed_port: u16,
+    listen: IpAddr,
+    secret: String,
+}
+
+/// What [`MihomoManager::start`] keeps when it reloads a running instance.
+struct ReloadTarget {
+    pid: u32,
+    mixed_port: u16,
+    controller_address: ControllerAddress,
+    /// Secret of the running config, a new subscription may bring another
+    secret: Option<String>,
+}
+
+/// Manages the proxy core (Mihomo unless another [`CoreBackend`] is selected),
+/// its downloads and one instance's running process.
 pub struct MihomoManager {
     client: Client,
+    core: &'static dyn CoreBackend,
+    /// Shared by all instances: the core, the WebUI and proxy-rs settings
     proxy_data_dir: PathBuf,
+    /// Per-instance state: config, pid files, logs and scripts
+    instance_dir: PathBuf,
+    instance: Option<String>,
     config_dir: PathBuf,
-    mihomo_path: PathBuf,
+    core_path: PathBuf,
+    github_mirrors: Vec<String>,
+    mirror_cache: MirrorCache,
+    /// Downloads shared with the other data dirs, `None` if the platform has no cache dir
+    shared_cache: Option<SharedCache>,
+    settings: Settings,
+    /// Phases of the current `start`
+    startup: StartupTimer,
 }
 
-enum ArchiveType {
-    GZ,
-    ZIP,
-}
-impl ArchiveType {
-    fn as_str(&self) -> &'static str {
-        match self {
-            ArchiveType::GZ => "gz",
-            ArchiveType::ZIP => "zip",
-        }
-    }
+/// (file name in the config dir, release asset) of the geodata Mihomo loads.
+/// With `geodata-mode: false` (the default) GEOIP rules use the MMDB database.
+/// Database of `IP-ASN` rules, downloaded by Mihomo itself when a rule needs it
+const ASN_FILE: &str = "ASN.mmdb";
+
+fn geodata_url(asset: &str) -> String {
+    format!("https://github.com/MetaCubeX/meta-rules-dat/releases/download/latest/{asset}")
 }
 
-impl std::fmt::Display for ArchiveType {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self.as_str())
+fn geodata_files(geodata_mode: bool) -> &'static [(&'static str, &'static str)] {
+    if geodata_mode {
+        &[("geosite.dat", "geosite.dat"), ("geoip.dat", "geoip.dat")]
+    } else {
+        &[
+            ("geosite.dat", "geosite.dat"),
+            ("country.mmdb", "geoip.metadb"),
+        ]
     }
 }
 
 impl MihomoManager {
-    pub fn new() -> Result<Self> {
-        let proxy_data_dir = PathBuf::from(PROXY_DATA_DIR);
-        let config_dir = proxy_data_dir.join("config");
-        let mihomo_path = proxy_data_dir.join(if cfg!(windows) {
-            "mihomo.exe"
-        } else {
-            "mihomo"
-        });
+    /// Manager for the default instance, or the named `instance` whose state lives
+    /// in `<data dir>/instances/<name>`.
+    pub fn new(proxy_data_dir: PathBuf, instance: Option<&str>) -> Result<Self> {
+        let instance_dir = match instance {
+            Some(name) => proxy_data_dir.join(INSTANCES_DIR).join(name),
+            None => proxy_data_dir.clone(),
+        };
+        let config_dir = instance_dir.join("config");
+        // A data dir proxy-rs creates is private, one the user picked is left alone
+        let created = !proxy_data_dir.exists();
         fs::create_dir_all(&proxy_data_dir)?;
         fs::write(proxy_data_dir.join(".gitignore"), "*\n")?;
         fs::create_dir_all(&config_dir)?;
+        let private = [
+            config_dir.clone(),
+            proxy_data_dir.join(SETTINGS_FILE),
+            proxy_data_dir.join(SYNC_DIR),
+            instance_dir.join(SUBSCRIPTION_URL_FILE),
+            instance_dir.join(SUBSCRIPTION_FILE),
+            instance_dir.join(PROVIDER_HEALTH_FILE),
+        ];
+        for path in created
+            .then_some(&proxy_data_dir)
+            .into_iter()
+            .chain(private.iter().filter(|path| path.exists()))
+        {
+            if let Err(e) = make_private(path) {
+                warn!(
+                    "Failed to make {} readable only by you: {e}",
+                    path.display()
+                );
+            }
+        }
+
+        let settings = Settings::load(&proxy_data_dir.join(SETTINGS_FILE))?;
+        let github_mirrors =
+            github_mirrors(&settings.github_mirrors, settings.replace_default_mirrors);
+        let core = running_core(&instance_dir)
+            .unwrap_or(settings.core)
+            .backend();
+        let core_path = settings
+            .core_path
+            .clone()
+            .unwrap_or_else(|| core_binary_path(&proxy_data_dir, core));
+
+        let mirror_cache = MirrorCache {
+            path: proxy_data_dir.join(MIRROR_CACHE_FILE),
+            reselect: false,
+        };
 
         Ok(Self {
-            client: Client::new(),
+            client: client_builder(settings.download_proxy.as_deref())?.build()?,
+            core,
             proxy_data_dir,
+            instance_dir,
+            instance: instance.map(String::from),
             config_dir,
-            mihomo_path,
+            core_path,
+            github_mirrors,
+            mirror_cache,
+            shared_cache: settings
+                .cache_dir
+                .clone()
+                .or_else(default_cache_dir)
+                .map(SharedCache::new),
+            settings,
+            startup: StartupTimer::default(),
         })
     }
 
-    pub fn start(&self, url: Option<&str>) -> Result<()> {
-        if let Some(pid) = self.is_running()? {
-            info!("Mihomo is already running (pid: {pid}). Stopping it first...");
-            self.stop()?;
+    /// Overrides the `core` setting, e.g. from `--core`.
+    pub fn set_core(&mut self, kind: CoreKind) {
+        self.core = kind.backend();
+        if self.settings.core_path.is_none() {
+            self.core_path = core_binary_path(&self.proxy_data_dir, self.core);
         }
+    }
+
+    /// Overrides the `core-path` setting, e.g. from `start --core-path`.
+    pub fn set_core_path(&mut self, path: PathBuf) {
+        self.settings.core_path = Some(path.clone());
+        self.core_path = path;
+    }
 
-        if !self.mihomo_path.exists() {
-            self.download_mihomo()?;
+    /// Fails unless the core binary is the one proxy-rs downloads, not `core-path`.
+    fn require_managed_core(&self) -> Result<()> {
+        if self.settings.core_path.is_some() {
+            return Err(anyhow!(
+                "{} is set as core-path, update it with the tool that installed it",
+                self.core_path.display()
+            ));
         }
+        Ok(())
+    }
 
-        self.download_metacubexd_if_necessary()?;
-        self.download_geodata_if_necessary()?;
+    fn config_path(&self) -> PathBuf {
+        self.config_dir.join(self.core.config_file())
+    }
 
-        let config_path = self.config_dir.join("config.yaml");
-        handle_subscription_config(&self.client, url, &config_path)?;
+    fn manifest_path(&self) -> PathBuf {
+        self.proxy_data_dir.join(match self.core.kind() {
+            CoreKind::Mihomo => MANIFEST_FILE,
+            CoreKind::SingBox => SING_BOX_MANIFEST_FILE,
+        })
+    }
 
-        let ext_port = find_unused_port(9090).context("Failed to find an unused port")?;
-        info!("Found unused port: {ext_port}");
+    /// Loads the manifest, lets `update` change it and saves it again.
+    fn update_manifest(&self, update: impl FnOnce(&mut Manifest)) -> Result<()> {
+        let manifest_path = self.manifest_path();
+        let mut manifest = Manifest::load(&manifest_path)?;
+        update(&mut manifest);
+        manifest.save(&manifest_path)
+    }
 
-        let metacubexd_path = self.proxy_data_dir.join("metacubexd");
-        let absolute_metacubexd_path = dunce::canonicalize(metacubexd_path)?;
+    /// Key of a geodata file in [`Manifest::geodata`].
+    fn geodata_key(&self, filename: &str) -> String {
+        let path = self.config_dir.join(filename);
+        path.strip_prefix(&self.proxy_data_dir)
+            .unwrap_or(&path)
+            .to_string_lossy()
+            .replace('\\', "/")
+    }
 
-        let mut command = Command::new(&self.mihomo_path);
-        command
-            .arg("-d")
-            .arg(&self.config_dir)
-            .arg("-ext-ctl")
-            .arg(format!("127.0.0.1:{}", ext_port))
-            .arg("-ext-ui")
-            .arg(absolute_metacubexd_path);
+    /// How the core binary compares to the one proxy-rs installed.
+    fn core_integrity(&self) -> Result<Integrity> {
+        let manifest = Manifest::load(&self.manifest_path())?;
+        Ok(manifest::integrity(manifest.core.as_ref(), &self.core_path))
+    }
 
-        let log_file = File::create(self.proxy_data_dir.join("mihomo.log"))?;
-        let stdout = Stdio::from(log_file);
-        let stderr = Stdio::from(File::create(self.proxy_data_dir.join("mihomo.err"))?);
+    /// How a geodata file compares to the one proxy-rs downloaded.
+    fn geodata_integrity(&self, filename: &str) -> Result<Integrity> {
+        let manifest = Manifest::load(&self.manifest_path())?;
+        Ok(manifest::integrity(
+            manifest.geodata.get(&self.geodata_key(filename)),
+            &self.config_dir.join(filename),
+        ))
+    }
 
-        let child = command.stdout(stdout).stderr(stderr).spawn()?;
-        self.save_pid(child)?;
+    /// When the WebUI was downloaded, or for copies from before the manifest
+    /// recorded it, when its folder was written.
+    fn ui_downloaded_at(&self, ui: WebUi) -> Result<Option<jiff::Timestamp>> {
+        let recorded = Manifest::load(&self.manifest_path())?
+            .ui
+            .get(ui.name())
+            .map(|artifact| artifact.installed_at);
+        Ok(recorded.or_else(|| modified_at(&self.proxy_data_dir.join(ui.name()))))
+    }
 
-        info!("Mihomo started in the background!");
+    /// Same as [`Self::ui_downloaded_at`] for a geodata file.
+    fn geodata_downloaded_at(&self, filename: &str) -> Result<Option<jiff::Timestamp>> {
+        let path = self.config_dir.join(filename);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let recorded = Manifest::load(&self.manifest_path())?
+            .geodata
+            .get(&self.geodata_key(filename))
+            .map(|artifact| artifact.installed_at);
+        Ok(recorded.or_else(|| modified_at(&path)))
+    }
 
-        let mixed_port = find_unused_port(7890).context("Failed to find unused port")?;
+    /// Fails with a message naming `feature` unless the core is Mihomo.
+    fn require_mihomo(&self, feature: &str) -> Result<()> {
+        if self.core.kind() != CoreKind::Mihomo {
+            return Err(anyhow!("{feature} is only supported with the Mihomo core"));
+        }
+        Ok(())
+    }
 
-        update_mixed_port(&config_path, mixed_port)?;
-        info!("Mihomo mixed-port is set to: {mixed_port}");
-        update_external_controller(&config_path, &format!("127.0.0.1:{}", ext_port))?;
-        info!("Web UI: http://127.0.0.1:{ext_port}/ui");
+    /// Overrides the `stop-timeout` setting, e.g. from `stop --timeout`.
+    pub fn set_stop_timeout(&mut self, secs: u64) {
+        self.settings.stop_timeout = Some(secs);
+    }
 
-        self.write_env_setup_script(mixed_port)?;
+    /// Probes the GitHub mirrors instead of using the one saved by an earlier run.
+    pub fn set_reselect_mirror(&mut self) {
+        self.mirror_cache.reselect = true;
+    }
 
-        info!(
-            "To stop Mihomo, run: `{} stop`",
-            std::env::current_exe()?
-                .as_path()
-                .file_name()
-                .and_then(|f| f.to_str())
-                .unwrap_or("<executable>")
-        );
+    /// Overrides the `core-arch` setting, e.g. from `--core-arch`.
+    pub fn set_core_arch(&mut self, arch: &str) {
+        self.settings.core_arch = Some(arch.to_string());
+    }
 
+    /// Sets the WebUI from `start --ui` and saves it as `ui` in proxy-rs.toml.
+    pub fn set_ui(&mut self, ui: WebUi) -> Result<()> {
+        if self.settings.ui != ui {
+            save_setting(&self.proxy_data_dir.join(SETTINGS_FILE), "ui", ui.name())?;
+            info!("Saved ui = \"{}\" to {SETTINGS_FILE}", ui.name());
+        }
+        self.settings.ui = ui;
         Ok(())
     }
 
-    pub fn stop(&self) -> anyhow::Result<()> {
-        let pid = self.load_pid();
-        let system = sysinfo::System::new_all();
-        let process = pid.and_then(|pid| system.process(pid));
+    /// Overrides the `via-proxy` setting, e.g. from `--via-proxy`.
+    pub fn set_via_proxy(&mut self, via_proxy: ViaProxy) {
+        self.settings.via_proxy = via_proxy;
+    }
 
-        match process {
-            Some(p) => {
-                p.kill_and_wait()
-                    .map_err(|e| anyhow!("Failed to stop: {e:?}"))?;
-                info!("Mihomo stopped.");
-            }
-            None => {
-                warn!("Mihomo process not found.");
-            }
-        }
-        let _ = fs::remove_file(MIHOMO_PID_FILE);
-        Ok(())
+    /// Turns on the `sticky-selection` setting, e.g. from `--sticky-selection`.
+    pub fn set_sticky_selection(&mut self) {
+        self.settings.sticky_selection = true;
     }
 
-    pub fn status(&self) -> anyhow::Result<()> {
-        if let Some(pid) = self.is_running()? {
-            info!("Mihomo is running (pid: {pid}).");
-        } else {
-            info!("Mihomo is not running.");
-        }
+    /// Overrides the `download-proxy` setting, e.g. from `--download-proxy`.
+    pub fn set_download_proxy(&mut self, url: &str) -> Result<()> {
+        self.client = client_builder(Some(url))?.build()?;
+        self.settings.download_proxy = Some(url.to_string());
         Ok(())
     }
 
-    fn is_running(&self) -> Result<Option<u32>, anyhow::Error> {
-        if let Some(pid) = self.load_pid() {
-            let system = sysinfo::System::new_all();
-            let process = system.process(pid);
-            match process {
-                Some(_) => Ok(Some(pid.as_u32())),
-                None => {
-                    let _ = fs::remove_file(MIHOMO_PID_FILE);
-                    return Ok(None);
-                }
-            }
-        } else {
-            Ok(None)
-        }
+    /// Proxy for downloads from `--download-proxy` or proxy-rs.toml, if any.
+    pub fn download_proxy(&self) -> Option<&str> {
+        self.settings.download_proxy.as_deref()
     }
 
-    fn save_pid(&self, child: Child) -> Result<()> {
-        fs::write(MIHOMO_PID_FILE, child.id().to_string())?;
-        Ok(())
+    /// Overrides the `delay-test-url` setting, e.g. from `--delay-test-url`.
+    pub fn set_delay_test_url(&mut self, url: &str) {
+        self.settings.delay_test_url = Some(url.to_string());
     }
-    fn load_pid(&self) -> Option<sysinfo::Pid> {
-        fs::read_to_string(MIHOMO_PID_FILE)
-            .ok()
-            .and_then(|pid_str| sysinfo::Pid::from_str(&pid_str).ok())
+
+    /// Overrides the `delay-test-interval` setting, e.g. from `--delay-test-interval`.
+    pub fn set_delay_test_interval(&mut self, secs: u64) {
+        self.settings.delay_test_interval = Some(secs);
     }
 
-    fn download_mihomo(&self) -> Result<()> {
-        info!("Downloading Mihomo...");
-        let proxy = select_fastest_github_proxy()?;
+    /// URL of delay tests, from `--delay-test-url` or proxy-rs.toml, or the default.
+    pub fn delay_test_url(&self) -> &str {
+        self.settings
+            .delay_test_url
+            .as_deref()
+            .unwrap_or(DEFAULT_DELAY_TEST_URL)
+    }
 
-        let version_url = format!(
-            "{}https://github.com/MetaCubeX/mihomo/releases/latest/download/version.txt",
-            proxy
-        );
-        let version = self
-            .client
-            .get(&version_url)
-            .send()?
-            .text()?
-            .trim()
-            .to_string();
-        info!("Latest version: {version}");
+    /// Downloads whatever is missing, then starts Mihomo in the background on
+    /// unused ports. A running Mihomo reloads the new config in place, keeping
+    /// its ports and connections, unless its binary or ports change.
+    /// With `watch` this only returns once the watchdog stops.
+    pub async fn start(&self, options: &StartOptions) -> Result<StartInfo> {
+        let lock = self.lock_instance()?;
+        self.refuse_adopted("start it again")?;
+        self.startup.reset();
+        self.start_locked(options, lock, true).await
+    }
 
-        let os = if cfg!(target_os = "windows") {
-            "windows"
-        } else if cfg!(target_os = "linux") {
-            "linux"
-        } else if cfg!(target_os = "macos") {
-            "darwin"
-        } else {
-            return Err(anyhow!("Unsupported OS"));
-        };
+    /// Takes the instance lock so it can be released before supervising with `--watch`,
+    /// otherwise `stop` would be blocked for as long as the watchdog runs.
+    /// Without `download` the config on disk is used as it is, after a new
+    /// subscription made the core exit and the previous config was restored.
+    ///
+    /// config.yaml is finished before the core is launched: subscription, then
+    /// overrides, then ports and secret, then the core's own check, so the file
+    /// on disk is always the config the core runs.
+    async fn start_locked(
+        &self,
+        options: &StartOptions,
+        lock: File,
+        download: bool,
+    ) -> Result<StartInfo> {
+        // Download while a running instance is still up, so it can be used as the proxy
+        let (core_updated, _, _, _) = tokio::try_join!(
+            self.download_mihomo_if_necessary(options.no_verify, options.core_version.as_deref()),
+            self.download_ui_if_necessary(),
+            self.download_geodata_if_necessary(),
+            self.download_adblock_list_if_necessary(),
+        )?;
 
-        let arch = if cfg!(target_arch = "x86_64") {
-            "amd64"
-        } else if cfg!(target_arch = "aarch64") {
-            "arm64"
+        let config_path = self.config_path();
+        // Read before a new subscription replaces the config
+        let reloadable = if core_updated {
+            None
         } else {
-            return Err(anyhow!("Unsupported architecture"));
+            self.reloadable(options)?
         };
-
-        let archive_type = if cfg!(windows) {
-            ArchiveType::ZIP
+        // Subscription
+        // URLs given to `start` win, then the ones last given and saved, then
+        // proxy-rs.toml's, which is only downloaded again on --update-sub
+        let saved_urls = self.saved_subscription_urls()?;
+        let urls = if !options.urls.is_empty() {
+            options.urls.clone()
+        } else if !saved_urls.is_empty() {
+            if options.update_sub {
+                saved_urls
+            } else {
+                Vec::new()
+            }
         } else {
-            ArchiveType::GZ
+            match &self.settings.subscription_url {
+                Some(url) if options.update_sub || !config_path.exists() => vec![url.clone()],
+                _ => Vec::new(),
+            }
         };
+        if options.update_sub && urls.is_empty() {
+            return Err(anyhow!(
+                "No subscription URL is saved yet, pass one to `start` first"
+            ));
+        }
+        let subconverter = options
+            .subconverter
+            .as_deref()
+            .or(self.settings.subconverter.as_deref());
+        let config_changed = download
+            && self
+                .startup
+                .time(
+                    "subscription",
+                    self.download_subscription(&urls, subconverter),
+                )
+                .await?;
+        if download && !options.urls.is_empty() {
+            let url_file = self.instance_dir.join(SUBSCRIPTION_URL_FILE);
+            fs::write(&url_file, options.urls.join("\n"))?;
+            make_private(&url_file)?;
+        }
+        // Overrides
+        let started = Instant::now();
+        self.apply_start_overrides(options)?;
+        self.startup.record("overrides", started);
 
-        let download_url = format!(
-            "{}https://github.com/MetaCubeX/mihomo/releases/download/{}/mihomo-{}-{}-{}.{}",
-            proxy, version, os, arch, version, archive_type
-        );
-
-        let archive_path = self.proxy_data_dir.join(format!("mihomo.{archive_type}"));
-        download_file_with_progress(&self.client, &download_url, &archive_path)?;
+        if let Some(running) = reloadable {
+            // Ports and secret of the running instance
+            let controller = self.keep_running_ports(&running)?;
+            if !self
+                .startup
+                .time("config check", self.validate_final_config(config_changed))
+                .await?
+            {
+                return Box::pin(self.start_locked(options, lock, false)).await;
+            }
+            let info = self
+                .startup
+                .time("reload", self.reload_in_place(options, running, controller))
+                .await?;
+            self.report_startup(options);
+            drop(lock);
+            if options.watch_config {
+                self.watch_config().await?;
+            }
+            return Ok(info);
+        }
 
-        match archive_type {
-            ArchiveType::GZ => decompress_gz(&archive_path, &self.mihomo_path)?,
-            ArchiveType::ZIP => decompress_zip(&archive_path, &self.mihomo_path)?,
-        };
+        if let Some(pid) = self.is_running()? {
+            info!(
+                "{} is already running (pid: {pid}). Stopping it first...",
+                self.display_name()
+            );
+            let started = Instant::now();
+            self.save_running_selections().await;
+            self.stop_locked().await?;
+            self.startup.record("stopping the running core", started);
+        }
 
-        fs::remove_file(&archive_path)?;
+        // Ports and secret, once the ports of a running instance are free
+        let Listeners {
+            controller_address,
+            tls_address,
+            mixed_port,
+            listen,
+            secret,
+        } = self.write_listeners(options)?;
 
-        #[cfg(unix)]
+        // Validation of the finished config, before anything runs it
+        if !self
+            .startup
+            .time("config check", self.validate_final_config(config_changed))
+            .await?
         {
-            use std::os::unix::fs::PermissionsExt;
-            let mut perms = fs::metadata(&self.mihomo_path)?.permissions();
-            perms.set_mode(0o755);
-            fs::set_permissions(&self.mihomo_path, perms)?;
+            return Box::pin(self.start_locked(options, lock, false)).await;
         }
+        self.run_script(Stage::PreStart, None)
+            .await
+            .map_err(|e| anyhow!("The pre-start hook failed, not starting: {e}"))?;
 
-        Ok(())
-    }
+        let health_port = options.health_port.or(self.settings.health_port);
+        if health_port.is_some() && !options.foreground && !options.watch {
+            warn!("The health endpoint is only served with --foreground or --watch");
+        }
+        // Bound before the core runs, a port in use doesn't take the core down later
+        let health_listener = match health_port.filter(|_| options.foreground || options.watch) {
+            Some(port) => match health::bind(SocketAddr::new(listen, port)).await {
+                Ok(listener) => Some(listener),
+                Err(e) => {
+                    warn!("{e:#}, running without it");
+                    None
+                }
+            },
+            None => None,
+        };
 
-    fn download_metacubexd_if_necessary(&self) -> Result<()> {
-        let metacubexd_path = self.proxy_data_dir.join("metacubexd");
-        if metacubexd_path.exists() {
-            info!("metacubexd already exists, skip downloading.");
-            return Ok(());
+        let limits = self.resource_limits(options);
+        let mut child = if options.foreground {
+            self.spawn_foreground(&controller_address, &limits)?
+        } else {
+            self.spawn_mihomo(&controller_address, false, options.watch, &limits)?
+        };
+        self.save_pid(&child)?;
+        let pid = child.id();
+        // A new process starts in the config's mode, neither paused nor blocked
+        let _ = fs::remove_file(self.instance_dir.join(PAUSED_FILE));
+        let _ = fs::remove_file(self.instance_dir.join(LIMIT_BLOCKED_FILE));
+
+        if options.foreground {
+            info!("{} started in the foreground", self.core.name());
+        } else {
+            info!("{} started in the background!", self.core.name());
+        }
+        match &controller_address {
+            ControllerAddress::Tcp(address) => {
+                info!(
+                    "Web UI: {}",
+                    webui_url(local_address(*address), &secret, false)
+                )
+            }
+            ControllerAddress::Unix(path) => info!(
+                "The controller only listens on {}, no Web UI is served",
+                path.display()
+            ),
+        }
+        if let Some(tls_address) = tls_address {
+            info!(
+                "Web UI over HTTPS: {}",
+                webui_url(local_address(tls_address), &secret, true)
+            );
+            info!("The certificate is self-signed, accept it in the browser once");
+        }
+        let exposed_port = tls_address
+            .map(|address| address.port())
+            .or(controller_address.port());
+        if let Some(exposed_port) = exposed_port.filter(|_| !listen.is_loopback()) {
+            let lan_ip = if listen.is_unspecified() {
+                lan_ip()
+            } else {
+                Some(listen)
+            };
+            match lan_ip {
+                Some(ip) => info!(
+                    "Web UI on the LAN: {}",
+                    webui_url(
+                        SocketAddr::new(ip, exposed_port),
+                        &secret,
+                        tls_address.is_some()
+                    )
+                ),
+                None => info!("Web UI is reachable from the LAN on port {exposed_port}"),
+            }
         }
 
-        info!("Downloading metacubexd...");
-        let proxy = select_fastest_github_proxy()?;
-        let url = format!(
-            "{}https://github.com/MetaCubeX/metacubexd/archive/refs/heads/gh-pages.zip",
-            proxy
-        );
-        let zip_path = self.proxy_data_dir.join("metacubexd.zip");
+        // Other containers reach the proxy by its address, not through these
+        if !options.container {
+            self.write_env_setup_script(mixed_port)?;
+        }
 
-        download_file_with_progress(&self.client, &url, &zip_path)?;
-        unzip_file(&zip_path, &self.proxy_data_dir)?;
-        fs::remove_file(&zip_path)?;
+        if let Some(check_url) = &options.check_url {
+            let started = Instant::now();
+            let verified = self
+                .verify_started(&mut child, &controller_address, mixed_port, check_url)
+                .await;
+            self.startup.record("core readiness", started);
+            if let Err(e) = verified {
+                // Only a core that exited blames the config, a failed test request may be the network
+                if config_changed && child.try_wait()?.is_some() && restore_backup(&config_path)? {
+                    warn!("{e}");
+                    warn!(
+                        "{} failed to start with the new subscription, starting it with the previous config",
+                        self.core.name()
+                    );
+                    return Box::pin(self.start_locked(options, lock, false)).await;
+                }
+                return Err(e);
+            }
+        }
+        self.restore_selections_after_start(&controller_address)
+            .await;
+        if options.auto_select {
+            let url = options
+                .check_url
+                .as_deref()
+                .unwrap_or(self.delay_test_url());
+            let auto_selected = self
+                .startup
+                .time("auto-select", self.auto_select(&controller_address, url))
+                .await;
+            if let Err(e) = auto_selected {
+                warn!("Failed to auto-select a node: {e}");
+            }
+        }
+        self.report_startup(options);
 
-        // The unzipped folder is named metacubexd-gh-pages, rename it
-        let unzipped_folder = self.proxy_data_dir.join("metacubexd-gh-pages");
-        if unzipped_folder.exists() {
-            fs::rename(unzipped_folder, metacubexd_path)?;
+        if options.foreground {
+            info!("Press Ctrl+C to stop {}", self.core.name());
+        } else {
+            info!(
+                "To stop {}, run: `{} stop{}`",
+                self.core.name(),
+                std::env::current_exe()?
+                    .as_path()
+                    .file_name()
+                    .and_then(|f| f.to_str())
+                    .unwrap_or("<executable>"),
+                self.instance
+                    .as_ref()
+                    .map(|name| format!(" {name}"))
+                    .unwrap_or_default()
+            );
         }
+        self.run_script_or_warn(Stage::PostStart, Some(pid)).await;
+        self.fire_hooks(
+            Event::Started,
+            &format!(
+                "{} started (pid: {pid}, mixed-port: {mixed_port})",
+                self.core.name()
+            ),
+        )
+        .await;
 
-        Ok(())
-    }
+        drop(lock);
+        // Never ends the run, the core keeps going without the endpoint
+        let health = async {
+            if let Some(listener) = health_listener {
+                let source = HealthSource {
+                    pid_path: self.instance_dir.join(MIHOMO_PID_FILE),
+                    refreshed_path: self.instance_dir.join(SUBSCRIPTION_REFRESHED_FILE),
+                    mixed_port,
+                    probe_url: self.delay_test_url().to_string(),
+                };
+                if let Err(e) = health::serve(listener, source).await {
+                    warn!("The health endpoint stopped: {e:#}");
+                }
+            }
+            std::future::pending::<Result<()>>().await
+        };
+        if options.foreground {
+            let run = async {
+                if options.watch_config {
+                    tokio::try_join!(self.run_foreground(child), self.watch_config())?;
+                    Ok(())
+                } else {
+                    self.run_foreground(child).await
+                }
+            };
+            tokio::select! {
+                result = run => result?,
+                result = health => result?,
+            }
+            return Ok(StartInfo {
+                pid,
+                mixed_port,
+                socks_port: parse_listener_port(&config_path, "socks-port"),
+                http_port: parse_listener_port(&config_path, "port"),
+                controller_port: controller_address.port(),
+            });
+        }
+        let jobs = if options.watch {
+            self.settings.schedule.jobs().unwrap_or_else(|e| {
+                warn!("Not running scheduled jobs: {e:#}");
+                Vec::new()
+            })
+        } else {
+            Vec::new()
+        };
+        let supervised = async {
+            tokio::select! {
+                result = self.supervise(child, &controller_address, &limits) => result,
+                result = health => result,
+            }
+        };
+        match (options.watch, options.watch_config) {
+            (true, true) => {
+                tokio::try_join!(supervised, self.watch_config(), self.run_jobs(jobs))?;
+            }
+            (true, false) => {
+                tokio::try_join!(supervised, self.run_jobs(jobs))?;
+            }
+            (false, true) => self.watch_config().await?,
+            (false, false) => {}
+        }
 
-    fn download_geodata_if_necessary(&self) -> Result<()> {
-        self.download_geofile("geosite.dat")?;
-        self.download_geofile("geoip.dat")?;
-        Ok(())
+        Ok(StartInfo {
+            pid,
+            mixed_port,
+            socks_port: parse_listener_port(&config_path, "socks-port"),
+            http_port: parse_listener_port(&config_path, "port"),
+            controller_port: controller_address.port(),
+        })
     }
 
-    fn download_geofile(&self, filename: &str) -> Result<()> {
-        let file_path = self.config_dir.join(filename);
-        if file_path.exists() {
-            return Ok(());
+    /// Downloads the subscriptions at `urls` into the config, saving the quota,
+    /// or checks the existing config without any. Returns whether the config changed.
+    async fn download_subscription(
+        &self,
+        urls: &[String],
+        subconverter: Option<&str>,
+    ) -> Result<bool> {
+        let config_path = self.config_path();
+        let downloaded = self
+            .fetch_subscription_config(urls, subconverter, &config_path)
+            .await;
+        self.record_fetches(urls, &downloaded);
+        let Some(downloaded) = downloaded? else {
+            return Ok(false);
+        };
+        if !downloaded.unchanged {
+            self.apply_delay_test()?;
         }
+        if !downloaded.unchanged && self.core_path.exists() {
+            if let Err(e) =
+                doctor::run_core(&self.core_path, self.core.test_args(&self.config_dir)).await
+            {
+                if !restore_backup(&config_path)? {
+                    return Err(anyhow!(
+                        "{} rejected the subscription: {e}",
+                        self.core.name()
+                    ));
+                }
+                warn!(
+                    "{} rejected the subscription: {e}, keeping the previous config",
+                    self.core.name()
+                );
+                return Ok(false);
+            }
+        }
+        fs::write(
+            self.instance_dir.join(SUBSCRIPTION_REFRESHED_FILE),
+            jiff::Timestamp::now().to_string(),
+        )?;
+        if !downloaded.unchanged {
+            self.fire_hooks(Event::SubscriptionRefreshed, "Subscription refreshed")
+                .await;
+        }
+        if let Some(subscription) = downloaded.info {
+            subscription.save(&self.instance_dir.join(SUBSCRIPTION_FILE))?;
+            subscription.log();
+            let percent = self
+                .settings
+                .quota_low_percent
+                .unwrap_or(DEFAULT_QUOTA_LOW_PERCENT);
+            if subscription.is_low(percent) {
+                self.fire_hooks(
+                    Event::QuotaLow,
+                    "Subscription quota is running low or expires soon",
+                )
+                .await;
+            }
+        }
+        Ok(!downloaded.unchanged)
+    }
 
-        info!("Downloading {filename}...");
-        let proxy = select_fastest_github_proxy()?;
-        let url = format!(
-            "{}https://github.com/MetaCubeX/meta-rules-dat/releases/download/latest/{}",
-            proxy, filename
-        );
-
-        if download_file_with_progress(&self.client, &url, &file_path).is_err() {
-            warn!("Failed to download {filename}");
+    /// [`handle_subscription_config`] through the running core if it can be
+    /// used, falling back to a direct download.
+    async fn fetch_subscription_config(
+        &self,
+        urls: &[String],
+        subconverter: Option<&str>,
+        config_path: &Path,
+    ) -> Result<Option<Downloaded>> {
+        let proxied = match urls {
+            [] => None,
+            _ => self.local_proxy_client()?,
+        };
+        let client = proxied.as_ref().unwrap_or(&self.client);
+        match handle_subscription_config(client, self.core, urls, subconverter, config_path).await {
+            Ok(downloaded) => Ok(downloaded),
+            Err(e) if proxied.is_none() || self.settings.via_proxy == ViaProxy::Always => Err(e),
+            Err(e) => {
+                warn!(
+                    "Downloading the subscription through the running {} failed: {e}, trying directly",
+                    self.core.name()
+                );
+                handle_subscription_config(&self.client, self.core, urls, subconverter, config_path)
+                    .await
+            }
         }
-        Ok(())
     }
 
-    fn write_env_setup_script(&self, mixed_port: u16) -> Result<()> {
-        let on_script_path = self.proxy_data_dir.join("on");
-        let off_script_path = self.proxy_data_dir.join("off");
-
-        let on_content = format!(
-            r#"#!/bin/sh
-export http_proxy="http://127.0.0.1:{}"
-export HTTP_PROXY=$http_proxy
-export https_proxy=$http_proxy
-export HTTPS_PROXY=$http_proxy
-export all_proxy=$http_proxy
-export ALL_PROXY=$http_proxy
-"#,
-            mixed_port
-        );
+    /// Records in [`PROVIDER_HEALTH_FILE`] which of `urls` could be downloaded.
+    fn record_fetches(&self, urls: &[String], result: &Result<Option<Downloaded>>) {
+        let failed = match result {
+            Ok(None) => return,
+            Ok(Some(_)) => None,
+            // Merged subscriptions are downloaded in order until one fails
+            Err(e) => match e.downcast_ref::<DownloadFailed>() {
+                Some(failed) => Some((failed.url.as_str(), e)),
+                None if urls.len() == 1 => Some((urls[0].as_str(), e)),
+                None => return,
+            },
+        };
+        let now = jiff::Timestamp::now().as_second();
+        let path = self.instance_dir.join(PROVIDER_HEALTH_FILE);
+        let mut health = ProviderHealth::load(&path);
+        health.retain(urls);
+        for url in urls {
+            match failed {
+                Some((failed, e)) if failed == url => {
+                    health.record_fetch(url, Err(e.root_cause().to_string()), now);
+                    break;
+                }
+                _ => health.record_fetch(url, Ok(()), now),
+            }
+        }
+        if let Err(e) = health.save(&path) {
+            warn!("{e:#}");
+        }
+    }
 
-        let off_content = r#"#!/bin/sh
-unset http_proxy HTTP_PROXY https_proxy HTTPS_PROXY all_proxy ALL_PROXY
-"#;
+    /// Records how many of the nodes the core checked failed, see [`ProviderHealth::record_nodes`].
+    fn record_nodes(&self, failing: impl IntoIterator<Item = bool>) {
+        let (total, failing) = failing.into_iter().fold((0, 0), |(total, count), failing| {
+            (total + 1, count + usize::from(failing))
+        });
+        if total == 0 {
+            return;
+        }
+        let path = self.instance_dir.join(PROVIDER_HEALTH_FILE);
+        let mut health = ProviderHealth::load(&path);
+        health.record_nodes(total, failing, jiff::Timestamp::now().as_second());
+        if let Err(e) = health.save(&path) {
+            warn!("{e:#}");
+        }
+    }
 
-        fs::write(on_script_path, on_content)?;
-        fs::write(off_script_path, off_content)?;
+    /// The subscription URLs last passed to `start`, one per line.
+    fn saved_subscription_urls(&self) -> Result<Vec<String>> {
+        let path = self.instance_dir.join(SUBSCRIPTION_URL_FILE);
+        if !path.exists() {
+            return Ok(Vec::new());
+        }
+        Ok(fs::read_to_string(path)?
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .map(String::from)
+            .collect())
+    }
 
-        #[cfg(unix)]
+    /// The running instance if `start` can reload its config in place instead
+    /// of restarting it: Mihomo is running and neither other ports nor
+    /// `--watch` are asked for.
+    fn reloadable(&self, options: &StartOptions) -> Result<Option<ReloadTarget>> {
+        if self.core.kind() != CoreKind::Mihomo
+            || options.watch
+            || options.mixed_port.is_some()
+            || options.controller_port.is_some()
+            || options.socks_port.is_some()
+            || options.http_port.is_some()
+            || options.listen.is_some()
+            || options.controller_tls
+            || options.controller_unix
         {
-            use std::os::unix::fs::PermissionsExt;
-            fs::set_permissions(
-                self.proxy_data_dir.join("on"),
-                fs::Permissions::from_mode(0o755),
-            )?;
-            fs::set_permissions(
-                self.proxy_data_dir.join("off"),
-                fs::Permissions::from_mode(0o755),
-            )?;
+            return Ok(None);
         }
-
-        info!("Environment setup scripts written to proxy-data/on and proxy-data/off");
-        info!("You can `source proxy-data/on` to set up the proxy environment, and `source proxy-data/off` to unset it.");
-
-        Ok(())
+        let Some(pid) = self.is_running()? else {
+            return Ok(None);
+        };
+        let config_path = self.config_path();
+        let mixed_port = self.core.mixed_port(&config_path);
+        let controller_address = self.core.controller_address(&config_path);
+        Ok(mixed_port
+            .zip(controller_address)
+            .map(|(mixed_port, controller_address)| ReloadTarget {
+                pid,
+                mixed_port,
+                controller_address,
+                secret: self.core.secret(&config_path),
+            }))
     }
+
+    /// Applies the start flags that change the config and override.yaml, then
+    /// the overrides themselves and TUN mode.
+    fn apply_start_overrides(&self, options: &StartOptions) -> Result<()> {
+        let config_path = self.config_path();
+        if options.dns.is_some() || !options.nameservers.is_empty() {
+            self.require_mihomo("--dns")?;
+        }
+        if options.ipv6.is_some() {
+            self.require_mihomo("--ipv6")?;
+        }
+        if options.interface.is_some() {
+            self.require_mihomo("--interface")?;
+        }
+        if options.watch_config {
+            self.require_mihomo("--watch-config")?;
+        }
+        if options.include.is_some() || options.exclude.is_some() {
+            self.require_mihomo("--include/--exclude")?;
+            // Nodes the previous filters dropped are only in the pristine copy
+            if rebuild_from_pristine(&config_path)? {
+                debug!(
+                    "Filtering the nodes of {}",
+                    pristine_path(&config_path).display()
+                );
+            } else {
+                let previous = Overrides::load(&self.config_dir.join(OVERRIDES_FILE))?;
+                if previous.include.is_some() || previous.exclude.is_some() {
+                    warn!("Nodes dropped by the previous filters stay dropped until the subscription is downloaded again");
+                }
+            }
+        }
+        if options.auto_groups {
+            self.require_mihomo("--auto-groups")?;
+            if config_path.exists() {
+                let added = add_auto_groups(&config_path)?;
+                if !added.is_empty() {
+                    info!("Added proxy groups: {}", added.join(", "));
+                }
+            }
+        }
+        let overrides_path = self.config_dir.join(OVERRIDES_FILE);
+        let mut overrides = Overrides::load(&overrides_path)?;
+        if options.dns.is_some() || !options.nameservers.is_empty() {
+            let mut dns = overrides.dns.take().unwrap_or_default();
+            if let Some(mode) = options.dns {
+                dns.mode = mode;
+            }
+            if !options.nameservers.is_empty() {
+                dns.nameservers = options.nameservers.clone();
+            }
+            info!("Using {} DNS", dns.mode.as_str());
+            overrides.dns = Some(dns);
+            overrides.save(&overrides_path)?;
+        }
+        if let Some(ipv6) = options.ipv6 {
+            info!("IPv6 is {}", if ipv6 { "on" } else { "off" });
+            overrides.ipv6 = Some(ipv6);
+            overrides.save(&overrides_path)?;
+        }
+        if let Some(interface) = &options.interface {
+            if interface == "auto" {
+                info!("Outbound interface is detected by {}", self.core.name());
+                overrides.interface_name = None;
+                if config_path.exists() {
+                    remove_key(&config_path, "interface-name")?;
+                }
+            } else {
+                let networks = sysinfo::Networks::new_with_refreshed_list();
+                if !networks.contains_key(interface) {
+                    let mut names: Vec<&str> = networks.keys().map(String::as_str).collect();
+                    names.sort_unstable();
+                    warn!(
+                        "No network interface named {interface}, available: {}",
+                        names.join(", ")
+                    );
+                }
+                info!("Outbound traffic leaves through {interface}");
+                overrides.interface_name = Some(interface.clone());
+            }
+            overrides.save(&overrides_path)?;
+        }
+        if options.include.is_some() || options.exclude.is_some() {
+            for (filter, pattern) in [
+                (&mut overrides.include, &options.include),
+                (&mut overrides.exclude, &options.exclude),
+            ] {
+                if let Some(pattern) = pattern {
+                    *filter = Some(pattern.clone()).filter(|pattern| !pattern.is_empty());
+                }
+            }
+            overrides.save(&overrides_path)?;
+        }
+        if !options.bypass.is_empty() {
+            self.require_mihomo("--bypass")?;
+            let before = overrides.front_rules(overrides.adblock);
+            overrides.bypass = if options.bypass.contains(&Bypass::None) {
+                Vec::new()
+            } else {
+                options.bypass.clone()
+            };
+            // Rules of dropped presets are already in config.yaml
+            let after = overrides.front_rules(overrides.adblock);
+            if config_path.exists() {
+                for rule in before.iter().filter(|rule| !after.contains(rule)) {
+                    remove_config_rule(&config_path, rule)?;
+                }
+            }
+            match overrides.bypass.as_slice() {
+                [] => info!("No bypass rules"),
+                presets => info!(
+                    "Bypassing the proxy for {}",
+                    presets
+                        .iter()
+                        .filter_map(|preset| preset.to_possible_value())
+                        .map(|value| value.get_name().to_string())
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ),
+            }
+            overrides.save(&overrides_path)?;
+        }
+        // The override file holds Mihomo rules and DNS, sing-box configs are used as they are
+        if config_path.exists() && self.core.kind() == CoreKind::Mihomo {
+            overrides.apply(&config_path)?;
+        }
+        if let Some(tun) = options.tun {
+            overrides.tun = Some(tun);
+            overrides.save(&overrides_path)?;
+            if !tun {
+                info!("TUN mode is off");
+            }
+        }
+        if config_path.exists() {
+            if let Some(tun) = overrides.tun {
+                if tun && !has_tun_privileges(&self.core_path) {
+                    warn_missing_tun_privileges(&self.core_path);
+                }
+                self.core.set_tun(&config_path, tun)?;
+                if tun {
+                    info!("TUN mode is enabled");
+                }
+            }
+        }
+        self.apply_delay_test()
+    }
+
+    /// Points the delay tests of the config's groups at `delay-test-url` and
+    /// `delay-test-interval`, if set.
+    fn apply_delay_test(&self) -> Result<()> {
+        let config_path = self.config_path();
+        let url = self.settings.delay_test_url.as_deref();
+        let interval = self.settings.delay_test_interval;
+        if !config_path.exists() || (url.is_none() && interval.is_none()) {
+            return Ok(());
+        }
+        let changed = self.core.set_delay_test(&config_path, url, interval)?;
+        if changed > 0 {
+            info!(
+                "Set the delay test of {changed} groups to {}{}",
+                url.unwrap_or("their URL"),
+                interval
+                    .map(|secs| format!(" every {secs}s"))
+                    .unwrap_or_default()
+            );
+        }
+        Ok(())
+    }
+
+    /// Picks free ports for the controller and the proxy, and writes them,
+    /// the secret and the listeners asked for to the config.
+    fn write_listeners(&self, options: &StartOptions) -> Result<Listeners> {
+        let config_path = self.config_path();
+        let preferred_ext_port = options
+            .controller_port
+            .or(self.settings.controller_port)
+            .unwrap_or(DEFAULT_CONTROLLER_PORT);
+        // Ports of stopped instances stay theirs, so each keeps its own ports
+        let reserved = self.other_instances_ports()?;
+        let mut taken: Vec<u16> = reserved.iter().map(|(port, _)| *port).collect();
+        let listen = options.listen.unwrap_or(if options.container {
+            IpAddr::V4(Ipv4Addr::UNSPECIFIED)
+        } else {
+            IpAddr::V4(Ipv4Addr::LOCALHOST)
+        });
+        let controller_address = match self.controller_socket(options)? {
+            Some(path) => ControllerAddress::Unix(path),
+            None => {
+                let ext_port = if options.random_ports {
+                    find_random_port(&taken).context("Failed to find an unused port")?
+                } else {
+                    let port = find_unused_port_except(preferred_ext_port, &taken)
+                        .context("Failed to find an unused port")?;
+                    warn_port_moved(preferred_ext_port, port, "--controller-port", &reserved);
+                    port
+                };
+                info!("Found unused port: {ext_port}");
+                taken.push(ext_port);
+                ControllerAddress::Tcp(SocketAddr::new(listen, ext_port))
+            }
+        };
+        // With HTTPS the secret never crosses the network in plain text,
+        // the HTTP controller is only for proxy-rs itself
+        let (controller_address, tls_address) = match controller_address {
+            ControllerAddress::Tcp(address) if options.controller_tls => {
+                self.require_mihomo("--controller-tls")?;
+                let tls_port = if options.random_ports {
+                    find_random_port(&taken)
+                } else {
+                    find_unused_port_except(address.port() + 1, &taken)
+                }
+                .context("Failed to find an unused port")?;
+                taken.push(tls_port);
+                (
+                    ControllerAddress::Tcp(SocketAddr::new(
+                        IpAddr::V4(Ipv4Addr::LOCALHOST),
+                        address.port(),
+                    )),
+                    Some(SocketAddr::new(listen, tls_port)),
+                )
+            }
+            address => (address, None),
+        };
+
+        let preferred_mixed_port = options
+            .mixed_port
+            .or(self.settings.mixed_port)
+            .unwrap_or(DEFAULT_MIXED_PORT);
+        let mixed_port = if options.random_ports {
+            find_random_port(&taken).context("Failed to find unused port")?
+        } else {
+            let port = find_unused_port_except(preferred_mixed_port, &taken)
+                .context("Failed to find unused port")?;
+            warn_port_moved(preferred_mixed_port, port, "--mixed-port", &reserved);
+            port
+        };
+        taken.push(mixed_port);
+
+        // Written on every start, a new subscription download replaces the config
+        let secret = self.controller_secret()?;
+        self.core.set_secret(&config_path, &secret)?;
+        self.core.set_mixed_port(&config_path, mixed_port)?;
+        if options.container {
+            self.require_mihomo("--container")?;
+            update_allow_lan(&config_path, true)?;
+        }
+        info!("{} mixed-port is set to: {mixed_port}", self.core.name());
+        for (key, flag, preferred) in [
+            ("socks-port", "--socks-port", options.socks_port),
+            ("port", "--http-port", options.http_port),
+        ] {
+            let Some(preferred) = preferred else {
+                continue;
+            };
+            self.require_mihomo(flag)?;
+            let port = find_unused_port_except(preferred, &taken)
+                .context("Failed to find an unused port")?;
+            warn_port_moved(preferred, port, flag, &reserved);
+            update_listener_port(&config_path, key, port)?;
+            info!("{} {key} is set to: {port}", self.core.name());
+            taken.push(port);
+        }
+        self.core.set_external_controller(
+            &config_path,
+            &controller_address,
+            self.ui_path()?.as_deref(),
+        )?;
+        if self.core.kind() == CoreKind::Mihomo {
+            self.set_controller_tls(&config_path, tls_address)?;
+        }
+        Ok(Listeners {
+            controller_address,
+            tls_address,
+            mixed_port,
+            listen,
+            secret,
+        })
+    }
+
+    /// Logs how long `start` took, and prints each phase with `--trace-startup`.
+    fn report_startup(&self, options: &StartOptions) {
+        info!("{}", self.startup.summary());
+        if options.trace_startup {
+            match self.startup.to_json() {
+                Ok(json) => println!("{json}"),
+                Err(e) => warn!("Failed to write the startup trace: {e}"),
+            }
+        }
+    }
+
+    /// Socket for the controller with `--controller-unix`, `None` to listen on TCP.
+    fn controller_socket(&self, options: &StartOptions) -> Result<Option<PathBuf>> {
+        if !options.controller_unix {
+            return Ok(None);
+        }
+        self.require_mihomo("--controller-unix")?;
+        if cfg!(windows) {
+            warn!("--controller-unix is not supported on Windows, the controller listens on TCP");
+            return Ok(None);
+        }
+        let path = dunce::canonicalize(&self.config_dir)?.join(CONTROLLER_SOCKET_FILE);
+        if path.as_os_str().len() > MAX_SOCKET_PATH {
+            return Err(anyhow!(
+                "{} is too long for a Unix socket, use a shorter --data-dir",
+                path.display()
+            ));
+        }
+        Ok(Some(path))
+    }
+
+    /// Runs the core's check on the finished config. When it rejects a new
+    /// subscription, the previous config is restored and `false` is returned
+    /// so `start` can finish that one instead.
+    async fn validate_final_config(&self, config_changed: bool) -> Result<bool> {
+        if !self.core_path.exists() {
+            return Ok(true);
+        }
+        let Err(e) = doctor::run_core(&self.core_path, self.core.test_args(&self.config_dir)).await
+        else {
+            return Ok(true);
+        };
+        if config_changed && restore_backup(&self.config_path())? {
+            warn!(
+                "{} rejects the config with the new subscription: {e}, starting it with the previous config",
+                self.core.name()
+            );
+            return Ok(false);
+        }
+        Err(anyhow!("{} rejects the config: {e}", self.core.name()))
+    }
+
+    /// Reloads the new config, already pointed at the running instance's ports,
+    /// through `controller`, keeping the process and its connections.
+    async fn reload_in_place(
+        &self,
+        options: &StartOptions,
+        running: ReloadTarget,
+        controller: Controller,
+    ) -> Result<StartInfo> {
+        let pid = running.pid;
+        let mixed_port = running.mixed_port;
+        let controller_address = running.controller_address;
+        let config_path = self.config_path();
+        self.reload_config(&controller).await?;
+        info!(
+            "Reloaded the config of the running {} (pid: {pid}), connections and ports are kept",
+            self.display_name()
+        );
+        if options.auto_select {
+            let url = options
+                .check_url
+                .as_deref()
+                .unwrap_or(self.delay_test_url());
+            if let Err(e) = self.auto_select(&controller_address, url).await {
+                warn!("Failed to auto-select a node: {e}");
+            }
+        }
+        Ok(StartInfo {
+            pid,
+            mixed_port,
+            socks_port: parse_listener_port(&config_path, "socks-port"),
+            http_port: parse_listener_port(&config_path, "port"),
+            controller_port: controller_address.port(),
+        })
+    }
+
+    /// Points the config at the running instance's ports and secret, which a new
+    /// subscription replaces. Returns a controller for the running instance.
+    fn keep_running_ports(&self, running: &ReloadTarget) -> Result<Controller> {
+        let config_path = self.config_path();
+        let secret = match &running.secret {
+            Some(secret) => secret.clone(),
+            None => self.controller_secret()?,
+        };
+        self.core.set_secret(&config_path, &secret)?;
+        self.core.set_mixed_port(&config_path, running.mixed_port)?;
+        self.core.set_external_controller(
+            &config_path,
+            &running.controller_address,
+            self.ui_path()?.as_deref(),
+        )?;
+        Controller::connect(&running.controller_address, Some(secret))
+    }
+
+    /// Absolute path of the WebUI served by the controller, `None` with `ui = "none"`.
+    fn ui_path(&self) -> Result<Option<PathBuf>> {
+        if self.settings.ui == WebUi::None {
+            return Ok(None);
+        }
+        Ok(Some(dunce::canonicalize(
+            self.proxy_data_dir.join(self.settings.ui.name()),
+        )?))
+    }
+
+    /// Spawns the core with its output redirected to `mihomo.log`/`mihomo.err`.
+    /// When `append` is set the previous logs are kept, e.g. on a watchdog restart,
+    /// otherwise they are archived as `mihomo.log.1` and so on. A `supervised`
+    /// core, run by the watchdog, is stopped when proxy-rs dies.
+    fn spawn_mihomo(
+        &self,
+        controller_address: &ControllerAddress,
+        append: bool,
+        supervised: bool,
+        limits: &ResourceLimits,
+    ) -> Result<Child> {
+        let mut command = Command::new(&self.core_path);
+        command.args(self.core.run_args(
+            &self.config_dir,
+            controller_address,
+            self.ui_path()?.as_deref(),
+        ));
+
+        if !append {
+            for name in LOG_FILES {
+                logs::rotate(&self.instance_dir.join(name), self.log_keep())?;
+            }
+        }
+        // Always append, so the logs can be truncated while Mihomo writes to them
+        let open_log = |name: &str| {
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(self.instance_dir.join(name))
+        };
+        let stdout = Stdio::from(open_log("mihomo.log")?);
+        let stderr = Stdio::from(open_log("mihomo.err")?);
+
+        // In this order, the pre_exec hooks lower the niceness and raise the
+        // open-file limit as root, then switch user, then set the death signal
+        limits.apply(&mut command)?;
+        self.drop_privileges(&mut command)?;
+        if supervised {
+            child::tie_to_parent(&mut command);
+        }
+        let child = command.stdout(stdout).stderr(stderr).spawn()?;
+        if supervised {
+            self.adopt(&child);
+        }
+        limits.confine(&child, &self.cgroup_name());
+        Ok(child)
+    }
+
+    /// Runs the core as the `run-as` user when proxy-rs is root, granting the
+    /// binary the capabilities for TUN first.
+    fn drop_privileges(&self, command: &mut Command) -> Result<()> {
+        let Some(user) = self.settings.run_as.as_deref() else {
+            return Ok(());
+        };
+        if !is_elevated() {
+            debug!("Not root, the core runs as the current user instead of {user}");
+            return Ok(());
+        }
+        #[cfg(target_os = "linux")]
+        {
+            child::run_as(
+                command,
+                user,
+                &self.proxy_data_dir,
+                &self.config_dir,
+                &[&self.core_path],
+            )?;
+            grant_tun_capabilities(&self.core_path)?;
+            info!("Running {} as {user}", self.core.name());
+        }
+        #[cfg(not(target_os = "linux"))]
+        {
+            let _ = command;
+            warn!("run-as is only supported on Linux, the core runs as root");
+        }
+        Ok(())
+    }
+
+    /// The start flags for the core's resources, falling back to proxy-rs.toml.
+    fn resource_limits(&self, options: &StartOptions) -> ResourceLimits {
+        ResourceLimits {
+            memory_mb: options.memory_limit_mb.or(self.settings.memory_limit_mb),
+            nice: options.nice.or(self.settings.nice),
+            open_files: options.open_files_limit.or(self.settings.open_files_limit),
+        }
+    }
+
+    /// cgroup the memory limit of this instance's core is enforced in.
+    fn cgroup_name(&self) -> String {
+        match &self.instance {
+            Some(name) => format!("proxy-rs-{name}"),
+            None => "proxy-rs".to_string(),
+        }
+    }
+
+    /// [`child::adopt`], a failure only means the core may outlive a crash of proxy-rs.
+    fn adopt(&self, child: &Child) {
+        if let Err(e) = child::adopt(child) {
+            warn!(
+                "{e}, {} keeps running if proxy-rs is killed",
+                self.core.name()
+            );
+        }
+    }
+
+    /// Spawns the core with its output piped through proxy-rs's logger, and still
+    /// appended to `mihomo.log`/`mihomo.err` for `logs` and bug reports.
+    fn spawn_foreground(
+        &self,
+        controller_address: &ControllerAddress,
+        limits: &ResourceLimits,
+    ) -> Result<Child> {
+        let mut command = Command::new(&self.core_path);
+        command.args(self.core.run_args(
+            &self.config_dir,
+            controller_address,
+            self.ui_path()?.as_deref(),
+        ));
+        for name in LOG_FILES {
+            logs::rotate(&self.instance_dir.join(name), self.log_keep())?;
+        }
+        // Same order as in spawn_mihomo
+        limits.apply(&mut command)?;
+        self.drop_privileges(&mut command)?;
+        child::tie_to_parent(&mut command);
+        let mut child = command
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+        self.adopt(&child);
+        limits.confine(&child, &self.cgroup_name());
+
+        let stdout = child
+            .stdout
+            .take()
+            .map(|s| Box::new(s) as Box<dyn Read + Send>);
+        let stderr = child
+            .stderr
+            .take()
+            .map(|s| Box::new(s) as Box<dyn Read + Send>);
+        let target = self.core.name();
+        // Lines that aren't Mihomo's format, e.g. a panic, at the level of their stream
+        for (output, name, default) in [
+            (stdout, "mihomo.log", Level::Info),
+            (stderr, "mihomo.err", Level::Warn),
+        ] {
+            let Some(output) = output else {
+                continue;
+            };
+            let mut file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(self.instance_dir.join(name))?;
+            // Read from the start, the core blocks once a pipe buffer is full
+            std::thread::spawn(move || {
+                for line in BufReader::new(output).lines().map_while(|line| line.ok()) {
+                    let _ = writeln!(file, "{line}");
+                    match logs::parse_core_line(&line) {
+                        Some((level, message)) => log!(target: target, level, "{message}"),
+                        None => log!(target: target, default, "{line}"),
+                    }
+                }
+            });
+        }
+        Ok(child)
+    }
+
+    /// Waits for a core started by [`Self::spawn_foreground`] to exit, or stops it
+    /// gracefully on Ctrl+C or SIGTERM, e.g. from systemd or `docker stop`.
+    async fn run_foreground(&self, mut child: Child) -> Result<()> {
+        #[cfg(unix)]
+        let mut sigterm =
+            tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())?;
+        loop {
+            #[cfg(unix)]
+            let terminated = sigterm.recv();
+            #[cfg(not(unix))]
+            let terminated = std::future::pending::<Option<()>>();
+            tokio::select! {
+                _ = tokio::time::sleep(Duration::from_millis(200)) => {}
+                _ = tokio::signal::ctrl_c() => break,
+                _ = terminated => break,
+            }
+            if let Some(status) = child.try_wait()? {
+                let _ = fs::remove_file(self.instance_dir.join(MIHOMO_PID_FILE));
+                return Err(anyhow!("{} exited ({status})", self.core.name()));
+            }
+        }
+
+        info!("Stopping {}...", self.display_name());
+        self.terminate_child(&mut child)?;
+        let _ = fs::remove_file(self.instance_dir.join(MIHOMO_PID_FILE));
+        if let Err(e) = sysproxy::restore(&self.instance_dir.join(SYSPROXY_BACKUP_FILE)) {
+            warn!("Failed to restore system proxy settings: {e}");
+        }
+        info!("{} stopped.", self.display_name());
+        Ok(())
+    }
+
+    /// Waits for the controller and the mixed port to come up, then makes a test
+    /// request through the proxy. Fails if Mihomo exits in the meantime.
+    async fn verify_started(
+        &self,
+        child: &mut Child,
+        controller_address: &ControllerAddress,
+        mixed_port: u16,
+        check_url: &str,
+    ) -> Result<()> {
+        info!("Verifying that {} is up...", self.core.name());
+        let secret = self.core.secret(&self.config_path());
+        let controller = Controller::connect(controller_address, secret)?;
+        let deadline = Instant::now() + STARTUP_TIMEOUT;
+
+        let running_config = loop {
+            if let Some(status) = child.try_wait()? {
+                let _ = fs::remove_file(self.instance_dir.join(MIHOMO_PID_FILE));
+                let log = self.log_excerpt();
+                let message = format!(
+                    "{} exited during startup ({status}):\n{log}",
+                    self.core.name()
+                );
+                return Err(match errors::from_core_log(&log, self.core_path.clone()) {
+                    Some(failure) => anyhow::Error::new(failure).context(message),
+                    None => anyhow!(message),
+                });
+            }
+            if let Ok(config) = controller.configs().await {
+                break config;
+            }
+            if Instant::now() >= deadline {
+                return Err(anyhow!(
+                    "{} controller did not come up within {STARTUP_TIMEOUT:?}:\n{}",
+                    self.core.name(),
+                    self.log_excerpt()
+                ));
+            }
+            tokio::time::sleep(Duration::from_millis(200)).await;
+        };
+
+        // sing-box's controller reports no ports
+        let port = [running_config.mixed_port, running_config.port]
+            .into_iter()
+            .find(|p| *p != 0)
+            .unwrap_or(mixed_port);
+        // A filtered port would otherwise hold the worker for the OS connect timeout
+        let accepts = || async {
+            let connect = tokio::net::TcpStream::connect(("127.0.0.1", port));
+            matches!(
+                tokio::time::timeout(Duration::from_secs(1), connect).await,
+                Ok(Ok(_))
+            )
+        };
+        while !accepts().await {
+            if Instant::now() >= deadline {
+                return Err(anyhow!("Port {port} is not accepting connections"));
+            }
+            tokio::time::sleep(Duration::from_millis(200)).await;
+        }
+
+        let client = Client::builder()
+            .proxy(reqwest::Proxy::all(format!("http://127.0.0.1:{port}"))?)
+            .timeout(Duration::from_secs(10))
+            .build()?;
+        let start_time = Instant::now();
+        match client.get(check_url).send().await {
+            Ok(response) => info!(
+                "Proxy works: {check_url} returned {} in {:?}",
+                response.status(),
+                start_time.elapsed()
+            ),
+            Err(e) => warn!(
+                "{} is running but the test request to {check_url} failed: {e}. \
+                 Check your subscription nodes.",
+                self.core.name()
+            ),
+        }
+        Ok(())
+    }
+
+    /// Waits up to [`STARTUP_TIMEOUT`] for the controller of a core just started.
+    async fn wait_for_controller(
+        &self,
+        controller_address: &ControllerAddress,
+    ) -> Result<Controller> {
+        let secret = self.core.secret(&self.config_path());
+        let controller = Controller::connect(controller_address, secret)?;
+        let deadline = Instant::now() + STARTUP_TIMEOUT;
+        while controller.version().await.is_err() {
+            if Instant::now() >= deadline {
+                return Err(anyhow!(
+                    "the controller did not come up within {STARTUP_TIMEOUT:?}"
+                ));
+            }
+            tokio::time::sleep(Duration::from_millis(200)).await;
+        }
+        Ok(controller)
+    }
+
+    /// With `sticky-selection`, waits for the controller of a core just started
+    /// and selects the nodes saved before it stopped.
+    async fn restore_selections_after_start(&self, controller_address: &ControllerAddress) {
+        if !self.settings.sticky_selection {
+            return;
+        }
+        match self.wait_for_controller(controller_address).await {
+            Ok(controller) => self.restore_selections(&controller).await,
+            Err(e) => warn!("Failed to restore the selected nodes: {e}"),
+        }
+    }
+
+    /// Waits for the controller, then switches the main selector group to the
+    /// node with the lowest delay to `url`.
+    async fn auto_select(&self, controller_address: &ControllerAddress, url: &str) -> Result<()> {
+        let controller = self.wait_for_controller(controller_address).await?;
+        let (group, node, delay) = picker::select_fastest(&controller, url).await?;
+        info!("Auto-selected {node} ({delay} ms) in {group}");
+        Ok(())
+    }
+
+    /// The last lines of Mihomo's output, for error messages.
+    fn log_excerpt(&self) -> String {
+        ["mihomo.err", "mihomo.log"]
+            .iter()
+            .filter_map(|name| fs::read_to_string(self.instance_dir.join(name)).ok())
+            .flat_map(|content| {
+                let lines: Vec<String> = content.lines().map(String::from).collect();
+                lines[lines.len().saturating_sub(10)..].to_vec()
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// Keeps Mihomo alive, restarting it with exponential backoff whenever it exits,
+    /// until Ctrl+C is pressed or `stop` kills the watchdog.
+    async fn supervise(
+        &self,
+        mut child: Child,
+        controller_address: &ControllerAddress,
+        limits: &ResourceLimits,
+    ) -> Result<()> {
+        fs::write(
+            self.instance_dir.join(WATCHDOG_PID_FILE),
+            std::process::id().to_string(),
+        )?;
+        info!("Watchdog is supervising Mihomo, press Ctrl+C to stop");
+
+        let mut backoff = WATCHDOG_MIN_BACKOFF;
+        let mut started_at = Instant::now();
+        let mut restarts = 0;
+        let network_action = self.settings.network_change.action();
+        let network_interval = self.settings.network_change.interval();
+        let mut network = (network_action != NetworkAction::Off).then(NetworkMonitor::new);
+        let mut network_checked_at = Instant::now();
+
+        loop {
+            tokio::select! {
+                _ = tokio::time::sleep(Duration::from_secs(1)) => {}
+                _ = tokio::signal::ctrl_c() => break,
+            }
+
+            let Some(status) = child.try_wait()? else {
+                self.rotate_large_logs();
+                let Some(monitor) = network.as_mut() else {
+                    continue;
+                };
+                if network_checked_at.elapsed() < network_interval {
+                    continue;
+                }
+                network_checked_at = Instant::now();
+                if let Some(change) = monitor.check() {
+                    if let Some(restarted) = self
+                        .on_network_change(
+                            &change,
+                            network_action,
+                            &mut child,
+                            controller_address,
+                            limits,
+                        )
+                        .await?
+                    {
+                        child = restarted;
+                        started_at = Instant::now();
+                    }
+                    monitor.reset();
+                }
+                continue;
+            };
+            let restart_request = self.instance_dir.join(RESTART_REQUEST_FILE);
+            if restart_request.exists() {
+                let _ = fs::remove_file(&restart_request);
+                child = self.spawn_mihomo(controller_address, true, true, limits)?;
+                self.save_pid(&child)?;
+                started_at = Instant::now();
+                info!("{} restarted (pid: {})", self.core.name(), child.id());
+                self.restore_selections_after_start(controller_address)
+                    .await;
+                continue;
+            }
+            warn!(
+                "{} exited unexpectedly ({status}), see mihomo.err for details",
+                self.core.name()
+            );
+            self.fire_hooks(
+                Event::Crashed,
+                &format!(
+                    "{} exited unexpectedly ({status}), restarting it",
+                    self.core.name()
+                ),
+            )
+            .await;
+
+            if started_at.elapsed() >= WATCHDOG_STABLE_UPTIME {
+                backoff = WATCHDOG_MIN_BACKOFF;
+            }
+            info!("Restarting Mihomo in {backoff:?}...");
+            tokio::select! {
+                _ = tokio::time::sleep(backoff) => {}
+                _ = tokio::signal::ctrl_c() => {
+                    let _ = fs::remove_file(self.instance_dir.join(WATCHDOG_PID_FILE));
+                    return Ok(());
+                }
+            }
+            backoff = (backoff * 2).min(WATCHDOG_MAX_BACKOFF);
+
+            child = self.spawn_mihomo(controller_address, true, true, limits)?;
+            self.save_pid(&child)?;
+            started_at = Instant::now();
+            restarts += 1;
+            info!(
+                "{} restarted (pid: {}, restarts: {restarts})",
+                self.core.name(),
+                child.id()
+            );
+        }
+
+        info!("Stopping Mihomo...");
+        self.terminate_child(&mut child)?;
+        let _ = fs::remove_file(self.instance_dir.join(MIHOMO_PID_FILE));
+        let _ = fs::remove_file(self.instance_dir.join(WATCHDOG_PID_FILE));
+        Ok(())
+    }
+
+    /// Waits for the network to settle after `change`, then closes the core's
+    /// connections or restarts it as `action` says. Returns the new core if it
+    /// was restarted.
+    async fn on_network_change(
+        &self,
+        change: &NetworkChange,
+        action: NetworkAction,
+        child: &mut Child,
+        controller_address: &ControllerAddress,
+        limits: &ResourceLimits,
+    ) -> Result<Option<Child>> {
+        let message = match action {
+            NetworkAction::Off => change.to_string(),
+            NetworkAction::Flush => {
+                format!("{change}, closing the connections of {}", self.core.name())
+            }
+            NetworkAction::Restart => format!("{change}, restarting {}", self.core.name()),
+        };
+        info!("{message}");
+        self.fire_hooks(Event::NetworkChanged, &message).await;
+        tokio::time::sleep(NETWORK_SETTLE).await;
+        match action {
+            NetworkAction::Off => Ok(None),
+            NetworkAction::Flush => {
+                let result = async {
+                    let controller = self.wait_for_controller(controller_address).await?;
+                    controller.close_all_connections().await
+                }
+                .await;
+                match result {
+                    Ok(()) => info!("Closed all connections"),
+                    Err(e) => warn!("Failed to close the connections: {e:#}"),
+                }
+                Ok(None)
+            }
+            NetworkAction::Restart => {
+                self.terminate_child(child)?;
+                let restarted = self.spawn_mihomo(controller_address, true, true, limits)?;
+                self.save_pid(&restarted)?;
+                info!("{} restarted (pid: {})", self.core.name(), restarted.id());
+                self.restore_selections_after_start(controller_address)
+                    .await;
+                Ok(Some(restarted))
+            }
+        }
+    }
+
+    /// Runs the jobs in `[schedule]` of proxy-rs.toml until Ctrl+C is pressed,
+    /// for servers without cron. With `list`, only prints when they run next.
+    pub async fn schedule(&self, list: bool) -> Result<()> {
+        let jobs = self.settings.schedule.jobs()?;
+        if jobs.is_empty() {
+            return Err(anyhow!(
+                "No jobs are scheduled, add them to [schedule] in {}",
+                self.proxy_data_dir.join(SETTINGS_FILE).display()
+            ));
+        }
+        if list {
+            let now = jiff::Zoned::now();
+            for (job, cron) in &jobs {
+                let next = cron.next_after(&now).map_or_else(
+                    || "never".to_string(),
+                    |next| next.strftime("%Y-%m-%d %H:%M").to_string(),
+                );
+                println!("{job:<22}{cron:<18}next: {next}");
+            }
+            return Ok(());
+        }
+        info!("Running scheduled jobs, press Ctrl+C to stop");
+        self.run_jobs(jobs).await
+    }
+
+    /// Runs each job whenever its expression matches, until Ctrl+C is pressed.
+    /// A failed job is logged and runs again at its next time.
+    async fn run_jobs(&self, jobs: Vec<(Job, Cron)>) -> Result<()> {
+        let mut jobs: Vec<(Job, Cron, Option<jiff::Zoned>)> = jobs
+            .into_iter()
+            .map(|(job, cron)| {
+                let next = cron.next_after(&jiff::Zoned::now());
+                match &next {
+                    Some(next) => info!(
+                        "Scheduled {job} ({cron}), next run at {}",
+                        next.strftime("%Y-%m-%d %H:%M")
+                    ),
+                    None => warn!("{job} is never run, {cron} matches no date"),
+                }
+                (job, cron, next)
+            })
+            .collect();
+        if jobs.iter().all(|(_, _, next)| next.is_none()) {
+            return Ok(());
+        }
+
+        loop {
+            // Wake up at least every minute so a suspended machine catches up quickly
+            let until_next = jobs
+                .iter()
+                .filter_map(|(_, _, next)| next.as_ref())
+                .map(|next| {
+                    // Overdue jobs run right away
+                    let until = jiff::Timestamp::now().duration_until(next.timestamp());
+                    Duration::try_from(until).unwrap_or(Duration::ZERO)
+                })
+                .min()
+                .unwrap_or(SCHEDULE_MAX_SLEEP)
+                .min(SCHEDULE_MAX_SLEEP);
+            tokio::select! {
+                _ = tokio::time::sleep(until_next) => {}
+                _ = tokio::signal::ctrl_c() => return Ok(()),
+            }
+
+            for (job, cron, next) in &mut jobs {
+                if next
+                    .as_ref()
+                    .is_none_or(|next| next.timestamp() > jiff::Timestamp::now())
+                {
+                    continue;
+                }
+                info!("Running scheduled {job}");
+                if let Err(e) = self.run_job(*job).await {
+                    warn!("Scheduled {job} failed: {e:#}");
+                }
+                *next = cron.next_after(&jiff::Zoned::now());
+            }
+        }
+    }
+
+    async fn run_job(&self, job: Job) -> Result<()> {
+        match job {
+            Job::RefreshSubscription => self.refresh_subscription().await,
+            Job::UpdateGeodata => self.update_geodata(false).await,
+            Job::UpdateCore => {
+                let installed = self.installed_core_version()?;
+                let updated = self.update_core(false, None, None).await?;
+                if installed.as_deref() != Some(updated.as_str()) && self.is_running()?.is_some() {
+                    Box::pin(self.restart()).await?;
+                }
+                Ok(())
+            }
+            Job::Reselect => {
+                let controller = self.controller()?;
+                let (group, node, delay) =
+                    picker::select_fastest(&controller, self.delay_test_url()).await?;
+                info!("Selected {node} ({delay} ms) in {group}");
+                Ok(())
+            }
+            Job::SampleStats => self.sample_stats().await,
+        }
+    }
+
+    /// Reloads the config with [`Self::reload_validated`] whenever config.yaml or
+    /// the override file changes, until Ctrl+C is pressed.
+    async fn watch_config(&self) -> Result<()> {
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let mut watcher = notify::recommended_watcher(move |event| {
+            if let Ok(event) = event {
+                let _ = tx.send(event);
+            }
+        })?;
+        watcher.watch(&self.config_dir, RecursiveMode::NonRecursive)?;
+        info!(
+            "Watching {} for changes, press Ctrl+C to stop",
+            self.config_dir.display()
+        );
+
+        let config_path = self.config_path();
+        let overrides_path = self.config_dir.join(OVERRIDES_FILE);
+        let read = || {
+            (
+                fs::read(&config_path).unwrap_or_default(),
+                fs::read(&overrides_path).unwrap_or_default(),
+            )
+        };
+        let mut last = read();
+        loop {
+            let event: notify::Event = tokio::select! {
+                event = rx.recv() => match event {
+                    Some(event) => event,
+                    None => break,
+                },
+                _ = tokio::signal::ctrl_c() => break,
+            };
+            if !event
+                .paths
+                .iter()
+                .any(|path| *path == config_path || *path == overrides_path)
+            {
+                continue;
+            }
+            tokio::time::sleep(CONFIG_WATCH_DEBOUNCE).await;
+            while rx.try_recv().is_ok() {}
+            // Reloading applies the overrides, which writes config.yaml again
+            let current = read();
+            if current == last {
+                continue;
+            }
+
+            info!("The config changed, reloading...");
+            let result = async {
+                let _lock = self.lock_instance()?;
+                let controller = self.controller()?;
+                self.reload_validated(&controller).await
+            }
+            .await;
+            if let Err(e) = result {
+                warn!("Failed to reload the config: {e}");
+            }
+            last = read();
+        }
+        Ok(())
+    }
+
+    /// Stops Mihomo and its watchdog, and restores the system proxy if
+    /// `sysproxy_on` changed it.
+    pub async fn stop(&self) -> Result<()> {
+        let _lock = self.lock_instance()?;
+        self.is_running_or_adopt()?;
+        self.save_running_selections().await;
+        self.stop_locked().await
+    }
+
+    /// Stops the core gracefully and starts it again on the same ports. A
+    /// watchdog started with `--watch` is left to start it again itself.
+    pub async fn restart(&self) -> Result<()> {
+        let lock = self.lock_instance()?;
+        let Some(pid) = self.is_running()? else {
+            return Err(anyhow!(
+                "{} is not running, start it first",
+                self.display_name()
+            ));
+        };
+        self.refuse_adopted("restart")?;
+        let system = sysinfo::System::new_all();
+        let watchdog = fs::read_to_string(self.instance_dir.join(WATCHDOG_PID_FILE))
+            .ok()
+            .and_then(|pid| sysinfo::Pid::from_str(pid.trim()).ok())
+            .filter(|pid| system.process(*pid).is_some());
+        if watchdog.is_some() {
+            self.save_running_selections().await;
+            fs::write(self.instance_dir.join(RESTART_REQUEST_FILE), "")?;
+            if let Some(p) = system.process(sysinfo::Pid::from_u32(pid)) {
+                terminate(p, self.stop_timeout())?;
+            }
+            info!(
+                "{} stopped, the watchdog starts it again",
+                self.display_name()
+            );
+            return Ok(());
+        }
+
+        let options = self.running_start_options();
+        self.startup.reset();
+        self.start_locked(&options, lock, false).await.map(|_| ())
+    }
+
+    /// Options that start the core again the way config.yaml says it runs:
+    /// on the same ports and listeners, checked once it is up.
+    fn running_start_options(&self) -> StartOptions {
+        let config_path = self.config_path();
+        let controller = self
+            .core
+            .external_controller(&config_path)
+            .and_then(|address| address.parse::<SocketAddr>().ok());
+        let tls = parse_controller_tls(&config_path)
+            .and_then(|address| address.parse::<SocketAddr>().ok());
+        StartOptions {
+            check_url: Some(self.delay_test_url().to_string()),
+            mixed_port: self.core.mixed_port(&config_path),
+            controller_port: controller.map(|address| address.port()),
+            socks_port: parse_listener_port(&config_path, "socks-port"),
+            http_port: parse_listener_port(&config_path, "port"),
+            listen: tls.or(controller).map(|address| address.ip()),
+            controller_tls: tls.is_some(),
+            controller_unix: matches!(
+                self.core.controller_address(&config_path),
+                Some(ControllerAddress::Unix(_))
+            ),
+            ..Default::default()
+        }
+    }
+
+    async fn stop_locked(&self) -> Result<()> {
+        let pid = self
+            .load_pid()
+            .filter(|pid| sysinfo::System::new_all().process(*pid).is_some());
+        if let Some(pid) = pid {
+            self.run_script_or_warn(Stage::PreStop, Some(pid.as_u32()))
+                .await;
+        }
+
+        // Stop the watchdog first, otherwise it restarts Mihomo right away
+        let system = sysinfo::System::new_all();
+        let watchdog = fs::read_to_string(self.instance_dir.join(WATCHDOG_PID_FILE))
+            .ok()
+            .and_then(|pid| sysinfo::Pid::from_str(pid.trim()).ok())
+            .and_then(|pid| system.process(pid));
+        if let Some(p) = watchdog {
+            p.kill_and_wait()
+                .map_err(|e| anyhow!("Failed to stop the watchdog: {e:?}"))?;
+            info!("Watchdog stopped.");
+        }
+        let _ = fs::remove_file(self.instance_dir.join(WATCHDOG_PID_FILE));
+
+        match pid {
+            Some(pid) => {
+                // On Linux the core is already exiting if the watchdog ran it
+                let mut system = sysinfo::System::new();
+                system.refresh_processes(sysinfo::ProcessesToUpdate::Some(&[pid]), true);
+                if let Some(p) = system.process(pid) {
+                    terminate(p, self.stop_timeout())?;
+                }
+                info!("{} stopped.", self.display_name());
+                self.run_script_or_warn(Stage::PostStop, None).await;
+            }
+            None => {
+                warn!("{} process not found.", self.display_name());
+            }
+        }
+        let _ = fs::remove_file(self.instance_dir.join(MIHOMO_PID_FILE));
+        let _ = fs::remove_file(self.instance_dir.join(ADOPTED_FILE));
+
+        if let Err(e) = sysproxy::restore(&self.instance_dir.join(SYSPROXY_BACKUP_FILE)) {
+            warn!("Failed to restore system proxy settings: {e}");
+        }
+        Ok(())
+    }
+
+    /// Stops Mihomo and removes the selected downloaded artifacts, asking first
+    /// unless `force` is set. The config and proxy-rs settings are always kept.
+    pub async fn clean(&self, targets: &CleanTargets, force: bool) -> Result<()> {
+        let _lock = self.lock_instance()?;
+        let _core_lock = self.lock_core()?;
+        let what: Vec<&str> = [
+            (targets.core, "the core binary"),
+            (targets.ui, "the WebUI"),
+            (targets.cache, "geodata, caches and logs"),
+        ]
+        .into_iter()
+        .filter_map(|(selected, what)| selected.then_some(what))
+        .collect();
+        let running = self.is_running()?.is_some();
+        let stopping = if running {
+            format!(", stopping the running {}", self.display_name())
+        } else {
+            String::new()
+        };
+        if !confirm_destructive(
+            &format!(
+                "This removes {} from {}{stopping}. Continue?",
+                what.join(", "),
+                self.proxy_data_dir.display()
+            ),
+            force,
+        ) {
+            return Ok(());
+        }
+        if running {
+            self.stop_locked().await?;
+        }
+
+        let mut paths = Vec::new();
+        if targets.core {
+            // Never the binary set as `core-path`, proxy-rs didn't download it
+            let core_path = core_binary_path(&self.proxy_data_dir, self.core);
+            paths.push(core_path.with_extension("bak"));
+            paths.push(core_path);
+        }
+        if targets.ui {
+            for ui in WebUi::ALL {
+                paths.push(self.proxy_data_dir.join(ui.name()));
+            }
+        }
+        if targets.cache {
+            for file in ["geosite.dat", "geoip.dat", "country.mmdb", "cache.db"] {
+                paths.push(self.config_dir.join(file));
+            }
+            for file in ["mihomo.log", "mihomo.err"] {
+                paths.push(self.instance_dir.join(file));
+            }
+        }
+
+        let mut removed = 0;
+        if let Some(cache) = self.shared_cache.as_ref().filter(|_| targets.cache) {
+            let (files, bytes) = cache.usage()?;
+            if files > 0 {
+                cache.clear()?;
+                info!(
+                    "Removed the shared download cache {} ({files} files, {})",
+                    cache.dir().display(),
+                    format_bytes(bytes)
+                );
+                removed += 1;
+            }
+        }
+        for path in paths.iter().filter(|p| p.exists()) {
+            if path.is_dir() {
+                fs::remove_dir_all(path)?;
+            } else {
+                fs::remove_file(path)?;
+            }
+            info!("Removed {}", path.display());
+            removed += 1;
+        }
+        // Only the entries of what was removed, the rest is still installed
+        self.update_manifest(|manifest| {
+            if targets.core {
+                manifest.forget_core();
+            }
+            if targets.ui {
+                manifest.ui.clear();
+            }
+            if targets.cache {
+                for file in ["geosite.dat", "geoip.dat", "country.mmdb"] {
+                    manifest.geodata.remove(&self.geodata_key(file));
+                }
+            }
+        })?;
+        if removed == 0 {
+            info!("Nothing to clean");
+        }
+        Ok(())
+    }
+
+    /// Names of the instances created with `instance create`.
+    pub fn instances(proxy_data_dir: &Path) -> Result<Vec<String>> {
+        let instances_dir = proxy_data_dir.join(INSTANCES_DIR);
+        if !instances_dir.is_dir() {
+            return Ok(Vec::new());
+        }
+        let mut names: Vec<String> = fs::read_dir(instances_dir)?
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.path().is_dir())
+            .filter_map(|entry| entry.file_name().into_string().ok())
+            .collect();
+        names.sort();
+        Ok(names)
+    }
+
+    /// Creates the named instance `name` for `--instance`, with a private
+    /// config dir like the default instance's.
+    pub fn create_instance(proxy_data_dir: &Path, name: &str) -> Result<()> {
+        let instance_dir = proxy_data_dir.join(INSTANCES_DIR).join(name);
+        if instance_dir.exists() {
+            return Err(anyhow!("Instance {name} already exists"));
+        }
+        let config_dir = instance_dir.join("config");
+        fs::create_dir_all(&config_dir)?;
+        make_private(&config_dir)
+    }
+
+    /// Mixed and controller ports in the config, picked on the last start.
+    pub fn configured_ports(&self) -> (Option<u16>, Option<u16>) {
+        instance_ports(&self.instance_dir, self.settings.core)
+    }
+
+    /// Names and directories of the default instance and every named one.
+    fn instance_dirs(&self) -> Result<Vec<(Option<String>, PathBuf)>> {
+        let mut dirs = vec![(None, self.proxy_data_dir.clone())];
+        for name in Self::instances(&self.proxy_data_dir)? {
+            let instance_dir = self.proxy_data_dir.join(INSTANCES_DIR).join(&name);
+            dirs.push((Some(name), instance_dir));
+        }
+        Ok(dirs)
+    }
+
+    /// Configured ports of the other instances, with the instance they belong to.
+    fn other_instances_ports(&self) -> Result<Vec<(u16, String)>> {
+        let mut ports = Vec::new();
+        for (name, instance_dir) in self.instance_dirs()? {
+            if name == self.instance {
+                continue;
+            }
+            let (mixed_port, controller_port) = instance_ports(&instance_dir, self.settings.core);
+            let name = name.unwrap_or_else(|| "default".to_string());
+            for port in [mixed_port, controller_port].into_iter().flatten() {
+                ports.push((port, name.clone()));
+            }
+        }
+        Ok(ports)
+    }
+
+    /// Serializes start/stop and other operations on this instance.
+    fn lock_instance(&self) -> Result<File> {
+        lock_file(&self.instance_dir.join(LOCK_FILE))
+    }
+
+    /// Serializes changes to the core binary shared by all instances.
+    fn lock_core(&self) -> Result<File> {
+        lock_file(&self.proxy_data_dir.join(CORE_LOCK_FILE))
+    }
+
+    /// Stops the core this process spawned as `stop` does, letting it flush
+    /// its state before it is killed.
+    fn terminate_child(&self, child: &mut Child) -> Result<()> {
+        let pid = sysinfo::Pid::from_u32(child.id());
+        let mut system = sysinfo::System::new();
+        system.refresh_processes(sysinfo::ProcessesToUpdate::Some(&[pid]), true);
+        if let Some(process) = system.process(pid) {
+            terminate(process, self.stop_timeout())?;
+        }
+        let _ = child.wait();
+        Ok(())
+    }
+
+    fn stop_timeout(&self) -> Duration {
+        Duration::from_secs(
+            self.settings
+                .stop_timeout
+                .unwrap_or(DEFAULT_STOP_TIMEOUT_SECS),
+        )
+    }
+
+    fn log_keep(&self) -> usize {
+        self.settings.log_keep.unwrap_or(DEFAULT_LOG_KEEP)
+    }
+
+    /// Rotates the logs of the running Mihomo that grew beyond `log-max-size-mb`.
+    fn rotate_large_logs(&self) {
+        let max_size = self
+            .settings
+            .log_max_size_mb
+            .unwrap_or(DEFAULT_LOG_MAX_SIZE_MB)
+            * 1024
+            * 1024;
+        for name in LOG_FILES {
+            let path = self.instance_dir.join(name);
+            if let Err(e) = logs::rotate_if_larger(&path, max_size, self.log_keep()) {
+                warn!("Failed to rotate {name}: {e}");
+            }
+        }
+    }
+
+    /// Runs the hooks from the settings that are subscribed to `event`.
+    async fn fire_hooks(&self, event: Event, message: &str) {
+        let instance = self.instance.as_deref().unwrap_or("default");
+        hooks::fire(&self.settings.hooks, event, instance, message).await;
+    }
+
+    /// Runs the `hooks/<stage>` script, telling it `pid` and what
+    /// [`Self::script_env`] does through `PROXY_RS_*` variables.
+    async fn run_script(&self, stage: Stage, pid: Option<u32>) -> Result<()> {
+        let mut env = self.script_env();
+        if let Some(pid) = pid {
+            env.push(("PROXY_RS_PID", pid.to_string()));
+        }
+        hooks::run_script(&self.proxy_data_dir.join(SCRIPTS_DIR), stage, &env).await
+    }
+
+    /// The instance, config path, data dir, ports and controller secret, for
+    /// hook scripts and plugins.
+    fn script_env(&self) -> Vec<(&'static str, String)> {
+        // Scripts may change the working directory
+        let absolute = |path: &Path| {
+            dunce::canonicalize(path)
+                .unwrap_or_else(|_| path.to_path_buf())
+                .display()
+                .to_string()
+        };
+        let config_path = self.config_path();
+        let mut env = vec![
+            (
+                "PROXY_RS_INSTANCE",
+                self.instance.as_deref().unwrap_or("default").to_string(),
+            ),
+            ("PROXY_RS_CONFIG", absolute(&config_path)),
+            // Also read by `--data-dir`, so the script can run proxy-rs itself
+            ("PROXY_RS_DATA_DIR", absolute(&self.proxy_data_dir)),
+        ];
+        if let Some(port) = self.core.mixed_port(&config_path) {
+            env.push(("PROXY_RS_MIXED_PORT", port.to_string()));
+        }
+        match self.core.controller_address(&config_path) {
+            Some(ControllerAddress::Unix(path)) => {
+                env.push(("PROXY_RS_CONTROLLER_UNIX", path.display().to_string()))
+            }
+            _ => {
+                if let Some(address) = self.core.external_controller(&config_path) {
+                    env.push(("PROXY_RS_CONTROLLER", address));
+                }
+            }
+        }
+        if let Some(secret) = self.core.secret(&config_path) {
+            env.push(("PROXY_RS_SECRET", secret));
+        }
+        env
+    }
+
+    /// Runs `proxy-rs-<name>` from PATH with `args`, for a subcommand proxy-rs
+    /// doesn't have, like git does. It gets the variables of hook scripts, so
+    /// it can reach the controller or run proxy-rs on the same data dir.
+    /// Returns its exit code, on Unix it replaces proxy-rs instead.
+    pub fn run_plugin(&self, name: &str, args: &[OsString]) -> Result<i32> {
+        let binary = format!("{PLUGIN_PREFIX}{name}");
+        let path = which::which(&binary).map_err(|_| {
+            anyhow!("`{name}` is not a proxy-rs command, and no {binary} was found on PATH")
+        })?;
+        debug!("Running the plugin {}", path.display());
+        let mut command = Command::new(&path);
+        command.args(args).envs(self.script_env());
+        #[cfg(unix)]
+        {
+            use std::os::unix::process::CommandExt;
+            let e = command.exec();
+            Err(anyhow!("Failed to run {}: {e}", path.display()))
+        }
+        #[cfg(not(unix))]
+        {
+            let status = command
+                .status()
+                .map_err(|e| anyhow!("Failed to run {}: {e}", path.display()))?;
+            Ok(status.code().unwrap_or(1))
+        }
+    }
+
+    /// [`Self::run_script`] for stages that must not stop what triggered them.
+    async fn run_script_or_warn(&self, stage: Stage, pid: Option<u32>) {
+        if let Err(e) = self.run_script(stage, pid).await {
+            warn!("The {} hook failed: {e}", stage.as_str());
+        }
+    }
+
+    fn display_name(&self) -> String {
+        match &self.instance {
+            Some(name) => format!("{} [{name}]", self.core.name()),
+            None => self.core.name().to_string(),
+        }
+    }
+
+    /// Packs proxy-rs.toml and the configs, override files and subscriptions of
+    /// all instances into a tar.gz at `path`, leaving out binaries and logs.
+    pub fn backup(&self, path: &Path) -> Result<()> {
+        let files = config_files(&self.proxy_data_dir)?;
+        let packed = backup::pack(&self.proxy_data_dir, &files, path)?;
+        info!("Backed up {} files to {}", packed.len(), path.display());
+        Ok(())
+    }
+
+    /// Restores a tar.gz made by [`Self::backup`] into the data dir, replacing
+    /// the configs it holds. Running instances keep their config until restarted.
+    /// Asks first unless `force` is set.
+    pub fn restore(&self, path: &Path, force: bool) -> Result<()> {
+        if (self.config_path().exists() || self.proxy_data_dir.join(SETTINGS_FILE).exists())
+            && !confirm_destructive(
+                &format!(
+                    "This replaces the config in {} with the backup. Continue?",
+                    self.proxy_data_dir.display()
+                ),
+                force,
+            )
+        {
+            return Ok(());
+        }
+        let restored = backup::unpack(path, &self.proxy_data_dir, is_backup_file)?;
+        // The archive keeps the modes the files were packed with
+        for relative in &restored {
+            make_private(&self.proxy_data_dir.join(relative))?;
+        }
+        info!(
+            "Restored {} files to {}",
+            restored.len(),
+            self.proxy_data_dir.display()
+        );
+        if self.is_running()?.is_some() {
+            info!("Restart with `proxy start` to use the restored config");
+        }
+        Ok(())
+    }
+
+    /// Downloads the core for the platform in `options`, the WebUI and all
+    /// geodata into a tar.gz at `path`, for [`Self::bundle_install`] on a machine
+    /// that can reach neither GitHub nor a mirror.
+    pub async fn bundle_create(&self, path: &Path, options: &BundleOptions) -> Result<()> {
+        let staging = self.proxy_data_dir.join(BUNDLE_STAGING_DIR);
+        if staging.exists() {
+            fs::remove_dir_all(&staging)?;
+        }
+        let result = self.bundle_into(&staging, path, options).await;
+        let _ = fs::remove_dir_all(&staging);
+        result
+    }
+
+    async fn bundle_into(
+        &self,
+        staging: &Path,
+        path: &Path,
+        options: &BundleOptions,
+    ) -> Result<()> {
+        let version = match &options.core_version {
+            Some(version) => version.clone(),
+            None => self.latest_core_version(Channel::Stable).await?,
+        };
+        let (asset_name, _) = self.core.release_asset(
+            &version,
+            options.os.as_deref(),
+            self.settings.core_arch.as_deref(),
+        )?;
+        info!(
+            "Downloading {} {version} ({asset_name})...",
+            self.core.name()
+        );
+        fs::create_dir_all(staging.join(bundle::CORE_DIR))?;
+        let core_url = self
+            .download_core_asset(
+                &version,
+                &asset_name,
+                &staging.join(bundle::CORE_DIR).join(&asset_name),
+                options.no_verify,
+            )
+            .await?;
+
+        let ui = self.settings.ui;
+        let ui = match ui.release() {
+            Some((url, _)) => {
+                info!("Downloading {}...", ui.name());
+                let name = format!("{}.zip", ui.name());
+                fs::create_dir_all(staging.join(bundle::UI_DIR))?;
+                self.download_from_github(url, &staging.join(bundle::UI_DIR).join(&name))
+                    .await?;
+                Some(BundledFile {
+                    name,
+                    url: url.to_string(),
+                })
+            }
+            None => None,
+        };
+
+        // Both geodata modes, the config on the target may use either
+        let mut geodata = Vec::new();
+        if self.core.kind() == CoreKind::Mihomo {
+            fs::create_dir_all(staging.join(bundle::GEODATA_DIR))?;
+            for (filename, asset) in geodata_files(false).iter().chain(geodata_files(true)) {
+                if geodata
+                    .iter()
+                    .any(|file: &BundledFile| file.name == *filename)
+                {
+                    continue;
+                }
+                info!("Downloading {filename}...");
+                let url = geodata_url(asset);
+                self.download_from_github(&url, &staging.join(bundle::GEODATA_DIR).join(filename))
+                    .await?;
+                geodata.push(BundledFile {
+                    name: filename.to_string(),
+                    url,
+                });
+            }
+        }
+
+        let manifest = BundleManifest {
+            created_at: jiff::Timestamp::now().to_string(),
+            os: match &options.os {
+                Some(os) => os.clone(),
+                None => backend::current_os()?.to_string(),
+            },
+            core: self.core.kind().as_str().to_string(),
+            core_version: version,
+            core_asset: BundledFile {
+                name: asset_name,
+                url: core_url,
+            },
+            ui,
+            geodata,
+        };
+        manifest.save(&staging.join(bundle::MANIFEST_FILE))?;
+        backup::pack(staging, &manifest.files(), path)?;
+        info!(
+            "Bundled {} {}, {} and {} geodata files into {}, install it with `proxy bundle install`",
+            self.core.name(),
+            manifest.core_version,
+            manifest.ui.as_ref().map_or("no WebUI", |ui| ui.name.trim_end_matches(".zip")),
+            manifest.geodata.len(),
+            path.display()
+        );
+        Ok(())
+    }
+
+    /// Installs the core, WebUI and geodata from a bundle made by
+    /// [`Self::bundle_create`], without any download.
+    pub async fn bundle_install(&self, path: &Path) -> Result<()> {
+        self.require_managed_core()?;
+        let staging = self.proxy_data_dir.join(BUNDLE_STAGING_DIR);
+        if staging.exists() {
+            fs::remove_dir_all(&staging)?;
+        }
+        let result = async {
+            backup::unpack(path, &staging, bundle::is_bundle_file)?;
+            self.install_bundle_dir(&staging).await
+        }
+        .await;
+        let _ = fs::remove_dir_all(&staging);
+        result
+    }
+
+    async fn install_bundle_dir(&self, dir: &Path) -> Result<()> {
+        let manifest = BundleManifest::load(&dir.join(bundle::MANIFEST_FILE))?;
+        if manifest.core != self.core.kind().as_str() {
+            return Err(anyhow!(
+                "The bundle holds {}, pass --core {} to install it",
+                manifest.core,
+                manifest.core
+            ));
+        }
+        let os = backend::current_os()?;
+        if manifest.os != os {
+            return Err(anyhow!(
+                "The bundle is for {}, create one with `proxy bundle create --os {os}`",
+                manifest.os
+            ));
+        }
+
+        let _core_lock = self.lock_core()?;
+        let asset = &manifest.core_asset;
+        let archive_type = ArchiveType::of(&asset.name)
+            .ok_or_else(|| anyhow!("Unknown archive type of {}", asset.name))?;
+        self.install_core_archive(
+            &dir.join(bundle::CORE_DIR).join(&asset.name),
+            archive_type,
+            &manifest.core_version,
+            &asset.url,
+        )?;
+        if doctor::run_core(&self.core_path, self.core.version_args())
+            .await
+            .is_err()
+        {
+            warn!(
+                "{} doesn't run on this machine, the bundle may be for another architecture, see --core-arch of `proxy bundle create`",
+                asset.name
+            );
+        }
+
+        if let Some(ui) = &manifest.ui {
+            let name = ui.name.trim_end_matches(".zip");
+            let web_ui = WebUi::from_str(name, true)
+                .map_err(|_| anyhow!("Unknown WebUI {name} in the bundle"))?;
+            self.install_ui_zip(web_ui, &dir.join(bundle::UI_DIR).join(&ui.name))?;
+            info!("{name} installed");
+            if web_ui != self.settings.ui {
+                warn!("Set ui = \"{name}\" in {SETTINGS_FILE} to serve it");
+            }
+        }
+
+        for file in &manifest.geodata {
+            let path = self.config_dir.join(&file.name);
+            fs::copy(dir.join(bundle::GEODATA_DIR).join(&file.name), &path)?;
+            let artifact = Artifact::for_file(&file.url, &path)?;
+            self.update_manifest(|manifest| {
+                manifest
+                    .geodata
+                    .insert(self.geodata_key(&file.name), artifact);
+            })?;
+        }
+        if !manifest.geodata.is_empty() {
+            info!("{} geodata files installed", manifest.geodata.len());
+        }
+
+        info!("Installed the bundle created at {}", manifest.created_at);
+        if self.is_running()?.is_some() {
+            info!("Restart {} to use it", self.core.name());
+        }
+        Ok(())
+    }
+
+    /// Commits the configs to the sync repository and rebases onto `sync-remote`.
+    /// Returns the repository.
+    fn sync_commit(&self) -> Result<PathBuf> {
+        let remote = self.settings.sync_remote.as_deref().ok_or_else(|| {
+            anyhow!("Set sync-remote in {SETTINGS_FILE} to the git remote to sync with")
+        })?;
+        let repo = self.proxy_data_dir.join(SYNC_DIR);
+        sync::init(&repo, remote)?;
+
+        // Files deleted here are deleted from the repository too
+        for relative in config_files(&repo)? {
+            if !self.proxy_data_dir.join(&relative).exists() && repo.join(&relative).exists() {
+                fs::remove_file(repo.join(&relative))?;
+            }
+        }
+        for relative in config_files(&self.proxy_data_dir)? {
+            let from = self.proxy_data_dir.join(&relative);
+            if from.is_file() {
+                let to = repo.join(&relative);
+                fs::create_dir_all(to.parent().unwrap_or(&repo))?;
+                fs::copy(from, to)?;
+            }
+        }
+        let host = sysinfo::System::host_name().unwrap_or_else(|| "unknown host".to_string());
+        if sync::commit(&repo, &format!("Sync from {host}"))? {
+            info!("Committed the local changes");
+        }
+        sync::pull(&repo)?;
+        Ok(repo)
+    }
+
+    /// Copies the configs in the sync repository over the local ones.
+    fn sync_checkout(&self, repo: &Path) -> Result<()> {
+        let files = config_files(repo)?;
+        for relative in &files {
+            let from = repo.join(relative);
+            if from.is_file() {
+                let to = self.proxy_data_dir.join(relative);
+                fs::create_dir_all(to.parent().unwrap_or(&self.proxy_data_dir))?;
+                fs::copy(from, to)?;
+            }
+        }
+        if self.is_running()?.is_some() {
+            info!("Restart with `proxy start` to use the synced config");
+        }
+        Ok(())
+    }
+
+    /// Commits the configs, merges the remote changes and pushes to `sync-remote`.
+    pub fn sync_push(&self) -> Result<()> {
+        let _lock = self.lock_instance()?;
+        let repo = self.sync_commit()?;
+        sync::push(&repo)?;
+        self.sync_checkout(&repo)?;
+        info!(
+            "Pushed the config to {}",
+            self.settings.sync_remote.as_deref().unwrap_or_default()
+        );
+        Ok(())
+    }
+
+    /// Merges the configs from `sync-remote` into the local ones. Local changes
+    /// are committed first, so they are kept until the next push.
+    pub fn sync_pull(&self) -> Result<()> {
+        let _lock = self.lock_instance()?;
+        let repo = self.sync_commit()?;
+        self.sync_checkout(&repo)?;
+        info!(
+            "Pulled the config from {}",
+            self.settings.sync_remote.as_deref().unwrap_or_default()
+        );
+        Ok(())
+    }
+
+    /// Stops Mihomo and removes its data directory, config included. For the
+    /// default instance this is the whole data directory, named instances included.
+    /// Asks first unless `force` is set.
+    pub async fn uninstall(&self, force: bool) -> Result<()> {
+        if !confirm_destructive(
+            &format!(
+                "This removes {} including your config. Continue?",
+                self.instance_dir.display()
+            ),
+            force,
+        ) {
+            return Ok(());
+        }
+        if self.instance.is_none() {
+            for name in Self::instances(&self.proxy_data_dir)? {
+                let manager = Self::new(self.proxy_data_dir.clone(), Some(&name))?;
+                if manager.is_running()?.is_some() {
+                    let _lock = manager.lock_instance()?;
+                    manager.stop_locked().await?;
+                }
+            }
+        }
+        let lock = self.lock_instance()?;
+        if self.is_running()?.is_some() {
+            self.stop_locked().await?;
+        }
+        if let Err(e) = sysproxy::restore(&self.instance_dir.join(SYSPROXY_BACKUP_FILE)) {
+            warn!("Failed to restore system proxy settings: {e}");
+        }
+        // The lock file is inside the directory being removed
+        drop(lock);
+        fs::remove_dir_all(&self.instance_dir)?;
+        info!("Removed {}", self.instance_dir.display());
+        Ok(())
+    }
+
+    /// Points the system proxy at the running Mihomo's mixed-port.
+    pub fn sysproxy_on(&self) -> Result<()> {
+        if self.is_running()?.is_none() {
+            return Err(anyhow!(
+                "{} is not running, start it first",
+                self.display_name()
+            ));
+        }
+        let config_path = self.config_path();
+        let port = self
+            .core
+            .mixed_port(&config_path)
+            .context("Failed to read mixed-port from the config")?;
+        sysproxy::enable(
+            &self.instance_dir.join(SYSPROXY_BACKUP_FILE),
+            port,
+            &self.no_proxy(),
+        )
+    }
+
+    /// Hosts the `on` scripts, `env`, `exec` and the system proxy send directly,
+    /// `no-proxy` in proxy-rs.toml or [`DEFAULT_NO_PROXY`].
+    pub fn no_proxy(&self) -> Vec<String> {
+        match &self.settings.no_proxy {
+            Some(hosts) => hosts.clone(),
+            None => DEFAULT_NO_PROXY
+                .iter()
+                .map(|host| host.to_string())
+                .collect(),
+        }
+    }
+
+    /// Adds `hosts` to the bypass list, starting from the defaults if it is unset.
+    pub fn add_no_proxy(&mut self, hosts: &[String]) -> Result<()> {
+        let mut no_proxy = self.no_proxy();
+        for host in hosts {
+            if no_proxy.contains(host) {
+                info!("{host} is already bypassed");
+            } else {
+                no_proxy.push(host.clone());
+                info!("Bypassing the proxy for {host}");
+            }
+        }
+        self.save_no_proxy(Some(no_proxy))
+    }
+
+    pub fn remove_no_proxy(&mut self, hosts: &[String]) -> Result<()> {
+        let mut no_proxy = self.no_proxy();
+        if let Some(host) = hosts.iter().find(|host| !no_proxy.contains(host)) {
+            return Err(anyhow!("{host} is not bypassed, see `proxy no-proxy list`"));
+        }
+        no_proxy.retain(|host| !hosts.contains(host));
+        info!("Sending {} through the proxy again", hosts.join(", "));
+        self.save_no_proxy(Some(no_proxy))
+    }
+
+    /// Goes back to [`DEFAULT_NO_PROXY`].
+    pub fn reset_no_proxy(&mut self) -> Result<()> {
+        info!("Bypassing the proxy for {}", DEFAULT_NO_PROXY.join(", "));
+        self.save_no_proxy(None)
+    }
+
+    /// Saves the bypass list to proxy-rs.toml and applies it to the env scripts
+    /// of the running instances and to the system proxy they set.
+    fn save_no_proxy(&mut self, hosts: Option<Vec<String>>) -> Result<()> {
+        let settings_path = self.proxy_data_dir.join(SETTINGS_FILE);
+        match &hosts {
+            Some(hosts) => save_setting(
+                &settings_path,
+                "no-proxy",
+                hosts.iter().collect::<toml_edit::Array>(),
+            )?,
+            None => remove_setting(&settings_path, "no-proxy")?,
+        }
+        self.settings.no_proxy = hosts;
+
+        for (name, instance_dir) in self.instance_dirs()? {
+            let manager = MihomoManager::new(self.proxy_data_dir.clone(), name.as_deref())?;
+            if manager.is_running()?.is_none() {
+                continue;
+            }
+            let Some(port) = manager.core.mixed_port(&manager.config_path()) else {
+                continue;
+            };
+            manager.write_env_setup_script(port)?;
+            let backup_path = instance_dir.join(SYSPROXY_BACKUP_FILE);
+            if backup_path.exists() {
+                sysproxy::enable(&backup_path, port, &manager.no_proxy())?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Commands that set the proxy environment variables in `shell` to the
+    /// running Mihomo's mixed-port, for `eval "$(proxy-rs env)"`.
+    pub fn env(&self, shell: Shell) -> Result<String> {
+        let (http_url, all_url) = self.running_proxy_urls()?;
+        Ok(shell.exports(&http_url, &all_url, &self.no_proxy()))
+    }
+
+    /// Runs `command` with the proxy environment variables pointing at the
+    /// running Mihomo and waits for it. Returns its exit code.
+    pub fn exec(&self, command: &[String]) -> Result<i32> {
+        let (program, args) = command
+            .split_first()
+            .ok_or_else(|| anyhow!("No command to run"))?;
+        let (http_url, all_url) = self.running_proxy_urls()?;
+        let status = Command::new(program)
+            .args(args)
+            .envs(shell::proxy_vars(&http_url, &all_url, &self.no_proxy()))
+            .status()
+            .with_context(|| format!("Failed to run {program}"))?;
+        // Killed by a signal on Unix
+        Ok(status.code().unwrap_or(1))
+    }
+
+    /// [`Self::proxy_urls`] of the running instance.
+    fn running_proxy_urls(&self) -> Result<(String, String)> {
+        if self.is_running()?.is_none() {
+            return Err(anyhow!(
+                "{} is not running, start it first",
+                self.display_name()
+            ));
+        }
+        let port = self
+            .core
+            .mixed_port(&self.config_path())
+            .context("Failed to read mixed-port from the config")?;
+        Ok(self.proxy_urls(port))
+    }
+
+    /// URLs for `http_proxy` and `all_proxy`: the HTTP-only `port` and the
+    /// SOCKS5-only `socks-port` when the config has them, otherwise `mixed_port`.
+    fn proxy_urls(&self, mixed_port: u16) -> (String, String) {
+        let config_path = self.config_path();
+        let http_port = parse_listener_port(&config_path, "port").unwrap_or(mixed_port);
+        let http_url = format!("http://127.0.0.1:{http_port}");
+        let all_url = match parse_listener_port(&config_path, "socks-port") {
+            Some(port) => format!("socks5://127.0.0.1:{port}"),
+            None => http_url.clone(),
+        };
+        (http_url, all_url)
+    }
+
+    /// Restores the system proxy settings saved by [`Self::sysproxy_on`].
+    pub fn sysproxy_off(&self) -> Result<()> {
+        if !sysproxy::restore(&self.instance_dir.join(SYSPROXY_BACKUP_FILE))? {
+            warn!("System proxy was not set by us, nothing to restore");
+        }
+        Ok(())
+    }
+
+    /// Client for the running Mihomo's external controller.
+    pub fn controller(&self) -> Result<Controller> {
+        if self.is_running_or_adopt()?.is_none() {
+            return Err(anyhow!(
+                "{} is not running, start it first",
+                self.display_name()
+            ));
+        }
+        if let Some(external) = self.adopted() {
+            let address = external.controller.ok_or_else(|| {
+                anyhow!(
+                    "The adopted {} has no external-controller in {}",
+                    self.core.name(),
+                    external.config.display()
+                )
+            })?;
+            return Controller::new(&address, external.secret);
+        }
+        let config_path = self.config_path();
+        if let Some(address) = self.core.controller_address(&config_path) {
+            return Controller::connect(&address, self.core.secret(&config_path));
+        }
+        let address = self
+            .core
+            .external_controller(&config_path)
+            .context("Failed to read external-controller from the config")?;
+        Controller::new(&address, self.core.secret(&config_path))
+    }
+
+    /// Serves the controller over HTTPS on `address`, generating the certificate
+    /// on first use, or turns HTTPS off again with `None`.
+    fn set_controller_tls(&self, config_path: &Path, address: Option<SocketAddr>) -> Result<()> {
+        let cert_path = self.instance_dir.join(tls::CERT_FILE);
+        let key_path = self.instance_dir.join(tls::KEY_FILE);
+        if let Some(address) = address {
+            let ip = if address.ip().is_unspecified() {
+                lan_ip()
+            } else {
+                Some(address.ip())
+            };
+            tls::ensure_certificate(&cert_path, &key_path, &Vec::from_iter(ip))?;
+        }
+        update_controller_tls(
+            config_path,
+            address.map(|a| a.to_string()).as_deref(),
+            &cert_path,
+            &key_path,
+        )
+    }
+
+    /// The external controller secret of this instance, saved to `secret` in the
+    /// instance dir. It is generated on first use, unless config.yaml already has one.
+    fn controller_secret(&self) -> Result<String> {
+        let path = self.instance_dir.join(SECRET_FILE);
+        if let Some(secret) = fs::read_to_string(&path)
+            .ok()
+            .map(|s| s.trim().to_string())
+            .filter(|s| !s.is_empty())
+        {
+            return Ok(secret);
+        }
+
+        let secret = self
+            .core
+            .secret(&self.config_path())
+            .unwrap_or_else(generate_secret);
+        fs::write(&path, &secret)?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&path, fs::Permissions::from_mode(0o600))?;
+        }
+        Ok(secret)
+    }
+
+    /// Sets the instance's secret in config.yaml if the controller has none
+    /// yet, e.g. it was started by an older version, and reloads Mihomo if it
+    /// is running. Returns the secret in use.
+    async fn ensure_controller_secret(&self) -> Result<String> {
+        let config_path = self.config_path();
+        if let Some(secret) = self.core.secret(&config_path) {
+            return Ok(secret);
+        }
+        let secret = self.controller_secret()?;
+        // Without a config yet, `start` writes the secret along with it
+        if !config_path.exists() {
+            return Ok(secret);
+        }
+        self.core.set_secret(&config_path, &secret)?;
+        if self.is_running()?.is_none() {
+            return Ok(secret);
+        }
+        let controller = self.controller()?;
+        let absolute_config_path = dunce::canonicalize(&config_path)?;
+        controller
+            .reload_config(&absolute_config_path.to_string_lossy())
+            .await
+            .context("Failed to reload Mihomo with the new secret")?;
+        info!("The external controller is now protected by a secret");
+        Ok(secret)
+    }
+
+    /// Lets other devices on the LAN use the proxy, or stops sharing it, by
+    /// setting `allow-lan`, kept in the override file, and reloading Mihomo.
+    pub async fn lan(&self, enable: bool) -> Result<()> {
+        self.require_mihomo("`lan`")?;
+        if enable {
+            // The controller may listen on the LAN too
+            self.ensure_controller_secret().await?;
+        }
+        let controller = self.controller()?;
+        let _lock = self.lock_instance()?;
+        let overrides_path = self.config_dir.join(OVERRIDES_FILE);
+        let mut overrides = Overrides::load(&overrides_path)?;
+        overrides.allow_lan = Some(enable);
+        overrides.save(&overrides_path)?;
+        let config_path = self.config_path();
+        update_allow_lan(&config_path, enable)?;
+        self.reload_config(&controller).await?;
+
+        if !enable {
+            info!("The proxy is no longer shared with the LAN");
+            return Ok(());
+        }
+        let port = self
+            .core
+            .mixed_port(&config_path)
+            .context("Failed to read mixed-port from the config")?;
+        match lan_ip() {
+            Some(ip) => info!("Other devices can use {ip}:{port} as their HTTP/SOCKS5 proxy"),
+            None => info!(
+                "Other devices can use port {port} of this machine as their HTTP/SOCKS5 proxy"
+            ),
+        }
+        Ok(())
+    }
+
+    /// Blocks ads with the anti-AD rule provider, downloaded through the GitHub
+    /// mirrors, or removes it again. Kept across subscription refreshes.
+    pub async fn adblock(&self, enable: bool) -> Result<()> {
+        self.require_mihomo("`adblock`")?;
+        let _lock = self.lock_instance()?;
+        let list_path = self.config_dir.join(ADBLOCK_FILE);
+        if enable {
+            self.update_adblock_list(true).await?;
+        }
+        let overrides_path = self.config_dir.join(OVERRIDES_FILE);
+        let mut overrides = Overrides::load(&overrides_path)?;
+        overrides.adblock = enable;
+        overrides.save(&overrides_path)?;
+        let config_path = self.config_path();
+        if config_path.exists() {
+            overrides.apply(&config_path)?;
+        }
+        self.reload_if_running().await?;
+        if enable {
+            info!("Ads are blocked with the anti-AD list");
+        } else {
+            let _ = fs::remove_file(&list_path);
+            info!("Ad blocking is off");
+        }
+        Ok(())
+    }
+
+    /// Downloads the anti-AD list if `adblock` is on and it is missing, or with
+    /// `force`, older than a day. Returns whether it was downloaded.
+    async fn update_adblock_list(&self, force: bool) -> Result<bool> {
+        let list_path = self.config_dir.join(ADBLOCK_FILE);
+        if !force {
+            let enabled = Overrides::load(&self.config_dir.join(OVERRIDES_FILE))?.adblock;
+            let age = fs::metadata(&list_path)
+                .and_then(|m| m.modified())
+                .ok()
+                .and_then(|modified| modified.elapsed().ok());
+            if !enabled || age.is_some_and(|age| age < GEODATA_MAX_AGE) {
+                return Ok(false);
+            }
+        }
+        info!("Downloading the anti-AD list...");
+        self.download_from_github(ADBLOCK_URL, &list_path)
+            .await
+            .context("Failed to download the anti-AD list")?;
+        Ok(true)
+    }
+
+    /// Prints QR codes of the LAN proxy address and WebUI, for phones and tablets
+    /// on the same network.
+    pub fn share(&self) -> Result<()> {
+        self.require_mihomo("`share`")?;
+        if self.is_running()?.is_none() {
+            return Err(anyhow!(
+                "{} is not running, start it first",
+                self.display_name()
+            ));
+        }
+        let ip = lan_ip().context("Failed to find the LAN IP of this machine")?;
+        let config_path = self.config_path();
+        let port = self
+            .core
+            .mixed_port(&config_path)
+            .context("Failed to read mixed-port from the config")?;
+        if !parse_allow_lan(&config_path) {
+            warn!("The proxy only accepts local connections, run `lan on` to share it");
+        }
+        share::print_qr("HTTP/SOCKS5 proxy", &format!("http://{ip}:{port}"))?;
+
+        // The HTTPS controller from `--controller-tls` if there is one
+        let (controller, https) = match parse_controller_tls(&config_path) {
+            Some(address) => (Some(address), true),
+            None => (self.core.external_controller(&config_path), false),
+        };
+        let Some(controller) = controller.and_then(|a| a.parse::<SocketAddr>().ok()) else {
+            return Ok(());
+        };
+        if controller.ip().is_loopback() {
+            warn!("The Web UI only listens on this machine, start with `--listen 0.0.0.0` to share it");
+            return Ok(());
+        }
+        let secret = self.core.secret(&config_path).unwrap_or_default();
+        share::print_qr(
+            "Web UI",
+            &webui_url(SocketAddr::new(ip, controller.port()), &secret, https),
+        )
+    }
+
+    /// Adds `rule` in front of the subscription's rules. It is saved to the
+    /// override file so it survives subscription refreshes.
+    pub async fn add_rule(&self, rule: &str) -> Result<()> {
+        self.require_mihomo("`rule`")?;
+        let rule = parse_rule(rule)?;
+        let _lock = self.lock_instance()?;
+        let overrides_path = self.config_dir.join(OVERRIDES_FILE);
+        let mut overrides = Overrides::load(&overrides_path)?;
+        if overrides.prepend_rules.contains(&rule) {
+            info!("Rule already exists: {rule}");
+            return Ok(());
+        }
+        overrides.prepend_rules.push(rule.clone());
+        overrides.save(&overrides_path)?;
+
+        let config_path = self.config_path();
+        if config_path.exists() {
+            overrides.apply(&config_path)?;
+        }
+        info!("Added rule: {rule}");
+        self.reload_if_running().await
+    }
+
+    /// Removes `rule` from the override file and config.yaml.
+    pub async fn remove_rule(&self, rule: &str) -> Result<()> {
+        self.require_mihomo("`rule`")?;
+        let rule = parse_rule(rule)?;
+        let _lock = self.lock_instance()?;
+        let overrides_path = self.config_dir.join(OVERRIDES_FILE);
+        let mut overrides = Overrides::load(&overrides_path)?;
+        let count = overrides.prepend_rules.len();
+        overrides.prepend_rules.retain(|r| *r != rule);
+        let overridden = overrides.prepend_rules.len() != count;
+        if overridden {
+            overrides.save(&overrides_path)?;
+        }
+
+        let config_path = self.config_path();
+        let in_config = config_path.exists() && remove_config_rule(&config_path, &rule)?;
+        if !overridden && !in_config {
+            return Err(anyhow!("Rule not found: {rule}"));
+        }
+        if !overridden {
+            warn!("{rule} comes from the subscription, it is back after the next refresh");
+        }
+        info!("Removed rule: {rule}");
+        self.reload_if_running().await
+    }
+
+    /// The rules added with [`Self::add_rule`], or every rule in config.yaml with `all`.
+    pub fn rules(&self, all: bool) -> Result<Vec<String>> {
+        self.require_mihomo("`rule`")?;
+        if all {
+            config_rules(&self.config_path()).context("Failed to read rules from config.yaml")
+        } else {
+            Ok(Overrides::load(&self.config_dir.join(OVERRIDES_FILE))?.prepend_rules)
+        }
+    }
+
+    /// Chains `entry` in front of `exit` in a `relay` group kept in the override
+    /// file. With `select` the main selector offers it first and switches to it.
+    pub async fn chain(&self, entry: &str, exit: &str, select: bool) -> Result<()> {
+        self.require_mihomo("`chain`")?;
+        let _lock = self.lock_instance()?;
+        let config_path = self.config_path();
+        if !config_path.exists() {
+            return Err(anyhow!("No config.yaml yet, run `start` first"));
+        }
+        let relay = Relay {
+            entry: entry.to_string(),
+            exit: exit.to_string(),
+            select,
+        };
+        check_relay(&config_path, &relay)?;
+        let overrides_path = self.config_dir.join(OVERRIDES_FILE);
+        let mut overrides = Overrides::load(&overrides_path)?;
+        overrides.relay = Some(relay);
+        overrides.save(&overrides_path)?;
+        overrides.apply(&config_path)?;
+        info!("{RELAY_GROUP}: {entry} -> {exit}");
+        self.reload_if_running().await?;
+
+        if select && self.is_running()?.is_some() {
+            let controller = self.controller()?;
+            let proxies = controller.proxies().await?;
+            if let Some(group) = picker::main_selector(&proxies) {
+                controller.select_proxy(&group.name, RELAY_GROUP).await?;
+                info!("{} -> {RELAY_GROUP}", group.name);
+            }
+        }
+        Ok(())
+    }
+
+    /// Removes the `chain` group from the override file and config.yaml.
+    pub async fn unchain(&self) -> Result<()> {
+        self.require_mihomo("`chain`")?;
+        let _lock = self.lock_instance()?;
+        let overrides_path = self.config_dir.join(OVERRIDES_FILE);
+        let mut overrides = Overrides::load(&overrides_path)?;
+        let overridden = overrides.relay.take().is_some();
+        if overridden {
+            overrides.save(&overrides_path)?;
+        }
+        let config_path = self.config_path();
+        let in_config = config_path.exists() && remove_relay_group(&config_path)?;
+        if !overridden && !in_config {
+            return Err(anyhow!("No {RELAY_GROUP} group to remove"));
+        }
+        info!("Removed the {RELAY_GROUP} group");
+        self.reload_if_running().await
+    }
+
+    /// Adds `group` to the override file and config.yaml, replacing a group of
+    /// the same name, then reloads a running Mihomo. With `select` the main
+    /// selector switches to it.
+    pub async fn add_group(&self, group: CustomGroup) -> Result<()> {
+        self.require_mihomo("`group`")?;
+        let _lock = self.lock_instance()?;
+        let config_path = self.config_path();
+        if !config_path.exists() {
+            return Err(anyhow!("No config.yaml yet, run `start` first"));
+        }
+        let nodes = group.matching_nodes(&config_path)?;
+        match &nodes {
+            None => warn!(
+                "Can't count the nodes of {}, its regex only compiles in Mihomo",
+                group.name
+            ),
+            Some(nodes) if nodes.is_empty() => warn!(
+                "No node in config.yaml matches {}, {} only gets nodes from proxy providers",
+                group.include, group.name
+            ),
+            Some(_) => {}
+        }
+
+        let overrides_path = self.config_dir.join(OVERRIDES_FILE);
+        let mut overrides = Overrides::load(&overrides_path)?;
+        overrides.groups.retain(|g| g.name != group.name);
+        overrides.remove_groups.retain(|name| *name != group.name);
+        overrides.groups.push(group.clone());
+        overrides.save(&overrides_path)?;
+        overrides.apply(&config_path)?;
+        match nodes {
+            Some(nodes) => info!(
+                "{} ({}): {} nodes",
+                group.name,
+                group.kind.as_str(),
+                nodes.len()
+            ),
+            None => info!("{} ({})", group.name, group.kind.as_str()),
+        }
+        self.reload_if_running().await?;
+
+        if group.select && self.is_running()?.is_some() {
+            let controller = self.controller()?;
+            let proxies = controller.proxies().await?;
+            if let Some(main) = picker::main_selector(&proxies) {
+                controller.select_proxy(&main.name, &group.name).await?;
+                info!("{} -> {}", main.name, group.name);
+            }
+        }
+        Ok(())
+    }
+
+    /// Removes the group `name`, one added by `group add` or one of the
+    /// subscription's, which stays removed after refreshes. Rules that sent
+    /// traffic to it use the main selector instead.
+    pub async fn remove_group(&self, name: &str) -> Result<()> {
+        self.require_mihomo("`group`")?;
+        let _lock = self.lock_instance()?;
+        let overrides_path = self.config_dir.join(OVERRIDES_FILE);
+        let mut overrides = Overrides::load(&overrides_path)?;
+        let count = overrides.groups.len();
+        overrides.groups.retain(|g| g.name != name);
+        let custom = overrides.groups.len() != count;
+        let config_path = self.config_path();
+        let in_config = config_path.exists() && remove_config_group(&config_path, name)?;
+        if !custom && !in_config {
+            return Err(anyhow!("No group named {name}"));
+        }
+        if !custom && !overrides.remove_groups.iter().any(|n| n == name) {
+            overrides.remove_groups.push(name.to_string());
+        }
+        overrides.save(&overrides_path)?;
+        info!("Removed the {name} group");
+        self.reload_if_running().await
+    }
+
+    /// Makes the running Mihomo load config.yaml again.
+    async fn reload_config(&self, controller: &Controller) -> Result<()> {
+        let absolute_config_path = dunce::canonicalize(self.config_path())?;
+        self.save_selections(controller).await;
+        controller
+            .reload_config(&absolute_config_path.to_string_lossy())
+            .await
+            .context("Failed to reload Mihomo")?;
+        self.restore_selections(controller).await;
+        self.run_script_or_warn(Stage::OnReload, self.load_pid().map(|pid| pid.as_u32()))
+            .await;
+        Ok(())
+    }
+
+    /// Downloads the subscription last passed to `start` (or `subscription-url`)
+    /// again. A running Mihomo keeps its ports and reloads the new config.
+    pub async fn refresh_subscription(&self) -> Result<()> {
+        let mut urls = self.saved_subscription_urls()?;
+        if urls.is_empty() {
+            urls.extend(self.settings.subscription_url.clone());
+        }
+        if urls.is_empty() {
+            return Err(anyhow!(
+                "No subscription URL is saved yet, pass one to `start` first"
+            ));
+        }
+        let _lock = self.lock_instance()?;
+        let running = self.reloadable(&StartOptions::default())?;
+        let adblock_updated = self.update_adblock_list(false).await.unwrap_or_else(|e| {
+            warn!("{e:#}");
+            false
+        });
+        let changed = self
+            .download_subscription(&urls, self.settings.subconverter.as_deref())
+            .await?;
+        if !changed && !adblock_updated {
+            return Ok(());
+        }
+        match running {
+            Some(running) => {
+                let controller = self.keep_running_ports(&running)?;
+                self.reload_validated(&controller).await
+            }
+            None if self.is_running()?.is_some() => {
+                warn!("Restart {} to use the new config", self.display_name());
+                Ok(())
+            }
+            None => {
+                info!("The new config is used on the next start");
+                Ok(())
+            }
+        }
+    }
+
+    /// Lists the profiles of `client` found in `dir`, or where the client keeps them.
+    pub fn import_profiles(&self, client: ImportClient, dir: Option<&Path>) -> Result<()> {
+        let dir = match dir {
+            Some(dir) => dir.to_path_buf(),
+            None => client.find_dir()?,
+        };
+        for profile in import::profiles(client, &dir)? {
+            let marker = if profile.current { "*" } else { " " };
+            let source = profile
+                .url
+                .as_deref()
+                .map_or("local".to_string(), subscription_name);
+            println!("{marker} {} ({source})", profile.name);
+        }
+        Ok(())
+    }
+
+    /// Replaces the config with a profile of `client`, the one it is using
+    /// unless `name` is given. Its subscription URL is saved for `sub refresh`,
+    /// `prepend-rules` become custom rules and the other merge enhancements are
+    /// applied to the imported config. JavaScript enhancements are only copied.
+    pub async fn import_profile(
+        &self,
+        client: ImportClient,
+        dir: Option<&Path>,
+        name: Option<&str>,
+    ) -> Result<()> {
+        self.require_mihomo("`import`")?;
+        let dir = match dir {
+            Some(dir) => dir.to_path_buf(),
+            None => client.find_dir()?,
+        };
+        let profiles = import::profiles(client, &dir)?;
+        let profile = pick_profile(profiles, name).map_err(|e| {
+            let client = match client {
+                ImportClient::ClashVerge => "clash-verge",
+                ImportClient::Cfw => "cfw",
+            };
+            anyhow!("{e}, run `proxy import {client} --list` to see the profiles")
+        })?;
+        info!("Importing {} from {}", profile.name, profile.file.display());
+        let content = fs::read_to_string(&profile.file)
+            .with_context(|| format!("Failed to read {}", profile.file.display()))?;
+        let mut config: serde_yaml::Mapping = serde_yaml::from_str(&content)
+            .with_context(|| format!("Invalid {}", profile.file.display()))?;
+
+        let _lock = self.lock_instance()?;
+        let overrides_path = self.config_dir.join(OVERRIDES_FILE);
+        let mut overrides = Overrides::load(&overrides_path)?;
+        let mut prepend_rules = Vec::new();
+        for (path, merge) in &profile.merges {
+            info!("Applying the enhancement {}", path.display());
+            for key in import::apply_merge(&mut config, merge, &mut prepend_rules) {
+                warn!(
+                    "Skipping `{key}` of {}, proxy-rs can't apply it",
+                    path.display()
+                );
+            }
+        }
+        let baked = profile.merges.iter().any(|(_, merge)| {
+            merge
+                .keys()
+                .any(|key| key.as_str() != Some("prepend-rules"))
+        });
+        if baked && profile.url.is_some() {
+            warn!(
+                "Enhancements other than prepend-rules are lost on the next subscription refresh, \
+                 add them again with `rule add`, `group add` or `config edit`"
+            );
+        }
+        for rule in prepend_rules {
+            match parse_rule(&rule) {
+                Ok(rule) if !overrides.prepend_rules.contains(&rule) => {
+                    overrides.prepend_rules.push(rule)
+                }
+                Ok(_) => {}
+                Err(e) => warn!("Skipping the rule {rule}: {e}"),
+            }
+        }
+
+        let config_path = self.config_path();
+        replace_config(self.core, &config_path, &serde_yaml::to_string(&config)?)?;
+        // They describe the last download, not the imported profile
+        Validators::default().save(&Validators::path(&config_path))?;
+        overrides.save(&overrides_path)?;
+        overrides.apply(&config_path)?;
+        if self.core_path.exists() {
+            if let Err(e) =
+                doctor::run_core(&self.core_path, self.core.test_args(&self.config_dir)).await
+            {
+                restore_backup(&config_path)?;
+                return Err(anyhow!(
+                    "{} rejected the imported profile: {e}",
+                    self.core.name()
+                ));
+            }
+        }
+
+        let url_file = self.instance_dir.join(SUBSCRIPTION_URL_FILE);
+        match &profile.url {
+            Some(url) => {
+                fs::write(&url_file, url)?;
+                make_private(&url_file)?;
+                info!("`proxy sub refresh` downloads it again from its subscription");
+            }
+            None if url_file.exists() => {
+                // A refresh would replace the local profile
+                fs::remove_file(&url_file)?;
+                info!("Forgot the previous subscription URL, the profile is local");
+            }
+            None => {}
+        }
+        if !profile.scripts.is_empty() {
+            let scripts_dir = self.config_dir.join(IMPORTED_SCRIPTS_DIR);
+            fs::create_dir_all(&scripts_dir)?;
+            for script in &profile.scripts {
+                let copy = scripts_dir.join(&script.name);
+                fs::write(&copy, &script.code)?;
+                warn!(
+                    "proxy-rs can't run the JavaScript enhancement in {}, copied it to {} for reference",
+                    script.source.display(),
+                    copy.display()
+                );
+            }
+        }
+        info!("Imported {} into {}", profile.name, config_path.display());
+        self.reload_if_running().await
+    }
+
+    /// Checks config.yaml with the core, then reloads it in the running Mihomo
+    /// and logs how the ports, proxies and rules changed.
+    pub async fn reload(&self) -> Result<()> {
+        self.require_mihomo("reload")?;
+        let _lock = self.lock_instance()?;
+        let controller = self.controller()?;
+        self.reload_validated(&controller).await
+    }
+
+    /// Applies the override file, checks the config with `mihomo -t` and reloads
+    /// it, keeping the running config if the check fails.
+    async fn reload_validated(&self, controller: &Controller) -> Result<()> {
+        let config_path = self.config_path();
+        Overrides::load(&self.config_dir.join(OVERRIDES_FILE))?.apply(&config_path)?;
+        doctor::run_core(&self.core_path, self.core.test_args(&self.config_dir))
+            .await
+            .map_err(|e| {
+                anyhow!(
+                    "{} rejected the config, keeping the running one: {e}",
+                    self.core.name()
+                )
+            })?;
+
+        let before = ConfigSummary::fetch(controller).await?;
+        self.reload_config(controller).await?;
+        let after = ConfigSummary::fetch(controller).await?;
+        info!("{} reloaded", self.display_name());
+        before.log_changes(&after);
+        Ok(())
+    }
+
+    /// Reloads config.yaml if Mihomo is running, otherwise changes apply on the next start.
+    async fn reload_if_running(&self) -> Result<()> {
+        if self.is_running()?.is_some() {
+            self.reload_config(&self.controller()?).await?;
+            info!("Mihomo reloaded");
+        }
+        Ok(())
+    }
+
+    /// Switches the node of a `Selector` group with an interactive fuzzy picker.
+    /// Returns the (group, node) selected, `None` if the picker was cancelled.
+    pub async fn select(&self, group: Option<&str>) -> Result<Option<(String, String)>> {
+        let controller = self.controller()?;
+        picker::pick_node(&controller, group).await
+    }
+
+    /// Switches the running Mihomo to `mode` and saves it to config.yaml and the
+    /// override file so it survives restarts. Returns the current mode.
+    pub async fn mode(&self, mode: Option<Mode>) -> Result<String> {
+        let controller = self.controller()?;
+        let Some(mode) = mode else {
+            let current = controller.configs().await?.mode;
+            info!("Mode: {current}");
+            return Ok(current);
+        };
+
+        let _lock = self.lock_instance()?;
+        controller.set_mode(mode).await?;
+        // sing-box keeps the mode in its cache file
+        if self.core.kind() == CoreKind::Mihomo {
+            // Kept in the override file, a subscription refresh replaces config.yaml
+            let overrides_path = self.config_dir.join(OVERRIDES_FILE);
+            let mut overrides = Overrides::load(&overrides_path)?;
+            overrides.mode = Some(mode);
+            overrides.save(&overrides_path)?;
+            update_mode(&self.config_path(), mode.as_str())?;
+        }
+        info!("Switched to {mode} mode");
+        Ok(mode.to_string())
+    }
+
+    /// Switches the running core to `direct` mode until [`Self::resume`], without
+    /// saving it to the config. Open connections are closed so they reconnect directly.
+    pub async fn pause(&self) -> Result<()> {
+        let _lock = self.lock_instance()?;
+        let paused_path = self.instance_dir.join(PAUSED_FILE);
+        if paused_path.exists() {
+            return Err(anyhow!(
+                "{} is already paused, run `proxy resume` first",
+                self.display_name()
+            ));
+        }
+        let controller = self.controller()?;
+        let mode = controller.configs().await?.mode;
+        let selected = selected_nodes(&controller).await?;
+        fs::write(
+            &paused_path,
+            toml::to_string_pretty(&Paused { mode, selected })?,
+        )?;
+
+        if let Err(e) = controller.set_mode(Mode::Direct).await {
+            let _ = fs::remove_file(&paused_path);
+            return Err(e);
+        }
+        if let Err(e) = controller.close_all_connections().await {
+            warn!("Failed to close connections: {e}");
+        }
+        info!("Paused, traffic goes out directly until `proxy resume`");
+        Ok(())
+    }
+
+    /// Restores the mode and the selected nodes saved by [`Self::pause`].
+    pub async fn resume(&self) -> Result<()> {
+        let _lock = self.lock_instance()?;
+        let paused_path = self.instance_dir.join(PAUSED_FILE);
+        if !paused_path.exists() {
+            return Err(anyhow!("{} is not paused", self.display_name()));
+        }
+        let paused: Paused = toml::from_str(&fs::read_to_string(&paused_path)?)
+            .with_context(|| format!("Invalid {}", paused_path.display()))?;
+        let mode = Mode::from_str(&paused.mode, true)
+            .map_err(|_| anyhow!("Unknown mode in {}: {}", paused_path.display(), paused.mode))?;
+
+        let controller = self.controller()?;
+        controller.set_mode(mode).await?;
+        for selection in &paused.selected {
+            if let Err(e) = controller
+                .select_proxy(&selection.group, &selection.node)
+                .await
+            {
+                warn!(
+                    "Failed to select {} in {}: {e}",
+                    selection.node, selection.group
+                );
+            }
+        }
+        if let Err(e) = controller.close_all_connections().await {
+            warn!("Failed to close connections: {e}");
+        }
+        fs::remove_file(&paused_path)?;
+        info!("Resumed {mode} mode");
+        Ok(())
+    }
+
+    /// Saves the selected nodes for [`Self::restore_selections`] if
+    /// `sticky-selection` is on. Failures are only logged.
+    async fn save_selections(&self, controller: &Controller) {
+        if !self.settings.sticky_selection {
+            return;
+        }
+        let saved = async {
+            let selected = selected_nodes(controller).await?;
+            fs::write(
+                self.instance_dir.join(SELECTIONS_FILE),
+                toml::to_string_pretty(&Selections { selected })?,
+            )?;
+            anyhow::Ok(())
+        };
+        if let Err(e) = saved.await {
+            warn!("Failed to save the selected nodes: {e}");
+        }
+    }
+
+    /// [`Self::save_selections`] for the running core, if there is one.
+    async fn save_running_selections(&self) {
+        if !self.settings.sticky_selection {
+            return;
+        }
+        match self.controller() {
+            Ok(controller) => self.save_selections(&controller).await,
+            Err(e) => warn!("Failed to save the selected nodes: {e}"),
+        }
+    }
+
+    /// Selects the nodes saved by [`Self::save_selections`] again, skipping
+    /// groups and nodes the config no longer has. Failures are only logged.
+    async fn restore_selections(&self, controller: &Controller) {
+        let path = self.instance_dir.join(SELECTIONS_FILE);
+        if !self.settings.sticky_selection || !path.exists() {
+            return;
+        }
+        let restored = async {
+            let saved: Selections = toml::from_str(&fs::read_to_string(&path)?)
+                .with_context(|| format!("Invalid {}", path.display()))?;
+            let proxies = controller.proxies().await?;
+            let mut restored = 0;
+            for selection in &saved.selected {
+                let Some(group) = proxies.get(&selection.group) else {
+                    continue;
+                };
+                if group.now.as_ref() == Some(&selection.node)
+                    || !group.all.contains(&selection.node)
+                {
+                    continue;
+                }
+                match controller
+                    .select_proxy(&selection.group, &selection.node)
+                    .await
+                {
+                    Ok(()) => restored += 1,
+                    Err(e) => warn!(
+                        "Failed to select {} in {}: {e}",
+                        selection.node, selection.group
+                    ),
+                }
+            }
+            anyhow::Ok(restored)
+        };
+        match restored.await {
+            Ok(0) => {}
+            Ok(restored) => info!("Restored the selected nodes ({restored} changed)"),
+            Err(e) => warn!("Failed to restore the selected nodes: {e}"),
+        }
+    }
+
+    /// Tests the delay of every node, or only the members of `group`, sorted
+    /// from fastest to slowest.
+    pub async fn test_latency(&self, group: Option<&str>, url: &str) -> Result<Vec<LatencyResult>> {
+        let controller = self.controller()?;
+        latency::test_latency(&controller, group, url, DEFAULT_DELAY_TIMEOUT_MS).await
+    }
+
+    /// Measures the download speed of the nodes through the mixed-port, see
+    /// [`speedtest::run_speedtest`].
+    pub async fn speedtest(&self, options: &SpeedtestOptions) -> Result<Vec<SpeedResult>> {
+        self.require_mihomo("speedtest")?;
+        let _lock = self.lock_instance()?;
+        let controller = self.controller()?;
+        let mixed_port = self
+            .core
+            .mixed_port(&self.config_path())
+            .ok_or_else(|| anyhow!("No mixed-port in the config"))?;
+        speedtest::run_speedtest(&controller, mixed_port, options).await
+    }
+
+    /// Tests the delay of the nodes against several targets over several rounds,
+    /// see [`bench::run_bench`].
+    pub async fn bench(&self, options: &BenchOptions) -> Result<Vec<BenchResult>> {
+        let controller = self.controller()?;
+        bench::run_bench(&controller, options).await
+    }
+
+    /// Prints the tail of mihomo.log, or with `remote` streams the log from the
+    /// controller, which includes the rule each connection matched at `debug`.
+    pub async fn logs(
+        &self,
+        remote: bool,
+        level: CoreLogLevel,
+        lines: usize,
+        json: bool,
+    ) -> Result<()> {
+        if remote {
+            return logs::stream_remote_logs(&self.controller()?, level, json).await;
+        }
+        let path = self.instance_dir.join("mihomo.log");
+        if !path.exists() {
+            return Err(anyhow!(
+                "{} does not exist, start {} first",
+                path.display(),
+                self.core.name()
+            ));
+        }
+        logs::print_log_tail(&path, lines, level)
+    }
+
+    /// Prints the throughput every second until Ctrl+C is pressed.
+    pub async fn watch_traffic(&self, json: bool) -> Result<()> {
+        traffic::watch_traffic(&self.controller()?, json).await
+    }
+
+    /// Enforces `limits` on the running core until Ctrl+C or SIGTERM, see
+    /// [`limit::enforce`].
+    pub async fn limit(&self, limits: &Limits) -> Result<()> {
+        self.require_mihomo("`limit`")?;
+        if self.instance_dir.join(PAUSED_FILE).exists() {
+            return Err(anyhow!(
+                "{} is paused, run `proxy resume` first",
+                self.display_name()
+            ));
+        }
+        limit::enforce(
+            &self.controller()?,
+            limits,
+            &self.instance_dir.join(LIMIT_BLOCKED_FILE),
+        )
+        .await
+    }
+
+    /// Prints the active connections, refreshed every second with `watch`.
+    pub async fn connections(&self, watch: bool) -> Result<()> {
+        let controller = self.controller()?;
+        if watch {
+            connections::watch_connections(&controller).await
+        } else {
+            connections::print_connections(&controller).await
+        }
+    }
+
+    /// Evaluates config.yaml's rules for each of `targets` without the core, or
+    /// for each line typed in until an empty one or EOF.
+    pub fn try_rules(&self, targets: &[String]) -> Result<()> {
+        self.require_mihomo("`rules try`")?;
+        let config_path = self.config_path();
+        if !config_path.exists() {
+            return Err(anyhow!("No config yet, run `proxy start` first"));
+        }
+        let matcher = RuleMatcher::load(&config_path, self.geodata_mode())?;
+        let evaluate = |target: &str| -> Result<()> {
+            let (host, port) = parse_target(target)?;
+            matcher.print(&matcher.evaluate(&host, port));
+            Ok(())
+        };
+        if !targets.is_empty() {
+            return targets.iter().try_for_each(|target| evaluate(target));
+        }
+
+        let interactive = std::io::stdin().is_terminal();
+        if interactive {
+            println!(
+                "{} rules loaded from {}. Type a domain, IP or URL, or an empty line to quit.",
+                matcher.rule_count(),
+                config_path.display()
+            );
+        }
+        let mut line = String::new();
+        loop {
+            if interactive {
+                print!("> ");
+                std::io::stdout().flush()?;
+            }
+            line.clear();
+            if std::io::stdin().read_line(&mut line)? == 0 || line.trim().is_empty() {
+                return Ok(());
+            }
+            if let Err(e) = evaluate(line.trim()) {
+                error!("{e}");
+            }
+        }
+    }
+
+    /// Reports the rule and proxy chain the running core picks for `target`,
+    /// a domain or IP with an optional port (443 by default) or a URL. The
+    /// rules are evaluated like `rules try` and the chain read from the groups'
+    /// selections, with `dial` the core is asked by connecting through it.
+    pub async fn which(&self, target: &str, dial: bool) -> Result<()> {
+        self.require_mihomo("`which`")?;
+        let controller = self.controller()?;
+        let (host, port) = parse_target(target)?;
+        if dial {
+            let proxy_port = self
+                .core
+                .mixed_port(&self.config_path())
+                .context("Failed to read mixed-port from the config")?;
+            let route = connections::which(&controller, proxy_port, &host, port).await?;
+            connections::print_route(&host, port, &route);
+            return Ok(());
+        }
+        let matcher = RuleMatcher::load(&self.config_path(), self.geodata_mode())?;
+        let verdict = matcher.evaluate(&host, port);
+        let (rule_index, rule) = match verdict.rule.clone() {
+            Some((index, rule)) => (Some(index), rule),
+            None => (None, "none matched, Mihomo sends it DIRECT".to_string()),
+        };
+        let route = connections::Route {
+            rule,
+            rule_index,
+            chain: connections::selected_chain(&controller, &verdict.target).await?,
+            ip: verdict.resolved.map(|ip| ip.to_string()),
+        };
+        connections::print_route(&host, port, &route);
+        if !verdict.skipped.is_empty() {
+            warn!(
+                "{} rules before it can only be evaluated by the core, pass --dial to ask it",
+                verdict.skipped.len()
+            );
+        }
+        Ok(())
+    }
+
+    /// Tests the delay of every node of the running core and appends the
+    /// delays and traffic counters to the stats file.
+    pub async fn sample_stats(&self) -> Result<()> {
+        let controller = self.controller()?;
+        let sample = stats::take_sample(&controller, self.delay_test_url()).await?;
+        let timeouts = sample.delays.values().filter(|d| d.is_none()).count();
+        stats::append(&self.instance_dir.join(STATS_FILE), &sample)?;
+        self.record_nodes(sample.delays.values().map(Option::is_none));
+        info!(
+            "Sampled {} nodes, {timeouts} timed out",
+            sample.delays.len()
+        );
+        Ok(())
+    }
+
+    /// Shows the delay trends of all nodes, or the delays of `node` over time,
+    /// from the samples taken within `since`.
+    pub fn stats(&self, node: Option<&str>, since: Duration) -> Result<()> {
+        let path = self.instance_dir.join(STATS_FILE);
+        if !path.exists() {
+            return Err(anyhow!(
+                "No stats recorded yet, set sample-stats under [schedule] in {SETTINGS_FILE} \
+                 and run `schedule` or `start --watch`, or take one with `stats --sample`"
+            ));
+        }
+        let since = jiff::Timestamp::now().as_second() - since.as_secs() as i64;
+        let samples = stats::load(&path, since)?;
+        match node {
+            Some(node) => stats::print_node(&samples, node),
+            None => {
+                stats::print_summary(&samples);
+                Ok(())
+            }
+        }
+    }
+
+    /// Closes the connection whose id starts with `id`, or all of them with `all`.
+    pub async fn kill_connections(&self, id: Option<&str>, all: bool) -> Result<()> {
+        let controller = self.controller()?;
+        match id {
+            Some(id) => connections::kill_connection(&controller, id).await,
+            None if all => connections::kill_all_connections(&controller).await,
+            None => Err(anyhow!("Specify a connection id or --all")),
+        }
+    }
+
+    /// Updates the proxy and rule providers, or only those of `kind` or named `name`.
+    pub async fn update_providers(
+        &self,
+        kind: Option<ProviderKind>,
+        name: Option<&str>,
+    ) -> Result<()> {
+        self.require_mihomo("providers")?;
+        providers::update_providers(&self.controller()?, kind, name).await
+    }
+
+    /// Runs the interactive terminal dashboard until it is closed.
+    pub async fn dashboard(&self) -> Result<()> {
+        let controller = self.controller()?;
+        let pid = self
+            .is_running()?
+            .ok_or_else(|| anyhow!("{} is not running, start it first", self.display_name()))?;
+        dashboard::run_dashboard(
+            controller,
+            pid,
+            self.instance_dir.join("mihomo.log"),
+            self.delay_test_url().to_string(),
+        )
+        .await
+    }
+
+    /// Prints whether Mihomo is running and which version is installed.
+    pub async fn status(&self) -> Result<Status> {
+        let pid = self.is_running_or_adopt()?;
+        let running = match pid {
+            Some(pid) => Some(self.running_status(pid).await),
+            None => None,
+        };
+        let status = Status {
+            data_dir: self.proxy_data_dir.clone(),
+            pid,
+            core_version: self.installed_core_version()?,
+            core_path: self.core_path.clone(),
+            subscription: SubscriptionInfo::load(&self.instance_dir.join(SUBSCRIPTION_FILE))?,
+            running,
+        };
+        let mut fields = Fields::new();
+        fields.add(
+            "Installation",
+            format!(
+                "{} ({})",
+                dunce::canonicalize(&status.data_dir)
+                    .unwrap_or_else(|_| status.data_dir.clone())
+                    .display(),
+                Installation::of(&status.data_dir).as_str()
+            ),
+        );
+        if let Some(pid) = status.pid {
+            fields.add(self.display_name(), format!("running (pid: {pid})").green());
+            if let Some(external) = self.adopted() {
+                fields.add(
+                    "Started",
+                    format!("outside proxy-rs, with {}", external.config.display()),
+                );
+            }
+        } else {
+            fields.add(self.display_name(), "not running".red());
+        }
+        if let Some(version) = &status.core_version {
+            fields.add("Version", version.as_str());
+        }
+        fields.add("Binary", status.core_path.display().to_string());
+        if let Some(running) = &status.running {
+            add_running_status(&mut fields, running);
+        }
+        if let Some(subscription) = &status.subscription {
+            fields.add("Traffic", subscription.traffic());
+            if let Some(expiry) = subscription.expiry() {
+                let expiry = if subscription.is_expired() {
+                    expiry.red()
+                } else {
+                    expiry.normal()
+                };
+                fields.add("Expires", expiry);
+            }
+        }
+        let blocked_path = self.instance_dir.join(LIMIT_BLOCKED_FILE);
+        if status.pid.is_some() && blocked_path.exists() {
+            match limit::blocking_pid(&blocked_path) {
+                Some(pid) => fields.add(
+                    "Limited",
+                    format!("traffic is blocked by `proxy limit` (pid: {pid})").yellow(),
+                ),
+                None => match limit::restore(&self.controller()?, &blocked_path).await {
+                    Ok(_) => info!("Unblocked the traffic a stopped limit left blocked"),
+                    Err(e) => {
+                        warn!("Failed to unblock the traffic a stopped limit left blocked: {e}")
+                    }
+                },
+            }
+        }
+        if status.pid.is_some() && self.instance_dir.join(PAUSED_FILE).exists() {
+            fields.add(
+                "Paused",
+                "run `proxy resume` to use the proxy again".yellow(),
+            );
+        }
+        fields.print();
+        let health = ProviderHealth::load(&self.instance_dir.join(PROVIDER_HEALTH_FILE));
+        let now = jiff::Timestamp::now().as_second();
+        for warning in health.fetch_warnings(now) {
+            warn!("{warning}");
+        }
+        // Only counted while the core runs, an old count says nothing now
+        if let Some(warning) = health
+            .node_warning(now)
+            .filter(|_| status.running.is_some())
+        {
+            warn!("{warning}");
+        }
+        if self.settings.check_update_on_status {
+            if let Err(e) = self.check_update().await {
+                warn!("Failed to check for updates: {e:#}");
+            }
+        }
+        Ok(status)
+    }
+
+    /// Repaints the pid, memory, CPU, throughput and connection count every
+    /// `interval` until Ctrl+C is pressed. Keeps watching while the core is stopped.
+    pub async fn watch_status(&self, interval: Duration) -> Result<()> {
+        let interval = interval.max(Duration::from_secs(1));
+        // (pid, time, upload total, download total) of the previous sample
+        let mut previous: Option<(u32, Instant, u64, u64)> = None;
+        loop {
+            let mut lines = vec![format!(
+                "{} status every {}s, press Ctrl+C to exit",
+                self.display_name(),
+                interval.as_secs()
+            )
+            .bold()
+            .to_string()];
+            match self.is_running()? {
+                None => {
+                    previous = None;
+                    lines.push(format!("{} is not running", self.display_name()));
+                }
+                Some(pid) => {
+                    let status = self.running_status(pid).await;
+                    lines.push(format!("Running (pid: {pid})"));
+                    lines.push(format!(
+                        "Uptime: {}, memory: {}, CPU: {:.1}%",
+                        format_duration(status.uptime),
+                        format_bytes(status.memory),
+                        status.cpu_usage
+                    ));
+                    if let Some(mode) = &status.mode {
+                        lines.push(format!("Mode: {mode}"));
+                    }
+                    match self.controller()?.connections().await {
+                        Ok(snapshot) => {
+                            let now = Instant::now();
+                            let rate = |before: u64, after: u64, since: Instant| {
+                                let secs = now.duration_since(since).as_secs_f64().max(0.001);
+                                format!(
+                                    "{}/s",
+                                    format_bytes(
+                                        (after.saturating_sub(before) as f64 / secs) as u64
+                                    )
+                                )
+                            };
+                            let (up, down) = match previous {
+                                Some((last_pid, since, up, down)) if last_pid == pid => (
+                                    rate(up, snapshot.upload_total, since),
+                                    rate(down, snapshot.download_total, since),
+                                ),
+                                _ => ("-".to_string(), "-".to_string()),
+                            };
+                            lines.push(format!(
+                                "Traffic: {} {up:>12}  {} {down:>12}  total {} {}  {} {}",
+                                "↑".green(),
+                                "↓".cyan(),
+                                "↑".green(),
+                                format_bytes(snapshot.upload_total),
+                                "↓".cyan(),
+                                format_bytes(snapshot.download_total)
+                            ));
+                            lines.push(format!(
+                                "Active connections: {}",
+                                snapshot.connections.len()
+                            ));
+                            previous =
+                                Some((pid, now, snapshot.upload_total, snapshot.download_total));
+                        }
+                        Err(e) => lines.push(format!("Controller: {e}")),
+                    }
+                    if !status.selected.is_empty() {
+                        lines.push("Selected nodes:".to_string());
+                    }
+                    for (group, node) in &status.selected {
+                        lines.push(format!("  {group}: {node}"));
+                    }
+                }
+            }
+            print!("\x1b[2J\x1b[H");
+            for line in lines {
+                println!("{line}");
+            }
+            tokio::select! {
+                _ = tokio::time::sleep(interval) => {}
+                _ = tokio::signal::ctrl_c() => break,
+            }
+        }
+        Ok(())
+    }
+
+    /// Collects process stats from the OS and the rest from config.yaml and
+    /// the controller. Controller errors are logged, not returned.
+    async fn running_status(&self, pid: u32) -> RunningStatus {
+        let pid = sysinfo::Pid::from_u32(pid);
+        let mut system = sysinfo::System::new();
+        // CPU usage is measured between two refreshes
+        system.refresh_processes(sysinfo::ProcessesToUpdate::Some(&[pid]), true);
+        tokio::time::sleep(sysinfo::MINIMUM_CPU_UPDATE_INTERVAL).await;
+        system.refresh_processes(sysinfo::ProcessesToUpdate::Some(&[pid]), true);
+
+        let config_path = self.config_path();
+        let (mixed_port, controller, secret) = match self.adopted() {
+            Some(external) => (external.mixed_port, external.controller, external.secret),
+            None => (
+                self.core.mixed_port(&config_path),
+                self.core.external_controller(&config_path),
+                self.core.secret(&config_path),
+            ),
+        };
+        let webui = controller
+            .as_deref()
+            .and_then(|address| address.parse::<SocketAddr>().ok())
+            .map(|address| webui_url(local_address(address), &secret.unwrap_or_default(), false));
+        let mut status = RunningStatus {
+            mixed_port,
+            controller,
+            webui,
+            ..Default::default()
+        };
+        if let Some(process) = system.process(pid) {
+            status.core_path = process.exe().map(Path::to_path_buf);
+            status.uptime = Duration::from_secs(process.run_time());
+            status.memory = process.memory();
+            status.cpu_usage = process.cpu_usage();
+        }
+
+        let result: Result<()> = async {
+            let controller = self.controller()?;
+            status.version = Some(controller.version().await?);
+            status.mode = Some(controller.configs().await?.mode);
+            let proxies = controller.proxies().await?;
+            // Nodes the core never health-checked tell nothing about the provider
+            self.record_nodes(
+                proxies
+                    .values()
+                    .filter(|p| p.is_node() && !p.history.is_empty())
+                    .map(|p| p.alive == Some(false)),
+            );
+            let mut selectors: Vec<_> = proxies
+                .into_values()
+                .filter(|p| p.kind == "Selector")
+                .filter_map(|p| p.now.map(|now| (p.name, now)))
+                .collect();
+            selectors.sort();
+            status.selected = selectors;
+            Ok(())
+        }
+        .await;
+        if let Err(e) = result {
+            warn!("Failed to query the controller: {e}");
+        }
+        status
+    }
+
+    /// The effective config, with `redact` masking credentials and server
+    /// addresses so it can be shared.
+    pub fn export_config(&self, redact: bool) -> Result<String> {
+        let config_path = self.config_path();
+        if !config_path.exists() {
+            return Err(anyhow!(
+                "{} does not exist, run `proxy start <URL>` first",
+                config_path.display()
+            ));
+        }
+        if redact {
+            self.core.redact_config(&config_path)
+        } else {
+            Ok(fs::read_to_string(&config_path)?)
+        }
+    }
+
+    /// Lints the config, then runs the core's own check on it. Prints every
+    /// problem found, failing if any is an error.
+    pub async fn check_config(&self) -> Result<()> {
+        let config_path = self.config_path();
+        let content = fs::read_to_string(&config_path).with_context(|| {
+            format!(
+                "Failed to read {}, run `proxy start <URL>` first",
+                config_path.display()
+            )
+        })?;
+        let file_name = self.core.config_file();
+        let mut issues = match self.core.kind() {
+            CoreKind::Mihomo => lint::lint_mihomo_config(&content),
+            CoreKind::SingBox => check_config_content(self.core, &content)
+                .err()
+                .map(|e| Issue {
+                    severity: Severity::Error,
+                    line: None,
+                    message: format!("{e:#}"),
+                })
+                .into_iter()
+                .collect(),
+        };
+        if !self.core_path.exists() {
+            warn!(
+                "{} is not installed, skipping its own check",
+                self.core.name()
+            );
+        } else if let Err(e) =
+            doctor::run_core(&self.core_path, self.core.test_args(&self.config_dir)).await
+        {
+            issues.push(Issue {
+                severity: Severity::Error,
+                line: None,
+                message: format!("{} rejects it: {e}", self.core.name()),
+            });
+        }
+        lint::print_issues(file_name, &issues);
+
+        let errors = issues
+            .iter()
+            .filter(|issue| issue.severity == Severity::Error)
+            .count();
+        let warnings = issues.len() - errors;
+        if errors > 0 {
+            return Err(anyhow!(
+                "{file_name} has errors: {errors}, warnings: {warnings}"
+            ));
+        }
+        info!("{file_name} has no errors, warnings: {warnings}");
+        Ok(())
+    }
+
+    /// Opens the config, or a template if there is none, in `$EDITOR`. The saved
+    /// file is checked by the core before it replaces the config, and a running
+    /// Mihomo is reloaded if the user agrees.
+    pub async fn edit_config(&self) -> Result<()> {
+        let _lock = self.lock_instance()?;
+        let config_path = self.config_path();
+        let mut initial = match fs::read_to_string(&config_path) {
+            Ok(content) => content,
+            Err(_) => config_template(self.core.kind()).to_string(),
+        };
+        loop {
+            let Some(content) = edit_config_content(self.core, &config_path, &initial)? else {
+                return Ok(());
+            };
+            let backup = edit_backup_path(&config_path);
+            let had_config = config_path.exists();
+            if had_config {
+                fs::copy(&config_path, &backup)?;
+            }
+            fs::write(&config_path, &content)?;
+            if !self.core_path.exists() {
+                break;
+            }
+            match doctor::run_core(&self.core_path, self.core.test_args(&self.config_dir)).await {
+                Ok(_) => break,
+                Err(e) => {
+                    if had_config {
+                        fs::rename(&backup, &config_path)?;
+                    } else {
+                        fs::remove_file(&config_path)?;
+                    }
+                    warn!("{} rejected the config: {e}", self.core.name());
+                    if !ask_for_confirmation("Edit it again?", false) {
+                        return Err(anyhow!("Discarded the changes"));
+                    }
+                    initial = content;
+                }
+            }
+        }
+        info!("Config saved to {}", config_path.display());
+
+        if self.is_running()?.is_none() {
+            return Ok(());
+        }
+        if self.core.kind() != CoreKind::Mihomo {
+            info!("Restart {} to use the new config", self.display_name());
+        } else if ask_for_confirmation(
+            &format!("Reload the running {}?", self.display_name()),
+            true,
+        ) {
+            self.reload_validated(&self.controller()?).await?;
+        }
+        Ok(())
+    }
+
+    /// Prints the latency of each GitHub mirror used for downloads.
+    pub async fn test_mirrors(&self) -> Result<()> {
+        let results = measure_github_proxies(&self.client, &self.github_mirrors).await;
+        let width = results
+            .iter()
+            .map(|(proxy, _)| proxy_display_name(proxy).len())
+            .max()
+            .unwrap_or(0);
+
+        println!();
+        for (proxy, elapsed) in results {
+            let latency = match elapsed {
+                Some(t) => format!("{} ms", t.as_millis()),
+                None => "unavailable".to_string(),
+            };
+            println!("{:<width$}  {}", proxy_display_name(&proxy), latency);
+        }
+        Ok(())
+    }
+
+    /// Checks the core, config, ports, controller, geodata, GitHub mirrors and
+    /// TUN prerequisites, printing a fix for each problem. Fails if any check did.
+    pub async fn doctor(&self) -> Result<()> {
+        let checks = self.doctor_checks().await?;
+        match doctor::report(&checks) {
+            0 => Ok(()),
+            failed => Err(anyhow!("{failed} of {} checks failed", checks.len())),
+        }
+    }
+
+    async fn doctor_checks(&self) -> Result<Vec<doctor::Check>> {
+        let config_path = self.config_path();
+        let running = self.is_running()?.is_some();
+
+        let core = doctor::core(self.core, &self.core_path, self.core_integrity()?).await;
+        let config =
+            doctor::config(self.core, &self.core_path, &self.config_dir, core.passed()).await;
+        let mut checks = vec![core, config];
+        if running {
+            let mixed_address = self
+                .core
+                .mixed_port(&config_path)
+                .map(|port| SocketAddr::from((Ipv4Addr::LOCALHOST, port)));
+            checks.push(doctor::listening("mixed-port", mixed_address));
+            // A Unix socket is only checked by the controller check below
+            match self.core.controller_address(&config_path) {
+                Some(ControllerAddress::Unix(_)) => {}
+                Some(ControllerAddress::Tcp(address)) => checks.push(doctor::listening(
+                    "Controller port",
+                    Some(local_address(address)),
+                )),
+                None => checks.push(doctor::listening("Controller port", None)),
+            }
+        } else {
+            checks.push(doctor::free(
+                "mixed-port",
+                self.settings.mixed_port.unwrap_or(DEFAULT_MIXED_PORT),
+                "--mixed-port",
+            ));
+            checks.push(doctor::free(
+                "Controller port",
+                self.settings
+                    .controller_port
+                    .unwrap_or(DEFAULT_CONTROLLER_PORT),
+                "--controller-port",
+            ));
+        }
+        checks.push(doctor::controller(running.then(|| self.controller())).await);
+        if self.core.kind() == CoreKind::Mihomo {
+            let files = geodata_files(self.geodata_mode())
+                .iter()
+                .map(|(filename, _)| Ok((*filename, self.geodata_integrity(filename)?)))
+                .collect::<Result<Vec<_>>>()?;
+            checks.push(doctor::geodata(&files));
+        }
+        checks.push(doctor::mirrors(&self.client, &self.github_mirrors).await);
+        checks.push(doctor::tun(&self.core_path));
+        Ok(checks)
+    }
+
+    /// Versions, platform, doctor results, the redacted config and the end of
+    /// the core's logs as Markdown to paste into an issue. With `archive` they
+    /// are written to a tar.gz instead, one file each.
+    pub async fn bug_report(&self, archive: Option<&Path>) -> Result<()> {
+        let pid = self.is_running()?;
+        let manifest = Manifest::load(&self.manifest_path())?;
+        let checks = self.doctor_checks().await?;
+        let config = self
+            .export_config(true)
+            .unwrap_or_else(|e| format!("# {e}"));
+        let logs: Vec<(&str, String)> = ["mihomo.log", "mihomo.err"]
+            .into_iter()
+            .filter_map(|name| {
+                let content = fs::read_to_string(self.instance_dir.join(name)).ok()?;
+                let lines: Vec<&str> = content.lines().collect();
+                let tail = lines[lines.len().saturating_sub(REPORT_LOG_LINES)..].join("\n");
+                Some((name, tail))
+            })
+            .collect();
+
+        let mut summary = format!(
+            "### Environment\n\n\
+             - proxy-rs: {}\n\
+             - OS: {} ({} {})\n\
+             - Core: {} {} at {}\n\
+             - Running: {}\n\n\
+             ### Doctor\n\n```text\n{}```\n",
+            env!("CARGO_PKG_VERSION"),
+            sysinfo::System::long_os_version().unwrap_or_else(|| "unknown".to_string()),
+            std::env::consts::OS,
+            std::env::consts::ARCH,
+            self.core.name(),
+            manifest
+                .core_version
+                .as_deref()
+                .unwrap_or("(version unknown)"),
+            self.core_path.display(),
+            pid.map_or_else(|| "no".to_string(), |pid| format!("yes (pid {pid})")),
+            doctor::to_text(&checks),
+        );
+
+        let Some(archive) = archive else {
+            summary.push_str(&format!(
+                "\n### Config (redacted)\n\n```{}\n{}\n```\n",
+                self.config_path()
+                    .extension()
+                    .map_or_else(String::new, |ext| ext.to_string_lossy().into_owned()),
+                config.trim_end()
+            ));
+            for (name, tail) in &logs {
+                summary.push_str(&format!(
+                    "\n### {name} (last {REPORT_LOG_LINES} lines)\n\n```text\n{tail}\n```\n"
+                ));
+            }
+            println!("{summary}");
+            return Ok(());
+        };
+
+        let dir = self.instance_dir.join("report");
+        fs::create_dir_all(&dir)?;
+        let config_name = self
+            .config_path()
+            .file_name()
+            .map_or_else(|| PathBuf::from("config"), PathBuf::from);
+        let mut files = vec![(PathBuf::from("report.md"), summary), (config_name, config)];
+        files.extend(
+            logs.into_iter()
+                .map(|(name, tail)| (PathBuf::from(name), tail)),
+        );
+        for (name, content) in &files {
+            fs::write(dir.join(name), content)?;
+        }
+        let names: Vec<PathBuf> = files.into_iter().map(|(name, _)| name).collect();
+        let packed = backup::pack(&dir, &names, archive);
+        fs::remove_dir_all(&dir)?;
+        packed?;
+        info!(
+            "Report written to {}, attach it to the issue",
+            archive.display()
+        );
+        Ok(())
+    }
+
+    /// Exposes localhost:`port` publicly through `backend`, downloading
+    /// cloudflared or bore into the data dir if it is not installed. Without a
+    /// port the running instance's controller is exposed, with the WebUI at `/ui`.
+    /// The controller is protected by a secret first, also for a later start.
+    /// With `proxy` the proxy itself is exposed instead, see [`Self::tunnel_proxy`].
+    pub async fn tunnel(&self, port: Option<u16>, options: &TunnelOptions) -> Result<()> {
+        if options.proxy {
+            return self.tunnel_proxy(options).await;
+        }
+        let port = match port {
+            Some(port) => port,
+            None => self.webui_port().await?,
+        };
+        let secret = Some(self.ensure_controller_secret().await?);
+        let binary = self.tunnel_binary(options.service).await?;
+        let mut services = tunnel::services(
+            options.service,
+            &binary,
+            port,
+            &self.settings.tunnel,
+            &self.instance_dir,
+        )?;
+        if options.no_fallback {
+            services.truncate(1);
+        }
+        let exposed = Exposed::WebUi { secret };
+        if options.detach {
+            tunnel::start_detached(&services, &self.instance_dir, options.json, &exposed)?;
+            Ok(())
+        } else {
+            tunnel::run_foreground(&services, options.json, &exposed, options.retries)
+        }
+    }
+
+    /// Exposes the proxy through a mixed listener on a random loopback port that
+    /// only accepts a generated password. The listener is removed once a
+    /// foreground tunnel ends, or by `tunnel stop` for a detached one.
+    async fn tunnel_proxy(&self, options: &TunnelOptions) -> Result<()> {
+        self.require_mihomo("`tunnel --proxy`")?;
+        if !options.service.forwards_tcp() {
+            return Err(anyhow!(
+                "This service only forwards HTTP, use --service pinggy, bore, ssh-host or frp with --proxy"
+            ));
+        }
+        let controller = self.controller()?;
+        let config_path = self.config_path();
+        if skips_loopback_auth(&config_path) {
+            return Err(anyhow!(
+                "skip-auth-prefixes in {} lets connections from 127.0.0.1 skip authentication, \
+                 so anyone could use the tunneled proxy. Remove loopback from it first.",
+                self.core.config_file()
+            ));
+        }
+        let port = find_unused_port(rand::thread_rng().gen_range(20000..60000))
+            .ok_or_else(|| anyhow!("No free port for the tunnel listener"))?;
+        let binary = self.tunnel_binary(options.service).await?;
+        let services = tunnel::proxy_services(
+            options.service,
+            &binary,
+            port,
+            &self.settings.tunnel,
+            &self.instance_dir,
+        )?;
+
+        warn!("--proxy lets anyone with the password use your proxy from the internet!");
+        let password = generate_secret();
+        {
+            let _lock = self.lock_instance()?;
+            add_tunnel_listener(&config_path, port, TUNNEL_USERNAME, &password)?;
+            self.reload_config(&controller).await?;
+        }
+        let exposed = Exposed::Proxy {
+            username: TUNNEL_USERNAME.to_string(),
+            password,
+        };
+        if options.detach {
+            let started =
+                tunnel::start_detached(&services, &self.instance_dir, options.json, &exposed);
+            if started.is_err() {
+                self.close_tunnel_listener().await?;
+            }
+            return started.map(|_| ());
+        }
+
+        // Ctrl+C reaches the tunnel program too, the listener is closed once it exits
+        tokio::spawn(async {
+            if tokio::signal::ctrl_c().await.is_ok() {
+                tunnel::interrupt();
+            }
+        });
+        let (json, retries) = (options.json, options.retries);
+        let result = tokio::task::spawn_blocking(move || {
+            tunnel::run_foreground(&services, json, &exposed, retries)
+        })
+        .await?;
+        self.close_tunnel_listener().await?;
+        result
+    }
+
+    /// Removes the listener added by `tunnel --proxy`, reloading Mihomo if it is running.
+    async fn close_tunnel_listener(&self) -> Result<()> {
+        if self.core.kind() != CoreKind::Mihomo {
+            return Ok(());
+        }
+        let _lock = self.lock_instance()?;
+        if !remove_tunnel_listener(&self.config_path())? {
+            return Ok(());
+        }
+        if self.is_running()?.is_some() {
+            self.reload_config(&self.controller()?).await?;
+        }
+        info!("Closed the tunnel's proxy listener");
+        Ok(())
+    }
+
+    /// Port of the running controller, downloading the WebUI it serves if missing.
+    async fn webui_port(&self) -> Result<u16> {
+        let controller = self.controller()?;
+        if let Some(path) = controller.unix_socket() {
+            return Err(anyhow!(
+                "The controller only listens on {}, which serves no WebUI. Start without --controller-unix",
+                path.display()
+            ));
+        }
+        let port = controller
+            .port()
+            .ok_or_else(|| anyhow!("Failed to read the port of the external controller"))?;
+        if self.settings.ui == WebUi::None {
+            warn!("No WebUI is served with ui = \"none\", pick one with `start --ui`");
+        } else {
+            self.download_ui_if_necessary().await?;
+        }
+        info!("Tunneling the external controller on port {port}");
+        Ok(port)
+    }
+
+    /// Prints the tunnel started with `--detach`, if it is still running.
+    pub fn tunnel_status(&self, json: bool) -> Result<Option<BackgroundTunnel>> {
+        let tunnel = tunnel::background_tunnel(&self.instance_dir)?;
+        match &tunnel {
+            Some(tunnel) => {
+                info!(
+                    "Tunnel through {} is running (pid: {}).",
+                    tunnel.info.service, tunnel.pid
+                );
+                tunnel::report(&tunnel.info, json);
+            }
+            None => info!("No tunnel is running."),
+        }
+        Ok(tunnel)
+    }
+
+    /// Stops the tunnel started with `--detach`, and closes the proxy listener
+    /// of `tunnel --proxy`.
+    pub async fn tunnel_stop(&self) -> Result<()> {
+        if !tunnel::stop_detached(&self.instance_dir)? {
+            warn!("No tunnel is running.");
+        }
+        self.close_tunnel_listener().await
+    }
+
+    /// The backend's executable from PATH or the data dir, downloaded if missing.
+    async fn tunnel_binary(&self, backend: TunnelBackend) -> Result<PathBuf> {
+        let name = backend.binary_name();
+        if let Ok(path) = which::which(name) {
+            return Ok(path);
+        }
+        let path = self
+            .proxy_data_dir
+            .join(format!("{name}{}", std::env::consts::EXE_SUFFIX));
+        if path.exists() {
+            return Ok(path);
+        }
+
+        let url = backend.release_url().ok_or_else(|| {
+            anyhow!("{name} can't be downloaded for this platform, install it and add it to PATH")
+        })?;
+        info!("Downloading {name}...");
+        let download_path = path.with_extension("download");
+        self.download_from_github(&url, &download_path).await?;
+        let is_zip = url.ends_with(".zip");
+        if is_zip || url.ends_with(".tar.gz") || url.ends_with(".tgz") {
+            let extract_dir = path.with_extension("extract");
+            if is_zip {
+                unzip_file(&download_path, &extract_dir)?;
+            } else {
+                decompress_tar_gz(&download_path, &extract_dir, 0)?;
+            }
+            let extracted = find_extracted(&extract_dir, path.file_name().unwrap())
+                .with_context(|| format!("{name} not found in the downloaded archive"))?;
+            fs::rename(extracted, &path)?;
+            fs::remove_dir_all(&extract_dir)?;
+            fs::remove_file(&download_path)?;
+        } else {
+            fs::rename(&download_path, &path)?;
+        }
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&path, fs::Permissions::from_mode(0o755))?;
+        }
+        Ok(path)
+    }
+
+    /// Pid of the running Mihomo, `None` if it is not running.
+    pub fn is_running(&self) -> Result<Option<u32>> {
+        if let Some(pid) = self.load_pid() {
+            let system = sysinfo::System::new_all();
+            let process = system.process(pid);
+            match process {
+                Some(_) => Ok(Some(pid.as_u32())),
+                None => {
+                    let _ = fs::remove_file(self.instance_dir.join(MIHOMO_PID_FILE));
+                    Ok(None)
+                }
+            }
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn save_pid(&self, child: &Child) -> Result<()> {
+        fs::write(
+            self.instance_dir.join(MIHOMO_PID_FILE),
+            child.id().to_string(),
+        )?;
+        fs::write(self.instance_dir.join(CORE_FILE), self.core.kind().as_str())?;
+        let _ = fs::remove_file(self.instance_dir.join(ADOPTED_FILE));
+        Ok(())
+    }
+
+    /// The core started outside proxy-rs this instance adopted, while it runs.
+    fn adopted(&self) -> Option<External> {
+        let pid = self.load_pid()?.as_u32();
+        External::load(&self.instance_dir.join(ADOPTED_FILE))
+            .ok()
+            .filter(|external| external.pid == pid)
+    }
+
+    fn refuse_adopted(&self, action: &str) -> Result<()> {
+        match self.adopted() {
+            Some(external) => Err(anyhow!(
+                "{} (pid: {}) was started outside proxy-rs with {}, `proxy stop` it before you {action}",
+                self.core.name(),
+                external.pid,
+                external.config.display()
+            )),
+            None => Ok(()),
+        }
+    }
+
+    /// Mihomo processes started outside proxy-rs, leaving out the ones any
+    /// instance already tracks.
+    fn find_external(&self) -> Result<Vec<External>> {
+        if self.core.kind() != CoreKind::Mihomo {
+            return Ok(Vec::new());
+        }
+        let mut known = Vec::new();
+        for (_, instance_dir) in self.instance_dirs()? {
+            known.extend(
+                fs::read_to_string(instance_dir.join(MIHOMO_PID_FILE))
+                    .ok()
+                    .and_then(|pid| pid.trim().parse::<u32>().ok()),
+            );
+        }
+        Ok(adopt::find(&known))
+    }
+
+    fn adopt_external(&self, external: &External) -> Result<()> {
+        fs::write(
+            self.instance_dir.join(MIHOMO_PID_FILE),
+            external.pid.to_string(),
+        )?;
+        fs::write(self.instance_dir.join(CORE_FILE), self.core.kind().as_str())?;
+        external.save(&self.instance_dir.join(ADOPTED_FILE))?;
+        info!("Adopted {} (pid: {})", self.core.name(), external.pid);
+        Ok(())
+    }
+
+    /// [`Self::is_running`], or when nothing runs, offers to adopt a Mihomo
+    /// started outside proxy-rs, so it can be queried and stopped like one of
+    /// ours. Only asks on a terminal and never adopts under `--yes`, scripts
+    /// use [`Self::adopt_external_core`].
+    fn is_running_or_adopt(&self) -> Result<Option<u32>> {
+        if let Some(pid) = self.is_running()? {
+            return Ok(Some(pid));
+        }
+        let interactive = std::io::stdin().is_terminal() && !assume_yes();
+        let found = self.find_external()?;
+        for external in &found {
+            info!(
+                "Found {} started outside proxy-rs (pid: {}, config: {}, controller: {})",
+                self.core.name(),
+                external.pid,
+                external.config.display(),
+                external.controller.as_deref().unwrap_or("none")
+            );
+            if !interactive {
+                continue;
+            }
+            if !ask_for_confirmation(
+                &format!(
+                    "Adopt it as {}, so proxy-rs can manage and stop it?",
+                    self.display_name()
+                ),
+                false,
+            ) {
+                continue;
+            }
+            self.adopt_external(external)?;
+            return Ok(Some(external.pid));
+        }
+        if !interactive && !found.is_empty() {
+            info!("Run `proxy adopt` to manage it with proxy-rs");
+        }
+        Ok(None)
+    }
+
+    /// Adopts the Mihomo started outside proxy-rs with `pid`, or the only one
+    /// found, without asking.
+    pub fn adopt_external_core(&self, pid: Option<u32>) -> Result<()> {
+        let _lock = self.lock_instance()?;
+        if let Some(running) = self.is_running()? {
+            return Err(anyhow!(
+                "{} is already running (pid: {running})",
+                self.display_name()
+            ));
+        }
+        let found = self.find_external()?;
+        let external = match pid {
+            Some(pid) => found
+                .iter()
+                .find(|external| external.pid == pid)
+                .ok_or_else(|| {
+                    anyhow!(
+                        "No {} started outside proxy-rs with pid {pid}",
+                        self.core.name()
+                    )
+                })?,
+            None => match found.as_slice() {
+                [] => {
+                    return Err(anyhow!(
+                        "No {} started outside proxy-rs found",
+                        self.core.name()
+                    ))
+                }
+                [external] => external,
+                _ => {
+                    return Err(anyhow!(
+                        "Found {} started outside proxy-rs with pids {}, pass --pid",
+                        self.core.name(),
+                        found
+                            .iter()
+                            .map(|external| external.pid.to_string())
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    ))
+                }
+            },
+        };
+        self.adopt_external(external)
+    }
+
+    fn load_pid(&self) -> Option<sysinfo::Pid> {
+        fs::read_to_string(self.instance_dir.join(MIHOMO_PID_FILE))
+            .ok()
+            .and_then(|pid_str| sysinfo::Pid::from_str(&pid_str).ok())
+    }
+
+    /// Downloads Mihomo if it is missing, or if `version` is pinned and differs
+    /// from the installed one. With `auto-update-core` and no pinned version,
+    /// the latest release is installed. Nothing is downloaded with `core-path`.
+    /// Returns whether a new binary was installed.
+    async fn download_mihomo_if_necessary(
+        &self,
+        no_verify: bool,
+        version: Option<&str>,
+    ) -> Result<bool> {
+        if self.settings.core_path.is_some() {
+            if !self.core_path.exists() {
+                return Err(anyhow!(
+                    "The core-path {} does not exist",
+                    self.core_path.display()
+                ));
+            }
+            return Ok(false);
+        }
+        let integrity = self.core_integrity()?;
+        if integrity == Integrity::Changed {
+            // May be a build the user put there on purpose, keep it
+            warn!(
+                "{} differs from the binary proxy-rs installed, run `proxy update` to replace it",
+                self.core_path.display()
+            );
+            if version.is_none() {
+                return Ok(false);
+            }
+        }
+        if integrity != Integrity::Missing {
+            let installed = self.installed_core_version()?;
+            match version {
+                None if !self.settings.auto_update_core => return Ok(false),
+                None => match self.latest_core_version(self.installed_channel()?).await {
+                    Ok(latest) if installed.as_deref() == Some(latest.as_str()) => {
+                        return Ok(false)
+                    }
+                    Ok(latest) => {
+                        info!(
+                            "Updating Mihomo {} to {latest}",
+                            installed.as_deref().unwrap_or("unknown")
+                        );
+                        let _core_lock = self.lock_core()?;
+                        self.download_mihomo(no_verify, Some(&latest), Channel::Stable)
+                            .await?;
+                        return Ok(true);
+                    }
+                    Err(e) => {
+                        warn!("Failed to check for Mihomo updates: {e}");
+                        return Ok(false);
+                    }
+                },
+                Some(version) if installed.as_deref() == Some(version) => return Ok(false),
+                Some(version) => info!(
+                    "Installed Mihomo version is {}, switching to {version}",
+                    installed.as_deref().unwrap_or("unknown")
+                ),
+            }
+        }
+        let _core_lock = self.lock_core()?;
+        self.download_mihomo(no_verify, version, Channel::Stable)
+            .await?;
+        Ok(true)
+    }
+
+    /// Downloads the core, replacing the installed binary if there is one.
+    /// Without `version` the latest release in `channel` is used, by default the
+    /// channel of the installed version. Returns the installed version.
+    pub async fn update_core(
+        &self,
+        no_verify: bool,
+        version: Option<&str>,
+        channel: Option<Channel>,
+    ) -> Result<String> {
+        self.require_managed_core()?;
+        let _core_lock = self.lock_core()?;
+        let installed = self.installed_core_version()?;
+        let channel = match channel {
+            Some(channel) => channel,
+            None => self.installed_channel()?,
+        };
+        let updated = self.download_mihomo(no_verify, version, channel).await?;
+        if installed.as_deref() == Some(updated.as_str()) {
+            info!("{} is already at {updated}", self.core.name());
+        } else if self.is_running()?.is_some() {
+            info!("Restart Mihomo to use the new version");
+        }
+        Ok(updated)
+    }
+
+    /// Where the previous binary is kept after an update, for `rollback`.
+    fn backup_core_path(&self) -> PathBuf {
+        self.core_path.with_extension("bak")
+    }
+
+    /// Swaps the installed binary with the one kept by the last update and
+    /// restarts Mihomo if it is running. Rolling back twice undoes the rollback.
+    /// Returns the version rolled back to, if it is known.
+    pub async fn rollback(&self) -> Result<Option<String>> {
+        self.require_managed_core()?;
+        let backup_path = self.backup_core_path();
+        if !backup_path.exists() {
+            return Err(anyhow!("No previous Mihomo binary to roll back to"));
+        }
+
+        let lock = self.lock_instance()?;
+        let _core_lock = self.lock_core()?;
+        let was_running = self.is_running()?.is_some();
+        if was_running {
+            self.save_running_selections().await;
+            self.stop_locked().await?;
+        }
+
+        let swap_path = self.core_path.with_extension("swap");
+        fs::rename(&self.core_path, &swap_path)?;
+        fs::rename(&backup_path, &self.core_path)?;
+        fs::rename(&swap_path, &backup_path)?;
+
+        let manifest_path = self.manifest_path();
+        let mut manifest = Manifest::load(&manifest_path)?;
+        std::mem::swap(
+            &mut manifest.core_version,
+            &mut manifest.previous_core_version,
+        );
+        std::mem::swap(&mut manifest.core, &mut manifest.previous_core);
+        manifest.save(&manifest_path)?;
+        info!(
+            "Rolled back to Mihomo {}",
+            manifest.core_version.as_deref().unwrap_or("unknown")
+        );
+
+        if was_running {
+            // Pin the version, otherwise `auto-update-core` would undo the rollback
+            let options = StartOptions {
+                core_version: manifest.core_version.clone(),
+                ..self.running_start_options()
+            };
+            self.startup.reset();
+            self.start_locked(&options, lock, true).await?;
+        }
+        Ok(manifest.core_version)
+    }
+
+    /// Version proxy-rs installed, `None` with `core-path` as it didn't install that one.
+    fn installed_core_version(&self) -> Result<Option<String>> {
+        if self.settings.core_path.is_some() {
+            return Ok(None);
+        }
+        Ok(Manifest::load(&self.manifest_path())?.core_version)
+    }
+
+    /// Channel of the installed version, stable if nothing is installed.
+    fn installed_channel(&self) -> Result<Channel> {
+        Ok(self
+            .installed_core_version()?
+            .map_or(Channel::Stable, |version| self.core.channel_of(&version)))
+    }
+
+    /// Most recent releases of the core on GitHub, newest first. Releases not
+    /// tagged with a version, like Mihomo's `Prerelease-Alpha`, are left out.
+    async fn core_releases(&self) -> Result<Vec<CoreRelease>> {
+        let body = self.fetch_github_text(&self.core.releases_url()).await?;
+        let releases: Vec<CoreRelease> = serde_json::from_str(&body)
+            .with_context(|| format!("Failed to parse the {} releases", self.core.name()))?;
+        Ok(releases
+            .into_iter()
+            .filter(|release| release.tag_name.starts_with('v'))
+            .collect())
+    }
+
+    /// Prints the version of proxy-rs and the core, and when the WebUI and the
+    /// geodata were downloaded.
+    pub async fn version(&self) -> Result<()> {
+        let mut rows = vec![(
+            "proxy-rs".to_string(),
+            env!("CARGO_PKG_VERSION").to_string(),
+        )];
+        rows.push((self.core.name().to_string(), self.core_version().await?));
+        let ui = self.settings.ui;
+        if ui != WebUi::None {
+            let date = self
+                .ui_downloaded_at(ui)?
+                .map_or_else(|| "not downloaded".to_string(), format_date);
+            rows.push((ui.name().to_string(), date));
+        }
+        if self.core.kind() == CoreKind::Mihomo {
+            for &(filename, _) in geodata_files(self.geodata_mode()) {
+                let date = self.geodata_downloaded_at(filename)?.map_or_else(
+                    || "not downloaded".to_string(),
+                    |time| {
+                        time.to_zoned(jiff::tz::TimeZone::system())
+                            .strftime("%Y-%m-%d %H:%M")
+                            .to_string()
+                    },
+                );
+                rows.push((filename.to_string(), date));
+            }
+        }
+
+        let width = rows.iter().map(|(name, _)| name.len()).max().unwrap_or(0);
+        for (name, version) in rows {
+            println!("{}  {version}", format!("{name:<width$}").bold());
+        }
+        Ok(())
+    }
+
+    /// What the core binary reports with its version argument, falling back
+    /// to the running core and the manifest. The running core is also shown
+    /// when it differs, i.e. it was updated without a restart.
+    async fn core_version(&self) -> Result<String> {
+        let running = match self.is_running()? {
+            Some(_) => self.controller()?.version().await.ok(),
+            None => None,
+        };
+        let binary = if self.core_path.exists() {
+            doctor::run_core(&self.core_path, self.core.version_args())
+                .await
+                .ok()
+                .and_then(|output| output.lines().next().map(|line| line.trim().to_string()))
+        } else {
+            None
+        };
+        let version = match (binary, running) {
+            (Some(binary), Some(running)) if !binary.contains(&running) => {
+                format!("{binary} (running {running}, restart to use the new one)")
+            }
+            (Some(binary), _) => binary,
+            (None, Some(running)) => running,
+            (None, None) if self.core_path.exists() => Manifest::load(&self.manifest_path())?
+                .core_version
+                .unwrap_or_else(|| "unknown".to_string()),
+            (None, None) => "not installed".to_string(),
+        };
+        Ok(version)
+    }
+
+    /// Compares the installed core, WebUI and geodata with their latest
+    /// releases and prints what is outdated, without installing anything.
+    /// A component whose check fails is skipped with a warning.
+    pub async fn check_update(&self) -> Result<Vec<UpdateCheck>> {
+        let (core, ui, geodata) = tokio::join!(
+            self.check_core_update(),
+            self.check_ui_update(),
+            self.check_geodata_update()
+        );
+        let mut checks = Vec::new();
+        let results = [
+            ("core", core.map(Vec::from_iter)),
+            ("WebUI", ui.map(Vec::from_iter)),
+            ("geodata", geodata),
+        ];
+        for (component, result) in results {
+            match result {
+                Ok(found) => checks.extend(found),
+                Err(e) => warn!("Failed to check the {component} for updates: {e:#}"),
+            }
+        }
+
+        for check in &checks {
+            if check.outdated {
+                info!(
+                    "{}: {} installed, {} is available, run `{}`",
+                    check.component.yellow(),
+                    check.installed,
+                    check.latest,
+                    check.update_command
+                );
+            } else {
+                info!("{}: {} is the latest", check.component, check.installed);
+            }
+        }
+        match checks.iter().filter(|check| check.outdated).count() {
+            0 if !checks.is_empty() => info!("Everything is up to date"),
+            0 => {}
+            n => info!("{n} update(s) available"),
+        }
+        Ok(checks)
+    }
+
+    /// The installed core against the latest release in its channel, nothing
+    /// if proxy-rs didn't install it.
+    async fn check_core_update(&self) -> Result<Option<UpdateCheck>> {
+        let Some(installed) = self.installed_core_version()? else {
+            return Ok(None);
+        };
+        let body = self
+            .fetch_github_text(
+                self.core
+                    .latest_version_url(self.core.channel_of(&installed)),
+            )
+            .await?;
+        let latest = self
+            .core
+            .parse_latest_version(self.core.channel_of(&installed), &body)?;
+        Ok(Some(UpdateCheck {
+            component: self.core.name().to_string(),
+            outdated: installed != latest,
+            installed,
+            latest,
+            update_command: "proxy update",
+        }))
+    }
+
+    /// The WebUI folder against the last commit of the branch it is downloaded from.
+    async fn check_ui_update(&self) -> Result<Option<UpdateCheck>> {
+        let ui = self.settings.ui;
+        let (Some((release_url, _)), Some(installed)) = (ui.release(), self.ui_downloaded_at(ui)?)
+        else {
+            return Ok(None);
+        };
+        // https://github.com/<owner>/<repo>/archive/refs/heads/<branch>.zip
+        let parts: Vec<&str> = release_url
+            .trim_start_matches("https://github.com/")
+            .split('/')
+            .collect();
+        let [owner, repo, "archive", "refs", "heads", branch] = parts[..] else {
+            return Err(anyhow!("Unexpected WebUI download URL {release_url}"));
+        };
+        let url = format!(
+            "https://api.github.com/repos/{owner}/{repo}/commits/{}",
+            branch.trim_end_matches(".zip")
+        );
+        let commit: GitHubCommit = serde_json::from_str(&self.fetch_github_text(&url).await?)
+            .with_context(|| format!("Failed to parse the commit from {url}"))?;
+        let latest = commit.commit.committer.date;
+        Ok(Some(UpdateCheck {
+            component: ui.name().to_string(),
+            installed: format_date(installed),
+            latest: format_date(latest),
+            outdated: latest > installed,
+            update_command: "proxy ui update",
+        }))
+    }
+
+    /// The geodata files against the assets of the `latest` rules release.
+    async fn check_geodata_update(&self) -> Result<Vec<UpdateCheck>> {
+        if self.core.kind() != CoreKind::Mihomo {
+            return Ok(Vec::new());
+        }
+        let mut installed: Vec<(&str, &str, jiff::Timestamp)> = Vec::new();
+        for &(filename, asset) in geodata_files(self.geodata_mode()) {
+            if let Some(time) = self.geodata_downloaded_at(filename)? {
+                installed.push((filename, asset, time));
+            }
+        }
+        if installed.is_empty() {
+            return Ok(Vec::new());
+        }
+        let url = "https://api.github.com/repos/MetaCubeX/meta-rules-dat/releases/tags/latest";
+        let release: GitHubRelease = serde_json::from_str(&self.fetch_github_text(url).await?)
+            .context("Failed to parse the geodata release")?;
+        Ok(installed
+            .into_iter()
+            .filter_map(|(filename, asset, installed)| {
+                let latest = release.assets.iter().find(|a| a.name == asset)?.updated_at;
+                Some(UpdateCheck {
+                    component: filename.to_string(),
+                    installed: format_date(installed),
+                    latest: format_date(latest),
+                    outdated: latest > installed,
+                    update_command: "proxy geo update",
+                })
+            })
+            .collect())
+    }
+
+    /// Prints the available releases with their dates, marking the installed one.
+    pub async fn list_core_releases(&self) -> Result<()> {
+        let installed = self.installed_core_version()?;
+        let releases = self.core_releases().await?;
+        let width = releases
+            .iter()
+            .map(|release| release.tag_name.len())
+            .max()
+            .unwrap_or(0);
+
+        println!();
+        for release in &releases {
+            let mut line = format!("{:<width$}  {}", release.tag_name, release.date());
+            if release.prerelease {
+                line.push_str("  prerelease");
+            }
+            if installed.as_deref() == Some(release.tag_name.as_str()) {
+                line.push_str("  (installed)");
+            }
+            println!("{line}");
+        }
+        Ok(())
+    }
+
+    /// Installs the release tagged `tag`, or the one picked from the list of
+    /// available releases. Returns the installed version, `None` if the prompt
+    /// was cancelled.
+    pub async fn install_core(&self, no_verify: bool, tag: Option<&str>) -> Result<Option<String>> {
+        self.require_managed_core()?;
+        let tag = match tag {
+            Some(tag) => tag.to_string(),
+            None => {
+                let installed = self.installed_core_version()?;
+                let mut releases = self.core_releases().await?;
+                if releases.is_empty() {
+                    return Err(anyhow!("No {} release found", self.core.name()));
+                }
+                let items: Vec<String> = releases
+                    .iter()
+                    .map(|release| {
+                        let kind = if release.prerelease {
+                            "  prerelease"
+                        } else {
+                            ""
+                        };
+                        format!("{}  {}{kind}", release.tag_name, release.date())
+                    })
+                    .collect();
+                let current = releases
+                    .iter()
+                    .position(|release| installed.as_deref() == Some(release.tag_name.as_str()))
+                    .unwrap_or(0);
+                let Some(index) = picker::fuzzy_select("Release", &items, current)? else {
+                    return Ok(None);
+                };
+                releases.swap_remove(index).tag_name
+            }
+        };
+        self.update_core(no_verify, Some(&tag), None)
+            .await
+            .map(Some)
+    }
+
+    async fn latest_core_version(&self, channel: Channel) -> Result<String> {
+        let body = self
+            .fetch_github_text(self.core.latest_version_url(channel))
+            .await?;
+        let version = self.core.parse_latest_version(channel, &body)?;
+        info!("Latest version: {version}");
+        Ok(version)
+    }
+
+    /// Installs `version`, or the latest release in `channel`. Returns the installed version.
+    async fn download_mihomo(
+        &self,
+        no_verify: bool,
+        version: Option<&str>,
+        channel: Channel,
+    ) -> Result<String> {
+        let name = self.core.name();
+        info!("Downloading {name}...");
+
+        let version = match version {
+            Some(version) => version.to_string(),
+            None => self.latest_core_version(channel).await?,
+        };
+
+        let (asset_name, archive_type) =
+            self.core
+                .release_asset(&version, None, self.settings.core_arch.as_deref())?;
+        let archive_path = self
+            .proxy_data_dir
+            .join(format!("{}.{archive_type}", self.core.binary_name()));
+        let download_url = self
+            .download_core_asset(&version, &asset_name, &archive_path, no_verify)
+            .await?;
+
+        self.install_core_archive(&archive_path, archive_type, &version, &download_url)?;
+        fs::remove_file(&archive_path)?;
+        Ok(version)
+    }
+
+    /// Downloads the release asset of the core to `path`, verified against the
+    /// published checksums unless `no_verify`. Returns its URL.
+    async fn download_core_asset(
+        &self,
+        version: &str,
+        asset_name: &str,
+        path: &Path,
+        no_verify: bool,
+    ) -> Result<String> {
+        let name = self.core.name();
+        let download_url = format!(
+            "https://github.com/{}/releases/download/{}/{}",
+            self.core.repository(),
+            self.core.release_tag(version),
+            asset_name
+        );
+
+        let expected_checksum = match self.core.checksums_asset() {
+            _ if no_verify => {
+                warn!("Checksum verification is disabled (--no-verify)");
+                None
+            }
+            Some(checksums_asset) => Some(
+                self.fetch_checksum(version, checksums_asset, asset_name)
+                    .await?,
+            ),
+            None => {
+                warn!("{name} publishes no checksums, the download can't be verified");
+                None
+            }
+        };
+
+        self.download_from_github(&download_url, path)
+            .await
+            .with_context(|| format!("Failed to download {name} {version}"))?;
+
+        if let Some(expected) = expected_checksum {
+            if let Err(e) = verify_sha256(path, &expected) {
+                let _ = fs::remove_file(path);
+                return Err(e.context(format!("Refusing to install the downloaded {name} binary")));
+            }
+        }
+        Ok(download_url)
+    }
+
+    /// Installs the core from a release asset downloaded from `url`, keeping the
+    /// replaced binary for `rollback`.
+    fn install_core_archive(
+        &self,
+        archive_path: &Path,
+        archive_type: ArchiveType,
+        version: &str,
+        url: &str,
+    ) -> Result<()> {
+        // Decompress next to the binary, on the same filesystem, so it can be
+        // renamed into place and a failed extraction leaves the core untouched
+        let new_binary_path = self.core_path.with_extension("new");
+        match archive_type {
+            ArchiveType::Gz => decompress_gz(archive_path, &new_binary_path)?,
+            ArchiveType::Zip | ArchiveType::TarGz => {
+                let extract_dir = self.core_path.with_extension("extract");
+                if archive_type == ArchiveType::Zip {
+                    unzip_file(archive_path, &extract_dir)?;
+                } else {
+                    decompress_tar_gz(archive_path, &extract_dir, 0)?;
+                }
+                let binary = find_binary(&extract_dir, self.core.binary_name())?;
+                fs::rename(binary, &new_binary_path)?;
+                fs::remove_dir_all(&extract_dir)?;
+            }
+        };
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mut perms = fs::metadata(&new_binary_path)?.permissions();
+            perms.set_mode(0o755);
+            fs::set_permissions(&new_binary_path, perms)?;
+        }
+        let manifest_path = self.manifest_path();
+        let mut manifest = Manifest::load(&manifest_path)?;
+        // A running binary can't be replaced on Windows, only renamed, so it
+        // is moved aside first and the new one takes its name
+        let replacing = self.core_path.exists();
+        if replacing {
+            fs::rename(&self.core_path, self.backup_core_path()).with_context(|| {
+                format!(
+                    "Failed to move {} aside, stop {} and try again",
+                    self.core_path.display(),
+                    self.core.name()
+                )
+            })?;
+            manifest.previous_core_version = manifest.core_version.take();
+            manifest.previous_core = manifest.core.take();
+        }
+        if let Err(e) = fs::rename(&new_binary_path, &self.core_path) {
+            // Put the old binary back, the core must not go missing
+            if replacing {
+                let _ = fs::rename(self.backup_core_path(), &self.core_path);
+            }
+            return Err(e.into());
+        }
+
+        manifest.core_version = Some(version.to_string());
+        manifest.core = Some(Artifact::for_file(url, &self.core_path)?);
+        manifest.save(&manifest_path)?;
+        info!("{} {version} installed", self.core.name());
+        Ok(())
+    }
+
+    /// Replaces this executable with the latest proxy-rs release for this
+    /// platform, verified against its published SHA256. Returns the new version,
+    /// `None` if this one is already the latest.
+    pub async fn self_update(&self, no_verify: bool, force: bool) -> Result<Option<String>> {
+        let current = env!("CARGO_PKG_VERSION");
+        let body = self
+            .fetch_github_text(self_update::LATEST_RELEASE_URL)
+            .await?;
+        let release: self_update::Release =
+            serde_json::from_str(&body).context("Failed to parse the proxy-rs release")?;
+        if !force && !self_update::is_newer(&release.tag_name, current) {
+            info!("proxy-rs {current} is the latest version");
+            return Ok(None);
+        }
+        let asset = self_update::platform_asset(&release)?;
+        info!(
+            "Downloading proxy-rs {} ({})...",
+            release.tag_name, asset.name
+        );
+
+        let expected_checksum = match self_update::checksum_asset(&release, asset) {
+            _ if no_verify => {
+                warn!("Checksum verification is disabled (--no-verify)");
+                None
+            }
+            Some(checksums) => {
+                let text = self
+                    .fetch_github_text(&checksums.browser_download_url)
+                    .await
+                    .with_context(|| {
+                        format!(
+                            "Failed to download {}, use --no-verify to skip verification",
+                            checksums.name
+                        )
+                    })?;
+                Some(
+                    self_update::parse_checksum(&text, &asset.name).ok_or_else(|| {
+                        anyhow!("No checksum found for {} in {}", asset.name, checksums.name)
+                    })?,
+                )
+            }
+            None => {
+                warn!("The release publishes no checksums, the download can't be verified");
+                None
+            }
+        };
+
+        let download_path = self.proxy_data_dir.join(&asset.name);
+        self.download_from_github(&asset.browser_download_url, &download_path)
+            .await
+            .with_context(|| format!("Failed to download proxy-rs {}", release.tag_name))?;
+        if let Some(expected) = expected_checksum {
+            if let Err(e) = verify_sha256(&download_path, &expected) {
+                let _ = fs::remove_file(&download_path);
+                return Err(e.context("Refusing to install the downloaded proxy-rs binary"));
+            }
+        }
+
+        let extract_dir = self.proxy_data_dir.join("self-update");
+        let binary = match self_update::archive_type(&asset.name) {
+            None => download_path.clone(),
+            Some(ArchiveType::Gz) => {
+                let binary = self.proxy_data_dir.join("self-update.bin");
+                decompress_gz(&download_path, &binary)?;
+                binary
+            }
+            Some(archive_type) => {
+                if archive_type == ArchiveType::Zip {
+                    unzip_file(&download_path, &extract_dir)?;
+                } else {
+                    decompress_tar_gz(&download_path, &extract_dir, 0)?;
+                }
+                find_binary(&extract_dir, self_update::BINARY_NAME)?
+            }
+        };
+        let replaced = self_update::replace_current_exe(&binary);
+        for leftover in [&download_path, &binary] {
+            let _ = fs::remove_file(leftover);
+        }
+        if extract_dir.exists() {
+            fs::remove_dir_all(&extract_dir)?;
+        }
+        let exe = replaced?;
+        info!(
+            "Updated {} from {current} to {}",
+            exe.display(),
+            release.tag_name
+        );
+        Ok(Some(release.tag_name))
+    }
+
+    async fn fetch_checksum(
+        &self,
+        version: &str,
+        checksums_asset: &str,
+        asset_name: &str,
+    ) -> Result<String> {
+        let checksums_url = format!(
+            "https://github.com/{}/releases/download/{}/{}",
+            self.core.repository(),
+            self.core.release_tag(version),
+            checksums_asset
+        );
+        let checksums = self
+            .fetch_github_text(&checksums_url)
+            .await
+            .with_context(|| {
+                format!(
+                    "Failed to download {checksums_asset}, use --no-verify to skip verification"
+                )
+            })?;
+
+        checksums
+            .lines()
+            .filter_map(|line| line.split_once(char::is_whitespace))
+            .find(|(_, name)| name.trim().trim_start_matches('*') == asset_name)
+            .map(|(hash, _)| hash.to_string())
+            .ok_or_else(|| anyhow!("No checksum found for {asset_name} in {checksums_asset}"))
+    }
+
+    async fn download_ui_if_necessary(&self) -> Result<()> {
+        let ui = self.settings.ui;
+        if ui == WebUi::None {
+            return Ok(());
+        }
+        let manifest = Manifest::load(&self.manifest_path())?;
+        let integrity = manifest::integrity(
+            manifest.ui.get(ui.name()),
+            &self.proxy_data_dir.join(ui.name()),
+        );
+        if integrity != Integrity::Missing {
+            info!("{} already exists, skip downloading.", ui.name());
+            return Ok(());
+        }
+        self.download_ui(ui, false).await
+    }
+
+    /// Downloads the WebUI again, replacing the old copy once the new one is extracted.
+    pub async fn update_ui(&self) -> Result<()> {
+        let ui = self.settings.ui;
+        if ui == WebUi::None {
+            return Err(anyhow!("No WebUI is used, pick one with `start --ui`"));
+        }
+        self.download_ui(ui, true).await?;
+        info!("Updated {}, reload it in the browser", ui.name());
+        Ok(())
+    }
+
+    /// Downloads and installs `ui`, the newest archive rather than a cached copy if `fresh`.
+    async fn download_ui(&self, ui: WebUi, fresh: bool) -> Result<()> {
+        let Some((release_url, _)) = ui.release() else {
+            return Ok(());
+        };
+        info!("Downloading {}...", ui.name());
+        let zip_path = self.proxy_data_dir.join(format!("{}.zip", ui.name()));
+        if fresh {
+            self.download_fresh_from_github(release_url, &zip_path)
+                .await?;
+        } else {
+            self.download_from_github(release_url, &zip_path).await?;
+        }
+        let installed = self.install_ui_zip(ui, &zip_path);
+        fs::remove_file(&zip_path)?;
+        installed
+    }
+
+    /// Extracts the release zip of `ui` into a staging folder first, so a failed
+    /// download keeps the old copy and the controller never serves a half-extracted one.
+    fn install_ui_zip(&self, ui: WebUi, zip_path: &Path) -> Result<()> {
+        let Some((release_url, unzipped_name)) = ui.release() else {
+            return Ok(());
+        };
+        let ui_path = self.proxy_data_dir.join(ui.name());
+        let staging = self.proxy_data_dir.join(format!("{}.new", ui.name()));
+        let old = self.proxy_data_dir.join(format!("{}.old", ui.name()));
+        for leftover in [&staging, &old] {
+            if leftover.exists() {
+                fs::remove_dir_all(leftover)?;
+            }
+        }
+
+        let sha256 = sha256_file(zip_path)?;
+        unzip_file(zip_path, &staging)?;
+
+        // The unzipped folder is named after the branch or archive
+        let unzipped_folder = staging.join(unzipped_name);
+        if !unzipped_folder.is_dir() {
+            fs::remove_dir_all(&staging)?;
+            return Err(anyhow!("{release_url} has no {unzipped_name} folder"));
+        }
+        if ui_path.exists() {
+            fs::rename(&ui_path, &old)?;
+        }
+        fs::rename(unzipped_folder, &ui_path)?;
+        fs::remove_dir_all(&staging)?;
+        if old.exists() {
+            fs::remove_dir_all(&old)?;
+        }
+        self.update_manifest(|manifest| {
+            manifest
+                .ui
+                .insert(ui.name().to_string(), Artifact::new(release_url, sha256));
+        })?;
+
+        Ok(())
+    }
+
+    fn geodata_mode(&self) -> bool {
+        parse_geodata_mode(&self.config_path()).unwrap_or(false)
+    }
+
+    /// Downloads missing geodata, or with `auto-update-geodata` refreshes
+    /// files older than a day.
+    async fn download_geodata_if_necessary(&self) -> Result<()> {
+        // sing-box downloads the rule sets its config refers to by itself
+        if self.core.kind() != CoreKind::Mihomo {
+            return Ok(());
+        }
+        if self.settings.auto_update_geodata {
+            if let Err(e) = self.update_geodata(false).await {
+                warn!("{e}");
+            }
+            return Ok(());
+        }
+        let files = geodata_files(self.geodata_mode());
+        let downloads = files.iter().map(|(filename, asset)| async move {
+            match self.geodata_integrity(filename) {
+                Ok(Integrity::Missing) => {}
+                Ok(Integrity::Changed) => {
+                    warn!("{filename} differs from the downloaded file, run `proxy geo update --force` to download it again");
+                    return;
+                }
+                _ => return,
+            }
+            if let Err(e) = self.download_geofile(filename, asset, false).await {
+                warn!("Failed to download {filename}: {e}");
+            }
+        });
+        join_all(downloads).await;
+        Ok(())
+    }
+
+    /// [`Self::update_adblock_list`] on `start`, a failure only skips the update.
+    async fn download_adblock_list_if_necessary(&self) -> Result<()> {
+        if let Err(e) = self.update_adblock_list(false).await {
+            warn!("{e:#}");
+        }
+        Ok(())
+    }
+
+    /// Prints the countries `GEOIP` rules and the ASN `IP-ASN` rules see for
+    /// `target`, an IP or a domain resolved with the system resolver.
+    pub async fn geoip(&self, target: &str) -> Result<()> {
+        self.require_mihomo("geoip")?;
+        let ips: Vec<IpAddr> = match target.parse() {
+            Ok(ip) => vec![ip],
+            Err(_) => {
+                let mut ips: Vec<IpAddr> = tokio::net::lookup_host((target, 0))
+                    .await
+                    .with_context(|| format!("Failed to resolve {target}"))?
+                    .map(|address| address.ip())
+                    .collect();
+                ips.dedup();
+                info!("Resolved {target} with the system resolver, Mihomo's DNS may answer differently");
+                ips
+            }
+        };
+
+        let geodata_mode = self.geodata_mode();
+        // geosite comes first
+        let (country_file, _) = geodata_files(geodata_mode)[1];
+        let country_path = self.config_dir.join(country_file);
+        if !country_path.exists() {
+            return Err(anyhow!(
+                "{country_file} is not downloaded yet, run `proxy geo update`"
+            ));
+        }
+        let country_db = if geodata_mode {
+            None
+        } else {
+            Some(Mmdb::open(&country_path)?)
+        };
+        let asn_path = self.config_dir.join(ASN_FILE);
+        let asn_db = if asn_path.exists() {
+            Some(Mmdb::open(&asn_path)?)
+        } else {
+            None
+        };
+
+        for ip in ips {
+            let mut countries = match &country_db {
+                Some(db) => db
+                    .lookup(ip)?
+                    .map(|record| geoip::country_codes(&record))
+                    .unwrap_or_default(),
+                None => geoip::dat_country_codes(&country_path, ip)?,
+            };
+            if geoip::is_lan(ip) {
+                countries.insert(0, "LAN".to_string());
+            }
+            let countries = if countries.is_empty() {
+                "no country".to_string()
+            } else {
+                countries.join(", ")
+            };
+            println!("{}", ip.to_string().bold());
+            println!("  GEOIP   {countries} ({country_file})");
+            let asn = match &asn_db {
+                Some(db) => match db.lookup(ip)?.as_ref().and_then(geoip::asn) {
+                    Some((number, organization)) => {
+                        format!("AS{number} {organization} ({ASN_FILE})")
+                    }
+                    None => format!("no ASN ({ASN_FILE})"),
+                },
+                None => format!("{ASN_FILE} is not downloaded, Mihomo fetches it for IP-ASN rules"),
+            };
+            println!("  IP-ASN  {asn}");
+        }
+        Ok(())
+    }
+
+    /// Re-downloads the geodata files used by the current `geodata-mode`.
+    /// Without `force`, files refreshed within the last day are skipped.
+    pub async fn update_geodata(&self, force: bool) -> Result<()> {
+        self.require_mihomo("Geodata")?;
+        let files = geodata_files(self.geodata_mode());
+        let downloads = files.iter().map(|(filename, asset)| async move {
+            let intact = !matches!(
+                self.geodata_integrity(filename)?,
+                Integrity::Missing | Integrity::Changed
+            );
+            let age = self
+                .geodata_downloaded_at(filename)?
+                .and_then(|time| jiff::Timestamp::now().duration_since(time).try_into().ok());
+            if !force && intact && age.is_some_and(|age: Duration| age < GEODATA_MAX_AGE) {
+                info!("{filename} is up to date, use --force to download it anyway");
+                return Ok(false);
+            }
+            self.download_geofile(filename, asset, force)
+                .await
+                .with_context(|| format!("Failed to update {filename}"))
+                .map(|_| true)
+        });
+
+        let mut updated = false;
+        let mut failed = false;
+        for result in join_all(downloads).await {
+            match result {
+                Ok(true) => updated = true,
+                Ok(false) => {}
+                Err(e) => {
+                    error!("{e:#}");
+                    failed = true;
+                }
+            }
+        }
+        if updated && self.is_running()?.is_some() {
+            info!("Restart Mihomo to load the new geodata");
+        }
+        if failed {
+            return Err(anyhow!("Some geodata files failed to update"));
+        }
+        Ok(())
+    }
+
+    /// Downloads a geodata file, the newest one rather than a cached copy if `fresh`.
+    async fn download_geofile(&self, filename: &str, asset: &str, fresh: bool) -> Result<()> {
+        info!("Downloading {filename}...");
+        let url = geodata_url(asset);
+        let path = self.config_dir.join(filename);
+        if fresh {
+            self.download_fresh_from_github(&url, &path).await?;
+        } else {
+            self.download_from_github(&url, &path).await?;
+        }
+        let artifact = Artifact::for_file(&url, &path)?;
+        self.update_manifest(|manifest| {
+            manifest
+                .geodata
+                .insert(self.geodata_key(filename), artifact);
+        })
+    }
+
+    /// Fetches a small text file from GitHub, through the running Mihomo if
+    /// `via-proxy` allows it, otherwise through the fastest mirror, switching
+    /// to the next fastest one if it fails.
+    async fn fetch_github_text(&self, github_url: &str) -> Result<String> {
+        if let Some(client) = self.local_proxy_client()? {
+            match fetch_text(&client, github_url).await {
+                Ok(text) => return Ok(text),
+                Err(e) if self.settings.via_proxy == ViaProxy::Always => return Err(e),
+                Err(e) => warn!(
+                    "Fetching through the running Mihomo failed: {e}, trying the GitHub mirrors"
+                ),
+            }
+        }
+        let mut proxy = self.github_mirror().await?;
+        loop {
+            match fetch_text(&self.client, &format!("{proxy}{github_url}")).await {
+                Ok(text) => return Ok(text),
+                Err(e) => {
+                    // A mirror saved by an earlier run may have gone down since
+                    warn!(
+                        "Fetching through {} failed: {e}",
+                        proxy_display_name(&proxy)
+                    );
+                    proxy = fallback_github_proxy(
+                        &self.client,
+                        &self.github_mirrors,
+                        &proxy,
+                        &self.mirror_cache,
+                    )
+                    .await
+                    .with_context(|| format!("Failed to fetch {github_url}"))?;
+                }
+            }
+        }
+    }
+
+    /// Client that goes through this instance's mixed-port when it is running
+    /// and `via-proxy` allows it, `None` if downloads should not use it.
+    fn local_proxy_client(&self) -> Result<Option<Client>> {
+        if self.settings.via_proxy == ViaProxy::Never {
+            return Ok(None);
+        }
+        let port = match self.is_running()? {
+            Some(_) => self.core.mixed_port(&self.config_path()),
+            None => None,
+        };
+        match port {
+            Some(port) => {
+                debug!("Downloading through the running Mihomo on port {port}");
+                let proxy = reqwest::Proxy::all(format!("http://127.0.0.1:{port}"))?;
+                Ok(Some(Client::builder().proxy(proxy).build()?))
+            }
+            None if self.settings.via_proxy == ViaProxy::Always => Err(anyhow!(
+                "Mihomo is not running, can't download through it (--via-proxy)"
+            )),
+            None => Ok(None),
+        }
+    }
+
+    /// The fastest GitHub mirror, probed once and timed as a startup phase.
+    async fn github_mirror(&self) -> Result<String> {
+        let started = Instant::now();
+        let proxy =
+            select_fastest_github_proxy(&self.client, &self.github_mirrors, &self.mirror_cache)
+                .await?;
+        self.startup.record_once("mirror selection", started);
+        Ok(proxy)
+    }
+
+    /// [`Self::try_download_from_github`] unless the shared cache has the file,
+    /// timed as a startup phase named after it.
+    async fn download_from_github(&self, github_url: &str, path: &Path) -> Result<()> {
+        self.download_from_github_with(github_url, path, true).await
+    }
+
+    /// Like [`Self::download_from_github`], skipping the shared cache for the
+    /// newest file, e.g. on `geo update --force`. The download is still added to it.
+    async fn download_fresh_from_github(&self, github_url: &str, path: &Path) -> Result<()> {
+        self.download_from_github_with(github_url, path, false)
+            .await
+    }
+
+    async fn download_from_github_with(
+        &self,
+        github_url: &str,
+        path: &Path,
+        cached: bool,
+    ) -> Result<()> {
+        // Files in the config dir are copied, the core may rewrite them in place
+        let link = !path.starts_with(&self.config_dir);
+        if let Some(cache) = self.shared_cache.as_ref().filter(|_| cached) {
+            match cache.restore(github_url, path, link) {
+                Ok(true) => return Ok(()),
+                Ok(false) => {}
+                Err(e) => warn!("Failed to use the shared cache: {e}"),
+            }
+        }
+        let name = github_url.rsplit('/').next().unwrap_or(github_url);
+        self.startup
+            .time(
+                &format!("download {name}"),
+                self.try_download_from_github(github_url, path),
+            )
+            .await?;
+        if let Some(cache) = &self.shared_cache {
+            if let Err(e) = cache.store(github_url, path) {
+                warn!("Failed to add {name} to the shared cache: {e}");
+            }
+        }
+        Ok(())
+    }
+
+    /// Downloads a GitHub URL through the running Mihomo or the fastest mirror,
+    /// retrying with backoff and switching to the next fastest mirror when it keeps failing.
+    async fn try_download_from_github(&self, github_url: &str, path: &Path) -> Result<()> {
+        let attempts = self
+            .settings
+            .download_retries
+            .unwrap_or(DEFAULT_DOWNLOAD_RETRIES)
+            .max(1);
+        if let Some(client) = self.local_proxy_client()? {
+            match download_with_retries(&client, github_url, path, attempts).await {
+                Ok(()) => return Ok(()),
+                Err(e) if self.settings.via_proxy == ViaProxy::Always => return Err(e),
+                Err(e) => warn!(
+                    "Downloading through the running Mihomo failed: {e}, trying the GitHub mirrors"
+                ),
+            }
+        }
+        let mut proxy = self.github_mirror().await?;
+        loop {
+            let url = format!("{proxy}{github_url}");
+            match download_with_retries(&self.client, &url, path, attempts).await {
+                Ok(()) => return Ok(()),
+                Err(e) => {
+                    warn!(
+                        "Downloading through {} failed: {e}",
+                        proxy_display_name(&proxy)
+                    );
+                    proxy = fallback_github_proxy(
+                        &self.client,
+                        &self.github_mirrors,
+                        &proxy,
+                        &self.mirror_cache,
+                    )
+                    .await
+                    .with_context(|| format!("Failed to download {github_url}"))?;
+                }
+            }
+        }
+    }
+
+    /// Writes `on`/`off` scripts for the shells of this platform, see [`Shell::platform_defaults`],
+    /// or `on-<name>`/`off-<name>` for a named instance.
+    fn write_env_setup_script(&self, mixed_port: u16) -> Result<()> {
+        let (http_url, all_url) = self.proxy_urls(mixed_port);
+        let no_proxy = self.no_proxy();
+        // Named instances get theirs next to the default ones, e.g. `on-work`
+        let (on, off) = match &self.instance {
+            Some(name) => (format!("on-{name}"), format!("off-{name}")),
+            None => ("on".to_string(), "off".to_string()),
+        };
+        for shell in Shell::platform_defaults() {
+            let on_script_path = self.proxy_data_dir.join(shell.script_name(&on));
+            let off_script_path = self.proxy_data_dir.join(shell.script_name(&off));
+            fs::write(
+                &on_script_path,
+                shell.on_script(&http_url, &all_url, &no_proxy),
+            )?;
+            fs::write(&off_script_path, shell.off_script())?;
+
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::PermissionsExt;
+                fs::set_permissions(&on_script_path, fs::Permissions::from_mode(0o755))?;
+                fs::set_permissions(&off_script_path, fs::Permissions::from_mode(0o755))?;
+            }
+
+            let on_script_path = on_script_path.display().to_string();
+            let off_script_path = off_script_path.display().to_string();
+            info!(
+                "Run `{}` to set up the proxy environment, and `{}` to unset it.",
+                shell.source_command(&on_script_path),
+                shell.source_command(&off_script_path)
+            );
+        }
+
+        Ok(())
+    }
+}
+
+/// The profile called `name`, or the one the client is using.
+fn pick_profile(profiles: Vec<Profile>, name: Option<&str>) -> Result<Profile> {
+    if profiles.is_empty() {
+        return Err(anyhow!("No profiles found"));
+    }
+    let found = match name {
+        Some(name) => profiles.into_iter().find(|profile| profile.name == name),
+        None if profiles.len() == 1 => profiles.into_iter().next(),
+        None => profiles.into_iter().find(|profile| profile.current),
+    };
+    found.ok_or_else(|| match name {
+        Some(name) => anyhow!("No profile named {name}"),
+        None => anyhow!("No profile is in use, pick one with --profile"),
+    })
+}
+
+/// Asks `prompt` unless `force` is set. `--yes` alone answers no, deleting
+/// data unattended needs `--force`.
+fn confirm_destructive(prompt: &str, force: bool) -> bool {
+    if force || ask_for_confirmation(prompt, false) {
+        return true;
+    }
+    if assume_yes() {
+        info!("Aborted, pass --force to run it without asking");
+    } else {
+        info!("Aborted");
+    }
+    false
+}
+
+fn add_running_status(fields: &mut Fields, status: &RunningStatus) {
+    fields.add("Uptime", format_duration(status.uptime));
+    fields.add("Memory", format_bytes(status.memory));
+    fields.add("CPU", format!("{:.1}%", status.cpu_usage));
+    if let Some(version) = &status.version {
+        fields.add("Running version", version.as_str());
+    }
+    if let Some(path) = &status.core_path {
+        fields.add("Running binary", path.display().to_string());
+    }
+    if let Some(port) = status.mixed_port {
+        fields.add("Mixed port", port.to_string());
+    }
+    if let Some(controller) = &status.controller {
+        fields.add("Controller", controller.as_str());
+    }
+    if let Some(webui) = &status.webui {
+        fields.add("Web UI", webui.as_str());
+    }
+    if let Some(mode) = &status.mode {
+        fields.add("Mode", mode.as_str());
+    }
+    for (i, (group, node)) in status.selected.iter().enumerate() {
+        let label = if i == 0 { "Selected" } else { "" };
+        fields.add(label, format!("{group}: {node}"));
+    }
+}
+
+/// Asks `process` to exit, with SIGTERM or `taskkill` without `/F` on Windows,
+/// and kills it if it is still running after `grace`.
+fn terminate(process: &sysinfo::Process, grace: Duration) -> Result<()> {
+    let pid = process.pid();
+    #[cfg(windows)]
+    let asked = Command::new("taskkill")
+        .args(["/PID", &pid.to_string()])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .is_ok_and(|status| status.success());
+    #[cfg(not(windows))]
+    let asked = process.kill_with(sysinfo::Signal::Term).unwrap_or(false);
+
+    let mut system = sysinfo::System::new();
+    let mut exited = || {
+        system.refresh_processes(sysinfo::ProcessesToUpdate::Some(&[pid]), true);
+        system
+            .process(pid)
+            .is_none_or(|p| p.status() == sysinfo::ProcessStatus::Zombie)
+    };
+    if asked {
+        let deadline = Instant::now() + grace;
+        while Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(100));
+            if exited() {
+                return Ok(());
+            }
+        }
+        warn!("Process {pid} is still running after {grace:?}, killing it");
+    } else if exited() {
+        // It exited on its own in the meantime
+        return Ok(());
+    }
+    process
+        .kill_and_wait()
+        .map_err(|e| anyhow!("Failed to stop: {e:?}"))?;
+    Ok(())
+}
+
+/// A binary in an extracted release archive: the file named `name`, or the
+/// only file if the archive has just one, like Mihomo's Windows zip.
+fn find_binary(dir: &Path, name: &str) -> Result<PathBuf> {
+    let binary_name = format!("{name}{}", std::env::consts::EXE_SUFFIX);
+    let mut files = Vec::new();
+    let mut dirs = vec![dir.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                dirs.push(path);
+            } else if path
+                .file_name()
+                .is_some_and(|name| name == binary_name.as_str())
+            {
+                return Ok(path);
+            } else {
+                files.push(path);
+            }
+        }
+    }
+    match <[PathBuf; 1]>::try_from(files) {
+        Ok([file]) => Ok(file),
+        Err(_) => Err(anyhow!("{binary_name} not found in the downloaded archive")),
+    }
+}
+
+/// The core recorded by the last start, if it has a pid file, so commands
+/// without `--core` talk to the core that is actually running.
+/// Mixed and controller ports in the config of the instance in `instance_dir`,
+/// read with the core it runs, or `default_core` while it is stopped.
+fn instance_ports(instance_dir: &Path, default_core: CoreKind) -> (Option<u16>, Option<u16>) {
+    let core = running_core(instance_dir).unwrap_or(default_core).backend();
+    let config_path = instance_dir.join("config").join(core.config_file());
+    if !config_path.exists() {
+        return (None, None);
+    }
+    let controller_port = core
+        .external_controller(&config_path)
+        .and_then(|address| address.rsplit(':').next()?.parse().ok());
+    (core.mixed_port(&config_path), controller_port)
+}
+
+/// Warns that `preferred` went to another instance or is in use when the port
+/// search moved on to `picked`.
+fn warn_port_moved(preferred: u16, picked: u16, flag: &str, reserved: &[(u16, String)]) {
+    match reserved.iter().find(|(port, _)| *port == preferred) {
+        Some((_, name)) if preferred != picked => warn!(
+            "Port {preferred} belongs to the {name} instance, using {picked} instead. Pass {flag} to choose another port"
+        ),
+        _ => warn_port_taken(preferred, picked, flag),
+    }
+}
+
+fn running_core(instance_dir: &Path) -> Option<CoreKind> {
+    if !instance_dir.join(MIHOMO_PID_FILE).exists() {
+        return None;
+    }
+    let name = fs::read_to_string(instance_dir.join(CORE_FILE)).ok()?;
+    CoreKind::from_str(name.trim(), true).ok()
+}
+
+/// Files of an instance a backup holds, relative to its directory: the
+/// configs of every core, the override file and the subscription's quota and URL.
+fn instance_backup_files() -> Vec<PathBuf> {
+    let config_dir = Path::new("config");
+    let mut files: Vec<PathBuf> = CoreKind::value_variants()
+        .iter()
+        .map(|kind| config_dir.join(kind.backend().config_file()))
+        .collect();
+    files.push(config_dir.join(OVERRIDES_FILE));
+    files.push(PathBuf::from(SUBSCRIPTION_FILE));
+    files.push(PathBuf::from(SUBSCRIPTION_URL_FILE));
+    files
+}
+
+/// Files [`MihomoManager::backup`] and `sync` cover in `dir`, relative to it:
+/// proxy-rs.toml and the [`instance_backup_files`] of every instance.
+fn config_files(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut files = vec![PathBuf::from(SETTINGS_FILE)];
+    files.extend(instance_backup_files());
+    for name in MihomoManager::instances(dir)? {
+        let instance_dir = Path::new(INSTANCES_DIR).join(name);
+        files.extend(
+            instance_backup_files()
+                .iter()
+                .map(|file| instance_dir.join(file)),
+        );
+    }
+    Ok(files)
+}
+
+/// Whether `relative` is a file [`MihomoManager::backup`] writes, so a
+/// restore never writes anywhere else.
+fn is_backup_file(relative: &Path) -> bool {
+    if relative == Path::new(SETTINGS_FILE) {
+        return true;
+    }
+    let components: Vec<_> = relative.components().collect();
+    if !components
+        .iter()
+        .all(|c| matches!(c, std::path::Component::Normal(_)))
+    {
+        return false;
+    }
+    let in_instance: PathBuf = match components.first() {
+        Some(first) if first.as_os_str() == INSTANCES_DIR => components.iter().skip(2).collect(),
+        _ => relative.to_path_buf(),
+    };
+    instance_backup_files().contains(&in_instance)
+}
+
+/// `file_name` at the top of `dir` or in one of its directories, where archives
+/// such as frp's keep their executables.
+fn find_extracted(dir: &Path, file_name: &OsStr) -> Option<PathBuf> {
+    let top = dir.join(file_name);
+    if top.is_file() {
+        return Some(top);
+    }
+    fs::read_dir(dir)
+        .ok()?
+        .flatten()
+        .map(|entry| entry.path().join(file_name))
+        .find(|path| path.is_file())
+}
+
+/// `<data dir>/<binary>`, shared by every instance.
+fn core_binary_path(proxy_data_dir: &Path, core: &dyn CoreBackend) -> PathBuf {
+    proxy_data_dir.join(format!(
+        "{}{}",
+        core.binary_name(),
+        std::env::consts::EXE_SUFFIX
+    ))
+}
+
+/// WebUI address that logs in to the controller at `address` with `secret`.
+fn webui_url(address: SocketAddr, secret: &str, https: bool) -> String {
+    let (scheme, https_param) = if https {
+        ("https", "&https=true")
+    } else {
+        ("http", "")
+    };
+    format!(
+        "{scheme}://{address}/ui/#/setup?hostname={}&port={}&secret={secret}{https_param}",
+        address.ip(),
+        address.port()
+    )
+}
+
+/// `address` with an unspecified IP (0.0.0.0 or ::) replaced by loopback, for
+/// connecting to a controller that listens on all interfaces.
+/// (host, port) of a `which` target: `host`, `host:port`, `[ipv6]:port`, an
+/// IPv6 address or a URL. The port is 443 unless given.
+fn parse_target(target: &str) -> Result<(String, u16)> {
+    if target.contains("://") {
+        let url = reqwest::Url::parse(target).with_context(|| format!("Invalid URL: {target}"))?;
+        let host = url
+            .host_str()
+            .with_context(|| format!("No host in {target}"))?
+            .trim_matches(['[', ']'])
+            .to_string();
+        return Ok((host, url.port_or_known_default().unwrap_or(443)));
+    }
+    if let Ok(ip) = target.parse::<IpAddr>() {
+        return Ok((ip.to_string(), 443));
+    }
+    if let Ok(address) = target.parse::<SocketAddr>() {
+        return Ok((address.ip().to_string(), address.port()));
+    }
+    match target.rsplit_once(':') {
+        Some((host, port)) => {
+            let port = port
+                .parse()
+                .map_err(|_| anyhow!("Invalid port in {target}"))?;
+            Ok((host.to_string(), port))
+        }
+        None => Ok((target.to_string(), 443)),
+    }
+}
+
+fn local_address(address: SocketAddr) -> SocketAddr {
+    match address.ip() {
+        IpAddr::V4(ip) if ip.is_unspecified() => {
+            SocketAddr::new(Ipv4Addr::LOCALHOST.into(), address.port())
+        }
+        IpAddr::V6(ip) if ip.is_unspecified() => {
+            SocketAddr::new(Ipv6Addr::LOCALHOST.into(), address.port())
+        }
+        _ => address,
+    }
+}
+
+async fn fetch_text(client: &Client, url: &str) -> Result<String> {
+    Ok(client
+        .get(url)
+        .send()
+        .await?
+        .error_for_status()?
+        .text()
+        .await?)
 }
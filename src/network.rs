@@ -0,0 +1,182 @@
+//! Notices when the machine wakes from sleep or moves to another network, so the
+//! watchdog can drop the core's connections, which otherwise hang on the old
+//! network until they time out.
+
+use serde::Deserialize;
+use std::collections::BTreeSet;
+use std::fmt;
+use std::net::IpAddr;
+use std::time::{Duration, Instant, SystemTime};
+
+/// How far the wall clock may run ahead of the monotonic clock between two
+/// checks before it counts as a wake from sleep, which only the former includes.
+const RESUME_THRESHOLD: Duration = Duration::from_secs(30);
+
+/// What the watchdog does when the network changes.
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum NetworkAction {
+    /// Leave the core alone
+    Off,
+    /// Close every connection through the controller, clients reconnect
+    #[default]
+    Flush,
+    /// Restart the core, for when flushing isn't enough, e.g. with TUN
+    Restart,
+}
+
+impl fmt::Display for NetworkAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            NetworkAction::Off => "off",
+            NetworkAction::Flush => "flush",
+            NetworkAction::Restart => "restart",
+        })
+    }
+}
+
+/// `[network-change]` in proxy-rs.toml: what a watchdog started with
+/// `start --watch` does after a wake from sleep or a network switch, e.g.
+/// `action = "flush"` and `macos = "restart"`.
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct NetworkChangeSettings {
+    /// Action on every platform without its own, `flush` by default
+    pub action: Option<NetworkAction>,
+    pub linux: Option<NetworkAction>,
+    pub macos: Option<NetworkAction>,
+    pub windows: Option<NetworkAction>,
+    /// Seconds between checks of the network interfaces, 5 by default
+    pub interval: Option<u64>,
+}
+
+impl NetworkChangeSettings {
+    /// The action for the platform proxy-rs runs on.
+    pub fn action(&self) -> NetworkAction {
+        let platform = if cfg!(target_os = "linux") {
+            self.linux
+        } else if cfg!(target_os = "macos") {
+            self.macos
+        } else if cfg!(windows) {
+            self.windows
+        } else {
+            None
+        };
+        platform.or(self.action).unwrap_or_default()
+    }
+
+    pub fn interval(&self) -> Duration {
+        Duration::from_secs(self.interval.unwrap_or(5).max(1))
+    }
+}
+
+/// Why the network is considered changed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NetworkChange {
+    /// The machine slept for about this long
+    Resumed(Duration),
+    /// Addresses appeared on or left the interfaces
+    Addresses {
+        added: Vec<String>,
+        removed: Vec<String>,
+    },
+}
+
+impl fmt::Display for NetworkChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NetworkChange::Resumed(slept) => {
+                write!(f, "Woke up after sleeping for {}s", slept.as_secs())
+            }
+            NetworkChange::Addresses { added, removed } => {
+                let mut parts = Vec::new();
+                if !added.is_empty() {
+                    parts.push(format!("added {}", added.join(", ")));
+                }
+                if !removed.is_empty() {
+                    parts.push(format!("removed {}", removed.join(", ")));
+                }
+                write!(f, "Network addresses changed ({})", parts.join("; "))
+            }
+        }
+    }
+}
+
+/// Polls the addresses of the network interfaces and the clocks. Polling
+/// works the same on every platform, where netlink, SystemConfiguration and
+/// Windows' notifications would each need their own code.
+pub struct NetworkMonitor {
+    addresses: BTreeSet<(String, IpAddr)>,
+    checked_at: (Instant, SystemTime),
+}
+
+impl Default for NetworkMonitor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl NetworkMonitor {
+    pub fn new() -> Self {
+        Self {
+            addresses: addresses(),
+            checked_at: (Instant::now(), SystemTime::now()),
+        }
+    }
+
+    /// What changed since the last check or [`Self::reset`], if anything.
+    pub fn check(&mut self) -> Option<NetworkChange> {
+        let (instant, wall) = self.checked_at;
+        self.checked_at = (Instant::now(), SystemTime::now());
+        let monotonic = instant.elapsed();
+        let slept = wall.elapsed().unwrap_or_default().saturating_sub(monotonic);
+
+        let current = addresses();
+        let added: Vec<String> = current
+            .difference(&self.addresses)
+            .map(|(name, ip)| format!("{ip} on {name}"))
+            .collect();
+        let removed: Vec<String> = self
+            .addresses
+            .difference(&current)
+            .map(|(name, ip)| format!("{ip} on {name}"))
+            .collect();
+        self.addresses = current;
+
+        if slept >= RESUME_THRESHOLD {
+            Some(NetworkChange::Resumed(slept))
+        } else if !added.is_empty() || !removed.is_empty() {
+            Some(NetworkChange::Addresses { added, removed })
+        } else {
+            None
+        }
+    }
+
+    /// Takes the current addresses as the baseline, e.g. after restarting a
+    /// core whose TUN interface came and went.
+    pub fn reset(&mut self) {
+        *self = Self::new();
+    }
+}
+
+/// Addresses that route traffic off the machine. IPv6 is left out, temporary
+/// addresses rotate on their own every few hours, and so is 198.18.0.0/15,
+/// which the TUN interfaces of Mihomo and sing-box use by default.
+fn addresses() -> BTreeSet<(String, IpAddr)> {
+    sysinfo::Networks::new_with_refreshed_list()
+        .iter()
+        .flat_map(|(name, data)| {
+            data.ip_networks()
+                .iter()
+                .map(move |network| (name.clone(), network.addr))
+        })
+        .filter(|(_, ip)| match ip {
+            IpAddr::V4(ip) => {
+                let [a, b, ..] = ip.octets();
+                let tun = a == 198 && b & 0xfe == 18;
+                !(ip.is_loopback() || ip.is_link_local() || ip.is_unspecified() || tun)
+            }
+            IpAddr::V6(_) => false,
+        })
+        .collect()
+}
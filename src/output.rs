@@ -0,0 +1,193 @@
+//! Formatting shared by the commands that print results on stdout: aligned
+//! tables and fields, byte counts and durations, and whether to color them.
+//!
+//! Results go to stdout and logs to stderr, so `proxy test | sort` gets only
+//! the table, uncolored unless `--color always` is passed.
+
+use clap::ValueEnum;
+use colored::{ColoredString, Colorize};
+use std::time::Duration;
+
+/// `--color`: when stdout and the logs are colored.
+#[derive(ValueEnum, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ColorChoice {
+    /// Only on a terminal, unless `NO_COLOR` is set
+    #[default]
+    Auto,
+    Never,
+    Always,
+}
+
+/// Applies `--color` to everything colored with [`colored`].
+pub fn set_color(choice: ColorChoice) {
+    match choice {
+        ColorChoice::Auto => colored::control::unset_override(),
+        ColorChoice::Never => colored::control::set_override(false),
+        ColorChoice::Always => colored::control::set_override(true),
+    }
+}
+
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KB", "MB", "GB", "TB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{bytes} B")
+    } else {
+        format!("{value:.1} {}", UNITS[unit])
+    }
+}
+
+/// `1d 2h 3m`, `2h 3m` or `3m 4s`, whichever fits.
+pub fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    let (days, hours, minutes) = (secs / 86400, secs / 3600 % 24, secs / 60 % 60);
+    if days > 0 {
+        format!("{days}d {hours}h {minutes}m")
+    } else if hours > 0 {
+        format!("{hours}h {minutes}m")
+    } else {
+        format!("{minutes}m {}s", secs % 60)
+    }
+}
+
+/// `text` padded to `width` characters. Padding a [`ColoredString`] with
+/// `format!` would count its escape codes.
+fn pad(text: &ColoredString, width: usize, right: bool) -> String {
+    let padding = " ".repeat(width.saturating_sub(text.chars().count()));
+    if right {
+        format!("{padding}{text}")
+    } else {
+        format!("{text}{padding}")
+    }
+}
+
+/// Columns separated by two spaces under a bold header, each as wide as its
+/// widest cell.
+#[derive(Debug, Clone)]
+pub struct Table {
+    header: Vec<String>,
+    right: Vec<bool>,
+    rows: Vec<Vec<ColoredString>>,
+}
+
+impl Table {
+    pub fn new<S: Into<String>>(header: impl IntoIterator<Item = S>) -> Self {
+        let header: Vec<String> = header.into_iter().map(Into::into).collect();
+        Self {
+            right: vec![false; header.len()],
+            header,
+            rows: Vec::new(),
+        }
+    }
+
+    /// Aligns the columns at `columns` to the right, for numbers.
+    pub fn align_right(mut self, columns: impl IntoIterator<Item = usize>) -> Self {
+        for column in columns {
+            self.right[column] = true;
+        }
+        self
+    }
+
+    /// Adds a row, cells past the header are dropped and missing ones left empty.
+    pub fn row(&mut self, cells: impl IntoIterator<Item = ColoredString>) {
+        let mut cells: Vec<ColoredString> = cells.into_iter().take(self.header.len()).collect();
+        cells.resize(self.header.len(), ColoredString::default());
+        self.rows.push(cells);
+    }
+
+    pub fn len(&self) -> usize {
+        self.rows.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rows.is_empty()
+    }
+
+    pub fn print(&self) {
+        print!("{self}");
+    }
+}
+
+impl std::fmt::Display for Table {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let widths: Vec<usize> = (0..self.header.len())
+            .map(|i| {
+                self.rows
+                    .iter()
+                    .map(|row| row[i].chars().count())
+                    .chain(std::iter::once(self.header[i].chars().count()))
+                    .max()
+                    .unwrap_or(0)
+            })
+            .collect();
+        let line = |cells: &[ColoredString]| {
+            let last = cells.len().saturating_sub(1);
+            cells
+                .iter()
+                .enumerate()
+                .map(|(i, cell)| {
+                    // No trailing spaces after a left-aligned last column
+                    let width = if i == last && !self.right[i] {
+                        0
+                    } else {
+                        widths[i]
+                    };
+                    pad(cell, width, self.right[i])
+                })
+                .collect::<Vec<_>>()
+                .join("  ")
+        };
+        let header: Vec<ColoredString> = self.header.iter().map(|h| h.as_str().bold()).collect();
+        writeln!(f, "{}", line(&header))?;
+        for row in &self.rows {
+            writeln!(f, "{}", line(row))?;
+        }
+        Ok(())
+    }
+}
+
+/// `Label: value` lines with the values aligned, for details of one thing.
+#[derive(Debug, Clone, Default)]
+pub struct Fields {
+    fields: Vec<(String, ColoredString)>,
+}
+
+impl Fields {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, label: impl Into<String>, value: impl Into<ColoredString>) {
+        self.fields.push((label.into(), value.into()));
+    }
+
+    pub fn print(&self) {
+        print!("{self}");
+    }
+}
+
+impl std::fmt::Display for Fields {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let width = self
+            .fields
+            .iter()
+            .map(|(label, _)| label.chars().count() + 1)
+            .max()
+            .unwrap_or(0);
+        for (label, value) in &self.fields {
+            // An empty label continues the value above, e.g. one line per group
+            let label = if label.is_empty() {
+                String::new()
+            } else {
+                format!("{label}:")
+            };
+            writeln!(f, "{label:<width$}  {value}")?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,864 @@
+use crate::controller::{Mode, DEFAULT_DELAY_TEST_URL};
+use anyhow::{anyhow, Context, Result};
+use log::*;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_yaml::{Mapping, Value};
+use std::fs;
+use std::path::Path;
+
+/// Kept next to config.yaml, applied again whenever the subscription is downloaded.
+pub const OVERRIDES_FILE: &str = "override.yaml";
+
+/// Name of the group `chain` creates.
+pub const RELAY_GROUP: &str = "Relay";
+
+/// Rule provider `adblock on` adds, kept next to config.yaml.
+pub const ADBLOCK_PROVIDER: &str = "anti-ad";
+pub const ADBLOCK_FILE: &str = "anti-ad.yaml";
+/// [anti-AD](https://github.com/privacy-protection-tools/anti-AD)'s domain list
+/// in the `payload:` format of Clash rule providers.
+pub const ADBLOCK_URL: &str =
+    "https://raw.githubusercontent.com/privacy-protection-tools/anti-AD/master/anti-ad-clash.yaml";
+const ADBLOCK_RULE: &str = "RULE-SET,anti-ad,REJECT";
+
+/// User changes to the config that survive subscription refreshes.
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct Overrides {
+    /// Rules inserted before the subscription's rules, e.g. `DOMAIN-SUFFIX,example.com,DIRECT`
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub prepend_rules: Vec<String>,
+    /// Replaces the subscription's `dns:` section
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dns: Option<DnsOverride>,
+    /// Sets `ipv6` and `dns.ipv6`, off for hosts whose IPv6 is broken
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ipv6: Option<bool>,
+    /// Sets `interface-name`, the network interface outbound traffic leaves through
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub interface_name: Option<String>,
+    /// Turns TUN mode on or off, for sing-box configs too
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tun: Option<bool>,
+    /// Sets `mode`, chosen with the `mode` command
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mode: Option<Mode>,
+    /// Sets `allow-lan`, and binds on all interfaces when it is on, see `lan`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub allow_lan: Option<bool>,
+    /// Keeps only the nodes whose name matches this regex
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub include: Option<String>,
+    /// Drops the nodes whose name matches this regex
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exclude: Option<String>,
+    /// Adds a `relay` group chaining two nodes
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub relay: Option<Relay>,
+    /// Presets whose DIRECT rules go after `prepend_rules`, ahead of the subscription's
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub bypass: Vec<Bypass>,
+    /// Rejects the domains of the anti-AD list, after `prepend_rules`
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub adblock: bool,
+    /// Groups added by `group add`, replacing subscription groups of the same name
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub groups: Vec<CustomGroup>,
+    /// Subscription groups dropped by `group remove`
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub remove_groups: Vec<String>,
+}
+
+/// Traffic sent DIRECT by `start --bypass`, so local sites skip the proxy.
+#[derive(Serialize, Deserialize, clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Bypass {
+    /// Private, loopback and link-local addresses
+    Lan,
+    /// Mainland China sites and IPs, and LAN
+    Cn,
+    /// Iranian sites and IPs, and LAN
+    Ir,
+    /// Russian sites and IPs, and LAN
+    Ru,
+    /// Remove the bypass rules
+    None,
+}
+
+/// Reserved ranges, matched without a DNS lookup.
+const LAN_RULES: &[&str] = &[
+    "DOMAIN-SUFFIX,local,DIRECT",
+    "IP-CIDR,127.0.0.0/8,DIRECT,no-resolve",
+    "IP-CIDR,10.0.0.0/8,DIRECT,no-resolve",
+    "IP-CIDR,172.16.0.0/12,DIRECT,no-resolve",
+    "IP-CIDR,192.168.0.0/16,DIRECT,no-resolve",
+    "IP-CIDR,100.64.0.0/10,DIRECT,no-resolve",
+    "IP-CIDR,169.254.0.0/16,DIRECT,no-resolve",
+    "IP-CIDR6,::1/128,DIRECT,no-resolve",
+    "IP-CIDR6,fc00::/7,DIRECT,no-resolve",
+    "IP-CIDR6,fe80::/10,DIRECT,no-resolve",
+];
+
+impl Bypass {
+    /// The preset's rules. GEOIP doesn't resolve domains here, the subscription's
+    /// own rules still get to match them by name first.
+    pub fn rules(self) -> Vec<String> {
+        let region = match self {
+            Bypass::None => return Vec::new(),
+            Bypass::Lan => None,
+            Bypass::Cn => Some(("cn", "CN")),
+            Bypass::Ir => Some(("category-ir", "IR")),
+            Bypass::Ru => Some(("category-ru", "RU")),
+        };
+        let mut rules: Vec<String> = LAN_RULES.iter().map(|&rule| rule.to_string()).collect();
+        if let Some((geosite, geoip)) = region {
+            rules.push(format!("GEOSITE,{geosite},DIRECT"));
+            rules.push(format!("GEOIP,{geoip},DIRECT,no-resolve"));
+        }
+        rules
+    }
+}
+
+/// A `relay` group, traffic goes through `entry` and leaves through `exit`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub struct Relay {
+    pub entry: String,
+    pub exit: String,
+    /// Listed first in the main selector
+    #[serde(default)]
+    pub select: bool,
+}
+
+/// A group of the nodes whose name matches `include`, Mihomo picks them from
+/// `proxies` and the proxy providers itself.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub struct CustomGroup {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub kind: GroupType,
+    /// Regex the node names have to match
+    pub include: String,
+    /// Regex of node names to leave out
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exclude: Option<String>,
+    /// Listed first in the main selector
+    #[serde(default)]
+    pub select: bool,
+}
+
+/// The kinds of group `group add` creates.
+#[derive(Serialize, Deserialize, clap::ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum GroupType {
+    /// Picked by hand
+    #[default]
+    Select,
+    /// The node with the lowest delay
+    UrlTest,
+    /// The first node that answers
+    Fallback,
+}
+
+impl GroupType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            GroupType::Select => "select",
+            GroupType::UrlTest => "url-test",
+            GroupType::Fallback => "fallback",
+        }
+    }
+}
+
+/// Seconds between the delay tests of `url-test` and `fallback` groups.
+const GROUP_TEST_INTERVAL: u64 = 300;
+
+/// A `dns:` section generated from a preset.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct DnsOverride {
+    pub mode: DnsMode,
+    /// Upstream servers, well-known DoH servers if empty
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub nameservers: Vec<String>,
+}
+
+/// Mihomo's `enhanced-mode`.
+#[derive(Serialize, Deserialize, clap::ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum DnsMode {
+    /// Answer with fake IPs and resolve remotely, fast and avoids DNS leaks
+    #[default]
+    FakeIp,
+    /// Resolve real IPs through the configured nameservers
+    RedirHost,
+}
+
+impl DnsMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DnsMode::FakeIp => "fake-ip",
+            DnsMode::RedirHost => "redir-host",
+        }
+    }
+}
+
+const DEFAULT_NAMESERVERS: &[&str] = &[
+    "https://doh.pub/dns-query",
+    "https://dns.alidns.com/dns-query",
+];
+/// Plain DNS used to resolve the DoH servers' own hostnames.
+const BOOTSTRAP_NAMESERVERS: &[&str] = &["223.5.5.5", "119.29.29.29"];
+/// Names that must get real IPs even in fake-ip mode, e.g. for LAN and captive portals.
+const FAKE_IP_FILTER: &[&str] = &[
+    "*.lan",
+    "*.local",
+    "+.localhost",
+    "+.msftconnecttest.com",
+    "+.msftncsi.com",
+    "time.*.com",
+    "ntp.*.com",
+];
+
+impl DnsOverride {
+    fn to_yaml(&self) -> Value {
+        let list = |items: &[&str]| Value::Sequence(items.iter().map(|&s| s.into()).collect());
+        let nameservers: Vec<&str> = if self.nameservers.is_empty() {
+            DEFAULT_NAMESERVERS.to_vec()
+        } else {
+            self.nameservers.iter().map(String::as_str).collect()
+        };
+
+        let mut dns = Mapping::new();
+        dns.insert("enable".into(), true.into());
+        dns.insert("ipv6".into(), false.into());
+        dns.insert("enhanced-mode".into(), self.mode.as_str().into());
+        if self.mode == DnsMode::FakeIp {
+            dns.insert("fake-ip-range".into(), "198.18.0.1/16".into());
+            dns.insert("fake-ip-filter".into(), list(FAKE_IP_FILTER));
+        }
+        dns.insert("default-nameserver".into(), list(BOOTSTRAP_NAMESERVERS));
+        dns.insert("nameserver".into(), list(&nameservers));
+        Value::Mapping(dns)
+    }
+}
+
+impl Overrides {
+    pub fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let content = fs::read_to_string(path)?;
+        serde_yaml::from_str(&content).with_context(|| format!("Invalid {}", path.display()))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        fs::write(path, serde_yaml::to_string(self)?)?;
+        Ok(())
+    }
+
+    /// Writes the overrides into config.yaml. Applying them twice has no further effect.
+    pub fn apply(&self, config_path: &Path) -> Result<()> {
+        let content = fs::read_to_string(config_path)?;
+        let mut yaml = serde_yaml::from_str::<Value>(&content)?;
+        let map = yaml
+            .as_mapping_mut()
+            .ok_or_else(|| anyhow!("Invalid YAML"))?;
+
+        let adblock = self.adblock && adblock_list_exists(config_path);
+        if self.adblock && !adblock {
+            warn!("Skipping the ad blocking rule, {ADBLOCK_FILE} is not downloaded yet");
+        }
+        let front = self.front_rules(adblock);
+        let mut rules: Vec<Value> = front
+            .iter()
+            .map(|rule| Value::from(rule.as_str()))
+            .collect();
+        if let Some(Value::Sequence(existing)) = map.get("rules") {
+            rules.extend(
+                existing
+                    .iter()
+                    .filter(|rule| {
+                        rule.as_str().is_none_or(|rule| {
+                            let rule = normalize_rule(rule);
+                            // Left over when ad blocking was turned off
+                            !front.contains(&rule) && rule != ADBLOCK_RULE
+                        })
+                    })
+                    .cloned(),
+            );
+        }
+        map.insert("rules".into(), Value::Sequence(rules));
+        set_adblock_provider(map, adblock)?;
+        if let Some(dns) = &self.dns {
+            map.insert("dns".into(), dns.to_yaml());
+        }
+        if let Some(ipv6) = self.ipv6 {
+            map.insert("ipv6".into(), ipv6.into());
+            if let Some(dns) = map.get_mut("dns").and_then(Value::as_mapping_mut) {
+                dns.insert("ipv6".into(), ipv6.into());
+            }
+        }
+        if let Some(interface) = &self.interface_name {
+            map.insert("interface-name".into(), interface.as_str().into());
+        }
+        if let Some(mode) = self.mode {
+            map.insert("mode".into(), mode.as_str().into());
+        }
+        if let Some(allow_lan) = self.allow_lan {
+            map.insert("allow-lan".into(), allow_lan.into());
+            if allow_lan {
+                map.insert("bind-address".into(), "*".into());
+            }
+        }
+        self.filter_nodes(map)?;
+        for name in &self.remove_groups {
+            // Already gone when the overrides were applied before
+            if !remove_group(map, name) {
+                debug!("No {name} group to remove");
+            }
+        }
+        for group in &self.groups {
+            group.insert(map)?;
+        }
+        if let Some(relay) = &self.relay {
+            match relay.missing(map) {
+                Some(name) => {
+                    warn!("Skipping the {RELAY_GROUP} group, {name} is not in the config")
+                }
+                None => relay.insert(map)?,
+            }
+        }
+
+        fs::write(config_path, serde_yaml::to_string(&yaml)?)?;
+        Ok(())
+    }
+
+    /// `prepend_rules` followed by the ad blocking rule with `adblock` and the
+    /// rules of the bypass presets, without duplicates.
+    pub fn front_rules(&self, adblock: bool) -> Vec<String> {
+        let mut rules = self.prepend_rules.clone();
+        let adblock = adblock.then(|| ADBLOCK_RULE.to_string());
+        for rule in adblock
+            .into_iter()
+            .chain(self.bypass.iter().flat_map(|bypass| bypass.rules()))
+        {
+            if !rules.contains(&rule) {
+                rules.push(rule);
+            }
+        }
+        rules
+    }
+
+    /// Removes the nodes `include`/`exclude` reject from `proxies` and from
+    /// every group. A group left empty falls back to DIRECT, Mihomo rejects it otherwise.
+    fn filter_nodes(&self, map: &mut Mapping) -> Result<()> {
+        if self.include.is_none() && self.exclude.is_none() {
+            return Ok(());
+        }
+        // Mihomo's regex engine takes lookarounds Rust's rejects, a filter
+        // written for it is skipped rather than failing the whole config
+        let regex = |pattern: &Option<String>| {
+            let pattern = pattern.as_deref()?;
+            Regex::new(pattern)
+                .inspect_err(|e| warn!("Skipping the node filter {pattern}: {e}"))
+                .ok()
+        };
+        let (include, exclude) = (regex(&self.include), regex(&self.exclude));
+        let keep = |name: &str| {
+            include.as_ref().is_none_or(|regex| regex.is_match(name))
+                && exclude.as_ref().is_none_or(|regex| !regex.is_match(name))
+        };
+
+        let Some(Value::Sequence(proxies)) = map.get_mut("proxies") else {
+            return Ok(());
+        };
+        let mut dropped = Vec::new();
+        proxies.retain(|proxy| match proxy.get("name").and_then(Value::as_str) {
+            Some(name) if !keep(name) => {
+                dropped.push(name.to_string());
+                false
+            }
+            _ => true,
+        });
+        if dropped.is_empty() {
+            return Ok(());
+        }
+        if let Some(Value::Sequence(groups)) = map.get_mut("proxy-groups") {
+            for group in groups.iter_mut().filter_map(Value::as_mapping_mut) {
+                let uses_providers = group.contains_key("use");
+                let Some(Value::Sequence(members)) = group.get_mut("proxies") else {
+                    continue;
+                };
+                members.retain(|member| {
+                    member
+                        .as_str()
+                        .is_none_or(|m| !dropped.iter().any(|d| d == m))
+                });
+                if members.is_empty() && !uses_providers {
+                    members.push("DIRECT".into());
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Relay {
+    /// `entry` or `exit` if the config has no node or group with its name.
+    fn missing<'a>(&'a self, map: &Mapping) -> Option<&'a str> {
+        let names = proxy_names(map);
+        [&self.entry, &self.exit]
+            .into_iter()
+            .find(|name| !names.contains(name))
+            .map(String::as_str)
+    }
+
+    /// Adds or replaces the group, and offers it first in the main selector
+    /// (the first `select` group) with `select`.
+    fn insert(&self, map: &mut Mapping) -> Result<()> {
+        let groups = map
+            .entry("proxy-groups".into())
+            .or_insert_with(|| Value::Sequence(Vec::new()))
+            .as_sequence_mut()
+            .ok_or_else(|| anyhow!("proxy-groups is not a list"))?;
+        let mut group = Mapping::new();
+        group.insert("name".into(), RELAY_GROUP.into());
+        group.insert("type".into(), "relay".into());
+        group.insert(
+            "proxies".into(),
+            Value::Sequence(vec![self.entry.as_str().into(), self.exit.as_str().into()]),
+        );
+        match groups.iter_mut().find(|g| is_relay_group(g)) {
+            Some(existing) => *existing = Value::Mapping(group),
+            None => groups.push(Value::Mapping(group)),
+        }
+
+        if self.select {
+            if let Some(Value::Sequence(members)) = groups
+                .iter_mut()
+                .find(|g| g.get("type").and_then(Value::as_str) == Some("select"))
+                .and_then(|g| g.get_mut("proxies"))
+            {
+                members.retain(|m| m.as_str() != Some(RELAY_GROUP));
+                members.insert(0, RELAY_GROUP.into());
+            }
+        }
+        Ok(())
+    }
+}
+
+impl CustomGroup {
+    /// Adds the group, or replaces the one with the same name in place so the
+    /// groups and rules referring to it keep working. With `select` it is
+    /// offered first in the main selector.
+    fn insert(&self, map: &mut Mapping) -> Result<()> {
+        let groups = map
+            .entry("proxy-groups".into())
+            .or_insert_with(|| Value::Sequence(Vec::new()))
+            .as_sequence_mut()
+            .ok_or_else(|| anyhow!("proxy-groups is not a list"))?;
+        let mut group = Mapping::new();
+        group.insert("name".into(), self.name.as_str().into());
+        group.insert("type".into(), self.kind.as_str().into());
+        group.insert("include-all".into(), true.into());
+        group.insert("filter".into(), self.include.as_str().into());
+        if let Some(exclude) = &self.exclude {
+            group.insert("exclude-filter".into(), exclude.as_str().into());
+        }
+        if self.kind != GroupType::Select {
+            group.insert("url".into(), DEFAULT_DELAY_TEST_URL.into());
+            group.insert("interval".into(), GROUP_TEST_INTERVAL.into());
+        }
+        let is_this = |g: &Value| g.get("name").and_then(Value::as_str) == Some(&self.name);
+        match groups.iter_mut().find(|g| is_this(g)) {
+            Some(existing) => *existing = Value::Mapping(group),
+            None => groups.push(Value::Mapping(group)),
+        }
+
+        if self.select {
+            if let Some(Value::Sequence(members)) = groups
+                .iter_mut()
+                .find(|g| g.get("type").and_then(Value::as_str) == Some("select") && !is_this(g))
+                .and_then(|g| g.get_mut("proxies"))
+            {
+                members.retain(|m| m.as_str() != Some(&self.name));
+                members.insert(0, self.name.as_str().into());
+            }
+        }
+        Ok(())
+    }
+
+    /// Names of the nodes in `proxies` of config.yaml the group picks, nodes
+    /// of proxy providers are only known to the running core. `None` if a
+    /// regex only Mihomo's engine compiles, such as a lookaround, is used.
+    pub fn matching_nodes(&self, config_path: &Path) -> Result<Option<Vec<String>>> {
+        let content = fs::read_to_string(config_path)?;
+        let yaml = serde_yaml::from_str::<Value>(&content)?;
+        let Ok(include) = Regex::new(&self.include) else {
+            return Ok(None);
+        };
+        let Ok(exclude) = self.exclude.as_deref().map(Regex::new).transpose() else {
+            return Ok(None);
+        };
+        Ok(Some(
+            yaml.get("proxies")
+                .and_then(Value::as_sequence)
+                .into_iter()
+                .flatten()
+                .filter_map(|proxy| proxy.get("name")?.as_str())
+                .filter(|name| {
+                    include.is_match(name) && exclude.as_ref().is_none_or(|e| !e.is_match(name))
+                })
+                .map(String::from)
+                .collect(),
+        ))
+    }
+}
+
+/// Drops the group `name` from `proxy-groups` and from the groups listing it.
+/// Rules sending traffic to it send it to the main selector instead, or
+/// DIRECT without one. Returns whether the group was there.
+fn remove_group(map: &mut Mapping, name: &str) -> bool {
+    let Some(Value::Sequence(groups)) = map.get_mut("proxy-groups") else {
+        return false;
+    };
+    let len = groups.len();
+    groups.retain(|g| g.get("name").and_then(Value::as_str) != Some(name));
+    if groups.len() == len {
+        return false;
+    }
+    for group in groups.iter_mut().filter_map(Value::as_mapping_mut) {
+        let uses_others = group.contains_key("use") || group.contains_key("include-all");
+        if let Some(Value::Sequence(members)) = group.get_mut("proxies") {
+            members.retain(|m| m.as_str() != Some(name));
+            if members.is_empty() && !uses_others {
+                members.push("DIRECT".into());
+            }
+        }
+    }
+    let fallback = groups
+        .iter()
+        .find(|g| g.get("type").and_then(Value::as_str) == Some("select"))
+        .and_then(|g| g.get("name")?.as_str())
+        .unwrap_or("DIRECT")
+        .to_string();
+
+    if let Some(Value::Sequence(rules)) = map.get_mut("rules") {
+        for rule in rules.iter_mut() {
+            let Some(text) = rule.as_str() else {
+                continue;
+            };
+            let mut fields: Vec<&str> = text.split(',').map(str::trim).collect();
+            // The policy is last, unless options like no-resolve follow it
+            let policy = match fields.last() {
+                Some(&"no-resolve") | Some(&"src") if fields.len() > 2 => fields.len() - 2,
+                _ => fields.len() - 1,
+            };
+            if fields[policy] == name {
+                fields[policy] = &fallback;
+                *rule = fields.join(",").into();
+            }
+        }
+    }
+    true
+}
+
+fn adblock_list_exists(config_path: &Path) -> bool {
+    config_path
+        .parent()
+        .is_some_and(|dir| dir.join(ADBLOCK_FILE).exists())
+}
+
+/// Adds the anti-AD `rule-providers` entry, or removes it with `!enable`.
+fn set_adblock_provider(map: &mut Mapping, enable: bool) -> Result<()> {
+    if !enable {
+        if let Some(Value::Mapping(providers)) = map.get_mut("rule-providers") {
+            providers.remove(ADBLOCK_PROVIDER);
+            if providers.is_empty() {
+                map.remove("rule-providers");
+            }
+        }
+        return Ok(());
+    }
+    let providers = map
+        .entry("rule-providers".into())
+        .or_insert_with(|| Value::Mapping(Mapping::new()))
+        .as_mapping_mut()
+        .ok_or_else(|| anyhow!("rule-providers is not a map"))?;
+    let mut provider = Mapping::new();
+    provider.insert("type".into(), "file".into());
+    provider.insert("behavior".into(), "domain".into());
+    provider.insert("format".into(), "yaml".into());
+    provider.insert("path".into(), format!("./{ADBLOCK_FILE}").into());
+    providers.insert(ADBLOCK_PROVIDER.into(), Value::Mapping(provider));
+    Ok(())
+}
+
+fn is_relay_group(group: &Value) -> bool {
+    group.get("name").and_then(Value::as_str) == Some(RELAY_GROUP)
+}
+
+/// Names of the nodes and groups in the config, and the built-in policies.
+fn proxy_names(map: &Mapping) -> Vec<String> {
+    let names = |key: &str| {
+        map.get(key)
+            .and_then(Value::as_sequence)
+            .into_iter()
+            .flatten()
+            .filter_map(|proxy| proxy.get("name")?.as_str())
+            .map(String::from)
+            .collect::<Vec<_>>()
+    };
+    let mut all = names("proxies");
+    all.extend(names("proxy-groups"));
+    all.extend(["DIRECT", "REJECT"].map(String::from));
+    all
+}
+
+/// Checks that both nodes of `relay` are in config.yaml.
+pub(crate) fn check_relay(config_path: &Path, relay: &Relay) -> Result<()> {
+    let content = fs::read_to_string(config_path)?;
+    let yaml = serde_yaml::from_str::<Value>(&content)?;
+    let map = yaml.as_mapping().ok_or_else(|| anyhow!("Invalid YAML"))?;
+    match relay.missing(map) {
+        Some(name) => Err(anyhow!("No node or group named {name} in the config")),
+        None => Ok(()),
+    }
+}
+
+/// Removes the `chain` group from config.yaml and from the groups listing it,
+/// returning whether it was there.
+pub(crate) fn remove_relay_group(config_path: &Path) -> Result<bool> {
+    let content = fs::read_to_string(config_path)?;
+    let mut yaml = serde_yaml::from_str::<Value>(&content)?;
+    let Some(Value::Sequence(groups)) = yaml.get_mut("proxy-groups") else {
+        return Ok(false);
+    };
+    let len = groups.len();
+    groups.retain(|g| !is_relay_group(g));
+    if groups.len() == len {
+        return Ok(false);
+    }
+    for group in groups.iter_mut() {
+        if let Some(Value::Sequence(members)) = group.get_mut("proxies") {
+            members.retain(|m| m.as_str() != Some(RELAY_GROUP));
+        }
+    }
+    fs::write(config_path, serde_yaml::to_string(&yaml)?)?;
+    Ok(true)
+}
+
+/// Removes the group `name` from config.yaml, see [`remove_group`].
+pub(crate) fn remove_config_group(config_path: &Path, name: &str) -> Result<bool> {
+    let content = fs::read_to_string(config_path)?;
+    let mut yaml = serde_yaml::from_str::<Value>(&content)?;
+    let map = yaml
+        .as_mapping_mut()
+        .ok_or_else(|| anyhow!("Invalid YAML"))?;
+    if !remove_group(map, name) {
+        return Ok(false);
+    }
+    fs::write(config_path, serde_yaml::to_string(&yaml)?)?;
+    Ok(true)
+}
+
+/// `rule` without the spaces around its fields, failing if it is not
+/// `TYPE,...,POLICY` (or `MATCH,POLICY`).
+pub fn parse_rule(rule: &str) -> Result<String> {
+    let rule = normalize_rule(rule);
+    let fields: Vec<&str> = rule.split(',').collect();
+    let valid_type = fields[0]
+        .chars()
+        .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || c == '-');
+    if fields.len() < 2 || fields.iter().any(|f| f.is_empty()) || !valid_type {
+        return Err(anyhow!(
+            "Invalid rule: {rule}, expected e.g. DOMAIN-SUFFIX,example.com,DIRECT"
+        ));
+    }
+    Ok(rule)
+}
+
+fn normalize_rule(rule: &str) -> String {
+    rule.split(',').map(str::trim).collect::<Vec<_>>().join(",")
+}
+
+/// Every rule in config.yaml, in order.
+pub(crate) fn config_rules(config_path: &Path) -> Result<Vec<String>> {
+    let content = fs::read_to_string(config_path)?;
+    let yaml = serde_yaml::from_str::<Value>(&content)?;
+    Ok(yaml
+        .get("rules")
+        .and_then(Value::as_sequence)
+        .map(|rules| {
+            rules
+                .iter()
+                .filter_map(Value::as_str)
+                .map(String::from)
+                .collect()
+        })
+        .unwrap_or_default())
+}
+
+/// Removes `rule` from config.yaml's rules, returning whether it was there.
+pub(crate) fn remove_config_rule(config_path: &Path, rule: &str) -> Result<bool> {
+    let content = fs::read_to_string(config_path)?;
+    let mut yaml = serde_yaml::from_str::<Value>(&content)?;
+    let Some(Value::Sequence(rules)) = yaml.get_mut("rules") else {
+        return Ok(false);
+    };
+    let len = rules.len();
+    rules.retain(|r| r.as_str().is_none_or(|r| normalize_rule(r) != rule));
+    if rules.len() == len {
+        return Ok(false);
+    }
+    fs::write(config_path, serde_yaml::to_string(&yaml)?)?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = "
+proxies:
+  - {name: HK 01, type: ss}
+  - {name: US 01, type: ss}
+  - {name: US 02, type: ss}
+proxy-groups:
+  - {name: Proxy, type: select, proxies: [HK 01, US 01, US 02]}
+  - {name: US, type: url-test, proxies: [US 01, US 02]}
+  - {name: Providers, type: select, use: [sub], proxies: [US 01]}
+rules:
+  - DOMAIN,example.org,DIRECT
+  - MATCH,Proxy
+";
+
+    fn config() -> Mapping {
+        serde_yaml::from_str(CONFIG).unwrap()
+    }
+
+    fn names(map: &Mapping, key: &str) -> Vec<String> {
+        map[key]
+            .as_sequence()
+            .unwrap()
+            .iter()
+            .map(|item| match item {
+                Value::String(name) => name.clone(),
+                item => item["name"].as_str().unwrap().to_string(),
+            })
+            .collect()
+    }
+
+    fn members(map: &Mapping, group: usize) -> Vec<String> {
+        let group = map["proxy-groups"][group].as_mapping().unwrap();
+        names(group, "proxies")
+    }
+
+    #[test]
+    fn normalizes_rules() {
+        assert_eq!(
+            parse_rule(" DOMAIN-SUFFIX , example.com , DIRECT ").unwrap(),
+            "DOMAIN-SUFFIX,example.com,DIRECT"
+        );
+        assert_eq!(parse_rule("MATCH,Proxy").unwrap(), "MATCH,Proxy");
+        assert_eq!(
+            parse_rule("IP-CIDR,10.0.0.0/8,DIRECT,no-resolve").unwrap(),
+            "IP-CIDR,10.0.0.0/8,DIRECT,no-resolve"
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_rules() {
+        for rule in [
+            "",
+            "DIRECT",
+            "DOMAIN,,DIRECT",
+            "domain,a.com,DIRECT",
+            "DOMAIN,a.com,",
+        ] {
+            assert!(parse_rule(rule).is_err(), "{rule}");
+        }
+    }
+
+    #[test]
+    fn includes_nodes() {
+        let overrides = Overrides {
+            include: Some("^US".into()),
+            ..Default::default()
+        };
+        let mut map = config();
+        overrides.filter_nodes(&mut map).unwrap();
+        assert_eq!(names(&map, "proxies"), ["US 01", "US 02"]);
+        assert_eq!(members(&map, 0), ["US 01", "US 02"]);
+    }
+
+    #[test]
+    fn excluded_nodes_leave_their_groups() {
+        let overrides = Overrides {
+            exclude: Some("US".into()),
+            ..Default::default()
+        };
+        let mut map = config();
+        overrides.filter_nodes(&mut map).unwrap();
+        assert_eq!(names(&map, "proxies"), ["HK 01"]);
+        assert_eq!(members(&map, 0), ["HK 01"]);
+        // Mihomo rejects empty groups, unless they take nodes from providers
+        assert_eq!(members(&map, 1), ["DIRECT"]);
+        assert!(members(&map, 2).is_empty());
+    }
+
+    #[test]
+    fn keeps_nodes_without_filters() {
+        let mut map = config();
+        Overrides::default().filter_nodes(&mut map).unwrap();
+        assert_eq!(map, config());
+    }
+
+    #[test]
+    fn skips_filters_rust_cannot_compile() {
+        let mut map = config();
+        let overrides = Overrides {
+            include: Some("^(?!HK)".to_string()),
+            ..Default::default()
+        };
+        overrides.filter_nodes(&mut map).unwrap();
+        assert_eq!(map, config());
+    }
+
+    #[test]
+    fn applies_to_config_file() {
+        let dir = std::env::temp_dir().join(format!("proxy-rs-overrides-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let config_path = dir.join("config.yaml");
+        fs::write(&config_path, CONFIG).unwrap();
+        let overrides = Overrides {
+            prepend_rules: vec!["DOMAIN,a.com,DIRECT".into()],
+            bypass: vec![Bypass::Lan],
+            ipv6: Some(false),
+            mode: Some(Mode::Global),
+            exclude: Some("HK".into()),
+            ..Default::default()
+        };
+        overrides.apply(&config_path).unwrap();
+        // Applying again leaves the config as it is
+        let applied = fs::read_to_string(&config_path).unwrap();
+        overrides.apply(&config_path).unwrap();
+        assert_eq!(fs::read_to_string(&config_path).unwrap(), applied);
+
+        let map: Mapping = serde_yaml::from_str(&applied).unwrap();
+        let rules = names(&map, "rules");
+        assert_eq!(rules[0], "DOMAIN,a.com,DIRECT");
+        assert_eq!(rules[1..=LAN_RULES.len()], *LAN_RULES);
+        assert_eq!(
+            rules[LAN_RULES.len() + 1..],
+            ["DOMAIN,example.org,DIRECT", "MATCH,Proxy"]
+        );
+        assert_eq!(map["ipv6"], Value::Bool(false));
+        assert_eq!(map["mode"], Value::from("global"));
+        assert_eq!(names(&map, "proxies"), ["US 01", "US 02"]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
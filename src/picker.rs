@@ -0,0 +1,115 @@
+use crate::controller::{Controller, Proxy, DEFAULT_DELAY_TIMEOUT_MS};
+use crate::latency;
+use crate::utils::assume_yes;
+use anyhow::{anyhow, Result};
+use dialoguer::theme::ColorfulTheme;
+use dialoguer::FuzzySelect;
+use log::*;
+use std::collections::HashMap;
+
+/// Lets the user pick a `Selector` group (unless `group` is given) and then one
+/// of its members with a fuzzy-searchable prompt, and switches to it.
+/// Returns the (group, node) that was selected, `None` if the prompt was cancelled.
+pub async fn pick_node(
+    controller: &Controller,
+    group: Option<&str>,
+) -> Result<Option<(String, String)>> {
+    let proxies = controller.proxies().await?;
+    let mut selectors: Vec<&Proxy> = proxies.values().filter(|p| p.kind == "Selector").collect();
+    selectors.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let group = match group {
+        Some(name) => {
+            let group = proxies
+                .get(name)
+                .ok_or_else(|| anyhow!("Proxy group not found: {name}"))?;
+            if group.kind != "Selector" {
+                return Err(anyhow!(
+                    "{} is a {} group, it can't be selected",
+                    group.name,
+                    group.kind
+                ));
+            }
+            group
+        }
+        None if selectors.is_empty() => return Err(anyhow!("No selectable proxy group found")),
+        None if selectors.len() == 1 => selectors[0],
+        None => {
+            let items: Vec<String> = selectors
+                .iter()
+                .map(|g| format!("{}  [{}]", g.name, g.now.as_deref().unwrap_or("-")))
+                .collect();
+            let Some(index) = fuzzy_select("Group", &items, 0)? else {
+                return Ok(None);
+            };
+            selectors[index]
+        }
+    };
+
+    if group.all.is_empty() {
+        return Err(anyhow!("{} has no members", group.name));
+    }
+    let current = group
+        .now
+        .as_ref()
+        .and_then(|now| group.all.iter().position(|n| n == now))
+        .unwrap_or(0);
+    let Some(index) = fuzzy_select(&group.name, &group.all, current)? else {
+        return Ok(None);
+    };
+
+    let node = &group.all[index];
+    controller.select_proxy(&group.name, node).await?;
+    info!("{} -> {node}", group.name);
+    Ok(Some((group.name.clone(), node.clone())))
+}
+
+/// The group users pick their node in: the first `Selector` in the config's
+/// order, which `GLOBAL` lists its members in.
+pub(crate) fn main_selector(proxies: &HashMap<String, Proxy>) -> Option<&Proxy> {
+    let in_config_order = proxies
+        .get("GLOBAL")
+        .into_iter()
+        .flat_map(|global| &global.all)
+        .filter_map(|name| proxies.get(name))
+        .find(|p| p.kind == "Selector");
+    in_config_order.or_else(|| {
+        proxies
+            .values()
+            .filter(|p| p.kind == "Selector" && p.name != "GLOBAL")
+            .min_by(|a, b| a.name.cmp(&b.name))
+    })
+}
+
+/// Tests the delay of the nodes in the main `Selector` group with `url` and
+/// switches it to the fastest. Returns (group, node, delay in ms).
+pub async fn select_fastest(controller: &Controller, url: &str) -> Result<(String, String, u64)> {
+    let proxies = controller.proxies().await?;
+    let group =
+        main_selector(&proxies).ok_or_else(|| anyhow!("No selectable proxy group found"))?;
+    // DIRECT would always win, and nested groups only report their current node
+    let is_node = |name: &str| proxies.get(name).is_some_and(Proxy::is_node);
+    let results =
+        latency::test_latency(controller, Some(&group.name), url, DEFAULT_DELAY_TIMEOUT_MS).await?;
+    let (node, delay) = results
+        .into_iter()
+        .filter(|r| is_node(&r.name))
+        .find_map(|r| r.delay.map(|delay| (r.name, delay)))
+        .ok_or_else(|| anyhow!("No node in {} answered the delay test", group.name))?;
+    controller.select_proxy(&group.name, &node).await?;
+    Ok((group.name.clone(), node, delay))
+}
+
+/// Index of the chosen item, `None` if the prompt was cancelled with Esc or q.
+/// With `--yes` the default is taken without asking.
+pub fn fuzzy_select(prompt: &str, items: &[String], default: usize) -> Result<Option<usize>> {
+    if assume_yes() {
+        return Ok(Some(default));
+    }
+    Ok(FuzzySelect::with_theme(&ColorfulTheme::default())
+        .with_prompt(prompt)
+        .items(items)
+        .default(default)
+        .max_length(15)
+        .interact_opt()?)
+}
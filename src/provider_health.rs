@@ -0,0 +1,161 @@
+//! How the subscription provider has been doing: failed downloads of each
+//! subscription and the share of its nodes the core reports as failing, so
+//! `status` can tell a dead provider apart from a flaky node.
+
+use crate::output::format_duration;
+use crate::subscription::subscription_name;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+pub const PROVIDER_HEALTH_FILE: &str = "provider-health.toml";
+/// Failing downloads are reported once they have failed this long...
+const UNREACHABLE_AFTER: Duration = Duration::from_secs(24 * 60 * 60);
+/// ...or this many times in a row
+const UNREACHABLE_FAILURES: u32 = 3;
+/// Share of failing nodes, in percent, from which it is reported
+const FAILING_NODES_PERCENT: usize = 50;
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct ProviderHealth {
+    pub subscriptions: Vec<FetchHealth>,
+    pub nodes: Option<NodeHealth>,
+}
+
+/// Downloads of one subscription, times in Unix seconds.
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct FetchHealth {
+    pub url: String,
+    pub last_success: Option<i64>,
+    /// First failure since the last success
+    pub failing_since: Option<i64>,
+    /// Failures in a row
+    pub failures: u32,
+    pub last_error: Option<String>,
+}
+
+/// The last count of nodes the core checked and of those that failed.
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct NodeHealth {
+    pub checked_at: i64,
+    pub total: usize,
+    pub failing: usize,
+    /// First count at or over [`FAILING_NODES_PERCENT`] since it was last below
+    pub failing_since: Option<i64>,
+}
+
+impl ProviderHealth {
+    /// The saved health, empty if there is none or it can't be read.
+    pub fn load(path: &Path) -> Self {
+        fs::read_to_string(path)
+            .ok()
+            .and_then(|content| toml::from_str(&content).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        fs::write(path, toml::to_string_pretty(self)?)
+            .with_context(|| format!("Failed to write {}", path.display()))
+    }
+
+    /// Records a download of `url` at `now`, `Err` with the reason if it failed.
+    pub fn record_fetch(&mut self, url: &str, result: Result<(), String>, now: i64) {
+        let index = match self.subscriptions.iter().position(|s| s.url == url) {
+            Some(index) => index,
+            None => {
+                self.subscriptions.push(FetchHealth {
+                    url: url.to_string(),
+                    ..Default::default()
+                });
+                self.subscriptions.len() - 1
+            }
+        };
+        let fetch = &mut self.subscriptions[index];
+        match result {
+            Ok(()) => {
+                fetch.last_success = Some(now);
+                fetch.failing_since = None;
+                fetch.failures = 0;
+                fetch.last_error = None;
+            }
+            Err(error) => {
+                fetch.failing_since.get_or_insert(now);
+                fetch.failures += 1;
+                fetch.last_error = Some(error);
+            }
+        }
+    }
+
+    /// Forgets the subscriptions no longer in `urls`.
+    pub fn retain(&mut self, urls: &[String]) {
+        self.subscriptions.retain(|s| urls.contains(&s.url));
+    }
+
+    /// Records that `failing` of the `total` nodes the core checked failed.
+    pub fn record_nodes(&mut self, total: usize, failing: usize, now: i64) {
+        let failing_since = self
+            .nodes
+            .as_ref()
+            .and_then(|nodes| nodes.failing_since)
+            .unwrap_or(now);
+        self.nodes = Some(NodeHealth {
+            checked_at: now,
+            total,
+            failing,
+            failing_since: is_failing(total, failing).then_some(failing_since),
+        });
+    }
+
+    /// The subscriptions that have failed for long enough at `now`.
+    pub fn fetch_warnings(&self, now: i64) -> Vec<String> {
+        let mut warnings = Vec::new();
+        for fetch in &self.subscriptions {
+            let Some(failing_since) = fetch.failing_since else {
+                continue;
+            };
+            let failing_for = (now - failing_since).max(0) as u64;
+            if failing_for < UNREACHABLE_AFTER.as_secs() && fetch.failures < UNREACHABLE_FAILURES {
+                continue;
+            }
+            let last_success = match fetch.last_success {
+                Some(time) => format!("last downloaded {} ago", since(time, now)),
+                None => "never downloaded".to_string(),
+            };
+            warnings.push(format!(
+                "Subscription {} unreachable for {} ({} failed downloads, {last_success}): {}",
+                subscription_name(&fetch.url),
+                since(failing_since, now),
+                fetch.failures,
+                fetch.last_error.as_deref().unwrap_or_default()
+            ));
+        }
+        warnings
+    }
+
+    /// Whether most nodes failed in the last count, and since when.
+    pub fn node_warning(&self, now: i64) -> Option<String> {
+        let nodes = self.nodes.as_ref()?;
+        let failing_since = nodes.failing_since?;
+        Some(format!(
+            "{}% of nodes failing ({} of {}) for {}, the provider may be down",
+            nodes.failing * 100 / nodes.total,
+            nodes.failing,
+            nodes.total,
+            since(failing_since, now)
+        ))
+    }
+}
+
+/// Time from `time` to `now`, in Unix seconds.
+fn since(time: i64, now: i64) -> String {
+    format_duration(Duration::from_secs((now - time).max(0) as u64))
+}
+
+fn is_failing(total: usize, failing: usize) -> bool {
+    total > 0 && failing * 100 >= total * FAILING_NODES_PERCENT
+}
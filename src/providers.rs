@@ -0,0 +1,54 @@
+use crate::controller::{Controller, ProviderKind};
+use anyhow::{anyhow, Result};
+use log::*;
+
+/// Updates the providers of `kind` (both kinds by default), or only the one
+/// named `name`, logging the outcome of each. Fails if any update failed.
+pub async fn update_providers(
+    controller: &Controller,
+    kind: Option<ProviderKind>,
+    name: Option<&str>,
+) -> Result<()> {
+    let kinds = match kind {
+        Some(kind) => vec![kind],
+        None => ProviderKind::ALL.to_vec(),
+    };
+    let mut providers = Vec::new();
+    for kind in kinds {
+        let mut names: Vec<String> = controller
+            .providers(kind)
+            .await?
+            .into_values()
+            // The groups' built-in providers have nothing to update
+            .filter(|p| p.vehicle_type != "Compatible")
+            .map(|p| p.name)
+            .filter(|n| name.is_none_or(|name| n == name))
+            .collect();
+        names.sort();
+        providers.extend(names.into_iter().map(|name| (kind, name)));
+    }
+    if providers.is_empty() {
+        return Err(match name {
+            Some(name) => anyhow!("Provider not found: {name}"),
+            None => anyhow!("The config has no providers"),
+        });
+    }
+
+    let mut failed = 0;
+    for (kind, name) in &providers {
+        match controller.update_provider(*kind, name).await {
+            Ok(()) => info!("Updated {name}"),
+            Err(e) => {
+                warn!("Failed to update {name}: {e}");
+                failed += 1;
+            }
+        }
+    }
+    if failed > 0 {
+        return Err(anyhow!(
+            "{failed} of {} providers failed to update",
+            providers.len()
+        ));
+    }
+    Ok(())
+}
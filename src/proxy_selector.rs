@@ -1,6 +1,12 @@
+use futures_util::future::join_all;
+use futures_util::stream::{FuturesUnordered, StreamExt};
 use log::*;
-use reqwest::blocking::Client;
-use std::time::Duration;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::Mutex;
 
 static DIRECT_CONNECTION: &str = "Direct connection";
 
@@ -11,49 +17,209 @@ static GITHUB_PROXIES: &[&str] = &[
     "https://tvv.tw/",
 ];
 
+const PROBE_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// File in the data dir holding the fastest mirror of the last run
+pub const MIRROR_CACHE_FILE: &str = "mirror.toml";
+/// How long a saved mirror is used before the mirrors are probed again
+const MIRROR_CACHE_TTL: Duration = Duration::from_secs(60 * 60);
+
+static SELECTED_PROXY: Mutex<Option<String>> = Mutex::const_new(None);
+/// Proxies that kept failing, skipped by [`fallback_github_proxy`].
+static FAILED_PROXIES: Mutex<Vec<String>> = Mutex::const_new(Vec::new());
+
 static GITHUB_SPEEDTEST_URL: &str =
     "https://raw.githubusercontent.com/microsoft/vscode/main/LICENSE.txt";
 
-pub fn select_fastest_github_proxy() -> anyhow::Result<&'static str> {
-    info!("Selecting fastest GitHub proxy...");
+/// Where the fastest mirror is kept between runs.
+#[derive(Debug)]
+pub struct MirrorCache {
+    pub path: PathBuf,
+    /// Probe the mirrors even if the saved one is recent, `--reselect-mirror`
+    pub reselect: bool,
+}
 
-    let client = Client::builder().timeout(Duration::from_secs(3)).build()?;
+#[derive(Serialize, Deserialize)]
+struct SavedMirror {
+    mirror: String,
+    /// Unix time of the probe
+    probed_at: u64,
+}
 
-    let results: Vec<(&'static str, Duration)> = GITHUB_PROXIES
-        .iter()
-        .filter_map(|proxy| {
-            let url = format!("{}{}", proxy, GITHUB_SPEEDTEST_URL);
-            let start_time = std::time::Instant::now();
-
-            let proxy_name = if proxy.is_empty() {
-                DIRECT_CONNECTION
-            } else {
-                proxy
-            };
-            match client.get(&url).send() {
-                Ok(response) if response.status().is_success() => {
-                    let elapsed = start_time.elapsed();
-                    info!("{proxy_name} time: {elapsed:?}");
-                    Some((*proxy, elapsed))
-                }
-                _ => {
-                    info!("{proxy_name} is not available");
-                    None
-                }
-            }
-        })
-        .collect();
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
 
-    if let Some((fastest_proxy, _)) = results.iter().min_by_key(|(_, t)| *t) {
-        let proxy_name = if fastest_proxy.is_empty() {
-            DIRECT_CONNECTION
+impl MirrorCache {
+    /// The saved mirror if it was probed within the TTL and is one of `proxies`.
+    fn load(&self, proxies: &[String]) -> Option<String> {
+        if self.reselect {
+            return None;
+        }
+        let content = fs::read_to_string(&self.path).ok()?;
+        let saved: SavedMirror = toml::from_str(&content).ok()?;
+        let age = now().saturating_sub(saved.probed_at);
+        (age < MIRROR_CACHE_TTL.as_secs() && proxies.contains(&saved.mirror))
+            .then_some(saved.mirror)
+    }
+
+    fn save(&self, mirror: &str) {
+        let saved = SavedMirror {
+            mirror: mirror.to_string(),
+            probed_at: now(),
+        };
+        let Ok(content) = toml::to_string(&saved) else {
+            return;
+        };
+        // Only saves probing next time, not worth failing the download
+        if let Err(e) = fs::write(&self.path, content) {
+            debug!("Failed to save {}: {e}", self.path.display());
+        }
+    }
+}
+
+/// Builds the mirror list from the built-in proxies and user-provided ones.
+pub fn github_mirrors(extra: &[String], replace_defaults: bool) -> Vec<String> {
+    let mut mirrors: Vec<String> = if replace_defaults {
+        vec![String::new()]
+    } else {
+        GITHUB_PROXIES.iter().map(|p| p.to_string()).collect()
+    };
+
+    for mirror in extra {
+        let mirror = if mirror.ends_with('/') {
+            mirror.clone()
         } else {
-            fastest_proxy
+            format!("{mirror}/")
         };
-        info!("Fastest GitHub proxy: {proxy_name}");
-        Ok(*fastest_proxy)
+        if !mirrors.contains(&mirror) {
+            mirrors.push(mirror);
+        }
+    }
+    mirrors
+}
+
+pub fn proxy_display_name(proxy: &str) -> &str {
+    if proxy.is_empty() {
+        DIRECT_CONNECTION
+    } else {
+        proxy
+    }
+}
+
+async fn probe_github_proxy(client: &Client, proxy: &str) -> Option<Duration> {
+    let url = format!("{}{}", proxy, GITHUB_SPEEDTEST_URL);
+    let start_time = std::time::Instant::now();
+
+    let proxy_name = proxy_display_name(proxy);
+    match client.get(&url).timeout(PROBE_TIMEOUT).send().await {
+        Ok(response) if response.status().is_success() => {
+            let elapsed = start_time.elapsed();
+            info!("{proxy_name} time: {elapsed:?}");
+            Some(elapsed)
+        }
+        _ => {
+            info!("{proxy_name} is not available");
+            None
+        }
+    }
+}
+
+/// Measures the latency of each proxy concurrently, `None` if it is not available.
+pub async fn measure_github_proxies(
+    client: &Client,
+    proxies: &[String],
+) -> Vec<(String, Option<Duration>)> {
+    let probes = proxies
+        .iter()
+        .map(|proxy| async move { (proxy.clone(), probe_github_proxy(client, proxy).await) });
+    join_all(probes).await
+}
+
+/// Returns the first proxy to answer successfully, which is the fastest one.
+/// The result is cached for the rest of the process and in `cache` for later runs.
+pub async fn select_fastest_github_proxy(
+    client: &Client,
+    proxies: &[String],
+    cache: &MirrorCache,
+) -> anyhow::Result<String> {
+    let mut selected = SELECTED_PROXY.lock().await;
+    if let Some(proxy) = selected.as_ref() {
+        return Ok(proxy.clone());
+    }
+    if let Some(proxy) = cache.load(proxies) {
+        info!(
+            "Using GitHub proxy {} from the last run, --reselect-mirror to probe again",
+            proxy_display_name(&proxy)
+        );
+        *selected = Some(proxy.clone());
+        return Ok(proxy);
+    }
+
+    info!("Selecting fastest GitHub proxy...");
+
+    let mut probes: FuturesUnordered<_> = proxies
+        .iter()
+        .map(|proxy| async move { probe_github_proxy(client, proxy).await.map(|_| proxy) })
+        .collect();
+
+    let mut fastest_proxy = None;
+    while let Some(result) = probes.next().await {
+        if let Some(proxy) = result {
+            fastest_proxy = Some(proxy.clone());
+            break;
+        }
+    }
+
+    if let Some(fastest_proxy) = fastest_proxy {
+        info!(
+            "Fastest GitHub proxy: {}",
+            proxy_display_name(&fastest_proxy)
+        );
+        *selected = Some(fastest_proxy.clone());
+        cache.save(&fastest_proxy);
+        Ok(fastest_proxy)
     } else {
         error!("No GitHub proxy available");
         Err(anyhow::anyhow!("No GitHub proxy available"))
     }
 }
+
+/// Marks `failed` as unusable and selects the fastest of the remaining proxies.
+/// If another download already switched away from `failed`, that proxy is kept.
+pub async fn fallback_github_proxy(
+    client: &Client,
+    proxies: &[String],
+    failed: &str,
+    cache: &MirrorCache,
+) -> anyhow::Result<String> {
+    let remaining: Vec<String> = {
+        let mut failed_proxies = FAILED_PROXIES.lock().await;
+        if !failed_proxies.iter().any(|p| p == failed) {
+            failed_proxies.push(failed.to_string());
+        }
+
+        let mut selected = SELECTED_PROXY.lock().await;
+        if let Some(current) = selected.as_ref() {
+            if !failed_proxies.contains(current) {
+                return Ok(current.clone());
+            }
+        }
+        *selected = None;
+
+        proxies
+            .iter()
+            .filter(|p| !failed_proxies.contains(p))
+            .cloned()
+            .collect()
+    };
+
+    if remaining.is_empty() {
+        return Err(anyhow::anyhow!("All GitHub proxies failed"));
+    }
+    info!("Switching away from {}", proxy_display_name(failed));
+    select_fastest_github_proxy(client, &remaining, cache).await
+}
@@ -0,0 +1,254 @@
+//! Resource limits of the core, so a misbehaving core or a huge ruleset can't
+//! take down a small machine it shares with other work: a memory cap, CPU
+//! niceness and the open-file limit.
+
+use crate::utils::is_elevated;
+use anyhow::{anyhow, Result};
+use log::*;
+use std::process::{Child, Command};
+
+/// Limits from `start --memory-limit-mb`, `--nice` and `--open-files-limit`,
+/// or from proxy-rs.toml.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ResourceLimits {
+    /// Memory in MiB, through a cgroup v2 on Linux and a job object on Windows
+    pub memory_mb: Option<u64>,
+    /// Niceness from -20 to 19, a priority class on Windows
+    pub nice: Option<i32>,
+    /// Open files, on Unix only
+    pub open_files: Option<u64>,
+}
+
+impl ResourceLimits {
+    /// Has the core take on its niceness and open-file limit before it runs,
+    /// so every thread it starts gets them.
+    pub fn apply(&self, command: &mut Command) -> Result<()> {
+        if let Some(nice) = self.nice {
+            if !(-20..=19).contains(&nice) {
+                return Err(anyhow!("Niceness must be between -20 and 19, not {nice}"));
+            }
+            if nice < 0 && !is_elevated() {
+                return Err(anyhow!(
+                    "Only root can run the core with a negative niceness"
+                ));
+            }
+        }
+        #[cfg(unix)]
+        {
+            use std::os::unix::process::CommandExt;
+            let nice = self.nice;
+            let open_files = self.open_files.map(open_files_limit).transpose()?;
+            if nice.is_none() && open_files.is_none() {
+                return Ok(());
+            }
+            // SAFETY: setpriority and setrlimit are async-signal-safe
+            unsafe {
+                command.pre_exec(move || {
+                    use nix::libc;
+                    if let Some(nice) = nice {
+                        if libc::setpriority(libc::PRIO_PROCESS as _, 0, nice) != 0 {
+                            return Err(std::io::Error::last_os_error());
+                        }
+                    }
+                    if let Some(limit) = &open_files {
+                        if libc::setrlimit(libc::RLIMIT_NOFILE, limit) != 0 {
+                            return Err(std::io::Error::last_os_error());
+                        }
+                    }
+                    Ok(())
+                });
+            }
+        }
+        #[cfg(windows)]
+        {
+            let _ = command;
+            if self.open_files.is_some() {
+                warn!("The open-file limit is not supported on Windows, it is ignored");
+            }
+        }
+        Ok(())
+    }
+
+    /// Caps the memory of the running core, and on Windows sets its priority.
+    /// `name` tells the cgroups of several instances apart.
+    pub fn confine(&self, child: &Child, name: &str) {
+        #[cfg(windows)]
+        if let Some(nice) = self.nice {
+            if let Err(e) = windows::set_priority(child, nice) {
+                warn!("Failed to set the priority of the core: {e}");
+            }
+        }
+        let Some(memory_mb) = self.memory_mb else {
+            return;
+        };
+        let bytes = memory_mb.saturating_mul(1024 * 1024);
+        #[cfg(target_os = "linux")]
+        let result = cgroup::limit_memory(child.id(), name, bytes)
+            .map(|dir| debug!("Memory of the core is limited by {}", dir.display()));
+        #[cfg(windows)]
+        let result = {
+            let _ = name;
+            windows::limit_memory(child, bytes)
+        };
+        #[cfg(not(any(target_os = "linux", windows)))]
+        let result: Result<()> = {
+            let _ = (child, name, bytes);
+            Err(anyhow!("not supported on this OS"))
+        };
+        match result {
+            Ok(()) => info!("Memory of the core is limited to {memory_mb} MiB"),
+            Err(e) => {
+                warn!("Failed to limit the memory of the core: {e:#}, it runs without a limit")
+            }
+        }
+    }
+}
+
+/// Soft and hard limit of `files`. Go raises the soft limit to the hard one
+/// on start, so both are set.
+#[cfg(unix)]
+fn open_files_limit(files: u64) -> Result<nix::libc::rlimit> {
+    use nix::libc;
+    let mut current = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+    // SAFETY: `current` is a valid rlimit to write to
+    if unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut current) } != 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    let files = files as libc::rlim_t;
+    if files > current.rlim_max && !is_elevated() {
+        return Err(anyhow!(
+            "The open-file limit {files} is over the hard limit {}, only root can raise it",
+            current.rlim_max
+        ));
+    }
+    Ok(libc::rlimit {
+        rlim_cur: files,
+        rlim_max: files,
+    })
+}
+
+#[cfg(target_os = "linux")]
+mod cgroup {
+    use anyhow::{anyhow, Context, Result};
+    use std::fs;
+    use std::path::{Path, PathBuf};
+
+    const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+
+    /// Moves `pid` into the cgroup `name`, created next to proxy-rs's own and
+    /// capped at `bytes`. Returns its directory.
+    pub fn limit_memory(pid: u32, name: &str, bytes: u64) -> Result<PathBuf> {
+        let root = Path::new(CGROUP_ROOT);
+        if !root.join("cgroup.controllers").exists() {
+            return Err(anyhow!("cgroup v2 is not mounted at {CGROUP_ROOT}"));
+        }
+        let own = fs::read_to_string("/proc/self/cgroup")?;
+        let own = own
+            .lines()
+            .find_map(|line| line.strip_prefix("0::"))
+            .ok_or_else(|| anyhow!("proxy-rs is not in a cgroup v2"))?;
+        let own = root.join(own.trim().trim_start_matches('/'));
+        // Only a cgroup without processes can hand controllers to its children,
+        // which proxy-rs's own has, except for the root
+        let parent = match own.parent() {
+            Some(parent) if own != root => parent.to_path_buf(),
+            _ => own,
+        };
+        let subtree_control = parent.join("cgroup.subtree_control");
+        let enabled = fs::read_to_string(&subtree_control).unwrap_or_default();
+        if !enabled.split_whitespace().any(|c| c == "memory") {
+            fs::write(&subtree_control, "+memory").with_context(|| {
+                format!(
+                    "Failed to enable the memory controller in {}",
+                    parent.display()
+                )
+            })?;
+        }
+        let dir = parent.join(name);
+        if !dir.exists() {
+            fs::create_dir(&dir).with_context(|| {
+                format!(
+                    "Failed to create {}, run proxy-rs as root or under `systemd-run --user --scope`",
+                    dir.display()
+                )
+            })?;
+        }
+        fs::write(dir.join("memory.max"), bytes.to_string())?;
+        // Swapping would only make it slower before the limit is hit
+        let _ = fs::write(dir.join("memory.swap.max"), "0");
+        fs::write(dir.join("cgroup.procs"), pid.to_string())
+            .with_context(|| format!("Failed to move the core into {}", dir.display()))?;
+        Ok(dir)
+    }
+}
+
+#[cfg(windows)]
+mod windows {
+    use anyhow::{anyhow, Result};
+    use std::os::windows::io::AsRawHandle;
+    use std::process::Child;
+    use winapi::um::handleapi::CloseHandle;
+    use winapi::um::jobapi2::{
+        AssignProcessToJobObject, CreateJobObjectW, SetInformationJobObject,
+    };
+    use winapi::um::processthreadsapi::SetPriorityClass;
+    use winapi::um::winbase::{
+        ABOVE_NORMAL_PRIORITY_CLASS, BELOW_NORMAL_PRIORITY_CLASS, HIGH_PRIORITY_CLASS,
+        IDLE_PRIORITY_CLASS, NORMAL_PRIORITY_CLASS,
+    };
+    use winapi::um::winnt::{
+        JobObjectExtendedLimitInformation, HANDLE, JOBOBJECT_EXTENDED_LIMIT_INFORMATION,
+        JOB_OBJECT_LIMIT_PROCESS_MEMORY,
+    };
+
+    /// Adds `child` to a job of its own whose processes can't commit more than
+    /// `bytes`. The job lives on with the process once the handle is closed.
+    pub fn limit_memory(child: &Child, bytes: u64) -> Result<()> {
+        // SAFETY: the limit information is fully initialized before it is
+        // passed on, and both handles are valid until closed
+        unsafe {
+            let job = CreateJobObjectW(std::ptr::null_mut(), std::ptr::null());
+            if job.is_null() {
+                return Err(anyhow!(
+                    "Failed to create a job object: {}",
+                    std::io::Error::last_os_error()
+                ));
+            }
+            let mut info: JOBOBJECT_EXTENDED_LIMIT_INFORMATION = std::mem::zeroed();
+            info.BasicLimitInformation.LimitFlags = JOB_OBJECT_LIMIT_PROCESS_MEMORY;
+            info.ProcessMemoryLimit = bytes as usize;
+            let limited = SetInformationJobObject(
+                job,
+                JobObjectExtendedLimitInformation,
+                &mut info as *mut _ as *mut _,
+                std::mem::size_of::<JOBOBJECT_EXTENDED_LIMIT_INFORMATION>() as u32,
+            ) != 0
+                && AssignProcessToJobObject(job, child.as_raw_handle() as HANDLE) != 0;
+            let error = std::io::Error::last_os_error();
+            CloseHandle(job);
+            if !limited {
+                return Err(anyhow!("{error}"));
+            }
+        }
+        Ok(())
+    }
+
+    /// The priority class closest to the Unix niceness `nice`.
+    pub fn set_priority(child: &Child, nice: i32) -> Result<()> {
+        let class = match nice {
+            15.. => IDLE_PRIORITY_CLASS,
+            1..=14 => BELOW_NORMAL_PRIORITY_CLASS,
+            0 => NORMAL_PRIORITY_CLASS,
+            -14..=-1 => ABOVE_NORMAL_PRIORITY_CLASS,
+            _ => HIGH_PRIORITY_CLASS,
+        };
+        // SAFETY: the handle is valid while `child` is alive
+        if unsafe { SetPriorityClass(child.as_raw_handle() as HANDLE, class) } == 0 {
+            return Err(anyhow!("{}", std::io::Error::last_os_error()));
+        }
+        Ok(())
+    }
+}
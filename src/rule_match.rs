@@ -0,0 +1,673 @@
+//! Evaluates the config's rules without the core for `rules try`: the first
+//! rule a domain or IP matches and where it sends the connection. Rules on the
+//! connection's source, process or inbound can't be evaluated and are skipped.
+
+use crate::config::prefix_contains;
+use crate::geoip::{self, GeoSite, Mmdb};
+use anyhow::{anyhow, Context, Result};
+use colored::Colorize;
+use regex::Regex;
+use serde_yaml::Value;
+use std::cell::OnceCell;
+use std::collections::HashMap;
+use std::fs;
+use std::net::{IpAddr, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+
+/// Targets that are not proxy groups or nodes.
+const BUILT_IN_TARGETS: &[&str] = &["DIRECT", "REJECT", "REJECT-DROP", "PASS", "COMPATIBLE"];
+/// Skipped rules listed under a result, the others are only counted
+const SKIPPED_SHOWN: usize = 3;
+
+/// The rules, groups and rule-providers of a config, with the geodata loaded
+/// as rules need it.
+pub struct RuleMatcher {
+    rules: Vec<String>,
+    /// Type and member count of each proxy group
+    groups: HashMap<String, (String, usize)>,
+    providers: HashMap<String, Provider>,
+    geosite_path: PathBuf,
+    country_path: PathBuf,
+    asn_path: PathBuf,
+    geosite: OnceCell<Result<GeoSite, String>>,
+    country: OnceCell<Result<Option<Mmdb>, String>>,
+    asn: OnceCell<Result<Mmdb, String>>,
+}
+
+/// Where a domain or IP goes.
+pub struct Verdict {
+    pub host: String,
+    pub port: u16,
+    /// The IP the domain resolved to, if an IP rule needed it
+    pub resolved: Option<IpAddr>,
+    /// 1-based position and text of the matching rule
+    pub rule: Option<(usize, String)>,
+    pub target: String,
+    /// Rules before the match that couldn't be evaluated, and why
+    pub skipped: Vec<(usize, String, String)>,
+}
+
+/// The destination being matched, with the IP resolved once a rule needs it.
+struct Destination<'a> {
+    host: &'a str,
+    port: u16,
+    ip: Option<IpAddr>,
+    resolved: OnceCell<Option<IpAddr>>,
+}
+
+impl Destination<'_> {
+    fn domain(&self) -> Option<&str> {
+        self.ip.is_none().then_some(self.host)
+    }
+
+    /// The IP, resolving the domain unless the rule says `no-resolve`.
+    fn ip(&self, no_resolve: bool) -> Option<IpAddr> {
+        if self.ip.is_some() {
+            return self.ip;
+        }
+        if no_resolve {
+            return None;
+        }
+        *self.resolved.get_or_init(|| {
+            let addresses: Vec<IpAddr> = (self.host, self.port)
+                .to_socket_addrs()
+                .ok()?
+                .map(|address| address.ip())
+                .collect();
+            // Mihomo prefers IPv4
+            addresses
+                .iter()
+                .find(|ip| ip.is_ipv4())
+                .or(addresses.first())
+                .copied()
+        })
+    }
+}
+
+enum Provider {
+    Domain(Vec<String>),
+    IpCidr(Vec<String>),
+    Classical(Vec<String>),
+    /// Why the rules of the provider can't be read
+    Unavailable(String),
+}
+
+impl RuleMatcher {
+    /// Loads the rules of `config_path`, with geodata looked up in its directory.
+    pub fn load(config_path: &Path, geodata_mode: bool) -> Result<Self> {
+        let content = fs::read_to_string(config_path)
+            .with_context(|| format!("Failed to read {}", config_path.display()))?;
+        let yaml: Value = serde_yaml::from_str(&content)?;
+        let config_dir = config_path
+            .parent()
+            .ok_or_else(|| anyhow!("Invalid config path"))?
+            .to_path_buf();
+        let rules = yaml
+            .get("rules")
+            .and_then(Value::as_sequence)
+            .into_iter()
+            .flatten()
+            .filter_map(Value::as_str)
+            .map(String::from)
+            .collect();
+        let groups = yaml
+            .get("proxy-groups")
+            .and_then(Value::as_sequence)
+            .into_iter()
+            .flatten()
+            .filter_map(|group| {
+                let name = group.get("name")?.as_str()?;
+                let kind = group
+                    .get("type")
+                    .and_then(Value::as_str)
+                    .unwrap_or("select");
+                let members = group
+                    .get("proxies")
+                    .and_then(Value::as_sequence)
+                    .map_or(0, Vec::len);
+                Some((name.to_string(), (kind.to_string(), members)))
+            })
+            .collect();
+        let providers = yaml
+            .get("rule-providers")
+            .and_then(Value::as_mapping)
+            .into_iter()
+            .flatten()
+            .filter_map(|(name, provider)| {
+                let name = name.as_str()?;
+                Some((name.to_string(), load_provider(&config_dir, provider)))
+            })
+            .collect();
+        let country_file = if geodata_mode {
+            "geoip.dat"
+        } else {
+            "country.mmdb"
+        };
+        Ok(Self {
+            geosite_path: config_dir.join("geosite.dat"),
+            country_path: config_dir.join(country_file),
+            asn_path: config_dir.join("ASN.mmdb"),
+            rules,
+            groups,
+            providers,
+            geosite: OnceCell::new(),
+            country: OnceCell::new(),
+            asn: OnceCell::new(),
+        })
+    }
+
+    pub fn rule_count(&self) -> usize {
+        self.rules.len()
+    }
+
+    /// The first rule `host` on `port` matches. Without any, Mihomo sends it DIRECT.
+    pub fn evaluate(&self, host: &str, port: u16) -> Verdict {
+        let destination = Destination {
+            host,
+            port,
+            ip: host.parse().ok(),
+            resolved: OnceCell::new(),
+        };
+        let mut skipped = Vec::new();
+        let mut matched = None;
+        for (index, rule) in self.rules.iter().enumerate() {
+            let Some((condition, target)) = split_rule(rule) else {
+                skipped.push((index + 1, rule.clone(), "can't be parsed".to_string()));
+                continue;
+            };
+            match self.matches(&condition, &destination) {
+                Ok(true) => {
+                    matched = Some((index + 1, rule.clone(), target));
+                    break;
+                }
+                Ok(false) => {}
+                Err(reason) => skipped.push((index + 1, rule.clone(), reason)),
+            }
+        }
+        let (rule, target) = match matched {
+            Some((index, rule, target)) => (Some((index, rule)), target),
+            None => (None, "DIRECT".to_string()),
+        };
+        Verdict {
+            host: host.to_string(),
+            port,
+            resolved: destination.resolved.get().copied().flatten(),
+            rule,
+            target,
+            skipped,
+        }
+    }
+
+    /// Whether `condition`, a rule without its target such as
+    /// `DOMAIN-SUFFIX,example.com` or `IP-CIDR,10.0.0.0/8,no-resolve`, matches.
+    /// `Err` with the reason if it can't be evaluated without the core.
+    fn matches(&self, condition: &str, destination: &Destination) -> Result<bool, String> {
+        let (kind, rest) = condition.split_once(',').unwrap_or((condition, ""));
+        let kind = kind.trim().to_uppercase();
+        if matches!(kind.as_str(), "AND" | "OR" | "NOT") {
+            return self.matches_logic(&kind, rest, destination);
+        }
+        let mut parts = rest.split(',').map(str::trim);
+        let payload = parts.next().unwrap_or_default();
+        let no_resolve = parts.any(|param| param == "no-resolve");
+        let domain = destination.domain().map(str::to_lowercase);
+        let domain = domain.as_deref();
+        let payload_lower = payload.to_lowercase();
+        Ok(match kind.as_str() {
+            "MATCH" | "FINAL" => true,
+            "DOMAIN" => domain == Some(payload_lower.as_str()),
+            "DOMAIN-SUFFIX" => domain.is_some_and(|d| is_subdomain(d, &payload_lower)),
+            "DOMAIN-KEYWORD" => domain.is_some_and(|d| d.contains(payload_lower.as_str())),
+            "DOMAIN-WILDCARD" => domain.is_some_and(|d| wildcard_matches(&payload_lower, d)),
+            "DOMAIN-REGEX" => {
+                let regex = Regex::new(payload).map_err(|_| "has a regex Rust can't compile")?;
+                domain.is_some_and(|d| regex.is_match(d))
+            }
+            "GEOSITE" => match domain {
+                Some(domain) => self.geosite()?.contains(payload, domain),
+                None => false,
+            },
+            "IP-CIDR" | "IP-CIDR6" => destination
+                .ip(no_resolve)
+                .is_some_and(|ip| prefix_contains(payload, ip)),
+            "GEOIP" => match destination.ip(no_resolve) {
+                Some(ip) if payload.eq_ignore_ascii_case("LAN") => geoip::is_lan(ip),
+                Some(ip) => self
+                    .countries(ip)?
+                    .iter()
+                    .any(|code| code.eq_ignore_ascii_case(payload)),
+                None => false,
+            },
+            "IP-ASN" => match destination.ip(no_resolve) {
+                Some(ip) => {
+                    let record = self
+                        .asn()?
+                        .lookup(ip)
+                        .map_err(|e| format!("can't read ASN.mmdb: {e}"))?;
+                    record
+                        .as_ref()
+                        .and_then(geoip::asn)
+                        .is_some_and(|(number, _)| payload == number.to_string())
+                }
+                None => false,
+            },
+            "DST-PORT" => port_matches(payload, destination.port),
+            // The destinations are tried as TCP connections
+            "NETWORK" => payload.eq_ignore_ascii_case("tcp"),
+            "RULE-SET" => self.matches_provider(payload, destination)?,
+            _ => {
+                return Err(format!(
+                    "{kind} depends on the connection, not its destination"
+                ))
+            }
+        })
+    }
+
+    /// `AND`, `OR` and `NOT` over conditions like `((DOMAIN,a.com),(NETWORK,TCP))`.
+    fn matches_logic(
+        &self,
+        kind: &str,
+        payload: &str,
+        destination: &Destination,
+    ) -> Result<bool, String> {
+        let conditions = logic_conditions(payload).ok_or("can't be parsed")?;
+        let results: Vec<Result<bool, String>> = conditions
+            .iter()
+            .map(|condition| self.matches(condition, destination))
+            .collect();
+        let known = |value: bool| results.iter().any(|r| r.as_ref() == Ok(&value));
+        let first_error = || {
+            results
+                .iter()
+                .find_map(|r| r.clone().err())
+                .unwrap_or_default()
+        };
+        match kind {
+            "AND" if known(false) => Ok(false),
+            "OR" if known(true) => Ok(true),
+            "AND" | "OR" if results.iter().all(Result::is_ok) => Ok(kind == "AND"),
+            "NOT" => match results.as_slice() {
+                [Ok(value)] => Ok(!value),
+                [Err(reason)] => Err(reason.clone()),
+                _ => Err("NOT takes one condition".to_string()),
+            },
+            _ => Err(first_error()),
+        }
+    }
+
+    fn matches_provider(&self, name: &str, destination: &Destination) -> Result<bool, String> {
+        let provider = self
+            .providers
+            .get(name)
+            .ok_or_else(|| format!("{name} is not in rule-providers"))?;
+        Ok(match provider {
+            Provider::Domain(domains) => destination.domain().is_some_and(|domain| {
+                let domain = domain.to_lowercase();
+                domains
+                    .iter()
+                    .any(|entry| domain_entry_matches(entry, &domain))
+            }),
+            Provider::IpCidr(cidrs) => destination
+                .ip(false)
+                .is_some_and(|ip| cidrs.iter().any(|cidr| prefix_contains(cidr, ip))),
+            Provider::Classical(rules) => {
+                let mut unknown = None;
+                for rule in rules {
+                    match self.matches(rule, destination) {
+                        Ok(true) => return Ok(true),
+                        Ok(false) => {}
+                        Err(reason) => unknown = Some(reason),
+                    }
+                }
+                if let Some(reason) = unknown {
+                    return Err(format!("{name} has a rule that {reason}"));
+                }
+                false
+            }
+            Provider::Unavailable(reason) => return Err(reason.clone()),
+        })
+    }
+
+    fn geosite(&self) -> Result<&GeoSite, String> {
+        self.geosite
+            .get_or_init(|| {
+                GeoSite::open(&self.geosite_path)
+                    .map_err(|_| "needs geosite.dat, run `proxy geo update`".to_string())
+            })
+            .as_ref()
+            .map_err(Clone::clone)
+    }
+
+    /// Country codes of `ip`, from country.mmdb or geoip.dat in geodata mode.
+    fn countries(&self, ip: IpAddr) -> Result<Vec<String>, String> {
+        let file = self
+            .country_path
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy();
+        let missing = || format!("needs {file}, run `proxy geo update`");
+        let db = self
+            .country
+            .get_or_init(|| {
+                if !self.country_path.exists() {
+                    return Err(missing());
+                }
+                if file == "geoip.dat" {
+                    return Ok(None);
+                }
+                Mmdb::open(&self.country_path)
+                    .map(Some)
+                    .map_err(|e| e.to_string())
+            })
+            .as_ref()
+            .map_err(Clone::clone)?;
+        match db {
+            Some(db) => Ok(db
+                .lookup(ip)
+                .map_err(|e| e.to_string())?
+                .map(|record| geoip::country_codes(&record))
+                .unwrap_or_default()),
+            None => geoip::dat_country_codes(&self.country_path, ip).map_err(|e| e.to_string()),
+        }
+    }
+
+    fn asn(&self) -> Result<&Mmdb, String> {
+        self.asn
+            .get_or_init(|| {
+                Mmdb::open(&self.asn_path).map_err(|_| {
+                    "needs ASN.mmdb, which Mihomo downloads once it runs an IP-ASN rule".to_string()
+                })
+            })
+            .as_ref()
+            .map_err(Clone::clone)
+    }
+
+    /// What `target` is: a proxy group, a node or a built-in target.
+    fn describe(&self, target: &str) -> String {
+        if BUILT_IN_TARGETS.contains(&target) {
+            return target.to_string();
+        }
+        match self.groups.get(target) {
+            Some((kind, 0)) => format!("{target} ({kind} group)"),
+            Some((kind, members)) => format!("{target} ({kind} group, {members} members)"),
+            None => format!("{target} (node)"),
+        }
+    }
+
+    /// Prints `verdict` like `which` prints the route the core picked.
+    pub fn print(&self, verdict: &Verdict) {
+        let host = if verdict.host.contains(':') {
+            format!("[{}]:{}", verdict.host, verdict.port)
+        } else {
+            format!("{}:{}", verdict.host, verdict.port)
+        };
+        println!("{}", host.bold());
+        if let Some(ip) = verdict.resolved {
+            println!("  IP:      {ip} (system resolver, Mihomo's DNS may answer differently)");
+        }
+        match &verdict.rule {
+            Some((index, rule)) => println!("  Rule:    #{index} {rule}"),
+            None => println!("  Rule:    none matched, Mihomo sends it DIRECT"),
+        }
+        println!("  Target:  {}", self.describe(&verdict.target).green());
+        if verdict.skipped.is_empty() {
+            return;
+        }
+        println!(
+            "  {}",
+            format!(
+                "Skipped {} rules that can't be evaluated without the core:",
+                verdict.skipped.len()
+            )
+            .yellow()
+        );
+        for (index, rule, reason) in verdict.skipped.iter().take(SKIPPED_SHOWN) {
+            println!("    #{index} {rule}: {reason}");
+        }
+        if verdict.skipped.len() > SKIPPED_SHOWN {
+            println!("    ...");
+        }
+    }
+}
+
+/// The condition and target of `rule`, the params after the target belong to
+/// the condition: `IP-CIDR,10.0.0.0/8,DIRECT,no-resolve` gives
+/// (`IP-CIDR,10.0.0.0/8,no-resolve`, `DIRECT`), `MATCH,Proxy` (`MATCH`, `Proxy`).
+fn split_rule(rule: &str) -> Option<(String, String)> {
+    let (kind, rest) = rule.split_once(',')?;
+    let kind_upper = kind.trim().to_uppercase();
+    if matches!(kind_upper.as_str(), "MATCH" | "FINAL") {
+        return Some((kind.to_string(), rest.trim().to_string()));
+    }
+    let payload_end = if matches!(kind_upper.as_str(), "AND" | "OR" | "NOT") {
+        balanced_end(rest)?
+    } else {
+        rest.find(',')?
+    };
+    let (payload, after) = rest.split_at(payload_end);
+    let mut after = after.trim_start_matches(',').split(',').map(str::trim);
+    let target = after.next()?.to_string();
+    let condition = std::iter::once(format!("{kind},{payload}"))
+        .chain(after.map(String::from))
+        .collect::<Vec<_>>()
+        .join(",");
+    Some((condition, target))
+}
+
+/// Length of the parenthesized group `text` starts with.
+fn balanced_end(text: &str) -> Option<usize> {
+    let mut depth = 0;
+    for (i, c) in text.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i + 1);
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+/// The conditions of a logic rule's payload, `((A,a),(B,b))` gives `A,a` and `B,b`.
+fn logic_conditions(payload: &str) -> Option<Vec<String>> {
+    let payload = payload.trim();
+    let end = balanced_end(payload)?;
+    let inner = payload.get(1..end - 1)?.trim();
+    let mut conditions = Vec::new();
+    let mut rest = inner;
+    while !rest.is_empty() {
+        let end = balanced_end(rest)?;
+        conditions.push(rest.get(1..end - 1)?.trim().to_string());
+        rest = rest[end..].trim_start_matches([',', ' ']);
+    }
+    Some(conditions)
+}
+
+fn is_subdomain(domain: &str, suffix: &str) -> bool {
+    domain == suffix || domain.ends_with(&format!(".{suffix}"))
+}
+
+/// `*` matches any run of characters and `?` one, as in `DOMAIN-WILDCARD`.
+fn wildcard_matches(pattern: &str, text: &str) -> bool {
+    let pattern = format!(
+        "^{}$",
+        regex::escape(pattern)
+            .replace(r"\*", ".*")
+            .replace(r"\?", ".")
+    );
+    Regex::new(&pattern).is_ok_and(|regex| regex.is_match(text))
+}
+
+/// An entry of a domain rule-provider: `+.a.com` is a.com and its subdomains,
+/// `.a.com` only the subdomains, `*` one label, anything else the exact domain.
+fn domain_entry_matches(entry: &str, domain: &str) -> bool {
+    let entry = entry.to_lowercase();
+    if let Some(suffix) = entry.strip_prefix("+.") {
+        is_subdomain(domain, suffix)
+    } else if entry.starts_with('.') {
+        domain.ends_with(&entry)
+    } else if entry.contains('*') {
+        let pattern = format!("^{}$", regex::escape(&entry).replace(r"\*", "[^.]+"));
+        Regex::new(&pattern).is_ok_and(|regex| regex.is_match(domain))
+    } else {
+        entry == domain
+    }
+}
+
+/// `DST-PORT` payloads: `443`, `8000-9000`, or several joined by `/`.
+fn port_matches(payload: &str, port: u16) -> bool {
+    payload.split('/').any(|range| match range.split_once('-') {
+        Some((low, high)) => {
+            let (Ok(low), Ok(high)) = (low.trim().parse::<u16>(), high.trim().parse::<u16>())
+            else {
+                return false;
+            };
+            (low..=high).contains(&port)
+        }
+        None => range.trim().parse() == Ok(port),
+    })
+}
+
+/// Reads the rules of a `rule-providers` entry: inline, or from the file of a
+/// `file` provider or the download of an `http` one.
+fn load_provider(config_dir: &Path, provider: &Value) -> Provider {
+    let field = |name: &str| provider.get(name).and_then(Value::as_str);
+    let format = field("format").unwrap_or("yaml");
+    let entries: Vec<String> = if field("type") == Some("inline") {
+        provider
+            .get("payload")
+            .and_then(Value::as_sequence)
+            .into_iter()
+            .flatten()
+            .filter_map(Value::as_str)
+            .map(String::from)
+            .collect()
+    } else {
+        let Some(path) = field("path") else {
+            return Provider::Unavailable(
+                "uses a rule-provider without a path, whose download can't be found".to_string(),
+            );
+        };
+        if format == "mrs" {
+            return Provider::Unavailable("uses a rule-provider in the binary mrs format".into());
+        }
+        let path = config_dir.join(path);
+        let Ok(content) = fs::read_to_string(&path) else {
+            return Provider::Unavailable(format!(
+                "uses {}, which is not downloaded yet, start the core once",
+                path.display()
+            ));
+        };
+        if format == "text" {
+            content
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                .map(String::from)
+                .collect()
+        } else {
+            serde_yaml::from_str::<Value>(&content)
+                .ok()
+                .and_then(|yaml| yaml.get("payload").and_then(Value::as_sequence).cloned())
+                .into_iter()
+                .flatten()
+                .filter_map(|entry| entry.as_str().map(|s| s.trim().to_string()))
+                .collect()
+        }
+    };
+    match field("behavior").unwrap_or("classical") {
+        "domain" => Provider::Domain(entries),
+        "ipcidr" => Provider::IpCidr(entries),
+        _ => Provider::Classical(entries),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parts(condition: &str, target: &str) -> Option<(String, String)> {
+        Some((condition.to_string(), target.to_string()))
+    }
+
+    #[test]
+    fn splits_rules() {
+        assert_eq!(
+            split_rule("DOMAIN-SUFFIX,example.com,Proxy"),
+            parts("DOMAIN-SUFFIX,example.com", "Proxy")
+        );
+        assert_eq!(
+            split_rule("IP-CIDR,10.0.0.0/8,DIRECT,no-resolve"),
+            parts("IP-CIDR,10.0.0.0/8,no-resolve", "DIRECT")
+        );
+        assert_eq!(split_rule("MATCH,Proxy"), parts("MATCH", "Proxy"));
+        assert_eq!(
+            split_rule("AND,((DOMAIN,a.com),(NETWORK,TCP)),REJECT"),
+            parts("AND,((DOMAIN,a.com),(NETWORK,TCP))", "REJECT")
+        );
+        assert_eq!(split_rule("DOMAIN,a.com"), None);
+        assert_eq!(split_rule("MATCH"), None);
+        assert_eq!(split_rule("AND,((DOMAIN,a.com),DIRECT"), None);
+    }
+
+    #[test]
+    fn finds_balanced_groups() {
+        assert_eq!(balanced_end("(a,(b))"), Some(7));
+        assert_eq!(balanced_end("(a),(b)"), Some(3));
+        assert_eq!(balanced_end("((a)"), None);
+        assert_eq!(balanced_end("a"), None);
+    }
+
+    #[test]
+    fn parses_logic_conditions() {
+        assert_eq!(
+            logic_conditions("((DOMAIN,a.com), (NETWORK,UDP))").unwrap(),
+            ["DOMAIN,a.com", "NETWORK,UDP"]
+        );
+        assert_eq!(
+            logic_conditions("((OR,((DOMAIN,a.com),(DOMAIN,b.com))))").unwrap(),
+            ["OR,((DOMAIN,a.com),(DOMAIN,b.com))"]
+        );
+        assert_eq!(logic_conditions("(())").unwrap(), [""]);
+        assert_eq!(logic_conditions("((DOMAIN,a.com)"), None);
+        assert_eq!(logic_conditions("(DOMAIN)"), None);
+    }
+
+    #[test]
+    fn matches_ports() {
+        assert!(port_matches("443", 443));
+        assert!(!port_matches("443", 80));
+        assert!(port_matches("8000-9000", 8000));
+        assert!(port_matches("8000-9000", 9000));
+        assert!(!port_matches("8000-9000", 9001));
+        assert!(port_matches("80/443/1000-2000", 1500));
+        assert!(!port_matches("80/443", 8080));
+        assert!(!port_matches("x-9000", 100));
+    }
+
+    #[test]
+    fn matches_domain_entries() {
+        assert!(domain_entry_matches("+.example.com", "example.com"));
+        assert!(domain_entry_matches("+.example.com", "a.b.example.com"));
+        assert!(!domain_entry_matches("+.example.com", "badexample.com"));
+        assert!(domain_entry_matches(".example.com", "a.example.com"));
+        assert!(!domain_entry_matches(".example.com", "example.com"));
+        assert!(domain_entry_matches("*.example.com", "a.example.com"));
+        assert!(!domain_entry_matches("*.example.com", "a.b.example.com"));
+        assert!(domain_entry_matches("Example.com", "example.com"));
+        assert!(!domain_entry_matches("example.com", "a.example.com"));
+    }
+
+    #[test]
+    fn matches_wildcards() {
+        assert!(wildcard_matches("*.example.com", "a.b.example.com"));
+        assert!(wildcard_matches("ad?.example.com", "ads.example.com"));
+        assert!(!wildcard_matches("ad?.example.com", "adss.example.com"));
+        assert!(is_subdomain("example.com", "example.com"));
+        assert!(!is_subdomain("myexample.com", "example.com"));
+    }
+}
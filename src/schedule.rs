@@ -0,0 +1,379 @@
+use anyhow::{anyhow, Context, Result};
+use jiff::civil::{Date, DateTime};
+use jiff::Zoned;
+use serde::Deserialize;
+use std::fmt;
+use std::str::FromStr;
+
+/// Candidate times tried before deciding an expression never matches, e.g. `0 0 30 2 *`.
+const MAX_STEPS: usize = 100_000;
+
+const MONTHS: &[&str] = &[
+    "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+];
+const WEEKDAYS: &[&str] = &["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+
+/// `[schedule]` in proxy-rs.toml: cron expressions for the jobs run by `schedule`
+/// and by a watchdog started with `start --watch`, e.g. `refresh-subscription = "0 */6 * * *"`.
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct ScheduleSettings {
+    /// Download the subscription again, like `sub refresh`
+    pub refresh_subscription: Option<String>,
+    /// Refresh geodata older than a day, like `update-geodata`
+    pub update_geodata: Option<String>,
+    /// Install a newer core release and restart the core if it is running
+    pub update_core: Option<String>,
+    /// Switch the main selector group to the node with the lowest delay
+    pub reselect: Option<String>,
+    /// Record the node delays and traffic for `stats`
+    pub sample_stats: Option<String>,
+}
+
+impl ScheduleSettings {
+    /// The configured jobs with their parsed expressions.
+    pub fn jobs(&self) -> Result<Vec<(Job, Cron)>> {
+        [
+            (Job::RefreshSubscription, &self.refresh_subscription),
+            (Job::UpdateGeodata, &self.update_geodata),
+            (Job::UpdateCore, &self.update_core),
+            (Job::Reselect, &self.reselect),
+            (Job::SampleStats, &self.sample_stats),
+        ]
+        .into_iter()
+        .filter_map(|(job, expression)| expression.as_deref().map(|e| (job, e)))
+        .map(|(job, expression)| {
+            let cron = expression
+                .parse()
+                .with_context(|| format!("Invalid schedule for {job}"))?;
+            Ok((job, cron))
+        })
+        .collect()
+    }
+}
+
+/// A recurring maintenance job.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Job {
+    RefreshSubscription,
+    UpdateGeodata,
+    UpdateCore,
+    Reselect,
+    SampleStats,
+}
+
+impl Job {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Job::RefreshSubscription => "refresh-subscription",
+            Job::UpdateGeodata => "update-geodata",
+            Job::UpdateCore => "update-core",
+            Job::Reselect => "reselect",
+            Job::SampleStats => "sample-stats",
+        }
+    }
+}
+
+impl fmt::Display for Job {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(self.as_str())
+    }
+}
+
+/// A five-field cron expression (minute, hour, day of month, month, day of week)
+/// evaluated in local time. Fields take `*`, `N`, `N-M`, `*/S`, `N-M/S` and
+/// lists of those, months and weekdays also take names like `jan` and `mon`.
+/// `@hourly`, `@daily`, `@weekly`, `@monthly` and `@yearly` are shorthands.
+#[derive(Debug, Clone)]
+pub struct Cron {
+    expression: String,
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    /// Whether the day of month and the day of week fields start with `*`.
+    /// As in cron, a day matches either field when neither does, and both
+    /// fields otherwise, so `0 0 1,15 * mon` runs on the 1st, the 15th and
+    /// every Monday while `0 0 */2 * mon` runs on odd-numbered Mondays.
+    any_day: bool,
+    any_weekday: bool,
+}
+
+impl FromStr for Cron {
+    type Err = anyhow::Error;
+
+    fn from_str(expression: &str) -> Result<Self> {
+        let expanded = match expression.trim() {
+            "@hourly" => "0 * * * *",
+            "@daily" | "@midnight" => "0 0 * * *",
+            "@weekly" => "0 0 * * 0",
+            "@monthly" => "0 0 1 * *",
+            "@yearly" | "@annually" => "0 0 1 1 *",
+            other => other,
+        };
+        let fields: Vec<&str> = expanded.split_whitespace().collect();
+        let [minute, hour, day, month, weekday] = fields[..] else {
+            return Err(anyhow!(
+                "expected 5 fields (minute hour day month weekday), got {}",
+                fields.len()
+            ));
+        };
+        // 7 is Sunday too
+        let weekdays = parse_field(weekday, 0, 7, WEEKDAYS).context("day of week")?;
+        Ok(Cron {
+            expression: expression.trim().to_string(),
+            minutes: parse_field(minute, 0, 59, &[]).context("minute")?,
+            hours: parse_field(hour, 0, 23, &[]).context("hour")?,
+            days: parse_field(day, 1, 31, &[]).context("day of month")?,
+            months: parse_field(month, 1, 12, MONTHS).context("month")?,
+            weekdays: (weekdays | weekdays >> 7) & 0x7f,
+            any_day: day.starts_with('*'),
+            any_weekday: weekday.starts_with('*'),
+        })
+    }
+}
+
+impl fmt::Display for Cron {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(&self.expression)
+    }
+}
+
+impl Cron {
+    /// The first matching minute after `time`, `None` if the expression never matches.
+    pub fn next_after(&self, time: &Zoned) -> Option<Zoned> {
+        let start = time.datetime();
+        let mut candidate = start.date().at(start.hour(), start.minute(), 0, 0);
+        candidate = next_minute(candidate)?;
+        for _ in 0..MAX_STEPS {
+            let date = candidate.date();
+            candidate = if !bit(self.months, date.month()) {
+                date.last_of_month().tomorrow().ok()?.at(0, 0, 0, 0)
+            } else if !self.matches_day(date) {
+                date.tomorrow().ok()?.at(0, 0, 0, 0)
+            } else if !bit(self.hours, candidate.hour()) {
+                next_hour(candidate)?
+            } else if !bit(self.minutes, candidate.minute()) {
+                next_minute(candidate)?
+            } else {
+                return candidate.to_zoned(time.time_zone().clone()).ok();
+            };
+        }
+        None
+    }
+
+    fn matches_day(&self, date: Date) -> bool {
+        let day = bit(self.days, date.day());
+        let weekday = bit(self.weekdays, date.weekday().to_sunday_zero_offset());
+        match (self.any_day, self.any_weekday) {
+            (false, false) => day || weekday,
+            _ => day && weekday,
+        }
+    }
+}
+
+fn bit(set: u64, value: i8) -> bool {
+    set & (1 << value) != 0
+}
+
+fn next_minute(time: DateTime) -> Option<DateTime> {
+    match time.minute() {
+        59 => next_hour(time),
+        minute => Some(time.date().at(time.hour(), minute + 1, 0, 0)),
+    }
+}
+
+fn next_hour(time: DateTime) -> Option<DateTime> {
+    match time.hour() {
+        23 => Some(time.date().tomorrow().ok()?.at(0, 0, 0, 0)),
+        hour => Some(time.date().at(hour + 1, 0, 0, 0)),
+    }
+}
+
+/// Parses one field into a bit set of the values it matches.
+fn parse_field(field: &str, min: u8, max: u8, names: &[&str]) -> Result<u64> {
+    let value = |s: &str| -> Result<u8> {
+        let lower = s.to_ascii_lowercase();
+        let value = match names.iter().position(|name| *name == lower) {
+            // Months count from 1, weekdays from 0
+            Some(index) => index as u8 + min,
+            None => s.parse().map_err(|_| anyhow!("invalid value `{s}`"))?,
+        };
+        if !(min..=max).contains(&value) {
+            return Err(anyhow!("{value} is not within {min}-{max}"));
+        }
+        Ok(value)
+    };
+
+    let mut set = 0;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => {
+                let step: u8 = step
+                    .parse()
+                    .ok()
+                    .filter(|step| *step > 0)
+                    .ok_or_else(|| anyhow!("invalid step `{step}`"))?;
+                (range, step)
+            }
+            None => (part, 1),
+        };
+        let (first, last) = match range {
+            "*" => (min, max),
+            range => match range.split_once('-') {
+                Some((first, last)) => (value(first)?, value(last)?),
+                // `N/S` runs from N to the end of the range
+                None if step > 1 => (value(range)?, max),
+                None => {
+                    let value = value(range)?;
+                    (value, value)
+                }
+            },
+        };
+        if first > last {
+            return Err(anyhow!("invalid range `{range}`"));
+        }
+        for value in (first..=last).step_by(step as usize) {
+            set |= 1 << value;
+        }
+    }
+    Ok(set)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cron(expression: &str) -> Cron {
+        expression.parse().unwrap()
+    }
+
+    /// The next `count` times `expression` matches after `start`, in UTC.
+    fn runs(expression: &str, start: &str, count: usize) -> Vec<String> {
+        let cron = cron(expression);
+        let mut time: Zoned = format!("{start}[UTC]").parse().unwrap();
+        (0..count)
+            .map(|_| {
+                time = cron.next_after(&time).unwrap();
+                time.datetime().strftime("%a %Y-%m-%d %H:%M").to_string()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn parses_fields() {
+        assert_eq!(parse_field("*", 0, 3, &[]).unwrap(), 0b1111);
+        assert_eq!(parse_field("1,3", 0, 59, &[]).unwrap(), 0b1010);
+        assert_eq!(parse_field("2-4", 0, 59, &[]).unwrap(), 0b11100);
+        assert_eq!(parse_field("*/2", 0, 5, &[]).unwrap(), 0b10101);
+        assert_eq!(parse_field("1/2", 0, 5, &[]).unwrap(), 0b101010);
+        assert_eq!(parse_field("1-5/2", 0, 59, &[]).unwrap(), 0b101010);
+        assert_eq!(parse_field("mon-wed", 0, 7, WEEKDAYS).unwrap(), 0b1110);
+        assert_eq!(parse_field("Feb", 1, 12, MONTHS).unwrap(), 0b100);
+    }
+
+    #[test]
+    fn rejects_invalid_fields() {
+        for field in ["60", "5-1", "*/0", "x", "", "1-", "-1"] {
+            assert!(parse_field(field, 0, 59, &[]).is_err(), "{field}");
+        }
+        assert!("* * * *".parse::<Cron>().is_err());
+        assert!("* * * * * *".parse::<Cron>().is_err());
+        assert!("0 0 0 * *".parse::<Cron>().is_err());
+    }
+
+    #[test]
+    fn sunday_is_0_and_7() {
+        assert_eq!(cron("0 0 * * 7").weekdays, cron("0 0 * * 0").weekdays);
+        assert_eq!(cron("0 0 * * sun").weekdays, 1);
+    }
+
+    #[test]
+    fn finds_next_minute() {
+        assert_eq!(
+            runs("*/15 * * * *", "2024-01-01T10:07", 3),
+            [
+                "Mon 2024-01-01 10:15",
+                "Mon 2024-01-01 10:30",
+                "Mon 2024-01-01 10:45"
+            ]
+        );
+        // Never the start time itself
+        assert_eq!(
+            runs("0 */6 * * *", "2024-01-01T06:00", 2),
+            ["Mon 2024-01-01 12:00", "Mon 2024-01-01 18:00"]
+        );
+    }
+
+    #[test]
+    fn expands_shorthands() {
+        assert_eq!(
+            runs("@daily", "2024-01-31T12:00", 1),
+            ["Thu 2024-02-01 00:00"]
+        );
+        assert_eq!(
+            runs("@weekly", "2024-01-01T12:00", 1),
+            ["Sun 2024-01-07 00:00"]
+        );
+        assert_eq!(
+            runs("@monthly", "2024-02-15T00:00", 1),
+            ["Fri 2024-03-01 00:00"]
+        );
+        assert_eq!(
+            runs("@yearly", "2024-02-15T00:00", 1),
+            ["Wed 2025-01-01 00:00"]
+        );
+    }
+
+    #[test]
+    fn restricted_day_fields_match_either() {
+        // The 1st and the 15th, and every Friday
+        assert_eq!(
+            runs("0 0 1,15 * fri", "2024-03-01T12:00", 4),
+            [
+                "Fri 2024-03-08 00:00",
+                "Fri 2024-03-15 00:00",
+                "Fri 2024-03-22 00:00",
+                "Fri 2024-03-29 00:00",
+            ]
+        );
+        assert_eq!(
+            runs("0 0 13 * 5", "2024-09-01T00:00", 3),
+            [
+                "Fri 2024-09-06 00:00",
+                "Fri 2024-09-13 00:00",
+                "Fri 2024-09-20 00:00"
+            ]
+        );
+    }
+
+    #[test]
+    fn starred_day_fields_match_both() {
+        // Only the weekday restricts
+        assert_eq!(
+            runs("0 0 * * mon", "2024-01-01T12:00", 2),
+            ["Mon 2024-01-08 00:00", "Mon 2024-01-15 00:00"]
+        );
+        // A field starting with `*` counts as unrestricted, so both have to match
+        assert_eq!(
+            runs("0 0 */2 * mon", "2024-01-01T12:00", 3),
+            [
+                "Mon 2024-01-15 00:00",
+                "Mon 2024-01-29 00:00",
+                "Mon 2024-02-05 00:00"
+            ]
+        );
+    }
+
+    #[test]
+    fn impossible_dates_never_match() {
+        let time: Zoned = "2024-01-01T00:00[UTC]".parse().unwrap();
+        assert!(cron("0 0 30 2 *").next_after(&time).is_none());
+        // Leap days come around
+        assert_eq!(
+            runs("0 0 29 2 *", "2024-03-01T00:00", 1),
+            ["Tue 2028-02-29 00:00"]
+        );
+    }
+}
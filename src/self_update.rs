@@ -0,0 +1,203 @@
+use crate::backend::ArchiveType;
+use anyhow::{anyhow, Context, Result};
+use serde::Deserialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// GitHub API URL of proxy-rs's latest release
+pub const LATEST_RELEASE_URL: &str =
+    "https://api.github.com/repos/sunfkny/proxy-rs/releases/latest";
+
+/// Name of the executable in the release archives
+pub const BINARY_NAME: &str = "proxy";
+
+/// A release of proxy-rs, from the releases API.
+#[derive(Debug, Deserialize)]
+pub struct Release {
+    pub tag_name: String,
+    pub assets: Vec<Asset>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Asset {
+    pub name: String,
+    pub browser_download_url: String,
+}
+
+/// Whether the release tagged `tag` is newer than `current`, comparing the
+/// numeric parts of `v1.2.3`.
+pub fn is_newer(tag: &str, current: &str) -> bool {
+    let parts = |version: &str| -> Vec<u64> {
+        version
+            .trim_start_matches('v')
+            .split(['.', '-', '+'])
+            .map_while(|part| part.parse().ok())
+            .collect()
+    };
+    parts(tag) > parts(current)
+}
+
+fn is_checksum(name: &str) -> bool {
+    let name = name.to_ascii_lowercase();
+    name.ends_with(".sha256") || name.contains("checksum") || name.contains("sha256sum")
+}
+
+/// The asset built for this platform: its name mentions the OS and the
+/// architecture the way Rust targets or Go releases spell them.
+pub fn platform_asset(release: &Release) -> Result<&Asset> {
+    let os: &[&str] = if cfg!(target_os = "windows") {
+        &["windows", "win64", "win32"]
+    } else if cfg!(target_os = "macos") {
+        &["darwin", "macos", "apple"]
+    } else {
+        &["linux"]
+    };
+    let arch: &[&str] = if cfg!(target_arch = "x86_64") {
+        &["x86_64", "amd64", "x64"]
+    } else if cfg!(target_arch = "aarch64") {
+        &["aarch64", "arm64"]
+    } else if cfg!(target_arch = "arm") {
+        &["armv7", "arm-"]
+    } else {
+        &[std::env::consts::ARCH]
+    };
+    let matches = |name: &str, keywords: &[&str]| keywords.iter().any(|k| name.contains(k));
+    release
+        .assets
+        .iter()
+        .filter(|asset| !is_checksum(&asset.name))
+        .find(|asset| {
+            let name = asset.name.to_ascii_lowercase();
+            matches(&name, os) && matches(&name, arch)
+        })
+        .ok_or_else(|| {
+            anyhow!(
+                "{} has no build for {}/{}",
+                release.tag_name,
+                std::env::consts::OS,
+                std::env::consts::ARCH
+            )
+        })
+}
+
+/// Asset holding the SHA256 of `asset`: `<asset>.sha256` or a checksums file.
+pub fn checksum_asset<'a>(release: &'a Release, asset: &Asset) -> Option<&'a Asset> {
+    let own = format!("{}.sha256", asset.name);
+    release
+        .assets
+        .iter()
+        .find(|a| a.name == own)
+        .or_else(|| release.assets.iter().find(|a| is_checksum(&a.name)))
+}
+
+/// The SHA256 of `asset_name` in a checksums file, or the only hash of a `.sha256` file.
+pub fn parse_checksum(checksums: &str, asset_name: &str) -> Option<String> {
+    let mut lines = checksums.lines().map(str::trim).filter(|l| !l.is_empty());
+    let named = lines.clone().find_map(|line| {
+        let (hash, name) = line.split_once(char::is_whitespace)?;
+        (name.trim().trim_start_matches('*') == asset_name).then(|| hash.to_string())
+    });
+    named.or_else(|| match (lines.next(), lines.next()) {
+        (Some(line), None) => line.split_whitespace().next().map(String::from),
+        _ => None,
+    })
+}
+
+/// How the asset is packed, `None` for a bare binary.
+pub fn archive_type(name: &str) -> Option<ArchiveType> {
+    if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+        Some(ArchiveType::TarGz)
+    } else if name.ends_with(".zip") {
+        Some(ArchiveType::Zip)
+    } else if name.ends_with(".gz") {
+        Some(ArchiveType::Gz)
+    } else {
+        None
+    }
+}
+
+/// Replaces the running executable with `new_binary`. The new file is copied
+/// next to it first, so the swap is a rename on the same filesystem; the old
+/// binary is kept as `.old` until the next update, Windows can't delete it while it runs.
+pub fn replace_current_exe(new_binary: &Path) -> Result<PathBuf> {
+    let exe = dunce::canonicalize(std::env::current_exe()?)?;
+    let staged = exe.with_extension("new");
+    let old = exe.with_extension("old");
+    if old.exists() {
+        let _ = fs::remove_file(&old);
+    }
+    fs::copy(new_binary, &staged).with_context(|| {
+        format!(
+            "Failed to write to {}, run with permission to replace {}",
+            staged.display(),
+            exe.display()
+        )
+    })?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&staged, fs::Permissions::from_mode(0o755))?;
+    }
+
+    fs::rename(&exe, &old)?;
+    if let Err(e) = fs::rename(&staged, &exe) {
+        fs::rename(&old, &exe)?;
+        let _ = fs::remove_file(&staged);
+        return Err(e).context(format!("Failed to replace {}", exe.display()));
+    }
+    #[cfg(unix)]
+    let _ = fs::remove_file(&old);
+    Ok(exe)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compares_versions_numerically() {
+        assert!(is_newer("v1.10.0", "1.9.9"));
+        assert!(is_newer("v2.0.0", "v1.99.99"));
+        assert!(is_newer("1.2.4", "1.2.3"));
+        assert!(!is_newer("v1.2.3", "1.2.3"));
+        assert!(!is_newer("v1.2.2", "1.2.3"));
+        // A longer version with the same prefix is newer
+        assert!(is_newer("1.2.3.1", "1.2.3"));
+    }
+
+    #[test]
+    fn ignores_prerelease_and_build_suffixes() {
+        assert!(!is_newer("v1.2.3-beta.1", "1.2.3"));
+        assert!(is_newer("v1.3.0-rc1", "1.2.3"));
+        assert!(!is_newer("v1.2.3+build", "1.2.3"));
+    }
+
+    #[test]
+    fn finds_checksum_by_name() {
+        let checksums = "\
+aaaa  proxy-x86_64-unknown-linux-gnu.tar.gz
+bbbb *proxy-aarch64-apple-darwin.tar.gz
+
+cccc  proxy-x86_64-pc-windows-msvc.zip
+";
+        assert_eq!(
+            parse_checksum(checksums, "proxy-aarch64-apple-darwin.tar.gz").as_deref(),
+            Some("bbbb")
+        );
+        assert_eq!(
+            parse_checksum(checksums, "proxy-x86_64-pc-windows-msvc.zip").as_deref(),
+            Some("cccc")
+        );
+        assert_eq!(parse_checksum(checksums, "proxy-riscv64.tar.gz"), None);
+    }
+
+    #[test]
+    fn takes_the_only_hash_of_a_sha256_file() {
+        assert_eq!(parse_checksum("dddd\n", "any").as_deref(), Some("dddd"));
+        assert_eq!(
+            parse_checksum("eeee  other-name.tar.gz", "any").as_deref(),
+            Some("eeee")
+        );
+        assert_eq!(parse_checksum("", "any"), None);
+    }
+}
@@ -0,0 +1,269 @@
+use crate::backend::CoreKind;
+use crate::hooks::Hook;
+use crate::network::NetworkChangeSettings;
+use crate::schedule::ScheduleSettings;
+use crate::tunnel::TunnelSettings;
+use crate::utils::{default_data_dir, local_data_dir};
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub const SETTINGS_FILE: &str = "proxy-rs.toml";
+const GITHUB_MIRRORS_ENV: &str = "PROXY_RS_GITHUB_MIRRORS";
+/// Bypassed when `no-proxy` is unset: loopback, the private networks of RFC 1918
+/// and mDNS names.
+pub const DEFAULT_NO_PROXY: &[&str] = &[
+    "localhost",
+    // For tools without CIDR support, like curl before 7.86
+    "127.0.0.1",
+    "127.0.0.0/8",
+    "::1",
+    "10.0.0.0/8",
+    "172.16.0.0/12",
+    "192.168.0.0/16",
+    "*.local",
+];
+
+/// Defaults for proxy-rs, read from `proxy-rs.toml` in the data dir.
+/// Command line flags take precedence over these.
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct Settings {
+    /// Proxy core to run, `mihomo` or `sing-box`
+    pub core: CoreKind,
+    /// Architecture in the core's release asset names, e.g. `amd64-compatible`
+    /// or `armv6`, detected by default
+    pub core_arch: Option<String>,
+    /// Existing core binary to run instead of downloading one, e.g. one
+    /// packaged by the distro
+    pub core_path: Option<PathBuf>,
+    /// Unprivileged user the core runs as when proxy-rs is started as root, on
+    /// Linux only. The binary is granted the capabilities TUN needs instead
+    pub run_as: Option<String>,
+    /// Subscription `start` downloads without a URL when none is saved yet,
+    /// on the first start and with `--update-sub`
+    pub subscription_url: Option<String>,
+    /// subconverter `/sub` endpoint for subscriptions that are not Clash configs,
+    /// e.g. `https://sub.example.com/sub`
+    pub subconverter: Option<String>,
+    /// Preferred mixed-port, the next free port is used if it is taken
+    pub mixed_port: Option<u16>,
+    /// Preferred external controller port, the next free port is used if it is taken
+    pub controller_port: Option<u16>,
+    /// Extra GitHub mirrors, e.g. `https://ghproxy.example.com/`
+    pub github_mirrors: Vec<String>,
+    /// Use only `github-mirrors` instead of adding them to the built-in list
+    pub replace_default_mirrors: bool,
+    /// Download attempts per mirror before switching to the next one, 3 by default
+    pub download_retries: Option<u32>,
+    /// Whether downloads go through the running Mihomo's mixed-port
+    pub via_proxy: ViaProxy,
+    /// Proxy for downloads, e.g. `http://proxy.corp:3128` or `socks5://127.0.0.1:1080`,
+    /// `HTTPS_PROXY`, `HTTP_PROXY` and `ALL_PROXY` are used when unset
+    pub download_proxy: Option<String>,
+    /// Hosts, domains like `*.corp.example` and CIDRs the `on` scripts and the
+    /// system proxy send directly, [`DEFAULT_NO_PROXY`] when unset
+    pub no_proxy: Option<Vec<String>>,
+    /// WebUI served by the external controller
+    pub ui: WebUi,
+    /// Keep everything in this directory instead, only read from the default data dir
+    pub data_dir: Option<PathBuf>,
+    /// Downloads shared by every data dir on this machine, the platform cache
+    /// dir by default, e.g. `~/.cache/proxy-rs`
+    pub cache_dir: Option<PathBuf>,
+    /// Install the latest Mihomo release on `start` unless a version is pinned
+    pub auto_update_core: bool,
+    /// Refresh geodata older than a day on `start`
+    pub auto_update_geodata: bool,
+    /// Also report outdated core, WebUI and geodata in `status`, like `check-update`
+    pub check_update_on_status: bool,
+    /// Size in MiB at which the logs of a Mihomo supervised by `--watch` are rotated, 10 by default
+    pub log_max_size_mb: Option<u64>,
+    /// Rotated `mihomo.log.N`/`mihomo.err.N` files kept, 3 by default
+    pub log_keep: Option<usize>,
+    /// Remaining traffic in percent below which the `quota-low` hook fires, 10 by default
+    pub quota_low_percent: Option<u8>,
+    /// Select the nodes picked in each group again after the core restarts or reloads
+    pub sticky_selection: bool,
+    /// URL of the delay tests of url-test groups, `test`, `bench` and the
+    /// checks after `start`, Google's `generate_204` by default
+    pub delay_test_url: Option<String>,
+    /// Seconds between the delay tests of url-test and fallback groups, the
+    /// subscription's own interval is kept when unset
+    pub delay_test_interval: Option<u64>,
+    /// Memory in MiB the core may use before the OS kills it, through a cgroup v2
+    /// on Linux and a job object on Windows
+    pub memory_limit_mb: Option<u64>,
+    /// Niceness of the core from -20 to 19, higher values yield the CPU to other work
+    pub nice: Option<i32>,
+    /// Open files the core may have, on Unix only
+    pub open_files_limit: Option<u64>,
+    /// Port of `/healthz` while proxy-rs runs the core in the foreground or watches it
+    pub health_port: Option<u16>,
+    /// Seconds `stop` waits for the core to exit on its own before killing it, 5 by default
+    pub stop_timeout: Option<u64>,
+    /// Git remote `sync` pushes the configs to and pulls them from, e.g.
+    /// `git@github.com:me/proxy-config.git`
+    pub sync_remote: Option<String>,
+    /// Commands and webhooks run on events, see [`Hook`]
+    pub hooks: Vec<Hook>,
+    /// Self-hosted tunnel endpoints, see [`TunnelSettings`]
+    pub tunnel: TunnelSettings,
+    /// Recurring maintenance jobs, see [`ScheduleSettings`]
+    pub schedule: ScheduleSettings,
+    /// What the watchdog does after sleep or a network switch, see [`NetworkChangeSettings`]
+    pub network_change: NetworkChangeSettings,
+}
+
+/// When proxy-rs's own downloads use the running Mihomo as their proxy.
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ViaProxy {
+    /// Use it if the instance is running, falling back to direct downloads
+    #[default]
+    Auto,
+    /// Always use it, failing if the instance is not running
+    Always,
+    /// Always download directly or through the GitHub mirrors
+    Never,
+}
+
+/// The WebUIs proxy-rs can download and serve, selected with `start --ui` or
+/// `ui` in proxy-rs.toml.
+#[derive(Deserialize, clap::ValueEnum, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum WebUi {
+    #[default]
+    Metacubexd,
+    Yacd,
+    Zashboard,
+    /// Serve no WebUI, the controller API still works
+    None,
+}
+
+impl WebUi {
+    /// The WebUIs that are downloaded, each into its own folder.
+    pub const ALL: [WebUi; 3] = [WebUi::Metacubexd, WebUi::Yacd, WebUi::Zashboard];
+
+    /// Directory name in the data dir, also the value in proxy-rs.toml.
+    pub fn name(&self) -> &'static str {
+        match self {
+            WebUi::Metacubexd => "metacubexd",
+            WebUi::Yacd => "yacd",
+            WebUi::Zashboard => "zashboard",
+            WebUi::None => "none",
+        }
+    }
+
+    /// (GitHub download URL of the gh-pages archive, folder the zip extracts to),
+    /// `None` for [`WebUi::None`]
+    pub(crate) fn release(&self) -> Option<(&'static str, &'static str)> {
+        match self {
+            WebUi::Metacubexd => Some((
+                "https://github.com/MetaCubeX/metacubexd/archive/refs/heads/gh-pages.zip",
+                "metacubexd-gh-pages",
+            )),
+            WebUi::Yacd => Some((
+                "https://github.com/MetaCubeX/Yacd-meta/archive/refs/heads/gh-pages.zip",
+                "Yacd-meta-gh-pages",
+            )),
+            WebUi::Zashboard => Some((
+                "https://github.com/Zephyruso/zashboard/archive/refs/heads/gh-pages.zip",
+                "zashboard-gh-pages",
+            )),
+            WebUi::None => None,
+        }
+    }
+}
+
+impl Settings {
+    pub fn load(path: &Path) -> Result<Self> {
+        let mut settings = if path.exists() {
+            let content = fs::read_to_string(path)?;
+            toml::from_str(&content).with_context(|| format!("Invalid {}", path.display()))?
+        } else {
+            Settings::default()
+        };
+
+        if let Ok(mirrors) = std::env::var(GITHUB_MIRRORS_ENV) {
+            settings.github_mirrors = mirrors
+                .split(',')
+                .map(str::trim)
+                .filter(|m| !m.is_empty())
+                .map(String::from)
+                .collect();
+        }
+
+        Ok(settings)
+    }
+}
+
+/// Sets `key` to `value` in the settings file at `path`, creating it if needed.
+/// The other settings and the comments are kept.
+pub fn save_setting(path: &Path, key: &str, value: impl Into<toml_edit::Value>) -> Result<()> {
+    let mut document = if path.exists() {
+        let content = fs::read_to_string(path)?;
+        content
+            .parse::<toml_edit::DocumentMut>()
+            .with_context(|| format!("Invalid {}", path.display()))?
+    } else {
+        toml_edit::DocumentMut::new()
+    };
+    document[key] = toml_edit::value(value);
+    fs::write(path, document.to_string())?;
+    Ok(())
+}
+
+/// Removes `key` from the settings file at `path`, if both exist.
+pub fn remove_setting(path: &Path, key: &str) -> Result<()> {
+    if !path.exists() {
+        return Ok(());
+    }
+    let content = fs::read_to_string(path)?;
+    let mut document = content
+        .parse::<toml_edit::DocumentMut>()
+        .with_context(|| format!("Invalid {}", path.display()))?;
+    if document.remove(key).is_some() {
+        fs::write(path, document.to_string())?;
+    }
+    Ok(())
+}
+
+/// The data dir to use: `explicit` (from `--data-dir`) if given, `./proxy-data`
+/// with `local`, otherwise the `data-dir` set in the global data dir's
+/// `proxy-rs.toml`, otherwise the global data dir.
+pub fn resolve_data_dir(explicit: Option<PathBuf>, local: bool) -> Result<PathBuf> {
+    if let Some(dir) = explicit {
+        return Ok(dir);
+    }
+    if local {
+        return Ok(local_data_dir());
+    }
+    let default = default_data_dir();
+    let settings = Settings::load(&default.join(SETTINGS_FILE))?;
+    Ok(settings.data_dir.unwrap_or(default))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_comments() {
+        let dir = std::env::temp_dir().join(format!("proxy-rs-settings-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(SETTINGS_FILE);
+        fs::write(&path, "# My ports\nmixed-port = 7890 # fixed\n").unwrap();
+        save_setting(&path, "ui", "yacd").unwrap();
+        save_setting(&path, "mixed-port", 7891).unwrap();
+        let content = fs::read_to_string(&path).unwrap();
+        assert!(content.starts_with("# My ports\n"), "{content}");
+        assert!(content.contains("mixed-port = 7891"), "{content}");
+        assert!(content.contains("ui = \"yacd\""), "{content}");
+        remove_setting(&path, "ui").unwrap();
+        let content = fs::read_to_string(&path).unwrap();
+        assert!(content.starts_with("# My ports\n") && !content.contains("ui"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
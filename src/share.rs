@@ -0,0 +1,18 @@
+use anyhow::Result;
+use qrcode::render::unicode::Dense1x2;
+use qrcode::QrCode;
+
+/// Prints `label`, `text` and a QR code of `text` that phones can scan from the terminal.
+pub fn print_qr(label: &str, text: &str) -> Result<()> {
+    let code = QrCode::new(text.as_bytes())?;
+    // Block characters are drawn in the text color, which is light on most terminals
+    let image = code
+        .render::<Dense1x2>()
+        .dark_color(Dense1x2::Light)
+        .light_color(Dense1x2::Dark)
+        .quiet_zone(true)
+        .build();
+    println!("{label}: {text}");
+    println!("{image}");
+    Ok(())
+}
@@ -0,0 +1,138 @@
+const HTTP_PROXY_VARS: &[&str] = &["http_proxy", "HTTP_PROXY", "https_proxy", "HTTPS_PROXY"];
+const ALL_PROXY_VARS: &[&str] = &["all_proxy", "ALL_PROXY"];
+const NO_PROXY_VARS: &[&str] = &["no_proxy", "NO_PROXY"];
+
+/// Shells the `on`/`off` environment scripts are generated for.
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Shell {
+    #[value(name = "bash", aliases = ["sh", "zsh"])]
+    Sh,
+    Fish,
+    Powershell,
+    Cmd,
+}
+
+impl Shell {
+    /// The scripts written on `start`: sh and fish on Unix, PowerShell and cmd on Windows.
+    pub fn platform_defaults() -> &'static [Shell] {
+        if cfg!(windows) {
+            &[Shell::Powershell, Shell::Cmd]
+        } else {
+            &[Shell::Sh, Shell::Fish]
+        }
+    }
+
+    /// The shell `env` prints for when `--shell` is not given: PowerShell on
+    /// Windows, fish if it is the login shell, otherwise sh.
+    pub fn detect() -> Shell {
+        if cfg!(windows) {
+            Shell::Powershell
+        } else if std::env::var("SHELL").is_ok_and(|shell| shell.ends_with("fish")) {
+            Shell::Fish
+        } else {
+            Shell::Sh
+        }
+    }
+
+    /// Script file name for `on`/`off`, e.g. `on.ps1`.
+    pub fn script_name(&self, name: &str) -> String {
+        match self {
+            Shell::Sh => name.to_string(),
+            Shell::Fish => format!("{name}.fish"),
+            Shell::Powershell => format!("{name}.ps1"),
+            Shell::Cmd => format!("{name}.bat"),
+        }
+    }
+
+    /// How to run a script in the current session of this shell.
+    pub fn source_command(&self, script: &str) -> String {
+        match self {
+            Shell::Sh | Shell::Fish => format!("source {script}"),
+            Shell::Powershell => format!(". {script}"),
+            Shell::Cmd => format!("call {script}"),
+        }
+    }
+
+    pub fn on_script(&self, http_url: &str, all_url: &str, no_proxy: &[String]) -> String {
+        self.header() + &self.exports(http_url, all_url, no_proxy)
+    }
+
+    /// Lines that set the variables of [`proxy_vars`].
+    pub fn exports(&self, http_url: &str, all_url: &str, no_proxy: &[String]) -> String {
+        let mut script = String::new();
+        for (var, value) in proxy_vars(http_url, all_url, no_proxy) {
+            let line = match self {
+                Shell::Sh => format!("export {var}=\"{value}\""),
+                Shell::Fish => format!("set -gx {var} \"{value}\""),
+                Shell::Powershell => format!("$env:{var} = \"{value}\""),
+                Shell::Cmd => format!("set \"{var}={value}\""),
+            };
+            script.push_str(&line);
+            script.push('\n');
+        }
+        script
+    }
+
+    /// Lines that unset everything set by [`Self::exports`].
+    pub fn off_script(&self) -> String {
+        let mut script = self.header();
+        let vars = HTTP_PROXY_VARS
+            .iter()
+            .chain(ALL_PROXY_VARS)
+            .chain(NO_PROXY_VARS);
+        match self {
+            Shell::Sh => {
+                let vars: Vec<&str> = vars.copied().collect();
+                script.push_str(&format!("unset {}\n", vars.join(" ")));
+            }
+            Shell::Fish => {
+                let vars: Vec<&str> = vars.copied().collect();
+                script.push_str(&format!("set -e {}\n", vars.join(" ")));
+            }
+            Shell::Powershell => {
+                for var in vars {
+                    script.push_str(&format!(
+                        "Remove-Item Env:{var} -ErrorAction SilentlyContinue\n"
+                    ));
+                }
+            }
+            Shell::Cmd => {
+                for var in vars {
+                    script.push_str(&format!("set {var}=\n"));
+                }
+            }
+        }
+        script
+    }
+
+    fn header(&self) -> String {
+        match self {
+            Shell::Sh => "#!/bin/sh\n",
+            Shell::Cmd => "@echo off\n",
+            Shell::Fish | Shell::Powershell => "",
+        }
+        .to_string()
+    }
+}
+
+/// The proxy environment variables: `http_proxy`/`https_proxy` point at
+/// `http_url`, `all_proxy` at `all_url`, e.g. a `socks5://` one, and
+/// `no_proxy` lists the hosts of `no_proxy`.
+pub fn proxy_vars(
+    http_url: &str,
+    all_url: &str,
+    no_proxy: &[String],
+) -> Vec<(&'static str, String)> {
+    // curl, wget, Go and Python match `.local` but not `*.local` as a suffix
+    let no_proxy = no_proxy
+        .iter()
+        .map(|host| host.strip_prefix('*').unwrap_or(host))
+        .collect::<Vec<_>>()
+        .join(",");
+    HTTP_PROXY_VARS
+        .iter()
+        .map(|&var| (var, http_url.to_string()))
+        .chain(ALL_PROXY_VARS.iter().map(|&var| (var, all_url.to_string())))
+        .chain(NO_PROXY_VARS.iter().map(|&var| (var, no_proxy.clone())))
+        .collect()
+}
@@ -0,0 +1,897 @@
+//! Converts sing-box JSON configs into Mihomo configs, for providers that
+//! only offer sing-box subscriptions.
+
+use crate::controller::DEFAULT_DELAY_TEST_URL;
+use anyhow::{anyhow, Context, Result};
+use log::*;
+use serde_json::Value as Json;
+use serde_yaml::{Mapping, Value};
+use std::collections::HashMap;
+
+/// Name of the group created when the config has no selector.
+const DEFAULT_GROUP: &str = "PROXY";
+
+/// Whether `content` is a sing-box config, a JSON object with an `outbounds` list.
+pub fn is_sing_box_config(content: &str) -> bool {
+    serde_json::from_str::<Json>(content)
+        .is_ok_and(|json| json.get("outbounds").is_some_and(Json::is_array))
+}
+
+/// Turns the outbounds of a sing-box config into Mihomo `proxies`, its
+/// selector and urltest outbounds into `proxy-groups` and the domain and IP
+/// matchers of its route rules into `rules`. Unsupported outbounds and rules
+/// are left out with a warning.
+pub fn to_mihomo_config(content: &str) -> Result<String> {
+    let json: Json = serde_json::from_str(content).context("Invalid JSON")?;
+    let outbounds = json
+        .get("outbounds")
+        .and_then(Json::as_array)
+        .ok_or_else(|| anyhow!("The sing-box config has no outbounds"))?;
+
+    // What each tag is called in the Mihomo config, unsupported tags are missing
+    let mut names: HashMap<&str, String> = HashMap::new();
+    let mut proxies = Vec::new();
+    let mut skipped = Vec::new();
+    for outbound in outbounds {
+        let (Some(kind), Some(tag)) = (str_of(outbound, "type"), str_of(outbound, "tag")) else {
+            continue;
+        };
+        match kind {
+            "direct" => {
+                names.insert(tag, "DIRECT".to_string());
+            }
+            "block" => {
+                names.insert(tag, "REJECT".to_string());
+            }
+            "selector" | "urltest" | "dns" => {}
+            _ => match convert_outbound(kind, tag, outbound) {
+                Some(proxy) => {
+                    names.insert(tag, tag.to_string());
+                    proxies.push(Value::Mapping(proxy));
+                }
+                None => skipped.push(format!("{tag} ({kind})")),
+            },
+        }
+    }
+    if !skipped.is_empty() {
+        warn!(
+            "Skipping {} sing-box outbound(s) Mihomo can't use: {}",
+            skipped.len(),
+            skipped.join(", ")
+        );
+    }
+    if proxies.is_empty() {
+        return Err(anyhow!("The sing-box config has no nodes Mihomo supports"));
+    }
+
+    // Groups are named before they are built, they may list each other
+    let groups: Vec<&Json> = outbounds
+        .iter()
+        .filter(|o| matches!(str_of(o, "type"), Some("selector" | "urltest")))
+        .collect();
+    for group in &groups {
+        if let Some(tag) = str_of(group, "tag") {
+            names.insert(tag, tag.to_string());
+        }
+    }
+    let mut proxy_groups: Vec<Value> = groups
+        .iter()
+        .filter_map(|group| convert_group(group, &names))
+        .map(Value::Mapping)
+        .collect();
+    if proxy_groups.is_empty() {
+        let members = proxies
+            .iter()
+            .filter_map(|proxy| proxy.get("name").cloned())
+            .collect();
+        let mut group = Mapping::new();
+        group.insert("name".into(), DEFAULT_GROUP.into());
+        group.insert("type".into(), "select".into());
+        group.insert("proxies".into(), Value::Sequence(members));
+        proxy_groups.push(Value::Mapping(group));
+    }
+
+    let route = json.get("route");
+    let mut rules = route
+        .and_then(|route| route.get("rules"))
+        .and_then(Json::as_array)
+        .map(|rules| convert_rules(rules, &names))
+        .unwrap_or_default();
+    let main_group = proxy_groups[0]
+        .get("name")
+        .and_then(Value::as_str)
+        .unwrap_or(DEFAULT_GROUP)
+        .to_string();
+    let last = route
+        .and_then(|route| str_of(route, "final"))
+        .and_then(|tag| names.get(tag).cloned())
+        .unwrap_or(main_group);
+    rules.push(format!("MATCH,{last}").into());
+
+    let mut config = Mapping::new();
+    config.insert("mixed-port".into(), 7890.into());
+    config.insert("mode".into(), "rule".into());
+    config.insert("proxies".into(), Value::Sequence(proxies));
+    config.insert("proxy-groups".into(), Value::Sequence(proxy_groups));
+    config.insert("rules".into(), Value::Sequence(rules));
+    Ok(serde_yaml::to_string(&Value::Mapping(config))?)
+}
+
+fn str_of<'a>(json: &'a Json, key: &str) -> Option<&'a str> {
+    json.get(key).and_then(Json::as_str)
+}
+
+/// A string or a list of strings, as sing-box accepts both in many places.
+fn strings_of(json: &Json, key: &str) -> Vec<String> {
+    match json.get(key) {
+        Some(Json::String(s)) => vec![s.clone()],
+        Some(Json::Array(items)) => items
+            .iter()
+            .filter_map(|item| item.as_str().map(String::from))
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Copies `key` of `json` to `target` of `proxy` if it is set.
+fn copy(proxy: &mut Mapping, json: &Json, key: &str, target: &str) {
+    if let Some(value) = json.get(key).filter(|value| !value.is_null()) {
+        if let Ok(value) = serde_yaml::to_value(value) {
+            proxy.insert(target.into(), value);
+        }
+    }
+}
+
+/// One node, `None` for outbound types Mihomo has no equivalent of.
+fn convert_outbound(kind: &str, tag: &str, outbound: &Json) -> Option<Mapping> {
+    let mut proxy = Mapping::new();
+    proxy.insert("name".into(), tag.into());
+    let mihomo_type = match kind {
+        "shadowsocks" => "ss",
+        "socks" => "socks5",
+        "vmess" | "vless" | "trojan" | "hysteria" | "hysteria2" | "tuic" | "http" | "anytls" => {
+            kind
+        }
+        _ => return None,
+    };
+    proxy.insert("type".into(), mihomo_type.into());
+    copy(&mut proxy, outbound, "server", "server");
+    copy(&mut proxy, outbound, "server_port", "port");
+
+    match kind {
+        "shadowsocks" => {
+            copy(&mut proxy, outbound, "method", "cipher");
+            copy(&mut proxy, outbound, "password", "password");
+            proxy.insert("udp".into(), true.into());
+            if let Some(plugin) = str_of(outbound, "plugin") {
+                let options = str_of(outbound, "plugin_opts").unwrap_or_default();
+                convert_plugin(&mut proxy, plugin, options)?;
+            }
+        }
+        "vmess" => {
+            copy(&mut proxy, outbound, "uuid", "uuid");
+            proxy.insert(
+                "alterId".into(),
+                outbound
+                    .get("alter_id")
+                    .and_then(Json::as_u64)
+                    .unwrap_or(0)
+                    .into(),
+            );
+            proxy.insert(
+                "cipher".into(),
+                str_of(outbound, "security").unwrap_or("auto").into(),
+            );
+            proxy.insert("udp".into(), true.into());
+        }
+        "vless" => {
+            copy(&mut proxy, outbound, "uuid", "uuid");
+            copy(&mut proxy, outbound, "flow", "flow");
+            copy(&mut proxy, outbound, "packet_encoding", "packet-encoding");
+            proxy.insert("udp".into(), true.into());
+        }
+        "trojan" | "anytls" => {
+            copy(&mut proxy, outbound, "password", "password");
+            proxy.insert("udp".into(), true.into());
+        }
+        "hysteria" => {
+            copy(&mut proxy, outbound, "auth_str", "auth-str");
+            copy(&mut proxy, outbound, "obfs", "obfs");
+            copy(&mut proxy, outbound, "up_mbps", "up");
+            copy(&mut proxy, outbound, "down_mbps", "down");
+        }
+        "hysteria2" => {
+            copy(&mut proxy, outbound, "password", "password");
+            copy(&mut proxy, outbound, "up_mbps", "up");
+            copy(&mut proxy, outbound, "down_mbps", "down");
+            if let Some(obfs) = outbound.get("obfs") {
+                copy(&mut proxy, obfs, "type", "obfs");
+                copy(&mut proxy, obfs, "password", "obfs-password");
+            }
+        }
+        "tuic" => {
+            copy(&mut proxy, outbound, "uuid", "uuid");
+            copy(&mut proxy, outbound, "password", "password");
+            copy(
+                &mut proxy,
+                outbound,
+                "congestion_control",
+                "congestion-controller",
+            );
+            copy(&mut proxy, outbound, "udp_relay_mode", "udp-relay-mode");
+            copy(&mut proxy, outbound, "zero_rtt_handshake", "reduce-rtt");
+        }
+        "socks" | "http" => {
+            copy(&mut proxy, outbound, "username", "username");
+            copy(&mut proxy, outbound, "password", "password");
+            if kind == "socks" {
+                proxy.insert("udp".into(), true.into());
+            }
+        }
+        _ => {}
+    }
+
+    if let Some(tls) = outbound
+        .get("tls")
+        .filter(|tls| tls.get("enabled").and_then(Json::as_bool) == Some(true))
+    {
+        convert_tls(&mut proxy, kind, tls);
+    }
+    if let Some(transport) = outbound.get("transport") {
+        convert_transport(&mut proxy, transport, outbound.get("tls").is_some())?;
+    }
+    Some(proxy)
+}
+
+/// `obfs-local` and `v2ray-plugin` with their `key=value;...` options.
+fn convert_plugin(proxy: &mut Mapping, plugin: &str, options: &str) -> Option<()> {
+    let pairs: Vec<(&str, &str)> = options
+        .split(';')
+        .map(|pair| pair.split_once('=').unwrap_or((pair, "")))
+        .collect();
+    let get = |key: &str| pairs.iter().find(|(k, _)| *k == key).map(|(_, v)| *v);
+    let mut opts = Mapping::new();
+    match plugin {
+        "obfs-local" | "simple-obfs" => {
+            proxy.insert("plugin".into(), "obfs".into());
+            opts.insert("mode".into(), get("obfs").unwrap_or("http").into());
+            if let Some(host) = get("obfs-host") {
+                opts.insert("host".into(), host.into());
+            }
+        }
+        "v2ray-plugin" => {
+            proxy.insert("plugin".into(), "v2ray-plugin".into());
+            opts.insert("mode".into(), get("mode").unwrap_or("websocket").into());
+            opts.insert("tls".into(), get("tls").is_some().into());
+            for key in ["host", "path"] {
+                if let Some(value) = get(key) {
+                    opts.insert(key.into(), value.into());
+                }
+            }
+        }
+        _ => return None,
+    }
+    proxy.insert("plugin-opts".into(), Value::Mapping(opts));
+    Some(())
+}
+
+/// The `tls` object, which Mihomo spreads over the node's own keys.
+fn convert_tls(proxy: &mut Mapping, kind: &str, tls: &Json) {
+    // VMess and VLESS call the SNI servername and need TLS turned on
+    let sni_key = match kind {
+        "vmess" | "vless" => {
+            proxy.insert("tls".into(), true.into());
+            "servername"
+        }
+        "http" => {
+            proxy.insert("tls".into(), true.into());
+            "sni"
+        }
+        _ => "sni",
+    };
+    copy(proxy, tls, "server_name", sni_key);
+    copy(proxy, tls, "insecure", "skip-cert-verify");
+    let alpn = strings_of(tls, "alpn");
+    if !alpn.is_empty() {
+        proxy.insert(
+            "alpn".into(),
+            Value::Sequence(alpn.into_iter().map(Value::from).collect()),
+        );
+    }
+    if let Some(utls) = tls.get("utls") {
+        copy(proxy, utls, "fingerprint", "client-fingerprint");
+    }
+    if let Some(reality) = tls
+        .get("reality")
+        .filter(|reality| reality.get("enabled").and_then(Json::as_bool) == Some(true))
+    {
+        let mut opts = Mapping::new();
+        copy(&mut opts, reality, "public_key", "public-key");
+        copy(&mut opts, reality, "short_id", "short-id");
+        proxy.insert("reality-opts".into(), Value::Mapping(opts));
+    }
+}
+
+/// The V2Ray `transport` object, `None` for transports Mihomo lacks.
+fn convert_transport(proxy: &mut Mapping, transport: &Json, tls: bool) -> Option<()> {
+    let host = |transport: &Json| {
+        transport
+            .get("headers")
+            .map(|headers| strings_of(headers, "Host"))
+            .filter(|host| !host.is_empty())
+            .unwrap_or_else(|| strings_of(transport, "host"))
+    };
+    let mut opts = Mapping::new();
+    let network = match str_of(transport, "type")? {
+        kind @ ("ws" | "httpupgrade") => {
+            copy(&mut opts, transport, "path", "path");
+            if let Some(host) = host(transport).first() {
+                let mut headers = Mapping::new();
+                headers.insert("Host".into(), host.as_str().into());
+                opts.insert("headers".into(), Value::Mapping(headers));
+            }
+            copy(&mut opts, transport, "max_early_data", "max-early-data");
+            copy(
+                &mut opts,
+                transport,
+                "early_data_header_name",
+                "early-data-header-name",
+            );
+            if kind == "httpupgrade" {
+                opts.insert("v2ray-http-upgrade".into(), true.into());
+            }
+            "ws"
+        }
+        "grpc" => {
+            copy(&mut opts, transport, "service_name", "grpc-service-name");
+            "grpc"
+        }
+        // sing-box speaks HTTP/2 when TLS is on, plain HTTP/1.1 otherwise
+        "http" if tls => {
+            copy(&mut opts, transport, "path", "path");
+            let host = host(transport);
+            if !host.is_empty() {
+                opts.insert(
+                    "host".into(),
+                    Value::Sequence(host.into_iter().map(Value::from).collect()),
+                );
+            }
+            "h2"
+        }
+        "http" => {
+            if let Some(path) = str_of(transport, "path") {
+                opts.insert("path".into(), Value::Sequence(vec![path.into()]));
+            }
+            let host = host(transport);
+            if !host.is_empty() {
+                let mut headers = Mapping::new();
+                headers.insert(
+                    "Host".into(),
+                    Value::Sequence(host.into_iter().map(Value::from).collect()),
+                );
+                opts.insert("headers".into(), Value::Mapping(headers));
+            }
+            "http"
+        }
+        _ => return None,
+    };
+    proxy.insert("network".into(), network.into());
+    proxy.insert(format!("{network}-opts").into(), Value::Mapping(opts));
+    Some(())
+}
+
+/// A `selector` or `urltest` outbound, members Mihomo doesn't know are dropped.
+fn convert_group(group: &Json, names: &HashMap<&str, String>) -> Option<Mapping> {
+    let tag = str_of(group, "tag")?;
+    let mut members: Vec<String> = strings_of(group, "outbounds")
+        .iter()
+        .filter_map(|member| names.get(member.as_str()).cloned())
+        .collect();
+    // Mihomo selects the first member, sing-box the `default` one
+    if let Some(default) = str_of(group, "default").and_then(|tag| names.get(tag)) {
+        members.retain(|member| member != default);
+        members.insert(0, default.clone());
+    }
+    if members.is_empty() {
+        members.push("DIRECT".to_string());
+    }
+
+    let mut converted = Mapping::new();
+    converted.insert("name".into(), tag.into());
+    if str_of(group, "type") == Some("urltest") {
+        converted.insert("type".into(), "url-test".into());
+        converted.insert(
+            "url".into(),
+            str_of(group, "url")
+                .unwrap_or(DEFAULT_DELAY_TEST_URL)
+                .into(),
+        );
+        let interval = str_of(group, "interval")
+            .and_then(parse_duration)
+            .unwrap_or(180);
+        converted.insert("interval".into(), interval.into());
+        copy(&mut converted, group, "tolerance", "tolerance");
+    } else {
+        converted.insert("type".into(), "select".into());
+    }
+    converted.insert(
+        "proxies".into(),
+        Value::Sequence(members.into_iter().map(Value::from).collect()),
+    );
+    Some(converted)
+}
+
+/// Seconds in a Go duration like `3m`, `1m30s` or `300s`.
+fn parse_duration(duration: &str) -> Option<u64> {
+    let mut seconds = 0.0;
+    let mut rest = duration;
+    while !rest.is_empty() {
+        let number_end = rest
+            .find(|c: char| !c.is_ascii_digit() && c != '.')
+            .unwrap_or(rest.len());
+        let number: f64 = rest[..number_end].parse().ok()?;
+        rest = &rest[number_end..];
+        let unit_end = rest
+            .find(|c: char| c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        seconds += number
+            * match &rest[..unit_end] {
+                "h" => 3600.0,
+                "m" => 60.0,
+                "s" => 1.0,
+                "ms" => 0.001,
+                _ => return None,
+            };
+        rest = &rest[unit_end..];
+    }
+    Some(seconds as u64)
+}
+
+/// Matchers sing-box ORs together, each becomes its own Mihomo rule.
+const RULE_MATCHERS: &[&str] = &[
+    "domain",
+    "domain_suffix",
+    "domain_keyword",
+    "domain_regex",
+    "geosite",
+    "geoip",
+    "ip_cidr",
+    "ip_is_private",
+];
+
+/// Route rules that only match domains and IPs, others can't be expressed
+/// as Mihomo rules one by one and are skipped.
+fn convert_rules(rules: &[Json], names: &HashMap<&str, String>) -> Vec<Value> {
+    let mut converted = Vec::new();
+    let mut skipped = 0;
+    for rule in rules {
+        let policy = match str_of(rule, "action") {
+            None | Some("route") => str_of(rule, "outbound")
+                .and_then(|tag| names.get(tag))
+                .cloned(),
+            Some("reject") => Some("REJECT".to_string()),
+            // hijack-dns, sniff and resolve don't route
+            Some(_) => continue,
+        };
+        let Some(policy) = policy else {
+            skipped += 1;
+            continue;
+        };
+        let Some(object) = rule.as_object() else {
+            continue;
+        };
+        let only_matchers = object.keys().all(|key| {
+            RULE_MATCHERS.contains(&key.as_str()) || key == "outbound" || key == "action"
+        });
+        if !only_matchers {
+            skipped += 1;
+            continue;
+        }
+
+        let mut add = |kind: &str, value: &str, no_resolve: bool| {
+            let suffix = if no_resolve { ",no-resolve" } else { "" };
+            converted.push(Value::from(format!("{kind},{value},{policy}{suffix}")));
+        };
+        for domain in strings_of(rule, "domain") {
+            add("DOMAIN", &domain, false);
+        }
+        for suffix in strings_of(rule, "domain_suffix") {
+            add("DOMAIN-SUFFIX", suffix.trim_start_matches('.'), false);
+        }
+        for keyword in strings_of(rule, "domain_keyword") {
+            add("DOMAIN-KEYWORD", &keyword, false);
+        }
+        for regex in strings_of(rule, "domain_regex") {
+            add("DOMAIN-REGEX", &regex, false);
+        }
+        for site in strings_of(rule, "geosite") {
+            add("GEOSITE", &site, false);
+        }
+        for country in strings_of(rule, "geoip") {
+            let country = if country == "private" {
+                "LAN".to_string()
+            } else {
+                country.to_uppercase()
+            };
+            add("GEOIP", &country, true);
+        }
+        for cidr in strings_of(rule, "ip_cidr") {
+            let kind = if cidr.contains(':') {
+                "IP-CIDR6"
+            } else {
+                "IP-CIDR"
+            };
+            add(kind, &cidr, true);
+        }
+        if rule.get("ip_is_private").and_then(Json::as_bool) == Some(true) {
+            add("GEOIP", "LAN", true);
+        }
+    }
+    if skipped > 0 {
+        warn!(
+            "Skipping {skipped} sing-box route rule(s) that match more than domains and IPs, or use rule sets"
+        );
+    }
+    converted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    /// The Mihomo node for `outbound`, compared as YAML.
+    fn convert(outbound: Json) -> Option<Value> {
+        let kind = str_of(&outbound, "type").unwrap();
+        let tag = str_of(&outbound, "tag").unwrap();
+        convert_outbound(kind, tag, &outbound).map(Value::Mapping)
+    }
+
+    fn yaml(content: &str) -> Value {
+        serde_yaml::from_str(content).unwrap()
+    }
+
+    #[test]
+    fn converts_shadowsocks() {
+        let outbound = json!({
+            "type": "shadowsocks", "tag": "ss", "server": "1.2.3.4", "server_port": 8388,
+            "method": "aes-128-gcm", "password": "pw",
+            "plugin": "obfs-local", "plugin_opts": "obfs=tls;obfs-host=example.com",
+        });
+        assert_eq!(
+            convert(outbound),
+            Some(yaml(
+                "
+name: ss
+type: ss
+server: 1.2.3.4
+port: 8388
+cipher: aes-128-gcm
+password: pw
+udp: true
+plugin: obfs
+plugin-opts: {mode: tls, host: example.com}
+"
+            ))
+        );
+    }
+
+    #[test]
+    fn skips_unknown_shadowsocks_plugins() {
+        let outbound = json!({
+            "type": "shadowsocks", "tag": "ss", "method": "aes-128-gcm", "password": "pw",
+            "plugin": "kcptun",
+        });
+        assert_eq!(convert(outbound), None);
+    }
+
+    #[test]
+    fn converts_vmess_over_websocket() {
+        let outbound = json!({
+            "type": "vmess", "tag": "vm", "server": "a.com", "server_port": 443,
+            "uuid": "id", "security": "aes-128-gcm",
+            "tls": {"enabled": true, "server_name": "sni.com", "insecure": true},
+            "transport": {"type": "ws", "path": "/ws", "headers": {"Host": "cdn.com"}},
+        });
+        assert_eq!(
+            convert(outbound),
+            Some(yaml(
+                "
+name: vm
+type: vmess
+server: a.com
+port: 443
+uuid: id
+alterId: 0
+cipher: aes-128-gcm
+udp: true
+tls: true
+servername: sni.com
+skip-cert-verify: true
+network: ws
+ws-opts: {path: /ws, headers: {Host: cdn.com}}
+"
+            ))
+        );
+    }
+
+    #[test]
+    fn converts_vless_reality_over_grpc() {
+        let outbound = json!({
+            "type": "vless", "tag": "vl", "server": "a.com", "server_port": 443,
+            "uuid": "id", "flow": "xtls-rprx-vision",
+            "tls": {
+                "enabled": true, "server_name": "www.microsoft.com",
+                "utls": {"enabled": true, "fingerprint": "chrome"},
+                "reality": {"enabled": true, "public_key": "pk", "short_id": "ab"},
+            },
+            "transport": {"type": "grpc", "service_name": "svc"},
+        });
+        assert_eq!(
+            convert(outbound),
+            Some(yaml(
+                "
+name: vl
+type: vless
+server: a.com
+port: 443
+uuid: id
+flow: xtls-rprx-vision
+udp: true
+tls: true
+servername: www.microsoft.com
+client-fingerprint: chrome
+reality-opts: {public-key: pk, short-id: ab}
+network: grpc
+grpc-opts: {grpc-service-name: svc}
+"
+            ))
+        );
+    }
+
+    #[test]
+    fn converts_trojan_and_anytls() {
+        for kind in ["trojan", "anytls"] {
+            let outbound = json!({
+                "type": kind, "tag": "t", "server": "a.com", "server_port": 443,
+                "password": "pw",
+                "tls": {"enabled": true, "server_name": "a.com", "alpn": ["h2", "http/1.1"]},
+            });
+            assert_eq!(
+                convert(outbound),
+                Some(yaml(&format!(
+                    "
+name: t
+type: {kind}
+server: a.com
+port: 443
+password: pw
+udp: true
+sni: a.com
+alpn: [h2, http/1.1]
+"
+                )))
+            );
+        }
+    }
+
+    #[test]
+    fn converts_hysteria() {
+        let outbound = json!({
+            "type": "hysteria", "tag": "hy", "server": "a.com", "server_port": 443,
+            "auth_str": "secret", "obfs": "xplus", "up_mbps": 50, "down_mbps": 100,
+        });
+        assert_eq!(
+            convert(outbound),
+            Some(yaml(
+                "
+name: hy
+type: hysteria
+server: a.com
+port: 443
+auth-str: secret
+obfs: xplus
+up: 50
+down: 100
+"
+            ))
+        );
+    }
+
+    #[test]
+    fn converts_hysteria2() {
+        let outbound = json!({
+            "type": "hysteria2", "tag": "hy2", "server": "a.com", "server_port": 443,
+            "password": "pw", "obfs": {"type": "salamander", "password": "obfs"},
+            "tls": {"enabled": true, "server_name": "a.com"},
+        });
+        assert_eq!(
+            convert(outbound),
+            Some(yaml(
+                "
+name: hy2
+type: hysteria2
+server: a.com
+port: 443
+password: pw
+obfs: salamander
+obfs-password: obfs
+sni: a.com
+"
+            ))
+        );
+    }
+
+    #[test]
+    fn converts_tuic() {
+        let outbound = json!({
+            "type": "tuic", "tag": "tu", "server": "a.com", "server_port": 443,
+            "uuid": "id", "password": "pw", "congestion_control": "bbr",
+            "udp_relay_mode": "native", "zero_rtt_handshake": true,
+        });
+        assert_eq!(
+            convert(outbound),
+            Some(yaml(
+                "
+name: tu
+type: tuic
+server: a.com
+port: 443
+uuid: id
+password: pw
+congestion-controller: bbr
+udp-relay-mode: native
+reduce-rtt: true
+"
+            ))
+        );
+    }
+
+    #[test]
+    fn converts_socks_and_http() {
+        let socks = json!({
+            "type": "socks", "tag": "s", "server": "a.com", "server_port": 1080,
+            "username": "u", "password": "p",
+        });
+        assert_eq!(
+            convert(socks),
+            Some(yaml(
+                "{name: s, type: socks5, server: a.com, port: 1080, username: u, password: p, udp: true}"
+            ))
+        );
+        let http = json!({
+            "type": "http", "tag": "h", "server": "a.com", "server_port": 443,
+            "tls": {"enabled": true, "server_name": "a.com"},
+        });
+        assert_eq!(
+            convert(http),
+            Some(yaml(
+                "{name: h, type: http, server: a.com, port: 443, tls: true, sni: a.com}"
+            ))
+        );
+    }
+
+    #[test]
+    fn converts_http_transports() {
+        let h2 = json!({
+            "type": "vmess", "tag": "v", "uuid": "id",
+            "tls": {"enabled": true},
+            "transport": {"type": "http", "path": "/p", "host": ["a.com", "b.com"]},
+        });
+        let converted = convert(h2).unwrap();
+        assert_eq!(converted["network"], Value::from("h2"));
+        assert_eq!(
+            converted["h2-opts"],
+            yaml("{path: /p, host: [a.com, b.com]}")
+        );
+
+        let plain = json!({
+            "type": "vmess", "tag": "v", "uuid": "id",
+            "transport": {"type": "http", "path": "/p", "host": "a.com"},
+        });
+        let converted = convert(plain).unwrap();
+        assert_eq!(converted["network"], Value::from("http"));
+        assert_eq!(
+            converted["http-opts"],
+            yaml("{path: [/p], headers: {Host: [a.com]}}")
+        );
+
+        let upgrade = json!({
+            "type": "vless", "tag": "v", "uuid": "id",
+            "transport": {"type": "httpupgrade", "path": "/u"},
+        });
+        let converted = convert(upgrade).unwrap();
+        assert_eq!(converted["network"], Value::from("ws"));
+        assert_eq!(
+            converted["ws-opts"],
+            yaml("{path: /u, v2ray-http-upgrade: true}")
+        );
+    }
+
+    #[test]
+    fn skips_unsupported_outbounds() {
+        assert_eq!(convert(json!({"type": "wireguard", "tag": "wg"})), None);
+        let quic = json!({
+            "type": "vmess", "tag": "v", "uuid": "id", "transport": {"type": "quic"},
+        });
+        assert_eq!(convert(quic), None);
+    }
+
+    #[test]
+    fn converts_groups_and_rules() {
+        let config = json!({
+            "outbounds": [
+                {"type": "selector", "tag": "select", "outbounds": ["auto", "a", "wg"], "default": "a"},
+                {"type": "urltest", "tag": "auto", "outbounds": ["a", "b"], "interval": "1m30s"},
+                {"type": "shadowsocks", "tag": "a", "method": "aes-128-gcm", "password": "pw"},
+                {"type": "trojan", "tag": "b", "password": "pw"},
+                {"type": "wireguard", "tag": "wg"},
+                {"type": "direct", "tag": "direct"},
+                {"type": "block", "tag": "block"},
+            ],
+            "route": {
+                "rules": [
+                    {"action": "sniff"},
+                    {"domain_suffix": [".cn"], "geoip": ["private", "cn"], "outbound": "direct"},
+                    {"ip_cidr": ["10.0.0.0/8", "fd00::/8"], "action": "reject"},
+                    {"rule_set": "ads", "outbound": "block"},
+                ],
+                "final": "select",
+            },
+        });
+        let converted = yaml(&to_mihomo_config(&config.to_string()).unwrap());
+        assert_eq!(
+            converted["proxy-groups"],
+            yaml(&format!(
+                "
+- {{name: select, type: select, proxies: [a, auto]}}
+- {{name: auto, type: url-test, url: '{DEFAULT_DELAY_TEST_URL}', interval: 90, proxies: [a, b]}}
+"
+            ))
+        );
+        assert_eq!(
+            converted["rules"],
+            yaml(
+                "
+- DOMAIN-SUFFIX,cn,DIRECT
+- GEOIP,LAN,DIRECT,no-resolve
+- GEOIP,CN,DIRECT,no-resolve
+- IP-CIDR,10.0.0.0/8,REJECT,no-resolve
+- IP-CIDR6,fd00::/8,REJECT,no-resolve
+- MATCH,select
+"
+            )
+        );
+    }
+
+    #[test]
+    fn adds_a_group_when_there_is_none() {
+        let config = json!({
+            "outbounds": [{"type": "trojan", "tag": "b", "password": "pw"}],
+        });
+        let converted = yaml(&to_mihomo_config(&config.to_string()).unwrap());
+        assert_eq!(
+            converted["proxy-groups"],
+            yaml("[{name: PROXY, type: select, proxies: [b]}]")
+        );
+        assert_eq!(converted["rules"], yaml("['MATCH,PROXY']"));
+    }
+
+    #[test]
+    fn rejects_configs_without_nodes() {
+        assert!(to_mihomo_config(r#"{"outbounds": [{"type": "direct", "tag": "d"}]}"#).is_err());
+        assert!(to_mihomo_config("{}").is_err());
+        assert!(!is_sing_box_config("proxies: []"));
+        assert!(is_sing_box_config(r#"{"outbounds": []}"#));
+    }
+
+    #[test]
+    fn parses_go_durations() {
+        assert_eq!(parse_duration("3m"), Some(180));
+        assert_eq!(parse_duration("1m30s"), Some(90));
+        assert_eq!(parse_duration("1h"), Some(3600));
+        assert_eq!(parse_duration("1500ms"), Some(1));
+        assert_eq!(parse_duration("5x"), None);
+        assert_eq!(parse_duration("m"), None);
+    }
+}
@@ -0,0 +1,175 @@
+use crate::controller::{Controller, Mode, DEFAULT_DELAY_TEST_URL, DEFAULT_DELAY_TIMEOUT_MS};
+use crate::latency;
+use anyhow::{anyhow, Result};
+use clap::ValueEnum;
+use colored::Colorize;
+use futures_util::StreamExt;
+use log::*;
+use reqwest::Client;
+use serde::Serialize;
+use std::time::{Duration, Instant};
+
+/// Cloudflare's speed test endpoint, 50 MB, the test usually stops earlier
+pub const DEFAULT_SPEEDTEST_URL: &str = "https://speed.cloudflare.com/__down?bytes=50000000";
+pub const DEFAULT_SPEEDTEST_SECS: u64 = 10;
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+/// Group that routes all traffic in global mode
+const GLOBAL_GROUP: &str = "GLOBAL";
+
+/// How [`run_speedtest`] picks the nodes and how long it downloads.
+#[derive(Debug)]
+pub struct SpeedtestOptions {
+    /// Only test the members of this proxy group
+    pub group: Option<String>,
+    /// Payload downloaded through each node
+    pub url: String,
+    /// URL of the delay test that ranks the nodes first
+    pub delay_test_url: String,
+    /// Longest time spent downloading through one node
+    pub duration: Duration,
+    /// Only test this many nodes with the lowest delay
+    pub top: Option<usize>,
+}
+
+impl Default for SpeedtestOptions {
+    fn default() -> Self {
+        Self {
+            group: None,
+            url: DEFAULT_SPEEDTEST_URL.to_string(),
+            delay_test_url: DEFAULT_DELAY_TEST_URL.to_string(),
+            duration: Duration::from_secs(DEFAULT_SPEEDTEST_SECS),
+            top: None,
+        }
+    }
+}
+
+/// Throughput of one node.
+#[derive(Serialize, Debug)]
+pub struct SpeedResult {
+    pub name: String,
+    /// Delay in milliseconds from the delay test that picked the node
+    pub delay: Option<u64>,
+    /// Download speed in Mbit/s, `None` if the download failed
+    pub mbps: Option<f64>,
+}
+
+/// Downloads `url` through the mixed-port for up to `duration`, returning Mbit/s.
+async fn measure(mixed_port: u16, url: &str, duration: Duration) -> Result<f64> {
+    // A fresh client per node, a pooled connection would stay on the previous node
+    let client = Client::builder()
+        .proxy(reqwest::Proxy::all(format!(
+            "http://127.0.0.1:{mixed_port}"
+        ))?)
+        .connect_timeout(CONNECT_TIMEOUT)
+        .pool_max_idle_per_host(0)
+        .build()?;
+    let start = Instant::now();
+    let response = tokio::time::timeout(CONNECT_TIMEOUT, client.get(url).send())
+        .await
+        .map_err(|_| anyhow!("no response within {CONNECT_TIMEOUT:?}"))??
+        .error_for_status()?;
+    let mut stream = response.bytes_stream();
+    let mut bytes = 0u64;
+    while let Some(remaining) = duration.checked_sub(start.elapsed()) {
+        match tokio::time::timeout(remaining, stream.next()).await {
+            Ok(Some(chunk)) => bytes += chunk?.len() as u64,
+            Ok(None) | Err(_) => break,
+        }
+    }
+    let elapsed = start.elapsed().as_secs_f64();
+    Ok(bytes as f64 * 8.0 / elapsed / 1_000_000.0)
+}
+
+/// Measures the download speed of every reachable node, or only the members
+/// of `group`, one node at a time: Mihomo is switched to global mode and each
+/// node is selected in `GLOBAL`, then the mode and selection are restored.
+/// Sorted from fastest to slowest.
+pub async fn run_speedtest(
+    controller: &Controller,
+    mixed_port: u16,
+    options: &SpeedtestOptions,
+) -> Result<Vec<SpeedResult>> {
+    let delays = latency::test_latency(
+        controller,
+        options.group.as_deref(),
+        &options.delay_test_url,
+        DEFAULT_DELAY_TIMEOUT_MS,
+    )
+    .await?;
+    // Sorted by delay, dead nodes would only time out
+    let mut nodes: Vec<_> = delays.into_iter().filter(|r| r.delay.is_some()).collect();
+    if let Some(top) = options.top {
+        nodes.truncate(top);
+    }
+    if nodes.is_empty() {
+        return Err(anyhow!("No reachable node to test"));
+    }
+
+    let mode = controller.configs().await?.mode;
+    let selected = controller
+        .proxies()
+        .await?
+        .remove(GLOBAL_GROUP)
+        .and_then(|global| global.now);
+    controller.set_mode(Mode::Global).await?;
+    info!(
+        "Testing {} nodes for up to {:?} each, press Ctrl+C to stop",
+        nodes.len(),
+        options.duration
+    );
+
+    let mut results = Vec::new();
+    let test = async {
+        for node in nodes {
+            controller.select_proxy(GLOBAL_GROUP, &node.name).await?;
+            let mbps = match measure(mixed_port, &options.url, options.duration).await {
+                Ok(mbps) => {
+                    info!("{}: {mbps:.1} Mbps", node.name);
+                    Some(mbps)
+                }
+                Err(e) => {
+                    warn!("{}: {e}", node.name);
+                    None
+                }
+            };
+            results.push(SpeedResult {
+                name: node.name,
+                delay: node.delay,
+                mbps,
+            });
+        }
+        Ok::<_, anyhow::Error>(())
+    };
+    let outcome = tokio::select! {
+        outcome = test => outcome,
+        _ = tokio::signal::ctrl_c() => Ok(()),
+    };
+
+    if let Some(selected) = selected {
+        controller.select_proxy(GLOBAL_GROUP, &selected).await?;
+    }
+    let mode = Mode::from_str(&mode, true).unwrap_or(Mode::Rule);
+    controller.set_mode(mode).await?;
+    outcome?;
+
+    results.sort_by(|a, b| b.mbps.unwrap_or(-1.0).total_cmp(&a.mbps.unwrap_or(-1.0)));
+    Ok(results)
+}
+
+/// Prints the results as a table, colored by speed.
+pub fn print_speed_table(results: &[SpeedResult]) {
+    let width = results.iter().map(|r| r.name.len()).max().unwrap_or(0);
+    println!();
+    for result in results {
+        let speed = match result.mbps {
+            Some(mbps) if mbps >= 50.0 => format!("{mbps:.1} Mbps").green(),
+            Some(mbps) if mbps >= 10.0 => format!("{mbps:.1} Mbps").yellow(),
+            Some(mbps) => format!("{mbps:.1} Mbps").red(),
+            None => "failed".red(),
+        };
+        let delay = result
+            .delay
+            .map_or_else(|| "-".to_string(), |d| format!("{d} ms"));
+        println!("{:<width$}  {:>12}  {delay}", result.name, speed);
+    }
+}
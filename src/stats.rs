@@ -0,0 +1,259 @@
+use crate::controller::{Controller, DEFAULT_DELAY_TIMEOUT_MS};
+use crate::latency;
+use crate::output::format_bytes;
+use anyhow::{Context, Result};
+use colored::Colorize;
+use jiff::tz::TimeZone;
+use jiff::Timestamp;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::time::Duration;
+
+/// Samples of the node delays and traffic counters, one JSON object per line.
+pub const STATS_FILE: &str = "stats.jsonl";
+/// Samples older than this are dropped when a new one is added
+const RETENTION: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+/// Change of the average delay between the first and second half of the
+/// period above which the trend is flagged
+const TREND_THRESHOLD: f64 = 0.2;
+
+/// Node delays and traffic counters at one point in time.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Sample {
+    /// Unix time in seconds
+    pub time: i64,
+    /// Bytes sent since the core started
+    pub upload: u64,
+    /// Bytes received since the core started
+    pub download: u64,
+    /// Delay of each node in milliseconds, `None` on timeout
+    pub delays: BTreeMap<String, Option<u64>>,
+}
+
+/// Tests the delay of every node against `url` and reads the traffic counters.
+pub async fn take_sample(controller: &Controller, url: &str) -> Result<Sample> {
+    let traffic = controller.connections().await?;
+    let results = latency::test_latency(controller, None, url, DEFAULT_DELAY_TIMEOUT_MS).await?;
+    Ok(Sample {
+        time: Timestamp::now().as_second(),
+        upload: traffic.upload_total,
+        download: traffic.download_total,
+        delays: results.into_iter().map(|r| (r.name, r.delay)).collect(),
+    })
+}
+
+/// Appends `sample` to `path`, first dropping the samples older than [`RETENTION`].
+pub fn append(path: &Path, sample: &Sample) -> Result<()> {
+    let cutoff = sample.time - RETENTION.as_secs() as i64;
+    let oldest = fs::read_to_string(path)
+        .ok()
+        .and_then(|content| {
+            let line = content.lines().next()?;
+            serde_json::from_str::<Sample>(line).ok()
+        })
+        .map(|sample| sample.time);
+    if oldest.is_some_and(|oldest| oldest < cutoff) {
+        let kept: String = load(path, cutoff)?
+            .iter()
+            .map(|sample| Ok(serde_json::to_string(sample)? + "\n"))
+            .collect::<Result<_>>()?;
+        fs::write(path, kept)?;
+    }
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    writeln!(file, "{}", serde_json::to_string(sample)?)?;
+    Ok(())
+}
+
+/// The samples taken at or after `since` (Unix time), skipping damaged lines.
+pub fn load(path: &Path, since: i64) -> Result<Vec<Sample>> {
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    let content =
+        fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    Ok(content
+        .lines()
+        .filter_map(|line| serde_json::from_str::<Sample>(line).ok())
+        .filter(|sample| sample.time >= since)
+        .collect())
+}
+
+fn format_time(time: i64, format: &str) -> String {
+    Timestamp::from_second(time)
+        .map(|t| t.to_zoned(TimeZone::system()).strftime(format).to_string())
+        .unwrap_or_default()
+}
+
+/// Bytes (up, down) moved between the samples. A counter that went down
+/// means the core restarted and counted from zero again.
+fn traffic(samples: &[Sample]) -> (u64, u64) {
+    samples.windows(2).fold((0, 0), |(up, down), pair| {
+        let delta = |before: u64, after: u64| {
+            if after >= before {
+                after - before
+            } else {
+                after
+            }
+        };
+        (
+            up + delta(pair[0].upload, pair[1].upload),
+            down + delta(pair[0].download, pair[1].download),
+        )
+    })
+}
+
+/// Delays of `node` in the samples that tested it.
+fn delays<'a>(samples: &'a [Sample], node: &'a str) -> impl Iterator<Item = Option<u64>> + 'a {
+    samples
+        .iter()
+        .filter_map(move |sample| sample.delays.get(node).copied())
+}
+
+fn average(delays: impl Iterator<Item = Option<u64>>) -> Option<f64> {
+    let answered: Vec<u64> = delays.flatten().collect();
+    if answered.is_empty() {
+        return None;
+    }
+    Some(answered.iter().sum::<u64>() as f64 / answered.len() as f64)
+}
+
+/// Percentage of the delay tests that timed out.
+fn loss(delays: &[Option<u64>]) -> f64 {
+    if delays.is_empty() {
+        return 0.0;
+    }
+    delays.iter().filter(|d| d.is_none()).count() as f64 * 100.0 / delays.len() as f64
+}
+
+/// How the average delay of the second half of the samples compares to the first.
+fn trend(delays: &[Option<u64>]) -> String {
+    let (first, second) = delays.split_at(delays.len() / 2);
+    match (
+        average(first.iter().copied()),
+        average(second.iter().copied()),
+    ) {
+        (Some(before), Some(after)) if before > 0.0 => {
+            let change = (after - before) / before;
+            let text = format!("{:+.0}%", change * 100.0);
+            if change > TREND_THRESHOLD {
+                format!("↑ {text}").red().to_string()
+            } else if change < -TREND_THRESHOLD {
+                format!("↓ {text}").green().to_string()
+            } else {
+                format!("→ {text}")
+            }
+        }
+        _ => "-".to_string(),
+    }
+}
+
+fn format_delay(delay: Option<f64>) -> String {
+    delay.map_or_else(|| "-".to_string(), |d| format!("{d:.0} ms"))
+}
+
+/// Prints the traffic and, for every node, the average delay, timeouts and
+/// trend over the samples, fastest first.
+pub fn print_summary(samples: &[Sample]) {
+    let (Some(first), Some(last)) = (samples.first(), samples.last()) else {
+        println!("No samples yet");
+        return;
+    };
+    let (up, down) = traffic(samples);
+    println!(
+        "{} samples from {} to {}, traffic ↑ {} ↓ {}\n",
+        samples.len(),
+        format_time(first.time, "%Y-%m-%d %H:%M"),
+        format_time(last.time, "%Y-%m-%d %H:%M"),
+        format_bytes(up),
+        format_bytes(down)
+    );
+
+    let mut nodes: Vec<&String> = samples.iter().flat_map(|s| s.delays.keys()).collect();
+    nodes.sort();
+    nodes.dedup();
+    let mut rows: Vec<(f64, [String; 5])> = nodes
+        .into_iter()
+        .map(|node| {
+            let delays: Vec<Option<u64>> = delays(samples, node).collect();
+            let avg = average(delays.iter().copied());
+            let last = delays.last().copied().flatten().map(|d| d as f64);
+            let row = [
+                node.clone(),
+                format_delay(avg),
+                format_delay(last),
+                format!("{:.0}%", loss(&delays)),
+                trend(&delays),
+            ];
+            (avg.unwrap_or(f64::MAX), row)
+        })
+        .collect();
+    rows.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+    let width = rows
+        .iter()
+        .map(|(_, row)| row[0].chars().count())
+        .max()
+        .unwrap_or(0)
+        .max(4);
+    println!(
+        "{}",
+        format!(
+            "{:<width$}  {:>8}  {:>8}  {:>7}  TREND",
+            "NODE", "AVG", "LAST", "TIMEOUT"
+        )
+        .bold()
+    );
+    for (_, [node, avg, last, loss, trend]) in rows {
+        println!("{node:<width$}  {avg:>8}  {last:>8}  {loss:>7}  {trend}");
+    }
+}
+
+/// Prints the delays of `node` per hour, or per day for periods longer than two days.
+pub fn print_node(samples: &[Sample], node: &str) -> Result<()> {
+    let tested: Vec<&Sample> = samples
+        .iter()
+        .filter(|s| s.delays.contains_key(node))
+        .collect();
+    if tested.is_empty() {
+        return Err(anyhow::anyhow!("No samples of {node} in this period"));
+    }
+    let span = tested[tested.len() - 1].time - tested[0].time;
+    let format = if span > 2 * 24 * 60 * 60 {
+        "%Y-%m-%d"
+    } else {
+        "%m-%d %H:00"
+    };
+    let mut buckets: Vec<(String, Vec<Option<u64>>)> = Vec::new();
+    for sample in tested {
+        let bucket = format_time(sample.time, format);
+        let delay = sample.delays[node];
+        match buckets.last_mut() {
+            Some((last, delays)) if *last == bucket => delays.push(delay),
+            _ => buckets.push((bucket, vec![delay])),
+        }
+    }
+
+    println!("{}", node.bold());
+    println!(
+        "{}",
+        format!(
+            "{:<12}  {:>8}  {:>8}  {:>8}  {:>7}",
+            "TIME", "AVG", "MIN", "MAX", "TIMEOUT"
+        )
+        .bold()
+    );
+    for (bucket, delays) in buckets {
+        let answered = delays.iter().flatten();
+        println!(
+            "{bucket:<12}  {:>8}  {:>8}  {:>8}  {:>7}",
+            format_delay(average(delays.iter().copied())),
+            format_delay(answered.clone().min().map(|&d| d as f64)),
+            format_delay(answered.max().map(|&d| d as f64)),
+            format!("{:.0}%", loss(&delays))
+        );
+    }
+    Ok(())
+}
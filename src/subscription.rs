@@ -0,0 +1,293 @@
+use crate::output::format_bytes;
+use anyhow::{Context, Result};
+use log::*;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+pub const SUBSCRIPTION_FILE: &str = "subscription.toml";
+/// How long before the expiry date [`SubscriptionInfo::is_low`] starts reporting it.
+const EXPIRY_WARNING: Duration = Duration::from_secs(3 * 24 * 60 * 60);
+
+/// Result of downloading a subscription.
+#[derive(Debug)]
+pub struct Downloaded {
+    /// Quota from the `subscription-userinfo` header, if the provider sends it
+    pub info: Option<SubscriptionInfo>,
+    /// The provider answered 304 Not Modified and the config was kept as it is
+    pub unchanged: bool,
+}
+
+/// Context of an error downloading one of several merged subscriptions.
+#[derive(Debug)]
+pub struct DownloadFailed {
+    pub url: String,
+}
+
+impl fmt::Display for DownloadFailed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Failed to download {}", subscription_name(&self.url))
+    }
+}
+
+/// Host of a subscription URL, naming its group in a merged config.
+pub fn subscription_name(url: &str) -> String {
+    reqwest::Url::parse(url)
+        .ok()
+        .and_then(|url| url.host_str().map(String::from))
+        .unwrap_or_else(|| url.to_string())
+}
+
+/// `ETag` and `Last-Modified` of the last download, sent back so an unchanged
+/// subscription is not written again. Kept next to the config as `<config>.etag`.
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct Validators {
+    /// Subscription the validators belong to
+    pub url: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub etag: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_modified: Option<String>,
+}
+
+impl Validators {
+    pub fn path(config_path: &Path) -> PathBuf {
+        let mut name = config_path.file_name().unwrap_or_default().to_os_string();
+        name.push(".etag");
+        config_path.with_file_name(name)
+    }
+
+    /// The saved validators, `None` if there are none or they can't be read.
+    pub fn load(path: &Path) -> Option<Self> {
+        let content = fs::read_to_string(path).ok()?;
+        toml::from_str(&content).ok()
+    }
+
+    /// Saves the validators, or removes the file if the provider sent none.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if self.etag.is_none() && self.last_modified.is_none() {
+            if path.exists() {
+                fs::remove_file(path)?;
+            }
+            return Ok(());
+        }
+        fs::write(path, toml::to_string_pretty(self)?)?;
+        Ok(())
+    }
+}
+
+/// Traffic quota reported by the provider in the `subscription-userinfo` header,
+/// e.g. `upload=455727941; download=6174315083; total=1073741824000; expire=1671815872`.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct SubscriptionInfo {
+    /// Bytes uploaded
+    pub upload: u64,
+    /// Bytes downloaded
+    pub download: u64,
+    /// Quota in bytes, 0 if unlimited
+    pub total: u64,
+    /// Unix timestamp the subscription expires at, `None` if it doesn't
+    pub expire: Option<u64>,
+}
+
+impl SubscriptionInfo {
+    /// Parses the header value, `None` if it has none of the known fields.
+    pub fn parse(header: &str) -> Option<Self> {
+        let mut info = Self::default();
+        let mut found = false;
+        for (key, value) in header.split(';').filter_map(|field| field.split_once('=')) {
+            // Some providers send floats like `total=1.073741824E12`
+            let Ok(value) = value.trim().parse::<f64>() else {
+                continue;
+            };
+            let value = value as u64;
+            match key.trim().to_ascii_lowercase().as_str() {
+                "upload" => info.upload = value,
+                "download" => info.download = value,
+                "total" => info.total = value,
+                "expire" => info.expire = (value > 0).then_some(value),
+                _ => continue,
+            }
+            found = true;
+        }
+        found.then_some(info)
+    }
+
+    pub fn used(&self) -> u64 {
+        self.upload.saturating_add(self.download)
+    }
+
+    /// Bytes left, `None` for an unlimited quota.
+    pub fn remaining(&self) -> Option<u64> {
+        (self.total > 0).then(|| self.total.saturating_sub(self.used()))
+    }
+
+    /// Whether less than `percent` of the quota is left, or it expires within three days.
+    pub fn is_low(&self, percent: u8) -> bool {
+        // In u128, quotas near u64::MAX would overflow
+        let low_traffic = self.remaining().is_some_and(|remaining| {
+            u128::from(remaining) * 100 < u128::from(self.total) * u128::from(percent)
+        });
+        let expires_soon = self
+            .expire
+            .is_some_and(|expire| expire < now().saturating_add(EXPIRY_WARNING.as_secs()));
+        low_traffic || expires_soon
+    }
+
+    pub fn load(path: &Path) -> Result<Option<Self>> {
+        if !path.exists() {
+            return Ok(None);
+        }
+        let content = fs::read_to_string(path)?;
+        toml::from_str(&content)
+            .map(Some)
+            .with_context(|| format!("Invalid {}", path.display()))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        fs::write(path, toml::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    /// `1.0 GB used of 100.0 GB, 99.0 GB left`, or only the used traffic.
+    pub fn traffic(&self) -> String {
+        match self.remaining() {
+            Some(remaining) => format!(
+                "{} used of {}, {} left",
+                format_bytes(self.used()),
+                format_bytes(self.total),
+                format_bytes(remaining)
+            ),
+            None => format!("{} used", format_bytes(self.used())),
+        }
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.expire.is_some_and(|expire| expire <= now())
+    }
+
+    /// `2025-01-31 (30 days left)`, `None` if it never expires.
+    pub fn expiry(&self) -> Option<String> {
+        let expire = self.expire?;
+        Some(match expire.checked_sub(now()) {
+            Some(left) if left > 0 => {
+                format!("{} ({} days left)", format_date(expire), left / 86400)
+            }
+            _ => format!("{} (expired)", format_date(expire)),
+        })
+    }
+
+    /// Logs the used and remaining traffic and the expiry date.
+    pub fn log(&self) {
+        info!("Traffic: {}", self.traffic());
+        if let Some(expire) = self.expire.filter(|_| self.is_expired()) {
+            warn!("Subscription expired on {}", format_date(expire));
+        } else if let Some(expiry) = self.expiry() {
+            info!("Subscription expires on {expiry}");
+        }
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// `YYYY-MM-DD` (UTC) of a unix timestamp.
+fn format_date(timestamp: u64) -> String {
+    // Howard Hinnant's civil_from_days
+    let days = (timestamp / 86400) as i64 + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    format!("{year:04}-{month:02}-{day:02}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_userinfo_header() {
+        let info = SubscriptionInfo::parse(
+            "upload=455727941; download=6174315083; total=1073741824000; expire=1671815872",
+        )
+        .unwrap();
+        assert_eq!(
+            info,
+            SubscriptionInfo {
+                upload: 455727941,
+                download: 6174315083,
+                total: 1073741824000,
+                expire: Some(1671815872),
+            }
+        );
+    }
+
+    #[test]
+    fn parses_floats_and_odd_spacing() {
+        let info = SubscriptionInfo::parse(" Upload = 1 ;download=2;total=1.073741824E12").unwrap();
+        assert_eq!(info.upload, 1);
+        assert_eq!(info.download, 2);
+        assert_eq!(info.total, 1073741824000);
+        assert_eq!(info.expire, None);
+    }
+
+    #[test]
+    fn skips_unknown_and_invalid_fields() {
+        let info = SubscriptionInfo::parse("upload=abc; download=5; foo=1; expire=0").unwrap();
+        assert_eq!(info.upload, 0);
+        assert_eq!(info.download, 5);
+        assert_eq!(info.expire, None);
+        assert_eq!(SubscriptionInfo::parse("foo=1; bar"), None);
+        assert_eq!(SubscriptionInfo::parse(""), None);
+    }
+
+    #[test]
+    fn huge_values_saturate() {
+        let info = SubscriptionInfo::parse("upload=1e30; download=1e30; total=-5").unwrap();
+        assert_eq!(info.upload, u64::MAX);
+        assert_eq!(info.used(), u64::MAX);
+        assert_eq!(info.total, 0);
+        assert_eq!(info.remaining(), None);
+    }
+
+    #[test]
+    fn remaining_traffic() {
+        let info = SubscriptionInfo {
+            upload: 10,
+            download: 85,
+            total: 100,
+            expire: None,
+        };
+        assert_eq!(info.remaining(), Some(5));
+        assert!(info.is_low(10));
+        assert!(!info.is_low(5));
+
+        let over = SubscriptionInfo {
+            download: 200,
+            ..info
+        };
+        assert_eq!(over.remaining(), Some(0));
+
+        let huge = SubscriptionInfo {
+            upload: 0,
+            download: 0,
+            total: u64::MAX,
+            expire: None,
+        };
+        assert!(!huge.is_low(100));
+    }
+}
@@ -0,0 +1,114 @@
+use anyhow::{anyhow, Context, Result};
+use log::*;
+use std::fs;
+use std::path::Path;
+use std::process::{Command, Output};
+
+/// Git repository in the data dir that holds the synced files.
+pub const SYNC_DIR: &str = "sync";
+const BRANCH: &str = "main";
+const REMOTE: &str = "origin";
+/// A fixed identity, so machines without a git config can sync too
+const IDENTITY: [&str; 4] = [
+    "-c",
+    "user.name=proxy-rs",
+    "-c",
+    "user.email=proxy-rs@localhost",
+];
+
+fn git_output(repo: &Path, args: &[&str]) -> Result<Output> {
+    debug!("Running: git {}", args.join(" "));
+    Command::new("git")
+        .arg("-C")
+        .arg(repo)
+        .args(args)
+        .output()
+        .context("Failed to run git, is it installed?")
+}
+
+fn git(repo: &Path, args: &[&str]) -> Result<String> {
+    let output = git_output(repo, args)?;
+    if !output.status.success() {
+        return Err(anyhow!(
+            "`git {}` failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// Creates the repository on the first sync and points `origin` at `remote`.
+pub fn init(repo: &Path, remote: &str) -> Result<()> {
+    if !repo.join(".git").exists() {
+        fs::create_dir_all(repo)?;
+        git(repo, &["init", "--quiet"])?;
+        git(
+            repo,
+            &["symbolic-ref", "HEAD", &format!("refs/heads/{BRANCH}")],
+        )?;
+        info!("Created the sync repository in {}", repo.display());
+    }
+    match git(repo, &["remote", "get-url", REMOTE]) {
+        Ok(url) if url == remote => {}
+        Ok(_) => {
+            git(repo, &["remote", "set-url", REMOTE, remote])?;
+        }
+        Err(_) => {
+            git(repo, &["remote", "add", REMOTE, remote])?;
+        }
+    }
+    Ok(())
+}
+
+/// Commits every change in the repository. Returns whether there was any.
+pub fn commit(repo: &Path, message: &str) -> Result<bool> {
+    git(repo, &["add", "--all"])?;
+    if git(repo, &["status", "--porcelain"])?.is_empty() {
+        return Ok(false);
+    }
+    git(
+        repo,
+        &[&IDENTITY[..], &["commit", "--quiet", "--message", message]].concat(),
+    )?;
+    Ok(true)
+}
+
+/// Rebases the local commits onto the remote branch, if it exists yet.
+pub fn pull(repo: &Path) -> Result<()> {
+    let heads = git_output(
+        repo,
+        &["ls-remote", "--exit-code", "--heads", REMOTE, BRANCH],
+    )?;
+    match heads.status.code() {
+        Some(0) => {}
+        // The remote has no branch yet, the first push creates it
+        Some(2) => return Ok(()),
+        _ => {
+            return Err(anyhow!(
+                "Failed to reach the sync remote: {}",
+                String::from_utf8_lossy(&heads.stderr).trim()
+            ))
+        }
+    }
+    git(
+        repo,
+        &[
+            &IDENTITY[..],
+            &["pull", "--quiet", "--rebase", REMOTE, BRANCH],
+        ]
+        .concat(),
+    )
+    .with_context(|| {
+        format!(
+            "Resolve the conflict in {} with git, then sync again",
+            repo.display()
+        )
+    })?;
+    Ok(())
+}
+
+pub fn push(repo: &Path) -> Result<()> {
+    git(repo, &["push", "--quiet", REMOTE, BRANCH])?;
+    Ok(())
+}
@@ -0,0 +1,343 @@
+use anyhow::{anyhow, Context, Result};
+use log::*;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+pub const SYSPROXY_BACKUP_FILE: &str = "sysproxy.yaml";
+
+const PROXY_HOST: &str = "127.0.0.1";
+
+/// The commands needed to bring the system proxy back to the state it was in
+/// before `sysproxy on`.
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct SysproxyBackup {
+    restore: Vec<Vec<String>>,
+}
+
+/// Points the system proxy at `port`, sending the hosts of `bypass` directly.
+pub fn enable(backup_path: &Path, port: u16, bypass: &[String]) -> Result<()> {
+    if backup_path.exists() {
+        info!("System proxy was already set by us, keeping the original backup");
+    } else {
+        let backup = SysproxyBackup {
+            restore: current_restore_commands()?,
+        };
+        fs::write(backup_path, serde_yaml::to_string(&backup)?)?;
+    }
+
+    for command in enable_commands(port, bypass)? {
+        run(&command)?;
+    }
+    info!("System proxy is set to {PROXY_HOST}:{port}");
+    Ok(())
+}
+
+/// Restores the settings saved by [`enable`]. Returns `false` if there was nothing to restore.
+pub fn restore(backup_path: &Path) -> Result<bool> {
+    if !backup_path.exists() {
+        return Ok(false);
+    }
+    let backup: SysproxyBackup = serde_yaml::from_str(&fs::read_to_string(backup_path)?)
+        .with_context(|| format!("Invalid {}", backup_path.display()))?;
+    for command in &backup.restore {
+        run(command)?;
+    }
+    fs::remove_file(backup_path)?;
+    info!("System proxy settings restored");
+    Ok(true)
+}
+
+fn run(command: &[String]) -> Result<String> {
+    let (program, args) = command
+        .split_first()
+        .ok_or_else(|| anyhow!("Empty command"))?;
+    debug!("Running: {}", command.join(" "));
+    let output = Command::new(program)
+        .args(args)
+        .output()
+        .with_context(|| format!("Failed to run {program}"))?;
+    if !output.status.success() {
+        return Err(anyhow!(
+            "`{}` failed: {}",
+            command.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+fn cmd(args: &[&str]) -> Vec<String> {
+    args.iter().map(|a| a.to_string()).collect()
+}
+
+/// `bypass` with domain suffixes spelled `*.example.com`, the way the system
+/// settings match them, instead of `.example.com`.
+fn wildcard_domains(bypass: &[String]) -> Vec<String> {
+    bypass
+        .iter()
+        .map(|host| match host.strip_prefix('.') {
+            Some(domain) => format!("*.{domain}"),
+            None => host.clone(),
+        })
+        .collect()
+}
+
+#[cfg(windows)]
+const INTERNET_SETTINGS_KEY: &str =
+    r"HKCU\Software\Microsoft\Windows\CurrentVersion\Internet Settings";
+
+#[cfg(windows)]
+fn reg_query(value: &str) -> Option<String> {
+    let output = run(&cmd(&["reg", "query", INTERNET_SETTINGS_KEY, "/v", value])).ok()?;
+    // "    ProxyServer    REG_SZ    127.0.0.1:7890"
+    output
+        .lines()
+        .find(|line| line.trim_start().starts_with(value))
+        .and_then(|line| line.split_once("    REG_"))
+        .and_then(|(_, rest)| rest.split_once("    "))
+        .map(|(_, data)| data.trim().to_string())
+}
+
+#[cfg(windows)]
+fn reg_add(value: &str, kind: &str, data: &str) -> Vec<String> {
+    cmd(&[
+        "reg",
+        "add",
+        INTERNET_SETTINGS_KEY,
+        "/v",
+        value,
+        "/t",
+        kind,
+        "/d",
+        data,
+        "/f",
+    ])
+}
+
+#[cfg(windows)]
+fn current_restore_commands() -> Result<Vec<Vec<String>>> {
+    let enabled = reg_query("ProxyEnable").unwrap_or_else(|| "0x0".to_string());
+    let enabled = if enabled == "0x1" { "1" } else { "0" };
+    let mut commands = vec![reg_add("ProxyEnable", "REG_DWORD", enabled)];
+    for value in ["ProxyServer", "ProxyOverride"] {
+        match reg_query(value) {
+            Some(data) => commands.push(reg_add(value, "REG_SZ", &data)),
+            None => commands.push(cmd(&[
+                "reg",
+                "delete",
+                INTERNET_SETTINGS_KEY,
+                "/v",
+                value,
+                "/f",
+            ])),
+        }
+    }
+    Ok(commands)
+}
+
+/// `bypass` for ProxyOverride, which only knows wildcards: IPv4 CIDRs become
+/// patterns like `192.168.*`, IPv6 ones are dropped.
+#[cfg(windows)]
+fn proxy_override(bypass: &[String]) -> Vec<String> {
+    let mut patterns = Vec::new();
+    for host in wildcard_domains(bypass) {
+        let Some((address, prefix)) = host.split_once('/') else {
+            patterns.push(host);
+            continue;
+        };
+        let (Ok(address), Ok(prefix)) =
+            (address.parse::<std::net::Ipv4Addr>(), prefix.parse::<u32>())
+        else {
+            debug!("ProxyOverride can't match {host}, skipping it");
+            continue;
+        };
+        // Widen the prefix to whole octets, 172.16.0.0/12 is 16 patterns
+        let prefix = prefix.min(32);
+        let octets = prefix.div_ceil(8);
+        let network = u32::from(address)
+            .checked_shr(32 - prefix)
+            .map_or(0, |n| n << (32 - prefix));
+        for i in 0..1u32 << (octets * 8 - prefix) {
+            let subnet = network | i.checked_shl(32 - octets * 8).unwrap_or(0);
+            let mut parts: Vec<String> = subnet.to_be_bytes()[..octets as usize]
+                .iter()
+                .map(u8::to_string)
+                .collect();
+            if octets < 4 {
+                parts.push("*".to_string());
+            }
+            patterns.push(parts.join("."));
+        }
+    }
+    patterns
+}
+
+#[cfg(windows)]
+fn enable_commands(port: u16, bypass: &[String]) -> Result<Vec<Vec<String>>> {
+    let mut proxy_override = proxy_override(bypass);
+    // Plain host names without a dot, e.g. intranet servers
+    proxy_override.push("<local>".to_string());
+    Ok(vec![
+        reg_add("ProxyServer", "REG_SZ", &format!("{PROXY_HOST}:{port}")),
+        reg_add("ProxyOverride", "REG_SZ", &proxy_override.join(";")),
+        reg_add("ProxyEnable", "REG_DWORD", "1"),
+    ])
+}
+
+#[cfg(target_os = "macos")]
+const NETWORKSETUP_PROXIES: &[(&str, &str, &str)] = &[
+    // (get, set, set state)
+    ("-getwebproxy", "-setwebproxy", "-setwebproxystate"),
+    (
+        "-getsecurewebproxy",
+        "-setsecurewebproxy",
+        "-setsecurewebproxystate",
+    ),
+    (
+        "-getsocksfirewallproxy",
+        "-setsocksfirewallproxy",
+        "-setsocksfirewallproxystate",
+    ),
+];
+
+/// Enabled network services, e.g. "Wi-Fi" and "Ethernet".
+#[cfg(target_os = "macos")]
+fn network_services() -> Result<Vec<String>> {
+    let output = run(&cmd(&["networksetup", "-listallnetworkservices"]))?;
+    Ok(output
+        .lines()
+        .skip(1) // "An asterisk (*) denotes that a network service is disabled."
+        .filter(|line| !line.starts_with('*'))
+        .map(String::from)
+        .collect())
+}
+
+#[cfg(target_os = "macos")]
+fn current_restore_commands() -> Result<Vec<Vec<String>>> {
+    let mut commands = Vec::new();
+    for service in network_services()? {
+        for (get, set, set_state) in NETWORKSETUP_PROXIES {
+            let output = run(&cmd(&["networksetup", get, &service]))?;
+            let field = |name: &str| {
+                output
+                    .lines()
+                    .find_map(|line| line.strip_prefix(name))
+                    .map(|v| v.trim().to_string())
+                    .unwrap_or_default()
+            };
+            let (enabled, server, port) = (field("Enabled:"), field("Server:"), field("Port:"));
+            if !server.is_empty() && port != "0" {
+                commands.push(cmd(&["networksetup", set, &service, &server, &port]));
+            }
+            let state = if enabled == "Yes" { "on" } else { "off" };
+            commands.push(cmd(&["networksetup", set_state, &service, state]));
+        }
+        let bypass = run(&cmd(&["networksetup", "-getproxybypassdomains", &service]))?;
+        let mut restore_bypass = cmd(&["networksetup", "-setproxybypassdomains", &service]);
+        if bypass.contains("There aren't any") {
+            restore_bypass.push("Empty".to_string());
+        } else {
+            restore_bypass.extend(bypass.lines().map(String::from));
+        }
+        commands.push(restore_bypass);
+    }
+    Ok(commands)
+}
+
+#[cfg(target_os = "macos")]
+fn enable_commands(port: u16, bypass: &[String]) -> Result<Vec<Vec<String>>> {
+    let port = port.to_string();
+    let mut commands = Vec::new();
+    for service in network_services()? {
+        for (_, set, set_state) in NETWORKSETUP_PROXIES {
+            commands.push(cmd(&["networksetup", set, &service, PROXY_HOST, &port]));
+            commands.push(cmd(&["networksetup", set_state, &service, "on"]));
+        }
+        let mut set_bypass = cmd(&["networksetup", "-setproxybypassdomains", &service]);
+        set_bypass.extend(wildcard_domains(bypass));
+        commands.push(set_bypass);
+    }
+    Ok(commands)
+}
+
+#[cfg(all(unix, not(target_os = "macos")))]
+const GNOME_PROXY_SCHEMAS: &[&str] = &[
+    "org.gnome.system.proxy.http",
+    "org.gnome.system.proxy.https",
+    "org.gnome.system.proxy.socks",
+];
+
+#[cfg(all(unix, not(target_os = "macos")))]
+fn gsettings_get(schema: &str, key: &str) -> Result<String> {
+    run(&cmd(&["gsettings", "get", schema, key]))
+}
+
+#[cfg(all(unix, not(target_os = "macos")))]
+fn current_restore_commands() -> Result<Vec<Vec<String>>> {
+    if which::which("gsettings").is_err() {
+        return Err(anyhow!(
+            "gsettings not found, only GNOME is supported on this platform. \
+             Source the `on` script in the data directory to set the proxy for your shell instead."
+        ));
+    }
+    let mut commands = Vec::new();
+    for schema in GNOME_PROXY_SCHEMAS {
+        for key in ["host", "port"] {
+            let value = gsettings_get(schema, key)?;
+            commands.push(cmd(&["gsettings", "set", schema, key, &value]));
+        }
+    }
+    let ignore_hosts = gsettings_get("org.gnome.system.proxy", "ignore-hosts")?;
+    commands.push(cmd(&[
+        "gsettings",
+        "set",
+        "org.gnome.system.proxy",
+        "ignore-hosts",
+        &ignore_hosts,
+    ]));
+    // Restore the mode last so the proxy is never pointed at a half-restored host
+    let mode = gsettings_get("org.gnome.system.proxy", "mode")?;
+    commands.push(cmd(&[
+        "gsettings",
+        "set",
+        "org.gnome.system.proxy",
+        "mode",
+        &mode,
+    ]));
+    Ok(commands)
+}
+
+#[cfg(all(unix, not(target_os = "macos")))]
+fn enable_commands(port: u16, bypass: &[String]) -> Result<Vec<Vec<String>>> {
+    let port = port.to_string();
+    let mut commands = Vec::new();
+    for schema in GNOME_PROXY_SCHEMAS {
+        commands.push(cmd(&["gsettings", "set", schema, "host", PROXY_HOST]));
+        commands.push(cmd(&["gsettings", "set", schema, "port", &port]));
+    }
+    let ignore_hosts = format!(
+        "[{}]",
+        wildcard_domains(bypass)
+            .iter()
+            .map(|h| format!("'{h}'"))
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+    commands.push(cmd(&[
+        "gsettings",
+        "set",
+        "org.gnome.system.proxy",
+        "ignore-hosts",
+        &ignore_hosts,
+    ]));
+    commands.push(cmd(&[
+        "gsettings",
+        "set",
+        "org.gnome.system.proxy",
+        "mode",
+        "manual",
+    ]));
+    Ok(commands)
+}
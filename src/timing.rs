@@ -0,0 +1,115 @@
+//! Time spent in each phase of `start`: mirror selection, downloads, config
+//! processing and core readiness, summarized once the core is up and printed
+//! as JSON by `start --trace-startup`.
+
+use anyhow::Result;
+use serde::Serialize;
+use std::future::Future;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Phases shorter than this are left out of the summary, not of the JSON
+const SUMMARY_MIN: Duration = Duration::from_millis(100);
+
+pub struct StartupTimer {
+    started: Mutex<Instant>,
+    phases: Mutex<Vec<Phase>>,
+}
+
+/// One timed phase, downloads run at the same time so phases can overlap.
+#[derive(Serialize, Debug, Clone)]
+pub struct Phase {
+    pub name: String,
+    /// Since `start` began, in milliseconds
+    pub start_ms: u64,
+    pub duration_ms: u64,
+}
+
+#[derive(Serialize)]
+struct Trace<'a> {
+    total_ms: u64,
+    phases: &'a [Phase],
+}
+
+impl Default for StartupTimer {
+    fn default() -> Self {
+        Self {
+            started: Mutex::new(Instant::now()),
+            phases: Mutex::new(Vec::new()),
+        }
+    }
+}
+
+impl StartupTimer {
+    /// Starts over, forgetting the phases of an earlier start.
+    pub fn reset(&self) {
+        *self.started.lock().unwrap() = Instant::now();
+        self.phases.lock().unwrap().clear();
+    }
+
+    /// Runs `future`, recording how long it took as `name`.
+    pub async fn time<T>(&self, name: &str, future: impl Future<Output = T>) -> T {
+        let started = Instant::now();
+        let output = future.await;
+        self.record(name, started);
+        output
+    }
+
+    /// Records the phase `name` that began at `started` and ends now.
+    pub fn record(&self, name: &str, started: Instant) {
+        let origin = *self.started.lock().unwrap();
+        self.phases.lock().unwrap().push(Phase {
+            name: name.to_string(),
+            start_ms: millis(started.saturating_duration_since(origin)),
+            duration_ms: millis(started.elapsed()),
+        });
+    }
+
+    /// Like [`Self::record`], unless `name` is recorded already: for a phase
+    /// several downloads wait for, such as the mirror selection.
+    pub fn record_once(&self, name: &str, started: Instant) {
+        if self.phases.lock().unwrap().iter().any(|p| p.name == name) {
+            return;
+        }
+        self.record(name, started);
+    }
+
+    /// One line with the total and the phases that took a noticeable time.
+    pub fn summary(&self) -> String {
+        let total = self.started.lock().unwrap().elapsed();
+        let phases: Vec<String> = self
+            .phases
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|p| p.duration_ms >= millis(SUMMARY_MIN))
+            .map(|p| format!("{} {}", p.name, seconds(p.duration_ms)))
+            .collect();
+        if phases.is_empty() {
+            return format!("Started in {}", seconds(millis(total)));
+        }
+        format!(
+            "Started in {}: {}",
+            seconds(millis(total)),
+            phases.join(", ")
+        )
+    }
+
+    /// The total and every phase, in the order they ended.
+    pub fn to_json(&self) -> Result<String> {
+        let total_ms = millis(self.started.lock().unwrap().elapsed());
+        let phases = self.phases.lock().unwrap();
+        Ok(serde_json::to_string_pretty(&Trace {
+            total_ms,
+            phases: &phases,
+        })?)
+    }
+}
+
+fn millis(duration: Duration) -> u64 {
+    duration.as_millis() as u64
+}
+
+fn seconds(millis: u64) -> String {
+    format!("{:.1}s", millis as f64 / 1000.0)
+}
@@ -0,0 +1,50 @@
+use crate::utils::make_private;
+use anyhow::{Context, Result};
+use log::*;
+use std::fs;
+use std::io::Write;
+use std::net::IpAddr;
+use std::path::Path;
+
+/// Self-signed certificate and key of the HTTPS controller, in the instance dir.
+pub const CERT_FILE: &str = "controller.crt";
+pub const KEY_FILE: &str = "controller.key";
+
+/// Generates a self-signed certificate for localhost and `ips` unless one
+/// exists already. Delete the files to generate a new one, e.g. when the LAN
+/// IP changed.
+pub fn ensure_certificate(cert_path: &Path, key_path: &Path, ips: &[IpAddr]) -> Result<()> {
+    if cert_path.exists() && key_path.exists() {
+        debug!("Using the certificate in {}", cert_path.display());
+        // Written world-readable by earlier versions
+        return make_private(key_path);
+    }
+    let mut names = vec!["localhost".to_string(), "127.0.0.1".to_string()];
+    names.extend(ips.iter().map(IpAddr::to_string));
+    names.dedup();
+    let certified = rcgen::generate_simple_self_signed(names.clone())
+        .context("Failed to generate a self-signed certificate")?;
+    fs::write(cert_path, certified.cert.pem())?;
+    write_private(key_path, certified.key_pair.serialize_pem().as_bytes())?;
+    info!(
+        "Generated a self-signed certificate for {} in {}",
+        names.join(", "),
+        cert_path.display()
+    );
+    Ok(())
+}
+
+/// Writes `content` to `path` readable only by the owner from the start, the
+/// key is never on disk with wider permissions.
+fn write_private(path: &Path, content: &[u8]) -> Result<()> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    options.open(path)?.write_all(content)?;
+    // The mode only applies to a new file
+    make_private(path)
+}
@@ -0,0 +1,67 @@
+use crate::controller::Controller;
+use crate::output::format_bytes;
+use anyhow::Result;
+use colored::Colorize;
+use futures_util::{pin_mut, StreamExt};
+use log::*;
+use serde::Serialize;
+use std::io::{self, Write};
+
+#[derive(Serialize)]
+struct TrafficSample {
+    up: u64,
+    down: u64,
+    total_up: u64,
+    total_down: u64,
+}
+
+/// Streams live throughput until the connection closes or Ctrl+C is pressed.
+pub async fn watch_traffic(controller: &Controller, json: bool) -> Result<()> {
+    let stream = controller.traffic().await?;
+    pin_mut!(stream);
+
+    if !json {
+        info!("Watching traffic, press Ctrl+C to exit");
+    }
+
+    let (mut total_up, mut total_down) = (0u64, 0u64);
+    loop {
+        let traffic = tokio::select! {
+            traffic = stream.next() => match traffic {
+                Some(traffic) => traffic?,
+                None => break,
+            },
+            _ = tokio::signal::ctrl_c() => break,
+        };
+        total_up += traffic.up;
+        total_down += traffic.down;
+
+        if json {
+            let sample = TrafficSample {
+                up: traffic.up,
+                down: traffic.down,
+                total_up,
+                total_down,
+            };
+            println!("{}", serde_json::to_string(&sample)?);
+        } else {
+            print!(
+                "\r\x1b[2K{} {:>12}/s  {} {:>12}/s  total {} {:>10}  {} {:>10}",
+                "↑".green(),
+                format_bytes(traffic.up),
+                "↓".cyan(),
+                format_bytes(traffic.down),
+                "↑".green(),
+                format_bytes(total_up),
+                "↓".cyan(),
+                format_bytes(total_down),
+            );
+        }
+        io::stdout().flush()?;
+    }
+
+    if !json {
+        println!();
+    }
+    Ok(())
+}
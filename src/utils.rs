@@ -1,13 +1,377 @@
+use anyhow::{anyhow, Result};
+use log::*;
+use rand::Rng;
+use std::fs::{File, OpenOptions, TryLockError};
 use std::io::{self, Write};
+use std::net::{IpAddr, UdpSocket};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::atomic::{AtomicBool, Ordering};
 
-pub fn ask_for_confirmation(prompt: &str) -> bool {
-    print!("[QUESTION] {} (y/N) ", prompt);
+const LOCAL_DATA_DIR: &str = "proxy-data";
+
+static ASSUME_YES: AtomicBool = AtomicBool::new(false);
+
+/// Takes the default answer of every prompt, for cron, CI and provisioning
+/// scripts (`--yes`). Destructive commands still need their own `--force`.
+pub fn set_assume_yes(yes: bool) {
+    ASSUME_YES.store(yes, Ordering::Relaxed);
+}
+
+pub fn assume_yes() -> bool {
+    ASSUME_YES.load(Ordering::Relaxed)
+}
+
+/// Asks a yes/no question, `default` is the answer to an empty line and the
+/// one taken with `--yes`.
+pub fn ask_for_confirmation(prompt: &str, default: bool) -> bool {
+    let answer = if default { "yes" } else { "no" };
+    if assume_yes() {
+        info!("{prompt} {answer} (--yes)");
+        return default;
+    }
+    print!(
+        "[QUESTION] {prompt} {} ",
+        if default { "(Y/n)" } else { "(y/N)" }
+    );
     io::stdout().flush().unwrap();
     let mut input = String::new();
     io::stdin().read_line(&mut input).unwrap();
-    input.trim().eq_ignore_ascii_case("y")
+    match input.trim() {
+        "" => default,
+        input => input.eq_ignore_ascii_case("y") || input.eq_ignore_ascii_case("yes"),
+    }
+}
+
+/// Opens `path` in `$VISUAL` or `$EDITOR`, which may hold arguments such as
+/// `code --wait`, falling back to notepad on Windows and vi elsewhere.
+pub fn open_in_editor(path: &Path) -> Result<()> {
+    let editor = std::env::var("VISUAL")
+        .or_else(|_| std::env::var("EDITOR"))
+        .ok()
+        .filter(|editor| !editor.trim().is_empty())
+        .unwrap_or_else(|| if cfg!(windows) { "notepad" } else { "vi" }.to_string());
+    let mut words = editor.split_whitespace();
+    let program = words.next().unwrap_or_default();
+    let status = Command::new(program)
+        .args(words)
+        .arg(path)
+        .status()
+        .map_err(|e| anyhow!("Failed to run {program}: {e}, set EDITOR to your editor"))?;
+    if !status.success() {
+        return Err(anyhow!("{editor} exited with {status}"));
+    }
+    Ok(())
+}
+
+/// Where proxy-rs keeps its data when `--data-dir`/`PROXY_RS_DATA_DIR` and `--local`
+/// are not set: the global platform data dir, e.g. `~/.local/share/proxy-rs`,
+/// `%APPDATA%\proxy-rs` or `~/Library/Application Support/proxy-rs`.
+pub fn default_data_dir() -> PathBuf {
+    dirs::data_dir()
+        .map(|dir| dir.join("proxy-rs"))
+        .unwrap_or_else(local_data_dir)
+}
+
+/// Where downloads are shared between data dirs when `cache-dir` is not set,
+/// e.g. `~/.cache/proxy-rs` or `%LOCALAPPDATA%\proxy-rs`.
+pub fn default_cache_dir() -> Option<PathBuf> {
+    dirs::cache_dir().map(|dir| dir.join("proxy-rs"))
+}
+
+/// The project-local data dir used with `--local`, `./proxy-data`.
+pub fn local_data_dir() -> PathBuf {
+    PathBuf::from(LOCAL_DATA_DIR)
+}
+
+/// Which kind of installation a data dir is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Installation {
+    /// The platform data dir
+    Global,
+    /// `./proxy-data` in the current directory
+    Local,
+    /// Any other dir, from `--data-dir` or `data-dir` in proxy-rs.toml
+    Custom,
+}
+
+impl Installation {
+    pub fn of(data_dir: &Path) -> Installation {
+        let same =
+            |other: PathBuf| match (dunce::canonicalize(data_dir), dunce::canonicalize(&other)) {
+                (Ok(a), Ok(b)) => a == b,
+                _ => data_dir == other,
+            };
+        if same(local_data_dir()) {
+            Installation::Local
+        } else if same(default_data_dir()) {
+            Installation::Global
+        } else {
+            Installation::Custom
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Installation::Global => "global",
+            Installation::Local => "project-local",
+            Installation::Custom => "custom",
+        }
+    }
+}
+
+/// Takes an exclusive lock on `path`, held until the returned file is dropped.
+/// Fails right away if another proxy-rs process holds it.
+pub fn lock_file(path: &Path) -> Result<File> {
+    let file = OpenOptions::new()
+        .create(true)
+        .write(true)
+        .truncate(false)
+        .open(path)?;
+    match file.try_lock() {
+        Ok(()) => Ok(file),
+        Err(TryLockError::WouldBlock) => Err(anyhow!(
+            "Another operation is in progress (locked: {}), try again later",
+            path.display()
+        )),
+        Err(TryLockError::Error(e)) => Err(e.into()),
+    }
+}
+
+/// Takes group and other permissions away from `path`, as it holds server
+/// credentials: directories become 0700 and files 0600. Does nothing on
+/// Windows, where the user's profile is private already.
+pub fn make_private(path: &Path) -> Result<()> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let metadata = std::fs::metadata(path)?;
+        if metadata.permissions().mode() & 0o077 != 0 {
+            let mode = if metadata.is_dir() { 0o700 } else { 0o600 };
+            std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))?;
+            debug!("Restricted {} to mode {mode:o}", path.display());
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = path;
+    Ok(())
+}
+
+/// Random token for the external controller's `secret`.
+pub fn generate_secret() -> String {
+    rand::thread_rng()
+        .sample_iter(&rand::distributions::Alphanumeric)
+        .take(32)
+        .map(char::from)
+        .collect()
+}
+
+/// The IP of the interface used for the default route, i.e. this machine's LAN address.
+/// Connecting a UDP socket only picks the route, nothing is sent.
+pub fn lan_ip() -> Option<IpAddr> {
+    let socket = UdpSocket::bind("0.0.0.0:0").ok()?;
+    socket.connect("8.8.8.8:80").ok()?;
+    socket.local_addr().ok().map(|addr| addr.ip())
 }
 
 pub fn find_unused_port(start_port: u16) -> Option<u16> {
-    (start_port..65535).find(|port| std::net::TcpListener::bind(("127.0.0.1", *port)).is_ok())
+    (start_port..65535).find(|port| is_port_free(*port))
+}
+
+/// Whether `port` can be bound on loopback and on all interfaces, the core
+/// listens on the latter with `allow-lan` or `--listen 0.0.0.0`.
+fn is_port_free(port: u16) -> bool {
+    ["127.0.0.1", "0.0.0.0"]
+        .iter()
+        .all(|address| std::net::TcpListener::bind((*address, port)).is_ok())
+}
+
+/// Warns that `preferred` is taken when a port search moved on to `picked`,
+/// naming the process that holds it if it can be found.
+pub fn warn_port_taken(preferred: u16, picked: u16, flag: &str) {
+    if preferred == picked {
+        return;
+    }
+    match port_owner(preferred) {
+        Some((pid, name)) => warn!(
+            "Port {preferred} is used by {name} (pid: {pid}), using {picked} instead. Pass {flag} to choose another port"
+        ),
+        None => warn!(
+            "Port {preferred} is already in use, using {picked} instead. Pass {flag} to choose another port"
+        ),
+    }
+}
+
+/// Pid and name of the process listening on TCP `port`.
+pub fn port_owner(port: u16) -> Option<(u32, String)> {
+    let pid = sysinfo::Pid::from_u32(listening_pid(port)?);
+    let mut system = sysinfo::System::new();
+    system.refresh_processes(sysinfo::ProcessesToUpdate::Some(&[pid]), true);
+    let name = system
+        .process(pid)
+        .map(|p| p.name().to_string_lossy().into_owned())
+        .unwrap_or_else(|| "an unknown process".to_string());
+    Some((pid.as_u32(), name))
+}
+
+/// Finds the listening socket's inode in /proc/net/tcp{,6}, then the process
+/// holding it. Sockets of other users' processes are only visible to root.
+#[cfg(target_os = "linux")]
+fn listening_pid(port: u16) -> Option<u32> {
+    const LISTEN: &str = "0A";
+    let mut inodes = Vec::new();
+    for table in ["/proc/net/tcp", "/proc/net/tcp6"] {
+        let Ok(content) = std::fs::read_to_string(table) else {
+            continue;
+        };
+        for line in content.lines().skip(1) {
+            // sl local_address rem_address st ... inode
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let local_port = fields
+                .get(1)
+                .and_then(|address| address.rsplit(':').next())
+                .and_then(|hex| u16::from_str_radix(hex, 16).ok());
+            if local_port == Some(port) && fields.get(3) == Some(&LISTEN) {
+                inodes.extend(fields.get(9).map(|inode| format!("socket:[{inode}]")));
+            }
+        }
+    }
+    if inodes.is_empty() {
+        return None;
+    }
+    for entry in std::fs::read_dir("/proc").ok()?.flatten() {
+        let Ok(pid) = entry.file_name().to_string_lossy().parse::<u32>() else {
+            continue;
+        };
+        let Ok(fds) = std::fs::read_dir(entry.path().join("fd")) else {
+            continue;
+        };
+        for fd in fds.flatten() {
+            if std::fs::read_link(fd.path())
+                .is_ok_and(|target| inodes.iter().any(|inode| target == Path::new(inode)))
+            {
+                return Some(pid);
+            }
+        }
+    }
+    None
+}
+
+#[cfg(all(unix, not(target_os = "linux")))]
+fn listening_pid(port: u16) -> Option<u32> {
+    let output = Command::new("lsof")
+        .args(["-nP", &format!("-iTCP:{port}"), "-sTCP:LISTEN", "-t"])
+        .output()
+        .ok()?;
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .next()?
+        .trim()
+        .parse()
+        .ok()
+}
+
+#[cfg(windows)]
+fn listening_pid(port: u16) -> Option<u32> {
+    let output = Command::new("netstat")
+        .args(["-ano", "-p", "TCP"])
+        .output()
+        .ok()?;
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .find_map(|line| {
+            // Proto  Local Address  Foreign Address  State  PID
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let local_port = fields.get(1)?.rsplit(':').next()?.parse::<u16>().ok()?;
+            if local_port != port || fields.get(3) != Some(&"LISTENING") {
+                return None;
+            }
+            fields.get(4)?.parse().ok()
+        })
+}
+
+/// Like [`find_unused_port`], skipping the ports in `taken` that were picked
+/// for other listeners but are not bound yet.
+pub fn find_unused_port_except(start_port: u16, taken: &[u16]) -> Option<u16> {
+    (start_port..65535).find(|port| !taken.contains(port) && is_port_free(*port))
+}
+
+/// A free port picked at random from the high range, so it can't be guessed
+/// the way the ones searched from 7890 and 9090 can. Skips `taken` like
+/// [`find_unused_port_except`].
+pub fn find_random_port(taken: &[u16]) -> Option<u16> {
+    let mut rng = rand::thread_rng();
+    (0..100)
+        .map(|_| rng.gen_range(20000..60000))
+        .find(|port| !taken.contains(port) && is_port_free(*port))
+}
+
+/// Whether the TUN device can be created by Mihomo: root/administrator, or on
+/// Linux the binary has been granted `cap_net_admin`.
+pub fn has_tun_privileges(mihomo_path: &Path) -> bool {
+    is_elevated() || (cfg!(target_os = "linux") && has_tun_capabilities(mihomo_path))
+}
+
+fn has_tun_capabilities(mihomo_path: &Path) -> bool {
+    Command::new("getcap")
+        .arg(mihomo_path)
+        .output()
+        .map(|output| String::from_utf8_lossy(&output.stdout).contains("cap_net_admin"))
+        .unwrap_or(false)
+}
+
+/// Grants the Linux binary the capabilities for TUN and ports below 1024 unless
+/// it has them, so it doesn't need root. Needs root itself, and is repeated
+/// after updates as a new binary starts without them.
+pub fn grant_tun_capabilities(mihomo_path: &Path) -> Result<()> {
+    if has_tun_capabilities(mihomo_path) {
+        return Ok(());
+    }
+    let status = Command::new("setcap")
+        .arg("cap_net_admin,cap_net_bind_service=+ep")
+        .arg(mihomo_path)
+        .status()
+        .map_err(|e| {
+            anyhow!("Failed to run setcap: {e}, install libcap2-bin (Debian, Ubuntu) or libcap")
+        })?;
+    if !status.success() {
+        return Err(anyhow!(
+            "setcap failed to grant capabilities to {} ({status})",
+            mihomo_path.display()
+        ));
+    }
+    info!(
+        "Granted cap_net_admin and cap_net_bind_service to {}",
+        mihomo_path.display()
+    );
+    Ok(())
+}
+
+pub fn warn_missing_tun_privileges(mihomo_path: &Path) {
+    warn!("TUN mode requires elevated privileges, Mihomo may fail to create the TUN device.");
+    warn!("{}", tun_privileges_fix(mihomo_path));
+}
+
+/// How to give Mihomo the privileges [`has_tun_privileges`] checks for.
+pub fn tun_privileges_fix(mihomo_path: &Path) -> String {
+    if cfg!(windows) {
+        "Use an Administrator terminal.".to_string()
+    } else if cfg!(target_os = "linux") {
+        format!(
+            "Use sudo, or grant the capabilities once with: sudo setcap cap_net_admin,cap_net_bind_service=+ep {}",
+            mihomo_path.display()
+        )
+    } else {
+        "Use sudo.".to_string()
+    }
+}
+
+#[cfg(unix)]
+pub fn is_elevated() -> bool {
+    nix::unistd::geteuid().is_root()
+}
+
+#[cfg(windows)]
+pub fn is_elevated() -> bool {
+    unsafe { winapi::um::shlobj::IsUserAnAdmin() != 0 }
 }
@@ -0,0 +1,237 @@
+use crate::downloader::client_builder;
+use crate::mihomo::{DEFAULT_CONTROLLER_PORT, DEFAULT_MIXED_PORT};
+use crate::settings::{
+    remove_setting, resolve_data_dir, save_setting, Settings, WebUi, SETTINGS_FILE,
+};
+use crate::utils::{assume_yes, default_data_dir};
+use anyhow::{anyhow, Context, Result};
+use clap::ValueEnum;
+use dialoguer::theme::ColorfulTheme;
+use dialoguer::{Confirm, Input, Select};
+use log::*;
+use reqwest::Client;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+const FETCH_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// Asks for the data dir, subscription, WebUI, ports and autostart, and writes
+/// the answers to proxy-rs.toml. Returns the data dir. The subscription is
+/// checked through `download_proxy`, or the `download-proxy` setting.
+pub async fn run_wizard(download_proxy: Option<&str>) -> Result<PathBuf> {
+    if assume_yes() {
+        return Err(anyhow!(
+            "init only asks questions, edit {SETTINGS_FILE} instead of using --yes"
+        ));
+    }
+    let theme = ColorfulTheme::default();
+    let default = default_data_dir();
+
+    let current = resolve_data_dir(None, false)?;
+    let data_dir: String = Input::with_theme(&theme)
+        .with_prompt("Data directory")
+        .default(current.display().to_string())
+        .interact_text()?;
+    let data_dir = PathBuf::from(data_dir);
+    fs::create_dir_all(&data_dir)
+        .with_context(|| format!("Failed to create {}", data_dir.display()))?;
+    // Other directories are found through `data-dir` in the default one
+    fs::create_dir_all(&default)?;
+    if data_dir == default {
+        remove_setting(&default.join(SETTINGS_FILE), "data-dir")?;
+    } else {
+        let absolute = dunce::canonicalize(&data_dir)?;
+        save_setting(
+            &default.join(SETTINGS_FILE),
+            "data-dir",
+            absolute.to_string_lossy().into_owned(),
+        )?;
+    }
+    let settings_path = data_dir.join(SETTINGS_FILE);
+    let settings = Settings::load(&settings_path)?;
+
+    let client = client_builder(download_proxy.or(settings.download_proxy.as_deref()))?
+        .timeout(FETCH_TIMEOUT)
+        .build()?;
+    let url = ask_subscription(&theme, &client, settings.subscription_url.as_deref()).await?;
+    match url {
+        Some(url) => save_setting(&settings_path, "subscription-url", url)?,
+        None => remove_setting(&settings_path, "subscription-url")?,
+    }
+
+    let uis = WebUi::value_variants();
+    let labels: Vec<&str> = uis.iter().map(WebUi::name).collect();
+    let current_ui = uis.iter().position(|ui| *ui == settings.ui).unwrap_or(0);
+    let ui = Select::with_theme(&theme)
+        .with_prompt("WebUI")
+        .items(&labels)
+        .default(current_ui)
+        .interact()?;
+    save_setting(&settings_path, "ui", uis[ui].name())?;
+
+    for (key, prompt, current, default) in [
+        (
+            "mixed-port",
+            "Proxy port (HTTP and SOCKS5)",
+            settings.mixed_port,
+            DEFAULT_MIXED_PORT,
+        ),
+        (
+            "controller-port",
+            "Controller and WebUI port",
+            settings.controller_port,
+            DEFAULT_CONTROLLER_PORT,
+        ),
+    ] {
+        let port: u16 = Input::with_theme(&theme)
+            .with_prompt(prompt)
+            .default(current.unwrap_or(default))
+            .interact_text()?;
+        if port == default {
+            remove_setting(&settings_path, key)?;
+        } else {
+            save_setting(&settings_path, key, i64::from(port))?;
+        }
+    }
+    info!("Saved {}", settings_path.display());
+
+    let autostart = Confirm::with_theme(&theme)
+        .with_prompt("Start proxy-rs automatically at login?")
+        .default(false)
+        .interact()?;
+    if autostart {
+        if let Err(e) = install_autostart(&data_dir) {
+            warn!("Failed to set up autostart: {e}");
+        }
+    }
+    Ok(data_dir)
+}
+
+/// Asks for a subscription URL and checks that it downloads, `None` if left empty.
+async fn ask_subscription(
+    theme: &ColorfulTheme,
+    client: &Client,
+    current: Option<&str>,
+) -> Result<Option<String>> {
+    loop {
+        let url: String = Input::with_theme(theme)
+            .with_prompt("Subscription URL (empty to skip)")
+            .default(current.unwrap_or_default().to_string())
+            .show_default(current.is_some())
+            .allow_empty(true)
+            .interact_text()?;
+        let url = url.trim().to_string();
+        if url.is_empty() {
+            return Ok(None);
+        }
+        if !is_http_url(&url) {
+            warn!("{url} is not an http:// or https:// URL");
+            continue;
+        }
+        match check_subscription(client, &url).await {
+            Ok(summary) => {
+                info!("{summary}");
+                return Ok(Some(url));
+            }
+            Err(e) => warn!("{e}"),
+        }
+        let keep = Confirm::with_theme(theme)
+            .with_prompt("Save it anyway?")
+            .default(false)
+            .interact()?;
+        if keep {
+            return Ok(Some(url));
+        }
+    }
+}
+
+/// Downloads the subscription once, describing what it holds.
+async fn check_subscription(client: &Client, url: &str) -> Result<String> {
+    let content = client
+        .get(url)
+        .header("User-Agent", "clash.meta")
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .context("Failed to download the subscription")?
+        .text()
+        .await?;
+    // A base64 node list parses as a YAML scalar
+    let yaml = serde_yaml::from_str::<serde_yaml::Value>(&content)
+        .ok()
+        .filter(serde_yaml::Value::is_mapping)
+        .ok_or_else(|| anyhow!("The subscription is not a Clash config, set `subconverter` in proxy-rs.toml to convert it"))?;
+    let nodes = yaml
+        .get("proxies")
+        .and_then(|proxies| proxies.as_sequence())
+        .map_or(0, Vec::len);
+    Ok(format!("The subscription has {nodes} nodes"))
+}
+
+/// Whether `url` is an http(s) URL with a host.
+fn is_http_url(url: &str) -> bool {
+    reqwest::Url::parse(url)
+        .is_ok_and(|url| matches!(url.scheme(), "http" | "https") && url.has_host())
+}
+
+/// Writes a systemd user service that runs `start --watch` at login.
+#[cfg(target_os = "linux")]
+fn install_autostart(data_dir: &Path) -> Result<()> {
+    let config_dir = dirs::config_dir().ok_or_else(|| anyhow!("No config directory"))?;
+    let unit_dir = config_dir.join("systemd").join("user");
+    fs::create_dir_all(&unit_dir)?;
+    let exe = dunce::canonicalize(std::env::current_exe()?)?;
+    let data_dir = dunce::canonicalize(data_dir)?;
+    let unit = format!(
+        "[Unit]\n\
+         Description=proxy-rs\n\
+         After=network-online.target\n\n\
+         [Service]\n\
+         ExecStart=\"{}\" --data-dir \"{}\" start --watch\n\
+         Restart=on-failure\n\n\
+         [Install]\n\
+         WantedBy=default.target\n",
+        exe.display(),
+        data_dir.display()
+    );
+    let unit_path = unit_dir.join("proxy-rs.service");
+    fs::write(&unit_path, unit)?;
+    let status = std::process::Command::new("systemctl")
+        .args(["--user", "enable", "proxy-rs.service"])
+        .status()
+        .context("Failed to run systemctl")?;
+    if !status.success() {
+        return Err(anyhow!("systemctl --user enable proxy-rs.service failed"));
+    }
+    info!(
+        "Wrote {}, run `systemctl --user start proxy-rs` to start it now",
+        unit_path.display()
+    );
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn install_autostart(data_dir: &Path) -> Result<()> {
+    let exe = std::env::current_exe()?;
+    Err(anyhow!(
+        "Autostart is only set up on Linux, add `{} --data-dir {} start --watch` to the login items",
+        exe.display(),
+        data_dir.display()
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn requires_http_urls() {
+        assert!(is_http_url("https://example.com/sub?token=1"));
+        assert!(is_http_url("http://127.0.0.1:8080/sub"));
+        assert!(!is_http_url("example.com/sub"));
+        assert!(!is_http_url("ftp://example.com/sub"));
+        assert!(!is_http_url("vmess://abc"));
+        assert!(!is_http_url("https://"));
+    }
+}